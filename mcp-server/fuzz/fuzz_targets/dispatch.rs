@@ -0,0 +1,30 @@
+//! The client's reader loop fed arbitrary bytes by a misbehaving server.
+//! A panic in any of the client's tasks aborts the run.
+
+#![no_main]
+
+use std::time::Duration;
+
+use libfuzzer_sys::fuzz_target;
+use lspmux_cc_mcp::mock_lsp;
+use serde_json::json;
+
+fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let (client, mut server) = mock_lsp::connect(json!({})).await.unwrap();
+        let _ = server.send_raw(data).await;
+        drop(server);
+        // The reader sees the end of the stream and marks the client dead.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while client.is_alive() {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("the client outlived its server");
+    });
+});
@@ -0,0 +1,21 @@
+//! The `Content-Length` framing of messages read from the server.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lspmux_cc_mcp::lsp_client::read_frame;
+use tokio::io::BufReader;
+
+fuzz_target!(|data: &[u8]| {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    runtime.block_on(async {
+        let mut reader = BufReader::new(data);
+        let mut read = 0;
+        while let Ok(Some(body)) = read_frame(&mut reader).await {
+            read += body.len();
+            assert!(read <= data.len());
+        }
+    });
+});
@@ -0,0 +1,17 @@
+//! Percent encoding of file paths into URIs and decoding of the URIs the
+//! server sends back.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use lspmux_cc_mcp::lsp_client::{file_uri, uri_to_path};
+
+fuzz_target!(|data: &str| {
+    if let Ok(uri) = data.parse::<lsp_types::Uri>() {
+        let _ = uri_to_path(&uri);
+    }
+    if data.starts_with('/') {
+        let uri = file_uri(data).unwrap();
+        assert_eq!(uri_to_path(&uri), data);
+    }
+});
@@ -0,0 +1,392 @@
+//! Plain HTTP/JSON API to the tools (`--api-listen`).
+//!
+//! Lets shell scripts, editor plugins, and CI jobs that do not speak MCP
+//! share the server's warm rust-analyzer:
+//!
+//! - `GET /tools` lists the tools, with their input and output schemas.
+//! - `POST /tools/<name>` calls a tool with the JSON object in the body as
+//!   its arguments (an empty body passes none) and returns its structured
+//!   result. The call's correlation id is in the `x-correlation-id` header.
+//!
+//! A failed call returns `{"error": {"code", "message", "data"}}`: 400 for
+//! bad arguments, 404 for an unknown tool, 504 if rust-analyzer timed out,
+//! and 500 otherwise. Every request must carry `Authorization: Bearer
+//! <token>`, or gets 401.
+//!
+//! All requests share one in-process MCP session (see [`crate::direct`]).
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use axum::body::Bytes;
+use axum::extract::{Path, Request, State};
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Json;
+use rmcp::model::{CallToolResult, ErrorCode, JsonObject};
+use rmcp::ErrorData as McpError;
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::direct::{result_text, DirectSession};
+use crate::tools::is_timeout_error;
+
+/// Header carrying the correlation id of a tool call.
+const CORRELATION_ID_HEADER: &str = "x-correlation-id";
+
+#[derive(Clone)]
+struct ApiState {
+    session: Arc<DirectSession>,
+    token: Arc<str>,
+}
+
+/// Random bytes in a generated token.
+const TOKEN_BYTES: usize = 32;
+
+/// The token requests must present: `token` if given, else a new random one
+/// written to [`token_path`], readable only by the user.
+///
+/// # Errors
+///
+/// Returns an error if no token is given and a new one cannot be generated
+/// or written.
+pub fn resolve_token(token: Option<String>) -> Result<String> {
+    if let Some(token) = token {
+        return Ok(token);
+    }
+    let token = generate_token()?;
+    let path = token_path()?;
+    if let Some(dir) = path.parent() {
+        private_dir(dir)?;
+    }
+    write_private(&path, &token)
+        .with_context(|| format!("failed to write the API token to {}", path.display()))?;
+    tracing::info!("API token written to {}", path.display());
+    Ok(token)
+}
+
+/// A new token: [`TOKEN_BYTES`] from the operating system's random source,
+/// hex-encoded.
+fn generate_token() -> Result<String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::fill(&mut bytes).context("failed to generate an API token")?;
+    Ok(bytes.iter().fold(String::new(), |mut token, byte| {
+        let _ = write!(token, "{byte:02x}");
+        token
+    }))
+}
+
+/// Where a generated API token is written: `lspmux-cc/api-token` in the
+/// user's cache directory.
+///
+/// # Errors
+///
+/// Returns an error if the user has no cache directory; pass `--api-token`
+/// then.
+pub fn token_path() -> Result<PathBuf> {
+    let dirs = directories::BaseDirs::new()
+        .context("no user cache directory to write the API token to; pass --api-token")?;
+    Ok(dirs.cache_dir().join("lspmux-cc").join("api-token"))
+}
+
+/// Create `dir` if needed and make it private (0700), after checking that it
+/// is a real directory, not a symlink, owned by the user.
+#[cfg(unix)]
+fn private_dir(dir: &std::path::Path) -> Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+
+    std::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("failed to create {}", dir.display()))?;
+    let metadata = std::fs::symlink_metadata(dir)
+        .with_context(|| format!("failed to inspect {}", dir.display()))?;
+    // SAFETY: `geteuid` is a side-effect-free libc call.
+    let uid = unsafe { libc::geteuid() };
+    if !metadata.is_dir() || metadata.uid() != uid {
+        anyhow::bail!(
+            "refusing to write the API token to {}: it is not a directory owned by the \
+             current user",
+            dir.display()
+        );
+    }
+    if metadata.mode() & 0o077 != 0 {
+        std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+            .with_context(|| format!("failed to make {} private", dir.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn private_dir(dir: &std::path::Path) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))
+}
+
+/// Write `contents` to a new file at `path`, readable only by the user. A
+/// file or symlink already there is removed first, never followed.
+#[cfg(unix)]
+fn write_private(path: &std::path::Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(contents.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &std::path::Path, contents: &str) -> std::io::Result<()> {
+    std::fs::write(path, contents)
+}
+
+/// Serve the API on `addr` until `shutdown` is cancelled, calling tools
+/// through `session`.
+///
+/// # Errors
+///
+/// Returns an error if `addr` cannot be bound.
+pub async fn serve(
+    session: DirectSession,
+    token: String,
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    if !addr.ip().is_loopback() {
+        tracing::warn!(
+            "the HTTP API listens on non-loopback address {addr}; anyone who can reach it \
+             and has the token can edit files in the workspace"
+        );
+    }
+    let state = ApiState {
+        session: Arc::new(session),
+        token: token.into(),
+    };
+    let router = axum::Router::new()
+        .route("/tools", get(list_tools))
+        .route("/tools/{name}", post(call_tool))
+        .layer(middleware::from_fn_with_state(state.clone(), require_token))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind HTTP API listener on {addr}"))?;
+    tracing::info!("serving the HTTP API at http://{addr}/tools");
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .context("HTTP API server exited with an error")
+}
+
+async fn require_token(State(state): State<ApiState>, request: Request, next: Next) -> Response {
+    if bearer_matches(request.headers(), &state.token) {
+        return next.run(request).await;
+    }
+    error_response(
+        StatusCode::UNAUTHORIZED,
+        &McpError::invalid_request("missing or wrong `Authorization: Bearer` token", None),
+    )
+}
+
+/// Whether `headers` carry `Authorization: Bearer <token>`, compared in
+/// constant time.
+fn bearer_matches(headers: &HeaderMap, token: &str) -> bool {
+    let Some(given) = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+    given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+async fn list_tools(State(state): State<ApiState>) -> Response {
+    match state.session.list_tools().await {
+        Ok(list) => Json(json!({ "tools": list.tools })).into_response(),
+        Err(error) => error_response(status_for(&error), &error),
+    }
+}
+
+async fn call_tool(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+    body: Bytes,
+) -> Response {
+    let arguments = match parse_arguments(&body) {
+        Ok(arguments) => arguments,
+        Err(error) => return error_response(StatusCode::BAD_REQUEST, &error),
+    };
+    // Checked first, so an unknown name does not start rust-analyzer.
+    match state.session.list_tools().await {
+        Ok(list) if list.tools.iter().any(|tool| tool.name == name) => {}
+        Ok(_) => {
+            let error = McpError::new(
+                ErrorCode::METHOD_NOT_FOUND,
+                format!("no tool named {name:?} is available; GET /tools lists them"),
+                None,
+            );
+            return error_response(StatusCode::NOT_FOUND, &error);
+        }
+        Err(error) => return error_response(status_for(&error), &error),
+    }
+    match state.session.call_tool(&name, arguments).await {
+        Ok(result) => tool_response(result),
+        Err(error) => error_response(status_for(&error), &error),
+    }
+}
+
+/// The tool arguments in a request body: a JSON object, or nothing.
+fn parse_arguments(body: &[u8]) -> Result<JsonObject, McpError> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(JsonObject::new());
+    }
+    serde_json::from_slice(body).map_err(|e| {
+        McpError::invalid_params(format!("the request body is not a JSON object: {e}"), None)
+    })
+}
+
+/// The HTTP status of a failed tool call.
+fn status_for(error: &McpError) -> StatusCode {
+    match error.code {
+        ErrorCode::INVALID_PARAMS | ErrorCode::INVALID_REQUEST | ErrorCode::PARSE_ERROR => {
+            StatusCode::BAD_REQUEST
+        }
+        ErrorCode::METHOD_NOT_FOUND | ErrorCode::RESOURCE_NOT_FOUND => StatusCode::NOT_FOUND,
+        _ if is_timeout_error(error) => StatusCode::GATEWAY_TIMEOUT,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+fn error_response(status: StatusCode, error: &McpError) -> Response {
+    (status, Json(json!({ "error": error }))).into_response()
+}
+
+/// The structured content of a successful call, or its text if it has none.
+fn tool_response(result: CallToolResult) -> Response {
+    let correlation_id = result
+        .meta
+        .as_ref()
+        .and_then(|meta| meta.get("correlation_id"))
+        .and_then(Value::as_str)
+        .and_then(|id| HeaderValue::from_str(id).ok());
+    let text = result_text(&result);
+    let mut response = if result.is_error == Some(true) {
+        let error = McpError::internal_error(text, None);
+        error_response(StatusCode::INTERNAL_SERVER_ERROR, &error)
+    } else {
+        Json(
+            result
+                .structured_content
+                .unwrap_or_else(|| json!({ "text": text })),
+        )
+        .into_response()
+    };
+    if let Some(id) = correlation_id {
+        response.headers_mut().insert(CORRELATION_ID_HEADER, id);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_exact_bearer_token_is_accepted() {
+        let with = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, HeaderValue::from_str(value).unwrap());
+            headers
+        };
+        assert!(bearer_matches(&with("Bearer s3cret"), "s3cret"));
+        assert!(!bearer_matches(&with("Bearer s3cre"), "s3cret"));
+        assert!(!bearer_matches(&with("Bearer s3creT"), "s3cret"));
+        assert!(!bearer_matches(&with("s3cret"), "s3cret"));
+        assert!(!bearer_matches(&HeaderMap::new(), "s3cret"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn generated_tokens_are_written_privately_without_following_symlinks() {
+        use std::os::unix::fs::{symlink, PermissionsExt};
+
+        let token = generate_token().unwrap();
+        assert_eq!(token.len(), 2 * TOKEN_BYTES);
+        assert_ne!(token, generate_token().unwrap());
+
+        let dir = tempfile::tempdir().unwrap();
+        let victim = dir.path().join("victim");
+        std::fs::write(&victim, "keep").unwrap();
+        let path = dir.path().join("api-token");
+        symlink(&victim, &path).unwrap();
+        write_private(&path, &token).unwrap();
+        assert_eq!(std::fs::read_to_string(&victim).unwrap(), "keep");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), token);
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.is_file());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+
+        let private = dir.path().join("lspmux-cc");
+        std::fs::create_dir(&private).unwrap();
+        std::fs::set_permissions(&private, std::fs::Permissions::from_mode(0o755)).unwrap();
+        private_dir(&private).unwrap();
+        let mode = std::fs::metadata(&private).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        let linked = dir.path().join("linked");
+        symlink(&private, &linked).unwrap();
+        assert!(private_dir(&linked).is_err());
+    }
+
+    #[test]
+    fn bodies_and_errors_map_to_http() {
+        assert_eq!(parse_arguments(b"").unwrap(), JsonObject::new());
+        assert_eq!(
+            parse_arguments(b"{\"query\": \"Lsp\"}").unwrap()["query"],
+            "Lsp"
+        );
+        assert!(parse_arguments(b"[1]").is_err());
+
+        let status = |error: McpError| status_for(&error);
+        assert_eq!(
+            status(McpError::invalid_params("bad line", None)),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            status(McpError::internal_error(
+                "hover request failed: LSP request textDocument/hover timed out after 5s",
+                Some(json!({ "timed_out": true, "correlation_id": "1f2a-17" }))
+            )),
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        // Only the marker counts, not the wording.
+        assert_eq!(
+            status(McpError::internal_error(
+                "cargo metadata timed out in the fixture",
+                None
+            )),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            status(McpError::internal_error("LSP connection lost", None)),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -0,0 +1,383 @@
+//! A stand-in for `lspmux client` and rust-analyzer, for hermetic tests.
+//!
+//! Run as `fake_lspmux client [--config PATH] --server-path PATH`, it speaks
+//! LSP on stdio like the real client, but answers a canned subset itself
+//! from the text of the open documents, so tests run without lspmux or
+//! rust-analyzer installed:
+//!
+//! - `textDocument/hover`: the identifier under the cursor, as Rust code
+//! - `textDocument/definition`: the identifier after `fn`, `struct`, `enum`,
+//!   `trait`, `type`, `const`, `static`, or `mod` in an open document
+//! - `textDocument/references`: every whole-word occurrence in open documents
+//! - `textDocument/documentSymbol` and `workspace/symbol`: those items
+//! - `textDocument/diagnostic`: an error at each `compile_error!("...")`
+//! - `textDocument/didSave`: a `rust-analyzer/flycheck/0` progress cycle
+//!   followed by `textDocument/publishDiagnostics`
+//!
+//! Other requests fail with `MethodNotFound`. Positions count characters, so
+//! test sources should stick to ASCII.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+
+/// Keywords that introduce a named item.
+const ITEM_KEYWORDS: &[(&str, u32)] = &[
+    ("fn", 12),
+    ("struct", 23),
+    ("enum", 10),
+    ("trait", 11),
+    ("type", 26),
+    ("const", 14),
+    ("static", 14),
+    ("mod", 2),
+];
+
+/// Reported in the `initialize` response.
+const SERVER_VERSION: &str = "0.0.0-fake";
+
+struct Server {
+    output: io::Stdout,
+    /// Open documents by URI.
+    documents: BTreeMap<String, String>,
+    next_id: u64,
+}
+
+fn main() -> Result<()> {
+    let mut input = BufReader::new(io::stdin().lock());
+    let mut server = Server {
+        output: io::stdout(),
+        documents: BTreeMap::new(),
+        next_id: 0,
+    };
+    while let Some(message) = read_message(&mut input)? {
+        let method = message.get("method").and_then(Value::as_str);
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        match (message.get("id"), method) {
+            (Some(id), Some(method)) => {
+                let reply = match server.request(method, &params) {
+                    Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                    Err(e) => json!({
+                        "jsonrpc": "2.0",
+                        "id": id,
+                        "error": { "code": -32601, "message": format!("{e:#}") },
+                    }),
+                };
+                server.send(&reply)?;
+                if method == "shutdown" {
+                    server.output.flush()?;
+                }
+            }
+            (None, Some("exit")) => return Ok(()),
+            (None, Some(method)) => server.notification(method, &params)?,
+            // Responses to our own requests, such as progress creation.
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The next framed message on `input`, or `None` at end of input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(length) = line.strip_prefix("Content-Length: ") {
+            content_length = Some(length.parse::<usize>()?);
+        }
+    }
+    let mut body = vec![0; content_length.context("missing Content-Length header")?];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+impl Server {
+    fn send(&mut self, message: &Value) -> Result<()> {
+        let body = serde_json::to_string(message)?;
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.output.flush()?;
+        Ok(())
+    }
+
+    fn notify(&mut self, method: &str, params: &Value) -> Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn request(&self, method: &str, params: &Value) -> Result<Value> {
+        Ok(match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": { "openClose": true, "change": 1, "save": {} },
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true,
+                    "workspaceSymbolProvider": true,
+                    "diagnosticProvider": {
+                        "interFileDependencies": true,
+                        "workspaceDiagnostics": false,
+                    },
+                },
+                "serverInfo": { "name": "fake-rust-analyzer", "version": SERVER_VERSION },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/hover" => self.word_at(params).map_or(Value::Null, |(_, word)| {
+                json!({ "contents": { "kind": "markdown", "value": format!("```rust\n{word}\n```") } })
+            }),
+            "textDocument/definition" => self
+                .word_at(params)
+                .and_then(|(_, word)| {
+                    self.items()
+                        .find(|item| item.name == word)
+                        .map(|item| item.location())
+                })
+                .unwrap_or(Value::Null),
+            "textDocument/references" => {
+                let Some((_, word)) = self.word_at(params) else {
+                    return Ok(Value::Null);
+                };
+                let (word, len) = (word.as_str(), word.len());
+                let locations: Vec<Value> = self
+                    .documents
+                    .iter()
+                    .flat_map(|(uri, text)| {
+                        occurrences(text, word)
+                            .map(move |(line, start)| location(uri, line, start, start + len))
+                    })
+                    .collect();
+                json!(locations)
+            }
+            "textDocument/documentSymbol" => {
+                let uri = document_uri(params)?;
+                let symbols: Vec<Value> = self
+                    .items()
+                    .filter(|item| item.uri == uri)
+                    .map(|item| item.document_symbol())
+                    .collect();
+                json!(symbols)
+            }
+            "workspace/symbol" => {
+                let query = params["query"].as_str().unwrap_or_default().to_lowercase();
+                let symbols: Vec<Value> = self
+                    .items()
+                    .filter(|item| item.name.to_lowercase().contains(&query))
+                    .map(|item| {
+                        json!({ "name": item.name, "kind": item.kind, "location": item.location() })
+                    })
+                    .collect();
+                json!(symbols)
+            }
+            "textDocument/diagnostic" => {
+                let uri = document_uri(params)?;
+                json!({ "kind": "full", "items": self.diagnostics(uri) })
+            }
+            _ => bail!("unsupported request: {method}"),
+        })
+    }
+
+    fn notification(&mut self, method: &str, params: &Value) -> Result<()> {
+        match method {
+            "initialized" => self.notify(
+                "experimental/serverStatus",
+                &json!({ "health": "ok", "quiescent": true }),
+            )?,
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                self.documents.insert(
+                    document["uri"].as_str().unwrap_or_default().to_string(),
+                    document["text"].as_str().unwrap_or_default().to_string(),
+                );
+            }
+            "textDocument/didChange" => {
+                let uri = document_uri(params)?.to_string();
+                if let Some(text) = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                {
+                    self.documents.insert(uri, text.to_string());
+                }
+            }
+            "textDocument/didClose" => {
+                let uri = document_uri(params)?.to_string();
+                self.documents.remove(&uri);
+            }
+            "textDocument/didSave" => {
+                let uri = document_uri(params)?.to_string();
+                self.check(&uri)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Run a pretend `cargo check` of the saved document `uri`.
+    fn check(&mut self, uri: &str) -> Result<()> {
+        let token = "rust-analyzer/flycheck/0";
+        self.next_id += 1;
+        let id = format!("fake-{}", self.next_id);
+        self.send(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "window/workDoneProgress/create",
+            "params": { "token": token },
+        }))?;
+        self.notify(
+            "$/progress",
+            &json!({ "token": token, "value": { "kind": "begin", "title": "cargo check" } }),
+        )?;
+        let diagnostics = self.diagnostics(uri);
+        self.notify(
+            "textDocument/publishDiagnostics",
+            &json!({ "uri": uri, "diagnostics": diagnostics }),
+        )?;
+        self.notify(
+            "$/progress",
+            &json!({ "token": token, "value": { "kind": "end" } }),
+        )
+    }
+
+    /// An error at each `compile_error!("...")` in document `uri`.
+    fn diagnostics(&self, uri: &str) -> Vec<Value> {
+        let text = self.documents.get(uri).map_or("", String::as_str);
+        let macro_call = "compile_error!(\"";
+        text.lines()
+            .enumerate()
+            .filter_map(|(line, content)| {
+                let start = content.find(macro_call)?;
+                let message = content[start + macro_call.len()..].split('"').next()?;
+                Some(json!({
+                    "range": range(line, start, content.len()),
+                    "severity": 1,
+                    "source": "rustc",
+                    "message": message,
+                }))
+            })
+            .collect()
+    }
+
+    /// The URI and identifier at the request's position.
+    fn word_at(&self, params: &Value) -> Option<(String, String)> {
+        let uri = document_uri(params).ok()?;
+        let line = usize::try_from(params["position"]["line"].as_u64()?).ok()?;
+        let character = usize::try_from(params["position"]["character"].as_u64()?).ok()?;
+        let content = self.documents.get(uri)?.lines().nth(line)?;
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        let chars: Vec<char> = content.chars().collect();
+        if !chars.get(character).copied().is_some_and(is_ident) {
+            return None;
+        }
+        let start = chars[..character]
+            .iter()
+            .rposition(|&c| !is_ident(c))
+            .map_or(0, |index| index + 1);
+        let end = chars[character..]
+            .iter()
+            .position(|&c| !is_ident(c))
+            .map_or(chars.len(), |index| character + index);
+        Some((uri.to_string(), chars[start..end].iter().collect()))
+    }
+
+    /// The named items declared in every open document.
+    fn items(&self) -> impl Iterator<Item = Item<'_>> {
+        self.documents.iter().flat_map(|(uri, text)| {
+            text.lines().enumerate().filter_map(move |(line, content)| {
+                let mut words = content.split_whitespace();
+                let kind = loop {
+                    let word = words.next()?;
+                    if let Some((_, kind)) =
+                        ITEM_KEYWORDS.iter().find(|(keyword, _)| *keyword == word)
+                    {
+                        break *kind;
+                    }
+                };
+                let name: String = words
+                    .next()?
+                    .chars()
+                    .take_while(|&c| c.is_alphanumeric() || c == '_')
+                    .collect();
+                let start = occurrences(content, &name).next()?.1;
+                (!name.is_empty()).then_some(Item {
+                    uri,
+                    name,
+                    kind,
+                    line,
+                    start,
+                    line_len: content.len(),
+                })
+            })
+        })
+    }
+}
+
+/// A named item declared on one line.
+struct Item<'a> {
+    uri: &'a str,
+    name: String,
+    /// The LSP `SymbolKind`.
+    kind: u32,
+    line: usize,
+    start: usize,
+    line_len: usize,
+}
+
+impl Item<'_> {
+    fn location(&self) -> Value {
+        location(
+            self.uri,
+            self.line,
+            self.start,
+            self.start + self.name.len(),
+        )
+    }
+
+    fn document_symbol(&self) -> Value {
+        json!({
+            "name": self.name,
+            "kind": self.kind,
+            "range": range(self.line, 0, self.line_len),
+            "selectionRange": range(self.line, self.start, self.start + self.name.len()),
+        })
+    }
+}
+
+fn document_uri(params: &Value) -> Result<&str> {
+    params["textDocument"]["uri"]
+        .as_str()
+        .context("missing textDocument.uri")
+}
+
+/// The line and start of each whole-word occurrence of `word` in `text`.
+fn occurrences<'a>(text: &'a str, word: &'a str) -> impl Iterator<Item = (usize, usize)> + 'a {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    text.lines().enumerate().flat_map(move |(line, content)| {
+        content
+            .match_indices(word)
+            .filter(move |&(start, _)| {
+                let before = content[..start].chars().next_back();
+                let after = content[start + word.len()..].chars().next();
+                !before.is_some_and(is_ident) && !after.is_some_and(is_ident)
+            })
+            .map(move |(start, _)| (line, start))
+    })
+}
+
+fn range(line: usize, start: usize, end: usize) -> Value {
+    json!({
+        "start": { "line": line, "character": start },
+        "end": { "line": line, "character": end },
+    })
+}
+
+fn location(uri: &str, line: usize, start: usize, end: usize) -> Value {
+    json!({ "uri": uri, "range": range(line, start, end) })
+}
@@ -1,6 +1,7 @@
 //! Runtime bootstrap and service discovery for the shared lspmux service.
 
 use std::fs;
+use std::io::Write as _;
 use std::net::TcpStream;
 #[cfg(unix)]
 use std::os::unix::fs::FileTypeExt;
@@ -10,17 +11,20 @@ use std::path::{Path, PathBuf};
 use std::time::Duration as StdDuration;
 
 use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
 use directories::BaseDirs;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use tokio::process::Command;
 use tokio::time::{sleep, Duration, Instant};
 
+use crate::supervisor::{free_port, loopback_config, LOOPBACK};
+
 /// The managed LSP backend exposed by this package.
 pub const SERVER_NAME: &str = "rust-analyzer";
 
 /// Environment-controlled bootstrap behavior.
-#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, JsonSchema)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize, JsonSchema, ValueEnum)]
 #[serde(rename_all = "snake_case")]
 pub enum BootstrapMode {
     /// Reuse an installed service when available, otherwise start one directly.
@@ -29,6 +33,8 @@ pub enum BootstrapMode {
     Require,
     /// Do not attempt to start a shared service.
     Off,
+    /// Run and supervise a private lspmux server instead of a shared one.
+    Managed,
 }
 
 impl BootstrapMode {
@@ -37,13 +43,32 @@ impl BootstrapMode {
             None | Some("" | "auto") => Ok(Self::Auto),
             Some("require") => Ok(Self::Require),
             Some("off") => Ok(Self::Off),
+            Some("managed") => Ok(Self::Managed),
             Some(other) => {
-                bail!("invalid LSPMUX_BOOTSTRAP value {other:?}; expected auto, require, or off")
+                bail!(
+                    "invalid LSPMUX_BOOTSTRAP value {other:?}; expected auto, require, off, \
+                     or managed"
+                )
             }
         }
     }
 }
 
+/// How the LSP client reaches the lspmux server.
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, JsonSchema, ValueEnum,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum LspTransport {
+    /// Connect to the server's socket, spawning `lspmux client` if that fails.
+    #[default]
+    Auto,
+    /// Connect to the server's socket only.
+    Socket,
+    /// Speak LSP over the stdio of an `lspmux client` child process.
+    Child,
+}
+
 /// Transport address parsed from the lspmux config's `connect` field.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ConnectAddr {
@@ -53,6 +78,45 @@ pub enum ConnectAddr {
     Unix(String),
 }
 
+impl std::fmt::Display for ConnectAddr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tcp(host, port) => write!(f, "{host}:{port}"),
+            Self::Unix(path) => f.write_str(path),
+        }
+    }
+}
+
+impl ConnectAddr {
+    /// Whether a client connecting here reaches a server listening on
+    /// `listen`. Hosts are not compared, since a server may listen on every
+    /// interface or name loopback differently.
+    fn reaches(&self, listen: &Self) -> bool {
+        match (self, listen) {
+            (Self::Tcp(_, port), Self::Tcp(_, listen_port)) => port == listen_port,
+            (Self::Unix(path), Self::Unix(listen_path)) => path == listen_path,
+            _ => false,
+        }
+    }
+}
+
+/// Check that the `connect` address of a lspmux TOML config string reaches
+/// its `listen` address, where both are set.
+fn check_endpoints(config_toml: &str) -> Result<()> {
+    let table: toml::Table = config_toml.parse().context("not valid TOML")?;
+    let listen = table.get("listen").and_then(parse_connect_value);
+    let connect = table.get("connect").and_then(parse_connect_value);
+    if let (Some(listen), Some(connect)) = (listen, connect) {
+        if !connect.reaches(&listen) {
+            bail!(
+                "clients connect to {connect} but the server listens on {listen}; \
+                 make `listen` and `connect` agree"
+            );
+        }
+    }
+    Ok(())
+}
+
 /// Parse the `connect` field from a lspmux TOML config string.
 ///
 /// Returns `None` if the field is missing or has an unrecognized shape.
@@ -62,6 +126,44 @@ fn parse_connect_addr(config_toml: &str) -> Option<ConnectAddr> {
     parse_connect_value(connect)
 }
 
+/// What `lspmux client` reads from an lspmux config to reach the server.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct LspmuxClientConfig {
+    /// The `connect` address, if set and recognized.
+    pub connect: Option<ConnectAddr>,
+    /// Environment variables (`pass_environment`) handed to the language server.
+    pub pass_environment: Vec<String>,
+}
+
+impl LspmuxClientConfig {
+    /// Read the lspmux config at `path`; a missing or unparsable file sets nothing.
+    #[must_use]
+    pub fn read(path: &Path) -> Self {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|contents| Self::parse(&contents))
+            .unwrap_or_default()
+    }
+
+    fn parse(config_toml: &str) -> Option<Self> {
+        let table: toml::Table = config_toml.parse().ok()?;
+        let pass_environment = table
+            .get("pass_environment")
+            .and_then(toml::Value::as_array)
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| name.as_str().map(ToOwned::to_owned))
+                    .collect()
+            })
+            .unwrap_or_default();
+        Some(Self {
+            connect: table.get("connect").and_then(parse_connect_value),
+            pass_environment,
+        })
+    }
+}
+
 fn parse_connect_value(value: &toml::Value) -> Option<ConnectAddr> {
     match value {
         toml::Value::String(raw) => parse_connect_string(raw),
@@ -104,6 +206,11 @@ pub enum ServiceMode {
     StartedViaManager,
     StartedDirectly,
     Skipped,
+    /// A private server started and supervised by this process.
+    Managed,
+    /// No lspmux: rust-analyzer runs as a child of this process, shared
+    /// with no one.
+    Unshared,
 }
 
 /// Runtime status surfaced through the MCP status tool.
@@ -122,12 +229,20 @@ pub struct RuntimeStatus {
 pub struct RuntimeConfig {
     pub lspmux_path: String,
     pub server_path: String,
+    /// Whether `server_path` was configured rather than discovered; a
+    /// discovered one gives way to the rust-analyzer of a workspace's pinned
+    /// toolchain.
+    pub server_path_pinned: bool,
     pub workspace_root: Option<String>,
     pub config_path: String,
     pub socket_path: String,
     pub bootstrap_mode: BootstrapMode,
     /// Transport address parsed from the config's `connect` field, if available.
     pub connect_addr: Option<ConnectAddr>,
+    /// How the LSP client reaches the lspmux server.
+    pub lsp_transport: LspTransport,
+    /// Run rust-analyzer without lspmux when the lspmux binary is missing.
+    pub no_mux_fallback: bool,
 }
 
 impl RuntimeConfig {
@@ -150,10 +265,9 @@ impl RuntimeConfig {
             )
         });
 
-        let server_path = resolve_server_path(
-            std::env::var("RUST_ANALYZER_PATH").ok(),
-            which::which(SERVER_NAME).ok(),
-        );
+        let configured_server = std::env::var("RUST_ANALYZER_PATH").ok();
+        let server_path_pinned = configured_server.is_some();
+        let server_path = resolve_server_path(configured_server, which::which(SERVER_NAME).ok());
 
         let workspace_root = std::env::var("WORKSPACE_ROOT").ok().or_else(|| {
             std::env::current_dir()
@@ -184,14 +298,51 @@ impl RuntimeConfig {
         Ok(Self {
             lspmux_path,
             server_path,
+            server_path_pinned,
             workspace_root,
             config_path,
             socket_path,
             bootstrap_mode,
             connect_addr,
+            lsp_transport: LspTransport::default(),
+            no_mux_fallback: false,
         })
     }
 
+    /// Write an lspmux config listening on a free loopback port if there is
+    /// none at `config_path`, so the `auto` bootstrap can start a server
+    /// instead of failing. Returns whether one was written; none is when
+    /// rust-analyzer [runs unshared](Self::runs_unshared).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the config cannot be written.
+    pub fn provision_config(&mut self) -> Result<bool> {
+        let path = Path::new(&self.config_path);
+        if self.bootstrap_mode != BootstrapMode::Auto || path.exists() || self.runs_unshared() {
+            return Ok(false);
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let port = free_port()?;
+        // Another process may be provisioning the same file; keep its config.
+        let written = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(loopback_config(port).as_bytes()));
+        match written {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => return Ok(false),
+            Err(e) => return Err(e).with_context(|| format!("failed to write {}", path.display())),
+        }
+        self.connect_addr = Some(ConnectAddr::Tcp(LOOPBACK.to_string(), port));
+        tracing::info!(event = "lspmux_config_provisioned", path = %path.display(), port);
+        Ok(true)
+    }
+
     /// Ensure the shared lspmux service is available according to the bootstrap policy.
     ///
     /// # Errors
@@ -199,6 +350,9 @@ impl RuntimeConfig {
     /// Returns an error if prerequisites are missing or the configured bootstrap policy
     /// cannot make the shared service available.
     pub async fn ensure_service_running(&self) -> Result<RuntimeStatus> {
+        if self.bootstrap_mode == BootstrapMode::Managed {
+            bail!("managed lspmux servers are started with `ManagedServer::start`");
+        }
         self.validate_prerequisites()?;
 
         if self.bootstrap_mode == BootstrapMode::Off {
@@ -234,7 +388,7 @@ impl RuntimeConfig {
         );
     }
 
-    fn runtime_status(&self, service_mode: ServiceMode) -> RuntimeStatus {
+    pub(crate) fn runtime_status(&self, service_mode: ServiceMode) -> RuntimeStatus {
         RuntimeStatus {
             bootstrap_mode: self.bootstrap_mode,
             service_mode,
@@ -246,7 +400,28 @@ impl RuntimeConfig {
     }
 
     fn validate_prerequisites(&self) -> Result<()> {
-        if !Path::new(&self.lspmux_path).exists() {
+        self.validate_binaries()?;
+        let Ok(contents) = fs::read_to_string(&self.config_path) else {
+            bail!(
+                "lspmux config not found at {}; run `./setup core` or set LSPMUX_CONFIG_PATH",
+                self.config_path
+            );
+        };
+        check_endpoints(&contents)
+            .with_context(|| format!("lspmux config {} is inconsistent", self.config_path))
+    }
+
+    /// Whether rust-analyzer runs without lspmux: the fallback is on and
+    /// the lspmux binary is missing.
+    #[must_use]
+    pub fn runs_unshared(&self) -> bool {
+        self.no_mux_fallback && !Path::new(&self.lspmux_path).exists()
+    }
+
+    /// Check that the lspmux and language server binaries exist; lspmux
+    /// only if it is used.
+    pub(crate) fn validate_binaries(&self) -> Result<()> {
+        if !self.runs_unshared() && !Path::new(&self.lspmux_path).exists() {
             bail!(
                 "lspmux binary not found at {}; install it or set LSPMUX_PATH",
                 self.lspmux_path
@@ -258,20 +433,24 @@ impl RuntimeConfig {
                 self.server_path
             );
         }
-        if !Path::new(&self.config_path).exists() {
-            bail!(
-                "lspmux config not found at {}; run `./setup core` or set LSPMUX_CONFIG_PATH",
-                self.config_path
-            );
-        }
         Ok(())
     }
 
-    fn service_ready(&self) -> bool {
-        match &self.connect_addr {
-            Some(ConnectAddr::Tcp(host, port)) => tcp_is_ready(host, *port),
-            Some(ConnectAddr::Unix(path)) => socket_is_ready(path),
-            None => socket_is_ready(&self.socket_path),
+    /// The configured lspmux endpoint: the `connect` address, else the
+    /// socket path.
+    #[must_use]
+    pub fn endpoint(&self) -> ConnectAddr {
+        self.connect_addr
+            .clone()
+            .unwrap_or_else(|| ConnectAddr::Unix(self.socket_path.clone()))
+    }
+
+    /// Whether the configured lspmux endpoint accepts connections.
+    #[must_use]
+    pub fn service_ready(&self) -> bool {
+        match self.endpoint() {
+            ConnectAddr::Tcp(host, port) => tcp_is_ready(&host, port),
+            ConnectAddr::Unix(path) => socket_is_ready(&path),
         }
     }
 
@@ -361,6 +540,7 @@ fn cargo_home_path(home: &str) -> String {
     }
 }
 
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
 fn nix_like_uid() -> u32 {
     #[cfg(unix)]
     {
@@ -420,7 +600,7 @@ fn resolve_server_path(configured_path: Option<String>, path_lookup: Option<Path
     })
 }
 
-fn tcp_is_ready(host: &str, port: u16) -> bool {
+pub(crate) fn tcp_is_ready(host: &str, port: u16) -> bool {
     TcpStream::connect_timeout(
         &format!("{host}:{port}").parse().unwrap(),
         StdDuration::from_millis(500),
@@ -499,6 +679,14 @@ mod tests {
         assert!(BootstrapMode::parse(Some("weird")).is_err());
     }
 
+    #[test]
+    fn bootstrap_mode_accepts_managed() {
+        assert_eq!(
+            BootstrapMode::parse(Some("managed")).unwrap(),
+            BootstrapMode::Managed
+        );
+    }
+
     #[test]
     fn default_socket_path_prefers_runtime_dir() {
         let path = default_socket_path(Some("/run/user/123"), None, Some("/tmp/custom"));
@@ -590,6 +778,71 @@ connect = ["127.0.0.1", 27631]
         );
     }
 
+    #[test]
+    fn a_missing_config_is_provisioned_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("lspmux/config.toml");
+        let mut runtime = RuntimeConfig {
+            lspmux_path: "/nonexistent/lspmux".to_string(),
+            server_path: "/nonexistent/rust-analyzer".to_string(),
+            server_path_pinned: false,
+            workspace_root: None,
+            config_path: config_path.display().to_string(),
+            socket_path: "/nonexistent/lspmux.sock".to_string(),
+            bootstrap_mode: BootstrapMode::Require,
+            connect_addr: None,
+            lsp_transport: LspTransport::Auto,
+            no_mux_fallback: false,
+        };
+        assert!(!runtime.provision_config().unwrap());
+        assert!(!config_path.exists());
+
+        runtime.bootstrap_mode = BootstrapMode::Auto;
+        runtime.no_mux_fallback = true;
+        assert!(runtime.runs_unshared());
+        let err = runtime.validate_binaries().unwrap_err();
+        assert!(err.to_string().starts_with(SERVER_NAME), "{err:#}");
+        assert!(!runtime.provision_config().unwrap());
+        runtime.no_mux_fallback = false;
+        assert!(runtime.provision_config().unwrap());
+        let contents = fs::read_to_string(&config_path).unwrap();
+        assert_eq!(parse_connect_addr(&contents), runtime.connect_addr);
+        check_endpoints(&contents).unwrap();
+        assert!(!runtime.provision_config().unwrap());
+        assert_eq!(fs::read_to_string(&config_path).unwrap(), contents);
+    }
+
+    #[test]
+    fn listen_and_connect_must_agree() {
+        check_endpoints("listen = [\"0.0.0.0\", 27631]\nconnect = \"localhost:27631\"\n").unwrap();
+        check_endpoints("listen = \"/run/lspmux.sock\"\n").unwrap();
+        let err =
+            check_endpoints("listen = [\"127.0.0.1\", 27631]\nconnect = [\"127.0.0.1\", 27632]\n")
+                .unwrap_err();
+        assert!(err.to_string().contains("127.0.0.1:27632"), "{err:#}");
+        assert!(check_endpoints("listen = \"/a.sock\"\nconnect = \"/b.sock\"\n").is_err());
+    }
+
+    #[test]
+    fn client_config_reads_connect_and_pass_environment() {
+        let config = r#"
+connect = ["127.0.0.1", 27631]
+pass_environment = ["PATH", "CARGO_HOME"]
+"#;
+        assert_eq!(
+            LspmuxClientConfig::parse(config),
+            Some(LspmuxClientConfig {
+                connect: Some(ConnectAddr::Tcp("127.0.0.1".to_string(), 27631)),
+                pass_environment: vec!["PATH".to_string(), "CARGO_HOME".to_string()],
+            })
+        );
+        let missing = tempfile::tempdir().unwrap().path().join("lspmux.toml");
+        assert_eq!(
+            LspmuxClientConfig::read(&missing),
+            LspmuxClientConfig::default()
+        );
+    }
+
     #[test]
     fn parse_connect_addr_missing() {
         let config = r#"listen = ["127.0.0.1", 27631]"#;
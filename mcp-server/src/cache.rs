@@ -0,0 +1,244 @@
+//! Memoized LSP responses for repeated queries on unchanged files.
+//!
+//! Agent loops often repeat the same hover or definition lookup. Responses
+//! are keyed by LSP method, file, file content hash, and request parameters.
+//! The whole cache is dropped whenever the LSP client's generation moves (a
+//! document was edited or rust-analyzer re-analyzed the workspace), since an
+//! edit to one file can change answers about another.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use metrics::counter;
+use rmcp::ErrorData as McpError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Responses kept before the least recently used one is evicted.
+const MAX_CACHED_RESPONSES: usize = 512;
+
+/// What a cached response answers.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    method: &'static str,
+    file_path: String,
+    content_hash: u64,
+    params: String,
+}
+
+impl CacheKey {
+    /// A key for `method` on `file_path` (whose content hashes to
+    /// `content_hash`) with the remaining request parameters in `params`.
+    pub fn new(method: &'static str, file_path: &str, content_hash: u64, params: String) -> Self {
+        Self {
+            method,
+            file_path: file_path.to_string(),
+            content_hash,
+            params,
+        }
+    }
+}
+
+#[derive(Default)]
+struct CacheState {
+    generation: u64,
+    tick: u64,
+    entries: HashMap<CacheKey, (Value, u64)>,
+    hits: u64,
+    misses: u64,
+}
+
+/// How well the cache has served lookups so far.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Responses currently cached.
+    pub entries: usize,
+}
+
+/// LSP responses shared by every MCP session.
+#[derive(Clone, Default)]
+pub struct ResponseCache {
+    state: Arc<Mutex<CacheState>>,
+}
+
+impl ResponseCache {
+    /// The cached response for `key` under `generation`, or the result of
+    /// `fetch` (cached on success).
+    ///
+    /// # Errors
+    ///
+    /// Propagates any error from `fetch`.
+    pub async fn get_or_fetch<T, F>(
+        &self,
+        generation: u64,
+        key: CacheKey,
+        fetch: F,
+    ) -> Result<T, McpError>
+    where
+        T: Serialize + DeserializeOwned,
+        F: Future<Output = Result<T, McpError>>,
+    {
+        let cached = self.get(generation, &key);
+        if let Some(value) = cached.and_then(|value| serde_json::from_value(value).ok()) {
+            self.lock().hits += 1;
+            counter!("lspmux_cc_response_cache_total", "result" => "hit").increment(1);
+            return Ok(value);
+        }
+        self.lock().misses += 1;
+        counter!("lspmux_cc_response_cache_total", "result" => "miss").increment(1);
+
+        let response = fetch.await?;
+        if let Ok(value) = serde_json::to_value(&response) {
+            self.insert(generation, key, value);
+        }
+        Ok(response)
+    }
+
+    /// Hits, misses, and size so far.
+    pub fn stats(&self) -> CacheStats {
+        let state = self.lock();
+        CacheStats {
+            hits: state.hits,
+            misses: state.misses,
+            entries: state.entries.len(),
+        }
+    }
+
+    fn get(&self, generation: u64, key: &CacheKey) -> Option<Value> {
+        let mut state = self.lock();
+        if state.generation != generation {
+            return None;
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        let value = state.entries.get_mut(key).map(|(value, used)| {
+            *used = tick;
+            value.clone()
+        });
+        drop(state);
+        value
+    }
+
+    fn insert(&self, generation: u64, key: CacheKey, value: Value) {
+        let mut state = self.lock();
+        // A response computed under an older generation may already be stale.
+        if generation < state.generation {
+            return;
+        }
+        if generation > state.generation {
+            state.entries.clear();
+            state.generation = generation;
+        }
+        while state.entries.len() >= MAX_CACHED_RESPONSES {
+            let Some(oldest) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, used))| *used)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            state.entries.remove(&oldest);
+        }
+        state.tick += 1;
+        let tick = state.tick;
+        state.entries.insert(key, (value, tick));
+        drop(state);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, CacheState> {
+        match self.state.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(content_hash: u64) -> CacheKey {
+        CacheKey::new(
+            "textDocument/hover",
+            "/a.rs",
+            content_hash,
+            "1:2".to_string(),
+        )
+    }
+
+    #[tokio::test]
+    async fn repeated_queries_are_served_from_the_cache() {
+        let cache = ResponseCache::default();
+        let first: u32 = cache
+            .get_or_fetch(0, key(1), async { Ok(7) })
+            .await
+            .unwrap();
+        let second: u32 = cache
+            .get_or_fetch(0, key(1), async {
+                panic!("cached responses must not refetch")
+            })
+            .await
+            .unwrap();
+        assert_eq!((first, second), (7, 7));
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 1,
+                misses: 1,
+                entries: 1
+            }
+        );
+
+        // Different file content is a different key.
+        let changed: u32 = cache
+            .get_or_fetch(0, key(2), async { Ok(8) })
+            .await
+            .unwrap();
+        assert_eq!(changed, 8);
+    }
+
+    #[tokio::test]
+    async fn a_new_generation_invalidates_everything() {
+        let cache = ResponseCache::default();
+        let _: u32 = cache
+            .get_or_fetch(0, key(1), async { Ok(7) })
+            .await
+            .unwrap();
+        let refetched: u32 = cache
+            .get_or_fetch(1, key(1), async { Ok(9) })
+            .await
+            .unwrap();
+        assert_eq!(refetched, 9);
+
+        // A slow response from the old generation is not cached over it.
+        cache.insert(0, key(1), Value::from(5));
+        let current: u32 = cache
+            .get_or_fetch(1, key(1), async {
+                panic!("generation 1 entry must survive")
+            })
+            .await
+            .unwrap();
+        assert_eq!(current, 9);
+    }
+
+    #[tokio::test]
+    async fn errors_are_not_cached() {
+        let cache = ResponseCache::default();
+        let failed = cache
+            .get_or_fetch::<u32, _>(0, key(1), async {
+                Err(McpError::internal_error("boom", None))
+            })
+            .await;
+        assert!(failed.is_err());
+        let ok: u32 = cache
+            .get_or_fetch(0, key(1), async { Ok(3) })
+            .await
+            .unwrap();
+        assert_eq!(ok, 3);
+    }
+}
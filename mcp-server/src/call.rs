@@ -0,0 +1,144 @@
+//! `lspmux-cc-mcp call`: run one tool from the command line.
+//!
+//! The call goes through an in-process MCP session (see [`crate::direct`]),
+//! so it behaves exactly as it would for an MCP host, and rust-analyzer is
+//! reached the usual way: the shared lspmux instance, warm if an editor or
+//! another session already uses it. The tool's text is printed to stdout.
+
+use std::io::Read;
+
+use anyhow::{Context, Result};
+use rmcp::model::{ErrorCode, JsonObject};
+use rmcp::ServerHandler;
+use tokio_util::sync::CancellationToken;
+
+use crate::cli::CallOutput;
+use crate::direct::{result_text, DirectSession};
+
+/// Exit status of a call that ran and succeeded.
+pub const EXIT_SUCCESS: i32 = 0;
+
+/// Exit status of a call the tool failed.
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Exit status of a call rejected before it ran: an unknown tool, or
+/// arguments that are not valid for it.
+pub const EXIT_USAGE: i32 = 2;
+
+/// Exit status of a call interrupted by SIGINT or SIGTERM, as a shell
+/// reports a command killed by SIGINT.
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Call `tool` with the arguments in `json` (a JSON object, or `-` to read
+/// one from stdin), print its result, and return the exit status.
+pub async fn run<S: ServerHandler>(
+    server: S,
+    tool: &str,
+    json: &str,
+    output: CallOutput,
+    shutdown: &CancellationToken,
+) -> i32 {
+    let mut arguments = match parse_arguments(json) {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            return EXIT_USAGE;
+        }
+    };
+    arguments
+        .entry("output")
+        .or_insert_with(|| output.as_str().into());
+    tokio::select! {
+        code = call(server, tool, arguments, shutdown) => code,
+        () = shutdown.cancelled() => {
+            eprintln!("error: interrupted");
+            EXIT_INTERRUPTED
+        }
+    }
+}
+
+async fn call<S: ServerHandler>(
+    server: S,
+    tool: &str,
+    arguments: JsonObject,
+    shutdown: &CancellationToken,
+) -> i32 {
+    let session = match DirectSession::connect(server, shutdown).await {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            return EXIT_FAILURE;
+        }
+    };
+    match session.list_tools().await {
+        Ok(list) if list.tools.iter().any(|listed| listed.name == tool) => {}
+        Ok(list) => {
+            let mut names: Vec<_> = list
+                .tools
+                .iter()
+                .map(|listed| listed.name.as_ref())
+                .collect();
+            names.sort_unstable();
+            eprintln!(
+                "error: no tool named {tool:?} is available; the tools are: {}",
+                names.join(", ")
+            );
+            return EXIT_USAGE;
+        }
+        Err(error) => {
+            eprintln!("error: {}", error.message);
+            return EXIT_FAILURE;
+        }
+    }
+    match session.call_tool(tool, arguments).await {
+        Ok(result) => {
+            let text = result_text(&result);
+            if result.is_error == Some(true) {
+                eprintln!("error: {text}");
+                return EXIT_FAILURE;
+            }
+            println!("{text}");
+            EXIT_SUCCESS
+        }
+        Err(error) => {
+            eprintln!("error: {}", error.message);
+            if matches!(
+                error.code,
+                ErrorCode::INVALID_PARAMS | ErrorCode::INVALID_REQUEST
+            ) {
+                EXIT_USAGE
+            } else {
+                EXIT_FAILURE
+            }
+        }
+    }
+}
+
+/// The tool arguments given by `--json`: a JSON object, or `-` for one on
+/// stdin.
+fn parse_arguments(json: &str) -> Result<JsonObject> {
+    let json = if json == "-" {
+        let mut input = String::new();
+        std::io::stdin()
+            .read_to_string(&mut input)
+            .context("failed to read the tool arguments from stdin")?;
+        input
+    } else {
+        json.to_string()
+    };
+    serde_json::from_str(&json).context("--json must be a JSON object of tool arguments")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn arguments_must_be_a_json_object() {
+        let arguments = parse_arguments(r#"{"file_path": "/src/lib.rs", "line": 3}"#).unwrap();
+        assert_eq!(arguments["line"], 3);
+        assert!(parse_arguments("{}").unwrap().is_empty());
+        assert!(parse_arguments("[]").is_err());
+        assert!(parse_arguments("file_path=/src/lib.rs").is_err());
+    }
+}
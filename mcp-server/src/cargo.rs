@@ -0,0 +1,441 @@
+//! Running cargo for the `cargo_*` tools.
+//!
+//! rust-analyzer looks at one file at a time; cargo builds the workspace and
+//! is the ground truth for errors only a build shows, such as failing build
+//! scripts or link errors. Commands run in the workspace root and are killed
+//! if they outlast their timeout. Other toolchain programs, such as `rustc`
+//! for `rust_explain_error`, run the same way.
+
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+
+use rmcp::ErrorData as McpError;
+use serde::Deserialize;
+
+/// Captured output kept per failed test; longer output is cut.
+const MAX_TEST_OUTPUT_BYTES: usize = 8 * 1024;
+
+/// What a finished cargo command printed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CargoOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Run `cargo` with `args` in `root`.
+///
+/// # Errors
+///
+/// Returns `invalid_params` if cargo is not installed, or an internal error
+/// if it cannot be started or runs longer than `timeout` (it is killed then).
+pub async fn run(root: &Path, args: &[String], timeout: Duration) -> Result<CargoOutput, McpError> {
+    run_program("cargo", root, args, timeout).await
+}
+
+/// Run another toolchain `program`, such as `rustc`, like [`run`] runs cargo.
+///
+/// # Errors
+///
+/// As for [`run`].
+pub async fn run_program(
+    program: &str,
+    root: &Path,
+    args: &[String],
+    timeout: Duration,
+) -> Result<CargoOutput, McpError> {
+    let child = tokio::process::Command::new(program)
+        .args(args)
+        .current_dir(root)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                McpError::invalid_params(format!("{program} is not installed or not on PATH"), None)
+            } else {
+                McpError::internal_error(format!("failed to run {program}: {e}"), None)
+            }
+        })?;
+    let output = tokio::time::timeout(timeout, child.wait_with_output())
+        .await
+        .map_err(|_| {
+            McpError::internal_error(
+                format!(
+                    "`{program} {}` did not finish within {}s and was stopped",
+                    args.join(" "),
+                    timeout.as_secs()
+                ),
+                None,
+            )
+        })?
+        .map_err(|e| McpError::internal_error(format!("failed to run {program}: {e}"), None))?;
+    Ok(CargoOutput {
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// One compiler message, or one error cargo reported itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompilerMessage {
+    /// The package being built, if the message came from compiling one.
+    pub package: Option<String>,
+    /// `error`, `warning`, `note`, `help`, or `failure-note`.
+    pub level: String,
+    pub code: Option<String>,
+    pub message: String,
+    /// The primary span, if the message points into a file.
+    pub span: Option<Span>,
+    /// The message as cargo prints it, with source excerpts.
+    pub rendered: Option<String>,
+}
+
+/// A one-based line and column range in a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Span {
+    /// Absolute path of the file.
+    pub file_path: String,
+    pub line_start: u32,
+    pub column_start: u32,
+    pub line_end: u32,
+    pub column_end: u32,
+}
+
+/// A line of `cargo --message-format=json` output.
+#[derive(Deserialize)]
+#[serde(tag = "reason", rename_all = "kebab-case")]
+enum JsonMessage {
+    CompilerMessage {
+        package_id: String,
+        message: JsonDiagnostic,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct JsonDiagnostic {
+    message: String,
+    code: Option<JsonCode>,
+    level: String,
+    spans: Vec<JsonSpan>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct JsonCode {
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct JsonSpan {
+    file_name: String,
+    line_start: u32,
+    line_end: u32,
+    column_start: u32,
+    column_end: u32,
+    is_primary: bool,
+}
+
+/// The compiler messages in the JSON `stdout` of a cargo build in `root`,
+/// deduplicated (a file built for several targets repeats its messages),
+/// followed by the errors cargo printed to `stderr` itself, like a failed
+/// build script.
+pub fn compiler_messages(root: &Path, stdout: &str, stderr: &str) -> Vec<CompilerMessage> {
+    let mut messages: Vec<CompilerMessage> = Vec::new();
+    for line in stdout.lines() {
+        let Ok(JsonMessage::CompilerMessage {
+            package_id,
+            message,
+        }) = serde_json::from_str(line)
+        else {
+            continue;
+        };
+        // rustc's closing tallies repeat what the other messages say.
+        if message.spans.is_empty()
+            && (message.message.starts_with("aborting due to")
+                || message.message.ends_with("emitted"))
+        {
+            continue;
+        }
+        let span = message
+            .spans
+            .iter()
+            .find(|span| span.is_primary)
+            .map(|span| Span {
+                file_path: resolve(root, &span.file_name),
+                line_start: span.line_start,
+                column_start: span.column_start,
+                line_end: span.line_end,
+                column_end: span.column_end,
+            });
+        let message = CompilerMessage {
+            package: Some(package_name(&package_id).to_string()),
+            level: message.level,
+            code: message.code.map(|code| code.code),
+            message: message.message,
+            span,
+            rendered: message.rendered,
+        };
+        if !messages.contains(&message) {
+            messages.push(message);
+        }
+    }
+    messages.extend(cargo_errors(stderr));
+    messages
+}
+
+/// How a test ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TestOutcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+impl TestOutcome {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Passed => "passed",
+            Self::Failed => "failed",
+            Self::Ignored => "ignored",
+        }
+    }
+}
+
+/// One test from a `cargo test` run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TestResult {
+    pub name: String,
+    pub outcome: TestOutcome,
+    /// What a failed test printed, including its panic message.
+    pub output: Option<String>,
+}
+
+/// The tests in the libtest output of a `cargo test` run, in the order they
+/// finished, with the captured output of each failure.
+pub fn test_results(stdout: &str) -> Vec<TestResult> {
+    let mut results = Vec::new();
+    let mut outputs: Vec<(&str, Vec<&str>)> = Vec::new();
+    let mut capturing = false;
+    for line in stdout.lines() {
+        if let Some(name) = line
+            .strip_prefix("---- ")
+            .and_then(|rest| rest.strip_suffix(" stdout ----"))
+        {
+            outputs.push((name, Vec::new()));
+            capturing = true;
+        } else if line == "failures:" || line == "successes:" || line.starts_with("test result:") {
+            capturing = false;
+        } else if let Some((name, status)) = line
+            .strip_prefix("test ")
+            .and_then(|rest| rest.split_once(" ... "))
+        {
+            let outcome = if status.starts_with("FAILED") {
+                TestOutcome::Failed
+            } else if status.starts_with("ignored") {
+                TestOutcome::Ignored
+            } else {
+                TestOutcome::Passed
+            };
+            results.push(TestResult {
+                name: name.to_string(),
+                outcome,
+                output: None,
+            });
+        } else if capturing {
+            if let Some((_, lines)) = outputs.last_mut() {
+                lines.push(line);
+            }
+        }
+    }
+
+    for result in &mut results {
+        if result.outcome != TestOutcome::Failed {
+            continue;
+        }
+        if let Some((_, lines)) = outputs.iter().find(|(name, _)| *name == result.name) {
+            let mut output = lines.join("\n").trim().to_string();
+            if output.len() > MAX_TEST_OUTPUT_BYTES {
+                let mut end = MAX_TEST_OUTPUT_BYTES;
+                while !output.is_char_boundary(end) {
+                    end -= 1;
+                }
+                output.truncate(end);
+                output.push_str("\n[output truncated]");
+            }
+            result.output = Some(output);
+        }
+    }
+    results
+}
+
+/// The absolute path of `file_name`, which cargo gives relative to the
+/// root of the cargo workspace: `root` or, when `root` is a member, one of
+/// its ancestors.
+fn resolve(root: &Path, file_name: &str) -> String {
+    root.ancestors()
+        .map(|dir| dir.join(file_name))
+        .find(|path| path.exists())
+        .unwrap_or_else(|| root.join(file_name))
+        .display()
+        .to_string()
+}
+
+/// The name in a cargo package ID, either `name 0.1.0 (source)` or
+/// `source#name@0.1.0` (where `name@` is left out when it is the last
+/// component of a path source).
+pub fn package_name(package_id: &str) -> &str {
+    match package_id.split_once('#') {
+        Some((source, fragment)) => match fragment.split_once('@') {
+            Some((name, _)) => name,
+            None => source.rsplit('/').next().unwrap_or(source),
+        },
+        None => package_id.split(' ').next().unwrap_or(package_id),
+    }
+}
+
+/// Errors cargo printed to `stderr` itself, each with the lines that follow
+/// it up to the next status line, leaving out the summary of how many
+/// packages failed to compile.
+fn cargo_errors(stderr: &str) -> Vec<CompilerMessage> {
+    let mut errors: Vec<(String, Vec<&str>)> = Vec::new();
+    let mut current: Option<usize> = None;
+    for line in stderr.lines() {
+        if let Some(message) = line.strip_prefix("error: ") {
+            current = (!message.starts_with("could not compile")).then(|| {
+                errors.push((message.to_string(), vec![line]));
+                errors.len() - 1
+            });
+        } else if is_status_line(line) {
+            current = None;
+        } else if let Some(index) = current {
+            errors[index].1.push(line);
+        }
+    }
+    errors
+        .into_iter()
+        .map(|(message, lines)| CompilerMessage {
+            package: None,
+            level: "error".to_string(),
+            code: None,
+            message,
+            span: None,
+            rendered: Some(lines.join("\n").trim_end().to_string()),
+        })
+        .collect()
+}
+
+/// Whether `line` is a warning or one of cargo's status lines, whose verb
+/// is right-aligned to 12 columns (`   Compiling foo v0.1.0`); either ends
+/// the lines belonging to an error.
+fn is_status_line(line: &str) -> bool {
+    let trimmed = line.trim_start_matches(' ');
+    let indent = line.len() - trimmed.len();
+    let verb = trimmed.split(' ').next().unwrap_or_default();
+    line.starts_with("warning")
+        || (indent > 0
+            && indent + verb.len() == 12
+            && verb.starts_with(char::is_uppercase)
+            && verb.chars().all(char::is_alphabetic))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiler_messages_are_parsed_deduplicated_and_joined_by_cargo_errors() {
+        let error = r#"{"reason":"compiler-message","package_id":"path+file:///ws/app#0.1.0","target":{"name":"app"},"message":{"message":"mismatched types","code":{"code":"E0308","explanation":null},"level":"error","spans":[{"file_name":"src/main.rs","byte_start":1,"byte_end":2,"line_start":3,"line_end":3,"column_start":5,"column_end":9,"is_primary":true,"text":[],"label":null,"suggested_replacement":null,"suggestion_applicability":null,"expansion":null}],"children":[],"rendered":"error[E0308]: mismatched types\n"}}"#;
+        let tally = r#"{"reason":"compiler-message","package_id":"app 0.1.0 (path+file:///ws/app)","target":{"name":"app"},"message":{"message":"aborting due to 1 previous error","code":null,"level":"error","spans":[],"children":[],"rendered":"error: aborting due to 1 previous error\n"}}"#;
+        let finished = r#"{"reason":"build-finished","success":false}"#;
+        let stdout = [error, error, tally, finished, "not json"].join("\n");
+        let stderr = "   Compiling dep v0.1.0\n\
+                      error: failed to run custom build command for `dep v0.1.0`\n\
+                      \n\
+                      Caused by:\n  process didn't exit successfully (exit status: 101)\n  \
+                      --- stderr\n  boom\n\
+                      warning: build failed, waiting for other jobs to finish...\n\
+                      error: could not compile `app` (bin \"app\") due to 1 previous error\n";
+
+        let messages = compiler_messages(Path::new("/ws/app"), &stdout, stderr);
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].package.as_deref(), Some("app"));
+        assert_eq!(messages[0].code.as_deref(), Some("E0308"));
+        let span = messages[0].span.as_ref().unwrap();
+        assert_eq!(span.file_path, "/ws/app/src/main.rs");
+        assert_eq!((span.line_start, span.column_start), (3, 5));
+
+        assert_eq!(
+            messages[1].message,
+            "failed to run custom build command for `dep v0.1.0`"
+        );
+        let rendered = messages[1].rendered.as_deref().unwrap();
+        assert!(rendered.ends_with("--- stderr\n  boom"), "{rendered}");
+    }
+
+    #[test]
+    fn test_results_carry_the_output_of_failures() {
+        let stdout = "\n\
+running 4 tests
+test tests::adds ... ok
+test tests::slow ... ignored, takes a minute
+test tests::divides ... FAILED
+test tests::parses ... ok
+
+successes:
+
+---- tests::parses stdout ----
+parsed
+
+failures:
+
+---- tests::divides stdout ----
+thread 'tests::divides' panicked at src/lib.rs:9:5:
+attempt to divide by zero
+
+failures:
+    tests::divides
+
+test result: FAILED. 2 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out
+";
+        let results = test_results(stdout);
+        let outcomes: Vec<_> = results
+            .iter()
+            .map(|result| (result.name.as_str(), result.outcome.as_str()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                ("tests::adds", "passed"),
+                ("tests::slow", "ignored"),
+                ("tests::divides", "failed"),
+                ("tests::parses", "passed"),
+            ]
+        );
+        assert_eq!(
+            results[2].output.as_deref(),
+            Some("thread 'tests::divides' panicked at src/lib.rs:9:5:\nattempt to divide by zero")
+        );
+        assert_eq!(results[3].output, None);
+    }
+
+    #[test]
+    fn package_names_are_read_from_either_id_format() {
+        assert_eq!(package_name("serde 1.0.0 (registry+https://x)"), "serde");
+        assert_eq!(
+            package_name("registry+https://github.com/rust-lang/crates.io-index#serde@1.0.0"),
+            "serde"
+        );
+        assert_eq!(package_name("path+file:///ws/app#0.1.0"), "app");
+        assert_eq!(
+            package_name("path+file:///ws/crates/core#core-lib@0.2.0"),
+            "core-lib"
+        );
+    }
+}
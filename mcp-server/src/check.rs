@@ -0,0 +1,490 @@
+//! `lspmux-cc-mcp check`: rust-analyzer's diagnostics as a CI step.
+//!
+//! Runs cargo check through rust-analyzer (`rust_run_flycheck`), gathers the
+//! diagnostics of the given paths (`rust_diagnostics_dir`), and prints them
+//! as text, JSON, or SARIF. Like `call`, it goes through an in-process MCP
+//! session (see [`crate::direct`]).
+//!
+//! Exits 0 if nothing failed the check, [`EXIT_FINDINGS`] if an error (or,
+//! with `--deny-warnings`, a warning) was found, and [`EXIT_INCOMPLETE`] if
+//! the check could not run or did not cover every file.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use rmcp::model::JsonObject;
+use rmcp::{ErrorData as McpError, ServerHandler};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::call::EXIT_INTERRUPTED;
+use crate::cli::{CheckArgs, ReportFormat};
+use crate::direct::DirectSession;
+use crate::files::rust_files;
+use crate::tools::{
+    DiagnosticRecord, DirDiagnosticsResponse, FileDiagnostics, FileFailure, FlycheckResponse,
+};
+
+/// Exit status when diagnostics failed the check.
+pub const EXIT_FINDINGS: i32 = 1;
+
+/// Exit status when the check could not run, or left files unchecked.
+pub const EXIT_INCOMPLETE: i32 = 2;
+
+const SARIF_SCHEMA: &str = "https://json.schemastore.org/sarif-2.1.0.json";
+
+/// The diagnostics of every path checked.
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    /// Distinct files checked, however many paths covered each.
+    files_checked: usize,
+    error_count: usize,
+    warning_count: usize,
+    diagnostic_count: usize,
+    /// Whether some path matched more files than `--max-files`.
+    truncated: bool,
+    /// Files with at least one reported diagnostic, sorted by path.
+    files: Vec<FileDiagnostics>,
+    failures: Vec<FileFailure>,
+}
+
+impl Report {
+    /// Merge the reports of several paths, which together covered the
+    /// `checked` files, counting a file they share once.
+    fn merge(responses: Vec<DirDiagnosticsResponse>, checked: &BTreeSet<PathBuf>) -> Self {
+        let mut files = BTreeMap::new();
+        let mut failures = BTreeMap::new();
+        let mut report = Self {
+            files_checked: checked.len(),
+            ..Self::default()
+        };
+        for response in responses {
+            report.truncated |= response.truncated;
+            for file in response.files {
+                files.entry(file.file_path.clone()).or_insert(file);
+            }
+            for failure in response.failures {
+                failures.entry(failure.file_path.clone()).or_insert(failure);
+            }
+        }
+        report.files = files.into_values().collect();
+        report.failures = failures.into_values().collect();
+        report.error_count = report.files.iter().map(|file| file.error_count).sum();
+        report.warning_count = report.files.iter().map(|file| file.warning_count).sum();
+        report.diagnostic_count = report.files.iter().map(|file| file.diagnostics.len()).sum();
+        report
+    }
+
+    const fn exit_code(&self, deny_warnings: bool) -> i32 {
+        if self.truncated || !self.failures.is_empty() {
+            EXIT_INCOMPLETE
+        } else if self.error_count > 0 || (deny_warnings && self.warning_count > 0) {
+            EXIT_FINDINGS
+        } else {
+            0
+        }
+    }
+}
+
+/// Run the check `args` describes against `server`, print the report, and
+/// return the exit status. Paths are relative to the current directory;
+/// without any, `root` is checked.
+pub async fn run<S: ServerHandler>(
+    server: S,
+    args: &CheckArgs,
+    root: &Path,
+    shutdown: &CancellationToken,
+) -> i32 {
+    tokio::select! {
+        code = check(server, args, root, shutdown) => code,
+        () = shutdown.cancelled() => {
+            eprintln!("error: interrupted");
+            EXIT_INTERRUPTED
+        }
+    }
+}
+
+async fn check<S: ServerHandler>(
+    server: S,
+    args: &CheckArgs,
+    root: &Path,
+    shutdown: &CancellationToken,
+) -> i32 {
+    let session = match DirectSession::connect(server, shutdown).await {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("error: {e:#}");
+            return EXIT_INCOMPLETE;
+        }
+    };
+    let mut complete = true;
+    if !args.no_cargo_check {
+        match cargo_check(&session, args.check_timeout).await {
+            Ok(None) => {}
+            Ok(Some(note)) => eprintln!("note: {note}"),
+            Err(error) => {
+                eprintln!("error: {error}");
+                complete = false;
+            }
+        }
+    }
+
+    let targets = targets(&args.paths, root);
+    let mut responses = Vec::new();
+    for path in &targets {
+        let mut arguments = JsonObject::new();
+        arguments.insert("path".into(), path.display().to_string().into());
+        arguments.insert("min_severity".into(), args.min_severity.as_str().into());
+        arguments.insert("max_files".into(), args.max_files.into());
+        match call_tool(&session, "rust_diagnostics_dir", arguments).await {
+            Ok(response) => responses.push(response),
+            Err(error) => {
+                eprintln!("error: {}", error.message);
+                return EXIT_INCOMPLETE;
+            }
+        }
+    }
+    let report = Report::merge(responses, &checked_files(&targets, args.max_files));
+    match args.format {
+        ReportFormat::Text => print!("{}", render_text(&report, root)),
+        ReportFormat::Json => println!("{}", pretty(&report)),
+        ReportFormat::Sarif => println!("{}", pretty(&sarif(&report, root))),
+    }
+    for failure in &report.failures {
+        eprintln!(
+            "error: {} could not be checked: {}",
+            failure.file_path, failure.error
+        );
+    }
+    if report.truncated {
+        eprintln!(
+            "error: more than {} files matched; raise --max-files to check them all",
+            args.max_files
+        );
+    }
+    match report.exit_code(args.deny_warnings) {
+        0 if !complete => EXIT_INCOMPLETE,
+        code => code,
+    }
+}
+
+/// Run cargo check and wait for it. Returns a note if it did not run, and
+/// an error if it did not finish.
+async fn cargo_check(session: &DirectSession, timeout_secs: u64) -> Result<Option<String>, String> {
+    let mut arguments = JsonObject::new();
+    arguments.insert("timeout_secs".into(), timeout_secs.into());
+    let response: FlycheckResponse = call_tool(session, "rust_run_flycheck", arguments)
+        .await
+        .map_err(|error| format!("cargo check failed: {}", error.message))?;
+    match response.outcome.as_deref() {
+        Some("timed_out") => Err(format!(
+            "cargo check had not finished after {timeout_secs}s; its diagnostics are incomplete"
+        )),
+        Some("no_check") => Ok(Some(
+            "cargo check did not run (is rust-analyzer's `check.command` disabled?); only \
+             rust-analyzer's own diagnostics are reported"
+                .to_string(),
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Call `tool` and parse its structured result.
+async fn call_tool<T: DeserializeOwned>(
+    session: &DirectSession,
+    tool: &str,
+    arguments: JsonObject,
+) -> Result<T, McpError> {
+    let result = session.call_tool(tool, arguments).await?;
+    if result.is_error == Some(true) {
+        return Err(McpError::internal_error(
+            crate::direct::result_text(&result),
+            None,
+        ));
+    }
+    let structured = result
+        .structured_content
+        .ok_or_else(|| McpError::internal_error(format!("{tool} returned no result"), None))?;
+    serde_json::from_value(structured)
+        .map_err(|e| McpError::internal_error(format!("malformed {tool} result: {e}"), None))
+}
+
+/// The absolute paths to check: `paths` resolved against the current
+/// directory, or `root` if there are none.
+fn targets(paths: &[String], root: &Path) -> Vec<PathBuf> {
+    if paths.is_empty() {
+        return vec![root.to_path_buf()];
+    }
+    let cwd = std::env::current_dir().unwrap_or_else(|_| root.to_path_buf());
+    paths.iter().map(|path| cwd.join(path)).collect()
+}
+
+/// The Rust files `targets` cover, each once, as `rust_diagnostics_dir`
+/// expands them.
+fn checked_files(targets: &[PathBuf], max_files: usize) -> BTreeSet<PathBuf> {
+    targets
+        .iter()
+        .filter_map(|path| rust_files(&path.display().to_string(), max_files).ok())
+        .flat_map(|(files, _)| files)
+        .collect()
+}
+
+/// `path` relative to `root`, if it is inside it.
+fn relative<'a>(path: &'a str, root: &Path) -> Option<&'a str> {
+    Path::new(path)
+        .strip_prefix(root)
+        .ok()
+        .and_then(Path::to_str)
+}
+
+fn pretty(value: &impl Serialize) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|e| format!("{{\"error\": \"{e}\"}}"))
+}
+
+/// One `path:line:column: severity[code]: message` line per diagnostic, in
+/// the style of rustc, then the totals.
+fn render_text(report: &Report, root: &Path) -> String {
+    let mut out = String::new();
+    for file in &report.files {
+        let path = relative(&file.file_path, root).unwrap_or(&file.file_path);
+        for diagnostic in &file.diagnostics {
+            let code = diagnostic
+                .code
+                .as_ref()
+                .map(|code| format!("[{code}]"))
+                .unwrap_or_default();
+            let message = diagnostic.message.lines().next().unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{path}:{}:{}: {}{code}: {message}",
+                diagnostic.location.line, diagnostic.location.column, diagnostic.severity
+            );
+        }
+    }
+    let _ = writeln!(
+        out,
+        "Checked {} file(s): {} error(s), {} warning(s), {} diagnostic(s) in {} file(s).",
+        report.files_checked,
+        report.error_count,
+        report.warning_count,
+        report.diagnostic_count,
+        report.files.len()
+    );
+    out
+}
+
+/// The rule a diagnostic is filed under in SARIF: its code, else its source.
+fn rule_id(diagnostic: &DiagnosticRecord) -> String {
+    diagnostic
+        .code
+        .clone()
+        .or_else(|| diagnostic.source.clone())
+        .unwrap_or_else(|| "rust-analyzer".to_string())
+}
+
+/// The report as a SARIF 2.1.0 log with one run. Files in `root` are
+/// addressed relative to it, through the `SRCROOT` base.
+fn sarif(report: &Report, root: &Path) -> Value {
+    let mut rules = BTreeSet::new();
+    let mut results = Vec::new();
+    for file in &report.files {
+        let artifact = relative(&file.file_path, root).map_or_else(
+            || json!({ "uri": file.diagnostics.first().map(|d| d.location.uri.as_str()) }),
+            |path| json!({ "uri": path, "uriBaseId": "SRCROOT" }),
+        );
+        for diagnostic in &file.diagnostics {
+            let rule = rule_id(diagnostic);
+            let level = match diagnostic.severity.as_str() {
+                "error" => "error",
+                "warning" => "warning",
+                _ => "note",
+            };
+            let location = &diagnostic.location;
+            results.push(json!({
+                "ruleId": rule,
+                "level": level,
+                "message": { "text": diagnostic.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": artifact,
+                        "region": {
+                            "startLine": location.line,
+                            "startColumn": location.column,
+                            "endLine": location.end_line,
+                            "endColumn": location.end_column,
+                        },
+                    },
+                }],
+            }));
+            rules.insert(rule);
+        }
+    }
+    let root_uri = lspmux_cc_mcp::lsp_client::file_uri(&format!("{}/", root.display()))
+        .map_or_else(|_| root.display().to_string(), |uri| uri.to_string());
+    json!({
+        "version": "2.1.0",
+        "$schema": SARIF_SCHEMA,
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules.into_iter().map(|id| json!({ "id": id })).collect::<Vec<_>>(),
+                },
+            },
+            "originalUriBaseIds": { "SRCROOT": { "uri": root_uri } },
+            "results": results,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::LocationRecord;
+
+    fn diagnostic(severity: &str, code: Option<&str>, line: u32) -> DiagnosticRecord {
+        DiagnosticRecord {
+            index: 0,
+            severity: severity.to_string(),
+            message: format!("{severity} on line {line}\nwith a note"),
+            code: code.map(ToOwned::to_owned),
+            source: Some("rustc".to_string()),
+            location: LocationRecord {
+                file_path: "/repo/src/lib.rs".to_string(),
+                uri: "file:///repo/src/lib.rs".to_string(),
+                line,
+                column: 5,
+                end_line: line,
+                end_column: 9,
+                display: format!("/repo/src/lib.rs:{line}:5"),
+                snippet: None,
+            },
+            related: Vec::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    fn response(diagnostics: Vec<DiagnosticRecord>) -> DirDiagnosticsResponse {
+        DirDiagnosticsResponse {
+            path: "/repo".to_string(),
+            files_checked: 2,
+            truncated: false,
+            error_count: diagnostics.iter().filter(|d| d.severity == "error").count(),
+            warning_count: diagnostics
+                .iter()
+                .filter(|d| d.severity == "warning")
+                .count(),
+            diagnostic_count: diagnostics.len(),
+            files: vec![FileDiagnostics {
+                file_path: "/repo/src/lib.rs".to_string(),
+                error_count: diagnostics.iter().filter(|d| d.severity == "error").count(),
+                warning_count: diagnostics
+                    .iter()
+                    .filter(|d| d.severity == "warning")
+                    .count(),
+                diagnostics,
+            }],
+            failures: Vec::new(),
+            summary: String::new(),
+        }
+    }
+
+    fn checked() -> BTreeSet<PathBuf> {
+        ["/repo/src/lib.rs", "/repo/src/main.rs"]
+            .into_iter()
+            .map(PathBuf::from)
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_paths_count_each_file_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let src = dir.path().join("src");
+        std::fs::create_dir_all(src.join("bin")).unwrap();
+        for file in ["lib.rs", "bin/tool.rs"] {
+            std::fs::write(src.join(file), "").unwrap();
+        }
+        let targets = [
+            dir.path().to_path_buf(),
+            src.join("bin"),
+            src.join("lib.rs"),
+        ];
+        let checked = checked_files(&targets, 500);
+        assert_eq!(checked.len(), 2);
+
+        let mut whole = response(Vec::new());
+        whole.files_checked = 2;
+        let mut part = response(Vec::new());
+        part.files_checked = 1;
+        let report = Report::merge(vec![whole, part.clone(), part], &checked);
+        assert_eq!(report.files_checked, 2);
+        assert!(render_text(&report, dir.path()).starts_with("Checked 2 file(s)"));
+    }
+
+    #[test]
+    fn errors_fail_the_check_and_warnings_only_when_denied() {
+        let warnings = Report::merge(
+            vec![response(vec![diagnostic("warning", None, 3)])],
+            &checked(),
+        );
+        assert_eq!(warnings.exit_code(false), 0);
+        assert_eq!(warnings.exit_code(true), EXIT_FINDINGS);
+
+        // A file reported by two overlapping paths counts once.
+        let errors = Report::merge(
+            vec![
+                response(vec![diagnostic("error", Some("E0308"), 7)]),
+                response(vec![diagnostic("error", Some("E0308"), 7)]),
+            ],
+            &checked(),
+        );
+        assert_eq!(errors.error_count, 1);
+        assert_eq!(errors.exit_code(false), EXIT_FINDINGS);
+
+        let mut truncated = response(Vec::new());
+        truncated.truncated = true;
+        assert_eq!(
+            Report::merge(vec![truncated], &checked()).exit_code(false),
+            EXIT_INCOMPLETE
+        );
+    }
+
+    #[test]
+    fn reports_render_as_text_and_sarif() {
+        let report = Report::merge(
+            vec![response(vec![
+                diagnostic("error", Some("E0308"), 7),
+                diagnostic("hint", None, 9),
+            ])],
+            &checked(),
+        );
+        let root = Path::new("/repo");
+        assert_eq!(
+            render_text(&report, root),
+            "src/lib.rs:7:5: error[E0308]: error on line 7\n\
+             src/lib.rs:9:5: hint: hint on line 9\n\
+             Checked 2 file(s): 1 error(s), 0 warning(s), 2 diagnostic(s) in 1 file(s).\n"
+        );
+
+        let log = sarif(&report, root);
+        let run = &log["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"],
+            json!([{ "id": "E0308" }, { "id": "rustc" }])
+        );
+        assert_eq!(run["originalUriBaseIds"]["SRCROOT"]["uri"], "file:///repo/");
+        let result = &run["results"][0];
+        assert_eq!(result["level"], "error");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(
+            location["artifactLocation"],
+            json!({ "uri": "src/lib.rs", "uriBaseId": "SRCROOT" })
+        );
+        assert_eq!(location["region"]["startLine"], 7);
+        assert_eq!(run["results"][1]["level"], "note");
+    }
+}
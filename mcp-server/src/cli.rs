@@ -0,0 +1,808 @@
+//! Command-line interface.
+//!
+//! Every option falls back to an environment variable so MCP host configs
+//! that only set `env` keep working, then to the config file (see
+//! [`lspmux_cc_mcp::config`]); an explicit flag always wins.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use lspmux_cc_mcp::bootstrap::{BootstrapMode, LspTransport, RuntimeConfig};
+use lspmux_cc_mcp::config::{FileConfig, CONFIG_PATH_ENV};
+use lspmux_cc_mcp::launcher::DEFAULT_ANALYSIS_TARGET_DIR;
+use lspmux_cc_mcp::lsp_client::{
+    FileSizeLimit, LargeFileMode, DEFAULT_DOCUMENT_BUDGET, DEFAULT_MAX_FILE_SIZE,
+    DEFAULT_REQUEST_TIMEOUT,
+};
+use lspmux_cc_mcp::timeouts::{MethodClass, RequestTimeouts};
+use lspmux_cc_mcp::watchdog::DEFAULT_PROBE_INTERVAL;
+use serde::Serialize;
+
+use crate::limits::{DEFAULT_INDEX_WAIT, DEFAULT_MAX_IN_FLIGHT};
+use crate::pagination::DEFAULT_RESULT_BUDGET;
+
+/// MCP server exposing rust-analyzer through a shared lspmux instance.
+#[derive(Debug, Parser)]
+#[command(name = "lspmux-cc-mcp", version, about)]
+pub struct Cli {
+    /// Config file (default: `$XDG_CONFIG_HOME/lspmux-cc/config.toml` if present).
+    #[arg(long, env = CONFIG_PATH_ENV, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Path to the lspmux binary (default: `lspmux` on PATH, then `$CARGO_HOME/bin`).
+    #[arg(long, env = "LSPMUX_PATH", value_name = "PATH")]
+    pub lspmux_bin: Option<String>,
+
+    /// Path to the rust-analyzer binary (default: rustup proxy or PATH lookup).
+    #[arg(long, env = "RUST_ANALYZER_PATH", value_name = "PATH")]
+    pub rust_analyzer_bin: Option<String>,
+
+    /// Workspace root handed to rust-analyzer (default: the current directory).
+    #[arg(long, env = "WORKSPACE_ROOT", value_name = "DIR")]
+    pub workspace_root: Option<String>,
+
+    /// Seconds to wait for a single LSP response before failing the tool call (default: 30).
+    #[arg(
+        long,
+        env = "LSPMUX_TIMEOUT",
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..),
+    )]
+    pub timeout: Option<u64>,
+
+    /// Largest file, in bytes, sent to rust-analyzer (default: 10 MiB).
+    #[arg(
+        long,
+        env = "LSPMUX_MAX_FILE_SIZE",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u64).range(1..),
+    )]
+    pub max_file_size: Option<u64>,
+
+    /// What tools do with files over `--max-file-size`: fail with an
+    /// explanation (`refuse`) or query only the leading part (`truncate`).
+    /// Default: refuse.
+    #[arg(long, env = "LSPMUX_LARGE_FILES", value_enum, value_name = "MODE")]
+    pub large_files: Option<LargeFileMode>,
+
+    /// Total bytes of files kept open in rust-analyzer before the least
+    /// recently used are closed (default: 64 MiB).
+    #[arg(
+        long,
+        env = "LSPMUX_DOCUMENT_BUDGET",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u64).range(1..),
+    )]
+    pub document_budget: Option<u64>,
+
+    /// Tool calls run at once across all sessions; further calls queue until
+    /// one finishes (default: 16).
+    #[arg(
+        long,
+        env = "LSPMUX_MAX_IN_FLIGHT",
+        value_name = "CALLS",
+        value_parser = clap::value_parser!(u64).range(1..),
+    )]
+    pub max_in_flight: Option<u64>,
+
+    /// Bytes of items a paginated tool result may take before the page is
+    /// cut short with a truncation note and a cursor (default: 65536).
+    #[arg(
+        long,
+        env = "LSPMUX_MAX_RESULT_BYTES",
+        value_name = "BYTES",
+        value_parser = clap::value_parser!(u64).range(1..),
+    )]
+    pub max_result_bytes: Option<u64>,
+
+    /// Seconds a query waits for rust-analyzer to finish loading the
+    /// workspace before running anyway. 0 runs queries at once (default: 60).
+    #[arg(long, env = "LSPMUX_INDEX_WAIT", value_name = "SECS")]
+    pub index_wait: Option<u64>,
+
+    /// Seconds between probes of rust-analyzer; after three unanswered
+    /// probes in a row the connection is restarted. 0 turns the watchdog
+    /// off (default: 30).
+    #[arg(long, env = "LSPMUX_WATCHDOG_INTERVAL", value_name = "SECS")]
+    pub watchdog_interval: Option<u64>,
+
+    /// Target directory for rust-analyzer's builds, relative to the
+    /// workspace root, kept apart from the user's so neither waits on the
+    /// other's lock. `off` builds in the user's (default: target/lspmux-cc).
+    #[arg(long, env = "LSPMUX_ANALYSIS_TARGET_DIR", value_name = "DIR")]
+    pub analysis_target_dir: Option<String>,
+
+    /// Record every message exchanged with lspmux to a new JSON-lines file
+    /// in this directory, for replay in tests or offline debugging.
+    #[arg(long, env = "LSPMUX_RECORD_DIR", value_name = "DIR")]
+    pub record: Option<PathBuf>,
+
+    /// Log every message exchanged with lspmux to this file for debugging,
+    /// with document text cut to a preview. Rotated at 10 MiB.
+    #[arg(long, env = "LSPMUX_WIRETAP", value_name = "FILE")]
+    pub wiretap: Option<PathBuf>,
+
+    /// How to reach the lspmux server: reuse or start the shared service
+    /// (`auto`), require it (`require`), assume it (`off`), or run and
+    /// supervise a private one (`managed`). Default: auto.
+    #[arg(long, env = "LSPMUX_BOOTSTRAP", value_enum, value_name = "MODE")]
+    pub bootstrap: Option<BootstrapMode>,
+
+    /// How the LSP client reaches the lspmux server: over its socket, falling
+    /// back to an `lspmux client` child process (`auto`), over the socket
+    /// only (`socket`), or through the child process only (`child`).
+    /// Default: auto.
+    #[arg(long, env = "LSPMUX_LSP_TRANSPORT", value_enum, value_name = "MODE")]
+    pub lsp_transport: Option<LspTransport>,
+
+    /// Start and prime rust-analyzer in the background at startup, so the
+    /// first query does not hit a cold index.
+    #[arg(long, env = "LSPMUX_WARMUP")]
+    pub warmup: bool,
+
+    /// If the lspmux binary is missing, run rust-analyzer directly instead
+    /// of failing. It is then not shared with editors or other sessions.
+    #[arg(long, env = "LSPMUX_NO_MUX_FALLBACK")]
+    pub no_mux_fallback: bool,
+
+    /// Log filter for stderr, e.g. `info` or `lspmux_cc_mcp=debug` (default: warn).
+    #[arg(long, env = "RUST_LOG", value_name = "FILTER")]
+    pub log_level: Option<String>,
+
+    /// How MCP hosts connect to this server.
+    #[arg(long, value_enum, default_value_t = TransportKind::Stdio)]
+    pub transport: TransportKind,
+
+    /// Address (`http`, `sse`) or socket path (`unix`) to listen on.
+    #[arg(long, value_name = "ADDR|PATH")]
+    pub listen: Option<String>,
+
+    /// Shorthand for `--transport http --listen <ADDR>`.
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["transport", "listen", "sse", "listen_unix"])]
+    pub http: Option<SocketAddr>,
+
+    /// Shorthand for `--transport sse --listen <ADDR>`.
+    #[arg(long, value_name = "ADDR", conflicts_with_all = ["transport", "listen", "listen_unix"])]
+    pub sse: Option<SocketAddr>,
+
+    /// Shorthand for `--transport unix --listen <PATH>`.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["transport", "listen"])]
+    pub listen_unix: Option<PathBuf>,
+
+    /// Serve Prometheus metrics at `http://<ADDR>/metrics`.
+    #[arg(long, env = "LSPMUX_METRICS_LISTEN", value_name = "ADDR")]
+    pub metrics_listen: Option<SocketAddr>,
+
+    /// Serve the tools as a plain HTTP/JSON API at `http://<ADDR>/tools`,
+    /// alongside the MCP transport.
+    #[arg(long, env = "LSPMUX_API_LISTEN", value_name = "ADDR")]
+    pub api_listen: Option<SocketAddr>,
+
+    /// Bearer token the HTTP API requires (default: a random one, written
+    /// to `lspmux-cc/api-token` in the user's cache directory).
+    #[arg(
+        long,
+        env = "LSPMUX_API_TOKEN",
+        value_name = "TOKEN",
+        hide_env_values = true
+    )]
+    pub api_token: Option<String>,
+
+    /// Print the effective configuration as TOML and exit.
+    #[arg(long)]
+    pub print_config: bool,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Subcommands run instead of the MCP server.
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start lspmux and rust-analyzer, run every health check end to end,
+    /// and print a fix for each failure. Exits non-zero if any check fails.
+    Doctor {
+        /// Seconds to wait for rust-analyzer to finish indexing.
+        #[arg(long, value_name = "SECS", default_value_t = 60)]
+        wait: u64,
+    },
+    /// Run one tool, print its result, and exit: 0 if it succeeded, 1 if it
+    /// failed, 2 for an unknown tool or invalid arguments.
+    Call {
+        /// Tool name, e.g. `rust_hover`.
+        tool: String,
+        /// Tool arguments as a JSON object, or `-` to read them from stdin.
+        #[arg(long, value_name = "JSON", default_value = "{}")]
+        json: String,
+        /// Print the readable summary (`text`) or the structured result (`json`).
+        #[arg(long, value_enum, default_value_t = CallOutput::Text)]
+        output: CallOutput,
+    },
+    /// Run cargo check through rust-analyzer, report the diagnostics of the
+    /// given files (default: every Rust file in the workspace), and exit 1 if
+    /// any is an error, or 2 if the check could not run. For CI.
+    Check(CheckArgs),
+}
+
+/// Options of the `check` subcommand.
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    /// Files, directories, or globs to report on, relative to the current
+    /// directory (default: the workspace root).
+    #[arg(value_name = "PATH")]
+    pub paths: Vec<String>,
+    /// Report format.
+    #[arg(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+    /// Least severe diagnostic to report.
+    #[arg(long, value_enum, value_name = "LEVEL", default_value_t = Severity::Warning)]
+    pub min_severity: Severity,
+    /// Exit 1 on warnings too.
+    #[arg(long)]
+    pub deny_warnings: bool,
+    /// Report only rust-analyzer's own diagnostics, without running cargo check.
+    #[arg(long)]
+    pub no_cargo_check: bool,
+    /// Seconds to wait for cargo check to finish (at most 600).
+    #[arg(long, value_name = "SECS", default_value_t = 600)]
+    pub check_timeout: u64,
+    /// Most files to report on; the check fails if more match.
+    #[arg(long, value_name = "FILES", default_value_t = 10_000)]
+    pub max_files: usize,
+}
+
+/// Formats of the `check` report.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    /// One `path:line:column: severity[code]: message` line per diagnostic.
+    Text,
+    /// The diagnostics grouped by file, with totals.
+    Json,
+    /// SARIF 2.1.0, for code scanning services.
+    Sarif,
+}
+
+/// Diagnostic severities accepted by `check --min-severity`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Severity {
+    Error,
+    Warning,
+    Info,
+    Hint,
+}
+
+impl Severity {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+            Self::Info => "info",
+            Self::Hint => "hint",
+        }
+    }
+}
+
+/// What `call` prints, passed to the tool as its `output` argument.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum CallOutput {
+    Text,
+    Json,
+}
+
+impl CallOutput {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// Transport names accepted by `--transport`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TransportKind {
+    Stdio,
+    Http,
+    Sse,
+    Unix,
+}
+
+/// How MCP hosts connect to this server.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Transport {
+    /// A single host speaking MCP over this process's stdin/stdout.
+    Stdio,
+    /// Streamable HTTP at `http://<addr>/mcp`, one MCP session per client.
+    Http(SocketAddr),
+    /// Legacy HTTP+SSE at `http://<addr>/sse`, one MCP session per stream.
+    Sse(SocketAddr),
+    /// Unix domain socket at `<path>`, one MCP session per connection.
+    Unix(PathBuf),
+}
+
+impl Transport {
+    const fn name(&self) -> &'static str {
+        match self {
+            Self::Stdio => "stdio",
+            Self::Http(_) => "http",
+            Self::Sse(_) => "sse",
+            Self::Unix(_) => "unix",
+        }
+    }
+
+    fn listen(&self) -> Option<String> {
+        match self {
+            Self::Stdio => None,
+            Self::Http(addr) | Self::Sse(addr) => Some(addr.to_string()),
+            Self::Unix(path) => Some(path.display().to_string()),
+        }
+    }
+}
+
+impl Cli {
+    /// The transport selected by `--transport`/`--listen` or a shorthand flag.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `--listen` is missing, malformed, or not applicable.
+    pub fn transport(&self) -> Result<Transport> {
+        if let Some(addr) = self.http {
+            return Ok(Transport::Http(addr));
+        }
+        if let Some(addr) = self.sse {
+            return Ok(Transport::Sse(addr));
+        }
+        if let Some(path) = &self.listen_unix {
+            return Ok(Transport::Unix(path.clone()));
+        }
+
+        let listen = self.listen.as_deref();
+        let parse_addr = |value: &str| -> Result<SocketAddr> {
+            value.parse().with_context(|| {
+                format!("invalid --listen address {value:?}; expected e.g. 127.0.0.1:7878")
+            })
+        };
+        let need_listen = |kind: &str| -> Result<&str> {
+            listen.with_context(|| format!("--transport {kind} requires --listen"))
+        };
+        Ok(match self.transport {
+            TransportKind::Stdio => {
+                if listen.is_some() {
+                    bail!("--listen has no effect with --transport stdio");
+                }
+                Transport::Stdio
+            }
+            TransportKind::Http => Transport::Http(parse_addr(need_listen("http")?)?),
+            TransportKind::Sse => Transport::Sse(parse_addr(need_listen("sse")?)?),
+            TransportKind::Unix => Transport::Unix(PathBuf::from(need_listen("unix")?)),
+        })
+    }
+
+    /// Fill every setting not given by a flag or environment variable from
+    /// the config file.
+    #[must_use]
+    pub fn with_file_defaults(mut self, file: &FileConfig) -> Self {
+        self.lspmux_bin = self.lspmux_bin.or_else(|| file.lspmux_bin.clone());
+        self.rust_analyzer_bin = self
+            .rust_analyzer_bin
+            .or_else(|| file.server_bin().map(ToOwned::to_owned));
+        self.workspace_root = self.workspace_root.or_else(|| file.workspace_root.clone());
+        self.timeout = self.timeout.or(file.timeout);
+        self.max_file_size = self.max_file_size.or(file.max_file_size);
+        self.large_files = self.large_files.or(file.large_files);
+        self.document_budget = self.document_budget.or(file.document_budget);
+        self.max_in_flight = self
+            .max_in_flight
+            .or_else(|| file.tools.max_in_flight.map(|calls| calls as u64));
+        self.max_result_bytes = self
+            .max_result_bytes
+            .or_else(|| file.tools.max_result_bytes.map(|bytes| bytes as u64));
+        self.index_wait = self.index_wait.or(file.index_wait);
+        self.watchdog_interval = self.watchdog_interval.or(file.watchdog_interval);
+        self.analysis_target_dir = self
+            .analysis_target_dir
+            .or_else(|| file.analysis_target_dir.clone());
+        self.log_level = self.log_level.or_else(|| file.log_level.clone());
+        self.bootstrap = self.bootstrap.or(file.bootstrap);
+        self.lsp_transport = self.lsp_transport.or(file.lsp_transport);
+        self.warmup = self.warmup || file.warmup.unwrap_or(false);
+        self.no_mux_fallback = self.no_mux_fallback || file.no_mux_fallback.unwrap_or(false);
+        self
+    }
+
+    /// LSP timeout for requests outside every method class.
+    pub fn request_timeout(&self) -> Duration {
+        self.timeout
+            .map_or(DEFAULT_REQUEST_TIMEOUT, Duration::from_secs)
+    }
+
+    /// LSP timeouts by method, with the class timeouts from `file`.
+    pub fn request_timeouts(&self, file: &FileConfig) -> RequestTimeouts {
+        RequestTimeouts::new(self.request_timeout()).with_configured(&file.timeouts)
+    }
+
+    /// Size limit for files sent to rust-analyzer.
+    pub fn file_size_limit(&self) -> FileSizeLimit {
+        FileSizeLimit {
+            max_bytes: self.max_file_size.unwrap_or(DEFAULT_MAX_FILE_SIZE),
+            mode: self.large_files.unwrap_or_default(),
+        }
+    }
+
+    /// Total bytes of open documents kept in rust-analyzer.
+    pub fn document_budget(&self) -> u64 {
+        self.document_budget.unwrap_or(DEFAULT_DOCUMENT_BUDGET)
+    }
+
+    /// Tool calls run at once before further calls queue.
+    pub fn max_in_flight(&self) -> usize {
+        self.max_in_flight
+            .and_then(|calls| usize::try_from(calls).ok())
+            .unwrap_or(DEFAULT_MAX_IN_FLIGHT)
+    }
+
+    /// Bytes of items on one page of a paginated tool result.
+    pub fn max_result_bytes(&self) -> usize {
+        self.max_result_bytes
+            .and_then(|bytes| usize::try_from(bytes).ok())
+            .unwrap_or(DEFAULT_RESULT_BUDGET)
+    }
+
+    /// How long a query waits for the workspace to load.
+    pub fn index_wait(&self) -> Duration {
+        self.index_wait
+            .map_or(DEFAULT_INDEX_WAIT, Duration::from_secs)
+    }
+
+    /// How often the watchdog probes rust-analyzer, or `None` if it is off.
+    pub const fn watchdog_interval(&self) -> Option<Duration> {
+        match self.watchdog_interval {
+            Some(0) => None,
+            Some(secs) => Some(Duration::from_secs(secs)),
+            None => Some(DEFAULT_PROBE_INTERVAL),
+        }
+    }
+
+    /// Where rust-analyzer builds, or `None` to build in the user's target
+    /// directory.
+    pub fn analysis_target_dir(&self) -> Option<&str> {
+        match self.analysis_target_dir.as_deref() {
+            Some("off") => None,
+            Some(dir) => Some(dir),
+            None => Some(DEFAULT_ANALYSIS_TARGET_DIR),
+        }
+    }
+
+    /// Log filter for stderr output. `call` and `check` report their own
+    /// errors, so they only log errors by default.
+    pub fn log_level(&self) -> &str {
+        let default = match self.command {
+            Some(Command::Call { .. } | Command::Check(_)) => "error",
+            _ => "warn",
+        };
+        self.log_level.as_deref().unwrap_or(default)
+    }
+
+    /// Apply binary, workspace, and bootstrap overrides to the discovered runtime config.
+    pub fn apply_to(&self, runtime: &mut RuntimeConfig) {
+        if let Some(path) = &self.lspmux_bin {
+            runtime.lspmux_path.clone_from(path);
+        }
+        if let Some(path) = &self.rust_analyzer_bin {
+            runtime.server_path.clone_from(path);
+            runtime.server_path_pinned = true;
+        }
+        if let Some(root) = &self.workspace_root {
+            runtime.workspace_root = Some(root.clone());
+        }
+        if let Some(mode) = self.bootstrap {
+            runtime.bootstrap_mode = mode;
+        }
+        if let Some(transport) = self.lsp_transport {
+            runtime.lsp_transport = transport;
+        }
+        runtime.no_mux_fallback = self.no_mux_fallback;
+    }
+
+    /// Render the effective configuration for `--print-config`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the transport selection is invalid.
+    pub fn render_config(
+        &self,
+        runtime: &RuntimeConfig,
+        file: &FileConfig,
+        file_path: Option<&std::path::Path>,
+    ) -> Result<String> {
+        let transport = self.transport()?;
+        let config = EffectiveConfig {
+            config_file: file_path.map(|path| path.display().to_string()),
+            lspmux_bin: &runtime.lspmux_path,
+            rust_analyzer_bin: &runtime.server_path,
+            workspace_root: runtime.workspace_root.as_deref(),
+            timeout: self.request_timeout().as_secs(),
+            max_file_size: self.file_size_limit().max_bytes,
+            large_files: self.file_size_limit().mode,
+            document_budget: self.document_budget(),
+            max_in_flight: self.max_in_flight(),
+            max_result_bytes: self.max_result_bytes(),
+            index_wait: self.index_wait().as_secs(),
+            watchdog_interval: self
+                .watchdog_interval()
+                .map_or(0, |interval| interval.as_secs()),
+            analysis_target_dir: self.analysis_target_dir().unwrap_or("off"),
+            log_level: self.log_level(),
+            transport: transport.name(),
+            listen: transport.listen(),
+            bootstrap: runtime.bootstrap_mode,
+            lsp_transport: runtime.lsp_transport,
+            warmup: self.warmup,
+            no_mux_fallback: runtime.no_mux_fallback,
+            lspmux_config_path: &runtime.config_path,
+            lspmux_socket_path: &runtime.socket_path,
+            disabled_tools: &file.tools.disabled,
+            timeouts: self
+                .request_timeouts(file)
+                .classes()
+                .map(|(class, timeout)| (class, timeout.as_secs()))
+                .collect(),
+        };
+        toml::to_string(&config).context("failed to render configuration")
+    }
+}
+
+/// The settings in effect after merging flags, env vars, and defaults.
+#[derive(Serialize)]
+struct EffectiveConfig<'a> {
+    config_file: Option<String>,
+    lspmux_bin: &'a str,
+    rust_analyzer_bin: &'a str,
+    workspace_root: Option<&'a str>,
+    timeout: u64,
+    max_file_size: u64,
+    large_files: LargeFileMode,
+    document_budget: u64,
+    max_in_flight: usize,
+    max_result_bytes: usize,
+    index_wait: u64,
+    watchdog_interval: u64,
+    analysis_target_dir: &'a str,
+    log_level: &'a str,
+    transport: &'static str,
+    listen: Option<String>,
+    bootstrap: BootstrapMode,
+    lsp_transport: LspTransport,
+    warmup: bool,
+    no_mux_fallback: bool,
+    lspmux_config_path: &'a str,
+    lspmux_socket_path: &'a str,
+    disabled_tools: &'a [String],
+    timeouts: BTreeMap<MethodClass, u64>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Transport> {
+        let command_line = std::iter::once("lspmux-cc-mcp").chain(args.iter().copied());
+        Cli::try_parse_from(command_line)?.transport()
+    }
+
+    #[test]
+    fn transport_defaults_to_stdio() {
+        assert_eq!(parse(&[]).unwrap(), Transport::Stdio);
+    }
+
+    #[test]
+    fn transport_parses_http_address() {
+        let expected = Transport::Http("127.0.0.1:7878".parse().unwrap());
+        assert_eq!(parse(&["--http", "127.0.0.1:7878"]).unwrap(), expected);
+        assert_eq!(parse(&["--http=127.0.0.1:7878"]).unwrap(), expected);
+        assert_eq!(
+            parse(&["--transport", "http", "--listen", "127.0.0.1:7878"]).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn transport_parses_sse_address() {
+        assert_eq!(
+            parse(&["--sse", "127.0.0.1:7879"]).unwrap(),
+            Transport::Sse("127.0.0.1:7879".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn transport_parses_unix_socket_path() {
+        let expected = Transport::Unix(PathBuf::from("/run/lspmux-cc/mcp.sock"));
+        assert_eq!(
+            parse(&["--listen-unix", "/run/lspmux-cc/mcp.sock"]).unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse(&["--transport=unix", "--listen=/run/lspmux-cc/mcp.sock"]).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn transport_rejects_bad_arguments() {
+        assert!(parse(&["--http"]).is_err());
+        assert!(parse(&["--http", "localhost"]).is_err());
+        assert!(parse(&["--verbose"]).is_err());
+        assert!(parse(&["--transport", "http"]).is_err());
+        assert!(parse(&["--listen", "127.0.0.1:7878"]).is_err());
+        assert!(parse(&["--http", "127.0.0.1:1", "--sse", "127.0.0.1:2"]).is_err());
+    }
+
+    #[test]
+    fn flags_override_discovered_runtime() {
+        let cli = Cli::try_parse_from([
+            "lspmux-cc-mcp",
+            "--lspmux-bin",
+            "/opt/lspmux",
+            "--workspace-root",
+            "/src/project",
+            "--timeout",
+            "90",
+        ])
+        .unwrap();
+        let mut runtime = RuntimeConfig::discover().unwrap();
+        cli.apply_to(&mut runtime);
+        assert_eq!(runtime.lspmux_path, "/opt/lspmux");
+        assert_eq!(runtime.workspace_root.as_deref(), Some("/src/project"));
+        assert_eq!(cli.request_timeout(), Duration::from_secs(90));
+
+        let rendered = cli
+            .render_config(&runtime, &FileConfig::default(), None)
+            .unwrap();
+        assert!(rendered.contains("lspmux_bin = \"/opt/lspmux\""));
+        assert!(rendered.contains("timeout = 90"));
+        assert!(rendered.contains("[timeouts]\nhover = 5\n"));
+        assert!(rendered.contains("transport = \"stdio\""));
+        assert!(!rendered.contains("listen ="));
+    }
+
+    #[test]
+    fn flags_take_precedence_over_config_file() {
+        let file = FileConfig::parse(
+            r#"
+            lspmux_bin = "/file/lspmux"
+            rust_analyzer_bin = "/file/rust-analyzer"
+            timeout = 45
+            bootstrap = "off"
+            lsp_transport = "child"
+            warmup = true
+            no_mux_fallback = true
+            large_files = "truncate"
+            index_wait = 0
+            analysis_target_dir = "off"
+
+            [timeouts]
+            references = 90
+
+            [tools]
+            max_in_flight = 4
+            max_result_bytes = 8192
+            "#,
+        )
+        .unwrap();
+        let cli = Cli::try_parse_from([
+            "lspmux-cc-mcp",
+            "--lspmux-bin",
+            "/flag/lspmux",
+            "--bootstrap",
+            "managed",
+            "--lsp-transport",
+            "socket",
+        ])
+        .unwrap()
+        .with_file_defaults(&file);
+        assert_eq!(cli.lspmux_bin.as_deref(), Some("/flag/lspmux"));
+        assert_eq!(
+            cli.rust_analyzer_bin.as_deref(),
+            Some("/file/rust-analyzer")
+        );
+        assert_eq!(cli.request_timeout(), Duration::from_secs(45));
+        let timeouts = cli.request_timeouts(&file);
+        assert_eq!(
+            timeouts.for_method("textDocument/references"),
+            Duration::from_secs(90)
+        );
+        assert_eq!(
+            timeouts.for_method("textDocument/definition"),
+            Duration::from_secs(45)
+        );
+        assert_eq!(cli.bootstrap, Some(BootstrapMode::Managed));
+        assert_eq!(cli.lsp_transport, Some(LspTransport::Socket));
+        assert!(cli.warmup);
+        assert!(cli.no_mux_fallback);
+        assert_eq!(cli.max_in_flight(), 4);
+        assert_eq!(cli.max_result_bytes(), 8192);
+        assert_eq!(cli.index_wait(), Duration::ZERO);
+        assert_eq!(cli.analysis_target_dir(), None);
+        assert_eq!(
+            cli.file_size_limit(),
+            FileSizeLimit {
+                max_bytes: DEFAULT_MAX_FILE_SIZE,
+                mode: LargeFileMode::Truncate,
+            }
+        );
+    }
+
+    #[test]
+    fn doctor_subcommand_accepts_global_flags() {
+        let cli = Cli::try_parse_from([
+            "lspmux-cc-mcp",
+            "--bootstrap",
+            "managed",
+            "doctor",
+            "--wait",
+            "5",
+        ])
+        .unwrap();
+        assert!(matches!(cli.command, Some(Command::Doctor { wait: 5 })));
+        assert_eq!(cli.bootstrap, Some(BootstrapMode::Managed));
+    }
+
+    #[test]
+    fn call_subcommand_takes_a_tool_and_its_arguments() {
+        let cli = Cli::try_parse_from([
+            "lspmux-cc-mcp",
+            "call",
+            "rust_hover",
+            "--json",
+            r#"{"file_path": "/src/lib.rs", "symbol": "load"}"#,
+            "--output",
+            "json",
+        ])
+        .unwrap();
+        let Some(Command::Call { tool, json, output }) = cli.command else {
+            panic!("expected the call subcommand, got {:?}", cli.command);
+        };
+        assert_eq!(tool, "rust_hover");
+        assert!(json.contains("\"symbol\""));
+        assert_eq!(output, CallOutput::Json);
+
+        let cli = Cli::try_parse_from(["lspmux-cc-mcp", "call", "rust_server_status"]).unwrap();
+        assert!(matches!(
+            cli.command,
+            Some(Command::Call { json, output: CallOutput::Text, .. }) if json == "{}"
+        ));
+        assert!(Cli::try_parse_from(["lspmux-cc-mcp", "call"]).is_err());
+    }
+
+    #[test]
+    fn check_subcommand_takes_paths_and_a_format() {
+        let cli = Cli::try_parse_from([
+            "lspmux-cc-mcp",
+            "check",
+            "src",
+            "crates/*/src/**/*.rs",
+            "--format",
+            "sarif",
+            "--deny-warnings",
+        ])
+        .unwrap();
+        assert_eq!(cli.log_level(), "error");
+        let Some(Command::Check(args)) = cli.command else {
+            panic!("expected the check subcommand, got {:?}", cli.command);
+        };
+        assert_eq!(args.paths, ["src", "crates/*/src/**/*.rs"]);
+        assert_eq!(args.format, ReportFormat::Sarif);
+        assert_eq!(args.min_severity, Severity::Warning);
+        assert!(args.deny_warnings);
+        assert!(!args.no_cargo_check);
+    }
+
+    #[test]
+    fn timeout_must_be_positive() {
+        assert!(Cli::try_parse_from(["lspmux-cc-mcp", "--timeout", "0"]).is_err());
+    }
+}
@@ -0,0 +1,752 @@
+//! User configuration file (`~/.config/lspmux-cc/config.toml`).
+//!
+//! Every setting is optional. Values here are the lowest-precedence layer:
+//! command-line flags win over environment variables, which win over this
+//! file. Unknown keys are rejected so typos surface instead of being ignored.
+//!
+//! ```toml
+//! lspmux_bin = "/opt/lspmux/bin/lspmux"
+//! workspace_root = "/home/me/src/project"
+//! timeout = 60
+//! log_level = "info"
+//! warmup = true
+//! no_mux_fallback = true
+//! max_file_size = 20971520
+//! large_files = "truncate"
+//! document_budget = 134217728
+//! analysis_target_dir = "target/lspmux-cc"
+//!
+//! [timeouts]
+//! workspace_symbol = 300
+//!
+//! [rust_analyzer]
+//! extra_path = ["/opt/mold/bin"]
+//!
+//! [rust_analyzer.initialization_options]
+//! cargo.features = "all"
+//!
+//! [rust_analyzer.env]
+//! RUSTUP_TOOLCHAIN = "nightly"
+//!
+//! [servers.rust-analyzer]
+//! command = "/home/me/.local/bin/rust-analyzer"
+//!
+//! [tools]
+//! disabled = ["rust_workspace_symbol"]
+//! max_in_flight = 16
+//! max_result_bytes = 131072
+//!
+//! [tools.limits]
+//! rust_workspace_symbol = 2
+//! ```
+//!
+//! A repository can also pin its own settings in a `.lspmux-cc.toml`
+//! ([`ProjectConfig`]) at or above the files being queried.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::bootstrap::{BootstrapMode, LspTransport, SERVER_NAME};
+use crate::lsp_client::LargeFileMode;
+use crate::server_env::ServerEnv;
+use crate::timeouts::MethodClass;
+
+/// Environment variable naming an explicit config file path.
+pub const CONFIG_PATH_ENV: &str = "LSPMUX_CC_CONFIG";
+
+/// File name of the per-project config, looked up from the queried file upward.
+pub const PROJECT_CONFIG_FILE: &str = ".lspmux-cc.toml";
+
+/// Settings read from the config file.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// Path to the lspmux binary.
+    pub lspmux_bin: Option<String>,
+    /// Path to the rust-analyzer binary.
+    pub rust_analyzer_bin: Option<String>,
+    /// Absolute workspace root handed to rust-analyzer.
+    pub workspace_root: Option<String>,
+    /// Seconds to wait for a single LSP response.
+    pub timeout: Option<u64>,
+    /// Log filter for stderr output.
+    pub log_level: Option<String>,
+    /// How the lspmux server is found or started.
+    pub bootstrap: Option<BootstrapMode>,
+    /// How the LSP client reaches the lspmux server.
+    pub lsp_transport: Option<LspTransport>,
+    /// Start and prime rust-analyzer at startup instead of on first use.
+    pub warmup: Option<bool>,
+    /// Run rust-analyzer without lspmux, unshared, if lspmux is missing.
+    pub no_mux_fallback: Option<bool>,
+    /// Largest file, in bytes, sent to the language server.
+    pub max_file_size: Option<u64>,
+    /// What to do with files over `max_file_size`.
+    pub large_files: Option<LargeFileMode>,
+    /// Total bytes of open documents before the least recently used are closed.
+    pub document_budget: Option<u64>,
+    /// Seconds a query waits for the workspace to load; 0 runs it at once.
+    pub index_wait: Option<u64>,
+    /// Seconds between watchdog probes of rust-analyzer; 0 turns them off.
+    pub watchdog_interval: Option<u64>,
+    /// Target directory for rust-analyzer's builds, relative to the
+    /// workspace root; `off` shares the user's.
+    pub analysis_target_dir: Option<String>,
+    /// Seconds to wait for the LSP requests of each method class, over
+    /// their defaults.
+    #[serde(default)]
+    pub timeouts: BTreeMap<MethodClass, u64>,
+    #[serde(default)]
+    pub rust_analyzer: RustAnalyzerConfig,
+    /// Language-server registry, keyed by server name.
+    #[serde(default)]
+    pub servers: BTreeMap<String, LanguageServerConfig>,
+    #[serde(default)]
+    pub tools: ToolsConfig,
+}
+
+/// `[rust_analyzer]` section.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct RustAnalyzerConfig {
+    /// Sent verbatim as `initializationOptions` in the LSP initialize request.
+    pub initialization_options: Option<Value>,
+    /// Environment variables for rust-analyzer and the cargo it runs, such as
+    /// `RUSTFLAGS` or `RUSTUP_TOOLCHAIN`.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Directories put in front of `PATH` for rust-analyzer, e.g. where a
+    /// custom linker lives.
+    #[serde(default)]
+    pub extra_path: Vec<PathBuf>,
+}
+
+impl RustAnalyzerConfig {
+    /// The environment to start rust-analyzer with.
+    #[must_use]
+    pub fn server_env(&self) -> ServerEnv {
+        ServerEnv::new(self.env.clone(), self.extra_path.clone())
+    }
+}
+
+/// One `[servers.<name>]` entry.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LanguageServerConfig {
+    /// Server binary passed to `lspmux client --server-path`.
+    pub command: String,
+    /// File extensions (without the dot) the server handles.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+}
+
+/// `[tools]` section.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ToolsConfig {
+    /// Tool names hidden from `tools/list` and rejected by `tools/call`.
+    #[serde(default)]
+    pub disabled: Vec<String>,
+    /// Tool calls running at once, across all tools and sessions; more wait
+    /// in a queue. User config only.
+    pub max_in_flight: Option<usize>,
+    /// Bytes of items on one page of a paginated tool result. User config
+    /// only.
+    pub max_result_bytes: Option<usize>,
+    /// Per-tool limits on calls running at once, keyed by tool name. User
+    /// config only.
+    #[serde(default)]
+    pub limits: BTreeMap<String, usize>,
+}
+
+/// The default config file location: `$XDG_CONFIG_HOME/lspmux-cc/config.toml`,
+/// falling back to `~/.config/lspmux-cc/config.toml` on every platform.
+#[must_use]
+pub fn default_config_path() -> Option<PathBuf> {
+    let root = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| directories::BaseDirs::new().map(|dirs| dirs.home_dir().join(".config")))?;
+    Some(root.join("lspmux-cc").join("config.toml"))
+}
+
+impl FileConfig {
+    /// Load the config file.
+    ///
+    /// An `explicit` path must exist. Without one, the default location is
+    /// used if present and an empty config is returned otherwise. Also returns
+    /// the path that was actually read.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read, is not valid TOML, has
+    /// unknown keys, or fails validation.
+    pub fn load(explicit: Option<&Path>) -> Result<(Self, Option<PathBuf>)> {
+        let path = match explicit {
+            Some(path) => path.to_path_buf(),
+            None => match default_config_path() {
+                Some(path) if path.is_file() => path,
+                _ => return Ok((Self::default(), None)),
+            },
+        };
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let config = Self::parse(&contents)
+            .with_context(|| format!("invalid config file {}", path.display()))?;
+        Ok((config, Some(path)))
+    }
+
+    /// Parse and validate config file contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the offending key if parsing or validation fails.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let config: Self = toml::from_str(contents)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        for (key, value) in [
+            ("lspmux_bin", &self.lspmux_bin),
+            ("rust_analyzer_bin", &self.rust_analyzer_bin),
+            ("workspace_root", &self.workspace_root),
+            ("analysis_target_dir", &self.analysis_target_dir),
+        ] {
+            if value
+                .as_deref()
+                .is_some_and(|value| value.trim().is_empty())
+            {
+                bail!("`{key}` must not be empty");
+            }
+        }
+        if let Some(root) = &self.workspace_root {
+            if !Path::new(root).is_absolute() {
+                bail!("`workspace_root` must be an absolute path, got {root:?}");
+            }
+        }
+        if self.timeout == Some(0) {
+            bail!("`timeout` must be at least 1 second");
+        }
+        if let Some((class, _)) = self.timeouts.iter().find(|(_, secs)| **secs == 0) {
+            bail!("`timeouts.{}` must be at least 1 second", class.name());
+        }
+        if self.max_file_size == Some(0) {
+            bail!("`max_file_size` must be at least 1 byte");
+        }
+        if self.document_budget == Some(0) {
+            bail!("`document_budget` must be at least 1 byte");
+        }
+        if self.tools.max_in_flight == Some(0) {
+            bail!("`tools.max_in_flight` must be at least 1");
+        }
+        if self.tools.max_result_bytes == Some(0) {
+            bail!("`tools.max_result_bytes` must be at least 1 byte");
+        }
+        if let Some((tool, _)) = self.tools.limits.iter().find(|(_, limit)| **limit == 0) {
+            bail!("`tools.limits.{tool}` must be at least 1");
+        }
+        if let Some(filter) = &self.log_level {
+            tracing_subscriber::EnvFilter::try_new(filter)
+                .with_context(|| format!("`log_level` {filter:?} is not a valid log filter"))?;
+        }
+        if let Some(options) = &self.rust_analyzer.initialization_options {
+            if !options.is_object() {
+                bail!("`rust_analyzer.initialization_options` must be a table");
+            }
+        }
+        if let Some(name) = self
+            .rust_analyzer
+            .env
+            .keys()
+            .find(|name| name.is_empty() || name.contains('='))
+        {
+            bail!("`rust_analyzer.env` key {name:?} is not a valid variable name");
+        }
+        if self.rust_analyzer.env.contains_key("PATH") && !self.rust_analyzer.extra_path.is_empty()
+        {
+            bail!("set either `rust_analyzer.env.PATH` or `rust_analyzer.extra_path`, not both");
+        }
+        for (name, server) in &self.servers {
+            if server.command.trim().is_empty() {
+                bail!("`servers.{name}.command` must not be empty");
+            }
+            if let Some(extension) = server
+                .extensions
+                .iter()
+                .find(|extension| extension.is_empty() || extension.starts_with('.'))
+            {
+                bail!(
+                    "`servers.{name}.extensions` entry {extension:?} must be a bare extension \
+                     such as \"rs\""
+                );
+            }
+        }
+        if self.rust_analyzer_bin.is_some() && self.servers.contains_key(SERVER_NAME) {
+            bail!(
+                "both `rust_analyzer_bin` and `servers.{SERVER_NAME}.command` are set; \
+                 keep only one"
+            );
+        }
+        Ok(())
+    }
+
+    /// The configured rust-analyzer binary, from either `rust_analyzer_bin`
+    /// or the `servers.rust-analyzer` registry entry.
+    #[must_use]
+    pub fn server_bin(&self) -> Option<&str> {
+        self.rust_analyzer_bin
+            .as_deref()
+            .or_else(|| self.servers.get(SERVER_NAME).map(|s| s.command.as_str()))
+    }
+
+    /// Registry entries for servers other than rust-analyzer, which this
+    /// server does not route to yet.
+    pub fn unused_servers(&self) -> impl Iterator<Item = &str> {
+        self.servers
+            .keys()
+            .map(String::as_str)
+            .filter(|name| *name != SERVER_NAME)
+    }
+}
+
+/// Repository-local settings from a `.lspmux-cc.toml`, typically checked
+/// into git next to the workspace `Cargo.toml`.
+///
+/// ```toml
+/// [rust_analyzer]
+/// features = ["serde", "tokio"]   # or "all"
+/// no_default_features = true
+/// check_command = "clippy"
+/// target_dir = "target/ra"          # relative to the workspace root
+///
+/// # Non-Cargo builds: the crate graph, and the command that writes it.
+/// project_file = "rust-project.json"
+/// project_generator = ["buck2", "run", "prelude//rust/rust-analyzer:gen"]
+///
+/// # Any other rust-analyzer settings, merged last.
+/// [rust_analyzer.settings]
+/// procMacro.enable = false
+///
+/// [tools]
+/// disabled = ["rust_workspace_symbol"]
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    #[serde(default)]
+    pub rust_analyzer: ProjectRustAnalyzerConfig,
+    #[serde(default)]
+    pub tools: ToolsConfig,
+}
+
+/// `[rust_analyzer]` section of a project config.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectRustAnalyzerConfig {
+    /// Cargo features to enable: `"all"` or a list of feature names.
+    pub features: Option<CargoFeatures>,
+    /// Disable the default Cargo features.
+    pub no_default_features: Option<bool>,
+    /// Cargo subcommand run on save, e.g. `check` or `clippy`.
+    pub check_command: Option<String>,
+    /// Cargo target directory, so builds do not wait on the lock of `target`.
+    pub target_dir: Option<String>,
+    /// `rust-project.json` (or `Cargo.toml`) to load instead of discovering
+    /// the project, relative to the workspace root.
+    pub project_file: Option<String>,
+    /// Command that regenerates `project_file`, run in the workspace root by
+    /// `rust_link_project`.
+    pub project_generator: Option<Vec<String>>,
+    /// Raw rust-analyzer settings, merged over the keys above.
+    pub settings: Option<Value>,
+}
+
+/// The `features` value of a project config.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CargoFeatures {
+    /// Must be `"all"`.
+    All(String),
+    List(Vec<String>),
+}
+
+impl ProjectConfig {
+    /// Find the nearest `.lspmux-cc.toml` in `start` or any of its ancestors.
+    ///
+    /// Returns the config file path alongside its parsed contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the nearest file cannot be read or is invalid.
+    pub fn discover(start: &Path) -> Result<Option<(PathBuf, Self)>> {
+        let Some(path) = start
+            .ancestors()
+            .map(|dir| dir.join(PROJECT_CONFIG_FILE))
+            .find(|candidate| candidate.is_file())
+        else {
+            return Ok(None);
+        };
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read project config {}", path.display()))?;
+        let config = Self::parse(&contents)
+            .with_context(|| format!("invalid project config {}", path.display()))?;
+        Ok(Some((path, config)))
+    }
+
+    /// Parse and validate project config contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the offending key if parsing or validation fails.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let config: Self = toml::from_str(contents)?;
+        // The limits protect a rust-analyzer shared by every project.
+        if config.tools.max_in_flight.is_some() || !config.tools.limits.is_empty() {
+            bail!("`tools.max_in_flight` and `tools.limits` belong in the user config file");
+        }
+        let ra = &config.rust_analyzer;
+        if let Some(CargoFeatures::All(value)) = &ra.features {
+            if value != "all" {
+                bail!("`rust_analyzer.features` must be \"all\" or a list, got {value:?}");
+            }
+        }
+        if ra
+            .check_command
+            .as_deref()
+            .is_some_and(|command| command.trim().is_empty())
+        {
+            bail!("`rust_analyzer.check_command` must not be empty");
+        }
+        if ra
+            .target_dir
+            .as_deref()
+            .is_some_and(|dir| dir.trim().is_empty())
+        {
+            bail!("`rust_analyzer.target_dir` must not be empty");
+        }
+        if ra
+            .project_file
+            .as_deref()
+            .is_some_and(|file| file.trim().is_empty())
+        {
+            bail!("`rust_analyzer.project_file` must not be empty");
+        }
+        if ra
+            .project_generator
+            .as_ref()
+            .is_some_and(|command| command.first().is_none_or(String::is_empty))
+        {
+            bail!("`rust_analyzer.project_generator` must name a program");
+        }
+        if ra
+            .settings
+            .as_ref()
+            .is_some_and(|settings| !settings.is_object())
+        {
+            bail!("`rust_analyzer.settings` must be a table");
+        }
+        Ok(config)
+    }
+
+    /// The rust-analyzer settings this project overrides, in the shape of
+    /// rust-analyzer's configuration object, or `None` if it sets none.
+    #[must_use]
+    pub fn rust_analyzer_settings(&self) -> Option<Value> {
+        let ra = &self.rust_analyzer;
+        let mut settings = serde_json::json!({});
+        if let Some(features) = &ra.features {
+            settings["cargo"]["features"] = match features {
+                CargoFeatures::All(all) => Value::from(all.as_str()),
+                CargoFeatures::List(list) => Value::from(list.clone()),
+            };
+        }
+        if let Some(no_default_features) = ra.no_default_features {
+            settings["cargo"]["noDefaultFeatures"] = Value::from(no_default_features);
+        }
+        if let Some(command) = &ra.check_command {
+            settings["check"]["command"] = Value::from(command.as_str());
+        }
+        if let Some(dir) = &ra.target_dir {
+            settings["cargo"]["targetDir"] = Value::from(dir.as_str());
+        }
+        if let Some(file) = &ra.project_file {
+            settings["linkedProjects"] = Value::from(vec![file.as_str()]);
+        }
+        if let Some(raw) = &ra.settings {
+            merge_settings(&mut settings, raw);
+        }
+        settings
+            .as_object()
+            .is_some_and(|object| !object.is_empty())
+            .then_some(settings)
+    }
+
+    /// Arguments that make a cargo command build what rust-analyzer
+    /// analyzes: the configured features and target directory.
+    #[must_use]
+    pub fn cargo_args(&self) -> Vec<String> {
+        let ra = &self.rust_analyzer;
+        let mut args = Vec::new();
+        match &ra.features {
+            Some(CargoFeatures::All(_)) => args.push("--all-features".to_string()),
+            Some(CargoFeatures::List(list)) if !list.is_empty() => {
+                args.extend(["--features".to_string(), list.join(",")]);
+            }
+            _ => {}
+        }
+        if ra.no_default_features == Some(true) {
+            args.push("--no-default-features".to_string());
+        }
+        if let Some(dir) = &ra.target_dir {
+            args.extend(["--target-dir".to_string(), dir.clone()]);
+        }
+        args
+    }
+}
+
+/// Deep-merge `overlay` into `base`: tables merge key by key, anything else
+/// in `overlay` replaces the value in `base`.
+pub fn merge_settings(base: &mut Value, overlay: &Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                merge_settings(base.entry(key.clone()).or_insert(Value::Null), value);
+            }
+        }
+        (base, overlay) => *base = overlay.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_full_config() {
+        let config = FileConfig::parse(
+            r#"
+            lspmux_bin = "/opt/lspmux"
+            workspace_root = "/src/project"
+            timeout = 60
+            log_level = "info"
+            bootstrap = "managed"
+            lsp_transport = "child"
+            warmup = true
+            no_mux_fallback = true
+            max_file_size = 1048576
+            large_files = "truncate"
+            document_budget = 1024
+
+            [timeouts]
+            hover = 2
+            workspace_symbol = 300
+
+            [rust_analyzer]
+            extra_path = ["/opt/mold/bin"]
+
+            [rust_analyzer.initialization_options]
+            cargo.features = "all"
+
+            [rust_analyzer.env]
+            RUSTFLAGS = "-C link-arg=-fuse-ld=mold"
+
+            [servers.rust-analyzer]
+            command = "/opt/rust-analyzer"
+            extensions = ["rs"]
+
+            [tools]
+            disabled = ["rust_workspace_symbol"]
+            max_in_flight = 8
+
+            [tools.limits]
+            rust_find_references = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.lspmux_bin.as_deref(), Some("/opt/lspmux"));
+        assert_eq!(config.timeout, Some(60));
+        assert_eq!(config.bootstrap, Some(BootstrapMode::Managed));
+        assert_eq!(config.lsp_transport, Some(LspTransport::Child));
+        assert_eq!(config.warmup, Some(true));
+        assert_eq!(config.no_mux_fallback, Some(true));
+        assert_eq!(config.max_file_size, Some(1_048_576));
+        assert_eq!(config.large_files, Some(LargeFileMode::Truncate));
+        assert_eq!(config.document_budget, Some(1024));
+        assert_eq!(config.timeouts[&MethodClass::WorkspaceSymbol], 300);
+        assert_eq!(config.server_bin(), Some("/opt/rust-analyzer"));
+        assert_eq!(
+            config.rust_analyzer.initialization_options,
+            Some(serde_json::json!({ "cargo": { "features": "all" } }))
+        );
+        assert_eq!(
+            config.rust_analyzer.env["RUSTFLAGS"],
+            "-C link-arg=-fuse-ld=mold"
+        );
+        assert_eq!(
+            config.rust_analyzer.extra_path,
+            [PathBuf::from("/opt/mold/bin")]
+        );
+        assert_eq!(config.tools.disabled, vec!["rust_workspace_symbol"]);
+        assert_eq!(config.tools.max_in_flight, Some(8));
+        assert_eq!(config.tools.limits["rust_find_references"], 2);
+        assert_eq!(config.unused_servers().count(), 0);
+    }
+
+    #[test]
+    fn empty_config_is_valid() {
+        assert_eq!(FileConfig::parse("").unwrap(), FileConfig::default());
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        let err = FileConfig::parse("timeuot = 5").unwrap_err();
+        assert!(format!("{err:#}").contains("timeuot"), "{err:#}");
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for (contents, needle) in [
+            ("timeout = 0", "timeout"),
+            ("[timeouts]\nrename = 0", "timeouts.rename"),
+            ("[timeouts]\ncompletion = 5", "completion"),
+            ("max_file_size = 0", "max_file_size"),
+            ("document_budget = 0", "document_budget"),
+            ("[tools]\nmax_in_flight = 0", "max_in_flight"),
+            ("[tools]\nmax_result_bytes = 0", "max_result_bytes"),
+            ("[tools.limits]\nrust_hover = 0", "tools.limits.rust_hover"),
+            ("workspace_root = \"relative/dir\"", "absolute"),
+            ("lspmux_bin = \"\"", "lspmux_bin"),
+            ("log_level = \"info,=[\"", "log_level"),
+            (
+                "[rust_analyzer]\ninitialization_options = 3",
+                "initialization_options",
+            ),
+            ("[rust_analyzer.env]\n\"A=B\" = \"1\"", "rust_analyzer.env"),
+            (
+                "[rust_analyzer]\nextra_path = [\"/opt\"]\n[rust_analyzer.env]\nPATH = \"/bin\"",
+                "extra_path",
+            ),
+            (
+                "[servers.taplo]\ncommand = \"taplo\"\nextensions = [\".toml\"]",
+                "bare extension",
+            ),
+            (
+                "rust_analyzer_bin = \"/a\"\n[servers.rust-analyzer]\ncommand = \"/b\"",
+                "keep only one",
+            ),
+        ] {
+            let err = FileConfig::parse(contents).unwrap_err();
+            assert!(format!("{err:#}").contains(needle), "{contents}: {err:#}");
+        }
+    }
+
+    #[test]
+    fn project_config_is_discovered_from_nested_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let nested = dir.path().join("crates/core/src");
+        std::fs::create_dir_all(&nested).unwrap();
+        let file = nested.join("lib.rs");
+        std::fs::write(&file, "").unwrap();
+        assert!(ProjectConfig::discover(&file).unwrap().is_none());
+
+        std::fs::write(
+            dir.path().join(PROJECT_CONFIG_FILE),
+            r#"
+            [rust_analyzer]
+            features = ["serde"]
+            check_command = "clippy"
+
+            [rust_analyzer.settings]
+            check.extraArgs = ["--tests"]
+            "#,
+        )
+        .unwrap();
+        let (path, config) = ProjectConfig::discover(&file).unwrap().unwrap();
+        assert_eq!(path, dir.path().join(PROJECT_CONFIG_FILE));
+        assert_eq!(
+            config.rust_analyzer_settings(),
+            Some(serde_json::json!({
+                "cargo": { "features": ["serde"] },
+                "check": { "command": "clippy", "extraArgs": ["--tests"] },
+            }))
+        );
+    }
+
+    #[test]
+    fn project_config_maps_to_cargo_arguments() {
+        let config = ProjectConfig::parse(
+            "[rust_analyzer]\nfeatures = [\"serde\", \"tokio\"]\nno_default_features = true\n\
+             target_dir = \"target/ra\"",
+        )
+        .unwrap();
+        assert_eq!(
+            config.cargo_args(),
+            [
+                "--features",
+                "serde,tokio",
+                "--no-default-features",
+                "--target-dir",
+                "target/ra"
+            ]
+        );
+        assert_eq!(
+            config.rust_analyzer_settings().unwrap()["cargo"]["targetDir"],
+            "target/ra"
+        );
+        let all = ProjectConfig::parse("[rust_analyzer]\nfeatures = \"all\"").unwrap();
+        assert_eq!(all.cargo_args(), ["--all-features"]);
+        assert!(ProjectConfig::default().cargo_args().is_empty());
+    }
+
+    #[test]
+    fn project_config_validates_features() {
+        assert!(ProjectConfig::parse("[rust_analyzer]\nfeatures = \"all\"").is_ok());
+        let err = ProjectConfig::parse("[rust_analyzer]\nfeatures = \"some\"").unwrap_err();
+        assert!(format!("{err:#}").contains("features"), "{err:#}");
+        let linked =
+            ProjectConfig::parse("[rust_analyzer]\nproject_file = \"rust-project.json\"").unwrap();
+        assert_eq!(
+            linked.rust_analyzer_settings(),
+            Some(serde_json::json!({ "linkedProjects": ["rust-project.json"] }))
+        );
+        let err = ProjectConfig::parse("[rust_analyzer]\nproject_generator = []").unwrap_err();
+        assert!(format!("{err:#}").contains("project_generator"), "{err:#}");
+        let err = ProjectConfig::parse("[tools]\nmax_in_flight = 4").unwrap_err();
+        assert!(format!("{err:#}").contains("user config"), "{err:#}");
+        assert_eq!(ProjectConfig::default().rust_analyzer_settings(), None);
+    }
+
+    #[test]
+    fn merge_settings_merges_tables_and_replaces_leaves() {
+        let mut base = serde_json::json!({ "cargo": { "features": "all", "target": "x" } });
+        merge_settings(
+            &mut base,
+            &serde_json::json!({ "cargo": { "features": ["a"] }, "check": { "command": "clippy" } }),
+        );
+        assert_eq!(
+            base,
+            serde_json::json!({
+                "cargo": { "features": ["a"], "target": "x" },
+                "check": { "command": "clippy" },
+            })
+        );
+    }
+
+    #[test]
+    fn load_requires_explicit_path_to_exist() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(FileConfig::load(Some(&dir.path().join("missing.toml"))).is_err());
+
+        let path = dir.path().join("config.toml");
+        std::fs::write(&path, "timeout = 5\n").unwrap();
+        let (config, loaded) = FileConfig::load(Some(&path)).unwrap();
+        assert_eq!(config.timeout, Some(5));
+        assert_eq!(loaded.as_deref(), Some(path.as_path()));
+    }
+}
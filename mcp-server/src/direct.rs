@@ -0,0 +1,245 @@
+//! Calling tools without an MCP host.
+//!
+//! Consumers that do not speak MCP still go through an MCP session, so their
+//! calls get the same dispatch as a host's: disabled tools, call limits,
+//! workspace detection, telemetry, and correlation ids. [`DirectSession`] is
+//! the client end of such a session, held in process and speaking
+//! newline-delimited JSON-RPC (the stdio framing) over an in-memory pipe.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::{Context, Result};
+use rmcp::model::{CallToolResult, JsonObject, ListToolsResult, ProtocolVersion};
+use rmcp::service::ServiceExt;
+use rmcp::{ErrorData as McpError, ServerHandler};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf};
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+/// Bytes buffered in the pipe in each direction before writers wait.
+const PIPE_CAPACITY: usize = 1024 * 1024;
+
+type Pending = Arc<Mutex<HashMap<u64, tokio::sync::oneshot::Sender<Value>>>>;
+type Writer = Arc<tokio::sync::Mutex<WriteHalf<DuplexStream>>>;
+
+/// An initialized MCP session with a server handler in this process.
+///
+/// Calls may run concurrently. Dropping the session ends it.
+pub struct DirectSession {
+    writer: Writer,
+    pending: Pending,
+    next_id: AtomicU64,
+    _session: DropGuard,
+}
+
+impl DirectSession {
+    /// Open a session with `server` and complete the MCP handshake. The
+    /// session also ends when `shutdown` is cancelled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server rejects the handshake.
+    pub async fn connect<S: ServerHandler>(
+        server: S,
+        shutdown: &CancellationToken,
+    ) -> Result<Self> {
+        let (client_end, server_end) = tokio::io::duplex(PIPE_CAPACITY);
+        let stop = shutdown.child_token();
+        let ct = stop.clone();
+        tokio::spawn(async move {
+            match server.serve_with_ct(server_end, ct).await {
+                Ok(running) => {
+                    if let Err(e) = running.waiting().await {
+                        tracing::warn!("in-process MCP session ended with an error: {e}");
+                    }
+                }
+                Err(e) => tracing::warn!("failed to start in-process MCP session: {e}"),
+            }
+        });
+
+        let (reader, writer) = tokio::io::split(client_end);
+        let pending = Pending::default();
+        tokio::spawn(route_responses(reader, Arc::clone(&pending)));
+        let session = Self {
+            writer: Arc::new(tokio::sync::Mutex::new(writer)),
+            pending,
+            next_id: AtomicU64::new(1),
+            _session: stop.drop_guard(),
+        };
+        session
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": ProtocolVersion::LATEST,
+                    "capabilities": {},
+                    "clientInfo": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                }),
+            )
+            .await
+            .context("in-process MCP handshake failed")?;
+        send(
+            &session.writer,
+            &json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }),
+        )
+        .await
+        .context("in-process MCP handshake failed")?;
+        Ok(session)
+    }
+
+    /// The tools the server lists.
+    ///
+    /// # Errors
+    ///
+    /// Returns the server's error, or an internal one if the session closed.
+    pub async fn list_tools(&self) -> Result<ListToolsResult, McpError> {
+        self.request_as("tools/list", json!({})).await
+    }
+
+    /// Call tool `name` with `arguments`.
+    ///
+    /// # Errors
+    ///
+    /// Returns the tool's error, or an internal one if the session closed.
+    pub async fn call_tool(
+        &self,
+        name: &str,
+        arguments: JsonObject,
+    ) -> Result<CallToolResult, McpError> {
+        self.request_as(
+            "tools/call",
+            json!({ "name": name, "arguments": arguments }),
+        )
+        .await
+    }
+
+    async fn request_as<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> Result<T, McpError> {
+        let result = self.request(method, params).await?;
+        serde_json::from_value(result)
+            .map_err(|e| McpError::internal_error(format!("malformed {method} result: {e}"), None))
+    }
+
+    /// Send a request and wait for its result. If the caller stops waiting,
+    /// the server is told to cancel it, as an MCP host would.
+    async fn request(&self, method: &str, params: Value) -> Result<Value, McpError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        lock_pending(&self.pending).insert(id, tx);
+        let mut in_flight = InFlight {
+            id,
+            pending: &self.pending,
+            writer: &self.writer,
+            done: false,
+        };
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        send(&self.writer, &message).await.map_err(closed)?;
+        let mut response = rx
+            .await
+            .map_err(|_| closed("the in-process MCP session closed"))?;
+        in_flight.done = true;
+        if let Some(error) = response.get_mut("error") {
+            return Err(serde_json::from_value(error.take()).unwrap_or_else(|e| {
+                McpError::internal_error(format!("malformed {method} error: {e}"), None)
+            }));
+        }
+        Ok(response.get_mut("result").map_or(Value::Null, Value::take))
+    }
+}
+
+/// The text content of a tool result, one block per line.
+pub fn result_text(result: &CallToolResult) -> String {
+    result
+        .content
+        .iter()
+        .filter_map(|content| content.as_text())
+        .map(|content| content.text.as_str())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A request awaiting its response; cancelled on the server if dropped first.
+struct InFlight<'a> {
+    id: u64,
+    pending: &'a Pending,
+    writer: &'a Writer,
+    done: bool,
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if self.done || lock_pending(self.pending).remove(&self.id).is_none() {
+            return;
+        }
+        let writer = Arc::clone(self.writer);
+        let cancel = json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": { "requestId": self.id },
+        });
+        tokio::spawn(async move {
+            if let Err(e) = send(&writer, &cancel).await {
+                tracing::debug!("failed to cancel an in-process MCP request: {e:#}");
+            }
+        });
+    }
+}
+
+fn closed(error: impl std::fmt::Display) -> McpError {
+    McpError::internal_error(format!("{error}"), None)
+}
+
+fn lock_pending(
+    pending: &Pending,
+) -> MutexGuard<'_, HashMap<u64, tokio::sync::oneshot::Sender<Value>>> {
+    match pending.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+async fn send(writer: &Writer, message: &Value) -> Result<()> {
+    let mut line = serde_json::to_vec(message).context("failed to encode an MCP message")?;
+    line.push(b'\n');
+    let mut writer = writer.lock().await;
+    writer
+        .write_all(&line)
+        .await
+        .context("the in-process MCP session closed")?;
+    writer
+        .flush()
+        .await
+        .context("the in-process MCP session closed")
+}
+
+/// Hand each response from the server to the request awaiting it. Server
+/// notifications, such as progress, are dropped. Once the server hangs up,
+/// every request still waiting fails.
+async fn route_responses(reader: ReadHalf<DuplexStream>, pending: Pending) {
+    let mut lines = BufReader::new(reader).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        let Ok(message) = serde_json::from_str::<Value>(&line) else {
+            tracing::debug!("dropping a malformed in-process MCP message");
+            continue;
+        };
+        if message.get("method").is_some() {
+            continue;
+        }
+        let Some(id) = message.get("id").and_then(Value::as_u64) else {
+            continue;
+        };
+        let waiter = lock_pending(&pending).remove(&id);
+        if let Some(waiter) = waiter {
+            let _ = waiter.send(message);
+        }
+    }
+    lock_pending(&pending).clear();
+}
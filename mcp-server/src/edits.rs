@@ -0,0 +1,1611 @@
+//! Applying LSP workspace edits to files on disk.
+//!
+//! Every editing tool goes through this engine. A `WorkspaceEdit` from
+//! rust-analyzer is read as a list of [`Operation`]s (text edits, and
+//! creating, renaming, or deleting files and directories), which [`plan`]
+//! replays against the current content of the files it touches, in memory.
+//! The resulting [`Plan`] describes each changed file as a unified diff, so
+//! every editing tool can offer a dry run.
+//!
+//! [`commit`] writes a plan all-or-nothing as far as the file system allows:
+//! it checks that nothing changed since the plan was made, backs up every
+//! file it will replace or remove, stages new content to temporary files,
+//! and only then moves them into place. If a step fails, the steps already
+//! taken are undone, and the error names any file that could not be
+//! restored along with the backup holding its original content.
+
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use lsp_types::{
+    DocumentChangeOperation, DocumentChanges, OneOf, ResourceOp, TextDocumentEdit, TextEdit,
+    WorkspaceEdit,
+};
+use lspmux_cc_mcp::lsp_client::{content_hash, uri_to_path, LspClient};
+use lspmux_cc_mcp::snippet::{self, SNIPPET_ANNOTATION};
+use rmcp::ErrorData as McpError;
+
+/// Unchanged lines shown around each change in a diff.
+const DIFF_CONTEXT: usize = 3;
+
+/// Largest line-by-line comparison (old lines × new lines) a diff computes
+/// exactly; beyond it the changed region is shown as replaced wholesale.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Backups kept under the backup root; older ones are removed.
+const MAX_BACKUPS: usize = 20;
+
+/// The text edits a workspace edit makes to one file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileEdits {
+    pub file_path: String,
+    /// The document version the edits were computed against, when the
+    /// server says.
+    pub version: Option<i32>,
+    pub edits: Vec<TextEdit>,
+    /// The tab stops of snippet edits, whose new text is already rendered:
+    /// the index of the edit in `edits`, and the stop in its new text.
+    pub tab_stops: Vec<(usize, snippet::TabStop)>,
+}
+
+/// One step of a workspace edit. Paths name files unless noted.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Operation {
+    Edit(FileEdits),
+    Create {
+        file_path: String,
+        /// Empty an existing file rather than fail.
+        overwrite: bool,
+        /// Leave an existing file alone rather than fail.
+        ignore_if_exists: bool,
+    },
+    /// Rename a file or a directory.
+    Rename {
+        from: String,
+        to: String,
+        overwrite: bool,
+        ignore_if_exists: bool,
+    },
+    /// Delete a file, or a directory (which must be empty unless `recursive`).
+    Delete {
+        file_path: String,
+        recursive: bool,
+        ignore_if_not_exists: bool,
+    },
+}
+
+/// What the language server holds for open documents, to tell whether an
+/// edit was computed against the files as they are now.
+pub trait Documents {
+    /// The version of `file_path` last sent to the server, if it is open.
+    fn version(&self, file_path: &str) -> Option<i32>;
+    /// The hash of the content the server has for `file_path`, if it is open.
+    fn content_hash(&self, file_path: &str) -> Option<u64>;
+}
+
+impl Documents for LspClient {
+    fn version(&self, file_path: &str) -> Option<i32> {
+        self.document_version(file_path)
+    }
+
+    fn content_hash(&self, file_path: &str) -> Option<u64> {
+        self.synced_content_hash(file_path)
+    }
+}
+
+/// The steps of `edit`, in order. Text edits come from `document_changes`
+/// when the server sends it, else from `changes` sorted by path.
+pub fn operations(edit: WorkspaceEdit) -> Vec<Operation> {
+    fn text_edits(document: TextDocumentEdit) -> Operation {
+        let mut tab_stops = Vec::new();
+        let edits = document
+            .edits
+            .into_iter()
+            .enumerate()
+            .map(|(i, edit)| match edit {
+                OneOf::Left(edit) => edit,
+                OneOf::Right(annotated) if annotated.annotation_id == SNIPPET_ANNOTATION => {
+                    let rendered = snippet::parse(&annotated.text_edit.new_text);
+                    tab_stops.extend(rendered.tab_stops.into_iter().map(|stop| (i, stop)));
+                    TextEdit::new(annotated.text_edit.range, rendered.text)
+                }
+                OneOf::Right(annotated) => annotated.text_edit,
+            })
+            .collect();
+        Operation::Edit(FileEdits {
+            file_path: uri_to_path(&document.text_document.uri),
+            version: document.text_document.version,
+            edits,
+            tab_stops,
+        })
+    }
+
+    // `changes` is only meaningful when `document_changes` is absent.
+    match edit.document_changes {
+        Some(DocumentChanges::Edits(edits)) => edits.into_iter().map(text_edits).collect(),
+        Some(DocumentChanges::Operations(operations)) => operations
+            .into_iter()
+            .map(|operation| match operation {
+                DocumentChangeOperation::Edit(edit) => text_edits(edit),
+                DocumentChangeOperation::Op(op) => resource_operation(op),
+            })
+            .collect(),
+        None => {
+            let mut changes: Vec<_> = edit.changes.unwrap_or_default().into_iter().collect();
+            changes.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+            changes
+                .into_iter()
+                .map(|(uri, edits)| {
+                    Operation::Edit(FileEdits {
+                        file_path: uri_to_path(&uri),
+                        version: None,
+                        edits,
+                        tab_stops: Vec::new(),
+                    })
+                })
+                .collect()
+        }
+    }
+}
+
+fn resource_operation(op: ResourceOp) -> Operation {
+    match op {
+        ResourceOp::Create(create) => {
+            let options = create.options.as_ref();
+            Operation::Create {
+                file_path: uri_to_path(&create.uri),
+                overwrite: options.and_then(|o| o.overwrite).unwrap_or(false),
+                ignore_if_exists: options.and_then(|o| o.ignore_if_exists).unwrap_or(false),
+            }
+        }
+        ResourceOp::Rename(rename) => {
+            let options = rename.options.as_ref();
+            Operation::Rename {
+                from: uri_to_path(&rename.old_uri),
+                to: uri_to_path(&rename.new_uri),
+                overwrite: options.and_then(|o| o.overwrite).unwrap_or(false),
+                ignore_if_exists: options.and_then(|o| o.ignore_if_exists).unwrap_or(false),
+            }
+        }
+        ResourceOp::Delete(delete) => {
+            let options = delete.options.as_ref();
+            Operation::Delete {
+                file_path: uri_to_path(&delete.uri),
+                recursive: options.and_then(|o| o.recursive).unwrap_or(false),
+                ignore_if_not_exists: options
+                    .and_then(|o| o.ignore_if_not_exists)
+                    .unwrap_or(false),
+            }
+        }
+    }
+}
+
+/// The text edits of `edit`, grouped by file path in the order they appear.
+/// For tools that expect an edit to stay within existing files.
+///
+/// # Errors
+///
+/// Returns `invalid_params` if the edit creates, renames, or deletes files.
+pub fn text_edits_by_file(edit: WorkspaceEdit) -> Result<Vec<FileEdits>, McpError> {
+    let mut files: Vec<FileEdits> = Vec::new();
+    for operation in operations(edit) {
+        let Operation::Edit(file_edits) = operation else {
+            return Err(McpError::invalid_params(
+                "the edit creates, renames, or deletes files, which is not supported here",
+                None,
+            ));
+        };
+        match files
+            .iter_mut()
+            .find(|file| file.file_path == file_edits.file_path)
+        {
+            Some(file) => {
+                let offset = file.edits.len();
+                file.edits.extend(file_edits.edits);
+                file.tab_stops.extend(
+                    file_edits
+                        .tab_stops
+                        .into_iter()
+                        .map(|(edit, stop)| (offset + edit, stop)),
+                );
+            }
+            None => files.push(file_edits),
+        }
+    }
+    Ok(files)
+}
+
+/// Apply `edits` (positions in UTF-16 code units, as LSP sends them) to `text`.
+///
+/// # Errors
+///
+/// Returns `invalid_params` if two edits overlap.
+pub fn apply_text_edits(text: &str, edits: &[TextEdit]) -> Result<String, McpError> {
+    splice(text, edits).map(|(text, _)| text)
+}
+
+/// `text` with `edits` applied, and the byte offset in it where the new
+/// text of each edit starts, in the order of `edits`.
+fn splice(text: &str, edits: &[TextEdit]) -> Result<(String, Vec<usize>), McpError> {
+    let mut spans = edits
+        .iter()
+        .enumerate()
+        .map(|(i, edit)| {
+            let start = byte_offset(text, edit.range.start);
+            let end = byte_offset(text, edit.range.end).max(start);
+            (start, end, i, edit.new_text.as_str())
+        })
+        .collect::<Vec<_>>();
+    // Stable, so inserts at one position keep the order the server sent.
+    spans.sort_by_key(|&(start, end, _, _)| (start, end));
+
+    let mut out = String::with_capacity(text.len());
+    let mut starts = vec![0; edits.len()];
+    let mut copied = 0;
+    for (start, end, i, new_text) in spans {
+        if start < copied {
+            return Err(McpError::invalid_params(
+                "the language server returned overlapping edits",
+                None,
+            ));
+        }
+        out.push_str(&text[copied..start]);
+        starts[i] = out.len();
+        out.push_str(new_text);
+        copied = end;
+    }
+    out.push_str(&text[copied..]);
+    Ok((out, starts))
+}
+
+/// The byte offset of an LSP position in `text`. Positions past the end of a
+/// line or of the text are clamped to it, as the protocol requires.
+pub fn byte_offset(text: &str, position: lsp_types::Position) -> usize {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        match text[line_start..].find('\n') {
+            Some(newline) => line_start += newline + 1,
+            None => return text.len(),
+        }
+    }
+    let line = &text[line_start..];
+    let line = &line[..line.find('\n').unwrap_or(line.len())];
+    let line = line.strip_suffix('\r').unwrap_or(line);
+
+    let mut units = 0;
+    for (offset, ch) in line.char_indices() {
+        if units >= position.character as usize {
+            return line_start + offset;
+        }
+        units += ch.len_utf16();
+    }
+    line_start + line.len()
+}
+
+/// The LSP position of byte `offset` in `text`, or `None` if the offset is
+/// past the end of the text or inside a character.
+pub fn position_at(text: &str, offset: usize) -> Option<lsp_types::Position> {
+    let before = text.get(..offset)?;
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    let line = before.matches('\n').count();
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    Some(lsp_types::Position::new(
+        u32::try_from(line).ok()?,
+        u32::try_from(character).ok()?,
+    ))
+}
+
+/// `text` with every whole-word occurrence of the identifier `from`
+/// replaced by `to`.
+pub fn replace_identifier(text: &str, from: &str, to: &str) -> String {
+    let is_ident = |ch: char| ch.is_alphanumeric() || ch == '_';
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(found) = rest.find(from) {
+        let (before, after) = (&rest[..found], &rest[found + from.len()..]);
+        let whole_word = !before.ends_with(is_ident) && !after.starts_with(is_ident);
+        out.push_str(before);
+        out.push_str(if whole_word { to } else { from });
+        rest = after;
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Whether `name` is usable as a Rust identifier (keywords aside).
+pub fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|first| first == '_' || first.is_alphabetic())
+        && chars.all(|ch| ch == '_' || ch.is_alphanumeric())
+        && name != "_"
+}
+
+/// How one file ends up after an edit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileChange {
+    pub file_path: String,
+    /// The file's previous path, when the edit moved it here.
+    pub renamed_from: Option<String>,
+    /// The content before the edit (at `renamed_from` if set), or `None` if
+    /// the file is new.
+    pub original: Option<Vec<u8>>,
+    /// The content after the edit, or `None` if the file is deleted.
+    pub edited: Option<Vec<u8>>,
+}
+
+impl FileChange {
+    /// A unified diff from the original to the edited content, headed by
+    /// `rename from`/`rename to` lines for a moved file. A new or deleted
+    /// file is diffed against `/dev/null`.
+    pub fn diff(&self) -> String {
+        let old_path = match (&self.original, &self.renamed_from) {
+            (None, _) => "/dev/null",
+            (Some(_), Some(from)) => from,
+            (Some(_), None) => &self.file_path,
+        };
+        let new_path = if self.edited.is_some() {
+            &self.file_path
+        } else {
+            "/dev/null"
+        };
+        let mut out = String::new();
+        if let Some(from) = &self.renamed_from {
+            let _ = writeln!(out, "rename from {from}\nrename to {}", self.file_path);
+        }
+        let old = self.original.as_deref().unwrap_or_default();
+        let new = self.edited.as_deref().unwrap_or_default();
+        match (std::str::from_utf8(old), std::str::from_utf8(new)) {
+            (Ok(old), Ok(new)) => {
+                let diff = unified_diff(old_path, new_path, old, new);
+                if diff.is_empty() && self.renamed_from.is_none() {
+                    let _ = writeln!(out, "--- {old_path}\n+++ {new_path}");
+                }
+                out.push_str(&diff);
+            }
+            _ if old == new => {}
+            _ => {
+                let _ = writeln!(out, "Binary files {old_path} and {new_path} differ");
+            }
+        }
+        out
+    }
+
+    /// The path the file had before the edit, if it existed.
+    fn source(&self) -> Option<&str> {
+        self.original
+            .as_ref()
+            .map(|_| self.renamed_from.as_deref().unwrap_or(&self.file_path))
+    }
+}
+
+/// Where a tab stop of a snippet edit ended up.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PlacedTabStop {
+    pub file_path: String,
+    /// `0` for the final cursor position.
+    pub index: u32,
+    /// The placeholder's range in the file as its edit left it; empty for a
+    /// bare tab stop.
+    pub range: lsp_types::Range,
+    pub placeholder: String,
+}
+
+/// The changes a workspace edit makes, computed without writing anything.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Plan {
+    /// Each changed file, ordered by path.
+    pub changes: Vec<FileChange>,
+    /// The tab stops of the snippet edits among the changes.
+    pub tab_stops: Vec<PlacedTabStop>,
+    /// The content on disk, when the plan was made, of every file it read
+    /// or will replace (`None`: no file there).
+    expected: BTreeMap<String, Option<Vec<u8>>>,
+    /// Directories renamed or deleted whole, to remove once emptied.
+    removed_dirs: Vec<String>,
+}
+
+impl Plan {
+    /// A plan that replaces the content of `file_path`, read as `original`,
+    /// with `edited`.
+    pub fn modify(file_path: String, original: String, edited: String) -> Self {
+        Self::modify_all([(file_path, original, edited)])
+    }
+
+    /// A plan that replaces the content of several files, given as
+    /// `(file_path, original, edited)`. Files left as they were are skipped.
+    pub fn modify_all(files: impl IntoIterator<Item = (String, String, String)>) -> Self {
+        let mut plan = Self::default();
+        for (file_path, original, edited) in files {
+            if original == edited {
+                continue;
+            }
+            let original = original.into_bytes();
+            plan.expected
+                .insert(file_path.clone(), Some(original.clone()));
+            plan.changes.push(FileChange {
+                file_path,
+                renamed_from: None,
+                original: Some(original),
+                edited: Some(edited.into_bytes()),
+            });
+        }
+        plan.changes.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+        plan
+    }
+
+    /// Paths that hold a file before the edit and none after it.
+    pub fn removed_paths(&self) -> Vec<&str> {
+        let mut removed: Vec<&str> = self
+            .changes
+            .iter()
+            .filter_map(FileChange::source)
+            .filter(|&source| {
+                !self
+                    .changes
+                    .iter()
+                    .any(|change| change.file_path == source && change.edited.is_some())
+            })
+            .collect();
+        removed.sort_unstable();
+        removed.dedup();
+        removed
+    }
+
+    /// Refuse the plan if the server's copy of a file it changes differs
+    /// from the content the plan started from: the server computed the edit
+    /// against other content.
+    ///
+    /// # Errors
+    ///
+    /// Returns `invalid_params` naming the first such file.
+    pub fn check_synced(&self, documents: &impl Documents) -> Result<(), McpError> {
+        for change in &self.changes {
+            let (Some(source), Some(original)) = (change.source(), &change.original) else {
+                continue;
+            };
+            let Ok(original) = std::str::from_utf8(original) else {
+                continue;
+            };
+            if documents
+                .content_hash(source)
+                .is_some_and(|hash| hash != content_hash(original))
+            {
+                return Err(stale(source));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn stale(file_path: &str) -> McpError {
+    McpError::invalid_params(
+        format!(
+            "{file_path} changed after the language server computed the edit; nothing was \
+             written, rerun the tool"
+        ),
+        None,
+    )
+}
+
+/// Replay `operations` against the files on disk, in memory.
+///
+/// # Errors
+///
+/// Returns `invalid_params` if an edit was computed against another version
+/// of a document than the one open in the server, if a file cannot be read
+/// or is not UTF-8 where text edits apply, if text edits overlap, or if a
+/// file operation conflicts with what is on disk (creating over an existing
+/// file without `overwrite`, renaming or deleting a missing file, deleting a
+/// non-empty directory without `recursive`).
+pub fn plan(operations: Vec<Operation>, documents: &impl Documents) -> Result<Plan, McpError> {
+    let mut files = VirtualFiles::default();
+    let mut tab_stops = Vec::new();
+    for operation in operations {
+        match operation {
+            Operation::Edit(file_edits) => {
+                tab_stops.extend(edit_file(&mut files, file_edits, documents)?);
+            }
+            Operation::Create {
+                file_path,
+                overwrite,
+                ignore_if_exists,
+            } => {
+                if files.is_dir(&file_path) {
+                    return Err(refused(format!(
+                        "cannot create {file_path}: it is a directory"
+                    )));
+                }
+                match files.get(&file_path)? {
+                    Some(mut file) if overwrite => {
+                        file.content.clear();
+                        files.set(&file_path, Some(file))?;
+                    }
+                    Some(_) if ignore_if_exists => {}
+                    Some(_) => {
+                        return Err(refused(format!(
+                            "cannot create {file_path}: it already exists"
+                        )))
+                    }
+                    None => files.set(&file_path, Some(VirtualFile::new()))?,
+                }
+            }
+            Operation::Rename {
+                from,
+                to,
+                overwrite,
+                ignore_if_exists,
+            } => {
+                if files.get(&to)?.is_some() || files.is_dir(&to) {
+                    if ignore_if_exists && !overwrite {
+                        continue;
+                    }
+                    if !overwrite {
+                        return Err(refused(format!(
+                            "cannot rename {from} to {to}: {to} already exists"
+                        )));
+                    }
+                }
+                if files.is_dir(&from) {
+                    for path in files.files_under(&from)? {
+                        let file = files.get(&path)?;
+                        files.set(&path, None)?;
+                        files.set(&format!("{to}{}", &path[from.len()..]), file)?;
+                    }
+                    files.removed_dirs.push(from);
+                } else {
+                    let Some(file) = files.get(&from)? else {
+                        return Err(refused(format!("cannot rename {from}: no such file")));
+                    };
+                    files.set(&from, None)?;
+                    files.set(&to, Some(file))?;
+                }
+            }
+            Operation::Delete {
+                file_path,
+                recursive,
+                ignore_if_not_exists,
+            } => {
+                if files.is_dir(&file_path) {
+                    let contents = files.files_under(&file_path)?;
+                    if !contents.is_empty() && !recursive {
+                        return Err(refused(format!(
+                            "cannot delete {file_path}: the directory is not empty"
+                        )));
+                    }
+                    for path in contents {
+                        files.set(&path, None)?;
+                    }
+                    files.removed_dirs.push(file_path);
+                } else if files.get(&file_path)?.is_some() {
+                    files.set(&file_path, None)?;
+                } else if !ignore_if_not_exists {
+                    return Err(refused(format!("cannot delete {file_path}: no such file")));
+                }
+            }
+        }
+    }
+    Ok(files.into_plan(tab_stops))
+}
+
+/// Apply the text edits of `file_edits` to their file in `files`, and
+/// return where the tab stops of its snippet edits ended up.
+fn edit_file(
+    files: &mut VirtualFiles,
+    file_edits: FileEdits,
+    documents: &impl Documents,
+) -> Result<Vec<PlacedTabStop>, McpError> {
+    let path = file_edits.file_path;
+    if file_edits.version.is_some_and(|version| {
+        documents
+            .version(&path)
+            .is_some_and(|current| current != version)
+    }) {
+        return Err(stale(&path));
+    }
+    let Some(mut file) = files.get(&path)? else {
+        return Err(refused(format!("cannot edit {path}: no such file")));
+    };
+    let text = String::from_utf8(file.content)
+        .map_err(|_| refused(format!("cannot edit {path}: it is not UTF-8 text")))?;
+    let (edited, starts) = splice(&text, &file_edits.edits)?;
+    let tab_stops = file_edits
+        .tab_stops
+        .iter()
+        .filter_map(|(edit, stop)| {
+            let (from, to) = (
+                starts[*edit] + stop.range.start,
+                starts[*edit] + stop.range.end,
+            );
+            Some(PlacedTabStop {
+                file_path: path.clone(),
+                index: stop.index,
+                range: lsp_types::Range::new(
+                    position_at(&edited, from)?,
+                    position_at(&edited, to)?,
+                ),
+                placeholder: edited.get(from..to)?.to_string(),
+            })
+        })
+        .collect();
+    file.content = edited.into_bytes();
+    files.set(&path, Some(file))?;
+    Ok(tab_stops)
+}
+
+fn refused(message: String) -> McpError {
+    McpError::invalid_params(message, None)
+}
+
+/// A file as an edit leaves it: its content, and the path it started at
+/// (`None` if the edit creates it).
+#[derive(Clone, Debug)]
+struct VirtualFile {
+    origin: Option<String>,
+    content: Vec<u8>,
+}
+
+impl VirtualFile {
+    const fn new() -> Self {
+        Self {
+            origin: None,
+            content: Vec::new(),
+        }
+    }
+}
+
+/// The files on disk as overlaid by the operations replayed so far.
+#[derive(Default)]
+struct VirtualFiles {
+    /// Every path touched so far; `None` where no file is left.
+    files: BTreeMap<String, Option<VirtualFile>>,
+    /// The on-disk content of every path touched so far.
+    disk: BTreeMap<String, Option<Vec<u8>>>,
+    removed_dirs: Vec<String>,
+}
+
+impl VirtualFiles {
+    fn get(&mut self, path: &str) -> Result<Option<VirtualFile>, McpError> {
+        if let Some(file) = self.files.get(path) {
+            return Ok(file.clone());
+        }
+        let content = read_file(path)
+            .map_err(|e| McpError::invalid_params(format!("failed to read {path}: {e}"), None))?;
+        self.disk.insert(path.to_string(), content.clone());
+        let file = content.map(|content| VirtualFile {
+            origin: Some(path.to_string()),
+            content,
+        });
+        self.files.insert(path.to_string(), file.clone());
+        Ok(file)
+    }
+
+    fn set(&mut self, path: &str, file: Option<VirtualFile>) -> Result<(), McpError> {
+        // Record what is on disk first, to diff, check, and back up against.
+        self.get(path)?;
+        self.files.insert(path.to_string(), file);
+        Ok(())
+    }
+
+    /// Whether `path` is a directory: one on disk that has not been renamed
+    /// or deleted, or one a file has been moved or created into.
+    fn is_dir(&self, path: &str) -> bool {
+        let prefix = format!("{path}/");
+        (!self.is_removed_dir(path) && Path::new(path).is_dir())
+            || self
+                .files
+                .range(prefix.clone()..)
+                .take_while(|(file, _)| file.starts_with(&prefix))
+                .any(|(_, file)| file.is_some())
+    }
+
+    /// Whether `path` is, or is under, a directory renamed or deleted whole.
+    fn is_removed_dir(&self, path: &str) -> bool {
+        self.removed_dirs
+            .iter()
+            .any(|dir| path == dir || path.starts_with(&format!("{dir}/")))
+    }
+
+    /// The files now under the directory `dir`, at any depth.
+    fn files_under(&mut self, dir: &str) -> Result<Vec<String>, McpError> {
+        let mut paths = Vec::new();
+        if !self.is_removed_dir(dir) {
+            walk(Path::new(dir), &mut paths).map_err(|e| {
+                McpError::invalid_params(format!("failed to list {dir}: {e}"), None)
+            })?;
+        }
+        let prefix = format!("{dir}/");
+        paths.extend(
+            self.files
+                .range(prefix.clone()..)
+                .take_while(|(file, _)| file.starts_with(&prefix))
+                .map(|(file, _)| file.clone()),
+        );
+        paths.sort_unstable();
+        paths.dedup();
+        let mut present = Vec::with_capacity(paths.len());
+        for path in paths {
+            if self.get(&path)?.is_some() {
+                present.push(path);
+            }
+        }
+        Ok(present)
+    }
+
+    fn into_plan(self, tab_stops: Vec<PlacedTabStop>) -> Plan {
+        let disk = |path: &str| self.disk.get(path).cloned().flatten();
+        let moved_from: Vec<&str> = self
+            .files
+            .iter()
+            .filter_map(|(path, file)| {
+                file.as_ref()
+                    .and_then(|file| file.origin.as_deref())
+                    .filter(|origin| origin != path)
+            })
+            .collect();
+
+        let mut changes = Vec::new();
+        for (path, file) in &self.files {
+            let change = match file {
+                Some(file) => {
+                    let renamed_from = file.origin.clone().filter(|origin| origin != path);
+                    FileChange {
+                        file_path: path.clone(),
+                        original: disk(renamed_from.as_deref().unwrap_or(path)),
+                        renamed_from,
+                        edited: Some(file.content.clone()),
+                    }
+                }
+                // A file moved elsewhere is reported at its new path.
+                None if moved_from.contains(&path.as_str()) => continue,
+                None => FileChange {
+                    file_path: path.clone(),
+                    renamed_from: None,
+                    original: disk(path),
+                    edited: None,
+                },
+            };
+            if change.renamed_from.is_some() || change.original != change.edited {
+                changes.push(change);
+            }
+        }
+        Plan {
+            changes,
+            tab_stops,
+            expected: self.disk,
+            removed_dirs: self.removed_dirs,
+        }
+    }
+}
+
+/// The content of the file at `path`, or `None` if there is none (or only
+/// a directory).
+fn read_file(path: &str) -> std::io::Result<Option<Vec<u8>>> {
+    match std::fs::read(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(_) if Path::new(path).is_dir() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Append the paths of the files under `dir` to `paths`.
+fn walk(dir: &Path, paths: &mut Vec<String>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            walk(&entry.path(), paths)?;
+        } else {
+            paths.push(entry.path().display().to_string());
+        }
+    }
+    Ok(())
+}
+
+/// Where backups of the files edits replace or remove are kept:
+/// `lspmux-cc/backups` in the user's cache directory.
+pub fn backup_root() -> PathBuf {
+    directories::BaseDirs::new()
+        .map_or_else(std::env::temp_dir, |dirs| dirs.cache_dir().to_path_buf())
+        .join("lspmux-cc")
+        .join("backups")
+}
+
+/// Write `plan` to disk, backing up the files it replaces or removes in a
+/// new directory under `backup_root`, and return that directory (`None` if
+/// there was nothing to back up).
+///
+/// # Errors
+///
+/// Returns `invalid_params`, before writing anything, if a file changed
+/// since the plan was made. Returns an internal error if backing up or
+/// staging fails (nothing is written then either), or if moving files into
+/// place fails; the steps already taken are then undone, and the error
+/// names any file that could not be restored.
+pub fn commit(plan: &Plan, backup_root: &Path) -> Result<Option<PathBuf>, McpError> {
+    for (path, expected) in &plan.expected {
+        if read_file(path).ok().as_ref() != Some(expected) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "{path} changed while the edit was being prepared; nothing was written, \
+                     rerun the tool"
+                ),
+                None,
+            ));
+        }
+    }
+
+    let writes: Vec<&FileChange> = plan
+        .changes
+        .iter()
+        .filter(|change| change.edited.is_some())
+        .collect();
+    let removals = plan.removed_paths();
+    let replaced: Vec<(&str, &[u8])> = plan
+        .expected
+        .iter()
+        .filter(|(path, _)| {
+            removals.contains(&path.as_str())
+                || writes.iter().any(|change| &change.file_path == *path)
+        })
+        .filter_map(|(path, content)| Some((path.as_str(), content.as_deref()?)))
+        .collect();
+    let backup = back_up(backup_root, &replaced).map_err(|e| {
+        McpError::internal_error(
+            format!("failed to back up the files to change: {e}; nothing was written"),
+            None,
+        )
+    })?;
+
+    let mut staged = Vec::with_capacity(writes.len());
+    for change in &writes {
+        match stage(change) {
+            Ok(temp) => staged.push(temp),
+            Err(e) => {
+                remove_all(&staged);
+                return Err(McpError::internal_error(
+                    format!(
+                        "failed to stage {}: {e}; nothing was written",
+                        change.file_path
+                    ),
+                    None,
+                ));
+            }
+        }
+    }
+
+    // Paths replaced or removed so far, to undo on failure.
+    let mut done: Vec<&str> = Vec::new();
+    let mut failure = None;
+    for (change, temp) in writes.iter().zip(&staged) {
+        if let Err(e) = std::fs::rename(temp, &change.file_path) {
+            failure = Some(format!("failed to replace {}: {e}", change.file_path));
+            break;
+        }
+        done.push(&change.file_path);
+    }
+    if failure.is_none() {
+        for &path in &removals {
+            if let Err(e) = std::fs::remove_file(path) {
+                failure = Some(format!("failed to remove {path}: {e}"));
+                break;
+            }
+            done.push(path);
+        }
+    }
+    if let Some(failure) = failure {
+        remove_all(&staged);
+        let unrestored: Vec<&str> = done
+            .iter()
+            .copied()
+            .filter(|&path| restore(path, plan.expected.get(path).cloned().flatten()).is_err())
+            .collect();
+        let mut message = format!(
+            "{failure}; undid {} of {} change(s) already made",
+            done.len() - unrestored.len(),
+            done.len()
+        );
+        if !unrestored.is_empty() {
+            let _ = write!(message, "; could not restore {}", unrestored.join(", "));
+            if let Some(backup) = &backup {
+                let _ = write!(message, " (originals are in {})", backup.display());
+            }
+        }
+        return Err(McpError::internal_error(message, None));
+    }
+
+    for dir in &plan.removed_dirs {
+        remove_empty_dirs(Path::new(dir));
+    }
+    if let Err(e) = prune_backups(backup_root) {
+        tracing::debug!("failed to prune old backups: {e}");
+    }
+    Ok(backup)
+}
+
+/// Copy each file's content to a new directory under `root`, at its path
+/// relative to `/`, and return the directory.
+fn back_up(root: &Path, files: &[(&str, &[u8])]) -> std::io::Result<Option<PathBuf>> {
+    if files.is_empty() {
+        return Ok(None);
+    }
+    let millis = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+    let dir = root.join(format!("{millis:013}-{}", std::process::id()));
+    for (path, content) in files {
+        let target = dir.join(path.trim_start_matches('/'));
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(target, content)?;
+    }
+    Ok(Some(dir))
+}
+
+/// Remove all but the newest [`MAX_BACKUPS`] backups under `root`.
+fn prune_backups(root: &Path) -> std::io::Result<()> {
+    let mut backups = std::fs::read_dir(root)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    // Names start with a zero-padded timestamp, so they sort by age.
+    backups.sort_unstable();
+    let excess = backups.len().saturating_sub(MAX_BACKUPS);
+    for backup in &backups[..excess] {
+        std::fs::remove_dir_all(backup)?;
+    }
+    Ok(())
+}
+
+/// Put back the file at `path` as it was: with `content`, or none.
+fn restore(path: &str, content: Option<Vec<u8>>) -> std::io::Result<()> {
+    content.map_or_else(
+        || std::fs::remove_file(path),
+        |content| std::fs::write(path, content),
+    )
+}
+
+/// Remove `dir` and the directories under it if they hold no files.
+fn remove_empty_dirs(dir: &Path) {
+    if let Ok(entries) = std::fs::read_dir(dir) {
+        for entry in entries.flatten() {
+            if entry.file_type().is_ok_and(|kind| kind.is_dir()) {
+                remove_empty_dirs(&entry.path());
+            }
+        }
+    }
+    if let Err(e) = std::fs::remove_dir(dir) {
+        tracing::debug!("left {} in place: {e}", dir.display());
+    }
+}
+
+/// Write the edited content of `change` to a temporary file in its
+/// directory (creating the directory if needed), with the permissions of
+/// the file it replaces or was renamed from, and return the temporary
+/// file's path.
+fn stage(change: &FileChange) -> std::io::Result<PathBuf> {
+    let target = Path::new(&change.file_path);
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let name = target.file_name().unwrap_or_default().to_string_lossy();
+    let temp = target.with_file_name(format!(".{name}.lspmux-cc-{}.tmp", std::process::id()));
+    let written =
+        std::fs::write(&temp, change.edited.as_deref().unwrap_or_default()).and_then(|()| {
+            match change.source() {
+                Some(source) => {
+                    let permissions = std::fs::metadata(source)?.permissions();
+                    std::fs::set_permissions(&temp, permissions)
+                }
+                None => Ok(()),
+            }
+        });
+    match written {
+        Ok(()) => Ok(temp),
+        Err(e) => {
+            let _ = std::fs::remove_file(&temp);
+            Err(e)
+        }
+    }
+}
+
+fn remove_all(paths: &[PathBuf]) {
+    for path in paths {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Sort each run of consecutive single-line `use` declarations by path and
+/// drop exact duplicates. As with rustfmt, a run ends at anything else (a
+/// blank line, comment, attribute, or multi-line `use`), so deliberate
+/// grouping is kept; only declarations at the same indentation are sorted
+/// together.
+pub fn sort_use_runs(text: &str) -> String {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut out = String::with_capacity(text.len());
+    let mut start = 0;
+    while start < lines.len() {
+        let Some(indent) = use_line_indent(lines[start]) else {
+            out.push_str(lines[start]);
+            start += 1;
+            continue;
+        };
+        let mut end = start + 1;
+        while end < lines.len() && use_line_indent(lines[end]) == Some(indent) {
+            end += 1;
+        }
+
+        let mut run: Vec<&str> = lines[start..end]
+            .iter()
+            .map(|line| line.trim_end())
+            .collect();
+        run.sort_by_cached_key(|line| use_sort_key(line));
+        run.dedup();
+        for line in run {
+            out.push_str(line);
+            out.push('\n');
+        }
+        // Keep a missing final newline missing.
+        if end == lines.len() && !text.ends_with('\n') {
+            out.pop();
+        }
+        start = end;
+    }
+    out
+}
+
+/// The indentation of `line` if it is a complete single-line `use`
+/// declaration with nothing else on it.
+fn use_line_indent(line: &str) -> Option<usize> {
+    let line = line.trim_end();
+    let body = line.trim_start();
+    let declaration = strip_visibility(body);
+    (declaration.starts_with("use ")
+        && body.ends_with(';')
+        && !body.contains("//")
+        && !body.contains("/*"))
+    .then_some(line.len() - body.len())
+}
+
+/// `declaration` without a leading `pub`, `pub(crate)`, or similar.
+fn strip_visibility(declaration: &str) -> &str {
+    let Some(rest) = declaration.strip_prefix("pub") else {
+        return declaration;
+    };
+    let rest = rest
+        .strip_prefix('(')
+        .and_then(|scoped| scoped.find(')').map(|close| &scoped[close + 1..]))
+        .unwrap_or(rest);
+    if rest.starts_with(char::is_whitespace) {
+        rest.trim_start()
+    } else {
+        declaration
+    }
+}
+
+/// Order `use` paths as rustfmt groups them: `self`, `super`, and `crate`
+/// paths after external ones, then alphabetically.
+fn use_sort_key(line: &str) -> (u8, String) {
+    let path = strip_visibility(line.trim())
+        .trim_start_matches("use ")
+        .trim_end_matches(';')
+        .trim()
+        .trim_start_matches("::");
+    let first = path.split("::").next().unwrap_or_default();
+    let rank = match first {
+        "self" => 1,
+        "super" => 2,
+        "crate" => 3,
+        _ => 0,
+    };
+    (rank, path.to_string())
+}
+
+/// A line of a diff: kept, removed from the old text, or added in the new.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum DiffLine<'a> {
+    Keep(&'a str),
+    Remove(&'a str),
+    Add(&'a str),
+}
+
+/// A unified diff of `old`, at `old_path`, against `new`, at `new_path`, or
+/// an empty string when they are equal.
+fn unified_diff(old_path: &str, new_path: &str, old: &str, new: &str) -> String {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let lines = diff_lines(&old_lines, &new_lines);
+    if lines.iter().all(|line| matches!(line, DiffLine::Keep(_))) {
+        return String::new();
+    }
+
+    let mut out = format!("--- {old_path}\n+++ {new_path}\n");
+    // Positions in `lines` of the changes, grouped into hunks whose context
+    // windows touch.
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|&i| !matches!(lines[i], DiffLine::Keep(_)))
+        .collect();
+    let mut start = 0;
+    while start < changed.len() {
+        let mut end = start;
+        while end + 1 < changed.len() && changed[end + 1] - changed[end] <= 2 * DIFF_CONTEXT + 1 {
+            end += 1;
+        }
+        let first = changed[start].saturating_sub(DIFF_CONTEXT);
+        let last = (changed[end] + DIFF_CONTEXT).min(lines.len() - 1);
+        write_hunk(&mut out, &lines, first, last);
+        start = end + 1;
+    }
+    out
+}
+
+/// Append the hunk covering `lines[first..=last]`.
+fn write_hunk(out: &mut String, lines: &[DiffLine<'_>], first: usize, last: usize) {
+    let counts = |upto: usize| {
+        lines[..upto]
+            .iter()
+            .fold((0, 0), |(old, new), line| match line {
+                DiffLine::Keep(_) => (old + 1, new + 1),
+                DiffLine::Remove(_) => (old + 1, new),
+                DiffLine::Add(_) => (old, new + 1),
+            })
+    };
+    let (old_before, new_before) = counts(first);
+    let (old_through, new_through) = counts(last + 1);
+    let (old_len, new_len) = (old_through - old_before, new_through - new_before);
+    // An empty range names the line before it, as `diff -u` does.
+    let old_start = if old_len == 0 {
+        old_before
+    } else {
+        old_before + 1
+    };
+    let new_start = if new_len == 0 {
+        new_before
+    } else {
+        new_before + 1
+    };
+    let _ = writeln!(out, "@@ -{old_start},{old_len} +{new_start},{new_len} @@");
+    for line in &lines[first..=last] {
+        let (marker, text) = match line {
+            DiffLine::Keep(text) => (' ', text),
+            DiffLine::Remove(text) => ('-', text),
+            DiffLine::Add(text) => ('+', text),
+        };
+        out.push(marker);
+        out.push_str(text);
+        if !text.ends_with('\n') {
+            out.push_str("\n\\ No newline at end of file\n");
+        }
+    }
+}
+
+/// A shortest line-level edit script from `old` to `new`.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut lines: Vec<DiffLine<'a>> = old[..prefix].iter().map(|l| DiffLine::Keep(l)).collect();
+    if old_mid.len().saturating_mul(new_mid.len()) > MAX_DIFF_CELLS {
+        lines.extend(old_mid.iter().map(|l| DiffLine::Remove(l)));
+        lines.extend(new_mid.iter().map(|l| DiffLine::Add(l)));
+    } else {
+        lines.extend(lcs_diff(old_mid, new_mid));
+    }
+    lines.extend(old[old.len() - suffix..].iter().map(|l| DiffLine::Keep(l)));
+    lines
+}
+
+/// Diff by longest common subsequence, removals before additions.
+fn lcs_diff<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<DiffLine<'a>> {
+    let width = new.len() + 1;
+    // `common[i * width + j]`: LCS length of `old[i..]` and `new[j..]`.
+    let mut common = vec![0_u32; (old.len() + 1) * width];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i * width + j] = if old[i] == new[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::with_capacity(old.len() + new.len());
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            lines.push(DiffLine::Keep(old[i]));
+            i += 1;
+            j += 1;
+        } else if i < old.len()
+            && (j == new.len() || common[(i + 1) * width + j] >= common[i * width + j + 1])
+        {
+            lines.push(DiffLine::Remove(old[i]));
+            i += 1;
+        } else {
+            lines.push(DiffLine::Add(new[j]));
+            j += 1;
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::{Position, Range};
+
+    fn edit(start: (u32, u32), end: (u32, u32), new_text: &str) -> TextEdit {
+        TextEdit {
+            range: Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1)),
+            new_text: new_text.to_string(),
+        }
+    }
+
+    #[test]
+    fn text_edits_use_utf16_positions_and_apply_back_to_front() {
+        let text = "let s = \"é😀x\";\nfoo();\r\nbar();\n";
+        let edits = [
+            // `x` follows a 1-unit and a 2-unit character.
+            edit((0, 12), (0, 13), "y"),
+            edit((1, 0), (1, 3), "baz"),
+            // Past the end of the line clamps to before `\r\n`.
+            edit((1, 99), (1, 99), " // done"),
+            edit((9, 0), (9, 0), "tail\n"),
+        ];
+        assert_eq!(
+            apply_text_edits(text, &edits).unwrap(),
+            "let s = \"é😀y\";\nbaz(); // done\r\nbar();\ntail\n"
+        );
+
+        let overlapping = [edit((0, 0), (0, 5), "a"), edit((0, 3), (0, 4), "b")];
+        assert!(apply_text_edits(text, &overlapping).is_err());
+    }
+
+    #[test]
+    fn byte_offsets_map_to_utf16_positions() {
+        let text = "let s = \"é😀x\";\nfoo();\n";
+        let x = text.find('x').unwrap();
+        assert_eq!(position_at(text, x), Some(Position::new(0, 12)));
+        assert_eq!(byte_offset(text, Position::new(0, 12)), x);
+        let foo = text.find("foo").unwrap();
+        assert_eq!(position_at(text, foo), Some(Position::new(1, 0)));
+        assert_eq!(position_at(text, text.len()), Some(Position::new(2, 0)));
+        assert_eq!(position_at(text, text.len() + 1), None);
+        // Inside `é`.
+        assert_eq!(position_at(text, text.find('é').unwrap() + 1), None);
+    }
+
+    #[test]
+    fn identifiers_are_replaced_as_whole_words() {
+        assert_eq!(
+            replace_identifier(
+                "fun_name(x); fn fun_name() {} fun_name2 my_fun_name",
+                "fun_name",
+                "total"
+            ),
+            "total(x); fn total() {} fun_name2 my_fun_name"
+        );
+        assert!(is_identifier("total_2") && is_identifier("_x"));
+        assert!(
+            !is_identifier("")
+                && !is_identifier("2x")
+                && !is_identifier("a-b")
+                && !is_identifier("_")
+        );
+    }
+
+    #[test]
+    fn unified_diff_groups_nearby_changes_into_hunks() {
+        let old = (1..=20).fold(String::new(), |mut out, n| {
+            let _ = writeln!(out, "line {n}");
+            out
+        });
+        let new = old
+            .replace("line 2\n", "line two\n")
+            .replace("line 4\n", "")
+            .replace("line 18\n", "line 18\ninserted\n");
+        assert_eq!(
+            unified_diff("/src/lib.rs", "/src/lib.rs", &old, &new),
+            "--- /src/lib.rs\n+++ /src/lib.rs\n\
+             @@ -1,7 +1,6 @@\n line 1\n-line 2\n+line two\n line 3\n-line 4\n line 5\n line 6\n line 7\n\
+             @@ -16,5 +15,6 @@\n line 16\n line 17\n line 18\n+inserted\n line 19\n line 20\n"
+        );
+        assert_eq!(unified_diff("/src/lib.rs", "/src/lib.rs", &old, &old), "");
+        assert_eq!(
+            unified_diff("/a.rs", "/a.rs", "", "fn main() {}"),
+            "--- /a.rs\n+++ /a.rs\n@@ -0,0 +1,1 @@\n+fn main() {}\n\\ No newline at end of file\n"
+        );
+    }
+
+    /// Documents open in a language server, by path: version and content.
+    #[derive(Default)]
+    struct Open(std::collections::HashMap<String, (i32, String)>);
+
+    impl Documents for Open {
+        fn version(&self, file_path: &str) -> Option<i32> {
+            self.0.get(file_path).map(|(version, _)| *version)
+        }
+
+        fn content_hash(&self, file_path: &str) -> Option<u64> {
+            self.0.get(file_path).map(|(_, text)| content_hash(text))
+        }
+    }
+
+    #[test]
+    fn plans_replay_edits_and_file_operations_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        std::fs::create_dir(path("module")).unwrap();
+        std::fs::write(path("module/mod.rs"), "pub fn f() {}\n").unwrap();
+        std::fs::write(path("module/inner.rs"), "fn g() {}\n").unwrap();
+        std::fs::write(path("lib.rs"), "mod module;\n").unwrap();
+        std::fs::write(path("old.rs"), "gone\n").unwrap();
+        let text = |file: &str, edit| {
+            Operation::Edit(FileEdits {
+                file_path: path(file),
+                version: Some(1),
+                edits: vec![edit],
+                tab_stops: Vec::new(),
+            })
+        };
+
+        let operations = vec![
+            text("lib.rs", edit((0, 4), (0, 10), "renamed")),
+            Operation::Rename {
+                from: path("module"),
+                to: path("renamed"),
+                overwrite: false,
+                ignore_if_exists: false,
+            },
+            // Edits after the rename name the new path.
+            text("renamed/mod.rs", edit((0, 4), (0, 4), "async ")),
+            Operation::Create {
+                file_path: path("new.rs"),
+                overwrite: false,
+                ignore_if_exists: false,
+            },
+            text("new.rs", edit((0, 0), (0, 0), "fn h() {}\n")),
+            Operation::Delete {
+                file_path: path("old.rs"),
+                recursive: false,
+                ignore_if_not_exists: false,
+            },
+        ];
+        let plan = plan(operations.clone(), &Open::default()).unwrap();
+        let summary: Vec<_> = plan
+            .changes
+            .iter()
+            .map(|change| {
+                (
+                    change.file_path.strip_prefix(&path("")).unwrap(),
+                    change.renamed_from.is_some(),
+                    change.original.is_some(),
+                    change.edited.is_some(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("lib.rs", false, true, true),
+                ("new.rs", false, false, true),
+                ("old.rs", false, true, false),
+                ("renamed/inner.rs", true, true, true),
+                ("renamed/mod.rs", true, true, true),
+            ]
+        );
+        assert_eq!(
+            plan.changes[4].diff(),
+            format!(
+                "rename from {0}\nrename to {1}\n--- {0}\n+++ {1}\n@@ -1,1 +1,1 @@\n\
+                 -pub fn f() {{}}\n+pub async fn f() {{}}\n",
+                path("module/mod.rs"),
+                path("renamed/mod.rs")
+            )
+        );
+        assert!(plan.changes[2].diff().starts_with("--- "));
+        assert!(plan.changes[2].diff().contains("+++ /dev/null\n"));
+
+        // The plan is computed in memory only.
+        assert!(Path::new(&path("old.rs")).exists());
+        assert!(!Path::new(&path("renamed")).exists());
+
+        // Edits computed against another version of an open document are refused.
+        let open = Open(std::collections::HashMap::from([(
+            path("lib.rs"),
+            (2, "mod module;\n".to_string()),
+        )]));
+        assert!(super::plan(operations, &open).is_err());
+
+        let backups = dir.path().join("backups");
+        let backup = commit(&plan, &backups).unwrap().unwrap();
+        let read = |name: &str| std::fs::read_to_string(path(name)).unwrap();
+        assert_eq!(read("lib.rs"), "mod renamed;\n");
+        assert_eq!(read("renamed/mod.rs"), "pub async fn f() {}\n");
+        assert_eq!(read("renamed/inner.rs"), "fn g() {}\n");
+        assert_eq!(read("new.rs"), "fn h() {}\n");
+        assert!(!Path::new(&path("old.rs")).exists());
+        assert!(!Path::new(&path("module")).exists());
+        // Every replaced or removed file is backed up at its absolute path.
+        let backed_up = |name: &str| {
+            std::fs::read_to_string(backup.join(path(name).trim_start_matches('/'))).unwrap()
+        };
+        assert_eq!(backed_up("old.rs"), "gone\n");
+        assert_eq!(backed_up("module/mod.rs"), "pub fn f() {}\n");
+    }
+
+    #[test]
+    fn commits_write_nothing_once_a_file_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).display().to_string();
+        std::fs::write(path("a.rs"), "old a").unwrap();
+        std::fs::write(path("b.rs"), "old b").unwrap();
+        let operations = ["a.rs", "b.rs"]
+            .map(|name| {
+                Operation::Edit(FileEdits {
+                    file_path: path(name),
+                    version: None,
+                    edits: vec![edit((0, 0), (0, 3), "new")],
+                    tab_stops: Vec::new(),
+                })
+            })
+            .to_vec();
+        let plan = plan(operations, &Open::default()).unwrap();
+
+        // The server holding other content than the disk means a stale edit.
+        let open = Open(std::collections::HashMap::from([(
+            path("a.rs"),
+            (0, "older a".to_string()),
+        )]));
+        assert!(plan.check_synced(&open).is_err());
+        assert!(plan.check_synced(&Open::default()).is_ok());
+
+        std::fs::write(path("b.rs"), "older b").unwrap();
+        let backups = dir.path().join("backups");
+        assert!(commit(&plan, &backups).is_err());
+        assert_eq!(std::fs::read_to_string(path("a.rs")).unwrap(), "old a");
+        assert!(!backups.exists());
+        // No temporary files are left behind.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 2);
+
+        let missing = Operation::Delete {
+            file_path: path("c.rs"),
+            recursive: false,
+            ignore_if_not_exists: false,
+        };
+        assert!(super::plan(vec![missing], &Open::default()).is_err());
+    }
+
+    #[test]
+    fn use_runs_are_sorted_within_their_groups() {
+        let text = "use std::io;\n\
+                    use crate::b;\n\
+                    pub(crate) use anyhow::Result;\n\
+                    use std::io;\n\
+                    \n\
+                    #[cfg(test)]\n\
+                    use zed::x;\n\
+                    use alpha::{\n    y,\n};\n\
+                    mod inner {\n    use super::z;\n    use std::fmt;\n}\n\
+                    use b::c; // keep\n\
+                    use a::b;";
+        assert_eq!(
+            sort_use_runs(text),
+            "pub(crate) use anyhow::Result;\n\
+             use std::io;\n\
+             use crate::b;\n\
+             \n\
+             #[cfg(test)]\n\
+             use zed::x;\n\
+             use alpha::{\n    y,\n};\n\
+             mod inner {\n    use std::fmt;\n    use super::z;\n}\n\
+             use b::c; // keep\n\
+             use a::b;"
+        );
+    }
+
+    #[test]
+    fn workspace_edits_are_read_as_operations() {
+        let uri = lspmux_cc_mcp::lsp_client::file_uri("/src/lib.rs").unwrap();
+        let changes = WorkspaceEdit {
+            changes: Some(std::collections::HashMap::from([(
+                uri.clone(),
+                vec![edit((0, 0), (0, 0), "a"), edit((1, 0), (1, 0), "b")],
+            )])),
+            ..WorkspaceEdit::default()
+        };
+        let files = text_edits_by_file(changes).unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].file_path, "/src/lib.rs");
+        assert_eq!(files[0].version, None);
+        assert_eq!(files[0].edits.len(), 2);
+
+        let versioned = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![lsp_types::TextDocumentEdit {
+                text_document: lsp_types::OptionalVersionedTextDocumentIdentifier {
+                    uri: uri.clone(),
+                    version: Some(3),
+                },
+                edits: vec![OneOf::Left(edit((0, 0), (0, 0), "a"))],
+            }])),
+            ..WorkspaceEdit::default()
+        };
+        assert_eq!(text_edits_by_file(versioned).unwrap()[0].version, Some(3));
+
+        let create = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(lsp_types::ResourceOp::Create(lsp_types::CreateFile {
+                    uri,
+                    options: None,
+                    annotation_id: None,
+                })),
+            ])),
+            ..WorkspaceEdit::default()
+        };
+        assert_eq!(
+            operations(create.clone()),
+            [Operation::Create {
+                file_path: "/src/lib.rs".to_string(),
+                overwrite: false,
+                ignore_if_exists: false,
+            }]
+        );
+        assert!(text_edits_by_file(create).is_err());
+    }
+
+    #[test]
+    #[allow(clippy::literal_string_with_formatting_args)] // snippet syntax
+    fn snippet_edits_are_rendered_with_their_tab_stops_placed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lib.rs").display().to_string();
+        std::fs::write(&path, "fn main() {}\n").unwrap();
+        let uri = lspmux_cc_mcp::lsp_client::file_uri(&path).unwrap();
+        let snippet = |range_start, new_text: &str| {
+            OneOf::Right(lsp_types::AnnotatedTextEdit {
+                text_edit: edit(range_start, range_start, new_text),
+                annotation_id: SNIPPET_ANNOTATION.to_string(),
+            })
+        };
+        let edit = WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![lsp_types::TextDocumentEdit {
+                text_document: lsp_types::OptionalVersionedTextDocumentIdentifier {
+                    uri,
+                    version: None,
+                },
+                edits: vec![
+                    snippet((1, 0), "fn ${1:helper}() {\n    $0\n}\n"),
+                    // Edits before the snippet shift where its stops land.
+                    OneOf::Left(self::edit((0, 0), (0, 0), "// top\n")),
+                ],
+            }])),
+            ..WorkspaceEdit::default()
+        };
+        let plan = plan(operations(edit), &Open::default()).unwrap();
+        assert_eq!(
+            plan.changes[0].edited.as_deref(),
+            Some(b"// top\nfn main() {}\nfn helper() {\n    \n}\n".as_slice())
+        );
+        let placed: Vec<_> = plan
+            .tab_stops
+            .iter()
+            .map(|stop| (stop.index, stop.placeholder.as_str(), stop.range.start))
+            .collect();
+        assert_eq!(
+            placed,
+            [
+                (1, "helper", Position::new(2, 3)),
+                (0, "", Position::new(3, 4))
+            ]
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//! `rustc --explain` for compiler error codes, for `rust_explain_error` and
+//! the `explain` flag of `rust_diagnostics`.
+//!
+//! An explanation only changes with the toolchain, so each is fetched once
+//! per process and kept in memory.
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use rmcp::ErrorData as McpError;
+use tokio::sync::Mutex;
+
+use crate::cargo;
+
+/// How long `rustc --explain` may take.
+const EXPLAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Explanations fetched so far, shared by every MCP session. `None` records
+/// a code rustc has no explanation for.
+#[derive(Clone, Default)]
+pub struct ExplainCache {
+    explanations: Arc<Mutex<HashMap<String, Option<Arc<str>>>>>,
+}
+
+impl ExplainCache {
+    /// The explanation of `code`, which must be in the form [`error_code`]
+    /// returns, or `None` if rustc has none; and whether it was cached.
+    /// `rustc` runs in `root`, so the workspace's toolchain answers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if rustc cannot be run.
+    pub async fn get(&self, root: &Path, code: &str) -> Result<(Option<Arc<str>>, bool), McpError> {
+        if let Some(explanation) = self.explanations.lock().await.get(code) {
+            return Ok((explanation.clone(), true));
+        }
+
+        let args = ["--explain".to_string(), code.to_string()];
+        let output = cargo::run_program("rustc", root, &args, EXPLAIN_TIMEOUT).await?;
+        // rustc fails for codes it does not know, including retired ones.
+        let explanation = (output.success && !output.stdout.trim().is_empty())
+            .then(|| Arc::from(output.stdout.trim_end()));
+        self.explanations
+            .lock()
+            .await
+            .insert(code.to_string(), explanation.clone());
+        Ok((explanation, false))
+    }
+}
+
+/// `code` as rustc names error codes, e.g. `E0308` from `e0308`, `0308`, or
+/// `308`; `None` if it is not an error code (lint names such as `dead_code`
+/// are not).
+pub fn error_code(code: &str) -> Option<String> {
+    let code = code.trim();
+    let digits = code.strip_prefix(['E', 'e']).unwrap_or(code);
+    (!digits.is_empty() && digits.len() <= 4 && digits.bytes().all(|b| b.is_ascii_digit()))
+        .then(|| format!("E{digits:0>4}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_codes_are_normalized() {
+        assert_eq!(error_code("E0308").as_deref(), Some("E0308"));
+        assert_eq!(error_code(" e0433 ").as_deref(), Some("E0433"));
+        assert_eq!(error_code("308").as_deref(), Some("E0308"));
+        assert_eq!(error_code("dead_code"), None);
+        assert_eq!(error_code("E"), None);
+        assert_eq!(error_code("E03080"), None);
+    }
+}
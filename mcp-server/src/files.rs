@@ -0,0 +1,204 @@
+//! Expanding a directory or glob tool argument into the Rust files it names.
+//!
+//! Globs support `*` and `?` within a path component and `**` for any number
+//! of components. Build output and hidden directories are never searched.
+
+use std::path::{Component, Path, PathBuf};
+
+use rmcp::ErrorData as McpError;
+
+/// Directories that are never descended into, besides hidden ones.
+const SKIPPED_DIRS: &[&str] = &["target", "node_modules"];
+
+/// Rust files under `path`, sorted, and whether more than `max_files` matched.
+///
+/// `path` is an absolute directory (searched recursively), a single `.rs`
+/// file, or a glob such as `/repo/crates/*/src/**/*.rs`.
+///
+/// # Errors
+///
+/// Returns `invalid_params` for a relative path or one that names nothing.
+pub fn rust_files(path: &str, max_files: usize) -> Result<(Vec<PathBuf>, bool), McpError> {
+    let full = Path::new(path);
+    if !full.is_absolute() {
+        return Err(McpError::invalid_params(
+            format!("path must be absolute, got: {path}"),
+            None,
+        ));
+    }
+
+    let (base, pattern) = split_glob(full);
+    let mut files = if pattern.is_empty() && base.is_file() {
+        vec![base]
+    } else if base.is_dir() {
+        walk(&base)
+            .into_iter()
+            .filter(|file| {
+                pattern.is_empty()
+                    || file.strip_prefix(&base).is_ok_and(|relative| {
+                        let parts: Vec<_> = relative.iter().filter_map(|p| p.to_str()).collect();
+                        matches_components(&pattern, &parts)
+                    })
+            })
+            .collect()
+    } else {
+        return Err(McpError::invalid_params(
+            format!("no such directory: {}", base.display()),
+            None,
+        ));
+    };
+
+    files.retain(|file| file.extension().is_some_and(|ext| ext == "rs"));
+    files.sort();
+    let truncated = files.len() > max_files;
+    files.truncate(max_files);
+    Ok((files, truncated))
+}
+
+/// Split `path` into its literal leading directories and the glob components
+/// that follow (empty when `path` has no wildcards).
+fn split_glob(path: &Path) -> (PathBuf, Vec<String>) {
+    let mut base = PathBuf::new();
+    let mut pattern = Vec::new();
+    for component in path.components() {
+        let text = component.as_os_str().to_string_lossy();
+        if !pattern.is_empty() || (matches!(component, Component::Normal(_)) && is_glob(&text)) {
+            pattern.push(text.into_owned());
+        } else {
+            base.push(component);
+        }
+    }
+    (base, pattern)
+}
+
+fn is_glob(component: &str) -> bool {
+    component.contains(['*', '?'])
+}
+
+/// Every file below `dir`, skipping hidden and build directories and not
+/// following symlinked directories.
+fn walk(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if file_type.is_dir() {
+                if !name.starts_with('.') && !SKIPPED_DIRS.contains(&name.as_ref()) {
+                    pending.push(entry.path());
+                }
+            } else {
+                files.push(entry.path());
+            }
+        }
+    }
+    files
+}
+
+/// Whether the path `parts` matches the glob `pattern`, component-wise.
+fn matches_components(pattern: &[String], parts: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => parts.is_empty(),
+        Some((first, rest)) if first == "**" => {
+            (0..=parts.len()).any(|skip| matches_components(rest, &parts[skip..]))
+        }
+        Some((first, rest)) => parts.split_first().is_some_and(|(part, remaining)| {
+            let pattern: Vec<char> = first.chars().collect();
+            let name: Vec<char> = part.chars().collect();
+            matches_name(&pattern, &name) && matches_components(rest, remaining)
+        }),
+    }
+}
+
+/// Whether one path component matches a pattern with `*` and `?`.
+fn matches_name(pattern: &[char], name: &[char]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some(('*', rest)), _) => {
+            matches_name(rest, name) || (!name.is_empty() && matches_name(pattern, &name[1..]))
+        }
+        (Some(('?', rest)), Some((_, remaining))) => matches_name(rest, remaining),
+        (Some((expected, rest)), Some((actual, remaining))) => {
+            expected == actual && matches_name(rest, remaining)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn touch(root: &Path, relative: &str) {
+        let path = root.join(relative);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+
+    fn names(root: &Path, files: &[PathBuf]) -> Vec<String> {
+        files
+            .iter()
+            .map(|file| file.strip_prefix(root).unwrap().display().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn directories_are_searched_recursively_for_rust_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in [
+            "src/lib.rs",
+            "src/a/mod.rs",
+            "src/notes.md",
+            "target/debug/build.rs",
+            ".git/hooks/x.rs",
+        ] {
+            touch(root, file);
+        }
+
+        let (files, truncated) = rust_files(root.to_str().unwrap(), 100).unwrap();
+        assert_eq!(names(root, &files), ["src/a/mod.rs", "src/lib.rs"]);
+        assert!(!truncated);
+
+        let (files, truncated) = rust_files(root.to_str().unwrap(), 1).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn globs_match_by_component() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in [
+            "crates/a/src/lib.rs",
+            "crates/b/src/deep/x.rs",
+            "crates/b/tests/t.rs",
+        ] {
+            touch(root, file);
+        }
+
+        let glob = format!("{}/crates/*/src/**/*.rs", root.display());
+        let (files, _) = rust_files(&glob, 100).unwrap();
+        assert_eq!(
+            names(root, &files),
+            ["crates/a/src/lib.rs", "crates/b/src/deep/x.rs"]
+        );
+
+        let glob = format!("{}/crates/?/tests/*.rs", root.display());
+        let (files, _) = rust_files(&glob, 100).unwrap();
+        assert_eq!(names(root, &files), ["crates/b/tests/t.rs"]);
+    }
+
+    #[test]
+    fn relative_and_missing_paths_are_rejected() {
+        assert!(rust_files("src", 10).is_err());
+        assert!(rust_files("/nonexistent/lspmux-cc/**/*.rs", 10).is_err());
+    }
+}
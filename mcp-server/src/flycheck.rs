@@ -0,0 +1,274 @@
+//! Waiting out rust-analyzer's check-on-save, for
+//! `rust_wait_for_diagnostics`, and checks started on request, for
+//! `rust_run_flycheck`.
+//!
+//! Saving a file makes rust-analyzer run `cargo check` (its "flycheck") and
+//! push the results. The check reports `$/progress` under a
+//! `rust-analyzer/flycheck/N` token per workspace, so a cycle is over once
+//! every flycheck that began after the save has ended.
+
+use std::collections::HashSet;
+use std::future::Future;
+use std::time::Duration;
+
+use lspmux_cc_mcp::lsp_client::{LspClient, ProgressEvent, PublishedDiagnostics};
+use tokio::sync::broadcast::{
+    self,
+    error::{RecvError, TryRecvError},
+};
+use tokio::time::Instant;
+
+/// How long to wait for a check to begin after the save before concluding
+/// that check-on-save is off.
+const START_GRACE: Duration = Duration::from_secs(3);
+
+/// How long to wait after the last check ends for its diagnostics to be
+/// published.
+const SETTLE: Duration = Duration::from_millis(300);
+
+/// How a wait for fresh diagnostics ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CycleOutcome {
+    /// A check ran to completion after the save.
+    Checked,
+    /// No check began, but the server published diagnostics for the file.
+    Published,
+    /// Nothing happened within the grace period; check-on-save is likely
+    /// disabled.
+    NoCheck,
+    /// A check began but had not finished at the deadline.
+    TimedOut,
+}
+
+impl CycleOutcome {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Checked => "checked",
+            Self::Published => "published",
+            Self::NoCheck => "no_check",
+            Self::TimedOut => "timed_out",
+        }
+    }
+}
+
+/// The check cycle seen so far for one file.
+#[derive(Debug)]
+struct Cycle {
+    /// The file whose diagnostics count as published; any file if `None`.
+    file_path: Option<String>,
+    /// Flycheck tokens that began after the save and have not ended.
+    running: HashSet<String>,
+    started: bool,
+    published: bool,
+}
+
+impl Cycle {
+    fn new(file_path: Option<&str>) -> Self {
+        Self {
+            file_path: file_path.map(String::from),
+            running: HashSet::new(),
+            started: false,
+            published: false,
+        }
+    }
+
+    fn observe_progress(&mut self, event: &ProgressEvent) {
+        if !event.token.contains("flycheck") {
+            return;
+        }
+        match event.kind.as_str() {
+            "begin" => {
+                self.started = true;
+                self.running.insert(event.token.clone());
+            }
+            // An end without a begin belongs to a check from before the save.
+            "end" => {
+                self.running.remove(&event.token);
+            }
+            _ => {}
+        }
+    }
+
+    fn observe_published(&mut self, published: &PublishedDiagnostics) {
+        if self
+            .file_path
+            .as_ref()
+            .is_none_or(|file_path| *file_path == published.file_path)
+        {
+            self.published = true;
+        }
+    }
+
+    /// Whether every check that began has ended.
+    fn finished(&self) -> bool {
+        self.started && self.running.is_empty()
+    }
+
+    /// The outcome if waiting stops now, `before_grace` telling whether the
+    /// grace period for a check to begin is still running.
+    const fn outcome(&self, before_grace: bool) -> Option<CycleOutcome> {
+        if self.started {
+            return None;
+        }
+        if self.published {
+            Some(CycleOutcome::Published)
+        } else if before_grace {
+            None
+        } else {
+            Some(CycleOutcome::NoCheck)
+        }
+    }
+}
+
+/// Save `file_path` to the server and wait, at most `timeout`, for the
+/// check that follows to finish.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be synchronized or the save
+/// notification fails to send.
+pub async fn save_and_wait(
+    lsp: &LspClient,
+    file_path: &str,
+    timeout: Duration,
+) -> anyhow::Result<CycleOutcome> {
+    wait_after(lsp, Some(file_path), lsp.save_document(file_path), timeout).await
+}
+
+/// Start a check of the workspace containing `file_path`, or of every
+/// workspace, and wait at most `timeout` for it to finish.
+///
+/// # Errors
+///
+/// Returns an error if the notification fails to send.
+pub async fn run_and_wait(
+    lsp: &LspClient,
+    file_path: Option<&str>,
+    timeout: Duration,
+) -> anyhow::Result<CycleOutcome> {
+    let run = async { lsp.run_flycheck(file_path) };
+    wait_after(lsp, file_path, run, timeout).await
+}
+
+/// Do `trigger`, then wait at most `timeout` for the check it causes to
+/// finish, counting diagnostics published for `file_path` (or any file) as
+/// a sign of one.
+async fn wait_after(
+    lsp: &LspClient,
+    file_path: Option<&str>,
+    trigger: impl Future<Output = anyhow::Result<()>>,
+    timeout: Duration,
+) -> anyhow::Result<CycleOutcome> {
+    // Subscribe first, so no event between the trigger and the loop is missed.
+    let mut progress = lsp.subscribe_progress();
+    let mut published = lsp.subscribe_diagnostics();
+    trigger.await?;
+
+    let start = Instant::now();
+    let deadline = start + timeout;
+    let grace = start + START_GRACE.min(timeout);
+    let mut cycle = Cycle::new(file_path);
+    loop {
+        let now = Instant::now();
+        if let Some(outcome) = cycle.outcome(now < grace) {
+            return Ok(outcome);
+        }
+        if cycle.finished() {
+            // Diagnostics follow the end of the check; give them a moment.
+            tokio::time::sleep(SETTLE.min(deadline.saturating_duration_since(now))).await;
+            return Ok(CycleOutcome::Checked);
+        }
+        if now >= deadline {
+            return Ok(CycleOutcome::TimedOut);
+        }
+        let wake = if cycle.started { deadline } else { grace };
+        tokio::select! {
+            event = progress.recv() => match event {
+                Ok(event) => cycle.observe_progress(&event),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::debug!("check wait skipped {skipped} progress event(s)");
+                }
+                Err(RecvError::Closed) => return Ok(CycleOutcome::TimedOut),
+            },
+            event = published.recv() => match event {
+                Ok(event) => {
+                    // The server sent any progress before these diagnostics,
+                    // but on another channel; take it into account first.
+                    drain_progress(&mut progress, &mut cycle);
+                    cycle.observe_published(&event);
+                }
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::debug!("check wait skipped {skipped} diagnostics notification(s)");
+                }
+                Err(RecvError::Closed) => return Ok(CycleOutcome::TimedOut),
+            },
+            () = tokio::time::sleep_until(wake) => {}
+        }
+    }
+}
+
+/// Observe the progress events already waiting in `progress`.
+fn drain_progress(progress: &mut broadcast::Receiver<ProgressEvent>, cycle: &mut Cycle) {
+    loop {
+        match progress.try_recv() {
+            Ok(event) => cycle.observe_progress(&event),
+            Err(TryRecvError::Lagged(_)) => {}
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(token: &str, kind: &str) -> ProgressEvent {
+        ProgressEvent {
+            token: token.to_string(),
+            kind: kind.to_string(),
+            title: None,
+            message: None,
+            percentage: None,
+        }
+    }
+
+    #[test]
+    fn a_cycle_ends_when_every_check_begun_after_the_save_ends() {
+        let mut cycle = Cycle::new(Some("/ws/src/lib.rs"));
+        // The end of a check that was running before the save.
+        cycle.observe_progress(&progress("rust-analyzer/flycheck/0", "end"));
+        assert!(!cycle.finished());
+        assert_eq!(cycle.outcome(true), None);
+
+        cycle.observe_progress(&progress("rust-analyzer/flycheck/0", "begin"));
+        cycle.observe_progress(&progress("rust-analyzer/flycheck/1", "begin"));
+        cycle.observe_progress(&progress("rustAnalyzer/Indexing", "end"));
+        cycle.observe_progress(&progress("rust-analyzer/flycheck/0", "end"));
+        assert!(!cycle.finished());
+        cycle.observe_progress(&progress("rust-analyzer/flycheck/1", "end"));
+        assert!(cycle.finished());
+        assert_eq!(cycle.outcome(false), None);
+    }
+
+    #[test]
+    fn without_a_check_the_outcome_depends_on_publishing() {
+        let mut cycle = Cycle::new(Some("/ws/src/lib.rs"));
+        assert_eq!(cycle.outcome(true), None);
+        assert_eq!(cycle.outcome(false), Some(CycleOutcome::NoCheck));
+
+        let published = |file_path: &str| PublishedDiagnostics {
+            file_path: file_path.to_string(),
+            version: None,
+            diagnostics: Vec::new(),
+        };
+        cycle.observe_published(&published("/ws/src/main.rs"));
+        assert_eq!(cycle.outcome(true), None);
+        cycle.observe_published(&published("/ws/src/lib.rs"));
+        assert_eq!(cycle.outcome(true), Some(CycleOutcome::Published));
+
+        // A workspace-wide check counts diagnostics for any file.
+        let mut cycle = Cycle::new(None);
+        cycle.observe_published(&published("/ws/src/main.rs"));
+        assert_eq!(cycle.outcome(true), Some(CycleOutcome::Published));
+    }
+}
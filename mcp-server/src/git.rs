@@ -0,0 +1,262 @@
+//! Finding the Rust files a git working tree has changed, and the lines
+//! that changed in them, for `rust_diagnostics_changed`.
+//!
+//! Changed means anything `git status` reports short of a deletion: modified
+//! in the index or the working tree, added, renamed, copied, or untracked
+//! (and not ignored). Against a revision other than `HEAD`, files that
+//! differ from it count too, even if committed since.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use rmcp::ErrorData as McpError;
+
+use crate::cargo::run_program;
+
+/// How long each git command may run.
+const GIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A directory inside a git working tree.
+pub struct WorkingTree {
+    /// The root of the working tree, which git paths are relative to.
+    toplevel: PathBuf,
+    /// The directory changed files are looked for under.
+    dir: PathBuf,
+}
+
+/// The one-based line ranges of each file that differ from a revision.
+/// Files the diff does not cover, such as untracked ones, are new in full.
+#[derive(Debug, Default)]
+pub struct TouchedLines {
+    hunks: HashMap<PathBuf, Vec<(u32, u32)>>,
+}
+
+impl TouchedLines {
+    /// Whether any of the lines `first..=last` of `file` changed.
+    pub fn touches(&self, file: &Path, first: u32, last: u32) -> bool {
+        self.hunks.get(file).is_none_or(|hunks| {
+            hunks
+                .iter()
+                .any(|&(start, end)| start <= last && first <= end)
+        })
+    }
+}
+
+impl WorkingTree {
+    /// The working tree `dir` is in.
+    ///
+    /// # Errors
+    ///
+    /// Returns `invalid_params` if git is not installed or `dir` is not
+    /// inside a git working tree.
+    pub async fn open(dir: &Path) -> Result<Self, McpError> {
+        let toplevel = git(dir, &["rev-parse", "--show-toplevel"])
+            .await
+            .map_err(|e| {
+                McpError::invalid_params(
+                    format!(
+                        "{} is not inside a git working tree: {}",
+                        dir.display(),
+                        e.message
+                    ),
+                    None,
+                )
+            })?;
+        // Compared canonically: git resolves symlinks in the root it prints.
+        Ok(Self {
+            toplevel: PathBuf::from(toplevel.trim_end()),
+            dir: dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf()),
+        })
+    }
+
+    /// The changed `.rs` files under the directory, sorted, as absolute
+    /// paths; with `since`, also those that differ from that revision.
+    ///
+    /// # Errors
+    ///
+    /// Returns an internal error if git fails, for example on an unknown
+    /// revision.
+    pub async fn changed_rust_files(&self, since: Option<&str>) -> Result<Vec<PathBuf>, McpError> {
+        let status = git(
+            &self.dir,
+            &["status", "--porcelain=v1", "-z", "--untracked-files=all"],
+        )
+        .await?;
+        let mut paths = changed_paths(&status);
+        if let Some(revision) = since {
+            let names = git(
+                &self.toplevel,
+                &[
+                    "diff",
+                    "--name-only",
+                    "-z",
+                    "--no-renames",
+                    "--diff-filter=d",
+                    revision,
+                    "--",
+                ],
+            )
+            .await?;
+            paths.extend(
+                names
+                    .split('\0')
+                    .filter(|name| !name.is_empty())
+                    .map(PathBuf::from),
+            );
+        }
+        let mut files = paths
+            .into_iter()
+            .filter(|path| path.extension().is_some_and(|ext| ext == "rs"))
+            .map(|path| self.toplevel.join(path))
+            .filter(|path| path.starts_with(&self.dir) && path.is_file())
+            .collect::<Vec<_>>();
+        files.sort();
+        files.dedup();
+        Ok(files)
+    }
+
+    /// The lines of the working tree that differ from `revision`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an internal error if git fails, for example on an unknown
+    /// revision.
+    pub async fn touched_lines(&self, revision: &str) -> Result<TouchedLines, McpError> {
+        let diff = git(
+            &self.toplevel,
+            &[
+                "diff",
+                "--unified=0",
+                "--no-color",
+                "--no-ext-diff",
+                "--no-prefix",
+                "--no-renames",
+                revision,
+                "--",
+            ],
+        )
+        .await?;
+        Ok(parse_hunks(&diff, &self.toplevel))
+    }
+}
+
+/// Run git with `args` in `dir`, returning what it printed.
+async fn git(dir: &Path, args: &[&str]) -> Result<String, McpError> {
+    let args = args.iter().map(ToString::to_string).collect::<Vec<_>>();
+    let output = run_program("git", dir, &args, GIT_TIMEOUT).await?;
+    if !output.success {
+        return Err(McpError::internal_error(
+            format!("`git {}` failed: {}", args.join(" "), output.stderr.trim()),
+            None,
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// The paths in `git status --porcelain=v1 -z` output, relative to the
+/// repository root, that were not deleted.
+fn changed_paths(status: &str) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    let mut entries = status.split('\0').filter(|entry| !entry.is_empty());
+    while let Some(entry) = entries.next() {
+        let Some((code, path)) = entry.split_at_checked(2) else {
+            continue;
+        };
+        let Some(path) = path.strip_prefix(' ') else {
+            continue;
+        };
+        // Renames and copies are followed by the original path.
+        if code.contains(['R', 'C']) {
+            entries.next();
+        }
+        if code.contains('D') {
+            continue;
+        }
+        paths.push(PathBuf::from(path));
+    }
+    paths
+}
+
+/// The new-side line ranges of each file in `git diff --unified=0
+/// --no-prefix` output, keyed by absolute path under `toplevel`.
+fn parse_hunks(diff: &str, toplevel: &Path) -> TouchedLines {
+    let mut touched = TouchedLines::default();
+    let mut file = None;
+    for line in diff.lines() {
+        if let Some(path) = line.strip_prefix("+++ ") {
+            file = (path != "/dev/null").then(|| toplevel.join(path));
+            if let Some(file) = &file {
+                touched.hunks.entry(file.clone()).or_default();
+            }
+        } else if let (Some(file), Some(hunk)) = (&file, line.strip_prefix("@@ ")) {
+            if let Some(range) = hunk_new_range(hunk) {
+                touched.hunks.entry(file.clone()).or_default().push(range);
+            }
+        }
+    }
+    touched
+}
+
+/// The new-side lines of a hunk header `-a,b +c,d @@ ...`. A pure deletion
+/// touches the lines on either side of it.
+fn hunk_new_range(hunk: &str) -> Option<(u32, u32)> {
+    let new = hunk.split(' ').find_map(|part| part.strip_prefix('+'))?;
+    let (start, count) = match new.split_once(',') {
+        Some((start, count)) => (start.parse::<u32>().ok()?, count.parse::<u32>().ok()?),
+        None => (new.parse().ok()?, 1),
+    };
+    Some(if count == 0 {
+        (start.max(1), start + 1)
+    } else {
+        (start, start + count - 1)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_entries_name_the_surviving_paths() {
+        let status = " M src/lib.rs\0A  src/new.rs\0R  src/moved.rs\0src/old.rs\0 D src/gone.rs\0\
+                      ?? tests/wip.rs\0MM README.md\0";
+        assert_eq!(
+            changed_paths(status),
+            [
+                "src/lib.rs",
+                "src/new.rs",
+                "src/moved.rs",
+                "tests/wip.rs",
+                "README.md"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn hunks_give_the_changed_lines_of_each_file() {
+        let diff = "diff --git src/lib.rs src/lib.rs\n\
+                    index 1111111..2222222 100644\n\
+                    --- src/lib.rs\n\
+                    +++ src/lib.rs\n\
+                    @@ -3 +3 @@ fn main() {\n\
+                    -    old();\n\
+                    +    new();\n\
+                    @@ -10,2 +10,0 @@\n\
+                    @@ -20,0 +19,3 @@ impl Foo {\n\
+                    diff --git src/gone.rs src/gone.rs\n\
+                    --- src/gone.rs\n\
+                    +++ /dev/null\n\
+                    @@ -1,5 +0,0 @@\n";
+        let touched = parse_hunks(diff, Path::new("/repo"));
+        let lib = Path::new("/repo/src/lib.rs");
+        assert!(touched.touches(lib, 3, 3));
+        assert!(!touched.touches(lib, 4, 8));
+        assert!(touched.touches(lib, 11, 11));
+        assert!(touched.touches(lib, 15, 19));
+        assert!(!touched.touches(lib, 22, 30));
+        // Files outside the diff, such as untracked ones, are new in full.
+        assert!(touched.touches(Path::new("/repo/src/untracked.rs"), 1, 1));
+    }
+}
@@ -0,0 +1,428 @@
+//! Health checks shared by the `rust_analyzer_health` tool and the
+//! `lspmux-cc-mcp doctor` subcommand.
+//!
+//! Each check reports a status and, when something is wrong, a hint that
+//! names the concrete fix.
+
+use std::fmt::Write as _;
+use std::time::{Duration, Instant};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::bootstrap::{BootstrapMode, ConnectAddr, RuntimeConfig, SERVER_NAME};
+use crate::launcher::LspLauncher;
+use crate::lsp_client::LspClient;
+use crate::telemetry::ReadinessState;
+use crate::warmup;
+
+/// Outcome of a single health check.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl CheckStatus {
+    const fn marker(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Warning => "warn",
+            Self::Error => "FAIL",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct HealthCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub detail: String,
+    /// What to do about a warning or error.
+    pub hint: Option<String>,
+}
+
+impl HealthCheck {
+    fn ok(name: &str, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn warning(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Warning,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+
+    fn error(name: &str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status: CheckStatus::Error,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+/// One unfinished unit of rust-analyzer work (indexing, cargo check, ...).
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct IndexingProgress {
+    pub title: Option<String>,
+    pub message: Option<String>,
+    pub percentage: Option<u32>,
+}
+
+// Flat yes/no fields are what agents read from the structured output.
+#[allow(clippy::struct_excessive_bools)]
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct HealthReport {
+    /// `true` when no check failed (warnings allowed).
+    pub healthy: bool,
+    /// Whether rust-analyzer has been started in this process.
+    pub started: bool,
+    /// Whether the lspmux client child process is still running.
+    pub child_alive: bool,
+    pub server_version: Option<String>,
+    pub lspmux_reachable: bool,
+    pub workspace_root: Option<String>,
+    pub readiness: ReadinessState,
+    pub indexing: Vec<IndexingProgress>,
+    pub open_documents: usize,
+    pub pending_requests: usize,
+    pub checks: Vec<HealthCheck>,
+}
+
+impl HealthReport {
+    /// One line per check, failures and warnings followed by their hint.
+    #[must_use]
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        for check in &self.checks {
+            let _ = writeln!(
+                out,
+                "[{}] {}: {}",
+                check.status.marker(),
+                check.name,
+                check.detail
+            );
+            if let Some(hint) = &check.hint {
+                let _ = writeln!(out, "       fix: {hint}");
+            }
+        }
+        let _ = write!(
+            out,
+            "{} open document(s), {} pending request(s). Overall: {}.",
+            self.open_documents,
+            self.pending_requests,
+            if self.healthy { "healthy" } else { "unhealthy" }
+        );
+        out
+    }
+}
+
+/// Run every check against `launcher`. With `start`, rust-analyzer is
+/// started first if needed; otherwise an unstarted server is only a warning.
+pub async fn check(launcher: &LspLauncher, start: bool) -> HealthReport {
+    let runtime = launcher.runtime();
+    let mut checks = Vec::new();
+
+    checks.push(binaries_check(runtime));
+
+    let client = if start {
+        match launcher.client().await {
+            Ok(client) => Some(client),
+            Err(e) => {
+                checks.push(HealthCheck::error(
+                    "lsp_client",
+                    format!("failed to start {SERVER_NAME}: {e:#}"),
+                    "fix the error above, then rerun `lspmux-cc-mcp doctor`",
+                ));
+                None
+            }
+        }
+    } else {
+        launcher.get()
+    };
+
+    let lspmux_reachable = launcher.lspmux_reachable().await;
+    checks.push(lspmux_check(launcher, lspmux_reachable, client.is_some()));
+
+    let Some(client) = client else {
+        if !start {
+            checks.push(HealthCheck::warning(
+                "lsp_client",
+                format!("{SERVER_NAME} has not been started yet"),
+                "it starts on the first rust_* query; call rust_warmup to start it now",
+            ));
+        }
+        return HealthReport {
+            healthy: is_healthy(&checks),
+            started: false,
+            child_alive: false,
+            server_version: None,
+            lspmux_reachable,
+            workspace_root: launcher.workspace_root().map(String::from),
+            readiness: ReadinessState::default(),
+            indexing: Vec::new(),
+            open_documents: 0,
+            pending_requests: 0,
+            checks,
+        };
+    };
+
+    let child_alive = client.is_alive();
+    let server_version = client.server_version().await;
+    checks.push(if child_alive {
+        HealthCheck::ok(
+            "lsp_client",
+            format!(
+                "{SERVER_NAME} {} connected {}",
+                server_version.as_deref().unwrap_or("(unknown version)"),
+                if runtime.runs_unshared() {
+                    "directly"
+                } else {
+                    "through lspmux"
+                }
+            ),
+        )
+    } else {
+        HealthCheck::error(
+            "lsp_client",
+            "the lspmux client process has exited",
+            "restart lspmux-cc-mcp (or reconnect the MCP host)",
+        )
+    });
+
+    if launcher.is_degraded() {
+        checks.push(HealthCheck::warning(
+            "watchdog",
+            format!("{SERVER_NAME} stopped answering and was restarted; waiting for it to answer"),
+            "if this keeps happening, rerun with RUST_LOG=debug and check rust-analyzer's memory use",
+        ));
+    }
+
+    let readiness = client.readiness().await;
+    checks.push(workspace_check(&readiness));
+
+    HealthReport {
+        healthy: is_healthy(&checks),
+        started: true,
+        child_alive,
+        server_version,
+        lspmux_reachable,
+        workspace_root: client.workspace_root().await,
+        readiness,
+        indexing: indexing_progress(&client).await,
+        open_documents: client.open_document_count(),
+        pending_requests: client.pending_request_count(),
+        checks,
+    }
+}
+
+/// The `doctor` variant of [`check`]: start rust-analyzer, give it up to
+/// `wait` to finish indexing, and confirm that it answers a real request.
+pub async fn diagnose(launcher: &LspLauncher, wait: Duration) -> HealthReport {
+    let mut report = check(launcher, true).await;
+    let Some(client) = launcher.get().filter(|client| client.is_alive()) else {
+        return report;
+    };
+
+    warmup::wait_until_quiescent(&client, Instant::now() + wait).await;
+    report = check(launcher, false).await;
+
+    let started = Instant::now();
+    report
+        .checks
+        .push(match client.workspace_symbols("main").await {
+            Ok(_) => HealthCheck::ok(
+                "query",
+                format!(
+                    "workspace/symbol answered in {} ms",
+                    started.elapsed().as_millis()
+                ),
+            ),
+            Err(e) => HealthCheck::error(
+                "query",
+                format!("workspace/symbol failed: {e:#}"),
+                "raise --timeout for large workspaces, or rerun with RUST_LOG=debug for details",
+            ),
+        });
+    report.healthy = is_healthy(&report.checks);
+    report
+}
+
+fn binaries_check(runtime: &RuntimeConfig) -> HealthCheck {
+    match runtime.validate_binaries() {
+        Ok(()) if runtime.runs_unshared() => HealthCheck::warning(
+            "binaries",
+            format!(
+                "lspmux not found at {}; {SERVER_NAME} at {} runs without it",
+                runtime.lspmux_path, runtime.server_path
+            ),
+            "install lspmux to share rust-analyzer with editors and other sessions",
+        ),
+        Ok(()) => HealthCheck::ok(
+            "binaries",
+            format!(
+                "lspmux at {}, {SERVER_NAME} at {}",
+                runtime.lspmux_path, runtime.server_path
+            ),
+        ),
+        Err(e) => HealthCheck::error(
+            "binaries",
+            e.to_string(),
+            "install the missing binary or point --lspmux-bin / --rust-analyzer-bin at it",
+        ),
+    }
+}
+
+fn lspmux_check(launcher: &LspLauncher, reachable: bool, started: bool) -> HealthCheck {
+    let runtime = launcher.runtime();
+    if runtime.runs_unshared() {
+        return HealthCheck::warning(
+            "lspmux",
+            format!("not used: {SERVER_NAME} runs unshared"),
+            "install lspmux, or point --lspmux-bin at it, then restart lspmux-cc-mcp",
+        );
+    }
+    let endpoint = match &runtime.connect_addr {
+        _ if runtime.bootstrap_mode == BootstrapMode::Managed => "the managed address".to_string(),
+        Some(ConnectAddr::Tcp(host, port)) => format!("{host}:{port}"),
+        Some(ConnectAddr::Unix(path)) => path.clone(),
+        None => runtime.socket_path.clone(),
+    };
+    if reachable {
+        return HealthCheck::ok("lspmux", format!("server reachable at {endpoint}"));
+    }
+    match runtime.bootstrap_mode {
+        BootstrapMode::Managed if !started => HealthCheck::warning(
+            "lspmux",
+            "managed server not started yet",
+            "it starts with rust-analyzer on the first rust_* query",
+        ),
+        BootstrapMode::Managed => HealthCheck::error(
+            "lspmux",
+            "managed server is not accepting connections",
+            "it is restarted automatically; check stderr logs for repeated restart failures",
+        ),
+        BootstrapMode::Auto if !started => HealthCheck::warning(
+            "lspmux",
+            format!("no server reachable at {endpoint}"),
+            "one is started on first use; run `./setup core` to install a shared service",
+        ),
+        _ => HealthCheck::error(
+            "lspmux",
+            format!("no server reachable at {endpoint}"),
+            "start it with `lspmux server` or `./setup core`, or pass --bootstrap managed",
+        ),
+    }
+}
+
+fn workspace_check(readiness: &ReadinessState) -> HealthCheck {
+    let message = readiness.message.clone().unwrap_or_default();
+    match readiness.health.as_str() {
+        "error" => HealthCheck::error(
+            "workspace",
+            format!("{SERVER_NAME} failed to load the workspace: {message}"),
+            "run `cargo metadata` in the workspace root and fix the reported error",
+        ),
+        "warning" => HealthCheck::warning(
+            "workspace",
+            format!("workspace loaded with warnings: {message}"),
+            "results may be incomplete; see the message for the affected crates",
+        ),
+        _ if readiness.quiescent == Some(true) => {
+            HealthCheck::ok("workspace", "workspace loaded and indexed")
+        }
+        _ => HealthCheck::warning(
+            "workspace",
+            "workspace is still loading or indexing",
+            "wait (or call rust_warmup with wait_until_ready) before relying on results",
+        ),
+    }
+}
+
+async fn indexing_progress(client: &LspClient) -> Vec<IndexingProgress> {
+    client
+        .active_progress()
+        .await
+        .into_iter()
+        .map(|event| IndexingProgress {
+            title: event.title,
+            message: event.message,
+            percentage: event.percentage,
+        })
+        .collect()
+}
+
+fn is_healthy(checks: &[HealthCheck]) -> bool {
+    checks
+        .iter()
+        .all(|check| check.status != CheckStatus::Error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workspace_check_follows_readiness() {
+        let ready = ReadinessState {
+            health: "ok".to_string(),
+            quiescent: Some(true),
+            message: None,
+            updated_at_ms: None,
+        };
+        assert_eq!(workspace_check(&ready).status, CheckStatus::Ok);
+
+        let loading = ReadinessState {
+            quiescent: Some(false),
+            ..ready.clone()
+        };
+        assert_eq!(workspace_check(&loading).status, CheckStatus::Warning);
+
+        let broken = ReadinessState {
+            health: "error".to_string(),
+            message: Some("failed to load Cargo.toml".to_string()),
+            ..ready
+        };
+        let check = workspace_check(&broken);
+        assert_eq!(check.status, CheckStatus::Error);
+        assert!(check.detail.contains("Cargo.toml"));
+        assert!(check.hint.is_some());
+    }
+
+    #[test]
+    fn render_lists_hints_and_overall_verdict() {
+        let checks = vec![
+            HealthCheck::ok("binaries", "found"),
+            HealthCheck::error("lspmux", "unreachable", "start it"),
+        ];
+        let report = HealthReport {
+            healthy: is_healthy(&checks),
+            started: false,
+            child_alive: false,
+            server_version: None,
+            lspmux_reachable: false,
+            workspace_root: None,
+            readiness: ReadinessState::default(),
+            indexing: Vec::new(),
+            open_documents: 0,
+            pending_requests: 0,
+            checks,
+        };
+        let text = report.render();
+        assert!(text.contains("[ok] binaries: found"));
+        assert!(text.contains("[FAIL] lspmux: unreachable\n       fix: start it"));
+        assert!(text.ends_with("Overall: unhealthy."));
+    }
+}
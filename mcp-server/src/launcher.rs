@@ -0,0 +1,729 @@
+//! Deferred startup of the shared lspmux service and the LSP client.
+//!
+//! The MCP transport comes up immediately; lspmux and rust-analyzer are only
+//! started by the first tool call that needs them (or an explicit warm-up).
+//! Sessions that never touch Rust never pay for the LSP handshake.
+//!
+//! In the `managed` bootstrap mode the launcher also owns the private lspmux
+//! server (see [`crate::supervisor`]) and stops it on shutdown.
+//!
+//! Clients connect straight to the lspmux server's socket unless the
+//! [`LspTransport`] says otherwise; with `auto`, a failed connection falls
+//! back to spawning `lspmux client`.
+//!
+//! Projects built without Cargo (Buck, Bazel) describe their crates in a
+//! [`RUST_PROJECT_FILE`]. A workspace root holding one and no `Cargo.toml`
+//! is initialized with rust-analyzer's `linkedProjects` pointing at it, and
+//! [`LspLauncher::link_project`] points the launcher at any other.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, PoisonError};
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use metrics::counter;
+use serde_json::{json, Value};
+use tokio::sync::{watch, Mutex};
+
+use crate::bootstrap::{
+    BootstrapMode, ConnectAddr, LspTransport, LspmuxClientConfig, RuntimeConfig, RuntimeStatus,
+    ServiceMode, SERVER_NAME,
+};
+use crate::config::merge_settings;
+use crate::lsp_client::{
+    FileSizeLimit, LspClient, DEFAULT_DOCUMENT_BUDGET, DEFAULT_REQUEST_TIMEOUT,
+};
+use crate::record::Recorder;
+use crate::server_env::ServerEnv;
+use crate::stderr_tail::StderrTail;
+use crate::supervisor::ManagedServer;
+use crate::telemetry::TelemetryState;
+use crate::timeouts::RequestTimeouts;
+use crate::toolchain;
+use crate::traffic::{Observers, TrafficObserver};
+use crate::wiretap::{WireTap, DEFAULT_MAX_LOG_BYTES};
+
+/// Where rust-analyzer builds, relative to the workspace root, so its checks
+/// neither wait on the lock of the user's `cargo build` nor invalidate its
+/// artifacts.
+pub const DEFAULT_ANALYSIS_TARGET_DIR: &str = "target/lspmux-cc";
+
+/// The project file rust-analyzer loads for non-Cargo builds.
+pub const RUST_PROJECT_FILE: &str = "rust-project.json";
+
+/// A started LSP client and the bootstrap outcome that preceded it.
+#[derive(Clone)]
+struct Started {
+    client: Arc<LspClient>,
+    runtime_status: RuntimeStatus,
+}
+
+/// Starts the LSP client on first use and shares it afterwards.
+pub struct LspLauncher {
+    runtime: RuntimeConfig,
+    telemetry: TelemetryState,
+    initialization_options: Option<Value>,
+    server_env: ServerEnv,
+    /// rust-analyzer's `cargo.targetDir` unless the options set one.
+    analysis_target_dir: Option<String>,
+    request_timeouts: RequestTimeouts,
+    file_size_limit: FileSizeLimit,
+    document_budget: u64,
+    /// Where to record the traffic of each client started, if anywhere.
+    record_dir: Option<PathBuf>,
+    /// Where to log each client's traffic for debugging, if anywhere.
+    wiretap: Option<PathBuf>,
+    /// The open `wiretap` log, shared by every client of this launcher and
+    /// of those made by [`Self::for_workspace`], so they rotate it together.
+    wiretap_log: Arc<std::sync::Mutex<Option<Arc<WireTap>>>>,
+    /// The project file set by [`Self::link_project`].
+    linked_project: std::sync::Mutex<Option<PathBuf>>,
+    started: watch::Sender<Option<Started>>,
+    /// Serializes startup so concurrent first calls spawn a single client.
+    start_lock: Mutex<()>,
+    /// The private lspmux server, in the `managed` bootstrap mode.
+    managed: Mutex<Option<ManagedServer>>,
+    /// Set by the watchdog while the client does not answer its probes.
+    degraded: AtomicBool,
+    /// The last stderr lines of the `lspmux client` children and managed
+    /// server started for this launcher.
+    stderr: StderrTail,
+}
+
+impl LspLauncher {
+    /// A launcher for the service described by `runtime`. Nothing is spawned
+    /// until [`Self::client`] is first called.
+    #[must_use]
+    pub fn new(runtime: RuntimeConfig, telemetry: TelemetryState) -> Self {
+        Self {
+            runtime,
+            telemetry,
+            initialization_options: None,
+            server_env: ServerEnv::default(),
+            analysis_target_dir: None,
+            request_timeouts: RequestTimeouts::new(DEFAULT_REQUEST_TIMEOUT),
+            file_size_limit: FileSizeLimit::default(),
+            document_budget: DEFAULT_DOCUMENT_BUDGET,
+            record_dir: None,
+            wiretap: None,
+            wiretap_log: Arc::default(),
+            linked_project: std::sync::Mutex::new(None),
+            started: watch::Sender::new(None),
+            start_lock: Mutex::new(()),
+            managed: Mutex::new(None),
+            degraded: AtomicBool::new(false),
+            stderr: StderrTail::default(),
+        }
+    }
+
+    /// Send `initialization_options` in the LSP initialize request.
+    #[must_use]
+    pub fn with_initialization_options(mut self, initialization_options: Option<Value>) -> Self {
+        self.initialization_options = initialization_options;
+        self
+    }
+
+    /// Start rust-analyzer with the variables of `server_env` set.
+    #[must_use]
+    pub fn with_server_env(mut self, server_env: ServerEnv) -> Self {
+        self.server_env = server_env;
+        self
+    }
+
+    /// Have rust-analyzer build in `analysis_target_dir` unless the
+    /// initialization options or project config name a target directory.
+    #[must_use]
+    pub fn with_analysis_target_dir(mut self, analysis_target_dir: Option<String>) -> Self {
+        self.analysis_target_dir = analysis_target_dir;
+        self
+    }
+
+    /// Apply `request_timeouts` to the client once it is started.
+    #[must_use]
+    pub fn with_request_timeouts(mut self, request_timeouts: RequestTimeouts) -> Self {
+        self.request_timeouts = request_timeouts;
+        self
+    }
+
+    /// Apply `file_size_limit` to the client once it is started.
+    #[must_use]
+    pub const fn with_file_size_limit(mut self, file_size_limit: FileSizeLimit) -> Self {
+        self.file_size_limit = file_size_limit;
+        self
+    }
+
+    /// Apply `document_budget` to the client once it is started.
+    #[must_use]
+    pub const fn with_document_budget(mut self, document_budget: u64) -> Self {
+        self.document_budget = document_budget;
+        self
+    }
+
+    /// Record each client's traffic with lspmux to a new file in `record_dir`.
+    #[must_use]
+    pub fn with_record_dir(mut self, record_dir: Option<PathBuf>) -> Self {
+        self.record_dir = record_dir;
+        self
+    }
+
+    /// Log each client's traffic with lspmux, redacted, to `wiretap`.
+    #[must_use]
+    pub fn with_wiretap(mut self, wiretap: Option<PathBuf>) -> Self {
+        self.wiretap = wiretap;
+        self
+    }
+
+    /// A launcher with the same settings for the workspace at `root`. In the
+    /// `managed` bootstrap mode it runs a private lspmux server of its own.
+    /// Its clients log to the same wiretap.
+    #[must_use]
+    pub fn for_workspace(&self, root: &str) -> Self {
+        let mut runtime = self.runtime.clone();
+        runtime.workspace_root = Some(root.to_string());
+        let mut launcher = Self::new(runtime, self.telemetry.clone())
+            .with_initialization_options(self.initialization_options.clone())
+            .with_server_env(self.server_env.clone())
+            .with_analysis_target_dir(self.analysis_target_dir.clone())
+            .with_request_timeouts(self.request_timeouts.clone())
+            .with_file_size_limit(self.file_size_limit)
+            .with_document_budget(self.document_budget)
+            .with_record_dir(self.record_dir.clone())
+            .with_wiretap(self.wiretap.clone());
+        launcher.wiretap_log = Arc::clone(&self.wiretap_log);
+        launcher
+    }
+
+    /// Load `project_file` (a `rust-project.json` or `Cargo.toml`) instead
+    /// of what rust-analyzer discovers in the workspace root. Clients started
+    /// from now on are initialized with it; a running client must be sent
+    /// [`Self::linked_project_settings`].
+    pub fn link_project(&self, project_file: PathBuf) {
+        *self
+            .linked_project
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(project_file);
+    }
+
+    /// The project file rust-analyzer loads, if not discovered by itself:
+    /// the one linked with [`Self::link_project`], or else a
+    /// [`RUST_PROJECT_FILE`] in a workspace root without a `Cargo.toml`.
+    pub fn linked_project(&self) -> Option<PathBuf> {
+        let linked = self
+            .linked_project
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        linked.or_else(|| {
+            let root = Path::new(self.workspace_root()?);
+            let project = root.join(RUST_PROJECT_FILE);
+            (project.is_file() && !root.join("Cargo.toml").is_file()).then_some(project)
+        })
+    }
+
+    /// The rust-analyzer settings for a project linked with
+    /// [`Self::link_project`], to merge over the others.
+    pub fn linked_project_settings(&self) -> Option<Value> {
+        self.linked_project
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .as_ref()
+            .map(|project| json!({ "linkedProjects": [project] }))
+    }
+
+    /// The initialization options to start a client with: those configured,
+    /// plus `linkedProjects` for the [`Self::linked_project`], the
+    /// [`ServerEnv`] as `extraEnv`, and the analysis target directory. A
+    /// linked project overrides configured `linkedProjects`; a discovered one
+    /// does not.
+    fn initialization_options(&self) -> Option<Value> {
+        let mut options = self.project_options();
+        if let Some(dir) = &self.analysis_target_dir {
+            let mut defaults = json!({ "cargo": { "targetDir": dir } });
+            if let Some(configured) = &options {
+                merge_settings(&mut defaults, configured);
+            }
+            options = Some(defaults);
+        }
+        self.server_env.apply(options)
+    }
+
+    /// The configured initialization options, with `linkedProjects` set as
+    /// [`Self::initialization_options`] describes.
+    fn project_options(&self) -> Option<Value> {
+        let Some(project) = self.linked_project() else {
+            return self.initialization_options.clone();
+        };
+        let mut options = self
+            .initialization_options
+            .clone()
+            .unwrap_or_else(|| json!({}));
+        if self.linked_project_settings().is_some() || options.get("linkedProjects").is_none() {
+            merge_settings(&mut options, &json!({ "linkedProjects": [project] }));
+        }
+        Some(options)
+    }
+
+    /// The running client, starting lspmux and rust-analyzer if needed.
+    ///
+    /// A failed start is not cached; the next call tries again.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the shared service cannot be prepared or the LSP
+    /// handshake fails.
+    pub async fn client(&self) -> Result<Arc<LspClient>> {
+        if let Some(client) = self.get() {
+            return Ok(client);
+        }
+        let _starting = self.start_lock.lock().await;
+        if let Some(client) = self.get() {
+            return Ok(client);
+        }
+
+        tracing::info!(event = "lsp_start", trigger = "first_use");
+        let mut runtime_status = self.prepare_service().await?;
+        if let Some(server_path) = self.toolchain_server().await {
+            runtime_status.server_path = server_path;
+        }
+        let mut observers: Vec<Arc<dyn TrafficObserver>> = Vec::new();
+        if let Some(dir) = &self.record_dir {
+            let recorder = Recorder::create(dir)?;
+            tracing::info!("recording LSP traffic to {}", recorder.path().display());
+            observers.push(Arc::new(recorder));
+        }
+        if let Some(tap) = self.wiretap_log()? {
+            observers.push(tap);
+        }
+        let client = self
+            .connect(&runtime_status, observers.into())
+            .await
+            .context("failed to initialize LSP client")?
+            .with_request_timeouts(self.request_timeouts.clone())
+            .with_file_size_limit(self.file_size_limit)
+            .with_document_budget(self.document_budget);
+        let client = Arc::new(client);
+        counter!("lspmux_cc_lsp_client_starts_total").increment(1);
+
+        self.started.send_replace(Some(Started {
+            client: Arc::clone(&client),
+            runtime_status,
+        }));
+        Ok(client)
+    }
+
+    /// The wiretap log, opened on first use, if one is configured.
+    fn wiretap_log(&self) -> Result<Option<Arc<WireTap>>> {
+        let Some(path) = &self.wiretap else {
+            return Ok(None);
+        };
+        let mut log = self
+            .wiretap_log
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if let Some(tap) = &*log {
+            return Ok(Some(Arc::clone(tap)));
+        }
+        let tap = Arc::new(WireTap::open(path, DEFAULT_MAX_LOG_BYTES)?);
+        tracing::info!("logging LSP traffic to {}", tap.path().display());
+        *log = Some(Arc::clone(&tap));
+        drop(log);
+        Ok(Some(tap))
+    }
+
+    /// The rust-analyzer of the toolchain the workspace pins, unless the
+    /// binary was configured explicitly.
+    async fn toolchain_server(&self) -> Option<String> {
+        if self.runtime.server_path_pinned {
+            return None;
+        }
+        let root = Path::new(self.workspace_root()?);
+        toolchain::toolchain_server(root, &self.server_env).await
+    }
+
+    /// Connect a client to the lspmux server prepared as `status`: over its
+    /// socket, or through an `lspmux client` child process, as the
+    /// [`LspTransport`] says, for the rust-analyzer at `status.server_path`.
+    async fn connect(&self, status: &RuntimeStatus, observers: Observers) -> Result<LspClient> {
+        let config_path = Path::new(&status.config_path);
+        let transport = self.runtime.lsp_transport;
+        let server_env = self.server_env.vars();
+        let env = server_env
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect::<Vec<_>>();
+        if status.service_mode == ServiceMode::Unshared {
+            return LspClient::spawn_unshared(
+                &status.server_path,
+                self.runtime.workspace_root.as_deref(),
+                &env,
+                self.initialization_options(),
+                observers,
+                &self.stderr,
+            )
+            .await;
+        }
+        if transport != LspTransport::Child {
+            let lspmux = LspmuxClientConfig::read(config_path);
+            let addr = lspmux.connect.unwrap_or_else(|| self.runtime.endpoint());
+            let connected = LspClient::connect_lspmux(
+                &addr,
+                &status.server_path,
+                self.runtime.workspace_root.as_deref(),
+                &lspmux.pass_environment,
+                &server_env,
+                self.initialization_options(),
+                Arc::clone(&observers),
+            )
+            .await;
+            match connected {
+                Ok(client) => {
+                    tracing::info!(event = "lsp_connected", transport = "socket");
+                    return Ok(client);
+                }
+                Err(error) if transport == LspTransport::Auto => {
+                    tracing::warn!("falling back to `lspmux client`: {error:#}");
+                }
+                Err(error) => return Err(error),
+            }
+        }
+        // `lspmux client` only passes on the variables its config names.
+        let passed = LspmuxClientConfig::read(config_path).pass_environment;
+        for name in self.server_env.names() {
+            if !passed.iter().any(|passed| passed == name) {
+                tracing::warn!(
+                    "{name} is not in the lspmux `pass_environment`; only cargo sees it"
+                );
+            }
+        }
+        let lspmux_config = (status.service_mode == ServiceMode::Managed).then_some(config_path);
+        let client = LspClient::new_with_options(
+            &self.runtime.lspmux_path,
+            &status.server_path,
+            self.runtime.workspace_root.as_deref(),
+            lspmux_config,
+            &env,
+            self.initialization_options(),
+            observers,
+            &self.stderr,
+        )
+        .await?;
+        tracing::info!(event = "lsp_connected", transport = "child");
+        Ok(client)
+    }
+
+    /// The client, if it has already been started.
+    pub fn get(&self) -> Option<Arc<LspClient>> {
+        self.started
+            .borrow()
+            .as_ref()
+            .map(|started| Arc::clone(&started.client))
+    }
+
+    /// Use `client`, connected by the caller (e.g. to a
+    /// [`crate::mock_lsp`] server), in place of starting one.
+    pub fn adopt(&self, client: LspClient) -> Arc<LspClient> {
+        let client = Arc::new(client);
+        self.started.send_replace(Some(Started {
+            client: Arc::clone(&client),
+            runtime_status: self.runtime.runtime_status(ServiceMode::Skipped),
+        }));
+        client
+    }
+
+    /// Replace the running client with a new one, shutting the old one down
+    /// in the background, e.g. because it stopped answering. The documents
+    /// the old client had open are opened in the new one, least recently
+    /// used first.
+    ///
+    /// # Errors
+    ///
+    /// As for [`Self::client`].
+    pub async fn restart(&self) -> Result<Arc<LspClient>> {
+        let old = {
+            let _starting = self.start_lock.lock().await;
+            self.started.send_replace(None)
+        };
+        let mut documents = Vec::new();
+        if let Some(old) = old {
+            documents = old.client.tracked_documents();
+            tokio::spawn(async move { old.client.shutdown().await });
+        }
+        tracing::info!(event = "lsp_start", trigger = "restart");
+        let client = self.client().await?;
+        documents.sort_by_key(|document| std::cmp::Reverse(document.since_use));
+        for document in documents {
+            if let Err(e) = client.ensure_file_open(&document.file_path).await {
+                tracing::debug!("not reopening {}: {e:#}", document.file_path);
+            }
+        }
+        Ok(client)
+    }
+
+    /// Whether the watchdog found the client unresponsive and has not heard
+    /// from its replacement yet.
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Record whether the client answers the watchdog's probes.
+    pub fn set_degraded(&self, degraded: bool) {
+        self.degraded.store(degraded, Ordering::Relaxed);
+    }
+
+    /// The bootstrap outcome, if the client has been started.
+    pub fn runtime_status(&self) -> Option<RuntimeStatus> {
+        self.started
+            .borrow()
+            .as_ref()
+            .map(|started| started.runtime_status.clone())
+    }
+
+    /// The runtime configuration the client is (or will be) started with.
+    pub const fn runtime(&self) -> &RuntimeConfig {
+        &self.runtime
+    }
+
+    /// Whether the lspmux server the client connects to accepts connections.
+    ///
+    /// In the `managed` mode this is `false` until the server is started.
+    pub async fn lspmux_reachable(&self) -> bool {
+        if self.runtime.bootstrap_mode == BootstrapMode::Managed {
+            return self
+                .managed
+                .lock()
+                .await
+                .as_ref()
+                .is_some_and(ManagedServer::is_ready);
+        }
+        self.runtime.service_ready()
+    }
+
+    /// The endpoint of the lspmux server the client connects to, or `None`
+    /// in the `managed` mode until the server is started and when
+    /// rust-analyzer [runs unshared](RuntimeConfig::runs_unshared).
+    pub async fn lspmux_endpoint(&self) -> Option<ConnectAddr> {
+        if self.runtime.runs_unshared() {
+            return None;
+        }
+        if self.runtime.bootstrap_mode == BootstrapMode::Managed {
+            return self
+                .managed
+                .lock()
+                .await
+                .as_ref()
+                .map(ManagedServer::endpoint);
+        }
+        let lspmux = LspmuxClientConfig::read(Path::new(&self.runtime.config_path));
+        Some(lspmux.connect.unwrap_or_else(|| self.runtime.endpoint()))
+    }
+
+    /// The workspace root the client is (or will be) started with.
+    pub fn workspace_root(&self) -> Option<&str> {
+        self.runtime.workspace_root.as_deref()
+    }
+
+    /// Wait until the client has been started by some caller.
+    pub async fn wait_started(&self) -> Arc<LspClient> {
+        let mut started = self.started.subscribe();
+        loop {
+            if let Some(started) = started.borrow_and_update().as_ref() {
+                return Arc::clone(&started.client);
+            }
+            // The sender lives as long as `self`, so this cannot fail.
+            let _ = started.changed().await;
+        }
+    }
+
+    /// The last stderr lines of the processes started for this launcher.
+    pub const fn stderr_tail(&self) -> &StderrTail {
+        &self.stderr
+    }
+
+    /// Shut down the client if it was ever started, then any managed server.
+    pub async fn shutdown(&self) {
+        if let Some(client) = self.get() {
+            client.shutdown().await;
+        }
+        let managed = self.managed.lock().await.take();
+        if let Some(server) = managed {
+            server.shutdown().await;
+        }
+    }
+
+    /// Start the private lspmux server, unless a previous attempt to start
+    /// the client already did.
+    async fn start_managed(&self) -> Result<RuntimeStatus> {
+        self.runtime.validate_binaries()?;
+        let mut managed = self.managed.lock().await;
+        let server = match managed.take() {
+            Some(server) => server,
+            None => ManagedServer::start(&self.runtime.lspmux_path, self.stderr.clone()).await?,
+        };
+        let mut status = self.runtime.runtime_status(ServiceMode::Managed);
+        status.config_path = server.config_path().display().to_string();
+        status.socket_path = server.addr();
+        *managed = Some(server);
+        drop(managed);
+        Ok(status)
+    }
+
+    /// Make the lspmux service available, recording the outcome in telemetry.
+    async fn prepare_service(&self) -> Result<RuntimeStatus> {
+        let bootstrap_started = Instant::now();
+        let prepared = if self.runtime.runs_unshared() {
+            tracing::warn!(
+                "lspmux not found at {}; running {SERVER_NAME} directly, so it is not shared \
+                 with editors or other sessions. Install lspmux to share it.",
+                self.runtime.lspmux_path
+            );
+            Ok(self.runtime.runtime_status(ServiceMode::Unshared))
+        } else if self.runtime.bootstrap_mode == BootstrapMode::Managed {
+            self.start_managed().await
+        } else {
+            self.runtime.ensure_service_running().await
+        };
+        match prepared {
+            Ok(status) => {
+                let bootstrap_latency_ms =
+                    u64::try_from(bootstrap_started.elapsed().as_millis()).unwrap_or(u64::MAX);
+                self.telemetry.record_bootstrap_success(
+                    match status.service_mode {
+                        ServiceMode::Reused => "reused",
+                        ServiceMode::StartedViaManager => "started_via_manager",
+                        ServiceMode::StartedDirectly => "started_directly",
+                        ServiceMode::Skipped => "skipped",
+                        ServiceMode::Managed => "managed",
+                        ServiceMode::Unshared => "unshared",
+                    },
+                    bootstrap_latency_ms,
+                );
+                tracing::info!(
+                    event = "bootstrap_result",
+                    service_mode = ?status.service_mode,
+                    latency_ms = bootstrap_latency_ms
+                );
+                Ok(status)
+            }
+            Err(error) => {
+                let bootstrap_latency_ms =
+                    u64::try_from(bootstrap_started.elapsed().as_millis()).unwrap_or(u64::MAX);
+                self.telemetry.record_bootstrap_failure(
+                    "prepare_service",
+                    &error.to_string(),
+                    bootstrap_latency_ms,
+                );
+                tracing::error!(
+                    event = "bootstrap_result",
+                    outcome = "failure",
+                    error = %error,
+                    latency_ms = bootstrap_latency_ms
+                );
+                Err(error).context("failed to prepare lspmux service")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootstrap::BootstrapMode;
+
+    fn missing_binaries() -> RuntimeConfig {
+        RuntimeConfig {
+            lspmux_path: "/nonexistent/lspmux".to_string(),
+            server_path: "/nonexistent/rust-analyzer".to_string(),
+            server_path_pinned: false,
+            workspace_root: Some("/tmp".to_string()),
+            config_path: "/nonexistent/lspmux.toml".to_string(),
+            socket_path: "/nonexistent/lspmux.sock".to_string(),
+            bootstrap_mode: BootstrapMode::Off,
+            connect_addr: None,
+            lsp_transport: LspTransport::Child,
+            no_mux_fallback: false,
+        }
+    }
+
+    #[tokio::test]
+    async fn nothing_is_started_until_first_use() {
+        let launcher = LspLauncher::new(missing_binaries(), TelemetryState::from_env());
+        assert!(launcher.get().is_none());
+        assert!(launcher.runtime_status().is_none());
+        assert_eq!(launcher.workspace_root(), Some("/tmp"));
+    }
+
+    #[test]
+    fn rust_project_files_are_linked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut runtime = missing_binaries();
+        runtime.workspace_root = Some(dir.path().display().to_string());
+        let launcher = LspLauncher::new(runtime, TelemetryState::from_env())
+            .with_initialization_options(Some(json!({ "cargo": { "features": "all" } })));
+        assert_eq!(launcher.linked_project(), None);
+
+        let project = dir.path().join(RUST_PROJECT_FILE);
+        std::fs::write(&project, "{}").unwrap();
+        assert_eq!(
+            launcher.initialization_options(),
+            Some(json!({
+                "cargo": { "features": "all" },
+                "linkedProjects": [project],
+            }))
+        );
+        // Discovered, so not pushed over the settings of a running client.
+        assert_eq!(launcher.linked_project_settings(), None);
+
+        let custom = dir.path().join("buck-out/rust-project.json");
+        launcher.link_project(custom.clone());
+        assert_eq!(launcher.linked_project(), Some(custom.clone()));
+        assert_eq!(
+            launcher.linked_project_settings(),
+            Some(json!({ "linkedProjects": [custom] }))
+        );
+    }
+
+    #[test]
+    fn configured_target_dirs_win_over_the_analysis_one() {
+        let launcher = LspLauncher::new(missing_binaries(), TelemetryState::from_env())
+            .with_analysis_target_dir(Some(DEFAULT_ANALYSIS_TARGET_DIR.to_string()));
+        assert_eq!(
+            launcher.initialization_options(),
+            Some(json!({ "cargo": { "targetDir": DEFAULT_ANALYSIS_TARGET_DIR } }))
+        );
+        let launcher = launcher.with_initialization_options(Some(
+            json!({ "cargo": { "features": "all", "targetDir": true } }),
+        ));
+        assert_eq!(
+            launcher.initialization_options(),
+            Some(json!({ "cargo": { "features": "all", "targetDir": true } }))
+        );
+    }
+
+    #[test]
+    fn workspaces_share_one_wiretap() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lsp.log");
+        let launcher = LspLauncher::new(missing_binaries(), TelemetryState::from_env())
+            .with_wiretap(Some(path.clone()));
+        let other = launcher.for_workspace("/elsewhere");
+        let tap = launcher.wiretap_log().unwrap().unwrap();
+        assert_eq!(tap.path(), path);
+        assert!(Arc::ptr_eq(&tap, &launcher.wiretap_log().unwrap().unwrap()));
+        assert!(Arc::ptr_eq(&tap, &other.wiretap_log().unwrap().unwrap()));
+        assert!(
+            LspLauncher::new(missing_binaries(), TelemetryState::from_env())
+                .wiretap_log()
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_start_is_retried_on_next_use() {
+        let launcher = LspLauncher::new(missing_binaries(), TelemetryState::from_env());
+        assert!(launcher.client().await.is_err());
+        assert!(launcher.get().is_none());
+        assert!(launcher.client().await.is_err());
+    }
+}
@@ -1,5 +1,24 @@
 //! lspmux-cc-mcp library: shared types for the MCP server and integration tests.
 
 pub mod bootstrap;
+pub mod config;
+pub mod health;
+pub mod launcher;
 pub mod lsp_client;
+pub mod lspmux_status;
+pub mod manifests;
+pub mod mock_lsp;
+pub mod notifications;
+pub mod pool;
+pub mod record;
+pub mod server_env;
+pub mod snippet;
+pub mod stderr_tail;
+pub mod supervisor;
 pub mod telemetry;
+pub mod timeouts;
+pub mod toolchain;
+pub mod traffic;
+pub mod warmup;
+pub mod watchdog;
+pub mod wiretap;
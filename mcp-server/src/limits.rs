@@ -0,0 +1,230 @@
+//! Limits on tool calls running at once.
+//!
+//! Every session shares one rust-analyzer, and an agent fanning out dozens of
+//! workspace-wide queries can keep it busy long enough to starve everyone
+//! else. [`CallLimits`] caps the calls in flight, globally and per tool
+//! (`[tools] max_in_flight` and `[tools.limits]` in the config file). A call
+//! over a limit waits in a queue until a slot frees up, and the client is
+//! told it is queued.
+//!
+//! Queries that arrive while rust-analyzer is still loading the workspace
+//! would get empty answers; [`IndexWait`] holds them until it is done, up
+//! to `--index-wait` seconds, with a bound on how many may wait.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Tool calls run at once across all tools, unless configured.
+pub const DEFAULT_MAX_IN_FLIGHT: usize = 16;
+
+/// How long a call waits in the queue before failing as busy.
+pub const MAX_QUEUE_WAIT: Duration = Duration::from_mins(2);
+
+/// How long a query waits for rust-analyzer to finish loading the
+/// workspace, unless configured.
+pub const DEFAULT_INDEX_WAIT: Duration = Duration::from_mins(1);
+
+/// Calls waiting for the workspace to load at once; more are refused as busy.
+pub const MAX_INDEX_WAITERS: usize = 64;
+
+/// Per-tool limits for the tools that walk the whole workspace, unless
+/// configured.
+const DEFAULT_TOOL_LIMITS: &[(&str, usize)] = &[
+    ("rust_workspace_symbol", 4),
+    ("rust_symbol_search", 4),
+    ("rust_find_references", 8),
+    ("rust_safe_delete_check", 2),
+    ("rust_diagnostics_dir", 2),
+    ("rust_diagnostics_changed", 2),
+    ("rust_diagnostics_snapshot", 2),
+    ("rust_diagnostics_diff", 2),
+    ("rust_unused_report", 2),
+];
+
+/// The in-flight limits shared by every session.
+#[derive(Clone, Debug)]
+pub struct CallLimits {
+    max_in_flight: usize,
+    global: Arc<Semaphore>,
+    tools: Arc<HashMap<String, (usize, Arc<Semaphore>)>>,
+}
+
+/// A slot for one call, released when dropped.
+#[derive(Debug)]
+pub struct CallPermit {
+    _tool: Option<OwnedSemaphorePermit>,
+    _global: OwnedSemaphorePermit,
+}
+
+impl Default for CallLimits {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_IN_FLIGHT, &BTreeMap::new())
+    }
+}
+
+impl CallLimits {
+    /// Allow `max_in_flight` calls at once, and per tool the default limits
+    /// overridden by `tool_limits`.
+    pub fn new(max_in_flight: usize, tool_limits: &BTreeMap<String, usize>) -> Self {
+        let mut limits = DEFAULT_TOOL_LIMITS
+            .iter()
+            .map(|&(tool, limit)| (tool.to_string(), limit))
+            .collect::<BTreeMap<_, _>>();
+        limits.extend(
+            tool_limits
+                .iter()
+                .map(|(tool, limit)| (tool.clone(), *limit)),
+        );
+        let tools = limits
+            .into_iter()
+            .map(|(tool, limit)| (tool, (limit, Arc::new(Semaphore::new(limit)))))
+            .collect();
+        Self {
+            max_in_flight,
+            global: Arc::new(Semaphore::new(max_in_flight)),
+            tools: Arc::new(tools),
+        }
+    }
+
+    /// Take a slot for a `tool` call if one is free right away.
+    pub fn try_acquire(&self, tool: &str) -> Option<CallPermit> {
+        let tool = match self.tools.get(tool) {
+            Some((_, semaphore)) => Some(Arc::clone(semaphore).try_acquire_owned().ok()?),
+            None => None,
+        };
+        let global = Arc::clone(&self.global).try_acquire_owned().ok()?;
+        Some(CallPermit {
+            _tool: tool,
+            _global: global,
+        })
+    }
+
+    /// Wait for a slot for a `tool` call. Calls waiting on the same limit
+    /// are served in order.
+    pub async fn acquire(&self, tool: &str) -> CallPermit {
+        // The tool's own slot first, so calls queued behind a busy tool do
+        // not hold global slots other tools could use.
+        let tool = match self.tools.get(tool) {
+            Some((_, semaphore)) => Some(
+                Arc::clone(semaphore)
+                    .acquire_owned()
+                    .await
+                    .expect("call limits are never closed"),
+            ),
+            None => None,
+        };
+        let global = Arc::clone(&self.global)
+            .acquire_owned()
+            .await
+            .expect("call limits are never closed");
+        CallPermit {
+            _tool: tool,
+            _global: global,
+        }
+    }
+
+    /// Which limit a `tool` call would currently wait on, for telling the
+    /// client why it is queued.
+    pub fn busy_reason(&self, tool: &str) -> String {
+        match self.tools.get(tool) {
+            Some((limit, semaphore)) if semaphore.available_permits() == 0 => {
+                format!("{limit} {tool} call(s) already running")
+            }
+            _ => format!("{} tool call(s) already running", self.max_in_flight),
+        }
+    }
+
+    /// The tools with a limit of their own.
+    pub fn limited_tools(&self) -> impl Iterator<Item = &str> {
+        self.tools.keys().map(String::as_str)
+    }
+}
+
+/// How long calls wait for rust-analyzer to finish loading the workspace,
+/// shared by every session.
+#[derive(Clone, Debug)]
+pub struct IndexWait {
+    limit: Duration,
+    waiters: Arc<Semaphore>,
+}
+
+impl Default for IndexWait {
+    fn default() -> Self {
+        Self::new(DEFAULT_INDEX_WAIT)
+    }
+}
+
+impl IndexWait {
+    /// Hold calls for at most `limit`; zero runs them at once.
+    pub fn new(limit: Duration) -> Self {
+        Self {
+            limit,
+            waiters: Arc::new(Semaphore::new(MAX_INDEX_WAITERS)),
+        }
+    }
+
+    /// The longest a call is held.
+    pub const fn limit(&self) -> Duration {
+        self.limit
+    }
+
+    /// A place among the waiting calls, unless they are all taken.
+    pub fn try_enter(&self) -> Option<OwnedSemaphorePermit> {
+        Arc::clone(&self.waiters).try_acquire_owned().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn calls_over_a_limit_wait_for_a_slot() {
+        let limits = CallLimits::new(2, &BTreeMap::from([("rust_hover".to_string(), 1)]));
+        let hover = limits.try_acquire("rust_hover").unwrap();
+        assert!(limits.try_acquire("rust_hover").is_none());
+        assert_eq!(
+            limits.busy_reason("rust_hover"),
+            "1 rust_hover call(s) already running"
+        );
+
+        // Another tool still gets the second global slot, then nothing does.
+        let definition = limits.try_acquire("rust_goto_definition").unwrap();
+        assert!(limits.try_acquire("rust_locate").is_none());
+        assert_eq!(
+            limits.busy_reason("rust_locate"),
+            "2 tool call(s) already running"
+        );
+
+        let queued = tokio::spawn({
+            let limits = limits.clone();
+            async move { drop(limits.acquire("rust_hover").await) }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!queued.is_finished());
+        drop(definition);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!queued.is_finished(), "the rust_hover slot is still taken");
+        drop(hover);
+        tokio::time::timeout(Duration::from_secs(5), queued)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[test]
+    fn configured_limits_override_the_defaults() {
+        let limits = CallLimits::new(
+            4,
+            &BTreeMap::from([("rust_workspace_symbol".to_string(), 1)]),
+        );
+        let _first = limits.try_acquire("rust_workspace_symbol").unwrap();
+        assert!(limits.try_acquire("rust_workspace_symbol").is_none());
+        assert!(limits
+            .limited_tools()
+            .any(|tool| tool == "rust_unused_report"));
+    }
+}
@@ -1,61 +1,446 @@
-//! LSP JSON-RPC client that communicates with lspmux via child process stdio.
+//! LSP JSON-RPC client that communicates with lspmux.
 //!
-//! Spawns `lspmux client --server-path <server>` and speaks LSP over its stdin/stdout.
-//! Handles the `Content-Length` framing, request ID tracking, and the
-//! `initialize`/`initialized` handshake.
+//! [`LspClient::connect_lspmux`] speaks LSP straight over the lspmux server's
+//! socket, asking for the language server in the `lspMux` initialization
+//! option just as `lspmux client` would. The constructors that spawn
+//! `lspmux client --server-path <server>` instead speak LSP over its
+//! stdin/stdout. Either way the client handles the `Content-Length` framing,
+//! request ID tracking, and the `initialize`/`initialized` handshake.
+//!
+//! [`LspClient::connect`] speaks the same protocol over any reader and
+//! writer, which is how tests drive it against [`crate::mock_lsp`].
+//!
+//! Independent requests run concurrently: outgoing frames are queued to a
+//! single writer task, and the pending-request and open-document maps are
+//! only locked briefly, never across an `.await`.
 
 use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
-use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
-use std::sync::Arc;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::ValueEnum;
 use lsp_types::{
-    request::{GotoDefinition, HoverRequest, References, Request, WorkspaceSymbolRequest},
-    ClientCapabilities, DidChangeTextDocumentParams, DidOpenTextDocumentParams, InitializeParams,
-    InitializedParams, TextDocumentContentChangeEvent, TextDocumentItem, Uri,
-    VersionedTextDocumentIdentifier, WorkspaceSymbolParams,
+    request::{
+        CodeActionRequest, CodeActionResolveRequest, Completion, DocumentLinkRequest,
+        DocumentLinkResolve, DocumentSymbolRequest, ExecuteCommand, GotoDefinition,
+        GotoImplementation, HoverRequest, References, Rename, Request, ResolveCompletionItem,
+        WillRenameFiles, WorkspaceSymbolRequest,
+    },
+    ClientCapabilities, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, InitializeParams, InitializedParams, TextDocumentContentChangeEvent,
+    TextDocumentItem, Uri, VersionedTextDocumentIdentifier, WorkspaceSymbolParams,
 };
-use metrics::counter;
+use metrics::{counter, histogram};
 use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use serde_json::Value;
-use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
 use tokio::process::{Child, Command};
-use tokio::sync::{oneshot, Mutex};
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
 use tokio::time::{timeout, Duration};
 
+use crate::bootstrap::{ConnectAddr, SERVER_NAME};
+use crate::notifications::{Notification, NotificationQueue, NOTIFICATION_QUEUE_CAPACITY};
+use crate::snippet;
+use crate::stderr_tail::StderrTail;
 use crate::telemetry::{now_unix_ms, ReadinessState};
+use crate::timeouts::RequestTimeouts;
+use crate::traffic::{Observers, Origin, Tap, TrafficTotals};
 
 /// A pending request awaiting its response.
 type PendingMap = Arc<Mutex<HashMap<i64, oneshot::Sender<Value>>>>;
 
-/// Timeout for LSP requests. Rust-analyzer can be slow on large workspaces,
-/// but 30 seconds is generous enough for any single request.
-const LSP_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Framed messages queued for the child's stdin, written in order by
+/// [`writer_loop`]. Holds at most [`OUTBOX_CAPACITY`] frames.
+type Outbox = mpsc::Sender<Vec<u8>>;
+
+/// In-progress `$/progress` work, keyed by token.
+type ActiveProgress = Arc<tokio::sync::Mutex<HashMap<String, ProgressEvent>>>;
+
+/// Edits the server asked to apply with `workspace/applyEdit` while a
+/// command runs; `None` while none does, when such requests are refused.
+type EditCapture = Arc<Mutex<Option<Vec<lsp_types::WorkspaceEdit>>>>;
+
+/// Per-file gates that let one caller synchronize a document while
+/// concurrent callers for the same file wait for its result. An entry lives
+/// only while some caller holds or waits on it; see [`SyncGate`].
+type SyncGates = Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>;
+
+/// Default timeout for LSP requests outside every
+/// [`MethodClass`](crate::timeouts::MethodClass). Rust-analyzer can be slow
+/// on large workspaces, but 30 seconds is generous enough for most requests.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Version of the lspmux protocol spoken by [`LspClient::connect_lspmux`].
+pub(crate) const LSPMUX_PROTOCOL_VERSION: &str = "1";
+
+/// How long [`LspClient::connect_lspmux`] waits for the server to accept.
+const LSPMUX_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Maximum allowed LSP message body size (100 MB). Prevents OOM from a
 /// maliciously large `Content-Length` header.
 const MAX_LSP_MESSAGE_SIZE: usize = 100 * 1024 * 1024;
 
-/// LSP client that talks to lspmux through a child process.
+/// Maximum length of one LSP header line, so a server that never ends a
+/// line cannot make the reader buffer without bound.
+const MAX_LSP_HEADER_LINE: usize = 8 * 1024;
+
+/// Largest file sent to the server by default (10 MiB).
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+
+/// Total content of open documents kept by default (64 MiB) before the
+/// least recently used ones are closed.
+pub const DEFAULT_DOCUMENT_BUDGET: u64 = 64 * 1024 * 1024;
+
+/// The coarsest modification-time resolution assumed of a file system. A
+/// file modified this recently before it was read may change again without
+/// its modification time changing.
+const MTIME_GRANULARITY: Duration = Duration::from_secs(1);
+
+/// Capacity of the `$/progress` broadcast channel. Slow subscribers skip
+/// stale events rather than holding up the others.
+const PROGRESS_CHANNEL_CAPACITY: usize = 64;
+
+/// Capacity of the `textDocument/publishDiagnostics` broadcast channel.
+const DIAGNOSTICS_CHANNEL_CAPACITY: usize = 256;
+
+/// Frames queued for the child's stdin before sending fails. A server that
+/// stops reading its stdin fails new messages instead of growing the queue.
+const OUTBOX_CAPACITY: usize = 1024;
+
+/// A `textDocument/publishDiagnostics` notification: the server's complete
+/// pushed diagnostics for one file, such as `cargo check` results.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PublishedDiagnostics {
+    pub file_path: String,
+    /// The document version the diagnostics were computed for, if given.
+    pub version: Option<i32>,
+    pub diagnostics: Vec<lsp_types::Diagnostic>,
+}
+
+/// A `$/progress` work-done notification from the language server.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProgressEvent {
+    /// The server-chosen progress token, rendered as a string.
+    pub token: String,
+    /// Progress phase: `"begin"`, `"report"`, or `"end"`.
+    pub kind: String,
+    /// Title from the `begin` notification (e.g. `"Indexing"`).
+    pub title: Option<String>,
+    /// Optional detail message (e.g. the crate currently being indexed).
+    pub message: Option<String>,
+    /// Completion percentage in `0..=100`, when the server reports one.
+    pub percentage: Option<u32>,
+}
+
+/// rust-analyzer's `rust-analyzer/analyzerStatus` request: a text dump of
+/// its state, cheap enough to check that it still answers.
+pub enum AnalyzerStatus {}
+
+impl Request for AnalyzerStatus {
+    type Params = AnalyzerStatusParams;
+    type Result = String;
+    const METHOD: &'static str = "rust-analyzer/analyzerStatus";
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct AnalyzerStatusParams {
+    /// The file to report on as well, if any.
+    pub text_document: Option<lsp_types::TextDocumentIdentifier>,
+}
+
+/// rust-analyzer's `rust-analyzer/reloadWorkspace` request: reread the
+/// manifests and reload the project model.
+pub enum ReloadWorkspace {}
+
+impl Request for ReloadWorkspace {
+    type Params = ();
+    type Result = ();
+    const METHOD: &'static str = "rust-analyzer/reloadWorkspace";
+}
+
+/// Parameters of rust-analyzer's `rust-analyzer/runFlycheck` notification.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RunFlycheckParams {
+    /// Check only the workspace containing this file; all of them if `None`.
+    pub text_document: Option<lsp_types::TextDocumentIdentifier>,
+}
+
+/// rust-analyzer's `experimental/runnables` request: the tests, binaries,
+/// and benchmarks in a file, with the cargo command that runs each.
+pub enum Runnables {}
+
+impl Request for Runnables {
+    type Params = RunnablesParams;
+    type Result = Vec<Runnable>;
+    const METHOD: &'static str = "experimental/runnables";
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct RunnablesParams {
+    pub text_document: lsp_types::TextDocumentIdentifier,
+    pub position: Option<lsp_types::Position>,
+}
+
+/// Something rust-analyzer can run, such as `test tests::parses`.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Runnable {
+    pub label: String,
+    pub location: Option<lsp_types::LocationLink>,
+    /// `cargo`, or `shell` for non-cargo projects.
+    pub kind: String,
+    /// For `cargo` runnables, [`CargoRunnableArgs`].
+    pub args: Value,
+}
+
+/// How to run a `cargo` runnable: `cargo <cargo_args> -- <executable_args>`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct CargoRunnableArgs {
+    /// The directory to run cargo in.
+    pub cwd: Option<String>,
+    /// What older rust-analyzer versions send instead of `cwd`.
+    pub workspace_root: Option<String>,
+    pub cargo_args: Vec<String>,
+    #[serde(default)]
+    pub executable_args: Vec<String>,
+}
+
+impl Runnable {
+    /// The cargo command of a `cargo` runnable.
+    #[must_use]
+    pub fn cargo_args(&self) -> Option<CargoRunnableArgs> {
+        (self.kind == "cargo")
+            .then(|| serde_json::from_value(self.args.clone()).ok())
+            .flatten()
+    }
+}
+
+/// The error of an LSP request that got no response within its timeout.
+#[derive(Debug)]
+pub struct RequestTimedOut {
+    pub method: &'static str,
+    pub after: Duration,
+}
+
+impl std::fmt::Display for RequestTimedOut {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "LSP request {} timed out after {}s",
+            self.method,
+            self.after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for RequestTimedOut {}
+
+/// Whether `error`, or an error it wraps, is a [`RequestTimedOut`].
+#[must_use]
+pub fn is_timeout(error: &anyhow::Error) -> bool {
+    error
+        .chain()
+        .any(<dyn std::error::Error>::is::<RequestTimedOut>)
+}
+
+/// A document the server has been sent.
+#[derive(Clone, Copy, Debug)]
+struct OpenDocument {
+    version: i32,
+    content_hash: u64,
+    /// The file's modification time and size when its content was read.
+    stamp: Option<FileStamp>,
+    /// Bytes of content the server holds for the document.
+    len: u64,
+    /// When the file was last checked against the content, taken before
+    /// reading its metadata.
+    synced_at: std::time::Instant,
+    /// When a tool call last touched the document, for eviction.
+    last_used: std::time::Instant,
+}
+
+/// What the server has been sent of one document, for reporting.
+#[derive(Clone, Debug)]
+pub struct TrackedDocument {
+    pub file_path: String,
+    /// The version of the last `didOpen` or `didChange`.
+    pub version: i32,
+    /// [`content_hash`] of the content the server holds.
+    pub content_hash: u64,
+    /// Bytes of content the server holds.
+    pub len: u64,
+    /// Time since the content was last compared against the file on disk.
+    pub since_sync: Duration,
+    /// Time since a tool call last touched the document.
+    pub since_use: Duration,
+    stamp: Option<FileStamp>,
+}
+
+impl TrackedDocument {
+    /// Whether the file, as described by `metadata`, still has the
+    /// modification time and size it had when its content was read; `None`
+    /// if that is unknown, as after the document was invalidated or where
+    /// modification times are unavailable.
+    #[must_use]
+    pub fn matches_disk(&self, metadata: &std::fs::Metadata) -> Option<bool> {
+        let stamp = self.stamp?;
+        Some(FileStamp::of(metadata)? == stamp)
+    }
+}
+
+/// Cheap evidence that a file has not changed since it was last read.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct FileStamp {
+    modified: std::time::SystemTime,
+    len: u64,
+}
+
+impl FileStamp {
+    /// The stamp of a file, or `None` where modification times are
+    /// unavailable (the file is then always re-read).
+    fn of(metadata: &std::fs::Metadata) -> Option<Self> {
+        Some(Self {
+            modified: metadata.modified().ok()?,
+            len: metadata.len(),
+        })
+    }
+
+    /// Whether the file may have been modified again, without a new
+    /// modification time, after being read at `read_at`: its modification
+    /// time is within one [`MTIME_GRANULARITY`] of the read.
+    fn is_racy(&self, read_at: std::time::SystemTime) -> bool {
+        read_at
+            .duration_since(self.modified)
+            .map_or(true, |age| age < MTIME_GRANULARITY)
+    }
+}
+
+/// A caller's hold on the sync gate of one file. The last caller to let go
+/// of a gate removes it from [`SyncGates`].
+struct SyncGate<'a> {
+    gates: &'a SyncGates,
+    file_path: &'a str,
+    gate: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl<'a> SyncGate<'a> {
+    fn new(gates: &'a SyncGates, file_path: &'a str) -> Self {
+        let gate = Arc::clone(lock(gates).entry(file_path.to_string()).or_default());
+        Self {
+            gates,
+            file_path,
+            gate,
+        }
+    }
+
+    /// Wait for the turn to synchronize the file.
+    async fn lock(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.gate.lock().await
+    }
+}
+
+impl Drop for SyncGate<'_> {
+    fn drop(&mut self) {
+        // Gates are cloned only under this lock, so no caller can take hold
+        // of this one between the count and the removal.
+        let mut gates = lock(self.gates);
+        if gates
+            .get(self.file_path)
+            .is_some_and(|gate| Arc::ptr_eq(gate, &self.gate) && Arc::strong_count(gate) == 2)
+        {
+            gates.remove(self.file_path);
+        }
+    }
+}
+
+/// What to do with a file larger than [`FileSizeLimit::max_bytes`].
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum LargeFileMode {
+    /// Fail the tool call with a message naming the limit.
+    #[default]
+    Refuse,
+    /// Send only the leading `max_bytes`, cut at a line boundary.
+    Truncate,
+}
+
+/// Guards rust-analyzer against huge (typically generated) files, which it
+/// can take minutes to process.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileSizeLimit {
+    pub max_bytes: u64,
+    pub mode: LargeFileMode,
+}
+
+impl Default for FileSizeLimit {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_FILE_SIZE,
+            mode: LargeFileMode::Refuse,
+        }
+    }
+}
+
+/// LSP client that talks to lspmux over its socket or through a child process.
 pub struct LspClient {
-    child_stdin: Arc<Mutex<tokio::process::ChildStdin>>,
+    outbox: Outbox,
     next_id: AtomicI64,
     pending: PendingMap,
-    /// Tracks files we've sent `didOpen` for: `(version, content_hash)`.
-    /// The content hash is used to skip redundant `didChange` notifications.
-    opened_files: Mutex<HashMap<String, (i32, u64)>>,
-    child: Arc<Mutex<Child>>,
+    /// Tracks files we've sent `didOpen` for. The content hash is used to
+    /// skip redundant `didChange` notifications.
+    opened_files: Mutex<HashMap<String, OpenDocument>>,
+    /// Serializes synchronization of each file; see [`SyncGates`].
+    sync_gates: SyncGates,
+    /// The lspmux client process; `None` for a client made by [`Self::connect`]
+    /// or [`Self::connect_lspmux`].
+    child: Arc<tokio::sync::Mutex<Option<Child>>>,
     /// Set to `false` when the reader task exits (child process died or stdout closed).
     alive: Arc<AtomicBool>,
     /// Workspace root path (set after LSP initialize handshake).
     workspace_root: tokio::sync::Mutex<Option<String>>,
-    /// Backend server version (set after LSP initialize handshake).
-    server_version: tokio::sync::Mutex<Option<String>>,
+    /// Backend server name and version (set after LSP initialize handshake).
+    server_info: tokio::sync::Mutex<Option<lsp_types::ServerInfo>>,
     /// Latest rust-analyzer readiness notification.
     readiness: Arc<tokio::sync::Mutex<ReadinessState>>,
+    /// Fan-out of `$/progress` notifications to in-flight tool calls.
+    progress_tx: broadcast::Sender<ProgressEvent>,
+    /// Work-done progress that has begun but not ended, keyed by token.
+    active_progress: ActiveProgress,
+    /// Fan-out of `textDocument/publishDiagnostics` notifications.
+    diagnostics_tx: broadcast::Sender<PublishedDiagnostics>,
+    /// Bumped whenever the server's view of the workspace may have changed:
+    /// a synchronized document was edited, or rust-analyzer's readiness moved
+    /// (it re-analyzes after watched files change on disk).
+    generation: Arc<AtomicU64>,
+    /// Capabilities negotiated during the LSP initialize handshake.
+    capabilities: watch::Sender<lsp_types::ServerCapabilities>,
+    /// How long a request may wait for its response, by method.
+    request_timeouts: RequestTimeouts,
+    /// Largest file sent as a document, and what happens to larger ones.
+    file_size_limit: FileSizeLimit,
+    /// Total bytes of open documents before the least recently used are closed.
+    document_budget: u64,
+    /// Settings sent as `initializationOptions`; project overrides merge over these.
+    base_settings: Value,
+    /// rust-analyzer settings currently in effect, served to `workspace/configuration`.
+    settings: watch::Sender<Value>,
+    /// Bytes exchanged with the server so far.
+    traffic: Arc<TrafficTotals>,
+    /// Edits requested by the running [`Self::execute_command`].
+    applied_edits: EditCapture,
+    /// Runs one command at a time, so each gets only its own edits.
+    command_lock: tokio::sync::Mutex<()>,
 }
 
 /// Bytes to percent-encode in file URI paths. Encodes everything except
@@ -67,6 +452,17 @@ const PATH_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
     .remove(b'~')
     .remove(b'/');
 
+/// The parameters of `workspace/willRenameFiles` and `didRenameFiles` for
+/// one move.
+fn file_rename_params(from: &str, to: &str) -> Result<lsp_types::RenameFilesParams> {
+    Ok(lsp_types::RenameFilesParams {
+        files: vec![lsp_types::FileRename {
+            old_uri: file_uri(from)?.to_string(),
+            new_uri: file_uri(to)?.to_string(),
+        }],
+    })
+}
+
 /// Create a `file://` URI from an absolute file path.
 ///
 /// # Errors
@@ -129,6 +525,222 @@ fn detect_language_id(path: &str) -> &'static str {
     }
 }
 
+/// The `lspmux client` command for `server_bin`, in its own process group.
+fn client_command(
+    lspmux_bin: &str,
+    server_bin: &str,
+    lspmux_config: Option<&Path>,
+    env: &[(&str, &str)],
+) -> Command {
+    let mut cmd = Command::new(lspmux_bin);
+    cmd.arg("client");
+    if let Some(config) = lspmux_config {
+        cmd.arg("--config").arg(config);
+    }
+    cmd.arg("--server-path").arg(server_bin);
+    stdio_command(cmd, env)
+}
+
+/// `cmd` with piped stdio and `env` set, in its own process group.
+fn stdio_command(mut cmd: Command, env: &[(&str, &str)]) -> Command {
+    cmd.stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        // Drained by a `StderrTail`, so verbose child logging cannot fill
+        // the pipe buffer and block the process.
+        .stderr(std::process::Stdio::piped());
+    for &(key, val) in env {
+        cmd.env(key, val);
+    }
+    // A terminal Ctrl-C goes to the whole foreground process group. Keep
+    // the child out of it so `shutdown` can still talk to it; kill-on-drop
+    // covers exits that never reach `shutdown`.
+    #[cfg(unix)]
+    cmd.process_group(0);
+    cmd.kill_on_drop(true);
+    cmd
+}
+
+/// The `lspMux` initialization option that asks the lspmux server for
+/// `server_bin`, like `lspmux client` sends it.
+fn lspmux_options(
+    server_bin: &str,
+    workspace_root: Option<&str>,
+    pass_environment: &[String],
+    server_env: &[(String, String)],
+) -> Value {
+    let mut env: serde_json::Map<String, Value> = pass_environment
+        .iter()
+        .filter_map(|name| Some((name.clone(), Value::from(std::env::var(name).ok()?))))
+        .collect();
+    for (name, value) in server_env {
+        env.insert(name.clone(), Value::from(value.as_str()));
+    }
+    let cwd = workspace_root.map(ToOwned::to_owned).or_else(|| {
+        std::env::current_dir()
+            .ok()
+            .map(|dir| dir.display().to_string())
+    });
+    json!({
+        "version": LSPMUX_PROTOCOL_VERSION,
+        "method": "connect",
+        "server": server_bin,
+        "args": [],
+        "env": env,
+        "cwd": cwd,
+    })
+}
+
+/// The read and write halves of a connection to the lspmux server.
+pub(crate) type LspmuxStream = (
+    Box<dyn AsyncRead + Unpin + Send>,
+    Box<dyn AsyncWrite + Unpin + Send>,
+);
+
+/// Connect to the lspmux server at `addr`.
+///
+/// # Errors
+///
+/// Returns an error if the server does not accept the connection within
+/// five seconds.
+pub(crate) async fn open_lspmux(addr: &ConnectAddr) -> Result<LspmuxStream> {
+    match addr {
+        ConnectAddr::Tcp(host, port) => {
+            let stream = timeout(
+                LSPMUX_CONNECT_TIMEOUT,
+                tokio::net::TcpStream::connect((host.as_str(), *port)),
+            )
+            .await
+            .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into()))
+            .with_context(|| format!("failed to connect to lspmux at {host}:{port}"))?;
+            let (reader, writer) = stream.into_split();
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+        #[cfg(unix)]
+        ConnectAddr::Unix(path) => {
+            let stream = timeout(
+                LSPMUX_CONNECT_TIMEOUT,
+                tokio::net::UnixStream::connect(path),
+            )
+            .await
+            .unwrap_or_else(|_| Err(std::io::ErrorKind::TimedOut.into()))
+            .with_context(|| format!("failed to connect to lspmux at {path}"))?;
+            let (reader, writer) = stream.into_split();
+            Ok((Box::new(reader), Box::new(writer)))
+        }
+        #[cfg(not(unix))]
+        ConnectAddr::Unix(path) => {
+            bail!("cannot connect to lspmux at {path}: Unix sockets are not supported")
+        }
+    }
+}
+
+/// `initialization_options` with the `lspMux` option added, if any.
+fn with_lspmux_options(
+    initialization_options: Option<Value>,
+    lsp_mux: Option<Value>,
+) -> Option<Value> {
+    let Some(lsp_mux) = lsp_mux else {
+        return initialization_options;
+    };
+    let mut options = initialization_options
+        .filter(Value::is_object)
+        .unwrap_or_else(|| json!({}));
+    options["lspMux"] = lsp_mux;
+    Some(options)
+}
+
+/// Capabilities advertised in the initialize request.
+fn client_capabilities() -> ClientCapabilities {
+    ClientCapabilities {
+        text_document: Some(lsp_types::TextDocumentClientCapabilities {
+            code_action: Some(lsp_types::CodeActionClientCapabilities {
+                code_action_literal_support: Some(lsp_types::CodeActionLiteralSupport {
+                    code_action_kind: lsp_types::CodeActionKindLiteralSupport {
+                        value_set: [
+                            lsp_types::CodeActionKind::QUICKFIX,
+                            lsp_types::CodeActionKind::REFACTOR,
+                            lsp_types::CodeActionKind::REFACTOR_EXTRACT,
+                            lsp_types::CodeActionKind::REFACTOR_INLINE,
+                            lsp_types::CodeActionKind::REFACTOR_REWRITE,
+                            lsp_types::CodeActionKind::SOURCE,
+                            lsp_types::CodeActionKind::SOURCE_ORGANIZE_IMPORTS,
+                        ]
+                        .iter()
+                        .map(|kind| kind.as_str().to_string())
+                        .collect(),
+                    },
+                }),
+                is_preferred_support: Some(true),
+                data_support: Some(true),
+                resolve_support: Some(lsp_types::CodeActionCapabilityResolveSupport {
+                    properties: vec!["edit".to_string()],
+                }),
+                ..lsp_types::CodeActionClientCapabilities::default()
+            }),
+            completion: Some(lsp_types::CompletionClientCapabilities {
+                completion_item: Some(lsp_types::CompletionItemCapability {
+                    snippet_support: Some(true),
+                    documentation_format: Some(vec![
+                        lsp_types::MarkupKind::Markdown,
+                        lsp_types::MarkupKind::PlainText,
+                    ]),
+                    resolve_support: Some(lsp_types::CompletionItemCapabilityResolveSupport {
+                        properties: ["documentation", "detail", "additionalTextEdits"]
+                            .map(String::from)
+                            .to_vec(),
+                    }),
+                    label_details_support: Some(true),
+                    ..lsp_types::CompletionItemCapability::default()
+                }),
+                ..lsp_types::CompletionClientCapabilities::default()
+            }),
+            synchronization: Some(lsp_types::TextDocumentSyncClientCapabilities {
+                did_save: Some(true),
+                ..lsp_types::TextDocumentSyncClientCapabilities::default()
+            }),
+            document_symbol: Some(lsp_types::DocumentSymbolClientCapabilities {
+                hierarchical_document_symbol_support: Some(true),
+                ..lsp_types::DocumentSymbolClientCapabilities::default()
+            }),
+            document_link: Some(lsp_types::DocumentLinkClientCapabilities {
+                dynamic_registration: Some(false),
+                tooltip_support: Some(true),
+            }),
+            ..lsp_types::TextDocumentClientCapabilities::default()
+        }),
+        window: Some(lsp_types::WindowClientCapabilities {
+            work_done_progress: Some(true),
+            ..lsp_types::WindowClientCapabilities::default()
+        }),
+        workspace: Some(lsp_types::WorkspaceClientCapabilities {
+            configuration: Some(true),
+            apply_edit: Some(true),
+            execute_command: Some(lsp_types::DynamicRegistrationClientCapabilities {
+                dynamic_registration: Some(false),
+            }),
+            workspace_edit: Some(lsp_types::WorkspaceEditClientCapabilities {
+                document_changes: Some(true),
+                ..lsp_types::WorkspaceEditClientCapabilities::default()
+            }),
+            did_change_configuration: Some(lsp_types::DynamicRegistrationClientCapabilities {
+                dynamic_registration: Some(false),
+            }),
+            file_operations: Some(lsp_types::WorkspaceFileOperationsClientCapabilities {
+                will_rename: Some(true),
+                did_rename: Some(true),
+                ..lsp_types::WorkspaceFileOperationsClientCapabilities::default()
+            }),
+            ..lsp_types::WorkspaceClientCapabilities::default()
+        }),
+        experimental: Some(json!({
+            "serverStatusNotification": true,
+            // Snippet edits are rendered before they are written.
+            "snippetTextEdit": true,
+        })),
+        ..ClientCapabilities::default()
+    }
+}
+
 impl LspClient {
     /// Spawn the lspmux client child process and perform the LSP handshake.
     ///
@@ -141,7 +753,51 @@ impl LspClient {
         server_bin: &str,
         workspace_root: Option<&str>,
     ) -> Result<Self> {
-        Self::new_with_env(lspmux_bin, server_bin, workspace_root, &[]).await
+        Self::spawn(
+            lspmux_bin,
+            server_bin,
+            workspace_root,
+            None,
+            &[],
+            None,
+            Observers::from([]),
+            &StderrTail::default(),
+        )
+        .await
+    }
+
+    /// Spawn the lspmux client, sending `initialization_options` (if any) in
+    /// the LSP initialize request. `lspmux_config` selects a non-default
+    /// lspmux config (e.g. that of a managed server); `env` is set on the
+    /// child; `observers` see every message exchanged with it; its stderr is
+    /// kept in `stderr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the child process cannot be spawned or the LSP
+    /// initialize handshake fails.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new_with_options(
+        lspmux_bin: &str,
+        server_bin: &str,
+        workspace_root: Option<&str>,
+        lspmux_config: Option<&Path>,
+        env: &[(&str, &str)],
+        initialization_options: Option<Value>,
+        observers: Observers,
+        stderr: &StderrTail,
+    ) -> Result<Self> {
+        Self::spawn(
+            lspmux_bin,
+            server_bin,
+            workspace_root,
+            lspmux_config,
+            env,
+            initialization_options,
+            observers,
+            stderr,
+        )
+        .await
     }
 
     /// Spawn the lspmux client with extra environment variables set on the child process.
@@ -159,60 +815,259 @@ impl LspClient {
         workspace_root: Option<&str>,
         env: &[(&str, &str)],
     ) -> Result<Self> {
-        let mut cmd = Command::new(lspmux_bin);
-        cmd.arg("client")
-            .arg("--server-path")
-            .arg(server_bin)
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            // Do not pipe stderr unless we actively drain it, otherwise verbose
-            // child logging can fill the pipe buffer and block the process.
-            .stderr(std::process::Stdio::inherit());
-        for &(key, val) in env {
-            cmd.env(key, val);
+        Self::spawn(
+            lspmux_bin,
+            server_bin,
+            workspace_root,
+            None,
+            env,
+            None,
+            Observers::from([]),
+            &StderrTail::default(),
+        )
+        .await
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn spawn(
+        lspmux_bin: &str,
+        server_bin: &str,
+        workspace_root: Option<&str>,
+        lspmux_config: Option<&Path>,
+        env: &[(&str, &str)],
+        initialization_options: Option<Value>,
+        observers: Observers,
+        stderr: &StderrTail,
+    ) -> Result<Self> {
+        let cmd = client_command(lspmux_bin, server_bin, lspmux_config, env);
+        Self::spawn_child(
+            cmd,
+            "lspmux client",
+            workspace_root,
+            initialization_options,
+            observers,
+            stderr,
+        )
+        .await
+    }
+
+    /// Spawn `server_bin` itself instead of `lspmux client`, so no other
+    /// client can share it, and perform the handshake. `env` is set on the
+    /// child and it runs in `workspace_root`; otherwise as
+    /// [`Self::new_with_options`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server cannot be spawned or the LSP
+    /// initialize handshake fails.
+    pub async fn spawn_unshared(
+        server_bin: &str,
+        workspace_root: Option<&str>,
+        env: &[(&str, &str)],
+        initialization_options: Option<Value>,
+        observers: Observers,
+        stderr: &StderrTail,
+    ) -> Result<Self> {
+        let mut cmd = Command::new(server_bin);
+        if let Some(root) = workspace_root {
+            cmd.current_dir(root);
         }
-        let mut child = cmd.spawn().context("failed to spawn lspmux client")?;
+        Self::spawn_child(
+            stdio_command(cmd, env),
+            SERVER_NAME,
+            workspace_root,
+            initialization_options,
+            observers,
+            stderr,
+        )
+        .await
+    }
+
+    /// Spawn `cmd`, named `name` in errors and logs, and speak LSP over its
+    /// stdio.
+    async fn spawn_child(
+        mut cmd: Command,
+        name: &'static str,
+        workspace_root: Option<&str>,
+        initialization_options: Option<Value>,
+        observers: Observers,
+        stderr: &StderrTail,
+    ) -> Result<Self> {
+        let mut child = cmd
+            .spawn()
+            .with_context(|| format!("failed to spawn {name}"))?;
 
         let stdin = child.stdin.take().context("no stdin on child")?;
         let stdout = child.stdout.take().context("no stdout on child")?;
+        if let Some(child_stderr) = child.stderr.take() {
+            stderr.capture(child_stderr, name);
+        }
+        Self::start(
+            Some(child),
+            stdout,
+            stdin,
+            workspace_root,
+            initialization_options,
+            None,
+            observers,
+        )
+        .await
+    }
+
+    /// Connect straight to the lspmux server at `addr` instead of spawning
+    /// `lspmux client`, ask it for `server_bin` with the environment
+    /// variables named in `pass_environment` and those of `server_env`, and
+    /// perform the handshake.
+    /// Otherwise as [`Self::new_with_options`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server does not accept the connection within
+    /// five seconds or the LSP initialize handshake fails.
+    pub async fn connect_lspmux(
+        addr: &ConnectAddr,
+        server_bin: &str,
+        workspace_root: Option<&str>,
+        pass_environment: &[String],
+        server_env: &[(String, String)],
+        initialization_options: Option<Value>,
+        observers: Observers,
+    ) -> Result<Self> {
+        let lsp_mux = lspmux_options(server_bin, workspace_root, pass_environment, server_env);
+        let (reader, writer) = open_lspmux(addr).await?;
+        Self::start(
+            None,
+            reader,
+            writer,
+            workspace_root,
+            initialization_options,
+            Some(lsp_mux),
+            observers,
+        )
+        .await
+    }
+
+    /// Speak LSP over `reader` and `writer` instead of a child process's
+    /// stdio, e.g. to an in-process server, and perform the handshake.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP initialize handshake fails.
+    pub async fn connect<R, W>(
+        reader: R,
+        writer: W,
+        workspace_root: Option<&str>,
+        initialization_options: Option<Value>,
+    ) -> Result<Self>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        Self::start(
+            None,
+            reader,
+            writer,
+            workspace_root,
+            initialization_options,
+            None,
+            Observers::from([]),
+        )
+        .await
+    }
 
+    /// Perform the handshake over `reader` and `writer`. `lsp_mux`, if
+    /// given, is sent as the `lspMux` initialization option, which the lspmux
+    /// server reads and strips before forwarding the rest.
+    async fn start<R, W>(
+        child: Option<Child>,
+        reader: R,
+        writer: W,
+        workspace_root: Option<&str>,
+        initialization_options: Option<Value>,
+        lsp_mux: Option<Value>,
+        observers: Observers,
+    ) -> Result<Self>
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+        W: AsyncWrite + Unpin + Send + 'static,
+    {
+        let traffic = Arc::new(TrafficTotals::default());
+        let (reader, writer) = tap(reader, writer, observers, &traffic);
         let pending: PendingMap = Arc::new(Mutex::new(HashMap::new()));
-        let child_stdin = Arc::new(Mutex::new(stdin));
         let alive = Arc::new(AtomicBool::new(true));
+        let outbox = spawn_writer(writer, Arc::clone(&alive));
         let readiness = Arc::new(tokio::sync::Mutex::new(ReadinessState::default()));
+        let generation = Arc::new(AtomicU64::new(0));
+        let (progress_tx, _) = broadcast::channel(PROGRESS_CHANNEL_CAPACITY);
+        let active_progress = ActiveProgress::default();
+        let (diagnostics_tx, _) = broadcast::channel(DIAGNOSTICS_CHANNEL_CAPACITY);
+        tokio::spawn(track_progress(
+            progress_tx.subscribe(),
+            Arc::clone(&active_progress),
+        ));
+        let base_settings = initialization_options.clone().unwrap_or_else(|| json!({}));
+        let settings = watch::Sender::new(base_settings.clone());
 
         // Spawn reader task
         let pending_clone = Arc::clone(&pending);
         let alive_clone = Arc::clone(&alive);
         let readiness_clone = Arc::clone(&readiness);
+        let generation_clone = Arc::clone(&generation);
+        let outbox_clone = outbox.clone();
+        let settings_rx = settings.subscribe();
+        let applied_edits = EditCapture::default();
+        let edits_clone = Arc::clone(&applied_edits);
+        let notifications = spawn_dispatcher(progress_tx.clone(), diagnostics_tx.clone());
         tokio::spawn(async move {
             let pending_for_cleanup = Arc::clone(&pending_clone);
-            if let Err(e) = reader_loop(stdout, pending_clone, readiness_clone).await {
+            if let Err(e) = reader_loop(
+                reader,
+                pending_clone,
+                readiness_clone,
+                generation_clone,
+                outbox_clone,
+                &notifications,
+                settings_rx,
+                &edits_clone,
+            )
+            .await
+            {
                 tracing::error!("LSP reader loop error: {e}");
             }
+            notifications.close();
             // Signal that the child process is no longer responsive.
             alive_clone.store(false, Ordering::Release);
-            // Drain pending requests so callers get immediate errors
-            // (dropping senders causes RecvError on the corresponding receivers).
-            let mut map = pending_for_cleanup.lock().await;
-            let count = map.len();
-            map.clear();
-            drop(map);
+            counter!("lspmux_cc_lsp_connections_lost_total").increment(1);
+            let count = drain_pending(&pending_for_cleanup);
             if count > 0 {
                 tracing::warn!("Reader loop exited with {count} pending request(s)");
             }
         });
 
         let client = Self {
-            child_stdin,
+            outbox,
             next_id: AtomicI64::new(1),
             pending,
             opened_files: Mutex::new(HashMap::new()),
-            child: Arc::new(Mutex::new(child)),
+            sync_gates: Mutex::new(HashMap::new()),
+            child: Arc::new(tokio::sync::Mutex::new(child)),
             alive,
             workspace_root: tokio::sync::Mutex::new(None),
-            server_version: tokio::sync::Mutex::new(None),
+            server_info: tokio::sync::Mutex::new(None),
             readiness,
+            progress_tx,
+            active_progress,
+            diagnostics_tx,
+            generation,
+            capabilities: watch::Sender::new(lsp_types::ServerCapabilities::default()),
+            request_timeouts: RequestTimeouts::new(DEFAULT_REQUEST_TIMEOUT),
+            file_size_limit: FileSizeLimit::default(),
+            document_budget: DEFAULT_DOCUMENT_BUDGET,
+            base_settings,
+            settings,
+            traffic,
+            applied_edits,
+            command_lock: tokio::sync::Mutex::new(()),
         };
 
         // Initialize handshake
@@ -224,12 +1079,8 @@ impl LspClient {
         #[allow(deprecated)] // root_uri deprecated but still needed
         let init_params = InitializeParams {
             root_uri,
-            capabilities: ClientCapabilities {
-                experimental: Some(json!({
-                    "serverStatusNotification": true,
-                })),
-                ..ClientCapabilities::default()
-            },
+            initialization_options: with_lspmux_options(initialization_options, lsp_mux),
+            capabilities: client_capabilities(),
             ..InitializeParams::default()
         };
 
@@ -240,12 +1091,12 @@ impl LspClient {
 
         // Store server metadata for rust_server_status tool
         *client.workspace_root.lock().await = workspace_root.map(String::from);
-        *client.server_version.lock().await = init_result.server_info.and_then(|info| info.version);
+        *client.server_info.lock().await = init_result.server_info;
+        client.set_capabilities(init_result.capabilities);
 
         // Send initialized notification
         client
             .notify("initialized", &InitializedParams {})
-            .await
             .context("LSP initialized notification failed")?;
 
         tracing::info!("LSP client initialized");
@@ -272,65 +1123,80 @@ impl LspClient {
         });
 
         let (tx, rx) = oneshot::channel();
-        self.pending.lock().await.insert(id, tx);
+        lock(&self.pending).insert(id, tx);
+        tracing::debug!(event = "lsp_request", id, method = R::METHOD);
 
-        if let Err(e) = self.send_message(&msg).await {
-            self.pending.lock().await.remove(&id);
+        if let Err(e) = self.send_message(&msg) {
+            lock(&self.pending).remove(&id);
+            record_request(id, R::METHOD, "closed", None);
             return Err(e);
         }
+        let started = std::time::Instant::now();
+
+        // From here on, dropping this future (the MCP call was cancelled) or
+        // timing out tells the server to stop working on the request.
+        let mut in_flight = InFlightRequest {
+            id,
+            pending: Arc::clone(&self.pending),
+            outbox: self.outbox.clone(),
+            alive: Arc::clone(&self.alive),
+            finished: false,
+        };
 
-        let response = match timeout(LSP_REQUEST_TIMEOUT, rx).await {
+        let request_timeout = self.request_timeouts.for_method(R::METHOD);
+        let response = match timeout(request_timeout, rx).await {
             Ok(Ok(response)) => response,
             Ok(Err(_)) => {
-                self.pending.lock().await.remove(&id);
+                in_flight.finished = true;
+                record_request(id, R::METHOD, "closed", Some(started));
                 bail!("LSP response channel closed (server may have crashed)");
             }
             Err(_) => {
-                self.pending.lock().await.remove(&id);
-                bail!(
-                    "LSP request timed out after {}s",
-                    LSP_REQUEST_TIMEOUT.as_secs()
-                );
+                record_request(id, R::METHOD, "timeout", Some(started));
+                return Err(RequestTimedOut {
+                    method: R::METHOD,
+                    after: request_timeout,
+                }
+                .into());
             }
         };
+        in_flight.finished = true;
 
         // Check for error
         if let Some(error) = response.get("error") {
+            record_request(id, R::METHOD, "error", Some(started));
             bail!("LSP error: {error}");
         }
+        record_request(id, R::METHOD, "ok", Some(started));
 
-        let result = response.get("result").cloned().unwrap_or(Value::Null);
+        let mut result = response.get("result").cloned().unwrap_or(Value::Null);
+        if [CodeActionRequest::METHOD, CodeActionResolveRequest::METHOD].contains(&R::METHOD) {
+            snippet::mark_snippet_edits(&mut result);
+        }
 
         serde_json::from_value(result).context("failed to deserialize LSP response")
     }
 
-    /// Send an LSP notification (no response expected).
-    async fn notify<P: Serialize + Sync>(&self, method: &str, params: &P) -> Result<()> {
+    /// Queue an LSP notification (no response expected).
+    fn notify<P: Serialize>(&self, method: &str, params: &P) -> Result<()> {
         let msg = serde_json::json!({
             "jsonrpc": "2.0",
             "method": method,
             "params": serde_json::to_value(params)?,
         });
-        self.send_message(&msg).await
+        self.send_message(&msg)
     }
 
-    /// Send a raw JSON-RPC message with `Content-Length` framing.
+    /// Queue a raw JSON-RPC message for the writer task. Messages are written
+    /// in the order they are queued.
     ///
     /// Returns an error immediately if the child process is no longer alive.
-    async fn send_message(&self, msg: &Value) -> Result<()> {
+    fn send_message(&self, msg: &Value) -> Result<()> {
         if !self.alive.load(Ordering::Acquire) {
             bail!("LSP server is no longer running (child process exited)");
         }
 
-        let body = serde_json::to_string(msg)?;
-        let header = format!("Content-Length: {}\r\n\r\n", body.len());
-
-        let mut stdin = self.child_stdin.lock().await;
-        stdin.write_all(header.as_bytes()).await?;
-        stdin.write_all(body.as_bytes()).await?;
-        stdin.flush().await?;
-        drop(stdin);
-        Ok(())
+        send_frame(&self.outbox, msg)
     }
 
     /// Send a `textDocument/hover` request.
@@ -370,7 +1236,78 @@ impl LspClient {
         self.request::<GotoDefinition>(params).await
     }
 
-    /// Send a `textDocument/references` request.
+    /// Send a `textDocument/implementation` request: the impl blocks of the
+    /// trait or type at the position.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn implementations(
+        &self,
+        file: &str,
+        line: u32,
+        character: u32,
+    ) -> Result<Option<lsp_types::GotoDefinitionResponse>> {
+        let params = lsp_types::GotoDefinitionParams {
+            text_document_position_params: text_doc_position(file, line, character)?,
+            work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            partial_result_params: lsp_types::PartialResultParams::default(),
+        };
+        self.request::<GotoImplementation>(params).await
+    }
+
+    /// Send a `textDocument/completion` request for the position.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn completion(
+        &self,
+        file: &str,
+        line: u32,
+        character: u32,
+    ) -> Result<Option<lsp_types::CompletionResponse>> {
+        let params = lsp_types::CompletionParams {
+            text_document_position: text_doc_position(file, line, character)?,
+            work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            partial_result_params: lsp_types::PartialResultParams::default(),
+            context: Some(lsp_types::CompletionContext {
+                trigger_kind: lsp_types::CompletionTriggerKind::INVOKED,
+                trigger_character: None,
+            }),
+        };
+        self.request::<Completion>(params).await
+    }
+
+    /// Whether the server leaves the documentation and extra edits of
+    /// completion items for `completionItem/resolve`.
+    pub fn resolves_completions(&self) -> bool {
+        self.capabilities()
+            .completion_provider
+            .is_some_and(|provider| provider.resolve_provider == Some(true))
+    }
+
+    /// Fill in the documentation and `additionalTextEdits` of a completion
+    /// item, such as the `use` declaration an auto-import completion adds,
+    /// via `completionItem/resolve`. Like code actions, items are listed
+    /// without them and only the one a caller picks is resolved. Items of a
+    /// server without `resolveProvider` are returned as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn resolve_completion(
+        &self,
+        item: lsp_types::CompletionItem,
+    ) -> Result<lsp_types::CompletionItem> {
+        if !self.resolves_completions() {
+            return Ok(item);
+        }
+        self.request::<ResolveCompletionItem>(item).await
+    }
+
+    /// Send a `textDocument/references` request. With `include_declaration`,
+    /// the symbol's own declaration is listed among the references.
     ///
     /// # Errors
     ///
@@ -380,71 +1317,371 @@ impl LspClient {
         file: &str,
         line: u32,
         character: u32,
+        include_declaration: bool,
     ) -> Result<Option<Vec<lsp_types::Location>>> {
         let params = lsp_types::ReferenceParams {
             text_document_position: text_doc_position(file, line, character)?,
             work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
             partial_result_params: lsp_types::PartialResultParams::default(),
             context: lsp_types::ReferenceContext {
-                include_declaration: true,
+                include_declaration,
             },
         };
         self.request::<References>(params).await
     }
 
-    /// Ensure a file is open in the LSP server with its current disk content.
-    ///
-    /// Sends `textDocument/didOpen` on first access, or `textDocument/didChange`
-    /// with updated content on subsequent accesses. This is required by the LSP
-    /// protocol before the server will provide diagnostics, hover, etc.
+    /// Send a `textDocument/rename` request renaming the symbol at the
+    /// position to `new_name`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails, e.g. when the position is
+    /// not on a renameable symbol.
+    pub async fn rename(
+        &self,
+        file: &str,
+        line: u32,
+        character: u32,
+        new_name: &str,
+    ) -> Result<Option<lsp_types::WorkspaceEdit>> {
+        let params = lsp_types::RenameParams {
+            text_document_position: text_doc_position(file, line, character)?,
+            new_name: new_name.to_string(),
+            work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+        };
+        self.request::<Rename>(params).await
+    }
+
+    /// The edit that keeps the workspace compiling when `from` is moved to
+    /// `to`, such as fixing `mod` declarations. Either may be a directory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a path is not absolute or the LSP request fails.
+    pub async fn will_rename_file(
+        &self,
+        from: &str,
+        to: &str,
+    ) -> Result<Option<lsp_types::WorkspaceEdit>> {
+        self.request::<WillRenameFiles>(file_rename_params(from, to)?)
+            .await
+    }
+
+    /// Tell the server `from` was moved to `to`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a path is not absolute or the notification fails
+    /// to send.
+    pub fn did_rename_file(&self, from: &str, to: &str) -> Result<()> {
+        self.notify("workspace/didRenameFiles", &file_rename_params(from, to)?)
+    }
+
+    /// The symbols defined in `file`, nested by containing item.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn document_symbols(
+        &self,
+        file: &str,
+    ) -> Result<Option<lsp_types::DocumentSymbolResponse>> {
+        let params = lsp_types::DocumentSymbolParams {
+            text_document: lsp_types::TextDocumentIdentifier {
+                uri: file_uri(file)?,
+            },
+            work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            partial_result_params: lsp_types::PartialResultParams::default(),
+        };
+        self.request::<DocumentSymbolRequest>(params).await
+    }
+
+    /// The links in `file`, such as doc links in comments; their targets
+    /// may be left for [`Self::resolve_document_link`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn document_links(&self, file: &str) -> Result<Option<Vec<lsp_types::DocumentLink>>> {
+        let params = lsp_types::DocumentLinkParams {
+            text_document: lsp_types::TextDocumentIdentifier {
+                uri: file_uri(file)?,
+            },
+            work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            partial_result_params: lsp_types::PartialResultParams::default(),
+        };
+        self.request::<DocumentLinkRequest>(params).await
+    }
+
+    /// Fill in the target of a link from [`Self::document_links`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn resolve_document_link(
+        &self,
+        link: lsp_types::DocumentLink,
+    ) -> Result<lsp_types::DocumentLink> {
+        self.request::<DocumentLinkResolve>(link).await
+    }
+
+    /// rust-analyzer's status dump for the workspace.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn analyzer_status(&self) -> Result<String> {
+        self.request::<AnalyzerStatus>(AnalyzerStatusParams::default())
+            .await
+    }
+
+    /// Have rust-analyzer reload the workspace after its manifests changed,
+    /// moving to a new generation so responses cached under the old project
+    /// model are dropped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn reload_workspace(&self) -> Result<()> {
+        self.request::<ReloadWorkspace>(()).await?;
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Start rust-analyzer's `cargo check` (its "flycheck") for the
+    /// workspace containing `file`, or for every workspace.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification fails to send.
+    pub fn run_flycheck(&self, file: Option<&str>) -> Result<()> {
+        let text_document = file
+            .map(|file| file_uri(file).map(|uri| lsp_types::TextDocumentIdentifier { uri }))
+            .transpose()?;
+        self.notify(
+            "rust-analyzer/runFlycheck",
+            &RunFlycheckParams { text_document },
+        )
+    }
+
+    /// Stop every running flycheck.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification fails to send.
+    pub fn cancel_flycheck(&self) -> Result<()> {
+        self.notify("rust-analyzer/cancelFlycheck", &())
+    }
+
+    /// Drop the diagnostics the flychecks have published.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification fails to send.
+    pub fn clear_flycheck(&self) -> Result<()> {
+        self.notify("rust-analyzer/clearFlycheck", &())
+    }
+
+    /// The runnables rust-analyzer finds in `file`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn runnables(&self, file: &str) -> Result<Vec<Runnable>> {
+        let params = RunnablesParams {
+            text_document: lsp_types::TextDocumentIdentifier {
+                uri: file_uri(file)?,
+            },
+            position: None,
+        };
+        self.request::<Runnables>(params).await
+    }
+
+    /// Send a `textDocument/codeAction` request for `range`, passing the
+    /// `diagnostics` it concerns and restricted to the `only` action kinds.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn code_actions(
+        &self,
+        file: &str,
+        range: lsp_types::Range,
+        diagnostics: Vec<lsp_types::Diagnostic>,
+        only: Option<Vec<lsp_types::CodeActionKind>>,
+    ) -> Result<Option<lsp_types::CodeActionResponse>> {
+        let params = lsp_types::CodeActionParams {
+            text_document: lsp_types::TextDocumentIdentifier {
+                uri: file_uri(file)?,
+            },
+            range,
+            context: lsp_types::CodeActionContext {
+                diagnostics,
+                only,
+                trigger_kind: Some(lsp_types::CodeActionTriggerKind::INVOKED),
+            },
+            work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+            partial_result_params: lsp_types::PartialResultParams::default(),
+        };
+        self.request::<CodeActionRequest>(params).await
+    }
+
+    /// Whether the server leaves code action edits out of
+    /// `textDocument/codeAction` and computes them on `codeAction/resolve`.
+    pub fn resolves_code_actions(&self) -> bool {
+        matches!(
+            self.capabilities().code_action_provider,
+            Some(lsp_types::CodeActionProviderCapability::Options(
+                lsp_types::CodeActionOptions {
+                    resolve_provider: Some(true),
+                    ..
+                }
+            ))
+        )
+    }
+
+    /// Fill in the `edit` of a code action the server computes lazily, via
+    /// `codeAction/resolve`. Since the client advertises `resolveSupport`
+    /// for edits, listing actions computes none, and only the action a
+    /// caller picks is resolved. Actions that already carry an edit, and
+    /// those of a server without `resolveProvider`, are returned as is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn resolve_code_action(
+        &self,
+        action: lsp_types::CodeAction,
+    ) -> Result<lsp_types::CodeAction> {
+        if action.edit.is_some() || action.data.is_none() || !self.resolves_code_actions() {
+            return Ok(action);
+        }
+        self.request::<CodeActionResolveRequest>(action).await
+    }
+
+    /// The commands the server runs with `workspace/executeCommand`.
+    pub fn server_commands(&self) -> Vec<String> {
+        self.capabilities()
+            .execute_command_provider
+            .map(|provider| provider.commands)
+            .unwrap_or_default()
+    }
+
+    /// Run `command` on the server with `workspace/executeCommand`. Returns
+    /// its result and the edits it asked for with `workspace/applyEdit`
+    /// meanwhile, which were acknowledged but are left to the caller to
+    /// apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the LSP request fails.
+    pub async fn execute_command(
+        &self,
+        command: &str,
+        arguments: Vec<Value>,
+    ) -> Result<(Option<Value>, Vec<lsp_types::WorkspaceEdit>)> {
+        let _running = self.command_lock.lock().await;
+        *lock(&self.applied_edits) = Some(Vec::new());
+        let capturing = Capturing(&self.applied_edits);
+        let params = lsp_types::ExecuteCommandParams {
+            command: command.to_string(),
+            arguments,
+            work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+        };
+        let result = self.request::<ExecuteCommand>(params).await?;
+        let edits = lock(capturing.0).take().unwrap_or_default();
+        Ok((result, edits))
+    }
+
+    /// Ensure a file is open in the LSP server with its current disk content.
+    ///
+    /// Sends `textDocument/didOpen` on first access, or `textDocument/didChange`
+    /// with updated content on subsequent accesses. This is required by the LSP
+    /// protocol before the server will provide diagnostics, hover, etc.
+    /// Returns the hash of the content the server now has.
+    ///
+    /// Calls are coalesced per file: a caller that waited for another's
+    /// synchronization of the file, begun after it arrived, shares its result.
+    /// A file whose modification time and size are unchanged since its last
+    /// read is not re-read.
     ///
     /// # Errors
     ///
     /// Returns an error if the file cannot be read from disk or the notification
     /// fails to send.
-    pub async fn ensure_file_open(&self, file_path: &str) -> Result<()> {
-        let uri = file_uri(file_path)?;
-        let content = tokio::fs::read_to_string(file_path)
+    pub async fn ensure_file_open(&self, file_path: &str) -> Result<u64> {
+        let arrived = std::time::Instant::now();
+        let gate = SyncGate::new(&self.sync_gates, file_path);
+        let _turn = gate.lock().await;
+        // Another caller may have synchronized the file while we waited.
+        if let Some(content_hash) = self.document_synced_since(file_path, arrived) {
+            counter!("lspmux_cc_document_syncs_total", "outcome" => "coalesced").increment(1);
+            return Ok(content_hash);
+        }
+
+        // Stat before reading: if the file changes in between, the stored
+        // stamp is older than the content and the next call re-reads it.
+        let checked_at = std::time::Instant::now();
+        let metadata = tokio::fs::metadata(file_path)
             .await
             .with_context(|| format!("failed to read {file_path}"))?;
+        let stamp = FileStamp::of(&metadata);
+        if let Some(content_hash) = self.unmodified_document_hash(file_path, stamp, checked_at) {
+            counter!("lspmux_cc_document_syncs_total", "outcome" => "unmodified").increment(1);
+            return Ok(content_hash);
+        }
 
-        let content_hash = {
-            let mut hasher = std::collections::hash_map::DefaultHasher::new();
-            content.hash(&mut hasher);
-            hasher.finish()
-        };
+        let uri = file_uri(file_path)?;
+        let content = self.read_document(file_path, metadata.len()).await?;
+        // A write in the same modification-time tick as the read would leave
+        // the stamp unchanged, so such a stamp is not kept (as git does).
+        let read_at = std::time::SystemTime::now();
+        let stamp = stamp.filter(|stamp| !stamp.is_racy(read_at));
+
+        let content_hash = content_hash(&content);
 
         let language_id = detect_language_id(file_path);
 
-        let mut opened = self.opened_files.lock().await;
-        if let Some((version, prev_hash)) = opened.get_mut(file_path) {
-            if *prev_hash == content_hash {
+        // Queue the notification before releasing the lock, so concurrent
+        // calls on one file reach the server in version order. The document
+        // is only recorded once the notification is queued: otherwise a
+        // failed send would leave the server without content we think it has.
+        let mut opened = lock(&self.opened_files);
+        let now = std::time::Instant::now();
+        let len = content.len() as u64;
+        if let Some(document) = opened.get_mut(file_path) {
+            if document.content_hash == content_hash {
                 // File unchanged since last notification — skip didChange.
-                return Ok(());
+                document.synced_at = checked_at;
+                document.last_used = now;
+                document.stamp = stamp;
+                drop(opened);
+                counter!("lspmux_cc_document_syncs_total", "outcome" => "unchanged").increment(1);
+                return Ok(content_hash);
             }
             // Content changed — send didChange with updated content.
-            *version += 1;
-            *prev_hash = content_hash;
-            let v = *version;
-            drop(opened);
-
+            let version = document.version + 1;
             self.notify(
                 "textDocument/didChange",
                 &DidChangeTextDocumentParams {
-                    text_document: VersionedTextDocumentIdentifier { uri, version: v },
+                    text_document: VersionedTextDocumentIdentifier { uri, version },
                     content_changes: vec![TextDocumentContentChangeEvent {
                         range: None,
                         range_length: None,
                         text: content,
                     }],
                 },
-            )
-            .await
+            )?;
+            document.version = version;
+            document.content_hash = content_hash;
+            document.len = len;
+            document.synced_at = checked_at;
+            document.last_used = now;
+            document.stamp = stamp;
+            self.generation.fetch_add(1, Ordering::AcqRel);
+            counter!("lspmux_cc_document_syncs_total", "outcome" => "changed").increment(1);
         } else {
             // First access — send didOpen.
-            opened.insert(file_path.to_string(), (0, content_hash));
-            drop(opened);
-
             self.notify(
                 "textDocument/didOpen",
                 &DidOpenTextDocumentParams {
@@ -455,9 +1692,236 @@ impl LspClient {
                         text: content,
                     },
                 },
-            )
+            )?;
+            opened.insert(
+                file_path.to_string(),
+                OpenDocument {
+                    version: 0,
+                    content_hash,
+                    stamp,
+                    len,
+                    synced_at: checked_at,
+                    last_used: now,
+                },
+            );
+            counter!("lspmux_cc_document_syncs_total", "outcome" => "opened").increment(1);
+        }
+        self.evict_over_budget(&mut opened, file_path)?;
+        drop(opened);
+        Ok(content_hash)
+    }
+
+    /// The content of `file_path` (`len` bytes on disk) to send to the
+    /// server, subject to the file size limit.
+    async fn read_document(&self, file_path: &str, len: u64) -> Result<String> {
+        let limit = self.file_size_limit;
+        let content = if len <= limit.max_bytes {
+            tokio::fs::read_to_string(file_path).await
+        } else if limit.mode == LargeFileMode::Truncate {
+            tracing::warn!(
+                "{file_path} is {len} bytes; sending only the first {} to the server",
+                limit.max_bytes
+            );
+            read_prefix(file_path, limit.max_bytes).await
+        } else {
+            bail!(
+                "{file_path} is {len} bytes, over the {}-byte limit for files sent to the \
+                 language server; raise --max-file-size or pass --large-files truncate to \
+                 query a truncated view",
+                limit.max_bytes
+            );
+        };
+        content.with_context(|| format!("failed to read {file_path}"))
+    }
+
+    /// The hash of the content the server holds for `file_path`, if it is open.
+    pub fn synced_content_hash(&self, file_path: &str) -> Option<u64> {
+        lock(&self.opened_files)
+            .get(file_path)
+            .map(|document| document.content_hash)
+    }
+
+    /// The version of `file_path` last sent to the server, if it is open.
+    pub fn document_version(&self, file_path: &str) -> Option<i32> {
+        lock(&self.opened_files)
+            .get(file_path)
+            .map(|document| document.version)
+    }
+
+    /// Forget what is known about the on-disk state of `file_path`, so the
+    /// next [`Self::ensure_file_open`] re-reads it. Call after writing the file:
+    /// its new modification time may not differ from the old one.
+    pub fn invalidate_document(&self, file_path: &str) {
+        if let Some(document) = lock(&self.opened_files).get_mut(file_path) {
+            document.stamp = None;
+        }
+    }
+
+    /// Close `file_path` in the server if it is open, e.g. after it was
+    /// deleted or renamed away.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification fails to send.
+    pub fn close_document(&self, file_path: &str) -> Result<()> {
+        let mut opened = lock(&self.opened_files);
+        if !opened.contains_key(file_path) {
+            return Ok(());
+        }
+        self.notify(
+            "textDocument/didClose",
+            &DidCloseTextDocumentParams {
+                text_document: lsp_types::TextDocumentIdentifier {
+                    uri: file_uri(file_path)?,
+                },
+            },
+        )?;
+        opened.remove(file_path);
+        drop(opened);
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        Ok(())
+    }
+
+    /// Synchronize `file_path` and tell the server it was saved, which makes
+    /// rust-analyzer start `cargo check` when check-on-save is enabled.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or the notification fails
+    /// to send.
+    pub async fn save_document(&self, file_path: &str) -> Result<()> {
+        // The file was likely just written; do not trust its stamp.
+        self.invalidate_document(file_path);
+        self.ensure_file_open(file_path).await?;
+        self.notify(
+            "textDocument/didSave",
+            &lsp_types::DidSaveTextDocumentParams {
+                text_document: lsp_types::TextDocumentIdentifier {
+                    uri: file_uri(file_path)?,
+                },
+                text: None,
+            },
+        )
+    }
+
+    /// Show the server `content` for `file_path` without writing the file, so
+    /// later requests are answered against a pending edit. The preview lasts
+    /// until the file changes on disk or [`Self::invalidate_document`] is
+    /// called; the next [`Self::ensure_file_open`] then restores the on-disk
+    /// content.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or the notification fails
+    /// to send.
+    pub async fn preview_document(&self, file_path: &str, content: String) -> Result<()> {
+        self.ensure_file_open(file_path).await?;
+        let metadata = tokio::fs::metadata(file_path)
             .await
+            .with_context(|| format!("failed to read {file_path}"))?;
+        let uri = file_uri(file_path)?;
+
+        let mut opened = lock(&self.opened_files);
+        let Some(document) = opened.get_mut(file_path) else {
+            bail!("{file_path} was closed before it could be previewed");
+        };
+        let content_hash = content_hash(&content);
+        if document.content_hash == content_hash {
+            return Ok(());
         }
+        document.version += 1;
+        document.content_hash = content_hash;
+        document.len = content.len() as u64;
+        // The on-disk stamp keeps the preview in place until the file changes.
+        document.stamp = FileStamp::of(&metadata);
+        let version = document.version;
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        self.notify(
+            "textDocument/didChange",
+            &DidChangeTextDocumentParams {
+                text_document: VersionedTextDocumentIdentifier { uri, version },
+                content_changes: vec![TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: content,
+                }],
+            },
+        )?;
+        drop(opened);
+        Ok(())
+    }
+
+    /// The content hash of `file_path` if a synchronization that checked the
+    /// file no earlier than `since` has finished.
+    fn document_synced_since(&self, file_path: &str, since: std::time::Instant) -> Option<u64> {
+        lock(&self.opened_files)
+            .get_mut(file_path)
+            .filter(|document| document.synced_at >= since)
+            .map(|document| {
+                document.last_used = std::time::Instant::now();
+                document.content_hash
+            })
+    }
+
+    /// Close the least recently used documents other than `keep` until the
+    /// open documents fit in the budget. rust-analyzer then reads those files
+    /// from disk again and can drop their in-memory overlays.
+    fn evict_over_budget(
+        &self,
+        opened: &mut HashMap<String, OpenDocument>,
+        keep: &str,
+    ) -> Result<()> {
+        let mut total: u64 = opened.values().map(|document| document.len).sum();
+        while total > self.document_budget {
+            let Some(oldest) = opened
+                .iter()
+                .filter(|(path, _)| path.as_str() != keep)
+                .min_by_key(|(_, document)| document.last_used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            tracing::debug!("closing {oldest} to stay within the open document budget");
+            self.notify(
+                "textDocument/didClose",
+                &DidCloseTextDocumentParams {
+                    text_document: lsp_types::TextDocumentIdentifier {
+                        uri: file_uri(&oldest)?,
+                    },
+                },
+            )?;
+            if let Some(document) = opened.remove(&oldest) {
+                total -= document.len;
+            }
+            self.generation.fetch_add(1, Ordering::AcqRel);
+            counter!("lspmux_cc_documents_evicted_total").increment(1);
+        }
+        Ok(())
+    }
+
+    /// The content hash of `file_path` if its on-disk `stamp`, taken at
+    /// `checked_at`, shows it has not changed since it was last read.
+    fn unmodified_document_hash(
+        &self,
+        file_path: &str,
+        stamp: Option<FileStamp>,
+        checked_at: std::time::Instant,
+    ) -> Option<u64> {
+        let stamp = stamp?;
+        lock(&self.opened_files)
+            .get_mut(file_path)
+            .filter(|document| document.stamp == Some(stamp))
+            .map(|document| {
+                document.synced_at = checked_at;
+                document.last_used = std::time::Instant::now();
+                document.content_hash
+            })
+    }
+
+    /// The current workspace generation; see the `generation` field. Results
+    /// computed under one generation may be stale under the next.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
     }
 
     /// Whether the LSP child process is still alive.
@@ -472,7 +1936,20 @@ impl LspClient {
 
     /// The backend server version from the initialize response.
     pub async fn server_version(&self) -> Option<String> {
-        self.server_version.lock().await.clone()
+        self.server_info
+            .lock()
+            .await
+            .as_ref()
+            .and_then(|info| info.version.clone())
+    }
+
+    /// What the server said about itself in the most recent initialize
+    /// handshake: its capabilities, name, and version.
+    pub async fn initialize_result(&self) -> lsp_types::InitializeResult {
+        lsp_types::InitializeResult {
+            capabilities: self.capabilities(),
+            server_info: self.server_info.lock().await.clone(),
+        }
     }
 
     /// The latest rust-analyzer readiness snapshot.
@@ -480,6 +1957,140 @@ impl LspClient {
         self.readiness.lock().await.clone()
     }
 
+    /// Layer project-specific rust-analyzer settings over the base settings.
+    ///
+    /// When the effective settings change, `workspace/didChangeConfiguration`
+    /// is sent and rust-analyzer pulls the new values through
+    /// `workspace/configuration`. Returns whether anything changed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the notification cannot be sent.
+    pub fn apply_project_settings(&self, overlay: Option<&Value>) -> Result<bool> {
+        let mut merged = self.base_settings.clone();
+        if let Some(overlay) = overlay {
+            crate::config::merge_settings(&mut merged, overlay);
+        }
+        let changed = self.settings.send_if_modified(|current| {
+            if *current == merged {
+                return false;
+            }
+            current.clone_from(&merged);
+            true
+        });
+        if changed {
+            self.notify(
+                "workspace/didChangeConfiguration",
+                &json!({ "settings": merged }),
+            )?;
+        }
+        Ok(changed)
+    }
+
+    /// Close the least recently used documents once open documents total
+    /// more than `document_budget` bytes.
+    #[must_use]
+    pub const fn with_document_budget(mut self, document_budget: u64) -> Self {
+        self.document_budget = document_budget;
+        self
+    }
+
+    /// Apply `file_size_limit` to files synchronized from now on.
+    #[must_use]
+    pub const fn with_file_size_limit(mut self, file_size_limit: FileSizeLimit) -> Self {
+        self.file_size_limit = file_size_limit;
+        self
+    }
+
+    /// Use `request_timeouts` instead of the default timeouts for
+    /// subsequent requests.
+    #[must_use]
+    pub fn with_request_timeouts(mut self, request_timeouts: RequestTimeouts) -> Self {
+        self.request_timeouts = request_timeouts;
+        self
+    }
+
+    /// The server capabilities from the most recent initialize handshake.
+    pub fn capabilities(&self) -> lsp_types::ServerCapabilities {
+        self.capabilities.borrow().clone()
+    }
+
+    /// Watch for changes to the negotiated server capabilities.
+    pub fn watch_capabilities(&self) -> watch::Receiver<lsp_types::ServerCapabilities> {
+        self.capabilities.subscribe()
+    }
+
+    /// Replace the negotiated capabilities, notifying watchers if they changed.
+    fn set_capabilities(&self, capabilities: lsp_types::ServerCapabilities) {
+        self.capabilities.send_if_modified(|current| {
+            let changed = *current != capabilities;
+            *current = capabilities;
+            changed
+        });
+    }
+
+    /// Work-done progress currently underway (e.g. indexing), ordered by token.
+    pub async fn active_progress(&self) -> Vec<ProgressEvent> {
+        let mut active = self
+            .active_progress
+            .lock()
+            .await
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        active.sort_by(|a, b| a.token.cmp(&b.token));
+        active
+    }
+
+    /// Number of documents synchronized with the server.
+    pub fn open_document_count(&self) -> usize {
+        lock(&self.opened_files).len()
+    }
+
+    /// The documents synchronized with the server, sorted by path.
+    pub fn tracked_documents(&self) -> Vec<TrackedDocument> {
+        let mut documents = lock(&self.opened_files)
+            .iter()
+            .map(|(file_path, document)| TrackedDocument {
+                file_path: file_path.clone(),
+                version: document.version,
+                content_hash: document.content_hash,
+                len: document.len,
+                since_sync: document.synced_at.elapsed(),
+                since_use: document.last_used.elapsed(),
+                stamp: document.stamp,
+            })
+            .collect::<Vec<_>>();
+        documents.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+        documents
+    }
+
+    /// Number of requests awaiting a response.
+    pub fn pending_request_count(&self) -> usize {
+        lock(&self.pending).len()
+    }
+
+    /// Bytes sent to the server so far, framing included.
+    pub fn bytes_sent(&self) -> u64 {
+        self.traffic.sent()
+    }
+
+    /// Bytes received from the server so far, framing included.
+    pub fn bytes_received(&self) -> u64 {
+        self.traffic.received()
+    }
+
+    /// Subscribe to `$/progress` notifications received after this call.
+    pub fn subscribe_progress(&self) -> broadcast::Receiver<ProgressEvent> {
+        self.progress_tx.subscribe()
+    }
+
+    /// Subscribe to `textDocument/publishDiagnostics` notifications received
+    /// after this call.
+    pub fn subscribe_diagnostics(&self) -> broadcast::Receiver<PublishedDiagnostics> {
+        self.diagnostics_tx.subscribe()
+    }
+
     /// Search for symbols matching `query` across the workspace.
     ///
     /// Returns `None` if the server returned no results, or the response
@@ -511,12 +2122,14 @@ impl LspClient {
         }
 
         // Send exit notification (best-effort)
-        if let Err(e) = self.notify("exit", &()).await {
+        if let Err(e) = self.notify("exit", &()) {
             tracing::warn!("LSP exit notification failed: {e}");
         }
 
         // Give the child a moment to exit, then kill it
-        let mut child = self.child.lock().await;
+        let Some(mut child) = self.child.lock().await.take() else {
+            return;
+        };
         match timeout(Duration::from_secs(5), child.wait()).await {
             Ok(Ok(status)) => {
                 tracing::info!("LSP child exited with {status}");
@@ -534,6 +2147,26 @@ impl LspClient {
     }
 }
 
+/// The first `max_bytes` of a file, cut after its last complete line so the
+/// server never sees half a token (or half a UTF-8 character).
+async fn read_prefix(file_path: &str, max_bytes: u64) -> std::io::Result<String> {
+    let file = tokio::fs::File::open(file_path).await?;
+    let mut bytes = Vec::new();
+    file.take(max_bytes).read_to_end(&mut bytes).await?;
+    if let Some(end) = bytes.iter().rposition(|&byte| byte == b'\n') {
+        bytes.truncate(end + 1);
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The hash [`LspClient::ensure_file_open`] reports for a document's content.
+#[must_use]
+pub fn content_hash(content: &str) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
 /// Build a `TextDocumentPositionParams` from a file path and position.
 fn text_doc_position(
     file: &str,
@@ -547,66 +2180,444 @@ fn text_doc_position(
     })
 }
 
+/// Log and count request `id` by method and `outcome` (`ok`, `error`,
+/// `timeout`, or `closed`), and time it if it was sent.
+fn record_request(
+    id: i64,
+    method: &'static str,
+    outcome: &'static str,
+    sent: Option<std::time::Instant>,
+) {
+    let elapsed_ms = sent.map(|sent| sent.elapsed().as_millis());
+    tracing::debug!(event = "lsp_response", id, method, outcome, elapsed_ms);
+    counter!("lspmux_cc_lsp_requests_total", "method" => method, "outcome" => outcome).increment(1);
+    if let Some(sent) = sent {
+        histogram!("lspmux_cc_lsp_request_duration_seconds", "method" => method)
+            .record(sent.elapsed().as_secs_f64());
+    }
+}
+
+/// Lock a map shared with the reader task. It is never held across an
+/// `.await`, and a panicking holder leaves it consistent.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Show both directions of a transport to `observers`, counting the bytes
+/// in `traffic`.
+fn tap<R, W>(
+    reader: R,
+    writer: W,
+    observers: Observers,
+    traffic: &Arc<TrafficTotals>,
+) -> (Tap<R>, Tap<W>) {
+    let reader = Tap::new(
+        reader,
+        Origin::Server,
+        Arc::clone(&observers),
+        Arc::clone(traffic),
+    );
+    let writer = Tap::new(writer, Origin::Client, observers, Arc::clone(traffic));
+    (reader, writer)
+}
+
+/// Frame a JSON-RPC message with its `Content-Length` header and queue it.
+///
+/// Fails rather than waits when the queue is full: the reader loop sends
+/// through here too, and waiting there on a server blocked writing to us
+/// would deadlock.
+fn send_frame(outbox: &Outbox, msg: &Value) -> Result<()> {
+    let body = serde_json::to_string(msg)?;
+    let mut frame = format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+    frame.extend_from_slice(body.as_bytes());
+    outbox.try_send(frame).map_err(|e| match e {
+        mpsc::error::TrySendError::Full(_) => anyhow!(
+            "LSP server is not reading its stdin ({OUTBOX_CAPACITY} messages already queued)"
+        ),
+        mpsc::error::TrySendError::Closed(_) => anyhow!("LSP server stdin is closed"),
+    })
+}
+
+/// Start the task that owns the child's stdin.
+fn spawn_writer(stdin: impl AsyncWrite + Unpin + Send + 'static, alive: Arc<AtomicBool>) -> Outbox {
+    let (outbox, frames) = mpsc::channel(OUTBOX_CAPACITY);
+    tokio::spawn(writer_loop(stdin, frames, alive));
+    outbox
+}
+
+/// Write queued frames to the child's stdin until every sender is gone, a
+/// write fails, or `alive` goes false (the child exited; the reader loop
+/// reports that). Frames still queued then are dropped.
+async fn writer_loop(
+    mut stdin: impl AsyncWrite + Unpin,
+    mut frames: mpsc::Receiver<Vec<u8>>,
+    alive: Arc<AtomicBool>,
+) {
+    while let Some(frame) = frames.recv().await {
+        if !alive.load(Ordering::Acquire) {
+            frames.close();
+            tracing::debug!("dropping {} queued LSP message(s)", frames.len() + 1);
+            return;
+        }
+        let written = async {
+            stdin.write_all(&frame).await?;
+            stdin.flush().await
+        };
+        if let Err(e) = written.await {
+            tracing::warn!("failed to write to LSP stdin: {e}");
+            return;
+        }
+    }
+}
+
+/// An LSP request that has been sent but not yet answered.
+///
+/// If it is dropped before [`Self::finished`] is set, the pending entry is
+/// removed and `$/cancelRequest` is sent so the server can abandon the work.
+struct InFlightRequest {
+    id: i64,
+    pending: PendingMap,
+    outbox: Outbox,
+    alive: Arc<AtomicBool>,
+    finished: bool,
+}
+
+impl Drop for InFlightRequest {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if lock(&self.pending).remove(&self.id).is_none() {
+            // The response arrived while we were being dropped.
+            return;
+        }
+        counter!("lspmux_cc_lsp_requests_cancelled_total").increment(1);
+        if !self.alive.load(Ordering::Acquire) {
+            return;
+        }
+        let id = self.id;
+        if let Err(e) = send_frame(&self.outbox, &cancel_request_message(id)) {
+            tracing::debug!("failed to send $/cancelRequest for {id}: {e}");
+        }
+    }
+}
+
+fn cancel_request_message(id: i64) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "$/cancelRequest",
+        "params": { "id": id },
+    })
+}
+
+/// Read the body of one `Content-Length` framed message from `reader`, or
+/// `None` once the stream ends.
+///
+/// # Errors
+///
+/// Returns an error on a malformed, overlong, or oversized header, or if
+/// the stream ends inside a body.
+pub async fn read_frame(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<Option<Vec<u8>>> {
+    let mut content_length: Option<usize> = None;
+    loop {
+        let mut line = String::new();
+        let limit = MAX_LSP_HEADER_LINE as u64;
+        let n = (&mut *reader).take(limit).read_line(&mut line).await?;
+        if n == 0 {
+            return Ok(None);
+        }
+        if n == MAX_LSP_HEADER_LINE && !line.ends_with('\n') {
+            bail!("LSP header line exceeds maximum of {MAX_LSP_HEADER_LINE} bytes");
+        }
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            break;
+        }
+        if let Some(len_str) = trimmed.strip_prefix("Content-Length: ") {
+            content_length = Some(len_str.parse().context("invalid Content-Length")?);
+        }
+    }
+
+    let length = content_length.context("missing Content-Length header")?;
+    if length > MAX_LSP_MESSAGE_SIZE {
+        bail!("LSP message size {length} exceeds maximum of {MAX_LSP_MESSAGE_SIZE}");
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).await?;
+    Ok(Some(body))
+}
+
 /// Read LSP JSON-RPC messages from stdout and dispatch responses to pending requests.
+#[allow(clippy::too_many_arguments)]
 async fn reader_loop(
-    stdout: tokio::process::ChildStdout,
+    stdout: impl AsyncRead + Unpin,
     pending: PendingMap,
     readiness: Arc<tokio::sync::Mutex<ReadinessState>>,
+    generation: Arc<AtomicU64>,
+    outbox: Outbox,
+    notifications: &NotificationQueue,
+    settings: watch::Receiver<Value>,
+    edits: &EditCapture,
 ) -> Result<()> {
     let mut reader = BufReader::new(stdout);
 
     loop {
-        // Read headers until blank line
-        let mut content_length: Option<usize> = None;
-        loop {
-            let mut line = String::new();
-            let n = reader.read_line(&mut line).await?;
-            if n == 0 {
-                tracing::info!("LSP stdout closed");
-                return Ok(());
+        let Some(body) = read_frame(&mut reader).await? else {
+            tracing::info!("LSP stdout closed");
+            return Ok(());
+        };
+        // The framing is intact, so a bad message is skipped, not fatal.
+        let msg: Value = match serde_json::from_slice(&body) {
+            Ok(msg) => msg,
+            Err(e) => {
+                tracing::warn!("skipping invalid JSON-RPC message: {e}");
+                continue;
             }
-            let trimmed = line.trim();
-            if trimmed.is_empty() {
-                break;
+        };
+
+        // Server-to-client requests carry both an id and a method; answer them
+        // so the server does not stall waiting on us.
+        if let (Some(id), Some(method)) = (msg.get("id"), msg.get("method").and_then(Value::as_str))
+        {
+            let response = if method == "workspace/applyEdit" {
+                capture_edit(id, msg.get("params"), edits)
+            } else {
+                server_request_response(id, method, msg.get("params"), &settings.borrow())
+            };
+            if let Err(e) = send_frame(&outbox, &response) {
+                tracing::warn!("failed to answer LSP server request {method}: {e}");
+                continue;
             }
-            if let Some(len_str) = trimmed.strip_prefix("Content-Length: ") {
-                content_length = Some(len_str.parse().context("invalid Content-Length")?);
+            tracing::debug!("answered LSP server request: {method}");
+            continue;
+        }
+
+        // If it has an id, it's a response to a request we sent
+        if let Some(id) = msg.get("id").and_then(Value::as_i64) {
+            let tx = lock(&pending).remove(&id);
+            if let Some(tx) = tx {
+                let _ = tx.send(msg);
+            } else {
+                tracing::warn!("received response for unknown request id {id}");
+            }
+        } else {
+            // It's a notification from the server (e.g., diagnostics)
+            let method = msg.get("method").and_then(Value::as_str).unwrap_or("?");
+            match (method, msg.get("params")) {
+                ("experimental/serverStatus", Some(params)) => {
+                    match handle_server_status_notification(&readiness, params).await {
+                        Ok(true) => {
+                            generation.fetch_add(1, Ordering::AcqRel);
+                        }
+                        Ok(false) => {}
+                        Err(e) => tracing::warn!("{e:#}"),
+                    }
+                }
+                // Queued, not sent: subscribers must not hold up responses.
+                ("$/progress", Some(params)) => {
+                    if let Some(event) = parse_progress_notification(params) {
+                        notifications.push(Notification::Progress(event));
+                    }
+                }
+                ("textDocument/publishDiagnostics", Some(params)) => {
+                    if let Some(published) = parse_published_diagnostics(params) {
+                        notifications.push(Notification::Diagnostics(published));
+                    }
+                }
+                _ => {}
+            }
+            tracing::debug!("LSP notification: {method}");
+        }
+    }
+}
+
+/// The queue the reader loop puts notifications in, with a task handing
+/// them to their subscribers until it is closed.
+fn spawn_dispatcher(
+    progress_tx: broadcast::Sender<ProgressEvent>,
+    diagnostics_tx: broadcast::Sender<PublishedDiagnostics>,
+) -> Arc<NotificationQueue> {
+    let notifications = Arc::new(NotificationQueue::new(NOTIFICATION_QUEUE_CAPACITY));
+    let queue = Arc::clone(&notifications);
+    tokio::spawn(async move {
+        while let Some(notification) = queue.next().await {
+            // No subscribers is the common case; ignore the send error.
+            match notification {
+                Notification::Progress(event) => {
+                    let _ = progress_tx.send(event);
+                }
+                Notification::Diagnostics(published) => {
+                    let _ = diagnostics_tx.send(published);
+                }
+            }
+        }
+    });
+    notifications
+}
+
+/// Build the response to a server-initiated request.
+///
+/// Progress token creation is acknowledged and `workspace/configuration` is
+/// answered from `settings`; anything else is reported as unsupported so the
+/// server can fall back gracefully.
+fn server_request_response(
+    id: &Value,
+    method: &str,
+    params: Option<&Value>,
+    settings: &Value,
+) -> Value {
+    if method == "window/workDoneProgress/create" {
+        json!({ "jsonrpc": "2.0", "id": id, "result": null })
+    } else if method == "workspace/configuration" {
+        let items = params
+            .and_then(|params| params.get("items"))
+            .and_then(Value::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        let result = items
+            .iter()
+            .map(|item| match item.get("section").and_then(Value::as_str) {
+                Some("rust-analyzer") => settings.clone(),
+                _ => Value::Null,
+            })
+            .collect::<Vec<_>>();
+        json!({ "jsonrpc": "2.0", "id": id, "result": result })
+    } else {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {
+                "code": -32601,
+                "message": format!("unsupported server request: {method}"),
+            },
+        })
+    }
+}
+
+/// Drop every pending request so its caller gets an immediate error
+/// (dropping a sender causes `RecvError` on its receiver). Returns how many
+/// there were.
+fn drain_pending(pending: &PendingMap) -> usize {
+    let mut map = lock(pending);
+    let count = map.len();
+    map.clear();
+    count
+}
+
+/// Answer `workspace/applyEdit`. The edit is kept for the running command,
+/// whose caller applies it once the command finishes; with no command
+/// running, it is refused.
+fn capture_edit(id: &Value, params: Option<&Value>, edits: &EditCapture) -> Value {
+    let edit = params
+        .and_then(|params| params.get("edit"))
+        .and_then(|edit| {
+            let mut edit = edit.clone();
+            snippet::mark_snippet_edits(&mut edit);
+            serde_json::from_value(edit).ok()
+        });
+    let refused = |reason: &str| {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": { "applied": false, "failureReason": reason },
+        })
+    };
+    let outcome = {
+        let mut captured = lock(edits);
+        match (captured.as_mut(), edit) {
+            (None, _) => Err("edits are only applied while a command runs"),
+            (Some(_), None) => Err("malformed workspace edit"),
+            (Some(captured), Some(edit)) => {
+                captured.push(edit);
+                Ok(())
+            }
+        }
+    };
+    match outcome {
+        Ok(()) => json!({ "jsonrpc": "2.0", "id": id, "result": { "applied": true } }),
+        Err(reason) => refused(reason),
+    }
+}
+
+/// Stops capturing edits when the command it was made for ends, even if
+/// the call is cancelled.
+struct Capturing<'a>(&'a EditCapture);
+
+impl Drop for Capturing<'_> {
+    fn drop(&mut self) {
+        lock(self.0).take();
+    }
+}
+
+/// Maintain the set of unfinished work-done progress from `events`.
+async fn track_progress(mut events: broadcast::Receiver<ProgressEvent>, active: ActiveProgress) {
+    loop {
+        match events.recv().await {
+            Ok(event) => record_progress(&mut *active.lock().await, event),
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::debug!("progress tracker skipped {skipped} event(s)");
             }
+            Err(broadcast::error::RecvError::Closed) => return,
         }
+    }
+}
 
-        let length = content_length.context("missing Content-Length header")?;
-
-        if length > MAX_LSP_MESSAGE_SIZE {
-            bail!("LSP message size {length} exceeds maximum of {MAX_LSP_MESSAGE_SIZE}");
+/// Apply one progress event: `end` retires the token; `report` keeps the
+/// title from `begin` and any fields the report leaves out.
+fn record_progress(active: &mut HashMap<String, ProgressEvent>, event: ProgressEvent) {
+    match event.kind.as_str() {
+        "end" => {
+            active.remove(&event.token);
         }
-
-        // Read body
-        let mut body = vec![0u8; length];
-        reader.read_exact(&mut body).await?;
-
-        let msg: Value = serde_json::from_slice(&body).context("invalid JSON-RPC message")?;
-
-        // If it has an id, it's a response to a request we sent
-        if let Some(id) = msg.get("id").and_then(Value::as_i64) {
-            let mut map = pending.lock().await;
-            if let Some(tx) = map.remove(&id) {
-                let _ = tx.send(msg);
-            } else {
-                tracing::warn!("received response for unknown request id {id}");
+        "begin" => {
+            active.insert(event.token.clone(), event);
+        }
+        _ => {
+            let entry = active
+                .entry(event.token.clone())
+                .or_insert_with(|| event.clone());
+            entry.kind = event.kind;
+            if event.message.is_some() {
+                entry.message = event.message;
             }
-        } else {
-            // It's a notification from the server (e.g., diagnostics)
-            let method = msg.get("method").and_then(Value::as_str).unwrap_or("?");
-            if method == "experimental/serverStatus" {
-                if let Some(params) = msg.get("params") {
-                    handle_server_status_notification(&readiness, params).await?;
-                }
+            if event.percentage.is_some() {
+                entry.percentage = event.percentage;
             }
-            tracing::debug!("LSP notification: {method}");
         }
     }
 }
 
+/// Parse the params of a `$/progress` notification carrying work-done progress.
+fn parse_progress_notification(params: &Value) -> Option<ProgressEvent> {
+    let token = match params.get("token")? {
+        Value::String(token) => token.clone(),
+        Value::Number(token) => token.to_string(),
+        _ => return None,
+    };
+    let value = params.get("value")?;
+    let text = |key: &str| value.get(key).and_then(Value::as_str).map(String::from);
+    Some(ProgressEvent {
+        token,
+        kind: text("kind")?,
+        title: text("title"),
+        message: text("message"),
+        percentage: value
+            .get("percentage")
+            .and_then(Value::as_u64)
+            .and_then(|pct| u32::try_from(pct).ok()),
+    })
+}
+
+/// Parse `textDocument/publishDiagnostics` params; `None` if malformed.
+fn parse_published_diagnostics(params: &Value) -> Option<PublishedDiagnostics> {
+    let params: lsp_types::PublishDiagnosticsParams =
+        serde_json::from_value(params.clone()).ok()?;
+    Some(PublishedDiagnostics {
+        file_path: uri_to_path(&params.uri),
+        version: params.version,
+        diagnostics: params.diagnostics,
+    })
+}
+
 #[derive(Debug, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ServerStatusParams {
@@ -633,10 +2644,11 @@ impl ServerHealth {
     }
 }
 
+/// Record a readiness notification, returning whether the state changed.
 async fn handle_server_status_notification(
     readiness: &Arc<tokio::sync::Mutex<ReadinessState>>,
     params: &Value,
-) -> Result<()> {
+) -> Result<bool> {
     let status: ServerStatusParams =
         serde_json::from_value(params.clone()).context("invalid server status notification")?;
     let next_state = ReadinessState {
@@ -665,12 +2677,14 @@ async fn handle_server_status_notification(
         );
     }
 
-    Ok(())
+    Ok(changed)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::mock_lsp;
+    use crate::timeouts::MethodClass;
 
     #[test]
     fn file_uri_absolute_path() {
@@ -707,6 +2721,197 @@ mod tests {
         assert!(file_uri("relative/path.rs").is_err());
     }
 
+    proptest::proptest! {
+        #[test]
+        fn file_uri_round_trips_any_absolute_path(path in "/.*") {
+            let uri = file_uri(&path).unwrap();
+            proptest::prop_assert_eq!(uri_to_path(&uri), path);
+        }
+
+        #[test]
+        fn frames_round_trip(
+            bodies in proptest::collection::vec(proptest::collection::vec(0..=255_u8, 0..256), 0..4),
+        ) {
+            let mut bytes = Vec::new();
+            for body in &bodies {
+                let header = format!(
+                    "Content-Length: {}\r\nContent-Type: application/vscode-jsonrpc\r\n\r\n",
+                    body.len()
+                );
+                bytes.extend_from_slice(header.as_bytes());
+                bytes.extend_from_slice(body);
+            }
+            let read = read_all_frames(&bytes);
+            proptest::prop_assert_eq!(read.0, bodies);
+            proptest::prop_assert!(read.1.is_none());
+        }
+
+        #[test]
+        fn arbitrary_bytes_never_panic_the_framing(
+            bytes in proptest::collection::vec(0..=255_u8, 0..1024),
+        ) {
+            let (frames, _) = read_all_frames(&bytes);
+            proptest::prop_assert!(frames.iter().map(Vec::len).sum::<usize>() <= bytes.len());
+        }
+    }
+
+    /// The frames in `bytes` up to the end or the first error, and the error.
+    fn read_all_frames(bytes: &[u8]) -> (Vec<Vec<u8>>, Option<anyhow::Error>) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let mut reader = BufReader::new(bytes);
+            let mut frames = Vec::new();
+            loop {
+                match read_frame(&mut reader).await {
+                    Ok(Some(body)) => frames.push(body),
+                    Ok(None) => return (frames, None),
+                    Err(e) => return (frames, Some(e)),
+                }
+            }
+        })
+    }
+
+    #[test]
+    fn overlong_header_lines_are_refused() {
+        let mut bytes = vec![b'X'; MAX_LSP_HEADER_LINE * 2];
+        bytes.extend_from_slice(b"\r\n\r\n");
+        let (frames, error) = read_all_frames(&bytes);
+        assert!(frames.is_empty());
+        assert!(error.unwrap().to_string().contains("header line"));
+    }
+
+    #[tokio::test]
+    async fn malformed_messages_do_not_drop_the_connection() {
+        let (client, mut server) = mock_lsp::connect(json!({})).await.unwrap();
+        server
+            .send_raw(b"Content-Length: 9\r\n\r\n{\"id\": 1,")
+            .await
+            .unwrap();
+        server
+            .notify("experimental/serverStatus", json!({ "health": 7 }))
+            .await
+            .unwrap();
+        let answer = async {
+            let (id, _) = server
+                .expect_request("rust-analyzer/reloadWorkspace")
+                .await
+                .unwrap();
+            server.respond(id, json!(null)).await.unwrap();
+        };
+        let (reloaded, ()) = tokio::join!(client.reload_workspace(), answer);
+        reloaded.unwrap();
+        assert!(client.is_alive());
+    }
+
+    #[tokio::test]
+    async fn commands_collect_the_edits_they_request() {
+        let (client, mut server) =
+            mock_lsp::connect(json!({ "executeCommandProvider": { "commands": ["demo.fix"] } }))
+                .await
+                .unwrap();
+        assert_eq!(client.server_commands(), ["demo.fix"]);
+        let edit = json!({ "changes": { "file:///tmp/a.rs": [{
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } },
+            "newText": "// fixed\n",
+        }] } });
+        let apply = |id: i64| {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "method": "workspace/applyEdit",
+                "params": { "edit": edit },
+            })
+        };
+
+        // Outside a command, the edit is refused.
+        server.send(&apply(100)).await.unwrap();
+        let refused = server.recv().await.unwrap();
+        assert_eq!(refused["result"]["applied"], false);
+
+        let answer = async {
+            let (id, params) = server
+                .expect_request("workspace/executeCommand")
+                .await
+                .unwrap();
+            assert_eq!(params["command"], "demo.fix");
+            server.send(&apply(101)).await.unwrap();
+            let applied = server.recv().await.unwrap();
+            assert_eq!(applied["result"]["applied"], true);
+            server.respond(id, json!("done")).await.unwrap();
+        };
+        let (executed, ()) =
+            tokio::join!(client.execute_command("demo.fix", vec![json!(1)]), answer);
+        let (result, edits) = executed.unwrap();
+        assert_eq!(result, Some(json!("done")));
+        assert_eq!(edits.len(), 1);
+        assert!(lock(&client.applied_edits).is_none());
+    }
+
+    #[tokio::test]
+    async fn only_the_picked_code_action_is_resolved() {
+        let action = json!({ "title": "Fill match arms", "data": { "id": 7 } });
+        let (client, mut server) =
+            mock_lsp::connect(json!({ "codeActionProvider": { "resolveProvider": true } }))
+                .await
+                .unwrap();
+        assert!(client.resolves_code_actions());
+        let listed: lsp_types::CodeAction = serde_json::from_value(action.clone()).unwrap();
+        let answer = async {
+            let (id, params) = server.expect_request("codeAction/resolve").await.unwrap();
+            assert_eq!(params["data"]["id"], 7);
+            let mut resolved = params;
+            resolved["edit"] = json!({ "changes": {} });
+            server.respond(id, resolved).await.unwrap();
+        };
+        let (resolved, ()) = tokio::join!(client.resolve_code_action(listed.clone()), answer);
+        assert!(resolved.unwrap().edit.is_some());
+
+        // An action with its edit, or from a server that does not resolve,
+        // is not sent back.
+        let with_edit = lsp_types::CodeAction {
+            edit: Some(lsp_types::WorkspaceEdit::default()),
+            ..listed.clone()
+        };
+        assert_eq!(
+            client.resolve_code_action(with_edit.clone()).await.unwrap(),
+            with_edit
+        );
+        let (client, _server) = mock_lsp::connect(json!({ "codeActionProvider": true }))
+            .await
+            .unwrap();
+        assert!(!client.resolves_code_actions());
+        assert_eq!(
+            client.resolve_code_action(listed.clone()).await.unwrap(),
+            listed
+        );
+    }
+
+    #[tokio::test]
+    async fn completions_are_resolved_for_their_imports() {
+        let (client, mut server) =
+            mock_lsp::connect(json!({ "completionProvider": { "resolveProvider": true } }))
+                .await
+                .unwrap();
+        assert!(client.resolves_completions());
+        let item = lsp_types::CompletionItem::new_simple("HashMap".to_string(), String::new());
+        let answer = async {
+            let (id, params) = server
+                .expect_request("completionItem/resolve")
+                .await
+                .unwrap();
+            let mut resolved = params;
+            resolved["additionalTextEdits"] = json!([{
+                "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } },
+                "newText": "use std::collections::HashMap;\n",
+            }]);
+            server.respond(id, resolved).await.unwrap();
+        };
+        let (resolved, ()) = tokio::join!(client.resolve_completion(item), answer);
+        assert_eq!(resolved.unwrap().additional_text_edits.unwrap().len(), 1);
+    }
+
     #[test]
     fn detect_language_id_common_extensions() {
         assert_eq!(detect_language_id("/foo/bar.rs"), "rust");
@@ -769,38 +2974,397 @@ mod tests {
         assert_eq!(version.lock().await.as_deref(), Some("2024-01-15"));
     }
 
+    /// A client writing to `child`'s stdin, with nothing reading its replies.
+    fn test_client(mut child: Child) -> LspClient {
+        let stdin = child.stdin.take().unwrap();
+        let alive = Arc::new(AtomicBool::new(true));
+        LspClient {
+            outbox: spawn_writer(stdin, Arc::clone(&alive)),
+            next_id: AtomicI64::new(1),
+            pending: Arc::new(Mutex::new(HashMap::new())),
+            opened_files: Mutex::new(HashMap::new()),
+            sync_gates: Mutex::new(HashMap::new()),
+            child: Arc::new(tokio::sync::Mutex::new(Some(child))),
+            alive,
+            workspace_root: tokio::sync::Mutex::new(None),
+            server_info: tokio::sync::Mutex::new(None),
+            readiness: Arc::new(tokio::sync::Mutex::new(ReadinessState::default())),
+            progress_tx: broadcast::channel(PROGRESS_CHANNEL_CAPACITY).0,
+            active_progress: ActiveProgress::default(),
+            diagnostics_tx: broadcast::channel(DIAGNOSTICS_CHANNEL_CAPACITY).0,
+            generation: Arc::new(AtomicU64::new(0)),
+            capabilities: watch::Sender::new(lsp_types::ServerCapabilities::default()),
+            request_timeouts: RequestTimeouts::new(DEFAULT_REQUEST_TIMEOUT),
+            file_size_limit: FileSizeLimit::default(),
+            document_budget: DEFAULT_DOCUMENT_BUDGET,
+            base_settings: json!({}),
+            settings: watch::Sender::new(json!({})),
+            traffic: Arc::default(),
+            applied_edits: EditCapture::default(),
+            command_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
     #[tokio::test]
     #[allow(clippy::significant_drop_tightening)]
     async fn request_send_failure_cleans_pending_entry() {
-        let mut child = Command::new("cat")
+        let child = Command::new("cat")
             .stdin(std::process::Stdio::piped())
             .stdout(std::process::Stdio::piped())
             .spawn()
             .unwrap();
-        let stdin = child.stdin.take().unwrap();
-
-        let client = LspClient {
-            child_stdin: Arc::new(Mutex::new(stdin)),
-            next_id: AtomicI64::new(1),
-            pending: Arc::new(Mutex::new(HashMap::new())),
-            opened_files: Mutex::new(HashMap::new()),
-            child: Arc::new(Mutex::new(child)),
-            alive: Arc::new(AtomicBool::new(false)),
-            workspace_root: tokio::sync::Mutex::new(None),
-            server_version: tokio::sync::Mutex::new(None),
-            readiness: Arc::new(tokio::sync::Mutex::new(ReadinessState::default())),
-        };
+        let client = test_client(child);
+        client.alive.store(false, Ordering::Release);
 
         let err = client.request::<lsp_types::request::Shutdown>(()).await;
         assert!(err.is_err());
-        assert!(client.pending.lock().await.is_empty());
+        assert!(lock(&client.pending).is_empty());
+
+        {
+            let mut child = client.child.lock().await;
+            let _ = child.as_mut().unwrap().kill().await;
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::significant_drop_tightening)]
+    async fn concurrent_requests_are_in_flight_together() {
+        // `cat` echoes our own frames back, so no request is ever answered.
+        let child = Command::new("cat")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut client = test_client(child);
+        client.request_timeouts = RequestTimeouts::new(Duration::from_millis(200));
+
+        let observe = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            client.pending_request_count()
+        };
+        let (first, second, in_flight) = tokio::join!(
+            client.request::<lsp_types::request::Shutdown>(()),
+            client.request::<lsp_types::request::Shutdown>(()),
+            observe,
+        );
+        assert_eq!(in_flight, 2);
+        assert!(first.is_err() && second.is_err());
+        assert_eq!(client.pending_request_count(), 0);
+
+        {
+            let mut child = client.child.lock().await;
+            let _ = child.as_mut().unwrap().kill().await;
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::significant_drop_tightening)]
+    async fn document_syncs_are_coalesced_and_skip_unmodified_files() {
+        let child = Command::new("cat")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let client = test_client(child);
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("lib.rs");
+        let file_path = file.to_str().unwrap();
+
+        std::fs::write(&file, "fn a() {}").unwrap();
+        let opened = client.ensure_file_open(file_path).await.unwrap();
+        // A rewrite is picked up by the very next call.
+        std::fs::write(&file, "fn b() {}").unwrap();
+        let changed = client.ensure_file_open(file_path).await.unwrap();
+        assert_ne!(changed, opened);
+        assert_eq!(lock(&client.opened_files)[file_path].version, 1);
+        assert_eq!(client.generation(), 1);
+
+        // Concurrent callers share one synchronization, and its gate goes
+        // once they are done.
+        std::fs::write(&file, "fn cc() {}").unwrap();
+        let (a, b, c) = tokio::join!(
+            client.ensure_file_open(file_path),
+            client.ensure_file_open(file_path),
+            client.ensure_file_open(file_path),
+        );
+        let rewritten = a.unwrap();
+        assert_ne!(rewritten, changed);
+        assert_eq!((b.unwrap(), c.unwrap()), (rewritten, rewritten));
+        assert_eq!(lock(&client.opened_files)[file_path].version, 2);
+        assert!(lock(&client.sync_gates).is_empty());
+
+        // Once the modification time is well before the read, an unchanged
+        // modification time and size skip the read, even if the bytes differ.
+        let modified = std::time::SystemTime::now() - Duration::from_mins(1);
+        set_modified(&file, modified);
+        client.invalidate_document(file_path);
+        assert_eq!(client.ensure_file_open(file_path).await.unwrap(), rewritten);
+        std::fs::write(&file, "fn dd() {}").unwrap();
+        set_modified(&file, modified);
+        assert_eq!(client.ensure_file_open(file_path).await.unwrap(), rewritten);
+
+        std::fs::write(&file, "fn longer() {}").unwrap();
+        assert_ne!(client.ensure_file_open(file_path).await.unwrap(), rewritten);
+        assert_eq!(lock(&client.opened_files)[file_path].version, 3);
+
+        {
+            let mut child = client.child.lock().await;
+            let _ = child.as_mut().unwrap().kill().await;
+        }
+    }
+
+    fn set_modified(file: &std::path::Path, modified: std::time::SystemTime) {
+        std::fs::File::options()
+            .write(true)
+            .open(file)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_rewrite_within_one_mtime_tick_of_the_read_is_not_missed() {
+        let (client, _server) = mock_lsp::connect(json!({})).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("lib.rs");
+        let file_path = file.to_str().unwrap();
+
+        // On a file system with coarse timestamps, a same-size rewrite right
+        // after the read keeps the modification time the read saw.
+        std::fs::write(&file, "fn a() {}").unwrap();
+        let modified = std::fs::metadata(&file).unwrap().modified().unwrap();
+        let opened = client.ensure_file_open(file_path).await.unwrap();
+        assert_eq!(client.tracked_documents()[0].stamp, None);
+        std::fs::write(&file, "fn b() {}").unwrap();
+        set_modified(&file, modified);
+        let rewritten = client.ensure_file_open(file_path).await.unwrap();
+        assert_ne!(rewritten, opened);
+        assert_eq!(rewritten, content_hash("fn b() {}"));
+    }
+
+    #[tokio::test]
+    async fn tracked_documents_report_what_the_server_holds() {
+        let (client, _server) = mock_lsp::connect(json!({})).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("lib.rs");
+        let file_path = file.to_str().unwrap();
+        std::fs::write(&file, "fn a() {}").unwrap();
+        set_modified(&file, std::time::SystemTime::now() - Duration::from_mins(1));
+        let content_hash = client.ensure_file_open(file_path).await.unwrap();
+
+        let documents = client.tracked_documents();
+        assert_eq!(documents.len(), 1);
+        let document = &documents[0];
+        assert_eq!(document.file_path, file_path);
+        assert_eq!((document.version, document.len), (0, 9));
+        assert_eq!(document.content_hash, content_hash);
+        let metadata = std::fs::metadata(&file).unwrap();
+        assert_eq!(document.matches_disk(&metadata), Some(true));
+
+        std::fs::write(&file, "fn longer() {}").unwrap();
+        let metadata = std::fs::metadata(&file).unwrap();
+        assert_eq!(document.matches_disk(&metadata), Some(false));
+        client.invalidate_document(file_path);
+        assert_eq!(client.tracked_documents()[0].matches_disk(&metadata), None);
+    }
+
+    #[tokio::test]
+    async fn a_stalled_server_fails_sends_and_a_dead_one_drops_them() {
+        // Nothing reads the server's end, so the writer blocks on a frame.
+        let (stdin, mut server_end) = tokio::io::duplex(64);
+        let alive = Arc::new(AtomicBool::new(true));
+        let outbox = spawn_writer(stdin, Arc::clone(&alive));
+        let msg = json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} });
+        let mut queued = 0;
+        let full = loop {
+            match send_frame(&outbox, &msg) {
+                Ok(()) => queued += 1,
+                Err(e) => break e,
+            }
+        };
+        assert!(queued >= OUTBOX_CAPACITY);
+        assert!(full.to_string().contains("not reading its stdin"), "{full}");
+
+        alive.store(false, Ordering::Release);
+        tokio::spawn(async move {
+            let mut sink = Vec::new();
+            let _ = server_end.read_to_end(&mut sink).await;
+        });
+        timeout(Duration::from_secs(5), outbox.closed())
+            .await
+            .expect("the writer kept the queue after the server died");
+        let closed = send_frame(&outbox, &msg).unwrap_err();
+        assert!(closed.to_string().contains("stdin is closed"), "{closed}");
+    }
+
+    #[tokio::test]
+    async fn documents_are_only_recorded_once_the_server_is_sent_them() {
+        /// Queue notifications the server does not read until the pipe to
+        /// it and the queue are both full, and return how many were queued.
+        async fn fill(client: &LspClient) -> usize {
+            let mut queued = 0;
+            loop {
+                while client.notify("$/filler", &json!({})).is_ok() {
+                    queued += 1;
+                }
+                // Let the writer move what it can into the pipe.
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                if client.notify("$/filler", &json!({})).is_err() {
+                    return queued;
+                }
+                queued += 1;
+            }
+        }
+        /// Read the `queued` fillers, making room for more messages.
+        async fn drain(server: &mut mock_lsp::MockServer, queued: usize) {
+            for _ in 0..queued {
+                server.recv().await.unwrap();
+            }
+        }
+
+        let (client, mut server) = mock_lsp::connect(json!({})).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("lib.rs");
+        let file_path = file.to_str().unwrap();
+        std::fs::write(&file, "fn a() {}").unwrap();
+        set_modified(&file, std::time::SystemTime::now() - Duration::from_mins(1));
+
+        let queued = fill(&client).await;
+        assert!(client.ensure_file_open(file_path).await.is_err());
+        assert_eq!(client.document_version(file_path), None);
+        drain(&mut server, queued).await;
+        client.ensure_file_open(file_path).await.unwrap();
+        let opened = server
+            .expect_notification("textDocument/didOpen")
+            .await
+            .unwrap();
+        assert_eq!(opened["textDocument"]["text"], "fn a() {}");
+
+        std::fs::write(&file, "fn b() {}").unwrap();
+        client.invalidate_document(file_path);
+        let queued = fill(&client).await;
+        assert!(client.ensure_file_open(file_path).await.is_err());
+        assert_eq!(client.document_version(file_path), Some(0));
+        assert_eq!(
+            client.synced_content_hash(file_path),
+            Some(content_hash("fn a() {}"))
+        );
+        // The next call sends the content the failed one could not.
+        drain(&mut server, queued).await;
+        client.ensure_file_open(file_path).await.unwrap();
+        let changed = server
+            .expect_notification("textDocument/didChange")
+            .await
+            .unwrap();
+        assert_eq!(changed["textDocument"]["version"], 1);
+        assert_eq!(changed["contentChanges"][0]["text"], "fn b() {}");
+
+        let queued = fill(&client).await;
+        assert!(client.close_document(file_path).is_err());
+        assert_eq!(client.document_version(file_path), Some(1));
+        drain(&mut server, queued).await;
+        client.close_document(file_path).unwrap();
+        server
+            .expect_notification("textDocument/didClose")
+            .await
+            .unwrap();
+        assert_eq!(client.document_version(file_path), None);
+    }
+
+    #[tokio::test]
+    #[allow(clippy::significant_drop_tightening)]
+    async fn dropped_request_sends_cancel_and_cleans_pending_entry() {
+        // `cat` echoes our own frames back, so the request is never answered.
+        let mut child = Command::new("cat")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = child.stdout.take().unwrap();
+
+        let mut client = test_client(child);
+        client.next_id = AtomicI64::new(7);
+
+        let abandoned = timeout(
+            Duration::from_millis(50),
+            client.request::<lsp_types::request::Shutdown>(()),
+        )
+        .await;
+        assert!(abandoned.is_err());
+
+        let mut echoed = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !String::from_utf8_lossy(&echoed).contains("$/cancelRequest") {
+            let n = timeout(Duration::from_secs(5), stdout.read(&mut buf))
+                .await
+                .expect("no $/cancelRequest was sent")
+                .unwrap();
+            assert!(n > 0, "stdout closed before $/cancelRequest");
+            echoed.extend_from_slice(&buf[..n]);
+        }
+        assert!(String::from_utf8_lossy(&echoed).contains(r#""params":{"id":7}"#));
+        assert!(lock(&client.pending).is_empty());
+
+        {
+            let mut child = client.child.lock().await;
+            let _ = child.as_mut().unwrap().kill().await;
+        }
+    }
+
+    #[tokio::test]
+    #[allow(clippy::significant_drop_tightening)]
+    async fn least_recently_used_documents_are_closed_over_budget() {
+        let child = Command::new("cat")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut client = test_client(child);
+        client.document_budget = 25;
+        let dir = tempfile::tempdir().unwrap();
+        let paths: Vec<String> = ["a.rs", "b.rs", "c.rs"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, "fn f() {}\n").unwrap();
+                path.display().to_string()
+            })
+            .collect();
+
+        client.ensure_file_open(&paths[0]).await.unwrap();
+        client.ensure_file_open(&paths[1]).await.unwrap();
+        // Touch `a.rs` so `b.rs` is the least recently used.
+        client.ensure_file_open(&paths[0]).await.unwrap();
+        client.ensure_file_open(&paths[2]).await.unwrap();
+
+        let mut open: Vec<String> = lock(&client.opened_files).keys().cloned().collect();
+        open.sort();
+        assert_eq!(open, [paths[0].clone(), paths[2].clone()]);
 
         {
             let mut child = client.child.lock().await;
-            let _ = child.kill().await;
+            let _ = child.as_mut().unwrap().kill().await;
         }
     }
 
+    #[tokio::test]
+    async fn read_prefix_stops_at_the_last_complete_line() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("generated.rs");
+        std::fs::write(&file, "const A: u8 = 1;\nconst B: &str = \"é\";\n").unwrap();
+        let file_path = file.to_str().unwrap();
+
+        // 21 bytes ends inside the second line (and its two-byte character).
+        assert_eq!(
+            read_prefix(file_path, 21).await.unwrap(),
+            "const A: u8 = 1;\n"
+        );
+        assert_eq!(
+            read_prefix(file_path, 1024).await.unwrap(),
+            "const A: u8 = 1;\nconst B: &str = \"é\";\n"
+        );
+    }
+
     #[tokio::test]
     async fn server_status_notification_updates_readiness() {
         let readiness = Arc::new(tokio::sync::Mutex::new(ReadinessState::default()));
@@ -821,4 +3385,266 @@ mod tests {
         assert_eq!(snapshot.message.as_deref(), Some("indexing"));
         assert!(snapshot.updated_at_ms.is_some());
     }
+
+    #[test]
+    fn parse_progress_notification_reads_work_done_report() {
+        let event = parse_progress_notification(&serde_json::json!({
+            "token": "rustAnalyzer/Indexing",
+            "value": { "kind": "report", "message": "1/4 (core)", "percentage": 25 }
+        }))
+        .unwrap();
+        assert_eq!(event.token, "rustAnalyzer/Indexing");
+        assert_eq!(event.kind, "report");
+        assert_eq!(event.message.as_deref(), Some("1/4 (core)"));
+        assert_eq!(event.percentage, Some(25));
+    }
+
+    #[test]
+    fn record_progress_tracks_unfinished_work() {
+        let event = |kind: &str, message: Option<&str>, percentage: Option<u32>| ProgressEvent {
+            token: "rustAnalyzer/Indexing".to_string(),
+            kind: kind.to_string(),
+            title: (kind == "begin").then(|| "Indexing".to_string()),
+            message: message.map(String::from),
+            percentage,
+        };
+        let mut active = HashMap::new();
+        record_progress(&mut active, event("begin", None, Some(0)));
+        record_progress(&mut active, event("report", Some("core"), Some(40)));
+        record_progress(&mut active, event("report", None, None));
+        let indexing = &active["rustAnalyzer/Indexing"];
+        assert_eq!(indexing.title.as_deref(), Some("Indexing"));
+        assert_eq!(indexing.message.as_deref(), Some("core"));
+        assert_eq!(indexing.percentage, Some(40));
+
+        record_progress(&mut active, event("end", None, None));
+        assert!(active.is_empty());
+    }
+
+    #[test]
+    fn parse_published_diagnostics_reads_the_file_path() {
+        let published = parse_published_diagnostics(&json!({
+            "uri": "file:///ws/src/lib.rs",
+            "version": 3,
+            "diagnostics": [{
+                "range": {
+                    "start": { "line": 1, "character": 0 },
+                    "end": { "line": 1, "character": 4 }
+                },
+                "severity": 1,
+                "message": "mismatched types"
+            }]
+        }))
+        .unwrap();
+        assert_eq!(published.file_path, "/ws/src/lib.rs");
+        assert_eq!(published.version, Some(3));
+        assert_eq!(published.diagnostics[0].message, "mismatched types");
+        assert!(parse_published_diagnostics(&json!({ "uri": 7 })).is_none());
+    }
+
+    #[test]
+    fn parse_progress_notification_ignores_partial_results() {
+        assert!(parse_progress_notification(&serde_json::json!({
+            "token": 7,
+            "value": [{ "name": "partial" }]
+        }))
+        .is_none());
+    }
+
+    #[test]
+    fn server_request_response_acknowledges_progress_create() {
+        let response = server_request_response(
+            &json!(3),
+            "window/workDoneProgress/create",
+            None,
+            &json!({}),
+        );
+        assert_eq!(response["id"], 3);
+        assert!(response["result"].is_null());
+        assert!(response.get("error").is_none());
+    }
+
+    #[test]
+    fn server_request_response_rejects_unknown_methods() {
+        let response =
+            server_request_response(&json!("abc"), "window/showDocument", None, &json!({}));
+        assert_eq!(response["error"]["code"], -32601);
+    }
+
+    #[test]
+    fn server_request_response_serves_rust_analyzer_settings() {
+        let settings = json!({ "check": { "command": "clippy" } });
+        let params = json!({ "items": [{ "section": "rust-analyzer" }, { "section": "other" }] });
+        let response = server_request_response(
+            &json!(9),
+            "workspace/configuration",
+            Some(&params),
+            &settings,
+        );
+        assert_eq!(response["result"], json!([settings, null]));
+    }
+
+    #[tokio::test]
+    async fn requests_round_trip_through_a_mock_server() {
+        let (client, mut server) = mock_lsp::connect(json!({ "hoverProvider": true }))
+            .await
+            .unwrap();
+        assert_eq!(
+            client.server_version().await.as_deref(),
+            Some(mock_lsp::MOCK_SERVER_VERSION)
+        );
+        let initialized = client.initialize_result().await;
+        assert_eq!(initialized.capabilities, client.capabilities());
+        assert!(initialized.server_info.is_some());
+        assert_eq!(
+            client.capabilities().hover_provider,
+            Some(lsp_types::HoverProviderCapability::Simple(true))
+        );
+
+        let answer = async {
+            let (id, params) = server.expect_request("workspace/symbol").await.unwrap();
+            assert_eq!(params["query"], "Config");
+            server.respond(id, json!([])).await.unwrap();
+        };
+        let (symbols, ()) = tokio::join!(client.workspace_symbols("Config"), answer);
+        assert!(matches!(
+            symbols.unwrap(),
+            Some(lsp_types::WorkspaceSymbolResponse::Flat(symbols)) if symbols.is_empty()
+        ));
+    }
+
+    #[tokio::test]
+    async fn socket_clients_ask_lspmux_for_the_server() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (mut server, client_reader, client_writer) = mock_lsp::pipe();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut client_end = tokio::io::join(client_reader, client_writer);
+            let _ = tokio::io::copy_bidirectional(&mut socket, &mut client_end).await;
+        });
+
+        let handshake = async {
+            let (id, params) = server.expect_request("initialize").await.unwrap();
+            server
+                .respond(id, json!({ "capabilities": {} }))
+                .await
+                .unwrap();
+            server.expect_notification("initialized").await.unwrap();
+            params
+        };
+        let addr = ConnectAddr::Tcp("127.0.0.1".to_string(), port);
+        let pass_environment = ["LSPMUX_CC_UNSET_VARIABLE".to_string()];
+        let server_env = [("RUSTUP_TOOLCHAIN".to_string(), "nightly".to_string())];
+        let (client, params) = tokio::join!(
+            LspClient::connect_lspmux(
+                &addr,
+                "/opt/rust-analyzer",
+                Some("/src/project"),
+                &pass_environment,
+                &server_env,
+                Some(json!({ "cargo": { "features": "all" } })),
+                Observers::from([]),
+            ),
+            handshake,
+        );
+        let client = client.unwrap();
+        assert_eq!(
+            params["initializationOptions"],
+            json!({
+                "cargo": { "features": "all" },
+                "lspMux": {
+                    "version": "1",
+                    "method": "connect",
+                    "server": "/opt/rust-analyzer",
+                    "args": [],
+                    "env": { "RUSTUP_TOOLCHAIN": "nightly" },
+                    "cwd": "/src/project",
+                },
+            })
+        );
+        // rust-analyzer never sees the lspMux option in its settings.
+        assert_eq!(
+            *client.settings.borrow(),
+            json!({ "cargo": { "features": "all" } })
+        );
+    }
+
+    #[tokio::test]
+    async fn server_errors_are_returned_to_the_caller() {
+        let (client, mut server) = mock_lsp::connect(json!({})).await.unwrap();
+        let answer = async {
+            let (id, _) = server.expect_request("workspace/symbol").await.unwrap();
+            server
+                .respond_error(id, -32801, "content modified")
+                .await
+                .unwrap();
+        };
+        let (result, ()) = tokio::join!(client.workspace_symbols("x"), answer);
+        let error = result.unwrap_err();
+        assert!(format!("{error:#}").contains("content modified"));
+        assert!(!is_timeout(&error));
+        assert_eq!(client.pending_request_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn unanswered_requests_time_out_and_are_cancelled() {
+        let (client, mut server) = mock_lsp::connect(json!({})).await.unwrap();
+        let client = client.with_request_timeouts(
+            RequestTimeouts::new(Duration::from_millis(50))
+                .with_class(MethodClass::WorkspaceSymbol, Duration::from_millis(50)),
+        );
+        let (result, request) = tokio::join!(
+            client.workspace_symbols("x"),
+            server.expect_request("workspace/symbol")
+        );
+        let error = result.unwrap_err();
+        assert!(error.to_string().contains("timed out"));
+        assert!(is_timeout(&error.context("workspace symbol search failed")));
+        assert_eq!(client.pending_request_count(), 0);
+        let cancel = server.expect_notification("$/cancelRequest").await.unwrap();
+        assert_eq!(cancel["id"], request.unwrap().0);
+    }
+
+    #[tokio::test]
+    async fn a_server_crash_fails_pending_requests() {
+        let (client, mut server) = mock_lsp::connect(json!({})).await.unwrap();
+        let crash = async {
+            server.expect_request("workspace/symbol").await.unwrap();
+            drop(server);
+        };
+        let (result, ()) = tokio::join!(client.workspace_symbols("x"), crash);
+        assert!(result.unwrap_err().to_string().contains("crashed"));
+        assert!(!client.is_alive());
+        assert!(client.workspace_symbols("x").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn notifications_reach_subscribers() {
+        let (client, mut server) = mock_lsp::connect(json!({})).await.unwrap();
+        let mut progress = client.subscribe_progress();
+        let mut diagnostics = client.subscribe_diagnostics();
+        server
+            .notify(
+                "$/progress",
+                json!({
+                    "token": "rust-analyzer/flycheck/0",
+                    "value": { "kind": "begin", "title": "cargo check" }
+                }),
+            )
+            .await
+            .unwrap();
+        server
+            .notify(
+                "textDocument/publishDiagnostics",
+                json!({ "uri": "file:///mock/workspace/src/lib.rs", "diagnostics": [] }),
+            )
+            .await
+            .unwrap();
+
+        let event = progress.recv().await.unwrap();
+        assert_eq!(event.title.as_deref(), Some("cargo check"));
+        let published = diagnostics.recv().await.unwrap();
+        assert_eq!(published.file_path, "/mock/workspace/src/lib.rs");
+    }
 }
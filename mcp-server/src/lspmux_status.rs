@@ -0,0 +1,354 @@
+//! Asking the lspmux server what it is running, and stopping instances.
+//!
+//! `lspmux status` sends an `initialize` request whose `lspMux` option has
+//! the `status` method instead of `connect`; the server answers with its
+//! language-server instances, each with the workspace it serves and the
+//! clients attached to it, and closes the connection. [`lspmux_status`]
+//! does the same over the socket, so a tool can show whether the MCP server
+//! and an editor really share one rust-analyzer.
+//!
+//! lspmux has no method to stop an instance; it drops one, disconnecting
+//! its clients, when the language server exits. [`stop_instance`] therefore
+//! signals the process of an instance the server reports, and waits until
+//! the server no longer lists it, so the next client to connect gets a
+//! fresh one.
+
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncWriteExt, BufReader};
+use tokio::process::Command;
+
+use crate::bootstrap::ConnectAddr;
+use crate::lsp_client::{open_lspmux, read_frame, LSPMUX_PROTOCOL_VERSION};
+
+/// How long the server may take to answer a status request.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a stopped instance may take to exit before it is killed.
+const STOP_GRACE: Duration = Duration::from_secs(5);
+
+/// How often [`stop_instance`] asks whether the instance is gone.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The lspmux server's state, as its `status` method reports it.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct LspmuxStatus {
+    /// The language-server instances the server runs.
+    pub instances: Vec<LspmuxInstance>,
+}
+
+/// One language server lspmux runs, shared by every client that asked for
+/// the same server in the same workspace.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct LspmuxInstance {
+    /// The language server's process id.
+    pub pid: u32,
+    /// The language server binary.
+    pub server: String,
+    /// Its arguments.
+    pub args: Vec<String>,
+    /// The environment it was started with.
+    pub env: BTreeMap<String, String>,
+    /// The workspace root it serves.
+    pub path: String,
+    /// When a client last sent it a message, in seconds since the epoch.
+    pub last_used: i64,
+    /// The clients attached to it.
+    pub clients: Vec<LspmuxInstanceClient>,
+}
+
+/// A client attached to an instance.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct LspmuxInstanceClient {
+    /// The client's port, which tells clients apart.
+    pub port: u16,
+}
+
+/// Ask the lspmux server at `addr` for its state.
+///
+/// # Errors
+///
+/// Returns an error if the server cannot be reached, does not answer
+/// within five seconds, or answers with an error.
+pub async fn lspmux_status(addr: &ConnectAddr) -> Result<LspmuxStatus> {
+    tokio::time::timeout(STATUS_TIMEOUT, request_status(addr))
+        .await
+        .with_context(|| format!("lspmux at {addr} did not report its status"))?
+}
+
+async fn request_status(addr: &ConnectAddr) -> Result<LspmuxStatus> {
+    let (reader, mut writer) = open_lspmux(addr).await?;
+    let request = json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": {
+            "capabilities": {},
+            "initializationOptions": {
+                "lspMux": { "version": LSPMUX_PROTOCOL_VERSION, "method": "status" },
+            },
+        },
+    });
+    let body = serde_json::to_vec(&request)?;
+    writer
+        .write_all(format!("Content-Length: {}\r\n\r\n", body.len()).as_bytes())
+        .await?;
+    writer.write_all(&body).await?;
+    writer.flush().await?;
+
+    let mut reader = BufReader::new(reader);
+    loop {
+        let Some(body) = read_frame(&mut reader).await? else {
+            bail!("lspmux at {addr} closed the connection without a status");
+        };
+        let message: Value = serde_json::from_slice(&body)?;
+        if message.get("id") != Some(&json!(1)) {
+            continue;
+        }
+        if let Some(error) = message.get("error") {
+            bail!("lspmux at {addr} refused the status request: {error}");
+        }
+        let result = message.get("result").cloned().unwrap_or(Value::Null);
+        return serde_json::from_value(result).context("unexpected lspmux status");
+    }
+}
+
+impl LspmuxInstance {
+    /// Seconds since a client last used the instance, as of `now` (seconds
+    /// since the epoch).
+    #[must_use]
+    pub fn idle_secs(&self, now: u64) -> u64 {
+        now.saturating_sub(u64::try_from(self.last_used).unwrap_or(0))
+    }
+}
+
+impl LspmuxStatus {
+    /// The instances no client is attached to that have been idle for at
+    /// least `min_idle` as of `now` (seconds since the epoch).
+    #[must_use]
+    pub fn idle_instances(&self, min_idle: Duration, now: u64) -> Vec<&LspmuxInstance> {
+        self.instances
+            .iter()
+            .filter(|instance| {
+                instance.clients.is_empty() && instance.idle_secs(now) >= min_idle.as_secs()
+            })
+            .collect()
+    }
+}
+
+/// Stop the instance with process id `pid` of the lspmux server at `addr`,
+/// returning it as it was. The process is sent `SIGTERM`, and `SIGKILL` if
+/// it is still listed after five seconds.
+///
+/// # Errors
+///
+/// Returns an error if the server cannot be asked, runs no instance with
+/// that process id, or still lists it after it was killed.
+pub async fn stop_instance(addr: &ConnectAddr, pid: u32) -> Result<LspmuxInstance> {
+    let status = lspmux_status(addr).await?;
+    let Some(instance) = status.instances.into_iter().find(|i| i.pid == pid) else {
+        bail!("lspmux at {addr} runs no language server with pid {pid}");
+    };
+    signal(pid, libc::SIGTERM)?;
+    if wait_gone(addr, pid, STOP_GRACE).await? {
+        return Ok(instance);
+    }
+    tracing::warn!("pid {pid} ignored SIGTERM; killing it");
+    signal(pid, libc::SIGKILL)?;
+    if wait_gone(addr, pid, STOP_GRACE).await? {
+        return Ok(instance);
+    }
+    bail!("lspmux at {addr} still lists pid {pid} after it was killed")
+}
+
+/// Wait up to `limit` for the server to stop listing `pid`; `false` if it
+/// still does.
+async fn wait_gone(addr: &ConnectAddr, pid: u32, limit: Duration) -> Result<bool> {
+    let deadline = tokio::time::Instant::now() + limit;
+    loop {
+        let status = lspmux_status(addr).await?;
+        if !status.instances.iter().any(|i| i.pid == pid) {
+            return Ok(true);
+        }
+        if tokio::time::Instant::now() >= deadline {
+            return Ok(false);
+        }
+        tokio::time::sleep(STOP_POLL_INTERVAL).await;
+    }
+}
+
+/// Send `signal` to the process `pid`; one that already exited is fine.
+fn signal(pid: u32, signal: libc::c_int) -> Result<()> {
+    // A pid of 0 would signal our own process group.
+    let pid = libc::pid_t::try_from(pid)
+        .ok()
+        .filter(|pid| *pid > 0)
+        .with_context(|| format!("{pid} is not a process id"))?;
+    // SAFETY: `kill` only sends a signal; it touches no memory of ours.
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        return Ok(());
+    }
+    let error = std::io::Error::last_os_error();
+    if error.raw_os_error() == Some(libc::ESRCH) {
+        return Ok(());
+    }
+    Err(error).with_context(|| format!("failed to signal pid {pid}"))
+}
+
+/// How long the process `pid` has been running, as `ps` reports it.
+pub async fn process_uptime(pid: u32) -> Option<Duration> {
+    let output = Command::new("ps")
+        .args(["-o", "etime=", "-p", &pid.to_string()])
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_elapsed(String::from_utf8_lossy(&output.stdout).trim())
+}
+
+/// Parse the `[[dd-]hh:]mm:ss` elapsed time of `ps -o etime`.
+fn parse_elapsed(etime: &str) -> Option<Duration> {
+    let (days, clock) = match etime.split_once('-') {
+        Some((days, clock)) => (days.parse::<u64>().ok()?, clock),
+        None => (0, etime),
+    };
+    let fields = clock
+        .split(':')
+        .map(str::parse::<u64>)
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    let (hours, minutes, seconds) = match fields[..] {
+        [minutes, seconds] => (0, minutes, seconds),
+        [hours, minutes, seconds] => (hours, minutes, seconds),
+        _ => return None,
+    };
+    Some(Duration::from_secs(
+        ((days * 24 + hours) * 60 + minutes) * 60 + seconds,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// A fake lspmux server answering every status request with
+    /// `instances()`.
+    async fn fake_lspmux(instances: impl Fn() -> Value + Send + 'static) -> ConnectAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (reader, mut writer) = stream.into_split();
+                let body = read_frame(&mut BufReader::new(reader))
+                    .await
+                    .unwrap()
+                    .unwrap();
+                let request: Value = serde_json::from_slice(&body).unwrap();
+                assert_eq!(
+                    request["params"]["initializationOptions"]["lspMux"]["method"],
+                    "status"
+                );
+                let response = json!({
+                    "jsonrpc": "2.0",
+                    "id": 1,
+                    "result": { "instances": instances() },
+                })
+                .to_string();
+                let frame = format!("Content-Length: {}\r\n\r\n{response}", response.len());
+                writer.write_all(frame.as_bytes()).await.unwrap();
+            }
+        });
+        ConnectAddr::Tcp("127.0.0.1".to_string(), port)
+    }
+
+    #[test]
+    fn elapsed_times_parse_in_every_ps_format() {
+        assert_eq!(parse_elapsed("00:07"), Some(Duration::from_secs(7)));
+        assert_eq!(parse_elapsed("01:02:03"), Some(Duration::from_secs(3723)));
+        assert_eq!(
+            parse_elapsed("2-00:00:01"),
+            Some(Duration::from_secs(2 * 86_400 + 1))
+        );
+        assert_eq!(parse_elapsed("7"), None);
+        assert_eq!(parse_elapsed("a:b"), None);
+    }
+
+    #[tokio::test]
+    async fn the_status_request_reports_shared_and_idle_instances() {
+        let addr = fake_lspmux(|| {
+            json!([
+                {
+                    "pid": 4242,
+                    "server": "rust-analyzer",
+                    "path": "/work/app",
+                    "last_used": 1_000,
+                    "clients": [{ "port": 50_001 }, { "port": 50_002 }],
+                },
+                { "pid": 4343, "path": "/work/old", "last_used": 400 },
+            ])
+        })
+        .await;
+
+        let status = lspmux_status(&addr).await.unwrap();
+        let [shared, abandoned] = &status.instances[..] else {
+            panic!("expected two instances: {status:?}");
+        };
+        assert_eq!(shared.pid, 4242);
+        assert_eq!(shared.path, "/work/app");
+        assert_eq!(shared.clients.len(), 2);
+        assert!(shared.args.is_empty());
+        assert_eq!(abandoned.idle_secs(1_000), 600);
+
+        let idle = status.idle_instances(Duration::from_mins(10), 1_000);
+        assert_eq!(idle, [abandoned]);
+        assert!(status
+            .idle_instances(Duration::from_secs(601), 1_000)
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn a_stopped_instance_exits_and_leaves_the_list() {
+        let mut child = Command::new("sleep").arg("30").spawn().unwrap();
+        let pid = child.id().unwrap();
+        let exited = Arc::new(AtomicBool::new(false));
+        let reaper = {
+            let exited = Arc::clone(&exited);
+            tokio::spawn(async move {
+                child.wait().await.unwrap();
+                exited.store(true, Ordering::Release);
+            })
+        };
+        let listed = Arc::clone(&exited);
+        let addr = fake_lspmux(move || {
+            if listed.load(Ordering::Acquire) {
+                json!([])
+            } else {
+                json!([{ "pid": pid, "path": "/work/app" }])
+            }
+        })
+        .await;
+
+        assert!(stop_instance(&addr, pid + 1).await.is_err());
+        let stopped = stop_instance(&addr, pid).await.unwrap();
+        assert_eq!(stopped.path, "/work/app");
+        reaper.await.unwrap();
+        assert!(signal(0, libc::SIGTERM).is_err());
+    }
+}
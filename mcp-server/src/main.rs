@@ -2,33 +2,98 @@
 //!
 //! Architecture:
 //! ```text
-//! Any MCP host <-MCP (stdio)-> lspmux-cc-mcp <-LSP (child stdio)-> lspmux client <-socket-> lspmux server -> rust-analyzer
+//! Any MCP host <-MCP (stdio | HTTP)-> lspmux-cc-mcp <-LSP (socket)-> lspmux server -> rust-analyzer
 //! ```
+//!
+//! When the lspmux socket cannot be reached, the LSP side falls back to the
+//! stdio of an `lspmux client` child process (see `--lsp-transport`).
+//!
+//! By default the server speaks MCP over stdio for a single host. Pass
+//! `--http <addr>` to serve the streamable HTTP transport at `/mcp` instead,
+//! letting several local clients share one warm rust-analyzer connection,
+//! `--sse <addr>` for clients that only speak the legacy SSE transport, or
+//! `--listen-unix <path>` to accept newline-delimited MCP connections on a
+//! Unix domain socket (for supervisors such as systemd). `--api-listen <addr>`
+//! additionally serves the tools as a plain HTTP/JSON API.
+//!
+//! Run with `--help` for all options, or `--print-config` to see the settings
+//! in effect after flags and environment variables are merged.
+//! `lspmux-cc-mcp doctor` checks the whole chain end to end instead of serving,
+//! `lspmux-cc-mcp call <tool> --json '<params>'` runs a single tool, and
+//! `lspmux-cc-mcp check [paths...]` reports diagnostics for CI.
 
+mod api;
+mod cache;
+mod call;
+mod cargo;
+mod check;
+mod cli;
+mod direct;
+mod edits;
+mod explain;
+mod files;
+mod flycheck;
+mod git;
+mod limits;
+mod metadata;
+mod pagination;
+mod prometheus;
+mod snapshots;
+mod sse;
+mod symbols;
 mod tools;
 
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use clap::Parser;
 use lspmux_cc_mcp::bootstrap::{RuntimeConfig, SERVER_NAME};
-use lspmux_cc_mcp::lsp_client::LspClient;
+use lspmux_cc_mcp::config::FileConfig;
+use lspmux_cc_mcp::health;
+use lspmux_cc_mcp::launcher::LspLauncher;
+use lspmux_cc_mcp::manifests::{ManifestWatcher, MANIFEST_POLL_INTERVAL};
+use lspmux_cc_mcp::pool::LspPool;
 use lspmux_cc_mcp::telemetry::TelemetryState;
+use lspmux_cc_mcp::warmup;
+use lspmux_cc_mcp::watchdog::Watchdog;
+use metrics_exporter_prometheus::PrometheusHandle;
 use rmcp::model::{
-    CallToolRequestParams, CallToolResult, ServerCapabilities, ServerInfo, ToolsCapability,
+    CallToolRequestParams, CallToolResult, JsonObject, ServerCapabilities, ServerInfo,
+    SetLevelRequestParams, ToolsCapability,
 };
-use rmcp::service::{RequestContext, ServiceExt};
+use rmcp::service::{NotificationContext, RequestContext, ServiceExt};
 use rmcp::transport::io::stdio;
+use rmcp::transport::streamable_http_server::session::local::LocalSessionManager;
+use rmcp::transport::{StreamableHttpServerConfig, StreamableHttpService};
 use rmcp::{ErrorData as McpError, RoleServer, ServerHandler};
+use tokio_util::sync::CancellationToken;
 
+use crate::cli::{Cli, Command, Transport};
+use crate::direct::DirectSession;
+use crate::limits::CallLimits;
 use crate::tools::RustAnalyzerTools;
 
+/// How long a signalled shutdown waits for the LSP client to exit cleanly.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
 /// MCP server wrapping the rust-analyzer tools.
 #[derive(Clone)]
 struct LspmuxMcpServer {
     tools: RustAnalyzerTools,
 }
 
+impl LspmuxMcpServer {
+    /// A handler for one new MCP session; all sessions share the LSP client.
+    fn for_session(&self, transport: &str) -> Self {
+        Self {
+            tools: self.tools.for_session(transport),
+        }
+    }
+}
+
 impl ServerHandler for LspmuxMcpServer {
     fn get_info(&self) -> ServerInfo {
         ServerInfo {
@@ -42,32 +107,102 @@ impl ServerHandler for LspmuxMcpServer {
                  rust-analyzer instance through lspmux.\n\
                  \n\
                  Tools:\n\
-                 - rust_diagnostics(file_path): compiler errors and warnings for a file\n\
-                 - rust_hover(file_path, line, character): type info and docs at a position\n\
-                 - rust_goto_definition(file_path, line, character): find definition location\n\
-                 - rust_find_references(file_path, line, character): find all references\n\
+                 - rust_diagnostics(file_path, explain?): compiler errors and warnings for a file; explain attaches `rustc --explain` for error codes\n\
+                 - rust_diagnostics_dir(path): diagnostics for every file under a directory or glob\n\
+                 - rust_diagnostics_changed(path?, new_only?, baseline?): diagnostics for every modified or untracked file in git; use to check work in progress, with new_only to see only what your changes introduced\n\
+                 - rust_diagnostics_snapshot(name?, path?): record the workspace's diagnostics under a name before a fix session\n\
+                 - rust_diagnostics_diff(name?, update?): what was fixed and what was introduced since a snapshot\n\
+                 - rust_wait_for_diagnostics(file_path, timeout_secs?): after an edit, save the file, wait for the cargo check that follows, and return the fresh diagnostics; use instead of sleeping and retrying rust_diagnostics\n\
+                 - rust_run_flycheck(file_path?, timeout_secs?) / rust_cancel_flycheck() / rust_clear_flycheck(): force a fresh cargo check and wait for it, stop a runaway one, or drop stale check diagnostics\n\
+                 - rust_hover(file_path, line+character | symbol): type info and docs at a position\n\
+                 - rust_goto_definition(file_path, line+character | symbol): find definition location\n\
+                 - rust_find_references(file_path, line+character | symbol): find all references\n\
+                 - rust_safe_delete_check(file_path, line+character | symbol, or file_path alone): whether deleting a symbol or file would break other code, with the blocking references\n\
+                 - rust_trait_impls(name | file_path + line + character): a trait's implementors, or the traits a type implements, grouped by name\n\
                  - rust_workspace_symbol(query): find symbols by name across the workspace\n\
+                 - rust_symbol_search(query?, kinds?, crate?): fuzzy search over an in-memory index of every workspace symbol; fast on repeat\n\
+                 - rust_locate(file_path, name, kind?, nth?): zero-based line and character of a symbol's name, to pass to position-based tools\n\
+                 - rust_document_links(file_path): links in a file (doc links, files named by include! and similar) with their URL or local path\n\
+                 - rust_doc(path): docs of an item by path, e.g. `tokio::sync::Mutex::lock`, with its docs.rs URL\n\
+                 - rust_explain_error(code): what an error code such as E0308 means, from `rustc --explain`; use it instead of guessing\n\
                  - rust_server_status(): check server health and active workspace root\n\
+                 - rust_analyzer_health(): binaries, lspmux reachability, indexing progress, and fixes for anything wrong; check it first when tools fail\n\
+                 - lspmux_status(): the rust-analyzer instances lspmux runs, with their workspace roots and attached clients; check it to confirm an editor shares this server's rust-analyzer\n\
+                 - lspmux_restart_instance(pid?): stop a shared rust-analyzer instance (default: this workspace's) for every attached client and reconnect; use when a wedged rust-analyzer also hangs the editor\n\
+                 - lspmux_reap_idle(idle_secs?): stop rust-analyzer instances no client has used for a while, to free memory\n\
+                 - mcp_server_stats(): uptime, calls and p50/p95 latency per tool, cache hit rate, pending LSP requests; check it to spot a slow or failing tool\n\
+                 - rust_warmup(wait_until_ready?): start and prime rust-analyzer before the first query\n\
+                 - rust_open_file(file_path) / rust_close_file(file_path): open a file in rust-analyzer ahead of queries, or close it to free memory once done\n\
+                 - rust_refresh_file(file_path): resend a file edited outside this server when answers look stale\n\
+                 - rust_tracked_documents(): the documents rust-analyzer holds, with versions and whether each changed on disk; use to debug stale answers\n\
+                 - rust_link_project(project_file?, regenerate?): load a Buck/Bazel project from its rust-project.json, optionally regenerating it first\n\
+                 - rust_restart_analyzer(): reconnect to rust-analyzer and reopen the open documents; use when queries keep timing out or rust_server_status reports degraded\n\
+                 - rust_apply_quickfix(file_path, index | code, dry_run?): apply the fix for a diagnostic\n\
+                 - rust_unused_report(path?, fix?, dry_run?): unused imports, variables, and dead code per file with counts; `fix` applies the preferred fixes\n\
+                 - rust_organize_imports(file_path, merge?, dry_run?): remove unused imports and sort the rest\n\
+                 - rust_rename(file_path, line+character | symbol, new_name, apply?): rename a symbol everywhere\n\
+                 - rust_move_file(file_path, new_path, dry_run?): move or rename a source file or directory and fix the mod declarations that refer to it; never move .rs files with a shell command\n\
+                 - rust_extract_function(file_path, start_line, start_character, end_line, end_character, name, dry_run?): extract code into a new function\n\
+                 - rust_inline(file_path, line+character | symbol, title?, dry_run?): inline a variable, call, constant, or alias\n\
+                 - rust_generate(file_path, line+character | symbol, title?, dry_run?): list generators (fill match arms, ...) or apply one\n\
+                 - rust_auto_import(file_path, name | line+character, path?, dry_run?): add the `use` for an unresolved name\n\
+                 - rust_completions(file_path, line+character, prefix?, label? | index?, apply?): list completions, or resolve one for its docs and edits (including auto-import `use` lines) and write them with apply\n\
+                 - rust_execute_command(command, arguments?, dry_run?): run the command of a code action or code lens (rust-analyzer.runSingle, rust-analyzer.gotoLocation, ...) and apply the edits it asks for\n\
+                 - cargo_check(package?, errors_only?): run `cargo check` on the workspace (slower, but build-wide)\n\
+                 - cargo_test(runnable? | file_path + line? | filter?): run tests and get each one's outcome, with the output of failures\n\
+                 - cargo_metadata(package? | file_path? | dependency?): packages, targets, features, and resolved dependencies; which package owns a file; who depends on a crate\n\
+                 - cargo_expand(file_path? | package + item?): a module or crate with all macros expanded (needs cargo-expand installed)\n\
+                 - cargo_tree(package? | invert? | duplicates?): the resolved dependency tree, what pulls a crate in, or crates locked at several versions\n\
                  \n\
-                 Position format: line and character inputs are ZERO-BASED (first line = 0).\n\
-                 Output locations (file:line:col) are ONE-BASED. Subtract 1 from each before\n\
-                 using as input to another tool.\n\
+                 Position format: line and character inputs are ZERO-BASED (first line = 0)\n\
+                 unless index_base: 1 is passed. Output locations (file:line:col) are ONE-BASED:\n\
+                 subtract 1 from each, or pass index_base: 1, before using them as input to\n\
+                 another tool. Instead of line and character, tools that take a position accept\n\
+                 offset (a byte offset into the file) or `symbol`, e.g. `LspClient::request` or\n\
+                 `fn ensure_file_open`; without file_path a symbol is looked up across the workspace.\n\
                  \n\
                  Workflow: run rust_diagnostics after edits to check for errors. If results\n\
                  seem stale, use rust_server_status to check readiness instead of guessing.\n\
-                 All file paths must be absolute. Tools are read-only and workspace-scoped.\n\
+                 All file paths must be absolute. Tools are workspace-scoped. Editing tools\n\
+                 (rust_apply_quickfix, rust_organize_imports, rust_rename, rust_move_file,\n\
+                 rust_extract_function, rust_inline, rust_generate, rust_auto_import, rust_completions)\n\
+                 return a unified diff; they write files unless dry_run is set (rust_rename and\n\
+                 rust_completions only with apply, rust_generate only with a title,\n\
+                 rust_unused_report only with fix). Every other tool is read-only,\n\
+                 except rust_link_project with regenerate, which runs the project's generator,\n\
+                 rust_restart_analyzer, which replaces the rust-analyzer connection, and\n\
+                 rust_execute_command, which runs the command it is given.\n\
+                 rust-analyzer starts on the first query; call rust_warmup early to hide that delay.\n\
                  Use rust_server_status to confirm the correct workspace root and shared-service \
                  bootstrap state."
                     .into(),
             ),
             capabilities: ServerCapabilities {
-                tools: Some(ToolsCapability { list_changed: None }),
+                tools: Some(ToolsCapability {
+                    list_changed: Some(true),
+                }),
+                logging: Some(JsonObject::new()),
                 ..ServerCapabilities::default()
             },
             ..ServerInfo::default()
         }
     }
 
+    async fn on_initialized(&self, context: NotificationContext<RoleServer>) {
+        self.tools.spawn_tool_list_watcher(context.peer.clone());
+        self.tools.spawn_warning_forwarder(context.peer);
+    }
+
+    /// Only watchdog warnings are logged to clients, so every level is
+    /// accepted without changing what is sent.
+    async fn set_level(
+        &self,
+        _request: SetLevelRequestParams,
+        _context: RequestContext<RoleServer>,
+    ) -> std::result::Result<(), McpError> {
+        Ok(())
+    }
+
     async fn list_tools(
         &self,
         _request: Option<rmcp::model::PaginatedRequestParams>,
@@ -85,22 +220,226 @@ impl ServerHandler for LspmuxMcpServer {
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    // Initialize tracing to stderr (stdout is MCP transport)
+/// Serve a single MCP session over stdio until the host disconnects or
+/// `shutdown` is cancelled.
+async fn serve_stdio(server: LspmuxMcpServer, shutdown: CancellationToken) -> Result<()> {
+    let service = match server
+        .for_session("stdio")
+        .serve_with_ct(stdio(), shutdown.child_token())
+        .await
+    {
+        Ok(service) => service,
+        // Signalled before the host finished the MCP handshake.
+        Err(_) if shutdown.is_cancelled() => return Ok(()),
+        Err(e) => return Err(e).context("failed to start MCP server"),
+    };
+    service
+        .waiting()
+        .await
+        .context("MCP server exited with an error")?;
+    Ok(())
+}
+
+/// A token that is cancelled once the process receives SIGINT or SIGTERM.
+fn shutdown_token() -> CancellationToken {
+    let token = CancellationToken::new();
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        match shutdown_signal().await {
+            Ok(signal) => tracing::info!(event = "shutdown_signal", signal),
+            Err(e) => {
+                tracing::warn!("failed to listen for shutdown signals: {e}");
+                return;
+            }
+        }
+        cancel.cancel();
+    });
+    token
+}
+
+#[cfg(unix)]
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    Ok(tokio::select! {
+        _ = interrupt.recv() => "SIGINT",
+        _ = terminate.recv() => "SIGTERM",
+    })
+}
+
+#[cfg(not(unix))]
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    tokio::signal::ctrl_c().await?;
+    Ok("Ctrl-C")
+}
+
+/// Serve the streamable HTTP transport until `shutdown` is cancelled.
+async fn serve_http(
+    server: LspmuxMcpServer,
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let service = StreamableHttpService::new(
+        move || Ok(server.for_session("http")),
+        Arc::new(LocalSessionManager::default()),
+        StreamableHttpServerConfig {
+            cancellation_token: shutdown.child_token(),
+            ..StreamableHttpServerConfig::default()
+        },
+    );
+    let router = axum::Router::new().nest_service("/mcp", service);
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind MCP HTTP listener on {addr}"))?;
+    tracing::info!("serving MCP over streamable HTTP at http://{addr}/mcp");
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .context("MCP HTTP server exited with an error")
+}
+
+/// Serve MCP over a Unix domain socket until `shutdown` is cancelled.
+///
+/// Each accepted connection speaks newline-delimited JSON-RPC (the same framing
+/// as stdio) and becomes its own MCP session. A stale socket file left behind
+/// by a crashed process is replaced; a live one is an error.
+#[cfg(unix)]
+async fn serve_unix(
+    server: LspmuxMcpServer,
+    path: &Path,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!("MCP socket {} is already in use", path.display());
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("failed to remove stale MCP socket {}", path.display()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("failed to bind MCP socket {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("failed to restrict permissions on {}", path.display()))?;
+    tracing::info!("serving MCP on unix socket {}", path.display());
+
+    loop {
+        tokio::select! {
+            () = shutdown.cancelled() => break,
+            accepted = listener.accept() => {
+                let (stream, _) = accepted.context("failed to accept MCP socket connection")?;
+                let server = server.for_session("unix");
+                let ct = shutdown.child_token();
+                tokio::spawn(async move {
+                    match server.serve_with_ct(stream, ct).await {
+                        Ok(running) => {
+                            if let Err(e) = running.waiting().await {
+                                tracing::warn!("unix socket MCP session ended with an error: {e}");
+                            }
+                        }
+                        Err(e) => tracing::warn!("failed to start unix socket MCP session: {e}"),
+                    }
+                });
+            }
+        }
+    }
+
+    if let Err(e) = std::fs::remove_file(path) {
+        tracing::warn!("failed to remove MCP socket {}: {e}", path.display());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(
+    _server: LspmuxMcpServer,
+    path: &Path,
+    _shutdown: CancellationToken,
+) -> Result<()> {
+    bail!(
+        "--listen-unix {} is only supported on Unix platforms",
+        path.display()
+    )
+}
+
+/// Start and prime rust-analyzer in the background for `--warmup`; the
+/// transport comes up without waiting for it.
+fn spawn_warmup(lsp: Arc<LspLauncher>) {
+    tokio::spawn(async move {
+        tracing::info!(event = "lsp_start", trigger = "warmup");
+        match lsp.client().await {
+            Ok(client) => {
+                warmup::prime(&client, warmup::READY_LIMIT).await;
+            }
+            Err(e) => tracing::warn!("startup warm-up failed: {e:#}"),
+        }
+    });
+}
+
+/// Serve `/metrics` for `--metrics-listen` alongside the MCP transport.
+fn spawn_metrics(
+    handle: PrometheusHandle,
+    lsp: Arc<LspLauncher>,
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+) {
+    tokio::spawn(async move {
+        if let Err(e) = prometheus::serve(handle, lsp, addr, shutdown).await {
+            tracing::error!("{e:#}");
+        }
+    });
+}
+
+/// Serve the HTTP API for `--api-listen`, if given, alongside the MCP
+/// transport, through an MCP session of its own.
+fn spawn_api(cli: &Cli, server: &LspmuxMcpServer, shutdown: &CancellationToken) -> Result<()> {
+    let Some(addr) = cli.api_listen else {
+        return Ok(());
+    };
+    let token = api::resolve_token(cli.api_token.clone())?;
+    let handler = server.for_session("api");
+    let shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        let result = match DirectSession::connect(handler, &shutdown).await {
+            Ok(session) => api::serve(session, token, addr, shutdown).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            tracing::error!("{e:#}");
+        }
+    });
+    Ok(())
+}
+
+/// Log to stderr, since stdout may be the MCP transport, with `log_level` as
+/// the filter.
+fn init_tracing(log_level: &str) -> Result<()> {
+    let log_filter = tracing_subscriber::EnvFilter::try_new(log_level)
+        .with_context(|| format!("invalid --log-level filter {log_level:?}"))?;
     tracing_subscriber::fmt()
-        .with_env_filter(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
-        )
+        .with_env_filter(log_filter)
         .with_writer(std::io::stderr)
         .init();
+    Ok(())
+}
 
-    let runtime = RuntimeConfig::discover().context("failed to resolve runtime configuration")?;
-    if std::env::var("WORKSPACE_ROOT").is_err() {
+/// Log the settings the server starts with.
+fn log_startup(
+    cli: &Cli,
+    runtime: &RuntimeConfig,
+    file_config: &FileConfig,
+    file_path: Option<&Path>,
+) {
+    if cli.workspace_root.is_none() && cli.command.is_none() {
         tracing::warn!(
             "WORKSPACE_ROOT env var not set; using current_dir as fallback: {:?}. \
-             Set WORKSPACE_ROOT in your MCP client env for deterministic workspace detection.",
+             Files outside a Cargo workspace there are served from the workspace detected \
+             above them. Set WORKSPACE_ROOT in your MCP client env (or pass \
+             --workspace-root) for deterministic workspace detection.",
             runtime.workspace_root
         );
     } else {
@@ -108,8 +447,154 @@ async fn main() -> Result<()> {
     }
 
     tracing::info!("Starting lspmux-cc-mcp server");
+    if let Some(path) = file_path {
+        tracing::info!("config file: {}", path.display());
+    }
+    for name in file_config.unused_servers() {
+        tracing::warn!(
+            "language server {name:?} in the config file is not served yet; only \
+             {SERVER_NAME} requests are routed"
+        );
+    }
     tracing::info!("lspmux binary: {}", runtime.lspmux_path);
     tracing::info!("{SERVER_NAME} binary: {}", runtime.server_path);
+}
+
+/// Run `lspmux-cc-mcp doctor`, failing if any health check fails.
+async fn run_doctor(
+    lsp: &LspLauncher,
+    config_file: Option<&Path>,
+    wait: Duration,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    println!("lspmux-cc-mcp {} doctor", env!("CARGO_PKG_VERSION"));
+    println!(
+        "config file: {}",
+        config_file.map_or_else(|| "(none)".to_string(), |path| path.display().to_string())
+    );
+    println!(
+        "workspace root: {}",
+        lsp.workspace_root().unwrap_or("(unset)")
+    );
+    println!("bootstrap: {:?}", lsp.runtime().bootstrap_mode);
+    println!();
+
+    let report = tokio::select! {
+        report = health::diagnose(lsp, wait) => report,
+        () = shutdown.cancelled() => {
+            lsp.shutdown().await;
+            bail!("interrupted");
+        }
+    };
+    println!("{}", report.render());
+    lsp.shutdown().await;
+    if !report.healthy {
+        bail!("doctor found problems; apply the fixes above and rerun");
+    }
+    Ok(())
+}
+
+/// Start the watchdog probing `pool` every `interval`, if it is on.
+fn attach_watchdog(
+    tools: RustAnalyzerTools,
+    pool: &Arc<LspPool>,
+    interval: Option<Duration>,
+    stop: &CancellationToken,
+) -> RustAnalyzerTools {
+    let Some(interval) = interval else {
+        return tools;
+    };
+    let watchdog = Watchdog::new(Arc::clone(pool), interval);
+    watchdog.spawn(stop.clone());
+    tools.with_watchdog(watchdog)
+}
+
+/// The tools, configured from `cli` and the `[tools]` section of `file_config`.
+fn build_tools(
+    cli: &Cli,
+    file_config: &FileConfig,
+    pool: &Arc<LspPool>,
+    telemetry: TelemetryState,
+) -> Result<RustAnalyzerTools> {
+    Ok(RustAnalyzerTools::new(Arc::clone(pool), telemetry)
+        .with_disabled_tools(&file_config.tools.disabled)
+        .and_then(|tools| {
+            tools.with_call_limits(CallLimits::new(
+                cli.max_in_flight(),
+                &file_config.tools.limits,
+            ))
+        })
+        .context("invalid [tools] section in config file")?
+        .with_result_budget(cli.max_result_bytes())
+        .with_index_wait(cli.index_wait()))
+}
+
+/// Run the one-shot `call` or `check` subcommand, returning its exit status.
+async fn run_command(
+    command: &Command,
+    server: &LspmuxMcpServer,
+    lsp: &LspLauncher,
+    shutdown: &CancellationToken,
+) -> i32 {
+    let session = server.for_session("cli");
+    match command {
+        Command::Call { tool, json, output } => {
+            call::run(session, tool, json, *output, shutdown).await
+        }
+        Command::Check(args) => {
+            let root = lsp.workspace_root().map_or_else(
+                || std::env::current_dir().unwrap_or_default(),
+                PathBuf::from,
+            );
+            check::run(session, args, &root, shutdown).await
+        }
+        Command::Doctor { .. } => unreachable!("doctor runs before the tools are built"),
+    }
+}
+
+/// Gracefully shut down the LSP child processes, if any were started.
+/// Past the grace period, kill-on-drop takes the children down with us.
+async fn shutdown_pool(pool: &LspPool) {
+    if tokio::time::timeout(SHUTDOWN_GRACE, pool.shutdown())
+        .await
+        .is_err()
+    {
+        tracing::warn!(
+            "LSP shutdown did not finish within {}s; exiting anyway",
+            SHUTDOWN_GRACE.as_secs()
+        );
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let (file_config, file_path) = FileConfig::load(cli.config.as_deref())?;
+    let cli = cli.with_file_defaults(&file_config);
+    let mut runtime =
+        RuntimeConfig::discover().context("failed to resolve runtime configuration")?;
+    cli.apply_to(&mut runtime);
+    if cli.print_config {
+        print!(
+            "{}",
+            cli.render_config(&runtime, &file_config, file_path.as_deref())?
+        );
+        return Ok(());
+    }
+    let transport = cli.transport()?;
+
+    init_tracing(cli.log_level())?;
+    if let Err(e) = runtime.provision_config() {
+        tracing::warn!("could not provision an lspmux config: {e:#}");
+    }
+
+    // Installed before anything records, so no early samples are lost.
+    let metrics = cli
+        .metrics_listen
+        .map(|_| prometheus::install())
+        .transpose()?;
+
+    log_startup(&cli, &runtime, &file_config, file_path.as_deref());
 
     let telemetry = TelemetryState::from_env();
     tracing::info!(
@@ -119,77 +604,69 @@ async fn main() -> Result<()> {
         session_id = %telemetry.client_identity().session_id
     );
 
-    let bootstrap_started = Instant::now();
-    let runtime_status = match runtime.ensure_service_running().await {
-        Ok(status) => {
-            let bootstrap_latency_ms =
-                u64::try_from(bootstrap_started.elapsed().as_millis()).unwrap_or(u64::MAX);
-            telemetry.record_bootstrap_success(
-                match status.service_mode {
-                    lspmux_cc_mcp::bootstrap::ServiceMode::Reused => "reused",
-                    lspmux_cc_mcp::bootstrap::ServiceMode::StartedViaManager => {
-                        "started_via_manager"
-                    }
-                    lspmux_cc_mcp::bootstrap::ServiceMode::StartedDirectly => "started_directly",
-                    lspmux_cc_mcp::bootstrap::ServiceMode::Skipped => "skipped",
-                },
-                bootstrap_latency_ms,
-            );
-            tracing::info!(
-                event = "bootstrap_result",
-                service_mode = ?status.service_mode,
-                latency_ms = bootstrap_latency_ms
-            );
-            status
-        }
-        Err(error) => {
-            let bootstrap_latency_ms =
-                u64::try_from(bootstrap_started.elapsed().as_millis()).unwrap_or(u64::MAX);
-            telemetry.record_bootstrap_failure(
-                "prepare_service",
-                &error.to_string(),
-                bootstrap_latency_ms,
-            );
-            tracing::error!(
-                event = "bootstrap_result",
-                outcome = "failure",
-                error = %error,
-                latency_ms = bootstrap_latency_ms
-            );
-            return Err(error).context("failed to prepare shared lspmux service");
-        }
-    };
-
-    // Initialize LSP client
-    let lsp = LspClient::new(
-        &runtime.lspmux_path,
-        &runtime.server_path,
-        runtime.workspace_root.as_deref(),
-    )
-    .await
-    .context("failed to initialize LSP client")?;
+    // lspmux and rust-analyzer are started by the first tool call that needs them.
+    let lsp = LspLauncher::new(runtime, telemetry.clone())
+        .with_initialization_options(file_config.rust_analyzer.initialization_options.clone())
+        .with_server_env(file_config.rust_analyzer.server_env())
+        .with_analysis_target_dir(cli.analysis_target_dir().map(ToOwned::to_owned))
+        .with_request_timeouts(cli.request_timeouts(&file_config))
+        .with_file_size_limit(cli.file_size_limit())
+        .with_document_budget(cli.document_budget())
+        .with_record_dir(cli.record.clone())
+        .with_wiretap(cli.wiretap.clone());
+    let shutdown = shutdown_token();
+    if let Some(Command::Doctor { wait }) = cli.command {
+        return run_doctor(
+            &lsp,
+            file_path.as_deref(),
+            Duration::from_secs(wait),
+            shutdown,
+        )
+        .await;
+    }
 
     let lsp = Arc::new(lsp);
-    let tools = RustAnalyzerTools::new(Arc::clone(&lsp), runtime_status, telemetry);
+    let pool = Arc::new(LspPool::new(Arc::clone(&lsp)));
+    let tools = build_tools(&cli, &file_config, &pool, telemetry)?;
+    // Stopped before the clients are shut down, so none is restarted then.
+    let watchdog_stop = shutdown.child_token();
+    let tools = attach_watchdog(tools, &pool, cli.watchdog_interval(), &watchdog_stop);
+    ManifestWatcher::new(Arc::clone(&pool), MANIFEST_POLL_INTERVAL).spawn(watchdog_stop.clone());
     let server = LspmuxMcpServer { tools };
+    if let Some(command) = &cli.command {
+        let code = run_command(command, &server, &lsp, &shutdown).await;
+        watchdog_stop.cancel();
+        shutdown_pool(&pool).await;
+        std::process::exit(code);
+    }
+    if cli.warmup {
+        spawn_warmup(Arc::clone(&lsp));
+    }
+    if let (Some(handle), Some(addr)) = (metrics, cli.metrics_listen) {
+        spawn_metrics(handle, Arc::clone(&lsp), addr, shutdown.clone());
+    }
+    spawn_api(&cli, &server, &shutdown)?;
 
-    // Start MCP server on stdio
-    let transport = stdio();
-    let service = match server.serve(transport).await {
-        Ok(service) => service,
-        Err(e) => {
-            lsp.shutdown().await;
-            return Err(e).context("failed to start MCP server");
+    let stdio = matches!(transport, Transport::Stdio);
+    let serve_result = match transport {
+        Transport::Stdio => serve_stdio(server, shutdown.clone()).await,
+        Transport::Http(addr) => serve_http(server, addr, shutdown.clone()).await,
+        Transport::Sse(addr) => {
+            sse::serve(move || server.for_session("sse"), addr, shutdown.clone()).await
         }
+        Transport::Unix(path) => serve_unix(server, &path, shutdown.clone()).await,
     };
 
-    // Wait for the service to finish
-    let waiting_result = service.waiting().await;
-
-    // Gracefully shut down LSP child process
-    lsp.shutdown().await;
-
-    waiting_result.context("MCP server exited with an error")?;
+    watchdog_stop.cancel();
+    shutdown_pool(&pool).await;
 
-    Ok(())
+    if stdio && shutdown.is_cancelled() {
+        // tokio reads stdin on a blocking thread that only returns on input
+        // or EOF, and dropping the runtime would wait for it.
+        if let Err(e) = &serve_result {
+            tracing::error!("{e:#}");
+        }
+        std::process::exit(i32::from(serve_result.is_err()));
+    }
+    serve_result
 }
@@ -0,0 +1,214 @@
+//! Reloading rust-analyzer's workspace when its manifests change.
+//!
+//! An agent that adds a dependency edits a `Cargo.toml`, and cargo rewrites
+//! `Cargo.lock`; until rust-analyzer reloads the workspace, the new crate's
+//! symbols do not resolve. The [`ManifestWatcher`] polls the manifests, the
+//! lockfile, and any `rust-project.json` of each started client's workspace.
+//! Once a change has held for a whole poll, so a burst of edits costs one
+//! reload, it sends `rust-analyzer/reloadWorkspace`, which also drops the
+//! responses cached under the old project model.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use metrics::counter;
+use tokio_util::sync::CancellationToken;
+
+use crate::launcher::RUST_PROJECT_FILE;
+use crate::pool::LspPool;
+use crate::warmup::package_dirs;
+
+/// How often the manifests are checked for changes.
+pub const MANIFEST_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The files whose changes call for a reload: the `Cargo.toml` of every
+/// package under `workspace`, the lockfile, and `rust-project.json`.
+#[must_use]
+pub fn manifest_files(workspace: &Path) -> Vec<PathBuf> {
+    let mut files = package_dirs(workspace)
+        .into_iter()
+        .map(|dir| dir.join("Cargo.toml"))
+        .collect::<Vec<_>>();
+    files.push(workspace.join("Cargo.lock"));
+    files.push(workspace.join(RUST_PROJECT_FILE));
+    files.sort();
+    files.dedup();
+    files
+}
+
+/// The size and modification time of each file; `None` for missing ones.
+fn fingerprint(files: &[PathBuf]) -> Vec<Option<(u64, SystemTime)>> {
+    files
+        .iter()
+        .map(|file| {
+            let metadata = std::fs::metadata(file).ok()?;
+            Some((metadata.len(), metadata.modified().ok()?))
+        })
+        .collect()
+}
+
+/// The manifests of one workspace, as last seen.
+#[derive(Debug)]
+struct Watched {
+    root: PathBuf,
+    files: Vec<PathBuf>,
+    fingerprint: Vec<Option<(u64, SystemTime)>>,
+    /// Whether the files changed at the previous poll.
+    changing: bool,
+}
+
+impl Watched {
+    fn new(root: &Path) -> Self {
+        let files = manifest_files(root);
+        Self {
+            root: root.to_path_buf(),
+            fingerprint: fingerprint(&files),
+            files,
+            changing: false,
+        }
+    }
+
+    /// Look at the files again; `true` once they changed and then held
+    /// still for a poll, when the workspace should be reloaded.
+    fn poll(&mut self) -> bool {
+        let current = fingerprint(&self.files);
+        if current != self.fingerprint {
+            self.fingerprint = current;
+            self.changing = true;
+            return false;
+        }
+        if !self.changing {
+            return false;
+        }
+        // Packages may have been added or removed; look for them again.
+        *self = Self::new(&self.root);
+        true
+    }
+}
+
+/// Watches the manifests of every started client in a pool.
+#[derive(Clone)]
+pub struct ManifestWatcher {
+    pool: Arc<LspPool>,
+    interval: Duration,
+}
+
+impl ManifestWatcher {
+    /// A watcher checking the workspaces of `pool` each `interval`.
+    #[must_use]
+    pub const fn new(pool: Arc<LspPool>, interval: Duration) -> Self {
+        Self { pool, interval }
+    }
+
+    /// Watch in the background until `shutdown` is cancelled.
+    pub fn spawn(&self, shutdown: CancellationToken) {
+        let watcher = self.clone();
+        tokio::spawn(async move {
+            let mut workspaces = HashMap::new();
+            let mut interval = tokio::time::interval(watcher.interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    () = shutdown.cancelled() => return,
+                    _ = interval.tick() => {}
+                }
+                tokio::select! {
+                    () = shutdown.cancelled() => return,
+                    () = watcher.poll_all(&mut workspaces) => {}
+                }
+            }
+        });
+    }
+
+    /// Check the workspace of every started client once, reloading those
+    /// whose manifests settled after a change. `watched` is keyed by
+    /// launcher.
+    async fn poll_all(&self, watched: &mut HashMap<usize, Watched>) {
+        let launchers = self.pool.launchers();
+        watched.retain(|key, _| {
+            launchers
+                .iter()
+                .any(|launcher| Arc::as_ptr(launcher) as usize == *key)
+        });
+        for launcher in launchers {
+            let (Some(client), Some(root)) = (launcher.get(), launcher.workspace_root()) else {
+                continue;
+            };
+            let key = Arc::as_ptr(&launcher) as usize;
+            let Some(entry) = watched.get_mut(&key) else {
+                // The first look only records what the files are like.
+                watched.insert(key, Watched::new(Path::new(root)));
+                continue;
+            };
+            if !entry.poll() {
+                continue;
+            }
+            counter!("lspmux_cc_workspace_reloads_total").increment(1);
+            match client.reload_workspace().await {
+                Ok(()) => tracing::info!(event = "workspace_reloaded", root),
+                Err(e) => tracing::warn!("failed to reload the workspace at {root}: {e:#}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock_lsp;
+
+    #[test]
+    fn a_change_triggers_one_reload_once_it_settles() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::write(root.join("Cargo.toml"), "[workspace]\n").unwrap();
+        std::fs::create_dir_all(root.join("app")).unwrap();
+        std::fs::write(root.join("app/Cargo.toml"), "[package]\n").unwrap();
+        assert_eq!(
+            manifest_files(root),
+            [
+                root.join("Cargo.lock"),
+                root.join("Cargo.toml"),
+                root.join("app/Cargo.toml"),
+                root.join(RUST_PROJECT_FILE),
+            ]
+        );
+
+        let mut watched = Watched::new(root);
+        assert!(!watched.poll());
+        std::fs::write(root.join("app/Cargo.toml"), "[package]\nname = \"app\"\n").unwrap();
+        assert!(!watched.poll());
+        std::fs::write(root.join("Cargo.lock"), "version = 4\n").unwrap();
+        assert!(!watched.poll());
+        assert!(watched.poll());
+        assert!(!watched.poll());
+
+        // A package added since is watched after the reload.
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(root.join("lib/Cargo.toml"), "[package]\n").unwrap();
+        std::fs::write(root.join("Cargo.toml"), "[workspace]\nmembers = [\"*\"]\n").unwrap();
+        assert!(!watched.poll());
+        assert!(watched.poll());
+        assert!(watched.files.contains(&root.join("lib/Cargo.toml")));
+    }
+
+    #[tokio::test]
+    async fn a_reload_moves_to_a_new_generation() {
+        let (client, mut server) = mock_lsp::connect(json!({})).await.unwrap();
+        let generation = client.generation();
+        let answer = async {
+            let (id, _) = server
+                .expect_request("rust-analyzer/reloadWorkspace")
+                .await
+                .unwrap();
+            server.respond(id, json!(null)).await.unwrap();
+        };
+        let (reloaded, ()) = tokio::join!(client.reload_workspace(), answer);
+        reloaded.unwrap();
+        assert!(client.generation() > generation);
+    }
+}
@@ -0,0 +1,624 @@
+//! `cargo metadata` for the `cargo_metadata` tool.
+//!
+//! Running `cargo metadata` takes a second or more on a large workspace, but
+//! its answer only changes with the manifests and the lockfile. The parsed
+//! result is kept until the content of one of those files changes.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rmcp::ErrorData as McpError;
+use serde::Deserialize;
+use tokio::sync::Mutex;
+
+use crate::cargo;
+
+/// The output of `cargo metadata --format-version 1`, reduced to what the
+/// tool reports.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_members: Vec<String>,
+    pub resolve: Option<Resolve>,
+    pub workspace_root: String,
+    pub target_directory: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    pub id: String,
+    /// Where the package comes from; `None` for local (path) packages.
+    pub source: Option<String>,
+    pub manifest_path: String,
+    pub edition: String,
+    pub features: BTreeMap<String, Vec<String>>,
+    pub targets: Vec<Target>,
+    pub dependencies: Vec<Dependency>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Target {
+    pub name: String,
+    /// `lib`, `bin`, `test`, `example`, `bench`, `custom-build`, ...
+    pub kind: Vec<String>,
+    pub src_path: String,
+}
+
+/// A dependency as the manifest declares it.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Dependency {
+    pub name: String,
+    pub req: String,
+    /// `dev` or `build`; `None` for normal dependencies.
+    pub kind: Option<String>,
+    pub optional: bool,
+    pub rename: Option<String>,
+}
+
+/// The resolved dependency graph.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Resolve {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Node {
+    pub id: String,
+    pub deps: Vec<NodeDep>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct NodeDep {
+    pub pkg: String,
+    #[serde(default)]
+    pub dep_kinds: Vec<DepKind>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DepKind {
+    /// `dev` or `build`; `None` for normal dependencies.
+    pub kind: Option<String>,
+}
+
+impl NodeDep {
+    /// `normal` if the edge is a normal dependency for any platform, else
+    /// `build` or `dev`.
+    fn kind(&self) -> &str {
+        if self.dep_kinds.iter().any(|kind| kind.kind.is_none()) {
+            return "normal";
+        }
+        self.dep_kinds
+            .iter()
+            .find_map(|kind| kind.kind.as_deref())
+            .unwrap_or("normal")
+    }
+}
+
+/// One line of a dependency tree.
+#[derive(Clone, Debug)]
+pub struct TreeEntry<'a> {
+    /// 0 for the roots.
+    pub depth: usize,
+    pub package: &'a Package,
+    /// How the parent depends on the package (or, inverted, the package on
+    /// its parent): `normal`, `build`, or `dev`.
+    pub kind: &'a str,
+    /// Whether the package's subtree was shown earlier and is left out here.
+    pub repeated: bool,
+}
+
+/// A package found at several versions in one dependency graph.
+#[derive(Clone, Debug)]
+pub struct Duplicate<'a> {
+    pub name: &'a str,
+    /// Each version, with the packages that depend on it.
+    pub versions: Vec<(&'a Package, Vec<&'a Package>)>,
+}
+
+impl Dependency {
+    /// Whether `name` names this dependency, by package name or rename, with
+    /// `-` and `_` treated alike.
+    pub fn is_named(&self, name: &str) -> bool {
+        let name = Self::normalize(name);
+        Self::normalize(&self.name) == name
+            || self
+                .rename
+                .as_ref()
+                .is_some_and(|rename| Self::normalize(rename) == name)
+    }
+
+    /// `name` with `-` spelled `_`, as the crate is named in code.
+    pub fn normalize(name: &str) -> String {
+        name.replace('-', "_")
+    }
+}
+
+impl Metadata {
+    /// The workspace's own packages.
+    pub fn members(&self) -> impl Iterator<Item = &Package> {
+        self.packages
+            .iter()
+            .filter(|package| self.workspace_members.contains(&package.id))
+    }
+
+    /// The version the lockfile resolved `dependency` of `package` to.
+    pub fn resolved_version(&self, package: &Package, dependency: &Dependency) -> Option<&str> {
+        let node = self
+            .resolve
+            .as_ref()?
+            .nodes
+            .iter()
+            .find(|node| node.id == package.id)?;
+        node.deps
+            .iter()
+            .filter_map(|dep| self.package(&dep.pkg))
+            .find(|resolved| resolved.name == dependency.name)
+            .map(|resolved| resolved.version.as_str())
+    }
+
+    fn package(&self, id: &str) -> Option<&Package> {
+        self.packages.iter().find(|package| package.id == id)
+    }
+
+    /// Each package's dependency edges, as `(package id, edge)`, or with
+    /// `inverted` its dependents' edges.
+    fn edges(&self, inverted: bool) -> HashMap<&str, Vec<(&str, &NodeDep)>> {
+        let mut edges: HashMap<&str, Vec<(&str, &NodeDep)>> = HashMap::new();
+        for node in self.resolve.iter().flat_map(|resolve| &resolve.nodes) {
+            for dep in &node.deps {
+                let (from, to) = if inverted {
+                    (dep.pkg.as_str(), node.id.as_str())
+                } else {
+                    (node.id.as_str(), dep.pkg.as_str())
+                };
+                edges.entry(from).or_default().push((to, dep));
+            }
+        }
+        for children in edges.values_mut() {
+            children.sort_by_key(|(id, _)| *id);
+        }
+        edges
+    }
+
+    /// The dependency tree under `roots` (package ids) down to `max_depth`,
+    /// or with `inverted` the tree of what depends on them. Like `cargo
+    /// tree`, a package's subtree is shown once and marked as repeated after.
+    pub fn tree(
+        &self,
+        roots: &[&str],
+        max_depth: Option<usize>,
+        inverted: bool,
+    ) -> Vec<TreeEntry<'_>> {
+        fn walk<'a>(
+            metadata: &'a Metadata,
+            edges: &HashMap<&str, Vec<(&'a str, &'a NodeDep)>>,
+            (id, kind, depth): (&'a str, &'a str, usize),
+            max_depth: Option<usize>,
+            shown: &mut HashSet<&'a str>,
+            entries: &mut Vec<TreeEntry<'a>>,
+        ) {
+            let Some(package) = metadata.package(id) else {
+                return;
+            };
+            let children = edges.get(id).map_or(&[][..], Vec::as_slice);
+            let expand = max_depth.is_none_or(|max| depth < max) && !children.is_empty();
+            let repeated = expand && !shown.insert(id);
+            entries.push(TreeEntry {
+                depth,
+                package,
+                kind,
+                repeated,
+            });
+            if expand && !repeated {
+                for (child, dep) in children {
+                    let next = (*child, dep.kind(), depth + 1);
+                    walk(metadata, edges, next, max_depth, shown, entries);
+                }
+            }
+        }
+
+        let edges = self.edges(inverted);
+        let mut shown = HashSet::new();
+        let mut entries = Vec::new();
+        for root in roots {
+            let Some(root) = self.package(root) else {
+                continue;
+            };
+            let start = (root.id.as_str(), "normal", 0);
+            walk(self, &edges, start, max_depth, &mut shown, &mut entries);
+        }
+        entries
+    }
+
+    /// The packages present at more than one version, by name.
+    pub fn duplicates(&self) -> Vec<Duplicate<'_>> {
+        let dependents = self.edges(true);
+        let mut by_name: BTreeMap<&str, Vec<&Package>> = BTreeMap::new();
+        for package in &self.packages {
+            by_name.entry(&package.name).or_default().push(package);
+        }
+        by_name
+            .into_iter()
+            .filter(|(_, packages)| packages.len() > 1)
+            .map(|(name, mut packages)| {
+                packages.sort_by_key(|package| version_key(&package.version));
+                let versions = packages
+                    .into_iter()
+                    .map(|package| {
+                        let parents = dependents
+                            .get(package.id.as_str())
+                            .into_iter()
+                            .flatten()
+                            .filter_map(|(parent, _)| self.package(parent))
+                            .collect();
+                        (package, parents)
+                    })
+                    .collect();
+                Duplicate { name, versions }
+            })
+            .collect()
+    }
+
+    /// Every version of the package `name` in the dependency graph.
+    pub fn versions_of(&self, name: &str) -> Vec<&str> {
+        let name = Dependency::normalize(name);
+        let mut versions: Vec<&str> = self
+            .packages
+            .iter()
+            .filter(|package| Dependency::normalize(&package.name) == name)
+            .map(|package| package.version.as_str())
+            .collect();
+        versions.sort_unstable();
+        versions.dedup();
+        versions
+    }
+
+    /// The package whose directory holds `file_path`, with the targets whose
+    /// source directory is nearest to it (`src/lib.rs` and `src/main.rs` both
+    /// own `src/parser.rs`).
+    pub fn owner(&self, file_path: &Path) -> Option<(&Package, Vec<&Target>)> {
+        let package = self
+            .packages
+            .iter()
+            .filter_map(|package| {
+                let dir = Path::new(&package.manifest_path).parent()?;
+                file_path
+                    .starts_with(dir)
+                    .then(|| (dir.components().count(), package))
+            })
+            .max_by_key(|(depth, _)| *depth)?
+            .1;
+        let depths: Vec<(usize, &Target)> = package
+            .targets
+            .iter()
+            .filter_map(|target| {
+                let dir = Path::new(&target.src_path).parent()?;
+                file_path
+                    .starts_with(dir)
+                    .then(|| (dir.components().count(), target))
+            })
+            .collect();
+        let nearest = depths.iter().map(|(depth, _)| *depth).max();
+        let targets = depths
+            .into_iter()
+            .filter(|(depth, _)| Some(*depth) == nearest)
+            .map(|(_, target)| target)
+            .collect();
+        Some((package, targets))
+    }
+
+    /// The files whose content the metadata depends on: the manifests of the
+    /// local packages and the workspace's lockfile.
+    fn inputs(&self) -> Vec<PathBuf> {
+        let mut inputs: Vec<PathBuf> = self
+            .packages
+            .iter()
+            .filter(|package| package.source.is_none())
+            .map(|package| PathBuf::from(&package.manifest_path))
+            .collect();
+        inputs.push(Path::new(&self.workspace_root).join("Cargo.toml"));
+        inputs.push(Path::new(&self.workspace_root).join("Cargo.lock"));
+        inputs.sort();
+        inputs.dedup();
+        inputs
+    }
+}
+
+/// `version`'s numeric parts, so that 0.10.0 sorts after 0.9.0.
+fn version_key(version: &str) -> Vec<u64> {
+    version
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// The module path of `file_path` inside the crate rooted at `root`
+/// (`src/parser/mod.rs` is `parser`); empty for the root itself.
+pub fn module_path(root: &Path, file_path: &Path) -> Option<String> {
+    if file_path == root {
+        return Some(String::new());
+    }
+    let relative = file_path.strip_prefix(root.parent()?).ok()?;
+    let mut segments: Vec<String> = relative
+        .with_extension("")
+        .iter()
+        .map(|segment| segment.to_string_lossy().into_owned())
+        .collect();
+    if segments.last().is_some_and(|last| last == "mod") {
+        segments.pop();
+    }
+    Some(segments.join("::"))
+}
+
+/// A hash of the content of `files`; a missing file hashes differently from
+/// an empty one.
+fn stamp(files: &[PathBuf]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for file in files {
+        file.hash(&mut hasher);
+        std::fs::read(file).ok().hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+struct Cached {
+    root: PathBuf,
+    stamp: u64,
+    metadata: Arc<Metadata>,
+}
+
+/// The metadata of the last workspace asked about, shared by every MCP
+/// session.
+#[derive(Clone, Default)]
+pub struct MetadataCache {
+    state: Arc<Mutex<Option<Cached>>>,
+}
+
+impl MetadataCache {
+    /// The metadata of the workspace around `root`, and whether it came from
+    /// the cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `cargo metadata` fails, times out, or prints
+    /// something other than metadata.
+    pub async fn get(
+        &self,
+        root: &Path,
+        timeout: Duration,
+    ) -> Result<(Arc<Metadata>, bool), McpError> {
+        // Held while cargo runs, so concurrent calls share one run.
+        let mut state = self.state.lock().await;
+        if let Some(cached) = state.as_ref().filter(|cached| {
+            cached.root == root && cached.stamp == stamp(&cached.metadata.inputs())
+        }) {
+            return Ok((cached.metadata.clone(), true));
+        }
+
+        let args = ["metadata", "--format-version", "1"].map(String::from);
+        let output = cargo::run(root, &args, timeout).await?;
+        if !output.success {
+            return Err(McpError::internal_error(
+                format!("`cargo metadata` failed:\n{}", output.stderr.trim_end()),
+                None,
+            ));
+        }
+        let metadata: Metadata = serde_json::from_str(&output.stdout).map_err(|e| {
+            McpError::internal_error(format!("unexpected `cargo metadata` output: {e}"), None)
+        })?;
+        let metadata = Arc::new(metadata);
+        *state = Some(Cached {
+            root: root.to_path_buf(),
+            stamp: stamp(&metadata.inputs()),
+            metadata: metadata.clone(),
+        });
+        drop(state);
+        Ok((metadata, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> Metadata {
+        serde_json::from_value(serde_json::json!({
+            "packages": [
+                {
+                    "name": "app",
+                    "version": "0.1.0",
+                    "id": "path+file:///ws/app#0.1.0",
+                    "source": null,
+                    "manifest_path": "/ws/app/Cargo.toml",
+                    "edition": "2021",
+                    "features": {"default": ["json"], "json": ["dep:serde_json"]},
+                    "targets": [
+                        {"name": "app", "kind": ["lib"], "src_path": "/ws/app/src/lib.rs"},
+                        {"name": "app", "kind": ["bin"], "src_path": "/ws/app/src/main.rs"},
+                        {"name": "it", "kind": ["test"], "src_path": "/ws/app/tests/it.rs"}
+                    ],
+                    "dependencies": [
+                        {"name": "serde", "req": "^1", "kind": null, "optional": false, "rename": null},
+                        {"name": "serde_json", "req": "^1", "kind": null, "optional": true, "rename": "json"}
+                    ]
+                },
+                {
+                    "name": "serde",
+                    "version": "1.0.210",
+                    "id": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.210",
+                    "source": "registry+https://github.com/rust-lang/crates.io-index",
+                    "manifest_path": "/home/u/.cargo/registry/src/serde-1.0.210/Cargo.toml",
+                    "edition": "2018",
+                    "features": {},
+                    "targets": [],
+                    "dependencies": []
+                }
+            ],
+            "workspace_members": ["path+file:///ws/app#0.1.0"],
+            "resolve": {"nodes": [{
+                "id": "path+file:///ws/app#0.1.0",
+                "deps": [{"pkg": "registry+https://github.com/rust-lang/crates.io-index#serde@1.0.210"}]
+            }]},
+            "workspace_root": "/ws",
+            "target_directory": "/ws/target"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn metadata_answers_ownership_and_dependency_questions() {
+        let metadata = fixture();
+        let app = metadata.members().next().unwrap();
+        assert_eq!(app.name, "app");
+        assert_eq!(
+            metadata.resolved_version(app, &app.dependencies[0]),
+            Some("1.0.210")
+        );
+        assert_eq!(metadata.resolved_version(app, &app.dependencies[1]), None);
+        assert!(app.dependencies[1].is_named("json"));
+        assert!(app.dependencies[1].is_named("serde-json"));
+        assert_eq!(metadata.versions_of("serde"), ["1.0.210"]);
+
+        let (owner, targets) = metadata.owner(Path::new("/ws/app/src/parser.rs")).unwrap();
+        assert_eq!(owner.name, "app");
+        let kinds: Vec<&str> = targets.iter().map(|t| t.kind[0].as_str()).collect();
+        assert_eq!(kinds, ["lib", "bin"]);
+        assert!(metadata.owner(Path::new("/elsewhere/main.rs")).is_none());
+    }
+
+    /// A graph where `app` uses `http` 1 (which uses `bytes` 0.9) and
+    /// `bytes` 0.10 directly, and `tempfile` as a dev-dependency.
+    fn graph() -> Metadata {
+        let package = |name: &str, version: &str| {
+            serde_json::json!({
+                "name": name,
+                "version": version,
+                "id": format!("{name}@{version}"),
+                "source": null,
+                "manifest_path": format!("/ws/{name}-{version}/Cargo.toml"),
+                "edition": "2021",
+                "features": {},
+                "targets": [],
+                "dependencies": []
+            })
+        };
+        let dep = |pkg: &str, kind: Option<&str>| serde_json::json!({"pkg": pkg, "dep_kinds": [{"kind": kind, "target": null}]});
+        serde_json::from_value(serde_json::json!({
+            "packages": [
+                package("app", "0.1.0"),
+                package("http", "1.0.0"),
+                package("bytes", "0.9.0"),
+                package("bytes", "0.10.0"),
+                package("tempfile", "3.0.0"),
+            ],
+            "workspace_members": ["app@0.1.0"],
+            "resolve": {"nodes": [
+                {"id": "app@0.1.0", "deps": [
+                    dep("bytes@0.10.0", None),
+                    dep("http@1.0.0", None),
+                    dep("tempfile@3.0.0", Some("dev")),
+                ]},
+                {"id": "http@1.0.0", "deps": [dep("bytes@0.9.0", None)]},
+                {"id": "bytes@0.9.0", "deps": []},
+                {"id": "bytes@0.10.0", "deps": []},
+                {"id": "tempfile@3.0.0", "deps": [dep("bytes@0.10.0", None)]},
+            ]},
+            "workspace_root": "/ws",
+            "target_directory": "/ws/target"
+        }))
+        .unwrap()
+    }
+
+    fn lines(entries: &[TreeEntry<'_>]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|entry| {
+                format!(
+                    "{}{} {} {}{}",
+                    "  ".repeat(entry.depth),
+                    entry.package.name,
+                    entry.package.version,
+                    entry.kind,
+                    if entry.repeated { " (*)" } else { "" }
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn trees_show_each_subtree_once_and_invert() {
+        let metadata = graph();
+        assert_eq!(
+            lines(&metadata.tree(&["app@0.1.0"], None, false)),
+            [
+                "app 0.1.0 normal",
+                "  bytes 0.10.0 normal",
+                "  http 1.0.0 normal",
+                "    bytes 0.9.0 normal",
+                "  tempfile 3.0.0 dev",
+                "    bytes 0.10.0 normal",
+            ]
+        );
+        assert_eq!(
+            lines(&metadata.tree(&["app@0.1.0"], Some(1), false)).len(),
+            4
+        );
+        assert_eq!(
+            lines(&metadata.tree(&["bytes@0.10.0"], None, true)),
+            [
+                "bytes 0.10.0 normal",
+                "  app 0.1.0 normal",
+                "  tempfile 3.0.0 normal",
+                "    app 0.1.0 dev",
+            ]
+        );
+
+        let duplicates = metadata.duplicates();
+        assert_eq!(duplicates.len(), 1);
+        let versions: Vec<(&str, Vec<&str>)> = duplicates[0]
+            .versions
+            .iter()
+            .map(|(package, parents)| {
+                let parents = parents.iter().map(|p| p.name.as_str()).collect();
+                (package.version.as_str(), parents)
+            })
+            .collect();
+        assert_eq!(
+            versions,
+            [("0.9.0", vec!["http"]), ("0.10.0", vec!["app", "tempfile"])]
+        );
+    }
+
+    #[test]
+    fn module_paths_follow_the_file_layout() {
+        let root = Path::new("/ws/app/src/lib.rs");
+        let module = |path: &str| module_path(root, Path::new(path));
+        assert_eq!(module("/ws/app/src/lib.rs").as_deref(), Some(""));
+        assert_eq!(module("/ws/app/src/parser.rs").as_deref(), Some("parser"));
+        assert_eq!(
+            module("/ws/app/src/parser/ast/mod.rs").as_deref(),
+            Some("parser::ast")
+        );
+        assert_eq!(module("/ws/app/tests/it.rs"), None);
+    }
+
+    #[test]
+    fn stamps_change_with_the_lockfile() {
+        let dir = tempfile::tempdir().unwrap();
+        let files = [dir.path().join("Cargo.toml"), dir.path().join("Cargo.lock")];
+        std::fs::write(&files[0], "[package]").unwrap();
+        let missing = stamp(&files);
+        std::fs::write(&files[1], "").unwrap();
+        let empty = stamp(&files);
+        assert_ne!(missing, empty);
+        assert_eq!(empty, stamp(&files));
+        std::fs::write(&files[1], "version = 4").unwrap();
+        assert_ne!(empty, stamp(&files));
+    }
+}
@@ -0,0 +1,217 @@
+//! A scriptable in-process LSP server for tests.
+//!
+//! [`connect`] joins an [`LspClient`] to a [`MockServer`] over in-memory
+//! pipes. The test then plays the server's side one message at a time:
+//! expect a request and answer it, push notifications, or drop the server to
+//! simulate a crash. No lspmux or rust-analyzer binary is involved, so
+//! request, timeout, crash, and notification paths run deterministically.
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use tokio::io::{
+    AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, DuplexStream, ReadHalf, WriteHalf,
+};
+use tokio::time::{timeout, Duration};
+
+use crate::lsp_client::LspClient;
+
+/// Buffer size of each direction of the in-memory pipe.
+const PIPE_CAPACITY: usize = 1024 * 1024;
+
+/// How long [`MockServer::recv`] waits for the client, so a test whose
+/// client never sends fails instead of hanging.
+const RECV_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Workspace root the client is initialized with.
+pub const MOCK_WORKSPACE_ROOT: &str = "/mock/workspace";
+
+/// Version the mock server reports in its `initialize` response.
+pub const MOCK_SERVER_VERSION: &str = "0.0.0-mock";
+
+/// The server end of a mock LSP connection.
+pub struct MockServer {
+    reader: BufReader<ReadHalf<DuplexStream>>,
+    writer: WriteHalf<DuplexStream>,
+}
+
+/// Start an [`LspClient`] against a new mock server, which completes the
+/// handshake advertising `capabilities` (a `ServerCapabilities` object).
+///
+/// # Errors
+///
+/// Returns an error if the handshake fails.
+pub async fn connect(capabilities: Value) -> Result<(LspClient, MockServer)> {
+    let (mut server, client_reader, client_writer) = pipe();
+    let (client, handshake) = tokio::join!(
+        LspClient::connect(
+            client_reader,
+            client_writer,
+            Some(MOCK_WORKSPACE_ROOT),
+            None
+        ),
+        server.handshake(capabilities),
+    );
+    handshake?;
+    Ok((client?, server))
+}
+
+/// A mock server and the client's ends of the pipes to it, for a client
+/// that must be connected by hand (see [`LspClient::connect`]); the test
+/// then plays the handshake too.
+#[must_use]
+pub fn pipe() -> (MockServer, ReadHalf<DuplexStream>, WriteHalf<DuplexStream>) {
+    let (client_end, server_end) = tokio::io::duplex(PIPE_CAPACITY);
+    let (client_reader, client_writer) = tokio::io::split(client_end);
+    let (server_reader, server_writer) = tokio::io::split(server_end);
+    let server = MockServer {
+        reader: BufReader::new(server_reader),
+        writer: server_writer,
+    };
+    (server, client_reader, client_writer)
+}
+
+impl MockServer {
+    /// Answer `initialize` with `capabilities` and wait for `initialized`.
+    async fn handshake(&mut self, capabilities: Value) -> Result<()> {
+        let (id, _) = self.expect_request("initialize").await?;
+        self.respond(
+            id,
+            json!({
+                "capabilities": capabilities,
+                "serverInfo": { "name": "mock", "version": MOCK_SERVER_VERSION },
+            }),
+        )
+        .await?;
+        self.expect_notification("initialized").await?;
+        Ok(())
+    }
+
+    /// The next message from the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client sends nothing within five seconds,
+    /// closes the connection, or sends a malformed frame.
+    pub async fn recv(&mut self) -> Result<Value> {
+        timeout(RECV_TIMEOUT, self.read_message())
+            .await
+            .context("timed out waiting for the client")?
+    }
+
+    async fn read_message(&mut self) -> Result<Value> {
+        let mut content_length = None;
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await? == 0 {
+                bail!("the client closed the connection");
+            }
+            let line = line.trim();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(length) = line.strip_prefix("Content-Length: ") {
+                content_length = Some(length.parse::<usize>()?);
+            }
+        }
+        let mut body = vec![0; content_length.context("missing Content-Length header")?];
+        self.reader.read_exact(&mut body).await?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    /// Wait for a `method` request, skipping notifications, and return its
+    /// id and params.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if another request arrives first, or as [`Self::recv`].
+    pub async fn expect_request(&mut self, method: &str) -> Result<(Value, Value)> {
+        loop {
+            let message = self.recv().await?;
+            let Some(id) = message.get("id") else {
+                continue;
+            };
+            if message["method"] != method {
+                bail!("expected a {method} request, got {message}");
+            }
+            return Ok((id.clone(), message["params"].clone()));
+        }
+    }
+
+    /// Wait for a `method` notification, skipping other notifications, and
+    /// return its params.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a request arrives first, or as [`Self::recv`].
+    pub async fn expect_notification(&mut self, method: &str) -> Result<Value> {
+        loop {
+            let message = self.recv().await?;
+            if message.get("id").is_some() {
+                bail!("expected a {method} notification, got {message}");
+            }
+            if message["method"] == method {
+                return Ok(message.get("params").cloned().unwrap_or(Value::Null));
+            }
+        }
+    }
+
+    /// Answer request `id` with `result`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client has gone.
+    pub async fn respond(&mut self, id: Value, result: Value) -> Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "result": result }))
+            .await
+    }
+
+    /// Fail request `id` with a JSON-RPC error.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client has gone.
+    pub async fn respond_error(&mut self, id: Value, code: i64, message: &str) -> Result<()> {
+        self.send(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }))
+        .await
+    }
+
+    /// Push a `method` notification to the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client has gone.
+    pub async fn notify(&mut self, method: &str, params: Value) -> Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+            .await
+    }
+
+    /// Send `bytes` as they are, framing and all, as a misbehaving server
+    /// might.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client has gone.
+    pub async fn send_raw(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Send `message` as one framed JSON-RPC message.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client has gone.
+    pub async fn send(&mut self, message: &Value) -> Result<()> {
+        let body = serde_json::to_vec(message)?;
+        let header = format!("Content-Length: {}\r\n\r\n", body.len());
+        self.writer.write_all(header.as_bytes()).await?;
+        self.writer.write_all(&body).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,249 @@
+//! The queue between the LSP reader loop and the subscribers of server
+//! notifications.
+//!
+//! While indexing, rust-analyzer sends thousands of `$/progress` reports and
+//! `textDocument/publishDiagnostics` notifications. The reader loop must
+//! keep answering responses meanwhile, so it only queues them, never
+//! waiting, and a dispatcher task hands them to subscribers. Queued
+//! notifications coalesce while they wait: a progress report replaces the
+//! unsent reports for its token, an `end` drops them, and diagnostics for a
+//! file replace its unsent ones, since each publish is complete. `begin` and
+//! `end` are kept, so waits on a check still see it finish.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use metrics::counter;
+use tokio::sync::Notify;
+
+use crate::lsp_client::{ProgressEvent, PublishedDiagnostics};
+
+/// Notifications queued at most, once coalescing has done what it can.
+pub const NOTIFICATION_QUEUE_CAPACITY: usize = 4096;
+
+/// A server notification the client passes on.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Notification {
+    Progress(ProgressEvent),
+    Diagnostics(PublishedDiagnostics),
+}
+
+/// What a newer notification replaces.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Key {
+    Report(String),
+    Diagnostics(String),
+}
+
+/// A bounded queue of notifications that never makes the sender wait.
+#[derive(Debug)]
+pub struct NotificationQueue {
+    state: Mutex<QueueState>,
+    ready: Notify,
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    /// Sequence number, what it may be replaced by, and the notification.
+    entries: VecDeque<(u64, Option<Key>, Notification)>,
+    /// The sequence number of the newest notification for each key; older
+    /// entries with that key are stale.
+    latest: HashMap<Key, u64>,
+    next_seq: u64,
+    closed: bool,
+}
+
+impl QueueState {
+    /// Take the oldest live notification.
+    fn pop(&mut self) -> Option<Notification> {
+        while let Some((seq, key, notification)) = self.entries.pop_front() {
+            match key {
+                Some(key) if self.latest.get(&key) == Some(&seq) => {
+                    self.latest.remove(&key);
+                }
+                Some(_) => continue,
+                None => {}
+            }
+            return Some(notification);
+        }
+        None
+    }
+}
+
+impl NotificationQueue {
+    /// A queue holding at most `capacity` notifications.
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            ready: Notify::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Queue `notification`, replacing any it makes stale. When the queue
+    /// is full of live notifications, the oldest is dropped.
+    pub fn push(&self, notification: Notification) {
+        let mut state = self.lock();
+        if state.closed {
+            return;
+        }
+        let seq = state.next_seq;
+        state.next_seq += 1;
+        let key = match &notification {
+            Notification::Progress(event) if event.kind == "report" => {
+                Some(Key::Report(event.token.clone()))
+            }
+            Notification::Progress(event) => {
+                if event.kind == "end" {
+                    // Reports from before the end are of no use anymore.
+                    state.latest.remove(&Key::Report(event.token.clone()));
+                }
+                None
+            }
+            Notification::Diagnostics(published) => {
+                Some(Key::Diagnostics(published.file_path.clone()))
+            }
+        };
+        if let Some(key) = &key {
+            state.latest.insert(key.clone(), seq);
+        }
+        state.entries.push_back((seq, key, notification));
+
+        if state.entries.len() > self.capacity {
+            let QueueState {
+                entries, latest, ..
+            } = &mut *state;
+            entries.retain(|(seq, key, _)| {
+                key.as_ref().is_none_or(|key| latest.get(key) == Some(seq))
+            });
+            let mut dropped = 0_u64;
+            while state.entries.len() > self.capacity {
+                if let Some((seq, Some(key), _)) = state.entries.pop_front() {
+                    if state.latest.get(&key) == Some(&seq) {
+                        state.latest.remove(&key);
+                    }
+                }
+                dropped += 1;
+            }
+            if dropped > 0 {
+                tracing::warn!("notification queue full; dropped {dropped} notification(s)");
+                counter!("lspmux_cc_lsp_notifications_dropped_total").increment(dropped);
+            }
+        }
+        drop(state);
+        self.ready.notify_one();
+    }
+
+    /// The oldest live notification, if any.
+    pub fn try_next(&self) -> Option<Notification> {
+        self.lock().pop()
+    }
+
+    /// Wait for the next notification; `None` once the queue is closed and
+    /// drained.
+    pub async fn next(&self) -> Option<Notification> {
+        loop {
+            if let Some(notification) = self.try_next() {
+                return Some(notification);
+            }
+            if self.lock().closed {
+                return None;
+            }
+            self.ready.notified().await;
+        }
+    }
+
+    /// Accept no more notifications; those queued are still delivered.
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.ready.notify_one();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(token: &str, kind: &str, percentage: u32) -> Notification {
+        Notification::Progress(ProgressEvent {
+            token: token.to_string(),
+            kind: kind.to_string(),
+            title: None,
+            message: None,
+            percentage: Some(percentage),
+        })
+    }
+
+    fn published(file_path: &str, version: i32) -> Notification {
+        Notification::Diagnostics(PublishedDiagnostics {
+            file_path: file_path.to_string(),
+            version: Some(version),
+            diagnostics: Vec::new(),
+        })
+    }
+
+    fn drain(queue: &NotificationQueue) -> Vec<Notification> {
+        std::iter::from_fn(|| queue.try_next()).collect()
+    }
+
+    #[test]
+    fn unsent_reports_and_diagnostics_coalesce() {
+        let queue = NotificationQueue::new(NOTIFICATION_QUEUE_CAPACITY);
+        queue.push(progress("indexing", "begin", 0));
+        queue.push(progress("indexing", "report", 10));
+        queue.push(published("/ws/src/lib.rs", 1));
+        queue.push(progress("indexing", "report", 20));
+        queue.push(progress("flycheck/0", "report", 50));
+        queue.push(published("/ws/src/lib.rs", 2));
+        queue.push(progress("flycheck/0", "end", 100));
+        assert_eq!(
+            drain(&queue),
+            [
+                progress("indexing", "begin", 0),
+                progress("indexing", "report", 20),
+                published("/ws/src/lib.rs", 2),
+                progress("flycheck/0", "end", 100),
+            ]
+        );
+
+        // Once sent, a report no longer stands in for the next one.
+        queue.push(progress("indexing", "report", 30));
+        assert_eq!(drain(&queue), [progress("indexing", "report", 30)]);
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest_live_notifications() {
+        let queue = NotificationQueue::new(3);
+        for version in 0..3 {
+            queue.push(published("/ws/src/lib.rs", version));
+        }
+        queue.push(progress("indexing", "begin", 0));
+        queue.push(progress("indexing", "end", 100));
+        queue.push(published("/ws/src/main.rs", 0));
+        queue.push(published("/ws/src/bin.rs", 0));
+        assert_eq!(
+            drain(&queue),
+            [
+                progress("indexing", "end", 100),
+                published("/ws/src/main.rs", 0),
+                published("/ws/src/bin.rs", 0),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn a_closed_queue_delivers_what_it_holds_then_ends() {
+        let queue = NotificationQueue::new(NOTIFICATION_QUEUE_CAPACITY);
+        queue.push(progress("indexing", "begin", 0));
+        queue.close();
+        queue.push(progress("indexing", "end", 100));
+        assert_eq!(queue.next().await, Some(progress("indexing", "begin", 0)));
+        assert_eq!(queue.next().await, None);
+    }
+}
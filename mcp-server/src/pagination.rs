@@ -0,0 +1,495 @@
+//! Cursor-based pagination for large tool results.
+//!
+//! The first call for a query computes the full result set; if it does not
+//! fit in one page the remainder is cached and an opaque cursor is returned.
+//! Follow-up calls pass the cursor back and are served from the cache without
+//! another LSP round trip.
+//!
+//! A page ends at `limit` items or at the response budget, the serialized
+//! bytes its items may take (`max_result_bytes` in the config file),
+//! whichever comes first. A page cut by the budget says so in its summary,
+//! along with how much was left out.
+
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use rmcp::ErrorData as McpError;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Page size used when a tool call does not specify `limit`.
+pub const DEFAULT_PAGE_LIMIT: usize = 200;
+
+/// Serialized bytes of the items on one page, unless configured: about 16k
+/// tokens.
+pub const DEFAULT_RESULT_BUDGET: usize = 64 * 1024;
+
+/// Cached result sets expire after this long without being paged.
+const CURSOR_TTL: Duration = Duration::from_mins(10);
+
+/// Upper bound on concurrently cached result sets; the oldest is evicted first.
+const MAX_CACHED_RESULTS: usize = 32;
+
+/// Tool parameters shared by every paginated tool.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct PageParams {
+    /// Maximum number of items to return (default 200). Pages are also cut
+    /// short to fit the server's response size budget.
+    pub limit: Option<usize>,
+    /// Cursor from a previous response's `next_cursor`. When set, the
+    /// remaining page of that earlier result set is returned.
+    pub cursor: Option<String>,
+}
+
+impl PageParams {
+    fn limit(&self) -> usize {
+        self.limit.unwrap_or(DEFAULT_PAGE_LIMIT).max(1)
+    }
+}
+
+/// What a result set answers: the tool, and its arguments other than
+/// `limit` and `cursor`. A cursor resumes only the query it was issued for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PageQuery {
+    tool: &'static str,
+    arguments: String,
+}
+
+impl PageQuery {
+    /// The query of a call to `tool` with `arguments`.
+    pub fn new(tool: &'static str, arguments: &impl Serialize) -> Self {
+        let mut arguments = serde_json::to_value(arguments).unwrap_or_default();
+        if let Some(arguments) = arguments.as_object_mut() {
+            arguments.remove("limit");
+            arguments.remove("cursor");
+        }
+        Self {
+            tool,
+            arguments: arguments.to_string(),
+        }
+    }
+}
+
+/// An item of a paginated result set.
+pub trait PageItem: Clone + Serialize + Send + Sync + 'static {
+    /// The file the item is in, for telling how many files a page cut by
+    /// the budget left out.
+    fn file_path(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// One page of a result set.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// Size of the full result set across all pages.
+    pub total: usize,
+    /// Index of the first item of this page within the full result set.
+    pub offset: usize,
+    /// Cursor for the following page, if any items remain.
+    pub next_cursor: Option<String>,
+    /// The budget, in bytes, if it cut this page short of `limit`.
+    pub budget_cut: Option<usize>,
+    /// How many files the items after this page are in, if they have files.
+    pub remaining_files: Option<usize>,
+}
+
+impl<T> Page<T> {
+    /// A short human-readable note about the page window, empty when the
+    /// page holds the whole result set.
+    pub fn window_note(&self) -> String {
+        if self.items.len() == self.total {
+            return String::new();
+        }
+        let first = self.offset + 1;
+        let last = self.offset + self.items.len();
+        let mut note = format!(" Showing {first}-{last} of {}.", self.total);
+        if let Some(budget) = self.budget_cut {
+            let remaining = self.total - last;
+            let _ = write!(
+                note,
+                " [truncated at the {budget}-byte response budget: {remaining} more result(s)"
+            );
+            if let Some(files) = self.remaining_files {
+                let _ = write!(note, " in {files} file(s)");
+            }
+            note.push_str(" not shown]");
+        }
+        if self.next_cursor.is_some() {
+            note.push_str(" Pass next_cursor to fetch more.");
+        }
+        note
+    }
+}
+
+struct CachedResult {
+    query: PageQuery,
+    items: Arc<dyn Any + Send + Sync>,
+    touched: Instant,
+}
+
+/// Result sets awaiting follow-up pages, shared by every MCP session.
+#[derive(Clone)]
+pub struct ResultPages {
+    entries: Arc<Mutex<HashMap<u64, CachedResult>>>,
+    next_id: Arc<AtomicU64>,
+    budget: usize,
+}
+
+impl Default for ResultPages {
+    fn default() -> Self {
+        Self::with_budget(DEFAULT_RESULT_BUDGET)
+    }
+}
+
+impl ResultPages {
+    /// Cut pages to at most `budget` bytes of serialized items.
+    pub fn with_budget(budget: usize) -> Self {
+        Self {
+            entries: Arc::default(),
+            next_id: Arc::default(),
+            budget: budget.max(1),
+        }
+    }
+
+    /// Return the page selected by `params` for `query`, whose full result
+    /// set is produced by `compute` (only invoked when no cursor is given).
+    ///
+    /// # Errors
+    ///
+    /// Returns `invalid_params` for a malformed or expired cursor, or one
+    /// issued for another query, and propagates any error from `compute`.
+    pub async fn page<T, F>(
+        &self,
+        query: PageQuery,
+        params: &PageParams,
+        compute: F,
+    ) -> Result<Page<T>, McpError>
+    where
+        T: PageItem,
+        F: std::future::Future<Output = Result<Vec<T>, McpError>>,
+    {
+        match &params.cursor {
+            Some(cursor) => self.resume(&query, cursor, params.limit()),
+            None => Ok(self.start(query, compute.await?, params.limit())),
+        }
+    }
+
+    fn start<T: PageItem>(&self, query: PageQuery, items: Vec<T>, limit: usize) -> Page<T> {
+        let (end, budget_cut) = self.fit(&items, limit);
+        if end == items.len() {
+            return Page {
+                total: items.len(),
+                items,
+                offset: 0,
+                next_cursor: None,
+                budget_cut: None,
+                remaining_files: None,
+            };
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let page = window(&items, 0, end, budget_cut.then_some(self.budget), id);
+        let mut entries = self.lock();
+        evict(&mut entries);
+        entries.insert(
+            id,
+            CachedResult {
+                query,
+                items: Arc::new(items),
+                touched: Instant::now(),
+            },
+        );
+        page
+    }
+
+    fn resume<T: PageItem>(
+        &self,
+        query: &PageQuery,
+        cursor: &str,
+        limit: usize,
+    ) -> Result<Page<T>, McpError> {
+        let (id, offset) = decode_cursor(cursor).ok_or_else(|| {
+            McpError::invalid_params(format!("malformed cursor {cursor:?}"), None)
+        })?;
+
+        let mut entries = self.lock();
+        evict(&mut entries);
+        let expired = || {
+            McpError::invalid_params(
+                format!("cursor {cursor:?} has expired; repeat the query without a cursor"),
+                None,
+            )
+        };
+        let entry = entries.get_mut(&id).ok_or_else(expired)?;
+        if entry.query != *query {
+            let issued_for = if entry.query.tool == query.tool {
+                "the same tool with other arguments"
+            } else {
+                entry.query.tool
+            };
+            return Err(McpError::invalid_params(
+                format!(
+                    "cursor {cursor:?} was issued for {issued_for}; pass the arguments of the \
+                     call that returned it, or repeat the query without a cursor"
+                ),
+                None,
+            ));
+        }
+        entry.touched = Instant::now();
+        let items = Arc::clone(&entry.items)
+            .downcast::<Vec<T>>()
+            .map_err(|_| McpError::invalid_params("cursor was issued for another tool", None))?;
+
+        let offset = offset.min(items.len());
+        let (fitted, budget_cut) = self.fit(&items[offset..], limit);
+        let page = window(
+            &items,
+            offset,
+            offset + fitted,
+            budget_cut.then_some(self.budget),
+            id,
+        );
+        if page.next_cursor.is_none() {
+            entries.remove(&id);
+        }
+        drop(entries);
+        Ok(page)
+    }
+
+    /// How many of `items` go on a page of at most `limit` items, and
+    /// whether the budget ended it first. A page always holds at least one
+    /// item, however large.
+    fn fit<T: Serialize>(&self, items: &[T], limit: usize) -> (usize, bool) {
+        let mut used = 0;
+        for (count, item) in items.iter().take(limit).enumerate() {
+            // Items that fail to serialize are left for the response to report.
+            used += serde_json::to_vec(item).map_or(0, |bytes| bytes.len() + 1);
+            if used > self.budget && count > 0 {
+                return (count, true);
+            }
+        }
+        (items.len().min(limit), false)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, CachedResult>> {
+        match self.entries.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        }
+    }
+}
+
+/// The page of `items[offset..end]` of the cached result set `id`.
+fn window<T: PageItem>(
+    items: &[T],
+    offset: usize,
+    end: usize,
+    budget_cut: Option<usize>,
+    id: u64,
+) -> Page<T> {
+    let rest = &items[end..];
+    let remaining_files = budget_cut.and_then(|_| {
+        let files = rest
+            .iter()
+            .map(PageItem::file_path)
+            .collect::<Option<HashSet<_>>>()?;
+        Some(files.len())
+    });
+    Page {
+        items: items[offset..end].to_vec(),
+        total: items.len(),
+        offset,
+        next_cursor: (!rest.is_empty()).then(|| encode_cursor(id, end)),
+        budget_cut,
+        remaining_files,
+    }
+}
+
+/// Drop expired entries and, if still full, the least recently used one.
+fn evict(entries: &mut HashMap<u64, CachedResult>) {
+    entries.retain(|_, entry| entry.touched.elapsed() < CURSOR_TTL);
+    while entries.len() >= MAX_CACHED_RESULTS {
+        let Some(oldest) = entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.touched)
+            .map(|(id, _)| *id)
+        else {
+            break;
+        };
+        entries.remove(&oldest);
+    }
+}
+
+fn encode_cursor(id: u64, offset: usize) -> String {
+    format!("{id}:{offset}")
+}
+
+fn decode_cursor(cursor: &str) -> Option<(u64, usize)> {
+    let (id, offset) = cursor.split_once(':')?;
+    Some((id.parse().ok()?, offset.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl PageItem for u32 {}
+
+    impl PageItem for String {
+        fn file_path(&self) -> Option<&str> {
+            self.split_once(':').map(|(file, _)| file)
+        }
+    }
+
+    fn query() -> PageQuery {
+        PageQuery::new("rust_find_references", &serde_json::json!({ "line": 3 }))
+    }
+
+    fn params(limit: usize, cursor: Option<String>) -> PageParams {
+        PageParams {
+            limit: Some(limit),
+            cursor,
+        }
+    }
+
+    #[tokio::test]
+    async fn small_result_sets_fit_in_one_page() {
+        let pages = ResultPages::default();
+        let page = pages
+            .page(query(), &params(10, None), async { Ok(vec![1, 2, 3]) })
+            .await
+            .unwrap();
+        assert_eq!(page.items, vec![1, 2, 3]);
+        assert_eq!(page.next_cursor, None);
+        assert!(page.window_note().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cursor_walks_the_cached_result_set() {
+        let pages = ResultPages::default();
+        let first = pages
+            .page(query(), &params(2, None), async {
+                Ok((0..5).collect::<Vec<u32>>())
+            })
+            .await
+            .unwrap();
+        assert_eq!(first.items, vec![0, 1]);
+        assert_eq!(first.total, 5);
+
+        let second = pages
+            .page::<u32, _>(query(), &params(2, first.next_cursor.clone()), async {
+                panic!("cursor pages must not recompute")
+            })
+            .await
+            .unwrap();
+        assert_eq!(second.items, vec![2, 3]);
+        assert_eq!(second.offset, 2);
+        assert!(second.window_note().contains("Showing 3-4 of 5"));
+
+        let last = pages
+            .page::<u32, _>(query(), &params(2, second.next_cursor), async {
+                Ok(vec![])
+            })
+            .await
+            .unwrap();
+        assert_eq!(last.items, vec![4]);
+        assert_eq!(last.next_cursor, None);
+
+        // The result set is released once fully consumed.
+        assert!(pages
+            .page::<u32, _>(query(), &params(2, first.next_cursor), async { Ok(vec![]) })
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn cursor_resumes_only_the_query_it_was_issued_for() {
+        let pages = ResultPages::default();
+        let first = pages
+            .page(query(), &params(1, None), async { Ok(vec![1_u32, 2, 3]) })
+            .await
+            .unwrap();
+
+        let other_arguments =
+            PageQuery::new("rust_find_references", &serde_json::json!({ "line": 4 }));
+        let err = pages
+            .page::<u32, _>(
+                other_arguments,
+                &params(1, first.next_cursor.clone()),
+                async { Ok(vec![]) },
+            )
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("same tool with other arguments"));
+
+        let other_tool = PageQuery::new("rust_workspace_symbol", &serde_json::json!({}));
+        let err = pages
+            .page::<String, _>(other_tool, &params(1, first.next_cursor.clone()), async {
+                Ok(vec![])
+            })
+            .await
+            .unwrap_err();
+        assert!(err.message.contains("rust_find_references"));
+
+        // Paging arguments may change between pages.
+        let rest = PageQuery::new(
+            "rust_find_references",
+            &serde_json::json!({ "line": 3, "limit": 5, "cursor": first.next_cursor }),
+        );
+        let second = pages
+            .page::<u32, _>(rest, &params(5, first.next_cursor), async { Ok(vec![]) })
+            .await
+            .unwrap();
+        assert_eq!(second.items, vec![2, 3]);
+    }
+
+    #[tokio::test]
+    async fn pages_are_cut_to_the_budget() {
+        // Each location serializes to 11 bytes, plus one for the separator.
+        let locations = (0..10)
+            .map(|i| format!("f{}.rs:{i:03}", i % 4))
+            .collect::<Vec<_>>();
+        let pages = ResultPages::with_budget(40);
+        let first = pages
+            .page(query(), &params(5, None), async { Ok(locations) })
+            .await
+            .unwrap();
+        assert_eq!(first.items.len(), 3);
+        assert_eq!(first.budget_cut, Some(40));
+        assert_eq!(first.remaining_files, Some(4));
+        assert_eq!(
+            first.window_note(),
+            " Showing 1-3 of 10. [truncated at the 40-byte response budget: 7 more result(s) \
+             in 4 file(s) not shown] Pass next_cursor to fetch more."
+        );
+
+        let second = pages
+            .page::<String, _>(query(), &params(2, first.next_cursor), async { Ok(vec![]) })
+            .await
+            .unwrap();
+        assert_eq!(second.items, vec!["f3.rs:003", "f0.rs:004"]);
+        assert_eq!(second.budget_cut, None);
+
+        // A single item over the budget still makes a page.
+        let pages = ResultPages::with_budget(1);
+        let page = pages
+            .page(query(), &params(5, None), async { Ok(vec![1_u32, 2]) })
+            .await
+            .unwrap();
+        assert_eq!(page.items, vec![1]);
+        assert_eq!(page.remaining_files, None);
+    }
+
+    #[test]
+    fn decode_cursor_rejects_garbage() {
+        assert_eq!(decode_cursor("3:40"), Some((3, 40)));
+        assert_eq!(decode_cursor("nonsense"), None);
+        assert_eq!(decode_cursor("a:b"), None);
+    }
+}
@@ -0,0 +1,366 @@
+//! One LSP client per workspace root.
+//!
+//! The server starts with a single workspace (`--workspace-root`), but a tool
+//! call may name another with its `workspace_root` argument, e.g. to compare
+//! two branches checked out side by side. [`LspPool`] starts a launcher for
+//! each such root on first use, with the same settings as the default one,
+//! and keeps a few of them around. lspmux gives each root its own
+//! rust-analyzer.
+//!
+//! Calls that name no workspace use the default one if it covers the queried
+//! file. Otherwise (typically when the server was started from `$HOME`,
+//! which rust-analyzer cannot make sense of) the pool detects the project
+//! around the file with [`project_root`] and serves the call from there.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Instant;
+
+use anyhow::{bail, Result};
+
+use crate::launcher::{LspLauncher, RUST_PROJECT_FILE};
+
+/// Workspaces besides the default one kept at a time. Starting another
+/// shuts down the least recently used that no call is using.
+pub const MAX_POOLED_WORKSPACES: usize = 4;
+
+/// A launcher for another workspace and when a call last used it.
+struct Pooled {
+    launcher: Arc<LspLauncher>,
+    last_used: Instant,
+}
+
+/// The default launcher and those started for other workspace roots.
+pub struct LspPool {
+    default: Arc<LspLauncher>,
+    others: Mutex<HashMap<PathBuf, Pooled>>,
+    /// The workspace last detected from a queried file.
+    detected: Mutex<Option<PathBuf>>,
+}
+
+impl LspPool {
+    /// A pool serving `default` unless a call names another workspace.
+    #[must_use]
+    pub fn new(default: Arc<LspLauncher>) -> Self {
+        Self {
+            default,
+            others: Mutex::new(HashMap::new()),
+            detected: Mutex::new(None),
+        }
+    }
+
+    /// The launcher for the workspace the server was started with.
+    pub const fn default_launcher(&self) -> &Arc<LspLauncher> {
+        &self.default
+    }
+
+    /// The launcher for the workspace at `root`, creating it if needed.
+    /// Nothing is started until the launcher's client is first used.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `root` is not an absolute path to a directory, or
+    /// if every pooled workspace is busy.
+    pub fn launcher(&self, root: &str) -> Result<Arc<LspLauncher>> {
+        let path = Path::new(root);
+        if !path.is_absolute() {
+            bail!("workspace_root must be an absolute path, got {root:?}");
+        }
+        if !path.is_dir() {
+            bail!("workspace_root {root:?} is not a directory");
+        }
+        // Components ignore trailing slashes and `.` segments.
+        let key = path.components().collect::<PathBuf>();
+        if self
+            .default
+            .workspace_root()
+            .is_some_and(|default| Path::new(default).components().eq(key.components()))
+        {
+            return Ok(Arc::clone(&self.default));
+        }
+
+        let mut others = self.others.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(pooled) = others.get_mut(&key) {
+            pooled.last_used = Instant::now();
+            return Ok(Arc::clone(&pooled.launcher));
+        }
+        if others.len() >= MAX_POOLED_WORKSPACES {
+            // A launcher only the pool holds has no call in flight.
+            let Some(idle) = others
+                .iter()
+                .filter(|(_, pooled)| Arc::strong_count(&pooled.launcher) == 1)
+                .min_by_key(|(_, pooled)| pooled.last_used)
+                .map(|(root, _)| root.clone())
+            else {
+                bail!(
+                    "all {MAX_POOLED_WORKSPACES} other workspaces are in use; retry {root} later"
+                );
+            };
+            if let Some(evicted) = others.remove(&idle) {
+                tracing::info!(event = "workspace_evicted", root = %idle.display());
+                tokio::spawn(async move { evicted.launcher.shutdown().await });
+            }
+        }
+        let launcher = Arc::new(self.default.for_workspace(&key.to_string_lossy()));
+        tracing::info!(event = "workspace_added", root = %key.display());
+        others.insert(
+            key,
+            Pooled {
+                launcher: Arc::clone(&launcher),
+                last_used: Instant::now(),
+            },
+        );
+        drop(others);
+        Ok(launcher)
+    }
+
+    /// The launcher for the project around `file`, if the default workspace
+    /// does not cover it: the default root is not a project root or `file` is
+    /// outside it. `None` leaves the call to the default one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if every pooled workspace is busy.
+    pub fn launcher_for_file(&self, file: &Path) -> Result<Option<Arc<LspLauncher>>> {
+        if !file.is_absolute() || self.default_covers(Some(file)) {
+            return Ok(None);
+        }
+        let Some(root) = project_root(file) else {
+            return Ok(None);
+        };
+        let launcher = self.launcher(&root.to_string_lossy())?;
+        let mut detected = self.detected.lock().unwrap_or_else(PoisonError::into_inner);
+        if detected.as_ref() != Some(&root) {
+            tracing::info!(
+                event = "workspace_detected",
+                root = %root.display(),
+                file = %file.display()
+            );
+            *detected = Some(root);
+        }
+        drop(detected);
+        Ok(Some(launcher))
+    }
+
+    /// The launcher for the workspace last detected from a queried file, for
+    /// calls without one, if the default root is not a project root.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if every pooled workspace is busy.
+    pub fn detected_launcher(&self) -> Result<Option<Arc<LspLauncher>>> {
+        if self.default_covers(None) {
+            return Ok(None);
+        }
+        let detected = self
+            .detected
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        detected
+            .map(|root| self.launcher(&root.to_string_lossy()))
+            .transpose()
+    }
+
+    /// Whether the default root is a project root, containing `file` if one
+    /// is given.
+    fn default_covers(&self, file: Option<&Path>) -> bool {
+        self.default.workspace_root().is_some_and(|root| {
+            let root = Path::new(root);
+            file.is_none_or(|file| file.starts_with(root))
+                && (root.join("Cargo.toml").is_file() || root.join(RUST_PROJECT_FILE).is_file())
+        })
+    }
+
+    /// Every launcher in the pool, the default one first.
+    pub fn launchers(&self) -> Vec<Arc<LspLauncher>> {
+        let others = self.others.lock().unwrap_or_else(PoisonError::into_inner);
+        std::iter::once(Arc::clone(&self.default))
+            .chain(others.values().map(|pooled| Arc::clone(&pooled.launcher)))
+            .collect()
+    }
+
+    /// The roots of the other workspaces in the pool.
+    pub fn other_roots(&self) -> Vec<PathBuf> {
+        let mut roots = self
+            .others
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        roots.sort();
+        roots
+    }
+
+    /// Shut down every launcher in the pool, the default one last.
+    pub async fn shutdown(&self) {
+        let others =
+            std::mem::take(&mut *self.others.lock().unwrap_or_else(PoisonError::into_inner));
+        for pooled in others.into_values() {
+            pooled.launcher.shutdown().await;
+        }
+        self.default.shutdown().await;
+    }
+}
+
+/// The root of the project `file` belongs to.
+///
+/// That is the nearest directory above it with a [`RUST_PROJECT_FILE`], which
+/// describes a whole non-Cargo build; else the outermost whose `Cargo.toml`
+/// has a `[workspace]` table; else the nearest with a `Cargo.toml`.
+#[must_use]
+pub fn project_root(file: &Path) -> Option<PathBuf> {
+    let mut package = None;
+    let mut workspace = None;
+    for dir in file.ancestors() {
+        if dir.join(RUST_PROJECT_FILE).is_file() {
+            return Some(dir.to_path_buf());
+        }
+        let Ok(manifest) = std::fs::read_to_string(dir.join("Cargo.toml")) else {
+            continue;
+        };
+        package.get_or_insert_with(|| dir.to_path_buf());
+        if manifest
+            .parse::<toml::Table>()
+            .is_ok_and(|manifest| manifest.contains_key("workspace"))
+        {
+            workspace = Some(dir.to_path_buf());
+        }
+    }
+    workspace.or(package)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bootstrap::{BootstrapMode, LspTransport, RuntimeConfig};
+    use crate::telemetry::TelemetryState;
+
+    fn pool_at(default_root: &Path) -> LspPool {
+        let runtime = RuntimeConfig {
+            lspmux_path: "/nonexistent/lspmux".to_string(),
+            server_path: "/nonexistent/rust-analyzer".to_string(),
+            server_path_pinned: false,
+            workspace_root: Some(default_root.display().to_string()),
+            config_path: "/nonexistent/lspmux.toml".to_string(),
+            socket_path: "/nonexistent/lspmux.sock".to_string(),
+            bootstrap_mode: BootstrapMode::Off,
+            connect_addr: None,
+            lsp_transport: LspTransport::Child,
+            no_mux_fallback: false,
+        };
+        LspPool::new(Arc::new(LspLauncher::new(
+            runtime,
+            TelemetryState::from_env(),
+        )))
+    }
+
+    #[tokio::test]
+    async fn each_root_gets_one_launcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool_at(dir.path());
+        let default = pool
+            .launcher(&format!("{}/", dir.path().display()))
+            .unwrap();
+        assert!(Arc::ptr_eq(&default, pool.default_launcher()));
+
+        let sibling = dir.path().join("sibling");
+        std::fs::create_dir(&sibling).unwrap();
+        let first = pool.launcher(&sibling.display().to_string()).unwrap();
+        let again = pool.launcher(&sibling.display().to_string()).unwrap();
+        assert!(Arc::ptr_eq(&first, &again));
+        assert_eq!(
+            first.workspace_root(),
+            Some(sibling.display().to_string().as_str())
+        );
+        assert_eq!(pool.other_roots(), [sibling]);
+
+        assert!(pool.launcher("relative/dir").is_err());
+        assert!(pool
+            .launcher(&dir.path().join("missing").display().to_string())
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn idle_workspaces_are_evicted_when_the_pool_is_full() {
+        let dir = tempfile::tempdir().unwrap();
+        let pool = pool_at(dir.path());
+        let roots = (0..=MAX_POOLED_WORKSPACES)
+            .map(|n| {
+                let root = dir.path().join(format!("checkout-{n}"));
+                std::fs::create_dir(&root).unwrap();
+                root.display().to_string()
+            })
+            .collect::<Vec<_>>();
+
+        // Every pooled workspace is busy, so there is no room for another.
+        let busy = roots[..MAX_POOLED_WORKSPACES]
+            .iter()
+            .map(|root| pool.launcher(root).unwrap())
+            .collect::<Vec<_>>();
+        assert!(pool.launcher(&roots[MAX_POOLED_WORKSPACES]).is_err());
+
+        // Once they are idle, the least recently used makes way.
+        drop(busy);
+        pool.launcher(&roots[MAX_POOLED_WORKSPACES]).unwrap();
+        let pooled = pool.other_roots();
+        assert_eq!(pooled.len(), MAX_POOLED_WORKSPACES);
+        assert!(!pooled.contains(&PathBuf::from(&roots[0])));
+    }
+
+    #[test]
+    fn project_roots_are_found_above_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let member = dir.path().join("crates/core");
+        std::fs::create_dir_all(member.join("src")).unwrap();
+        std::fs::write(member.join("Cargo.toml"), "[package]\nname = \"core\"\n").unwrap();
+        let lib = member.join("src/lib.rs");
+        assert_eq!(project_root(&lib), Some(member));
+
+        std::fs::write(
+            dir.path().join("Cargo.toml"),
+            "[workspace]\nmembers = [\"crates/*\"]\n",
+        )
+        .unwrap();
+        assert_eq!(project_root(&lib), Some(dir.path().to_path_buf()));
+
+        // A rust-project.json describes the whole build, whatever is below.
+        let monorepo = tempfile::tempdir().unwrap();
+        std::fs::write(monorepo.path().join(RUST_PROJECT_FILE), "{}").unwrap();
+        let vendored = monorepo.path().join("third-party/serde");
+        std::fs::create_dir_all(&vendored).unwrap();
+        std::fs::write(vendored.join("Cargo.toml"), "[workspace]\n").unwrap();
+        assert_eq!(
+            project_root(&vendored.join("src/lib.rs")),
+            Some(monorepo.path().to_path_buf())
+        );
+    }
+
+    #[tokio::test]
+    async fn files_outside_the_default_workspace_are_detected() {
+        let home = tempfile::tempdir().unwrap();
+        let project = home.path().join("src/project");
+        std::fs::create_dir_all(project.join("src")).unwrap();
+        std::fs::write(project.join("Cargo.toml"), "[workspace]\n").unwrap();
+        let lib = project.join("src/lib.rs");
+
+        // Started from a directory that is no Cargo workspace.
+        let pool = pool_at(home.path());
+        assert!(pool.detected_launcher().unwrap().is_none());
+        let detected = pool.launcher_for_file(&lib).unwrap().unwrap();
+        assert_eq!(
+            detected.workspace_root(),
+            Some(project.display().to_string().as_str())
+        );
+        // Calls without a file follow the detected workspace.
+        let followed = pool.detected_launcher().unwrap().unwrap();
+        assert!(Arc::ptr_eq(&detected, &followed));
+
+        // A default root that is the file's workspace keeps serving it.
+        let pool = pool_at(&project);
+        assert!(pool.launcher_for_file(&lib).unwrap().is_none());
+        assert!(pool.detected_launcher().unwrap().is_none());
+    }
+}
@@ -0,0 +1,145 @@
+//! Prometheus metrics endpoint (`--metrics-listen`).
+//!
+//! The server and library record counters and histograms through the
+//! `metrics` facade throughout; they go nowhere until [`install`] sets up a
+//! Prometheus recorder. [`serve`] then exposes them at `/metrics`, adding
+//! gauges read from the LSP client at scrape time:
+//!
+//! - `lspmux_cc_tool_requests_total`, `lspmux_cc_tool_latency_seconds`: MCP
+//!   tool calls by tool and outcome.
+//! - `lspmux_cc_tool_queued_total`, `lspmux_cc_tool_queue_wait_seconds`: tool
+//!   calls that waited for the in-flight limits, and for how long.
+//! - `lspmux_cc_lsp_requests_total`, `lspmux_cc_lsp_request_duration_seconds`:
+//!   LSP requests by method and outcome (`ok`, `error`, `timeout`, `closed`).
+//! - `lspmux_cc_lsp_client_starts_total`, `lspmux_cc_lsp_connections_lost_total`,
+//!   `lspmux_cc_managed_server_restarts_total`: child process churn.
+//! - `lspmux_cc_watchdog_restarts_total`: connections restarted because
+//!   rust-analyzer stopped answering.
+//! - `lspmux_cc_open_documents`, `lspmux_cc_pending_lsp_requests`: gauges.
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use axum::extract::State;
+use axum::http::header;
+use axum::response::IntoResponse;
+use axum::routing::get;
+use lspmux_cc_mcp::launcher::LspLauncher;
+use metrics::gauge;
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use tokio_util::sync::CancellationToken;
+
+/// Histogram buckets for every `_seconds` metric: from a cached answer to a
+/// cold workspace-wide query.
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// How often histogram samples are folded into their buckets.
+const UPKEEP_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Install the process-wide Prometheus recorder.
+///
+/// # Errors
+///
+/// Returns an error if a recorder is already installed.
+pub fn install() -> Result<PrometheusHandle> {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), LATENCY_BUCKETS)
+        .context("invalid latency buckets")?
+        .install_recorder()
+        .context("failed to install the Prometheus recorder")
+}
+
+#[derive(Clone)]
+struct MetricsState {
+    handle: PrometheusHandle,
+    lsp: Arc<LspLauncher>,
+}
+
+/// Serve `/metrics` on `addr` until `shutdown` is cancelled.
+///
+/// # Errors
+///
+/// Returns an error if `addr` cannot be bound.
+pub async fn serve(
+    handle: PrometheusHandle,
+    lsp: Arc<LspLauncher>,
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let upkeep = handle.clone();
+    let upkeep_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(UPKEEP_INTERVAL);
+        loop {
+            tokio::select! {
+                () = upkeep_shutdown.cancelled() => return,
+                _ = interval.tick() => upkeep.run_upkeep(),
+            }
+        }
+    });
+
+    let router = axum::Router::new()
+        .route("/metrics", get(render))
+        .with_state(MetricsState { handle, lsp });
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind metrics listener on {addr}"))?;
+    tracing::info!("serving Prometheus metrics at http://{addr}/metrics");
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .context("metrics server exited with an error")
+}
+
+async fn render(State(state): State<MetricsState>) -> impl IntoResponse {
+    let (open_documents, pending) = state.lsp.get().map_or((0, 0), |client| {
+        (client.open_document_count(), client.pending_request_count())
+    });
+    gauge!("lspmux_cc_open_documents").set(count_as_f64(open_documents));
+    gauge!("lspmux_cc_pending_lsp_requests").set(count_as_f64(pending));
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.handle.render(),
+    )
+}
+
+#[allow(clippy::cast_precision_loss)] // counts stay far below 2^52
+const fn count_as_f64(count: usize) -> f64 {
+    count as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use metrics::{counter, histogram};
+    use metrics_exporter_prometheus::PrometheusBuilder;
+
+    use super::*;
+
+    #[test]
+    fn latency_histograms_use_the_configured_buckets() {
+        let recorder = PrometheusBuilder::new()
+            .set_buckets_for_metric(Matcher::Suffix("_seconds".to_string()), LATENCY_BUCKETS)
+            .unwrap()
+            .build_recorder();
+        let handle = recorder.handle();
+        metrics::with_local_recorder(&recorder, || {
+            counter!("lspmux_cc_lsp_requests_total", "method" => "textDocument/hover", "outcome" => "timeout")
+                .increment(1);
+            histogram!("lspmux_cc_lsp_request_duration_seconds", "method" => "textDocument/hover")
+                .record(0.2);
+        });
+
+        let rendered = handle.render();
+        assert!(rendered.contains(
+            r#"lspmux_cc_lsp_requests_total{method="textDocument/hover",outcome="timeout"} 1"#
+        ));
+        assert!(rendered.contains(
+            r#"lspmux_cc_lsp_request_duration_seconds_bucket{method="textDocument/hover",le="0.25"} 1"#
+        ));
+    }
+}
@@ -0,0 +1,334 @@
+//! Recording the traffic with lspmux, and replaying recordings.
+//!
+//! With `--record <DIR>`, every message exchanged with lspmux is appended to
+//! a recording in `DIR`, one JSON object per line:
+//!
+//! ```text
+//! {"ms":12,"from":"client","message":{"jsonrpc":"2.0","id":1,"method":"initialize",...}}
+//! ```
+//!
+//! where `ms` counts from the start of the client. [`replay`] plays a
+//! recording's server side back to a fresh [`LspClient`], so a report of
+//! rust-analyzer "returning something weird" becomes a regression test or an
+//! offline debugging session: make the same calls in the same order and the
+//! client sees the same answers.
+
+use std::fs::File;
+use std::io::{LineWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::task::JoinHandle;
+
+use crate::lsp_client::{uri_to_path, LspClient};
+use crate::mock_lsp::{self, MockServer};
+use crate::telemetry::now_unix_ms;
+use crate::traffic::{Origin, TrafficObserver};
+
+/// One line of a recording.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedMessage {
+    /// Milliseconds since the recording started.
+    pub ms: u64,
+    pub from: Origin,
+    pub message: Value,
+}
+
+/// Recordings started by this process, numbering their files.
+static RECORDINGS: AtomicU64 = AtomicU64::new(0);
+
+/// Appends the traffic of one client to a new recording file.
+pub struct Recorder {
+    path: PathBuf,
+    started: Instant,
+    file: Mutex<LineWriter<File>>,
+}
+
+impl Recorder {
+    /// Start a new recording in `dir`, creating the directory if needed.
+    /// An existing recording is never overwritten.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory or file cannot be created.
+    pub fn create(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create recording directory {}", dir.display()))?;
+        let started_ms = now_unix_ms().unwrap_or_default();
+        let sequence = RECORDINGS.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!(
+            "lsp-{started_ms}-{}-{sequence}.jsonl",
+            std::process::id()
+        ));
+        let file = File::options()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .with_context(|| format!("failed to create recording {}", path.display()))?;
+        Ok(Self {
+            path,
+            started: Instant::now(),
+            file: Mutex::new(LineWriter::new(file)),
+        })
+    }
+
+    /// The file being recorded to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl TrafficObserver for Recorder {
+    fn observe(&self, from: Origin, message: &Value) {
+        let line = RecordedMessage {
+            ms: u64::try_from(self.started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            from,
+            message: message.clone(),
+        };
+        let written = {
+            let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+            serde_json::to_writer(&mut *file, &line)
+                .map_err(std::io::Error::from)
+                .and_then(|()| file.write_all(b"\n"))
+        };
+        if let Err(e) = written {
+            tracing::warn!(
+                "failed to record LSP message to {}: {e}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// Read the recording at `path`.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read or a line is not a recorded
+/// message.
+pub fn load(path: &Path) -> Result<Vec<RecordedMessage>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read recording {}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).with_context(|| {
+                format!("{}:{}: not a recorded message", path.display(), index + 1)
+            })
+        })
+        .collect()
+}
+
+/// The server side of a recording being played back.
+pub struct Replay {
+    script: JoinHandle<Result<()>>,
+}
+
+impl Replay {
+    /// Wait for the rest of the recording to play out.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the client sent something other than what was
+    /// recorded, or stopped sending before the recording ended.
+    pub async fn finish(self) -> Result<()> {
+        self.script.await.context("replay task panicked")?
+    }
+}
+
+/// Connect a client to a server that plays back the recording at `path`.
+///
+/// # Errors
+///
+/// Returns an error if the recording cannot be read or the recorded
+/// handshake does not play back.
+pub async fn replay(path: &Path) -> Result<(LspClient, Replay)> {
+    replay_messages(load(path)?).await
+}
+
+/// Connect a client to a server that plays back `messages`.
+///
+/// The client is initialized with the recorded workspace root and
+/// initialization options. Recorded server messages are sent in order, each
+/// once every client message recorded before it has arrived; client
+/// messages must match the recording by method and id.
+///
+/// # Errors
+///
+/// Returns an error if the recorded handshake does not play back.
+pub async fn replay_messages(messages: Vec<RecordedMessage>) -> Result<(LspClient, Replay)> {
+    let initialize = messages
+        .iter()
+        .find(|recorded| recorded.message["method"] == "initialize")
+        .context("the recording has no initialize request")?;
+    let params = &initialize.message["params"];
+    let workspace_root = params["rootUri"]
+        .as_str()
+        .and_then(|uri| lsp_types::Uri::from_str(uri).ok())
+        .map(|uri| uri_to_path(&uri));
+    // The `lspMux` option was meant for the lspmux server, not the client.
+    let initialization_options = params
+        .get("initializationOptions")
+        .filter(|options| !options.is_null())
+        .cloned()
+        .map(|mut options| {
+            if let Some(options) = options.as_object_mut() {
+                options.remove("lspMux");
+            }
+            options
+        });
+
+    let (server, reader, writer) = mock_lsp::pipe();
+    let script = tokio::spawn(play(server, messages));
+    let client = LspClient::connect(
+        reader,
+        writer,
+        workspace_root.as_deref(),
+        initialization_options,
+    )
+    .await
+    .context("the recorded handshake did not play back")?;
+    Ok((client, Replay { script }))
+}
+
+/// Play the server's side of `messages` to `server`.
+async fn play(mut server: MockServer, messages: Vec<RecordedMessage>) -> Result<()> {
+    for (index, recorded) in messages.into_iter().enumerate() {
+        match recorded.from {
+            Origin::Server => server.send(&recorded.message).await?,
+            Origin::Client => {
+                let sent = server.recv().await.with_context(|| {
+                    format!(
+                        "the client stopped before message {index}: {}",
+                        describe(&recorded.message)
+                    )
+                })?;
+                if sent.get("method") != recorded.message.get("method")
+                    || sent.get("id") != recorded.message.get("id")
+                {
+                    bail!(
+                        "message {index}: recorded {}, but the client sent {}",
+                        describe(&recorded.message),
+                        describe(&sent)
+                    );
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// `message` in brief, e.g. `request 3 textDocument/hover`.
+fn describe(message: &Value) -> String {
+    match (message.get("id"), message["method"].as_str()) {
+        (Some(id), Some(method)) => format!("request {id} {method}"),
+        (Some(id), None) => format!("response to {id}"),
+        (None, Some(method)) => format!("notification {method}"),
+        (None, None) => "a message without id or method".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::traffic::{Observers, Tap};
+
+    /// Serve one hover with `contents`, recording the session to `dir`.
+    async fn record_session(dir: &Path, contents: &str) -> PathBuf {
+        let recorder = Arc::new(Recorder::create(dir).unwrap());
+        let observers: Observers = Arc::new([recorder.clone() as Arc<dyn TrafficObserver>]);
+        let (mut server, reader, writer) = mock_lsp::pipe();
+        let script = async {
+            let (id, _) = server.expect_request("initialize").await.unwrap();
+            server
+                .respond(id, json!({ "capabilities": { "hoverProvider": true } }))
+                .await
+                .unwrap();
+            server.expect_notification("initialized").await.unwrap();
+            let (id, _) = server.expect_request("textDocument/hover").await.unwrap();
+            server
+                .respond(id, json!({ "contents": contents }))
+                .await
+                .unwrap();
+        };
+        let session = async {
+            let client = LspClient::connect(
+                Tap::new(reader, Origin::Server, observers.clone(), Arc::default()),
+                Tap::new(writer, Origin::Client, observers.clone(), Arc::default()),
+                Some("/recorded/workspace"),
+                None,
+            )
+            .await
+            .unwrap();
+            client
+                .hover("/recorded/workspace/src/lib.rs", 0, 0)
+                .await
+                .unwrap()
+        };
+        let ((), hover) = tokio::join!(script, session);
+        assert!(hover.is_some());
+        recorder.path().to_path_buf()
+    }
+
+    #[tokio::test]
+    async fn a_recording_replays_the_same_answers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = record_session(dir.path(), "weird answer").await;
+        let recorded = load(&path).unwrap();
+        assert_eq!(recorded[0].from, Origin::Client);
+        assert_eq!(recorded[0].message["method"], "initialize");
+
+        let (client, replay) = replay(&path).await.unwrap();
+        assert_eq!(
+            client.workspace_root().await.as_deref(),
+            Some("/recorded/workspace")
+        );
+        let hover = client
+            .hover("/recorded/workspace/src/lib.rs", 0, 0)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            hover.contents,
+            lsp_types::HoverContents::Scalar(lsp_types::MarkedString::String(
+                "weird answer".to_string()
+            ))
+        );
+        replay.finish().await.unwrap();
+    }
+
+    #[test]
+    fn recorders_started_together_get_their_own_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = Recorder::create(dir.path()).unwrap();
+        let second = Recorder::create(dir.path()).unwrap();
+        assert_ne!(first.path(), second.path());
+        first.observe(Origin::Client, &json!({ "method": "initialized" }));
+        assert_eq!(load(first.path()).unwrap().len(), 1);
+        assert!(load(second.path()).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn replay_reports_a_client_that_strays_from_the_recording() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = record_session(dir.path(), "answer").await;
+        let (client, replay) = replay(&path).await.unwrap();
+        let _ = client
+            .document_symbols("/recorded/workspace/src/lib.rs")
+            .await;
+        let error = replay.finish().await.unwrap_err().to_string();
+        assert!(error.contains("textDocument/hover"), "{error}");
+        assert!(error.contains("textDocument/documentSymbol"), "{error}");
+    }
+}
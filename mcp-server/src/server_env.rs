@@ -0,0 +1,138 @@
+//! The environment rust-analyzer and the cargo it runs see.
+//!
+//! Projects on a nightly toolchain or with a custom linker need variables
+//! such as `RUSTUP_TOOLCHAIN` and `RUSTFLAGS`, or extra `PATH` entries, that
+//! the MCP host does not set. lspmux starts rust-analyzer with the variables
+//! its client passes on, so the [`ServerEnv`] is set on the `lspmux client`
+//! child and sent in the `lspMux` option of a socket connection. It is also
+//! merged into rust-analyzer's `cargo.extraEnv` and `check.extraEnv`, which
+//! reach cargo even when the lspmux config does not pass a variable on.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use serde_json::{json, Value};
+
+use crate::config::merge_settings;
+
+/// Variables set for rust-analyzer, and directories put in front of `PATH`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ServerEnv {
+    vars: BTreeMap<String, String>,
+    extra_path: Vec<PathBuf>,
+}
+
+impl ServerEnv {
+    /// Set `vars`, and prepend `extra_path` to the inherited `PATH`.
+    #[must_use]
+    pub const fn new(vars: BTreeMap<String, String>, extra_path: Vec<PathBuf>) -> Self {
+        Self { vars, extra_path }
+    }
+
+    /// Whether nothing is set.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.vars.is_empty() && self.extra_path.is_empty()
+    }
+
+    /// The variables to set, with `PATH` extended if there are extra
+    /// entries.
+    #[must_use]
+    pub fn vars(&self) -> Vec<(String, String)> {
+        let mut vars = self
+            .vars
+            .iter()
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect::<Vec<_>>();
+        if self.extra_path.is_empty() {
+            return vars;
+        }
+        let inherited = std::env::var_os("PATH").unwrap_or_default();
+        let dirs = self
+            .extra_path
+            .iter()
+            .cloned()
+            .chain(std::env::split_paths(&inherited).filter(|dir| !dir.as_os_str().is_empty()));
+        match std::env::join_paths(dirs) {
+            Ok(path) => vars.push(("PATH".to_string(), path.to_string_lossy().into_owned())),
+            Err(e) => tracing::warn!("not extending PATH: {e}"),
+        }
+        vars
+    }
+
+    /// The names of the variables set.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.vars
+            .keys()
+            .map(String::as_str)
+            .chain((!self.extra_path.is_empty()).then_some("PATH"))
+    }
+
+    /// `initialization_options` with the variables added to `cargo.extraEnv`
+    /// and `check.extraEnv`. A variable the options set there already keeps
+    /// its value.
+    #[must_use]
+    pub fn apply(&self, initialization_options: Option<Value>) -> Option<Value> {
+        if self.is_empty() {
+            return initialization_options;
+        }
+        let env = self
+            .vars()
+            .into_iter()
+            .map(|(name, value)| (name, Value::from(value)))
+            .collect::<serde_json::Map<_, _>>();
+        let mut options = json!({
+            "cargo": { "extraEnv": env },
+            "check": { "extraEnv": env },
+        });
+        if let Some(configured) = &initialization_options {
+            merge_settings(&mut options, configured);
+        }
+        Some(options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variables_reach_cargo_without_overriding_configured_ones() {
+        let env = ServerEnv::new(
+            BTreeMap::from([
+                (
+                    "RUSTFLAGS".to_string(),
+                    "-C link-arg=-fuse-ld=mold".to_string(),
+                ),
+                ("RUSTUP_TOOLCHAIN".to_string(), "nightly".to_string()),
+            ]),
+            vec![PathBuf::from("/opt/mold/bin")],
+        );
+        let vars = env.vars();
+        let path = &vars.iter().find(|(name, _)| name == "PATH").unwrap().1;
+        assert_eq!(
+            std::env::split_paths(path).next(),
+            Some(PathBuf::from("/opt/mold/bin"))
+        );
+        assert_eq!(
+            env.names().collect::<Vec<_>>(),
+            ["RUSTFLAGS", "RUSTUP_TOOLCHAIN", "PATH"]
+        );
+
+        let options = env
+            .apply(Some(json!({
+                "cargo": { "features": "all", "extraEnv": { "RUSTUP_TOOLCHAIN": "stable" } },
+            })))
+            .unwrap();
+        assert_eq!(options["cargo"]["features"], "all");
+        assert_eq!(options["cargo"]["extraEnv"]["RUSTUP_TOOLCHAIN"], "stable");
+        assert_eq!(
+            options["cargo"]["extraEnv"]["RUSTFLAGS"],
+            "-C link-arg=-fuse-ld=mold"
+        );
+        assert_eq!(options["check"]["extraEnv"]["RUSTUP_TOOLCHAIN"], "nightly");
+        assert_eq!(options["check"]["extraEnv"]["PATH"], path.as_str());
+
+        assert_eq!(ServerEnv::default().apply(None), None);
+    }
+}
@@ -0,0 +1,194 @@
+//! Named snapshots of the workspace's diagnostics, for
+//! `rust_diagnostics_snapshot` and `rust_diagnostics_diff`.
+//!
+//! A snapshot records every diagnostic under a path at one moment; diffing
+//! it against a fresh sweep tells what a series of edits fixed and what it
+//! broke. Edits move code around, so diagnostics are matched by file,
+//! severity, code, and message rather than by position.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Instant;
+
+use crate::tools::{DiagnosticFilter, DiagnosticRecord};
+
+/// Snapshots kept before the oldest is dropped.
+const MAX_SNAPSHOTS: usize = 16;
+
+/// The diagnostics under a path at one moment.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    /// The directory or glob that was swept.
+    pub path: String,
+    pub max_files: usize,
+    pub filter: DiagnosticFilter,
+    pub taken_at: Instant,
+    pub diagnostics: Vec<DiagnosticRecord>,
+}
+
+/// Snapshots by name, shared by every MCP session.
+#[derive(Clone, Default)]
+pub struct DiagnosticSnapshots {
+    snapshots: Arc<Mutex<HashMap<String, Snapshot>>>,
+}
+
+impl DiagnosticSnapshots {
+    /// Store `snapshot` as `name`, replacing any snapshot of that name.
+    pub fn insert(&self, name: &str, snapshot: Snapshot) {
+        let mut snapshots = self.lock();
+        if !snapshots.contains_key(name) && snapshots.len() >= MAX_SNAPSHOTS {
+            let oldest = snapshots
+                .iter()
+                .min_by_key(|(_, snapshot)| snapshot.taken_at)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                snapshots.remove(&oldest);
+            }
+        }
+        snapshots.insert(name.to_string(), snapshot);
+    }
+
+    pub fn get(&self, name: &str) -> Option<Snapshot> {
+        self.lock().get(name).cloned()
+    }
+
+    /// The names of the stored snapshots, sorted.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.lock().keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, Snapshot>> {
+        self.snapshots
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner)
+    }
+}
+
+/// How the diagnostics changed between two sweeps.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DiagnosticsDiff {
+    /// Diagnostics only in the earlier sweep, where they were then.
+    pub fixed: Vec<DiagnosticRecord>,
+    /// Diagnostics only in the later sweep.
+    pub introduced: Vec<DiagnosticRecord>,
+    pub unchanged: usize,
+}
+
+/// Compare `before` with `after`. Of several identical diagnostics in a
+/// file, only those beyond the other side's count differ.
+pub fn diff(before: &[DiagnosticRecord], after: &[DiagnosticRecord]) -> DiagnosticsDiff {
+    fn key(diagnostic: &DiagnosticRecord) -> (&str, &str, Option<&str>, &str) {
+        (
+            &diagnostic.location.file_path,
+            &diagnostic.severity,
+            diagnostic.code.as_deref(),
+            &diagnostic.message,
+        )
+    }
+
+    /// The diagnostics of `from` left over once each of `against` has
+    /// cancelled one identical diagnostic.
+    fn unmatched(from: &[DiagnosticRecord], against: &[DiagnosticRecord]) -> Vec<DiagnosticRecord> {
+        let mut counts: HashMap<_, usize> = HashMap::new();
+        for diagnostic in against {
+            *counts.entry(key(diagnostic)).or_default() += 1;
+        }
+        from.iter()
+            .filter(|diagnostic| match counts.get_mut(&key(diagnostic)) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    false
+                }
+                _ => true,
+            })
+            .cloned()
+            .collect()
+    }
+
+    let fixed = unmatched(before, after);
+    let introduced = unmatched(after, before);
+    DiagnosticsDiff {
+        unchanged: before.len() - fixed.len(),
+        fixed,
+        introduced,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::LocationRecord;
+
+    fn record(file_path: &str, line: u32, code: &str, message: &str) -> DiagnosticRecord {
+        DiagnosticRecord {
+            index: 0,
+            severity: "error".to_string(),
+            message: message.to_string(),
+            code: Some(code.to_string()),
+            source: Some("rustc".to_string()),
+            location: LocationRecord {
+                file_path: file_path.to_string(),
+                uri: format!("file://{file_path}"),
+                line,
+                column: 1,
+                end_line: line,
+                end_column: 2,
+                display: format!("{file_path}:{line}:1"),
+                snippet: None,
+            },
+            related: Vec::new(),
+            tags: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn diffs_match_moved_diagnostics_and_count_duplicates() {
+        let before = [
+            record("/ws/a.rs", 3, "E0308", "mismatched types"),
+            record("/ws/a.rs", 9, "E0425", "cannot find value `x`"),
+            record("/ws/a.rs", 12, "E0425", "cannot find value `x`"),
+        ];
+        let after = [
+            // Moved down by an edit above it: unchanged.
+            record("/ws/a.rs", 5, "E0308", "mismatched types"),
+            record("/ws/a.rs", 14, "E0425", "cannot find value `x`"),
+            // Same message in another file: new.
+            record("/ws/b.rs", 1, "E0308", "mismatched types"),
+        ];
+        let diff = diff(&before, &after);
+        assert_eq!(diff.unchanged, 2);
+        let lines = |records: &[DiagnosticRecord]| -> Vec<(String, u32)> {
+            records
+                .iter()
+                .map(|record| (record.location.file_path.clone(), record.location.line))
+                .collect()
+        };
+        assert_eq!(lines(&diff.fixed), [("/ws/a.rs".to_string(), 12)]);
+        assert_eq!(lines(&diff.introduced), [("/ws/b.rs".to_string(), 1)]);
+    }
+
+    #[test]
+    fn the_oldest_snapshot_makes_room_for_a_new_name() {
+        let snapshots = DiagnosticSnapshots::default();
+        let start = Instant::now();
+        let snapshot = |secs| Snapshot {
+            path: "/ws".to_string(),
+            max_files: 10,
+            filter: DiagnosticFilter::default(),
+            taken_at: start + std::time::Duration::from_secs(secs),
+            diagnostics: Vec::new(),
+        };
+        for index in 0..MAX_SNAPSHOTS {
+            snapshots.insert(&format!("s{index:02}"), snapshot(index as u64));
+        }
+        // Replacing an existing name evicts nothing.
+        snapshots.insert("s05", snapshot(100));
+        assert_eq!(snapshots.names().len(), MAX_SNAPSHOTS);
+        snapshots.insert("new", snapshot(101));
+        let names = snapshots.names();
+        assert_eq!(names.len(), MAX_SNAPSHOTS);
+        assert!(names.contains(&"new".to_string()) && !names.contains(&"s00".to_string()));
+    }
+}
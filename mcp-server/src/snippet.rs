@@ -0,0 +1,329 @@
+//! Rendering LSP snippets as the text an editor would insert.
+//!
+//! With snippet support advertised, rust-analyzer returns the edits of many
+//! assists as snippets: `$0` marks where the cursor ends up and `${1:name}`
+//! a placeholder to type over. Written to disk as is, they break the code.
+//! [`parse`] renders a snippet as plain text and records where its tab
+//! stops are. Edits that arrive marked as snippets are tagged with
+//! [`SNIPPET_ANNOTATION`] by [`mark_snippet_edits`], so the edit engine
+//! knows to render them; lsp-types drops the `insertTextFormat` that marks
+//! them otherwise.
+
+use std::ops::Range;
+
+use serde_json::Value;
+
+/// The change annotation id of a text edit whose new text is a snippet.
+pub const SNIPPET_ANNOTATION: &str = "lspmux-cc/snippet";
+
+/// `insertTextFormat` of a snippet, as opposed to plain text (1).
+const SNIPPET_FORMAT: u64 = 2;
+
+/// A snippet rendered as plain text.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Snippet {
+    pub text: String,
+    /// The tab stops, in the order an editor visits them: by index, with
+    /// the final cursor position (`$0`) last.
+    pub tab_stops: Vec<TabStop>,
+}
+
+/// A tab stop, or a placeholder with its default text.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TabStop {
+    pub index: u32,
+    /// The byte range of the placeholder text in [`Snippet::text`]; empty
+    /// for a bare tab stop.
+    pub range: Range<usize>,
+}
+
+/// Render `snippet`.
+///
+/// Placeholders and choices keep their default text, variables their
+/// default or nothing, and escapes (`\$`, `\}`, `\\`) their character. A
+/// `$` that starts no valid construct is kept as is.
+#[must_use]
+pub fn parse(snippet: &str) -> Snippet {
+    let mut parser = Parser {
+        rest: snippet,
+        out: Snippet::default(),
+    };
+    parser.any(false);
+    let mut rendered = parser.out;
+    rendered
+        .tab_stops
+        .sort_by_key(|stop| (stop.index == 0, stop.index, stop.range.start));
+    rendered
+}
+
+/// Tag the snippet text edits anywhere in `value` with
+/// [`SNIPPET_ANNOTATION`].
+///
+/// Snippet edits are those with `insertTextFormat: 2`, such as in the
+/// edits of a code action. Edits in a `changes` map, which cannot carry an
+/// annotation, are rendered in place instead.
+pub fn mark_snippet_edits(value: &mut Value) {
+    mark(value, true);
+}
+
+fn mark(value: &mut Value, annotate: bool) {
+    match value {
+        Value::Object(map) => {
+            let is_snippet = map.get("insertTextFormat").and_then(Value::as_u64)
+                == Some(SNIPPET_FORMAT)
+                && map.contains_key("range")
+                && map.get("newText").is_some_and(Value::is_string);
+            if is_snippet {
+                if annotate {
+                    map.insert("annotationId".to_string(), SNIPPET_ANNOTATION.into());
+                } else if let Some(Value::String(new_text)) = map.get_mut("newText") {
+                    *new_text = parse(new_text).text;
+                }
+                map.remove("insertTextFormat");
+                return;
+            }
+            for (key, child) in map.iter_mut() {
+                mark(child, annotate && key != "changes");
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                mark(item, annotate);
+            }
+        }
+        _ => {}
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+    out: Snippet,
+}
+
+impl<'a> Parser<'a> {
+    /// Render text and snippet constructs up to the end or, when `nested`
+    /// in a placeholder, up to its closing `}`.
+    fn any(&mut self, nested: bool) {
+        while let Some(ch) = self.rest.chars().next() {
+            match ch {
+                '}' if nested => return,
+                '\\' => match self.rest[1..].chars().next() {
+                    Some(escaped @ ('$' | '}' | '\\')) => {
+                        self.out.text.push(escaped);
+                        self.rest = &self.rest[2..];
+                    }
+                    _ => self.literal(ch),
+                },
+                '$' if self.construct() => {}
+                _ => self.literal(ch),
+            }
+        }
+    }
+
+    fn literal(&mut self, ch: char) {
+        self.out.text.push(ch);
+        self.rest = &self.rest[ch.len_utf8()..];
+    }
+
+    /// Render the construct the `$` at the start of the input begins.
+    /// Returns false, consuming nothing, if it begins none.
+    fn construct(&mut self) -> bool {
+        let after = &self.rest[1..];
+        if let Some((index, tail)) = split_int(after) {
+            self.stop(index, self.out.text.len());
+            self.rest = tail;
+            return true;
+        }
+        if let Some((_, tail)) = split_name(after) {
+            // Variables have no value here.
+            self.rest = tail;
+            return true;
+        }
+        let Some(body) = after.strip_prefix('{') else {
+            return false;
+        };
+        let start = self.out.text.len();
+        if let Some((index, tail)) = split_int(body) {
+            if let Some(tail) = tail.strip_prefix('}') {
+                self.stop(index, start);
+                self.rest = tail;
+                return true;
+            }
+            if let Some(tail) = tail.strip_prefix(':') {
+                return self.placeholder(tail).is_some_and(|()| {
+                    self.stop(index, start);
+                    true
+                });
+            }
+            if let Some(tail) = tail.strip_prefix('|') {
+                let Some((first, tail)) = first_choice(tail) else {
+                    return false;
+                };
+                self.out.text.push_str(&first);
+                self.stop(index, start);
+                self.rest = tail;
+                return true;
+            }
+            return false;
+        }
+        let Some((_, tail)) = split_name(body) else {
+            return false;
+        };
+        if let Some(tail) = tail.strip_prefix('}') {
+            self.rest = tail;
+            return true;
+        }
+        if let Some(tail) = tail.strip_prefix(':') {
+            return self.placeholder(tail).is_some();
+        }
+        // A transform, `${name/regex/format/options}`, of a variable
+        // without a value.
+        if tail.starts_with('/') {
+            if let Some(end) = tail.find('}') {
+                self.rest = &tail[end + 1..];
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Render the default text of a placeholder that starts `body`, up to
+    /// its closing `}`. Consumes nothing if it is not closed.
+    fn placeholder(&mut self, body: &'a str) -> Option<()> {
+        let (rest, len, stops) = (self.rest, self.out.text.len(), self.out.tab_stops.len());
+        self.rest = body;
+        self.any(true);
+        if let Some(tail) = self.rest.strip_prefix('}') {
+            self.rest = tail;
+            return Some(());
+        }
+        self.rest = rest;
+        self.out.text.truncate(len);
+        self.out.tab_stops.truncate(stops);
+        None
+    }
+
+    /// Record tab stop `index` from `start` to the end of the output.
+    fn stop(&mut self, index: u32, start: usize) {
+        self.out.tab_stops.push(TabStop {
+            index,
+            range: start..self.out.text.len(),
+        });
+    }
+}
+
+/// The integer `text` starts with, and the rest.
+fn split_int(text: &str) -> Option<(u32, &str)> {
+    let digits = text.len()
+        - text
+            .trim_start_matches(|ch: char| ch.is_ascii_digit())
+            .len();
+    let index = text[..digits].parse().ok()?;
+    Some((index, &text[digits..]))
+}
+
+/// The variable name `text` starts with, and the rest.
+fn split_name(text: &str) -> Option<(&str, &str)> {
+    let first = text.chars().next()?;
+    if first != '_' && !first.is_ascii_alphabetic() {
+        return None;
+    }
+    let len = text.len()
+        - text
+            .trim_start_matches(|ch: char| ch == '_' || ch.is_ascii_alphanumeric())
+            .len();
+    Some((&text[..len], &text[len..]))
+}
+
+/// The first option of a choice whose options start `text`, unescaped, and
+/// the rest after the closing `|}`.
+fn first_choice(text: &str) -> Option<(String, &str)> {
+    let mut first = String::new();
+    let mut in_first = true;
+    let mut chars = text.char_indices();
+    while let Some((at, ch)) = chars.next() {
+        match ch {
+            '\\' => {
+                if let Some((_, escaped)) = chars.next() {
+                    if in_first {
+                        first.push(escaped);
+                    }
+                }
+            }
+            ',' => in_first = false,
+            '|' if text[at + 1..].starts_with('}') => return Some((first, &text[at + 2..])),
+            _ if in_first => first.push(ch),
+            _ => {}
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+#[allow(clippy::literal_string_with_formatting_args)] // snippets look like format strings
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn stops(snippet: &Snippet) -> Vec<(u32, &str)> {
+        snippet
+            .tab_stops
+            .iter()
+            .map(|stop| (stop.index, &snippet.text[stop.range.clone()]))
+            .collect()
+    }
+
+    #[test]
+    fn renders_placeholders_and_records_tab_stops() {
+        let snippet = parse("fn ${1:fun_name}(${2:arg}: ${3|u32,u64|}) {\n    $0\n}");
+        assert_eq!(snippet.text, "fn fun_name(arg: u32) {\n    \n}");
+        assert_eq!(
+            stops(&snippet),
+            [(1, "fun_name"), (2, "arg"), (3, "u32"), (0, "")]
+        );
+        assert_eq!(snippet.tab_stops[3].range, 28..28);
+
+        let nested = parse("${1:Vec<${2:T}>}");
+        assert_eq!(nested.text, "Vec<T>");
+        assert_eq!(stops(&nested), [(1, "Vec<T>"), (2, "T")]);
+    }
+
+    #[test]
+    fn escapes_variables_and_stray_dollars_render_as_text() {
+        let snippet = parse(r#"println!("\$x = {\}", "\\n");$TM_SELECTED_TEXT${VAR:default}$"#);
+        assert_eq!(snippet.text, r#"println!("$x = {}", "\n");default$"#);
+        assert!(snippet.tab_stops.is_empty());
+        assert_eq!(parse("${1:unclosed").text, "${1:unclosed");
+        assert_eq!(parse("a \\b").text, "a \\b");
+    }
+
+    #[test]
+    fn snippet_edits_are_tagged_or_rendered() {
+        let edit = |new_text: &str| {
+            json!({
+                "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } },
+                "newText": new_text,
+                "insertTextFormat": 2,
+            })
+        };
+        let mut action = json!({
+            "title": "Generate new",
+            "edit": {
+                "documentChanges": [{
+                    "textDocument": { "uri": "file:///tmp/lib.rs", "version": 1 },
+                    "edits": [edit("fn ${0:new}() {}")],
+                }],
+                "changes": { "file:///tmp/main.rs": [edit("$0x")] },
+            },
+        });
+        mark_snippet_edits(&mut action);
+        let tagged = &action["edit"]["documentChanges"][0]["edits"][0];
+        assert_eq!(tagged["annotationId"], SNIPPET_ANNOTATION);
+        assert_eq!(tagged["newText"], "fn ${0:new}() {}");
+        assert!(tagged.get("insertTextFormat").is_none());
+        let rendered = &action["edit"]["changes"]["file:///tmp/main.rs"][0];
+        assert_eq!(rendered["newText"], "x");
+        assert!(rendered.get("annotationId").is_none());
+    }
+}
@@ -0,0 +1,207 @@
+//! Legacy HTTP+SSE MCP transport (protocol revision 2024-11-05).
+//!
+//! rmcp only ships the streamable HTTP server, but some MCP clients still
+//! speak the older two-endpoint SSE transport:
+//!
+//! - `GET /sse` opens an event stream. The first event is `endpoint`, whose
+//!   data is the URL to POST client messages to (`/message?sessionId=<id>`).
+//!   Server messages follow as `message` events.
+//! - `POST /message?sessionId=<id>` delivers one JSON-RPC message from the
+//!   client and returns `202 Accepted`.
+//!
+//! Every SSE connection is its own MCP session, built by a handler factory;
+//! all sessions share the same LSP client.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::{Context, Result};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::{get, post};
+use axum::Json;
+use rmcp::model::{ClientJsonRpcMessage, ServerJsonRpcMessage};
+use rmcp::service::ServiceExt;
+use rmcp::transport::common::server_side_http::session_id;
+use rmcp::transport::Transport;
+use rmcp::{RoleServer, ServerHandler};
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tokio_util::sync::CancellationToken;
+
+/// Buffered messages per direction before senders wait.
+const SESSION_CHANNEL_CAPACITY: usize = 64;
+
+type Sessions = Arc<Mutex<HashMap<String, mpsc::Sender<ClientJsonRpcMessage>>>>;
+
+struct SseState<S> {
+    new_session: Arc<dyn Fn() -> S + Send + Sync>,
+    sessions: Sessions,
+    shutdown: CancellationToken,
+}
+
+// Manual impl: `S` itself need not be `Clone`.
+impl<S> Clone for SseState<S> {
+    fn clone(&self) -> Self {
+        Self {
+            new_session: Arc::clone(&self.new_session),
+            sessions: Arc::clone(&self.sessions),
+            shutdown: self.shutdown.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MessageQuery {
+    #[serde(rename = "sessionId")]
+    session_id: String,
+}
+
+/// An MCP transport backed by the channels of one SSE session.
+struct SessionTransport {
+    incoming: mpsc::Receiver<ClientJsonRpcMessage>,
+    outgoing: mpsc::Sender<ServerJsonRpcMessage>,
+}
+
+impl Transport<RoleServer> for SessionTransport {
+    type Error = io::Error;
+
+    fn send(
+        &mut self,
+        item: ServerJsonRpcMessage,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send + 'static {
+        let outgoing = self.outgoing.clone();
+        async move {
+            outgoing
+                .send(item)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "SSE client disconnected"))
+        }
+    }
+
+    fn receive(&mut self) -> impl Future<Output = Option<ClientJsonRpcMessage>> + Send {
+        self.incoming.recv()
+    }
+
+    async fn close(&mut self) -> Result<(), Self::Error> {
+        self.incoming.close();
+        Ok(())
+    }
+}
+
+/// Removes a session from the routing table when its SSE stream is dropped,
+/// which closes the session's inbound channel and ends the MCP service.
+struct SessionGuard {
+    sessions: Sessions,
+    id: String,
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        lock_sessions(&self.sessions).remove(&self.id);
+        tracing::info!(event = "sse_session_closed", session_id = %self.id);
+    }
+}
+
+fn lock_sessions(
+    sessions: &Sessions,
+) -> MutexGuard<'_, HashMap<String, mpsc::Sender<ClientJsonRpcMessage>>> {
+    match sessions.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Serve the SSE transport on `addr` until `shutdown` is cancelled.
+///
+/// `new_session` builds the handler for each incoming SSE connection.
+pub async fn serve<S>(
+    new_session: impl Fn() -> S + Send + Sync + 'static,
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+) -> Result<()>
+where
+    S: ServerHandler,
+{
+    let state = SseState {
+        new_session: Arc::new(new_session),
+        sessions: Arc::default(),
+        shutdown: shutdown.clone(),
+    };
+    let router = axum::Router::new()
+        .route("/sse", get(connect::<S>))
+        .route("/message", post(post_message::<S>))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("failed to bind MCP SSE listener on {addr}"))?;
+    tracing::info!("serving MCP over SSE at http://{addr}/sse");
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+        .context("MCP SSE server exited with an error")
+}
+
+async fn connect<S>(
+    State(state): State<SseState<S>>,
+) -> Sse<impl Stream<Item = Result<Event, axum::Error>>>
+where
+    S: ServerHandler,
+{
+    let id = session_id().to_string();
+    let (incoming_tx, incoming) = mpsc::channel(SESSION_CHANNEL_CAPACITY);
+    let (outgoing, outgoing_rx) = mpsc::channel(SESSION_CHANNEL_CAPACITY);
+    lock_sessions(&state.sessions).insert(id.clone(), incoming_tx);
+    tracing::info!(event = "sse_session_opened", session_id = %id);
+
+    let transport = SessionTransport { incoming, outgoing };
+    let server = (state.new_session)();
+    let ct = state.shutdown.child_token();
+    tokio::spawn(async move {
+        match server.serve_with_ct(transport, ct).await {
+            Ok(running) => {
+                if let Err(e) = running.waiting().await {
+                    tracing::warn!("SSE MCP session ended with an error: {e}");
+                }
+            }
+            Err(e) => tracing::warn!("failed to start SSE MCP session: {e}"),
+        }
+    });
+
+    let guard = SessionGuard {
+        sessions: Arc::clone(&state.sessions),
+        id: id.clone(),
+    };
+    let endpoint = Event::default()
+        .event("endpoint")
+        .data(format!("/message?sessionId={id}"));
+    let messages = ReceiverStream::new(outgoing_rx).map(move |message| {
+        let _session = &guard;
+        Event::default().event("message").json_data(message)
+    });
+    Sse::new(tokio_stream::once(Ok(endpoint)).chain(messages)).keep_alive(KeepAlive::default())
+}
+
+async fn post_message<S>(
+    State(state): State<SseState<S>>,
+    Query(query): Query<MessageQuery>,
+    Json(message): Json<ClientJsonRpcMessage>,
+) -> StatusCode {
+    let sender = lock_sessions(&state.sessions)
+        .get(&query.session_id)
+        .cloned();
+    let Some(sender) = sender else {
+        return StatusCode::NOT_FOUND;
+    };
+    if sender.send(message).await.is_err() {
+        return StatusCode::GONE;
+    }
+    StatusCode::ACCEPTED
+}
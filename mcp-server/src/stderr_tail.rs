@@ -0,0 +1,129 @@
+//! The last lines written to stderr by the processes behind a client.
+//!
+//! rust-analyzer reports panics, out-of-memory aborts, and broken project
+//! loads on stderr, not over LSP. Inherited, that output ends up somewhere
+//! in the MCP host's log while the tool call only sees "request failed". The
+//! stderr of the `lspmux client` child and of a managed `lspmux server`
+//! (which rust-analyzer's own stderr goes to) is piped instead, logged at
+//! debug level, and its last lines kept in a [`StderrTail`] so tool errors
+//! can show them.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+
+/// Lines kept per tail.
+pub const STDERR_TAIL_LINES: usize = 50;
+
+/// Lines appended to a tool error.
+const ATTACHED_LINES: usize = 20;
+
+/// Longer lines are cut to this many characters.
+const MAX_LINE_CHARS: usize = 500;
+
+/// A bounded buffer of recent stderr lines, shared by its clones.
+#[derive(Clone, Debug, Default)]
+pub struct StderrTail {
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl StderrTail {
+    /// Drain `stderr` of the process named `source` into the tail in the
+    /// background, until it closes.
+    pub fn capture<R>(&self, stderr: R, source: &'static str)
+    where
+        R: AsyncRead + Unpin + Send + 'static,
+    {
+        let tail = self.clone();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(stderr).lines();
+            loop {
+                match lines.next_line().await {
+                    Ok(Some(line)) => {
+                        tracing::debug!(event = "lsp_stderr", source, "{line}");
+                        tail.push(&line);
+                    }
+                    Ok(None) => return,
+                    Err(e) => {
+                        tracing::debug!("stopped reading {source} stderr: {e}");
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Keep `line`, dropping the oldest once the tail is full.
+    pub fn push(&self, line: &str) {
+        let line = line.trim_end();
+        if line.is_empty() {
+            return;
+        }
+        let line = match line.char_indices().nth(MAX_LINE_CHARS) {
+            Some((cut, _)) => format!("{}...", &line[..cut]),
+            None => line.to_string(),
+        };
+        let mut lines = self.lines.lock().unwrap_or_else(PoisonError::into_inner);
+        if lines.len() == STDERR_TAIL_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    /// The last `count` lines, oldest first.
+    #[must_use]
+    pub fn last(&self, count: usize) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(PoisonError::into_inner);
+        lines
+            .iter()
+            .skip(lines.len().saturating_sub(count))
+            .cloned()
+            .collect()
+    }
+
+    /// `message` followed by the last stderr lines, if there are any.
+    #[must_use]
+    pub fn annotate(&self, message: &str) -> String {
+        let lines = self.last(ATTACHED_LINES);
+        if lines.is_empty() {
+            return message.to_string();
+        }
+        format!(
+            "{message}\n\nLast {} line(s) of language server stderr:\n{}",
+            lines.len(),
+            lines.join("\n")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn keeps_the_last_lines_of_a_stream() {
+        let tail = StderrTail::default();
+        let mut output = (0..STDERR_TAIL_LINES + 5)
+            .map(|i| format!("line {i}"))
+            .collect::<Vec<_>>()
+            .join("\n");
+        output.push_str("\nthread 'main' panicked at crates/hir/src/lib.rs\n");
+        tail.capture(std::io::Cursor::new(output.into_bytes()), "test");
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while tail.last(1) != ["thread 'main' panicked at crates/hir/src/lib.rs"] {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+
+        assert_eq!(tail.last(usize::MAX).len(), STDERR_TAIL_LINES);
+        let annotated = tail.annotate("diagnostics request failed");
+        assert!(annotated.starts_with(
+            "diagnostics request failed\n\nLast 20 line(s) of language server stderr:\nline 36\n"
+        ));
+        assert!(annotated.ends_with("panicked at crates/hir/src/lib.rs"));
+        assert_eq!(StderrTail::default().annotate("failed"), "failed");
+    }
+}
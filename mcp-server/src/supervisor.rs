@@ -0,0 +1,289 @@
+//! A private lspmux server owned and supervised by this process.
+//!
+//! With the `managed` bootstrap mode, lspmux-cc-mcp does not look for a
+//! shared service. It writes its own lspmux config listening on a free
+//! loopback port, runs `lspmux server` as a child process, and restarts it
+//! with exponential backoff whenever it exits. Each (re)start picks a fresh
+//! port and rewrites the config, so clients started afterwards follow it.
+
+use std::fmt::Write as _;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use metrics::counter;
+use tokio::process::{Child, Command};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use crate::bootstrap::{tcp_is_ready, ConnectAddr};
+use crate::stderr_tail::StderrTail;
+
+pub(crate) const LOOPBACK: &str = "127.0.0.1";
+
+/// How long a freshly spawned server has to start accepting connections.
+const READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// First delay before restarting a server that exited.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Upper bound on the restart delay.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// A server that stays up this long resets the backoff.
+const STABLE_UPTIME: Duration = Duration::from_mins(1);
+
+/// Environment forwarded from clients to rust-analyzer, as in `config/lspmux.toml`.
+const PASS_ENVIRONMENT: &[&str] = &[
+    "CARGO_HOME",
+    "RUSTUP_HOME",
+    "RUSTUP_TOOLCHAIN",
+    "RUSTFLAGS",
+    "CARGO",
+    "PATH",
+    "HOME",
+    "USER",
+    "WORKSPACE_ROOT",
+    "LSPMUX_CLIENT_KIND",
+    "LSPMUX_CLIENT_HOST",
+    "LSPMUX_SESSION_ID",
+];
+
+/// A running, supervised lspmux server.
+pub struct ManagedServer {
+    dir: PathBuf,
+    config_path: PathBuf,
+    port: Arc<AtomicU16>,
+    shutdown: CancellationToken,
+    supervisor: JoinHandle<()>,
+}
+
+impl ManagedServer {
+    /// Write a private config, start `lspmux server`, and keep it running,
+    /// keeping the stderr of every instance in `stderr`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the config cannot be written or the first server
+    /// does not start accepting connections.
+    pub async fn start(lspmux_path: &str, stderr: StderrTail) -> Result<Self> {
+        let dir = std::env::temp_dir().join(format!("lspmux-cc-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+        let config_path = dir.join("lspmux.toml");
+
+        let (child, port) = launch(lspmux_path, &config_path, &stderr).await?;
+        tracing::info!(
+            event = "managed_lspmux_started",
+            port,
+            config = %config_path.display()
+        );
+
+        let port = Arc::new(AtomicU16::new(port));
+        let shutdown = CancellationToken::new();
+        let supervisor = tokio::spawn(supervise(
+            child,
+            lspmux_path.to_string(),
+            config_path.clone(),
+            Arc::clone(&port),
+            stderr,
+            shutdown.clone(),
+        ));
+        Ok(Self {
+            dir,
+            config_path,
+            port,
+            shutdown,
+            supervisor,
+        })
+    }
+
+    /// The generated lspmux config; pass it to `lspmux client --config`.
+    #[must_use]
+    pub fn config_path(&self) -> &Path {
+        &self.config_path
+    }
+
+    /// The address the current server instance listens on.
+    #[must_use]
+    pub fn addr(&self) -> String {
+        format!("{LOOPBACK}:{}", self.port.load(Ordering::Acquire))
+    }
+
+    /// The endpoint of the current server instance.
+    #[must_use]
+    pub fn endpoint(&self) -> ConnectAddr {
+        ConnectAddr::Tcp(LOOPBACK.to_string(), self.port.load(Ordering::Acquire))
+    }
+
+    /// Whether the current server instance accepts connections.
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        tcp_is_ready(LOOPBACK, self.port.load(Ordering::Acquire))
+    }
+
+    /// Stop the server and remove the generated config.
+    pub async fn shutdown(self) {
+        self.shutdown.cancel();
+        if let Err(e) = self.supervisor.await {
+            tracing::warn!("managed lspmux supervisor panicked: {e}");
+        }
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            tracing::warn!("failed to remove {}: {e}", self.dir.display());
+        }
+    }
+}
+
+/// An lspmux config for a server listening, and clients connecting, on
+/// loopback `port`.
+#[must_use]
+pub fn loopback_config(port: u16) -> String {
+    let pass_environment = PASS_ENVIRONMENT
+        .iter()
+        .fold(String::new(), |mut out, name| {
+            let _ = writeln!(out, "  {name:?},");
+            out
+        });
+    format!(
+        "instance_timeout = 300\n\
+         gc_interval = 10\n\
+         listen = [\"{LOOPBACK}\", {port}]\n\
+         connect = [\"{LOOPBACK}\", {port}]\n\
+         pass_environment = [\n{pass_environment}]\n"
+    )
+}
+
+/// A loopback port that is free right now.
+pub(crate) fn free_port() -> Result<u16> {
+    let listener =
+        TcpListener::bind((LOOPBACK, 0)).context("failed to find a free loopback port")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// Write a config for a fresh port and spawn a server on it, waiting until
+/// it accepts connections.
+async fn launch(
+    lspmux_path: &str,
+    config_path: &Path,
+    stderr: &StderrTail,
+) -> Result<(Child, u16)> {
+    let port = free_port()?;
+    std::fs::write(config_path, loopback_config(port))
+        .with_context(|| format!("failed to write {}", config_path.display()))?;
+
+    let mut command = Command::new(lspmux_path);
+    command
+        .arg("server")
+        .arg("--config")
+        .arg(config_path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // Outlive a terminal Ctrl-C until clients have shut down; see `LspClient::spawn`.
+    #[cfg(unix)]
+    command.process_group(0);
+    let mut child = command
+        .spawn()
+        .context("failed to spawn managed lspmux server")?;
+    if let Some(child_stderr) = child.stderr.take() {
+        stderr.capture(child_stderr, "lspmux server");
+    }
+
+    let deadline = Instant::now() + READY_TIMEOUT;
+    loop {
+        if tcp_is_ready(LOOPBACK, port) {
+            return Ok((child, port));
+        }
+        if let Some(status) = child.try_wait()? {
+            bail!("managed lspmux server exited during startup with {status}");
+        }
+        if Instant::now() >= deadline {
+            // `kill_on_drop` reaps the child.
+            bail!("managed lspmux server did not listen on {LOOPBACK}:{port} within 5s");
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Restart the server whenever it exits, until `shutdown` is cancelled.
+async fn supervise(
+    mut child: Child,
+    lspmux_path: String,
+    config_path: PathBuf,
+    port: Arc<AtomicU16>,
+    stderr: StderrTail,
+    shutdown: CancellationToken,
+) {
+    let mut backoff = INITIAL_BACKOFF;
+    loop {
+        let started = Instant::now();
+        tokio::select! {
+            () = shutdown.cancelled() => {
+                if let Err(e) = child.kill().await {
+                    tracing::warn!("failed to stop managed lspmux server: {e}");
+                }
+                return;
+            }
+            status = child.wait() => {
+                tracing::warn!(event = "managed_lspmux_exited", status = ?status);
+            }
+        }
+        if started.elapsed() >= STABLE_UPTIME {
+            backoff = INITIAL_BACKOFF;
+        }
+
+        loop {
+            tokio::select! {
+                () = shutdown.cancelled() => return,
+                () = sleep(backoff) => {}
+            }
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+            match launch(&lspmux_path, &config_path, &stderr).await {
+                Ok((next, next_port)) => {
+                    child = next;
+                    port.store(next_port, Ordering::Release);
+                    counter!("lspmux_cc_managed_server_restarts_total").increment(1);
+                    tracing::info!(event = "managed_lspmux_restarted", port = next_port);
+                    break;
+                }
+                Err(e) => tracing::warn!("failed to restart managed lspmux server: {e:#}"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_config_listens_and_connects_on_the_same_port() {
+        let table: toml::Table = loopback_config(4242).parse().unwrap();
+        let addr = toml::Value::Array(vec![LOOPBACK.into(), 4242.into()]);
+        assert_eq!(table["listen"], addr);
+        assert_eq!(table["connect"], addr);
+        assert_eq!(
+            table["pass_environment"].as_array().unwrap().len(),
+            PASS_ENVIRONMENT.len()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn launch_reports_a_server_that_exits_immediately() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = launch(
+            "/bin/false",
+            &dir.path().join("lspmux.toml"),
+            &StderrTail::default(),
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("exited during startup"), "{err}");
+    }
+}
@@ -0,0 +1,396 @@
+//! An in-process index of the workspace's symbols for `rust_symbol_search`.
+//!
+//! rust-analyzer's `workspace/symbol` answers one query at a time and caps
+//! its results. The index sweeps `textDocument/documentSymbol` over every
+//! Rust file in the workspace once, then answers fuzzy searches, kind
+//! filters, and per-crate listings from memory. When the LSP client's
+//! generation moves (a document changed, or rust-analyzer saw files change on
+//! disk), the files are stat'ed again and only those that changed are swept.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use futures::stream::{self, StreamExt};
+use lspmux_cc_mcp::lsp_client::LspClient;
+use rmcp::ErrorData as McpError;
+use tokio::sync::Mutex;
+
+use crate::files::rust_files;
+
+/// Files indexed at most; the rest of a larger workspace is left out.
+const MAX_INDEXED_FILES: usize = 20_000;
+
+/// Files whose symbols are requested from rust-analyzer at once.
+const SWEEP_CONCURRENCY: usize = 8;
+
+/// A symbol defined in a workspace file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedSymbol {
+    pub name: String,
+    pub kind: lsp_types::SymbolKind,
+    /// The name of the enclosing symbol, e.g. `impl Display for Config`.
+    pub container: Option<String>,
+    pub file_path: String,
+    /// The range of the symbol's name.
+    pub range: lsp_types::Range,
+}
+
+/// What a search keeps besides the query match.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolFilter<'a> {
+    /// Fuzzy query; empty matches every symbol.
+    pub query: &'a str,
+    pub kinds: &'a [lsp_types::SymbolKind],
+    /// Only symbols of files in this crate.
+    pub crate_name: Option<&'a str>,
+}
+
+/// How the index changed to answer a search.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Refresh {
+    pub indexed_files: usize,
+    /// Files swept again because they were new or had changed.
+    pub swept_files: usize,
+    /// Files rust-analyzer could not list the symbols of; they are retried
+    /// on the next search.
+    pub failed_files: usize,
+    /// Whether the workspace has more Rust files than are indexed.
+    pub truncated: bool,
+}
+
+struct IndexedFile {
+    stamp: Option<(SystemTime, u64)>,
+    crate_name: Option<String>,
+    symbols: Vec<IndexedSymbol>,
+}
+
+#[derive(Default)]
+struct IndexState {
+    root: PathBuf,
+    /// The client generation the files were last checked at.
+    checked_at: Option<u64>,
+    files: HashMap<PathBuf, IndexedFile>,
+    truncated: bool,
+}
+
+/// The symbol index, shared by every MCP session.
+#[derive(Clone, Default)]
+pub struct SymbolIndex {
+    state: Arc<Mutex<IndexState>>,
+}
+
+impl SymbolIndex {
+    /// The symbols under `root` matching `filter`, best match first, after
+    /// bringing the index up to date. `crate_of` names the crate a file
+    /// belongs to.
+    ///
+    /// # Errors
+    ///
+    /// Returns `invalid_params` if `root` cannot be searched for files.
+    pub async fn search(
+        &self,
+        lsp: &LspClient,
+        root: &Path,
+        crate_of: impl Fn(&Path) -> Option<String>,
+        filter: &SymbolFilter<'_>,
+    ) -> Result<(Vec<IndexedSymbol>, Refresh), McpError> {
+        let mut state = self.state.lock().await;
+        let refresh = refresh(&mut state, lsp, root, crate_of).await?;
+        let symbols = search(&state, filter);
+        drop(state);
+        Ok((symbols, refresh))
+    }
+}
+
+/// Bring `state` up to date with the Rust files under `root`.
+async fn refresh(
+    state: &mut IndexState,
+    lsp: &LspClient,
+    root: &Path,
+    crate_of: impl Fn(&Path) -> Option<String>,
+) -> Result<Refresh, McpError> {
+    if state.root != root {
+        *state = IndexState {
+            root: root.to_path_buf(),
+            ..IndexState::default()
+        };
+    }
+    // Read before checking, so changes made during the sweep are caught by
+    // the next search.
+    let generation = lsp.generation();
+    if state.checked_at == Some(generation) {
+        return Ok(Refresh {
+            indexed_files: state.files.len(),
+            truncated: state.truncated,
+            ..Refresh::default()
+        });
+    }
+
+    let (files, truncated) = rust_files(&root.to_string_lossy(), MAX_INDEXED_FILES)?;
+    let stamped: Vec<(PathBuf, Option<(SystemTime, u64)>)> = files
+        .into_iter()
+        .map(|file| {
+            let stamp = std::fs::metadata(&file)
+                .ok()
+                .and_then(|metadata| Some((metadata.modified().ok()?, metadata.len())));
+            (file, stamp)
+        })
+        .collect();
+    let present: HashSet<&PathBuf> = stamped.iter().map(|(file, _)| file).collect();
+    state.files.retain(|file, _| present.contains(file));
+    let changed: Vec<(PathBuf, Option<(SystemTime, u64)>)> = stamped
+        .into_iter()
+        .filter(|(file, stamp)| {
+            stamp.is_none()
+                || state
+                    .files
+                    .get(file)
+                    .is_none_or(|indexed| indexed.stamp != *stamp)
+        })
+        .collect();
+
+    let swept: Vec<_> = stream::iter(changed)
+        .map(|(file, stamp)| async move {
+            let file_path = file.to_string_lossy().into_owned();
+            let symbols = async {
+                lsp.ensure_file_open(&file_path).await?;
+                lsp.document_symbols(&file_path).await
+            }
+            .await;
+            (file, file_path, stamp, symbols)
+        })
+        .buffer_unordered(SWEEP_CONCURRENCY)
+        .collect()
+        .await;
+
+    let swept_files = swept.len();
+    let mut failed_files = 0;
+    for (file, file_path, stamp, symbols) in swept {
+        match symbols {
+            Ok(symbols) => {
+                let indexed = IndexedFile {
+                    stamp,
+                    crate_name: crate_of(&file),
+                    symbols: symbols
+                        .map(|symbols| flatten(symbols, &file_path))
+                        .unwrap_or_default(),
+                };
+                state.files.insert(file, indexed);
+            }
+            Err(e) => {
+                tracing::warn!("failed to index symbols of {file_path}: {e:#}");
+                failed_files += 1;
+                state.files.remove(&file);
+            }
+        }
+    }
+    if failed_files == 0 {
+        state.checked_at = Some(generation);
+    }
+    state.truncated = truncated;
+    Ok(Refresh {
+        indexed_files: state.files.len(),
+        swept_files,
+        failed_files,
+        truncated,
+    })
+}
+
+/// The symbols of `state` matching `filter`, best match first, then by name
+/// and location.
+fn search(state: &IndexState, filter: &SymbolFilter<'_>) -> Vec<IndexedSymbol> {
+    let crate_name = filter.crate_name.map(|name| name.replace('-', "_"));
+    let mut matches: Vec<(u32, &IndexedSymbol)> = state
+        .files
+        .values()
+        .filter(|file| {
+            crate_name.as_ref().is_none_or(|wanted| {
+                file.crate_name
+                    .as_ref()
+                    .is_some_and(|name| &name.replace('-', "_") == wanted)
+            })
+        })
+        .flat_map(|file| &file.symbols)
+        .filter(|symbol| filter.kinds.is_empty() || filter.kinds.contains(&symbol.kind))
+        .filter_map(|symbol| Some((fuzzy_score(filter.query, &symbol.name)?, symbol)))
+        .collect();
+    matches.sort_by(|(a_score, a), (b_score, b)| {
+        b_score
+            .cmp(a_score)
+            .then_with(|| a.name.len().cmp(&b.name.len()))
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a.file_path.cmp(&b.file_path))
+            .then_with(|| a.range.start.cmp(&b.range.start))
+    });
+    matches
+        .into_iter()
+        .map(|(_, symbol)| symbol.clone())
+        .collect()
+}
+
+/// How well `name` matches `query`, higher being better: exactly, then as a
+/// prefix, then as a substring, then as characters in order with the fewest
+/// gaps. Case is ignored except to prefer an exact match. `None` if `name`
+/// lacks some of the query's characters in order.
+pub fn fuzzy_score(query: &str, name: &str) -> Option<u32> {
+    if query.is_empty() {
+        return Some(0);
+    }
+    if query == name {
+        return Some(1000);
+    }
+    let query = query.to_lowercase();
+    let lower = name.to_lowercase();
+    if query == lower {
+        return Some(900);
+    }
+    if lower.starts_with(&query) {
+        return Some(800);
+    }
+    if lower.contains(&query) {
+        return Some(700);
+    }
+    let mut gaps = 0_u32;
+    let mut chars = lower.chars();
+    for wanted in query.chars() {
+        loop {
+            match chars.next() {
+                Some(c) if c == wanted => break,
+                Some(_) => gaps += 1,
+                None => return None,
+            }
+        }
+    }
+    Some(500_u32.saturating_sub(gaps).max(1))
+}
+
+/// The symbols of a `documentSymbol` response, nested ones included.
+pub fn flatten(response: lsp_types::DocumentSymbolResponse, file_path: &str) -> Vec<IndexedSymbol> {
+    fn walk(
+        symbols: Vec<lsp_types::DocumentSymbol>,
+        container: Option<&str>,
+        file_path: &str,
+        out: &mut Vec<IndexedSymbol>,
+    ) {
+        for symbol in symbols {
+            out.push(IndexedSymbol {
+                name: symbol.name.clone(),
+                kind: symbol.kind,
+                container: container.map(str::to_string),
+                file_path: file_path.to_string(),
+                range: symbol.selection_range,
+            });
+            if let Some(children) = symbol.children {
+                walk(children, Some(&symbol.name), file_path, out);
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    match response {
+        lsp_types::DocumentSymbolResponse::Nested(symbols) => {
+            walk(symbols, None, file_path, &mut out);
+        }
+        lsp_types::DocumentSymbolResponse::Flat(symbols) => {
+            out.extend(symbols.into_iter().map(|symbol| IndexedSymbol {
+                name: symbol.name,
+                kind: symbol.kind,
+                container: symbol.container_name,
+                file_path: file_path.to_string(),
+                range: symbol.location.range,
+            }));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_scores_rank_closer_matches_first() {
+        let score = |name| fuzzy_score("lspcl", name);
+        assert!(score("lspcl") > score("LspCl"));
+        assert!(score("LspCl") > score("LspClient"));
+        assert!(score("LspClient") > score("new_lspclient"));
+        assert!(score("new_lspclient") > score("lsp_mux_client"));
+        assert!(score("lsp_mux_client") > score("lsp_mux_long_client"));
+        assert_eq!(score("ClientLsp"), None);
+        assert_eq!(fuzzy_score("", "anything"), Some(0));
+    }
+
+    #[allow(deprecated)]
+    fn symbol(name: &str, kind: lsp_types::SymbolKind) -> lsp_types::DocumentSymbol {
+        lsp_types::DocumentSymbol {
+            name: name.to_string(),
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: lsp_types::Range::default(),
+            selection_range: lsp_types::Range::default(),
+            children: None,
+        }
+    }
+
+    #[test]
+    fn searches_filter_by_kind_and_crate() {
+        let mut config = symbol("Config", lsp_types::SymbolKind::STRUCT);
+        config.children = Some(vec![symbol("load", lsp_types::SymbolKind::FIELD)]);
+        let mut state = IndexState::default();
+        for (file, crate_name, symbols) in [
+            ("/ws/core/src/lib.rs", "app-core", vec![config]),
+            (
+                "/ws/cli/src/main.rs",
+                "cli",
+                vec![symbol("load_config", lsp_types::SymbolKind::FUNCTION)],
+            ),
+        ] {
+            state.files.insert(
+                PathBuf::from(file),
+                IndexedFile {
+                    stamp: None,
+                    crate_name: Some(crate_name.to_string()),
+                    symbols: flatten(lsp_types::DocumentSymbolResponse::Nested(symbols), file),
+                },
+            );
+        }
+
+        let names = |filter: &SymbolFilter<'_>| -> Vec<(String, Option<String>)> {
+            search(&state, filter)
+                .into_iter()
+                .map(|symbol| (symbol.name, symbol.container))
+                .collect()
+        };
+        assert_eq!(
+            names(&SymbolFilter {
+                query: "load",
+                ..SymbolFilter::default()
+            }),
+            [
+                ("load".to_string(), Some("Config".to_string())),
+                ("load_config".to_string(), None),
+            ]
+        );
+        assert_eq!(
+            names(&SymbolFilter {
+                kinds: &[lsp_types::SymbolKind::FUNCTION],
+                ..SymbolFilter::default()
+            }),
+            [("load_config".to_string(), None)]
+        );
+        assert_eq!(
+            names(&SymbolFilter {
+                crate_name: Some("app_core"),
+                ..SymbolFilter::default()
+            }),
+            [
+                ("load".to_string(), Some("Config".to_string())),
+                ("Config".to_string(), None),
+            ]
+        );
+    }
+}
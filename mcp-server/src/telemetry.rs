@@ -1,10 +1,10 @@
 //! In-process telemetry and accounting for the MCP server.
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use metrics::{counter, histogram};
 use schemars::JsonSchema;
@@ -60,10 +60,51 @@ pub struct ToolTelemetry {
     pub updated_at_ms: Option<u64>,
 }
 
+/// Request accounting for one connected MCP session.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct SessionTelemetry {
+    pub transport: String,
+    pub opened_at_ms: Option<u64>,
+    pub call_count: u64,
+    pub success_count: u64,
+    pub failure_count: u64,
+    pub last_tool: Option<String>,
+    pub updated_at_ms: Option<u64>,
+}
+
+/// Latencies of the most recent calls kept per tool for percentiles.
+const LATENCY_WINDOW: usize = 256;
+
+/// Latency percentiles over a tool's most recent calls.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    /// Calls the percentiles are computed over (at most the last 256).
+    pub samples: usize,
+}
+
+impl LatencySummary {
+    fn of(window: &VecDeque<u64>) -> Option<Self> {
+        let mut sorted: Vec<u64> = window.iter().copied().collect();
+        sorted.sort_unstable();
+        // Nearest rank: the smallest sample at or above `percent` of them.
+        let rank = |percent: usize| sorted[(sorted.len() * percent).div_ceil(100).max(1) - 1];
+        (!sorted.is_empty()).then(|| Self {
+            p50_ms: rank(50),
+            p95_ms: rank(95),
+            samples: sorted.len(),
+        })
+    }
+}
+
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
 pub struct TelemetrySnapshot {
     pub bootstrap: BootstrapTelemetry,
     pub tools: BTreeMap<String, ToolTelemetry>,
+    /// Currently connected MCP sessions, keyed by session id.
+    pub sessions: BTreeMap<String, SessionTelemetry>,
+    pub closed_session_count: u64,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
@@ -118,6 +159,7 @@ impl ToolOutcome {
 #[derive(Clone)]
 pub struct TelemetryState {
     client: ClientIdentity,
+    started: Instant,
     inner: Arc<RwLock<TelemetryInner>>,
 }
 
@@ -125,6 +167,11 @@ pub struct TelemetryState {
 struct TelemetryInner {
     bootstrap: BootstrapTelemetry,
     tools: BTreeMap<String, ToolTelemetry>,
+    /// The latest [`LATENCY_WINDOW`] latencies of each tool, oldest first.
+    latencies: BTreeMap<String, VecDeque<u64>>,
+    sessions: BTreeMap<String, SessionTelemetry>,
+    next_session: u64,
+    closed_session_count: u64,
     compiler_accounting: CompilerAccountingSnapshot,
     cached_accounting_path: Option<PathBuf>,
     cached_accounting_modified_ms: Option<u64>,
@@ -144,10 +191,17 @@ impl TelemetryState {
 
         Self {
             client,
+            started: Instant::now(),
             inner: Arc::new(RwLock::new(TelemetryInner::default())),
         }
     }
 
+    /// How long this server has been running.
+    #[must_use]
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
     #[must_use]
     pub fn client_identity(&self) -> ClientIdentity {
         self.client.clone()
@@ -212,6 +266,11 @@ impl TelemetryState {
                 }
                 ToolOutcome::Failure => tool_stats.failure_count += 1,
             }
+            let window = inner.latencies.entry(tool.to_string()).or_default();
+            if window.len() == LATENCY_WINDOW {
+                window.pop_front();
+            }
+            window.push_back(latency_ms);
             drop(inner);
         }
 
@@ -230,15 +289,83 @@ impl TelemetryState {
         .record(f64::from(u32::try_from(latency_ms).unwrap_or(u32::MAX)) / 1_000.0);
     }
 
+    /// Register a newly connected MCP session and return its id.
+    ///
+    /// Ids are derived from the client session id so log lines from one
+    /// process stay correlated (e.g. `claude-mcp-123/http-2`).
+    #[must_use]
+    pub fn open_session(&self, transport: &str) -> String {
+        let opened_at_ms = now_unix_ms();
+        let mut inner = self.write_inner();
+        inner.next_session += 1;
+        let session_id = format!(
+            "{}/{transport}-{}",
+            self.client.session_id, inner.next_session
+        );
+        inner.sessions.insert(
+            session_id.clone(),
+            SessionTelemetry {
+                transport: transport.to_string(),
+                opened_at_ms,
+                ..SessionTelemetry::default()
+            },
+        );
+        drop(inner);
+
+        counter!("lspmux_cc_sessions_opened_total", "transport" => transport.to_string())
+            .increment(1);
+        tracing::info!(event = "mcp_session_opened", session_id = %session_id, transport);
+        session_id
+    }
+
+    /// Forget a disconnected MCP session.
+    pub fn close_session(&self, session_id: &str) {
+        let mut inner = self.write_inner();
+        if inner.sessions.remove(session_id).is_some() {
+            inner.closed_session_count += 1;
+        }
+        drop(inner);
+        tracing::info!(event = "mcp_session_closed", session_id = %session_id);
+    }
+
+    /// Attribute a tool call to the MCP session that issued it.
+    pub fn record_session_call(&self, session_id: &str, tool: &str, outcome: ToolOutcome) {
+        let updated_at_ms = now_unix_ms();
+        let mut inner = self.write_inner();
+        if let Some(session) = inner.sessions.get_mut(session_id) {
+            session.call_count += 1;
+            if outcome == ToolOutcome::Success {
+                session.success_count += 1;
+            } else {
+                session.failure_count += 1;
+            }
+            session.last_tool = Some(tool.to_string());
+            session.updated_at_ms = updated_at_ms;
+        }
+        drop(inner);
+    }
+
     #[must_use]
     pub fn snapshot(&self) -> TelemetrySnapshot {
         let inner = self.read_inner();
         TelemetrySnapshot {
             bootstrap: inner.bootstrap.clone(),
             tools: inner.tools.clone(),
+            sessions: inner.sessions.clone(),
+            closed_session_count: inner.closed_session_count,
         }
     }
 
+    /// Latency percentiles of each tool called so far.
+    #[must_use]
+    pub fn tool_latencies(&self) -> BTreeMap<String, LatencySummary> {
+        self.read_inner()
+            .latencies
+            .iter()
+            .filter_map(|(tool, window)| Some((tool.clone(), LatencySummary::of(window)?)))
+            .collect()
+    }
+
     #[must_use]
     pub fn compiler_accounting_snapshot(&self) -> CompilerAccountingSnapshot {
         self.read_inner().compiler_accounting.clone()
@@ -440,6 +567,29 @@ not-json
         assert_eq!(tool.last_error_code.as_deref(), Some("internal_error"));
     }
 
+    #[test]
+    fn latency_percentiles_cover_the_recent_window() {
+        let telemetry = TelemetryState::from_env();
+        for latency_ms in 1..=20 {
+            telemetry.record_tool_result(
+                "rust_hover",
+                ToolOutcome::Success,
+                latency_ms,
+                None,
+                None,
+            );
+        }
+        let hover = telemetry.tool_latencies()["rust_hover"];
+        assert_eq!((hover.p50_ms, hover.p95_ms, hover.samples), (10, 19, 20));
+
+        // Older calls fall out of the window.
+        for _ in 0..LATENCY_WINDOW {
+            telemetry.record_tool_result("rust_hover", ToolOutcome::Success, 500, None, None);
+        }
+        let hover = telemetry.tool_latencies()["rust_hover"];
+        assert_eq!((hover.p50_ms, hover.samples), (500, LATENCY_WINDOW));
+    }
+
     #[test]
     fn bootstrap_result_updates_snapshot_latency() {
         let telemetry = TelemetryState::from_env();
@@ -460,4 +610,28 @@ not-json
         assert_eq!(snapshot.bootstrap.last_latency_ms, Some(34));
         assert_eq!(snapshot.bootstrap.last_error.as_deref(), Some("boom"));
     }
+
+    #[test]
+    fn sessions_are_accounted_independently() {
+        let telemetry = TelemetryState::from_env();
+        let first = telemetry.open_session("http");
+        let second = telemetry.open_session("http");
+        assert_ne!(first, second);
+
+        telemetry.record_session_call(&first, "rust_hover", ToolOutcome::Success);
+        telemetry.record_session_call(&first, "rust_hover", ToolOutcome::Timeout);
+        telemetry.record_session_call(&second, "rust_diagnostics", ToolOutcome::Success);
+
+        let snapshot = telemetry.snapshot();
+        let first_stats = snapshot.sessions.get(&first).unwrap();
+        assert_eq!(first_stats.transport, "http");
+        assert_eq!(first_stats.call_count, 2);
+        assert_eq!(first_stats.failure_count, 1);
+        assert_eq!(snapshot.sessions.get(&second).unwrap().call_count, 1);
+
+        telemetry.close_session(&first);
+        let snapshot = telemetry.snapshot();
+        assert!(!snapshot.sessions.contains_key(&first));
+        assert_eq!(snapshot.closed_session_count, 1);
+    }
 }
@@ -0,0 +1,169 @@
+//! How long each kind of LSP request may take.
+//!
+//! A hover answers in milliseconds, while a workspace symbol search on a cold
+//! index can run for minutes; one timeout for both either gives up on the
+//! slow ones or leaves a hung hover blocking its caller far too long. Methods
+//! are grouped into [`MethodClass`]es with a timeout each, overridable in the
+//! `[timeouts]` section of the config file. Methods outside every class use
+//! the global `timeout`.
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// A group of LSP methods that share a timeout.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MethodClass {
+    /// Answers about one position: hover, signature help, completion.
+    Hover,
+    /// Searches for the uses of a symbol across the workspace.
+    References,
+    /// Symbol search over the whole workspace.
+    WorkspaceSymbol,
+    /// Renames, which resolve every reference before editing them.
+    Rename,
+}
+
+/// The class of each method with a timeout of its own.
+const METHOD_CLASSES: &[(&str, MethodClass)] = &[
+    ("textDocument/hover", MethodClass::Hover),
+    ("textDocument/signatureHelp", MethodClass::Hover),
+    ("textDocument/completion", MethodClass::Hover),
+    ("completionItem/resolve", MethodClass::Hover),
+    ("textDocument/references", MethodClass::References),
+    ("textDocument/implementation", MethodClass::References),
+    ("callHierarchy/incomingCalls", MethodClass::References),
+    ("callHierarchy/outgoingCalls", MethodClass::References),
+    ("workspace/symbol", MethodClass::WorkspaceSymbol),
+    ("textDocument/rename", MethodClass::Rename),
+    ("textDocument/prepareRename", MethodClass::Rename),
+    ("workspace/willRenameFiles", MethodClass::Rename),
+];
+
+impl MethodClass {
+    /// Every class, in config file order.
+    pub const ALL: [Self; 4] = [
+        Self::Hover,
+        Self::References,
+        Self::WorkspaceSymbol,
+        Self::Rename,
+    ];
+
+    /// The class's key in the `[timeouts]` config section.
+    #[must_use]
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Hover => "hover",
+            Self::References => "references",
+            Self::WorkspaceSymbol => "workspace_symbol",
+            Self::Rename => "rename",
+        }
+    }
+
+    /// The timeout for this class unless configured.
+    #[must_use]
+    pub const fn default_timeout(self) -> Duration {
+        match self {
+            Self::Hover => Duration::from_secs(5),
+            Self::References => Duration::from_secs(30),
+            Self::WorkspaceSymbol => Duration::from_mins(2),
+            Self::Rename => Duration::from_mins(1),
+        }
+    }
+
+    /// The class `method` belongs to, if any.
+    #[must_use]
+    pub fn of(method: &str) -> Option<Self> {
+        METHOD_CLASSES
+            .iter()
+            .find(|(name, _)| *name == method)
+            .map(|&(_, class)| class)
+    }
+}
+
+/// The timeout of every LSP request, by method.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestTimeouts {
+    default: Duration,
+    classes: BTreeMap<MethodClass, Duration>,
+}
+
+impl RequestTimeouts {
+    /// The default class timeouts, and `default` for every other method.
+    #[must_use]
+    pub fn new(default: Duration) -> Self {
+        Self {
+            default,
+            classes: MethodClass::ALL
+                .into_iter()
+                .map(|class| (class, class.default_timeout()))
+                .collect(),
+        }
+    }
+
+    /// Use `timeout` for the methods in `class`.
+    #[must_use]
+    pub fn with_class(mut self, class: MethodClass, timeout: Duration) -> Self {
+        self.classes.insert(class, timeout);
+        self
+    }
+
+    /// Override class timeouts with `seconds` per class, as read from the
+    /// config file.
+    #[must_use]
+    pub fn with_configured(self, seconds: &BTreeMap<MethodClass, u64>) -> Self {
+        seconds.iter().fold(self, |timeouts, (&class, &secs)| {
+            timeouts.with_class(class, Duration::from_secs(secs))
+        })
+    }
+
+    /// How long a `method` request may wait for its response.
+    #[must_use]
+    pub fn for_method(&self, method: &str) -> Duration {
+        MethodClass::of(method)
+            .and_then(|class| self.classes.get(&class).copied())
+            .unwrap_or(self.default)
+    }
+
+    /// The timeout for methods outside every class.
+    #[must_use]
+    pub const fn default_timeout(&self) -> Duration {
+        self.default
+    }
+
+    /// The timeout of each class.
+    pub fn classes(&self) -> impl Iterator<Item = (MethodClass, Duration)> + '_ {
+        self.classes
+            .iter()
+            .map(|(&class, &timeout)| (class, timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn methods_use_their_class_timeout_or_the_default() {
+        let timeouts = RequestTimeouts::new(Duration::from_secs(45))
+            .with_configured(&BTreeMap::from([(MethodClass::Rename, 90)]));
+        assert_eq!(
+            timeouts.for_method("textDocument/hover"),
+            Duration::from_secs(5)
+        );
+        assert_eq!(
+            timeouts.for_method("workspace/symbol"),
+            Duration::from_mins(2)
+        );
+        assert_eq!(
+            timeouts.for_method("textDocument/prepareRename"),
+            Duration::from_secs(90)
+        );
+        assert_eq!(
+            timeouts.for_method("textDocument/definition"),
+            Duration::from_secs(45)
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Picking the rust-analyzer of a workspace's pinned toolchain.
+//!
+//! rust-analyzer expands proc macros by loading the libraries cargo built
+//! for them, which only works if both come from the same release. A
+//! workspace that pins a toolchain in `rust-toolchain.toml` builds its proc
+//! macros with that toolchain, so a rust-analyzer from another one silently
+//! stops expanding them. Unless the binary was configured explicitly, a
+//! workspace with a pin is served by its toolchain's rust-analyzer, as
+//! `rustup which` finds it from the workspace root.
+
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use tokio::process::Command;
+
+use crate::bootstrap::SERVER_NAME;
+use crate::server_env::ServerEnv;
+
+/// The files rustup reads a pinned toolchain from, in the order it looks.
+pub const TOOLCHAIN_FILES: [&str; 2] = ["rust-toolchain", "rust-toolchain.toml"];
+
+/// How long `rustup which` may take, e.g. while it syncs a channel.
+const RUSTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The toolchain file in `root`, if the workspace pins one.
+#[must_use]
+pub fn toolchain_file(root: &Path) -> Option<PathBuf> {
+    TOOLCHAIN_FILES
+        .iter()
+        .map(|name| root.join(name))
+        .find(|file| file.is_file())
+}
+
+/// The rust-analyzer of the toolchain the workspace at `root` pins.
+///
+/// The pin is `RUSTUP_TOOLCHAIN` in `env` or else a toolchain file. `None`
+/// keeps the default rust-analyzer; a pinned toolchain without one is
+/// logged.
+pub async fn toolchain_server(root: &Path, env: &ServerEnv) -> Option<String> {
+    let vars = env.vars();
+    let pinned_by = match vars.iter().find(|(name, _)| name == "RUSTUP_TOOLCHAIN") {
+        Some(_) => "RUSTUP_TOOLCHAIN".to_string(),
+        None => toolchain_file(root)?.display().to_string(),
+    };
+    let which = Command::new("rustup")
+        .args(["which", SERVER_NAME])
+        .current_dir(root)
+        .envs(vars)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    let output = match tokio::time::timeout(RUSTUP_TIMEOUT, which).await {
+        Ok(Ok(output)) => output,
+        Ok(Err(e)) => {
+            tracing::warn!("{pinned_by} pins a toolchain, but rustup cannot be run: {e}");
+            return None;
+        }
+        Err(_) => {
+            tracing::warn!(
+                "`rustup which {SERVER_NAME}` in {} timed out",
+                root.display()
+            );
+            return None;
+        }
+    };
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !output.status.success() || path.is_empty() {
+        tracing::warn!(
+            "the toolchain {pinned_by} pins has no {SERVER_NAME}, so proc macros may not \
+             expand; run `rustup component add {SERVER_NAME}` in {}: {}",
+            root.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        return None;
+    }
+    tracing::info!(event = "toolchain_server", root = %root.display(), path);
+    Some(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_pinned_workspaces_look_for_a_toolchain_server() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(toolchain_file(dir.path()), None);
+        assert_eq!(
+            toolchain_server(dir.path(), &ServerEnv::default()).await,
+            None
+        );
+
+        let file = dir.path().join("rust-toolchain.toml");
+        std::fs::write(&file, "[toolchain]\nchannel = \"nightly\"\n").unwrap();
+        assert_eq!(toolchain_file(dir.path()), Some(file));
+        std::fs::write(dir.path().join("rust-toolchain"), "stable\n").unwrap();
+        assert_eq!(
+            toolchain_file(dir.path()),
+            Some(dir.path().join("rust-toolchain"))
+        );
+    }
+}
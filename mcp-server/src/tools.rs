@@ -1,32 +1,242 @@
 //! MCP tool definitions for rust-analyzer access via lspmux.
 //!
-//! Six read-only tools:
+//! Twenty-nine read-only tools:
 //! - `rust_diagnostics`: Get errors/warnings for a file
+//! - `rust_diagnostics_dir`: Get errors/warnings for every file under a directory or glob
+//! - `rust_diagnostics_changed`: Get errors/warnings for every file git reports as changed
+//! - `rust_diagnostics_snapshot`: Record the workspace's diagnostics under a name
+//! - `rust_diagnostics_diff`: Report what was fixed and introduced since a snapshot
+//! - `rust_wait_for_diagnostics`: Save a file and wait for the check that follows
+//! - `rust_run_flycheck`, `rust_cancel_flycheck`, `rust_clear_flycheck`: Start, stop,
+//!   or clear rust-analyzer's `cargo check`
 //! - `rust_hover`: Get type signature + docs at a position
 //! - `rust_goto_definition`: Find definition location
 //! - `rust_find_references`: Find all references
+//! - `rust_safe_delete_check`: Check whether a symbol or file is used from elsewhere
+//! - `rust_trait_impls`: List a trait's implementors or a type's traits, grouped by name
 //! - `rust_workspace_symbol`: Search symbols by name across the workspace
+//! - `rust_symbol_search`: Fuzzy-search an in-memory index of the workspace's symbols
+//! - `rust_locate`: Find the zero-based position of a symbol's name in a file
+//! - `rust_document_links`: List doc links and referenced files in a file, with targets
+//! - `rust_doc`: Get the docs of an item by path, with its docs.rs URL
+//! - `rust_explain_error`: Explain a compiler error code with `rustc --explain`
 //! - `rust_server_status`: Check server health and workspace bootstrap status
+//! - `rust_warmup`: Start rust-analyzer ahead of the first query
+//! - `rust_open_file`, `rust_close_file`, `rust_refresh_file`: Control which documents
+//!   rust-analyzer tracks, and resend one after an external edit
+//! - `rust_tracked_documents`: List the documents rust-analyzer holds, with versions
+//! - `rust_analyzer_health`: Run health checks with actionable fixes
+//! - `mcp_server_stats`: Uptime, per-tool call counts and latencies, cache hit rate
+//! - `lspmux_status`: The lspmux server's language-server instances and their clients
+//!
+//! and ten that edit files (each returning a unified diff of the change):
+//! - `rust_apply_quickfix`: Apply rust-analyzer's fix for a diagnostic
+//! - `rust_unused_report`: Report unused and dead code per file, optionally fixing it
+//! - `rust_organize_imports`: Remove unused imports, then merge and sort the rest
+//! - `rust_rename`: Rename a symbol across the workspace
+//! - `rust_move_file`: Move a source file or directory, fixing the `mod` declarations
+//! - `rust_extract_function`: Move a range of code into a new function
+//! - `rust_inline`: Inline a variable, function call, constant, or type alias
+//! - `rust_generate`: List or apply generators such as "Fill match arms"
+//! - `rust_auto_import`: Add the `use` declaration for an unresolved name
+//! - `rust_completions`: List completions at a position, or apply one with its imports
+//!
+//! Tools named `cargo_*` run cargo in the workspace root instead of asking
+//! rust-analyzer:
+//! - `cargo_check`: Build-wide errors and warnings from `cargo check`
+//! - `cargo_test`: Run tests and report each one's outcome
+//! - `cargo_metadata`: Packages, targets, features, and resolved dependencies
+//! - `cargo_expand`: A whole crate or module with every macro expanded
+//! - `cargo_tree`: The dependency tree, inverted trees, and duplicate versions
+//!
+//! `rust_link_project` points rust-analyzer at a `rust-project.json` for
+//! non-Cargo builds, regenerating it first if asked. `rust_restart_analyzer`
+//! replaces a degraded rust-analyzer connection with a fresh one.
+//! `rust_execute_command` runs the command a code action or code lens
+//! carries, writing the edits the server asks for meanwhile.
+//! `lspmux_restart_instance` stops a shared instance for every client
+//! attached to it, and `lspmux_reap_idle` stops those no client uses.
+//!
+//! rust-analyzer is started lazily by the first tool call that needs it;
+//! `rust_server_status`, `rust_analyzer_health`, `mcp_server_stats`, and
+//! `lspmux_status` report on it without starting it.
 
-use std::path::Path;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use futures::stream::{self, StreamExt};
+use metrics::{counter, histogram};
 use rmcp::handler::server::router::tool::ToolRouter;
-use rmcp::handler::server::tool::ToolCallContext;
+use rmcp::handler::server::tool::{schema_for_output, IntoCallToolResult, ToolCallContext};
 use rmcp::handler::server::wrapper::Parameters;
-use rmcp::model::{CallToolRequestParams, CallToolResult, ErrorCode, ListToolsResult};
-use rmcp::service::RequestContext;
-use rmcp::{tool, tool_router, ErrorData as McpError, Json, RoleServer};
+use rmcp::model::{
+    CallToolRequestParams, CallToolResult, Content, ErrorCode, JsonObject, ListToolsResult,
+    LoggingLevel, LoggingMessageNotificationParam, Meta, ProgressNotificationParam, ProgressToken,
+    Tool,
+};
+use rmcp::service::{Peer, RequestContext};
+use rmcp::{tool, tool_router, ErrorData as McpError, RoleServer};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::Instrument;
 
-use lspmux_cc_mcp::bootstrap::{RuntimeStatus, SERVER_NAME};
-use lspmux_cc_mcp::lsp_client::{file_uri, uri_to_path, LspClient};
+use lspmux_cc_mcp::bootstrap::{ConnectAddr, RuntimeStatus, SERVER_NAME};
+use lspmux_cc_mcp::config::{merge_settings, ProjectConfig};
+use lspmux_cc_mcp::health::{self, HealthReport};
+use lspmux_cc_mcp::launcher::{LspLauncher, RUST_PROJECT_FILE};
+use lspmux_cc_mcp::lsp_client::{
+    self, file_uri, uri_to_path, CargoRunnableArgs, LspClient, ProgressEvent, Runnable,
+};
+use lspmux_cc_mcp::lspmux_status::{self, LspmuxInstance};
+use lspmux_cc_mcp::pool::LspPool;
+use lspmux_cc_mcp::snippet::{self, SNIPPET_ANNOTATION};
 use lspmux_cc_mcp::telemetry::{
-    ClientIdentity, CompilerAccountingSnapshot, ReadinessState, TelemetrySnapshot, TelemetryState,
-    ToolOutcome,
+    ClientIdentity, CompilerAccountingSnapshot, LatencySummary, ReadinessState, TelemetrySnapshot,
+    TelemetryState, ToolOutcome,
 };
+use lspmux_cc_mcp::warmup;
+use lspmux_cc_mcp::watchdog::Watchdog;
+
+use crate::cache::{CacheKey, ResponseCache};
+use crate::cargo;
+use crate::edits;
+use crate::explain::{self, ExplainCache};
+use crate::files::rust_files;
+use crate::flycheck::{self, CycleOutcome};
+use crate::git;
+use crate::limits::{CallLimits, CallPermit, IndexWait, MAX_INDEX_WAITERS, MAX_QUEUE_WAIT};
+use crate::metadata::{self, Dependency, Metadata, MetadataCache, Package};
+use crate::pagination::{PageItem, PageParams, PageQuery, ResultPages};
+use crate::snapshots::{self, DiagnosticSnapshots, Snapshot};
+use crate::symbols::{self, IndexedSymbol, SymbolFilter, SymbolIndex};
+
+/// Tools that report on rust-analyzer (or start it themselves), or do not
+/// use it, and so must not start it implicitly before they run.
+const NO_AUTOSTART_TOOLS: &[&str] = &[
+    "rust_server_status",
+    "lspmux_status",
+    "lspmux_restart_instance",
+    "lspmux_reap_idle",
+    "rust_analyzer_health",
+    "rust_warmup",
+    "rust_link_project",
+    "rust_restart_analyzer",
+    "rust_close_file",
+    "rust_tracked_documents",
+    "rust_cancel_flycheck",
+    "rust_clear_flycheck",
+    "cargo_check",
+    "cargo_test",
+    "cargo_metadata",
+    "cargo_expand",
+    "cargo_tree",
+    "rust_explain_error",
+];
+
+/// Tools that need no analysis of the workspace: they only manage documents
+/// or run `cargo check`. They neither wait for indexing nor fail when the
+/// workspace did not load, since `cargo check` shows why.
+const ANALYSIS_FREE_TOOLS: &[&str] = &["rust_open_file", "rust_refresh_file", "rust_run_flycheck"];
+
+/// How often a call held for indexing tells the client it is still waiting.
+const INDEX_WAIT_UPDATE: Duration = Duration::from_secs(2);
+
+/// The part of a call's progress, out of 100, that a wait for indexing
+/// fills as it runs out; rust-analyzer's own progress takes over above it.
+const INDEX_WAIT_PROGRESS: f64 = 10.0;
+
+/// How long `rust_wait_for_diagnostics` waits when no `timeout_secs` is
+/// given, and the most it accepts.
+const DEFAULT_CHECK_WAIT_SECS: u64 = 60;
+const MAX_CHECK_WAIT_SECS: u64 = 600;
+
+/// How long an lspmux instance without clients must have been idle before
+/// `lspmux_reap_idle` stops it, unless the call says otherwise.
+const DEFAULT_REAP_IDLE_SECS: u64 = 300;
+
+/// Completions `rust_completions` lists when no `max_items` is given.
+const DEFAULT_MAX_COMPLETIONS: usize = 50;
+
+/// How long `cargo_*` tools wait for cargo when no `timeout_secs` is given.
+const DEFAULT_CARGO_TIMEOUT_SECS: u64 = 600;
+
+/// rust-analyzer's editor commands that `rust_execute_command` carries out
+/// itself, since the server leaves them to its client.
+const CLIENT_COMMANDS: [&str; 3] = [
+    "rust-analyzer.runSingle",
+    "rust-analyzer.gotoLocation",
+    "rust-analyzer.showReferences",
+];
+
+/// Output kept from a `rust-analyzer.runSingle` run; the start of longer
+/// output is cut.
+const MAX_RUN_OUTPUT_BYTES: usize = 8 * 1024;
+
+/// Diagnostics `cargo_check` returns when `max_diagnostics` is not given.
+const DEFAULT_CARGO_MAX_DIAGNOSTICS: usize = 200;
+
+/// Tests `cargo_test` returns when `max_tests` is not given.
+const DEFAULT_CARGO_MAX_TESTS: usize = 200;
+
+/// Characters of expanded code `cargo_expand` returns when `max_chars` is
+/// not given.
+const DEFAULT_EXPAND_MAX_CHARS: usize = 50_000;
+
+/// Lines of dependency tree `cargo_tree` returns when `max_entries` is not
+/// given.
+const DEFAULT_TREE_MAX_ENTRIES: usize = 500;
+
+/// Same-named items `rust_doc` hovers over looking for the one in the
+/// path's crate.
+const DOC_MAX_HOVERS: usize = 10;
+
+/// Crates documented on doc.rust-lang.org rather than docs.rs.
+const STD_CRATES: &[&str] = &["std", "core", "alloc", "proc_macro", "test"];
+
+/// Files `rust_diagnostics_dir` checks when `max_files` is not given.
+const DEFAULT_DIR_MAX_FILES: usize = 500;
+
+/// Files `rust_diagnostics_dir` queries rust-analyzer about at once.
+const DIR_DIAGNOSTICS_CONCURRENCY: usize = 8;
+
+/// Title prefixes of the assists `rust_generate` offers, besides those the
+/// server files under the generate kind.
+const GENERATOR_PREFIXES: &[&str] = &["Generate", "Implement", "Add", "Fill", "Create"];
+
+/// Compiler error codes for names that are not in scope, which an import
+/// can fix.
+const UNRESOLVED_NAME_CODES: &[&str] = &[
+    "E0405", "E0412", "E0422", "E0423", "E0425", "E0433", "E0531", "E0532",
+];
+
+/// Upper bound on `context_lines`, to keep snippets from becoming whole files.
+const MAX_CONTEXT_LINES: u32 = 20;
+
+/// Key of the correlation id in a tool result's `_meta` and an error's `data`.
+const CORRELATION_ID_FIELD: &str = "correlation_id";
+
+/// Key in an error's `data`, set to `true`, of a call that ran out of time:
+/// rust-analyzer did not answer in time, or the call waited too long in the
+/// queue.
+const TIMED_OUT_FIELD: &str = "timed_out";
+
+/// A new id for a tool call, unique across the processes on this host, e.g.
+/// `1f2a-17` for the 17th call in process `0x1f2a`.
+fn next_correlation_id() -> String {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    format!(
+        "{:x}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    )
+}
 
 /// Validate that a file path is absolute and exists on disk.
 ///
@@ -48,10 +258,204 @@ fn validate_file_path(path: &str) -> Result<(), McpError> {
     Ok(())
 }
 
+/// Whether the connected language server advertises the capability a tool needs.
+///
+/// Tools that only report local state (such as `rust_server_status`) are
+/// always available.
+fn tool_supported(name: &str, capabilities: &lsp_types::ServerCapabilities) -> bool {
+    missing_capability(name, capabilities).is_none()
+}
+
+/// The feature a tool needs that the connected language server does not
+/// advertise, in words (e.g. `"hover"`), or `None` if it has them all.
+fn missing_capability(
+    name: &str,
+    capabilities: &lsp_types::ServerCapabilities,
+) -> Option<&'static str> {
+    use lsp_types::{CodeActionProviderCapability, HoverProviderCapability, OneOf};
+
+    const fn enabled<T>(provider: Option<&OneOf<bool, T>>) -> bool {
+        matches!(provider, Some(OneOf::Left(true) | OneOf::Right(_)))
+    }
+
+    const fn code_actions(capabilities: &lsp_types::ServerCapabilities) -> bool {
+        matches!(
+            capabilities.code_action_provider,
+            Some(
+                CodeActionProviderCapability::Simple(true)
+                    | CodeActionProviderCapability::Options(_)
+            )
+        )
+    }
+
+    let (feature, supported) = match name {
+        "rust_diagnostics"
+        | "rust_diagnostics_dir"
+        | "rust_diagnostics_changed"
+        | "rust_diagnostics_snapshot"
+        | "rust_diagnostics_diff"
+        | "rust_wait_for_diagnostics"
+        | "rust_unused_report" => (
+            "pull diagnostics",
+            capabilities.diagnostic_provider.is_some(),
+        ),
+        "rust_apply_quickfix" if capabilities.diagnostic_provider.is_none() => {
+            ("pull diagnostics", false)
+        }
+        "rust_apply_quickfix"
+        | "rust_organize_imports"
+        | "rust_extract_function"
+        | "rust_inline"
+        | "rust_generate"
+        | "rust_auto_import" => ("code actions", code_actions(capabilities)),
+        "rust_rename" => ("rename", enabled(capabilities.rename_provider.as_ref())),
+        "rust_completions" => ("completion", capabilities.completion_provider.is_some()),
+        "rust_hover" => (
+            "hover",
+            matches!(
+                capabilities.hover_provider,
+                Some(HoverProviderCapability::Simple(true) | HoverProviderCapability::Options(_))
+            ),
+        ),
+        "rust_goto_definition" => (
+            "go to definition",
+            enabled(capabilities.definition_provider.as_ref()),
+        ),
+        "rust_safe_delete_check" if !enabled(capabilities.document_symbol_provider.as_ref()) => {
+            ("document symbols", false)
+        }
+        "rust_find_references" | "rust_safe_delete_check" => (
+            "find references",
+            enabled(capabilities.references_provider.as_ref()),
+        ),
+        "rust_trait_impls" => (
+            "go to implementation",
+            capabilities
+                .implementation_provider
+                .as_ref()
+                .is_some_and(|provider| {
+                    !matches!(
+                        provider,
+                        lsp_types::ImplementationProviderCapability::Simple(false)
+                    )
+                }),
+        ),
+        "rust_workspace_symbol" | "rust_doc" => (
+            "workspace symbols",
+            enabled(capabilities.workspace_symbol_provider.as_ref()),
+        ),
+        "rust_symbol_search" | "rust_locate" => (
+            "document symbols",
+            enabled(capabilities.document_symbol_provider.as_ref()),
+        ),
+        "rust_document_links" => (
+            "document links",
+            capabilities.document_link_provider.is_some(),
+        ),
+        _ => return None,
+    };
+    (!supported).then_some(feature)
+}
+
+/// The error for a query while rust-analyzer reports that it failed to load
+/// the workspace, whose answers would be empty rather than wrong-looking.
+fn load_failure(readiness: &ReadinessState) -> Option<McpError> {
+    (readiness.health == "error").then(|| {
+        let reason = readiness
+            .message
+            .as_deref()
+            .map(str::trim)
+            .filter(|message| !message.is_empty())
+            .unwrap_or("no reason given");
+        McpError::internal_error(
+            format!(
+                "workspace failed to load: {reason}. Fix the cause (often Cargo.toml) and retry \
+                 once rust_server_status reports a healthy workspace; rust_run_flycheck shows \
+                 cargo's own errors."
+            ),
+            None,
+        )
+    })
+}
+
+/// What rust-analyzer reports it is doing, from its work-done progress,
+/// e.g. `Indexing: 12/40 (30%)`.
+fn indexing_activity(active: &[ProgressEvent]) -> String {
+    let activities = active
+        .iter()
+        .map(|event| {
+            let mut activity = event.title.clone().unwrap_or_else(|| event.token.clone());
+            if let Some(message) = &event.message {
+                let _ = write!(activity, ": {message}");
+            }
+            if let Some(percentage) = event.percentage {
+                let _ = write!(activity, " ({percentage}%)");
+            }
+            activity
+        })
+        .collect::<Vec<_>>();
+    if activities.is_empty() {
+        "loading the workspace".to_string()
+    } else {
+        activities.join(", ")
+    }
+}
+
+/// The state of `file_path` in `lsp` after a document tool did `action`.
+fn document_response(
+    lsp: &LspClient,
+    file_path: String,
+    action: &str,
+    summary: String,
+) -> DocumentResponse {
+    DocumentResponse {
+        version: lsp.document_version(&file_path),
+        open_documents: lsp.open_document_count(),
+        file_path,
+        action: action.to_string(),
+        summary,
+    }
+}
+
 fn internal_error(msg: impl Into<String>) -> McpError {
     McpError::internal_error(msg.into(), None)
 }
 
+/// An internal error reporting `e`, marked as a timeout in its data if an
+/// LSP request timed out.
+fn lsp_error(e: &anyhow::Error, msg: impl Into<String>) -> McpError {
+    if lsp_client::is_timeout(e) {
+        timeout_error(msg)
+    } else {
+        internal_error(msg)
+    }
+}
+
+/// An internal error for a call that ran out of time, marked so in its data.
+fn timeout_error(msg: impl Into<String>) -> McpError {
+    McpError::internal_error(
+        msg.into(),
+        Some(serde_json::json!({ TIMED_OUT_FIELD: true })),
+    )
+}
+
+/// Whether `error` is marked as a timeout; see [`TIMED_OUT_FIELD`].
+pub fn is_timeout_error(error: &McpError) -> bool {
+    error
+        .data
+        .as_ref()
+        .and_then(|data| data.get(TIMED_OUT_FIELD))
+        .and_then(serde_json::Value::as_bool)
+        == Some(true)
+}
+
+/// Seconds since the epoch.
+fn unix_now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
 const fn diagnostic_severity_name(severity: Option<lsp_types::DiagnosticSeverity>) -> &'static str {
     match severity {
         Some(lsp_types::DiagnosticSeverity::ERROR) => "error",
@@ -62,6 +466,37 @@ const fn diagnostic_severity_name(severity: Option<lsp_types::DiagnosticSeverity
     }
 }
 
+const fn completion_kind_name(kind: lsp_types::CompletionItemKind) -> &'static str {
+    match kind {
+        lsp_types::CompletionItemKind::TEXT => "text",
+        lsp_types::CompletionItemKind::METHOD => "method",
+        lsp_types::CompletionItemKind::FUNCTION => "function",
+        lsp_types::CompletionItemKind::CONSTRUCTOR => "constructor",
+        lsp_types::CompletionItemKind::FIELD => "field",
+        lsp_types::CompletionItemKind::VARIABLE => "variable",
+        lsp_types::CompletionItemKind::CLASS => "class",
+        lsp_types::CompletionItemKind::INTERFACE => "interface",
+        lsp_types::CompletionItemKind::MODULE => "module",
+        lsp_types::CompletionItemKind::PROPERTY => "property",
+        lsp_types::CompletionItemKind::UNIT => "unit",
+        lsp_types::CompletionItemKind::VALUE => "value",
+        lsp_types::CompletionItemKind::ENUM => "enum",
+        lsp_types::CompletionItemKind::KEYWORD => "keyword",
+        lsp_types::CompletionItemKind::SNIPPET => "snippet",
+        lsp_types::CompletionItemKind::COLOR => "color",
+        lsp_types::CompletionItemKind::FILE => "file",
+        lsp_types::CompletionItemKind::REFERENCE => "reference",
+        lsp_types::CompletionItemKind::FOLDER => "folder",
+        lsp_types::CompletionItemKind::ENUM_MEMBER => "enum_member",
+        lsp_types::CompletionItemKind::CONSTANT => "constant",
+        lsp_types::CompletionItemKind::STRUCT => "struct",
+        lsp_types::CompletionItemKind::EVENT => "event",
+        lsp_types::CompletionItemKind::OPERATOR => "operator",
+        lsp_types::CompletionItemKind::TYPE_PARAMETER => "type_parameter",
+        _ => "unknown",
+    }
+}
+
 const fn symbol_kind_name(kind: lsp_types::SymbolKind) -> &'static str {
     match kind {
         lsp_types::SymbolKind::FILE => "file",
@@ -94,6 +529,28 @@ const fn symbol_kind_name(kind: lsp_types::SymbolKind) -> &'static str {
     }
 }
 
+/// The symbol kind `symbol_kind_name` calls `name`.
+fn symbol_kind_from_name(name: &str) -> Result<lsp_types::SymbolKind, McpError> {
+    // The LSP numbers symbol kinds from 1 to 26.
+    let kinds = (1..=26)
+        .filter_map(|number| serde_json::from_value::<lsp_types::SymbolKind>(number.into()).ok());
+    let mut known = Vec::new();
+    for kind in kinds {
+        let kind_name = symbol_kind_name(kind);
+        if kind_name == name {
+            return Ok(kind);
+        }
+        known.push(kind_name);
+    }
+    Err(McpError::invalid_params(
+        format!(
+            "unknown symbol kind {name:?}; known kinds: {}",
+            known.join(", ")
+        ),
+        None,
+    ))
+}
+
 fn markup_to_text(contents: lsp_types::HoverContents) -> String {
     match contents {
         lsp_types::HoverContents::Markup(markup) => markup.value,
@@ -114,692 +571,10637 @@ fn markup_to_text(contents: lsp_types::HoverContents) -> String {
     }
 }
 
-/// Tool parameter: a file path.
-#[derive(Deserialize, JsonSchema)]
-pub struct FileParam {
-    /// Absolute path to the Rust source file.
-    pub file_path: String,
+/// How `rust_hover` formats its contents.
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HoverFormat {
+    /// rust-analyzer's markdown, with fenced code blocks.
+    #[default]
+    Markdown,
+    /// Markdown syntax stripped: no fences, rules, or link targets.
+    Plaintext,
 }
 
-/// Tool parameters: file path + position (line, character).
-#[derive(Deserialize, JsonSchema)]
-pub struct PositionParam {
-    /// Absolute path to the Rust source file.
-    pub file_path: String,
-    /// Zero-based line number.
-    pub line: u32,
-    /// Zero-based character offset.
-    pub character: u32,
+/// Which parts of a hover `rust_hover` returns.
+#[derive(Clone, Copy, Debug, Default, Deserialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HoverSections {
+    /// Only the first section: the item path and its signature.
+    Signature,
+    /// The signature plus layout details and documentation.
+    #[default]
+    All,
 }
 
-/// Tool parameters: workspace symbol search query.
-#[derive(Deserialize, JsonSchema)]
-pub struct WorkspaceSymbolParam {
-    /// Substring to search for in symbol names across the workspace.
-    pub query: String,
+/// Hover output controls.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema)]
+pub struct HoverOptions {
+    /// `markdown` (default) or `plaintext`.
+    #[serde(default)]
+    pub format: HoverFormat,
+    /// `all` (default) or `signature` to drop the documentation.
+    #[serde(default)]
+    pub sections: HoverSections,
+    /// Truncate the contents to about this many characters, cutting at a
+    /// paragraph or line break where possible.
+    pub max_chars: Option<usize>,
 }
 
-/// Empty parameter struct for tools that take no arguments.
+/// Tool parameters: file path + position and hover output controls.
 #[derive(Deserialize, JsonSchema)]
-pub struct NoParams {}
-
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct LocationRecord {
-    pub file_path: String,
-    pub uri: String,
-    pub line: u32,
-    pub column: u32,
-    pub end_line: u32,
-    pub end_column: u32,
-    pub display: String,
+pub struct HoverParam {
+    #[serde(flatten)]
+    pub position: PositionParam,
+    #[serde(flatten)]
+    pub options: HoverOptions,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct PositionRecord {
-    pub line: u32,
-    pub character: u32,
+/// Apply `options` to hover markdown, returning the result and whether it
+/// was truncated.
+fn shape_hover(markdown: &str, options: &HoverOptions) -> (String, bool) {
+    let mut text = match options.sections {
+        HoverSections::All => markdown.trim().to_string(),
+        HoverSections::Signature => markdown
+            .split("\n---\n")
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_string(),
+    };
+    if options.format == HoverFormat::Plaintext {
+        text = markdown_to_plaintext(&text);
+    }
+    match options.max_chars {
+        Some(max_chars) if text.chars().count() > max_chars => {
+            (truncate_hover(&text, max_chars, options.format), true)
+        }
+        _ => (text, false),
+    }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct RangeRecord {
-    pub start: PositionRecord,
-    pub end: PositionRecord,
+/// Whether a hover shows a trait's declaration.
+fn is_trait_hover(markdown: &str) -> bool {
+    markdown.lines().any(|line| {
+        let mut line = line.trim();
+        if let Some(rest) = line.strip_prefix("pub") {
+            // `pub`, `pub(crate)`, `pub(in path)`.
+            line = rest
+                .strip_prefix('(')
+                .and_then(|rest| rest.split_once(')'))
+                .map_or(rest, |(_, rest)| rest)
+                .trim_start();
+        }
+        for prefix in ["unsafe ", "auto "] {
+            line = line.strip_prefix(prefix).unwrap_or(line);
+        }
+        line.starts_with("trait ")
+    })
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct DiagnosticRecord {
-    pub severity: String,
-    pub message: String,
-    pub code: Option<String>,
-    pub source: Option<String>,
-    pub location: LocationRecord,
+/// The identifier at a zero-based position.
+fn word_at(source: &str, line: u32, character: u32) -> Option<&str> {
+    let at = edits::byte_offset(source, lsp_types::Position::new(line, character));
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let start = source[..at]
+        .rfind(|c: char| !is_word(c))
+        .map_or(0, |index| index + 1);
+    let end = source[at..]
+        .find(|c: char| !is_word(c))
+        .map_or(source.len(), |index| at + index);
+    Some(&source[start..end]).filter(|word| !word.is_empty())
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct DiagnosticsResponse {
-    pub file_path: String,
-    pub diagnostic_count: usize,
-    pub diagnostics: Vec<DiagnosticRecord>,
-    pub summary: String,
+/// An impl block read from source.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct ImplHeader {
+    /// The header with whitespace collapsed, or the derive attribute.
+    text: String,
+    trait_name: Option<String>,
+    self_type: String,
+    derived: bool,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct HoverResponse {
-    pub file_path: String,
-    pub requested_position: PositionRecord,
-    pub found: bool,
-    pub contents: String,
-    pub range: Option<RangeRecord>,
-    pub summary: String,
+/// The impl at an implementation location: the impl block whose header
+/// holds the position, or the derive attribute naming a derived trait.
+fn impl_header(source: &str, line: u32, character: u32) -> Option<ImplHeader> {
+    let at = edits::byte_offset(source, lsp_types::Position::new(line, character));
+    let line_start = source[..at].rfind('\n').map_or(0, |index| index + 1);
+    let line_end = source[at..]
+        .find('\n')
+        .map_or(source.len(), |index| at + index);
+    if source[line_start..line_end].contains("derive(") {
+        let trait_name = word_at(source, line, character)?.to_string();
+        let self_type = source[line_end..]
+            .split_whitespace()
+            .skip_while(|word| !matches!(*word, "struct" | "enum" | "union"))
+            .nth(1)?;
+        let self_type = self_type
+            .split(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .next()?
+            .to_string();
+        return Some(ImplHeader {
+            text: format!("#[derive({trait_name})]"),
+            trait_name: Some(trait_name),
+            self_type,
+            derived: true,
+        });
+    }
+
+    // The header may span lines and the position may point at any part of
+    // it, so look back for the keyword and forward for the body.
+    let search_end = (at + "impl".len()).min(source.len());
+    let impl_at = source[..search_end]
+        .rmatch_indices("impl")
+        .find_map(|(index, _)| {
+            let before = source[..index].chars().next_back();
+            let after = source[index + "impl".len()..].chars().next();
+            (before.is_none_or(|c| !c.is_alphanumeric() && c != '_')
+                && after.is_some_and(|c| c.is_whitespace() || c == '<'))
+            .then_some(index)
+        })?;
+    let header_end = source[impl_at..]
+        .find(['{', ';'])
+        .map(|index| impl_at + index)?;
+    let text = source[impl_at..header_end]
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let (trait_name, self_type) = split_impl_header(&text)?;
+    Some(ImplHeader {
+        text,
+        trait_name,
+        self_type,
+        derived: false,
+    })
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct LocationsResponse {
-    pub file_path: String,
-    pub requested_position: PositionRecord,
-    pub found: bool,
-    pub location_count: usize,
-    pub locations: Vec<LocationRecord>,
-    pub summary: String,
+/// The trait (if any) and self type of an impl header such as
+/// `impl<T: Clone> From<T> for Wrapper<T> where T: Debug`.
+fn split_impl_header(header: &str) -> Option<(Option<String>, String)> {
+    let mut rest = header.strip_prefix("impl")?;
+    if rest.starts_with('<') {
+        rest = &rest[top_level_end(rest)?..];
+    }
+    let rest = rest.trim();
+    let rest = top_level_find(rest, " where ").map_or(rest, |index| &rest[..index]);
+    Some(top_level_find(rest, " for ").map_or_else(
+        || (None, rest.trim().to_string()),
+        |index| {
+            (
+                Some(rest[..index].trim().to_string()),
+                rest[index + " for ".len()..].trim().to_string(),
+            )
+        },
+    ))
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct WorkspaceSymbolRecord {
-    pub name: String,
-    pub kind: String,
-    pub container_name: Option<String>,
-    pub location: LocationRecord,
+/// The byte just past the `>` closing the `<` that `text` starts with.
+fn top_level_end(text: &str) -> Option<usize> {
+    let mut depth = 0_usize;
+    for (index, c) in text.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => {
+                depth = depth.checked_sub(1)?;
+                if depth == 0 {
+                    return Some(index + 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct WorkspaceSymbolsResponse {
-    pub query: String,
-    pub symbol_count: usize,
-    pub symbols: Vec<WorkspaceSymbolRecord>,
-    pub summary: String,
+/// The first `needle` in `text` outside angle brackets and parentheses.
+fn top_level_find(text: &str, needle: &str) -> Option<usize> {
+    let mut depth = 0_i32;
+    for (index, c) in text.char_indices() {
+        match c {
+            '<' | '(' | '[' => depth += 1,
+            // `->` is not a closing bracket.
+            '>' if !text[..index].ends_with('-') => depth -= 1,
+            ')' | ']' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 && text[index..].starts_with(needle) {
+            return Some(index);
+        }
+    }
+    None
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
-pub struct ServerStatusResponse {
-    pub server: String,
-    pub server_status: String,
-    pub workspace_root: Option<String>,
-    pub server_version: Option<String>,
-    pub runtime: RuntimeStatus,
-    pub client: ClientIdentity,
-    pub readiness: ReadinessState,
-    pub telemetry: TelemetrySnapshot,
-    pub compiler_accounting: CompilerAccountingSnapshot,
-    pub summary: String,
+/// `std::fmt::Display` and `Wrapper<T>` become `Display` and `Wrapper`.
+fn base_name(path: &str) -> String {
+    let path = path
+        .trim()
+        .trim_start_matches('&')
+        .trim_start_matches("mut ");
+    let path = path.split('<').next().unwrap_or(path);
+    path.rsplit("::").next().unwrap_or(path).trim().to_string()
 }
 
-fn location_record(uri: &lsp_types::Uri, range: &lsp_types::Range) -> LocationRecord {
-    let file_path = uri_to_path(uri);
-    LocationRecord {
-        display: format!(
-            "{}:{}:{}",
-            file_path,
-            range.start.line + 1,
-            range.start.character + 1,
-        ),
-        file_path,
-        uri: uri.to_string(),
-        line: range.start.line + 1,
-        column: range.start.character + 1,
-        end_line: range.end.line + 1,
-        end_column: range.end.character + 1,
-    }
+/// The module path rust-analyzer puts on the first line of a hover, e.g.
+/// `tokio::sync::mutex`.
+fn hover_module_path(markdown: &str) -> Option<&str> {
+    let mut lines = markdown.lines().map(str::trim);
+    lines.find(|line| line.starts_with("```"))?;
+    lines
+        .next()
+        .filter(|line| !line.is_empty() && !line.starts_with("```"))
 }
 
-const fn range_record(range: &lsp_types::Range) -> RangeRecord {
-    RangeRecord {
-        start: PositionRecord {
-            line: range.start.line + 1,
-            character: range.start.character + 1,
-        },
-        end: PositionRecord {
-            line: range.end.line + 1,
-            character: range.end.character + 1,
-        },
-    }
+/// Whether a symbol's container (`Mutex<T>`, `impl Display for Mutex<T>`)
+/// is `parent`.
+fn container_matches(container: Option<&str>, parent: &str) -> bool {
+    container.is_some_and(|container| {
+        let mut ty = container.rsplit(" for ").next().unwrap_or(container).trim();
+        if let Some(rest) = ty.strip_prefix("impl") {
+            // Skip the impl's generic parameters, which may nest.
+            let mut depth = 0_usize;
+            let end = rest
+                .char_indices()
+                .find(|&(_, c)| {
+                    match c {
+                        '<' => depth += 1,
+                        '>' => depth = depth.saturating_sub(1),
+                        _ => {}
+                    }
+                    depth == 0 && c != '>'
+                })
+                .map_or(rest.len(), |(index, _)| index);
+            ty = rest[end..].trim();
+        }
+        let ty = ty.split('<').next().unwrap_or(ty);
+        ty.rsplit("::").next().unwrap_or(ty).trim() == parent
+    })
 }
 
-/// MCP server providing rust-analyzer tools via lspmux.
-#[derive(Clone)]
-pub struct RustAnalyzerTools {
-    lsp: Arc<LspClient>,
-    runtime_status: RuntimeStatus,
-    telemetry: TelemetryState,
-    tool_router: ToolRouter<Self>,
+/// Keywords a `symbol` parameter may start with, and the kinds of symbol
+/// rust-analyzer reports for each.
+const SYMBOL_KEYWORDS: &[(&str, &[lsp_types::SymbolKind])] = &[
+    (
+        "fn",
+        &[
+            lsp_types::SymbolKind::FUNCTION,
+            lsp_types::SymbolKind::METHOD,
+        ],
+    ),
+    ("struct", &[lsp_types::SymbolKind::STRUCT]),
+    ("union", &[lsp_types::SymbolKind::STRUCT]),
+    ("enum", &[lsp_types::SymbolKind::ENUM]),
+    ("trait", &[lsp_types::SymbolKind::INTERFACE]),
+    ("type", &[lsp_types::SymbolKind::TYPE_PARAMETER]),
+    ("const", &[lsp_types::SymbolKind::CONSTANT]),
+    (
+        "static",
+        &[
+            lsp_types::SymbolKind::CONSTANT,
+            lsp_types::SymbolKind::VARIABLE,
+        ],
+    ),
+    (
+        "mod",
+        &[
+            lsp_types::SymbolKind::MODULE,
+            lsp_types::SymbolKind::NAMESPACE,
+        ],
+    ),
+    ("macro", &[lsp_types::SymbolKind::FUNCTION]),
+    ("field", &[lsp_types::SymbolKind::FIELD]),
+    ("variant", &[lsp_types::SymbolKind::ENUM_MEMBER]),
+];
+
+/// A `symbol` parameter: `[KEYWORD] [PARENT::]NAME`.
+#[derive(Debug, PartialEq, Eq)]
+struct SymbolQuery<'a> {
+    /// The kinds the keyword allows; empty for any.
+    kinds: &'static [lsp_types::SymbolKind],
+    /// The type, trait, or module the symbol is in.
+    parent: Option<&'a str>,
+    name: &'a str,
 }
 
-#[tool_router]
-impl RustAnalyzerTools {
-    /// Create a new tools instance wrapping an LSP client.
-    pub fn new(
-        lsp: Arc<LspClient>,
-        runtime_status: RuntimeStatus,
-        telemetry: TelemetryState,
-    ) -> Self {
-        Self {
-            lsp,
-            runtime_status,
-            telemetry,
-            tool_router: Self::tool_router(),
+impl<'a> SymbolQuery<'a> {
+    fn parse(symbol: &'a str) -> Result<Self, McpError> {
+        let symbol = symbol.trim();
+        let (kinds, path) = symbol
+            .split_once(char::is_whitespace)
+            .and_then(|(keyword, rest)| {
+                SYMBOL_KEYWORDS
+                    .iter()
+                    .find(|(known, _)| *known == keyword)
+                    .map(|(_, kinds)| (*kinds, rest.trim()))
+            })
+            .unwrap_or((&[], symbol));
+        let path = path.trim_end_matches("()");
+        let (parent, name) = match path.rsplit_once("::") {
+            Some((parents, name)) => (Some(parents.rsplit("::").next().unwrap_or(parents)), name),
+            None => (None, path),
+        };
+        let keyword = SYMBOL_KEYWORDS.iter().any(|(known, _)| *known == name);
+        if keyword || !edits::is_identifier(name) || parent.is_some_and(str::is_empty) {
+            return Err(McpError::invalid_params(
+                format!(
+                    "cannot read symbol {symbol:?}; name it like `name`, `Type::name`, or \
+                     `fn name`"
+                ),
+                None,
+            ));
         }
+        Ok(Self {
+            kinds,
+            parent,
+            name,
+        })
     }
 
-    /// Get diagnostics (errors and warnings) for a Rust file.
-    #[tool(
-        name = "rust_diagnostics",
-        description = "Get Rust compiler errors and warnings for a file. Returns structured diagnostics with one-based locations."
-    )]
-    async fn diagnostics(
-        &self,
-        params: Parameters<FileParam>,
-    ) -> Result<Json<DiagnosticsResponse>, McpError> {
-        let file = &params.0.file_path;
-        validate_file_path(file)?;
+    /// Whether `symbol` is the one named: the parent may be its container
+    /// (an impl's self type included) or the module of its file.
+    fn matches(&self, symbol: &IndexedSymbol) -> bool {
+        symbol.name == self.name
+            && (self.kinds.is_empty() || self.kinds.contains(&symbol.kind))
+            && self.parent.is_none_or(|parent| {
+                container_matches(symbol.container.as_deref(), parent)
+                    || file_module(&symbol.file_path) == Some(parent)
+            })
+    }
+}
 
-        self.lsp
-            .ensure_file_open(file)
-            .await
-            .map_err(|e| internal_error(format!("failed to synchronize file with lspmux: {e}")))?;
+/// The name of the module `file_path` defines: its stem, or its directory's
+/// name for `mod.rs`.
+fn file_module(file_path: &str) -> Option<&str> {
+    let path = Path::new(file_path);
+    let stem = path.file_stem()?.to_str()?;
+    if stem == "mod" {
+        path.parent()?.file_name()?.to_str()
+    } else {
+        Some(stem)
+    }
+}
 
-        let uri = file_uri(file)
-            .map_err(|e| McpError::invalid_params(format!("invalid file path: {e}"), None))?;
+/// Whether an item defined in crate `defined_in` is reachable as
+/// `krate::...`; `std` re-exports `core` and `alloc`.
+fn same_crate(krate: &str, defined_in: &str) -> bool {
+    let krate = krate.replace('-', "_");
+    krate == defined_in
+        || (STD_CRATES.contains(&krate.as_str()) && STD_CRATES.contains(&defined_in))
+}
+
+/// The `(package, version)` of the crates.io package `krate` whose source
+/// holds `file_path`, e.g. `("serde-json", "1.0.1")` from
+/// `.../registry/src/index.crates.io-.../serde-json-1.0.1/src/lib.rs`.
+fn registry_package<'a>(file_path: &'a str, krate: &str) -> Option<(&'a str, &'a str)> {
+    file_path.split('/').rev().find_map(|component| {
+        // The version starts at the first `-` before a digit that leaves
+        // the package name; names can hold such dashes too (`sha-1-0.10.0`).
+        component
+            .match_indices('-')
+            .map(|(index, _)| (&component[..index], &component[index + 1..]))
+            .filter(|(_, version)| version.starts_with(|c: char| c.is_ascii_digit()))
+            .find(|(package, _)| package.replace('-', "_") == krate)
+    })
+}
+
+/// The rustdoc page of the item at `segments` (`[crate, modules.., name]`)
+/// of symbol kind `kind`, or for a member of a type of kind `member_of`, the
+/// type's page anchored at the member.
+fn docs_url(
+    segments: &[&str],
+    kind: &str,
+    member_of: Option<&str>,
+    file_path: &str,
+) -> Option<String> {
+    fn page(kind: &str) -> Option<&'static str> {
+        Some(match kind {
+            "struct" => "struct",
+            "enum" => "enum",
+            "interface" => "trait",
+            "function" => "fn",
+            "constant" => "constant",
+            "type_parameter" => "type",
+            _ => return None,
+        })
+    }
+
+    let (krate, rest) = segments.split_first()?;
+    let krate = krate.replace('-', "_");
+    let base = if STD_CRATES.contains(&krate.as_str()) {
+        format!("https://doc.rust-lang.org/{krate}")
+    } else {
+        let (package, version) = registry_package(file_path, &krate)?;
+        format!("https://docs.rs/{package}/{version}/{krate}")
+    };
+    let dirs = |modules: &[&str]| {
+        modules
+            .iter()
+            .flat_map(|module| ["/", module])
+            .collect::<String>()
+    };
+    if let Some(member_of) = member_of {
+        let (name, rest) = rest.split_last()?;
+        let (parent, modules) = rest.split_last()?;
+        let anchor = match kind {
+            "field" => "structfield",
+            "enum_member" => "variant",
+            "constant" => "associatedconstant",
+            "type_parameter" => "associatedtype",
+            _ => "method",
+        };
+        return Some(format!(
+            "{base}{}/{}.{parent}.html#{anchor}.{name}",
+            dirs(modules),
+            page(member_of)?
+        ));
+    }
+    match rest.split_last() {
+        None => Some(format!("{base}/index.html")),
+        Some(_) if kind == "module" => Some(format!("{base}{}/index.html", dirs(rest))),
+        Some((name, modules)) => Some(format!(
+            "{base}{}/{}.{name}.html",
+            dirs(modules),
+            page(kind)?
+        )),
+    }
+}
+
+/// Strip fences, horizontal rules, inline code ticks, and link targets,
+/// leaving at most one blank line between paragraphs.
+fn markdown_to_plaintext(markdown: &str) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    for line in markdown.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            continue;
+        }
+        if (trimmed.is_empty() || trimmed == "---") && out.ends_with("\n\n") {
+            continue;
+        }
+        if trimmed != "---" {
+            out.push_str(&strip_links(line).replace('`', ""));
+        }
+        out.push('\n');
+    }
+    out.trim().to_string()
+}
+
+/// `[text](target)` becomes `text`.
+fn strip_links(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(open) = rest.find('[') {
+        let Some(close) = rest[open..].find("](").map(|i| open + i) else {
+            break;
+        };
+        let Some(end) = rest[close..].find(')').map(|i| close + i) else {
+            break;
+        };
+        out.push_str(&rest[..open]);
+        out.push_str(&rest[open + 1..close]);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Cut `text` to at most `max_chars` characters at the last paragraph
+/// break, line break, or space in the second half of the budget, keeping
+/// markdown code fences balanced.
+fn truncate_hover(text: &str, max_chars: usize, format: HoverFormat) -> String {
+    let budget = text
+        .char_indices()
+        .nth(max_chars)
+        .map_or(text.len(), |(index, _)| index);
+    let head = &text[..budget];
+    let cut = ["\n\n", "\n", " "]
+        .iter()
+        .filter_map(|separator| head.rfind(separator))
+        .find(|&index| index >= budget / 2)
+        .unwrap_or(budget);
+    let mut out = text[..cut].trim_end().to_string();
+    if format == HoverFormat::Markdown
+        && out
+            .lines()
+            .filter(|line| line.trim_start().starts_with("```"))
+            .count()
+            % 2
+            == 1
+    {
+        out.push_str("\n```");
+    }
+    let remaining = text[cut..].trim().chars().count();
+    let _ = write!(out, "\n\n… truncated ({remaining} more characters)");
+    out
+}
+
+/// Tool parameter: a file path.
+#[derive(Deserialize, JsonSchema, Serialize)]
+pub struct FileParam {
+    /// Absolute path to the Rust source file.
+    pub file_path: String,
+}
+
+/// Tool parameters: a directory or glob and diagnostic filters.
+#[derive(Deserialize, JsonSchema)]
+pub struct DiagnosticsDirParam {
+    /// Absolute directory (searched recursively, skipping `target/` and
+    /// hidden directories) or glob such as `/repo/crates/*/src/**/*.rs`.
+    pub path: String,
+    #[serde(flatten)]
+    pub filter: DiagnosticFilter,
+    /// Maximum number of files to check (default 500).
+    pub max_files: Option<usize>,
+}
+
+/// Tool parameters: where to look for changed files, and diagnostic filters.
+#[derive(Deserialize, JsonSchema)]
+pub struct DiagnosticsChangedParam {
+    /// Absolute directory inside a git working tree; only changed files
+    /// under it are checked (default: the workspace root).
+    pub path: Option<String>,
+    #[serde(flatten)]
+    pub filter: DiagnosticFilter,
+    /// Maximum number of files to check (default 500).
+    pub max_files: Option<usize>,
+    /// Report only the diagnostics introduced since `baseline`, leaving out
+    /// those already there (default false).
+    #[serde(default)]
+    pub new_only: bool,
+    /// What `new_only` compares with: a git revision, keeping diagnostics
+    /// on the lines that differ from it (default `HEAD`), or
+    /// `snapshot:<name>` for a `rust_diagnostics_snapshot`. Implies `new_only`.
+    pub baseline: Option<String>,
+}
+
+/// Tool parameters: file path + position (line, character), or a symbol.
+#[derive(Deserialize, JsonSchema, Serialize)]
+pub struct PositionParam {
+    /// Absolute path to the Rust source file. May be omitted with `symbol`,
+    /// which is then looked up across the workspace.
+    pub file_path: Option<String>,
+    /// Zero-based line number.
+    pub line: Option<u32>,
+    /// Zero-based character offset.
+    pub character: Option<u32>,
+    /// Instead of `line` and `character`: the symbol to point at, by name,
+    /// optionally qualified by its type, trait, or module and prefixed with
+    /// a keyword, e.g. `LspClient::request` or `fn ensure_file_open`.
+    pub symbol: Option<String>,
+    /// Instead of `line` and `character`: zero-based byte offset into the
+    /// file.
+    pub offset: Option<usize>,
+    /// Whether `line` and `character` count from 0 (the default) or from 1,
+    /// as output locations do.
+    pub index_base: Option<u8>,
+}
+
+/// A position in a file, as resolved from a [`PositionParam`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FilePosition {
+    pub file_path: String,
+    pub line: u32,
+    pub character: u32,
+}
+
+/// Diagnostic severities, least to most severe.
+#[derive(
+    Clone, Copy, Debug, Deserialize, JsonSchema, PartialEq, Eq, PartialOrd, Ord, Serialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum SeverityLevel {
+    Hint,
+    Info,
+    Warning,
+    Error,
+}
+
+impl SeverityLevel {
+    /// The level named by [`diagnostic_severity_name`], if any.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "hint" => Some(Self::Hint),
+            "info" => Some(Self::Info),
+            "warning" => Some(Self::Warning),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Hint => "hint",
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        }
+    }
+}
+
+/// Tool parameters: which diagnostics to keep.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct DiagnosticFilter {
+    /// Drop diagnostics less severe than this (`error`, `warning`, `info`,
+    /// or `hint`). `error` returns errors only.
+    pub min_severity: Option<SeverityLevel>,
+    /// Keep only diagnostics with one of these codes, e.g. `["E0308", "E0433"]`.
+    #[serde(default)]
+    pub codes: Vec<String>,
+}
+
+impl DiagnosticFilter {
+    fn matches(&self, diagnostic: &DiagnosticRecord) -> bool {
+        let severe_enough = self.min_severity.is_none_or(|min| {
+            SeverityLevel::from_name(&diagnostic.severity).is_some_and(|level| level >= min)
+        });
+        let code_listed = self.codes.is_empty()
+            || diagnostic
+                .code
+                .as_ref()
+                .is_some_and(|code| self.codes.contains(code));
+        severe_enough && code_listed
+    }
+
+    /// A summary suffix naming the active filters, empty when there are none.
+    fn note(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(min) = self.min_severity {
+            parts.push(format!("severity >= {}", min.name()));
+        }
+        if !self.codes.is_empty() {
+            parts.push(format!("code in {}", self.codes.join("/")));
+        }
+        if parts.is_empty() {
+            String::new()
+        } else {
+            format!(" matching {}", parts.join(", "))
+        }
+    }
+}
+
+/// What `rust_diagnostics_changed` compares with to report only the
+/// diagnostics a change introduced.
+enum Baseline {
+    /// Keep diagnostics on lines that differ from a git revision.
+    Revision(String, git::TouchedLines),
+    /// Keep diagnostics a snapshot does not have.
+    Snapshot(String, Snapshot),
+}
+
+impl Baseline {
+    /// Of `diagnostics`, those of `file` that are new since the baseline.
+    fn introduced(&self, file: &str, diagnostics: Vec<DiagnosticRecord>) -> Vec<DiagnosticRecord> {
+        match self {
+            Self::Revision(_, touched) => diagnostics
+                .into_iter()
+                .filter(|diagnostic| {
+                    let location = &diagnostic.location;
+                    touched.touches(Path::new(file), location.line, location.end_line)
+                })
+                .collect(),
+            Self::Snapshot(_, snapshot) => {
+                snapshots::diff(&snapshot.diagnostics, &diagnostics).introduced
+            }
+        }
+    }
+
+    /// A summary sentence saying which diagnostics were kept.
+    fn note(&self) -> String {
+        match self {
+            Self::Revision(revision, _) => {
+                format!(" Only diagnostics on lines changed since {revision} are shown.")
+            }
+            Self::Snapshot(name, _) => {
+                format!(" Only diagnostics introduced since snapshot `{name}` are shown.")
+            }
+        }
+    }
+}
+
+/// Tool parameters: a file path and diagnostic filters, paginated.
+#[derive(Deserialize, JsonSchema, Serialize)]
+pub struct DiagnosticsParam {
+    #[serde(flatten)]
+    pub file: FileParam,
+    #[serde(flatten)]
+    pub filter: DiagnosticFilter,
+    /// Attach `rustc --explain` for each error code (e.g. `E0308`) among
+    /// the returned diagnostics.
+    #[serde(default)]
+    pub explain: bool,
+    #[serde(flatten)]
+    pub page: PageParams,
+}
+
+/// Tool parameter: how much source to inline with each returned location.
+#[derive(Clone, Debug, Default, Deserialize, JsonSchema, Serialize)]
+pub struct SnippetParam {
+    /// Inline the target line plus this many lines above and below it for
+    /// each location (at most 20). Omit for locations only.
+    pub context_lines: Option<u32>,
+}
+
+/// Tool parameters: file path + position, with optional source snippets.
+#[derive(Deserialize, JsonSchema)]
+pub struct DefinitionParam {
+    #[serde(flatten)]
+    pub position: PositionParam,
+    #[serde(flatten)]
+    pub snippet: SnippetParam,
+}
+
+/// Tool parameters: file path + position and reference options, paginated.
+#[derive(Deserialize, JsonSchema, Serialize)]
+pub struct ReferencesParam {
+    #[serde(flatten)]
+    pub position: PositionParam,
+    /// Count the symbol's own declaration as a reference (default true).
+    pub include_declaration: Option<bool>,
+    /// Return references grouped per file, with a count for each.
+    #[serde(default)]
+    pub group_by_file: bool,
+    /// Keep at most this many references in total; the rest are dropped
+    /// rather than paginated.
+    pub max_results: Option<usize>,
+    #[serde(flatten)]
+    pub snippet: SnippetParam,
+    #[serde(flatten)]
+    pub page: PageParams,
+}
+
+/// Tool parameters: a symbol, or with only `file_path` a whole file, to
+/// check before deleting it.
+#[derive(Deserialize, JsonSchema)]
+pub struct SafeDeleteParam {
+    #[serde(flatten)]
+    pub position: PositionParam,
+    #[serde(flatten)]
+    pub snippet: SnippetParam,
+}
+
+/// Tool parameters: workspace symbol search query, paginated.
+#[derive(Deserialize, JsonSchema, Serialize)]
+pub struct WorkspaceSymbolParam {
+    /// Substring to search for in symbol names across the workspace.
+    pub query: String,
+    #[serde(flatten)]
+    pub page: PageParams,
+}
+
+/// Tool parameters: the trait or type whose implementations to list.
+#[derive(Deserialize, JsonSchema)]
+pub struct TraitImplsParam {
+    /// The trait or type by name, e.g. `Display` or `Config`.
+    pub name: Option<String>,
+    /// Instead of `name`: absolute path of a file mentioning it.
+    pub file_path: Option<String>,
+    /// With `file_path`: zero-based line of the name.
+    pub line: Option<u32>,
+    /// With `file_path`: zero-based character offset of the name.
+    pub character: Option<u32>,
+}
+
+/// Tool parameters: indexed symbol search, paginated.
+#[derive(Deserialize, JsonSchema, Serialize)]
+pub struct SymbolSearchParam {
+    /// Fuzzy query: exact names rank first, then prefixes, substrings, and
+    /// names holding the query's characters in order (`lspcl` finds
+    /// `LspClient`). Omit to list every symbol that passes the filters.
+    pub query: Option<String>,
+    /// Only these kinds, e.g. `["struct", "enum"]`; names as
+    /// `rust_workspace_symbol` reports them.
+    pub kinds: Option<Vec<String>>,
+    /// Only symbols in this crate (workspace package), e.g. `my-crate`.
+    #[serde(rename = "crate")]
+    pub crate_name: Option<String>,
+    #[serde(flatten)]
+    pub page: PageParams,
+}
+
+/// Tool parameters: a symbol to locate in a file.
+#[derive(Deserialize, JsonSchema)]
+pub struct LocateParam {
+    /// Absolute path to the Rust source file.
+    pub file_path: String,
+    /// The symbol's name, optionally qualified by its type, trait, or module
+    /// (`Config::load`) or prefixed with a keyword (`fn load`).
+    pub name: String,
+    /// Only symbols of this kind, named as `rust_workspace_symbol` reports
+    /// kinds, e.g. `method` or `struct`.
+    pub kind: Option<String>,
+    /// Only the nth match (one-based) in source order.
+    pub nth: Option<usize>,
+}
+
+/// Tool parameters: an item path and hover output controls.
+#[derive(Deserialize, JsonSchema)]
+pub struct DocParam {
+    /// Path of the item, e.g. `tokio::sync::Mutex::lock` or
+    /// `serde_json::from_str`.
+    pub path: String,
+    #[serde(flatten)]
+    pub options: HoverOptions,
+}
+
+/// Tool parameters: `rust_warmup` options.
+#[derive(Deserialize, JsonSchema)]
+pub struct WarmupParam {
+    /// Also wait until rust-analyzer has finished indexing the workspace.
+    #[serde(default)]
+    pub wait_until_ready: bool,
+}
+
+/// Tool parameters: the project file to load, and whether to regenerate it.
+#[derive(Deserialize, JsonSchema)]
+pub struct LinkProjectParam {
+    /// Absolute path to a `rust-project.json` (or `Cargo.toml`) to load.
+    /// Defaults to the project config's `project_file`, else
+    /// `rust-project.json` in the workspace root.
+    pub project_file: Option<String>,
+    /// Run the project config's `project_generator` first.
+    #[serde(default)]
+    pub regenerate: bool,
+    /// Timeout for the generator in seconds (default 600).
+    pub timeout_secs: Option<u64>,
+}
+
+/// Tool parameters: the diagnostic to fix, and how.
+#[derive(Deserialize, JsonSchema)]
+pub struct QuickfixParam {
+    /// Absolute path to the Rust source file.
+    pub file_path: String,
+    /// The diagnostic's `index`, as reported by `rust_diagnostics`.
+    pub index: Option<usize>,
+    /// Instead of `index`: the diagnostic's code, e.g. `E0433` or `unused_imports`.
+    /// The first diagnostic with this code is fixed.
+    pub code: Option<String>,
+    /// With `code`: the diagnostic's one-based line, to pick one of several.
+    pub line: Option<u32>,
+    /// The title of the fix to apply, when several are offered and none is
+    /// preferred.
+    pub title: Option<String>,
+    /// Return the diff without writing any file.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Tool parameters: the command to run, as a code action or code lens
+/// gives it.
+#[derive(Deserialize, JsonSchema)]
+pub struct ExecuteCommandParam {
+    /// The command's name, e.g. `rust-analyzer.runSingle`.
+    pub command: String,
+    /// The command's arguments, passed on unchanged.
+    #[serde(default)]
+    pub arguments: Vec<serde_json::Value>,
+    /// Return the diff of the edits the command asks for without writing
+    /// any file.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// For `rust-analyzer.runSingle`: seconds to wait for cargo before
+    /// stopping it (default 600).
+    pub timeout_secs: Option<u64>,
+}
+
+/// Tool parameters: the file whose imports to organize, and how.
+#[derive(Deserialize, JsonSchema)]
+pub struct OrganizeImportsParam {
+    /// Absolute path to the Rust source file.
+    pub file_path: String,
+    /// Also merge imports that share a prefix into one `use` tree.
+    #[serde(default)]
+    pub merge: bool,
+    /// Return the diff without writing the file.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Tool parameters: what to snapshot the diagnostics of, and under which name.
+#[derive(Deserialize, JsonSchema)]
+pub struct DiagnosticsSnapshotParam {
+    /// Name to store the snapshot under (default `default`). Taking a
+    /// snapshot with the same name replaces it.
+    pub name: Option<String>,
+    /// Absolute directory (searched recursively, skipping `target/` and
+    /// hidden directories) or glob; defaults to the workspace root.
+    pub path: Option<String>,
+    #[serde(flatten)]
+    pub filter: DiagnosticFilter,
+    /// Maximum number of files to check (default 500).
+    pub max_files: Option<usize>,
+}
+
+/// Tool parameters: the snapshot to compare the current diagnostics with.
+#[derive(Deserialize, JsonSchema)]
+pub struct DiagnosticsDiffParam {
+    /// The snapshot's name (default `default`).
+    pub name: Option<String>,
+    /// Replace the snapshot with the current diagnostics, so the next diff
+    /// shows only changes made after this one.
+    #[serde(default)]
+    pub update: bool,
+}
+
+/// Tool parameters: the lspmux instance to restart.
+#[derive(Deserialize, JsonSchema)]
+pub struct RestartInstanceParam {
+    /// Process id of the instance, from `lspmux_status` (default: the one
+    /// serving this workspace).
+    pub pid: Option<u32>,
+}
+
+/// Tool parameters: how long an unused lspmux instance must have been idle.
+#[derive(Deserialize, JsonSchema)]
+pub struct ReapIdleParam {
+    /// Seconds since a client last used the instance (default 300).
+    pub idle_secs: Option<u64>,
+}
+
+/// Tool parameters: which workspace to check, and how long to wait.
+#[derive(Deserialize, JsonSchema)]
+pub struct RunFlycheckParam {
+    /// Absolute path to a file; only the workspace containing it is checked
+    /// (default: every workspace).
+    pub file_path: Option<String>,
+    /// Seconds to wait for the check to finish (default 60, at most 600);
+    /// 0 returns as soon as it is started.
+    pub timeout_secs: Option<u64>,
+}
+
+/// Tool parameters: a file to save, how long to wait for the check that
+/// follows, and diagnostic filters.
+#[derive(Deserialize, JsonSchema)]
+pub struct WaitForDiagnosticsParam {
+    #[serde(flatten)]
+    pub file: FileParam,
+    #[serde(flatten)]
+    pub filter: DiagnosticFilter,
+    /// Seconds to wait for the check to finish (default 60, at most 600).
+    pub timeout_secs: Option<u64>,
+}
+
+/// Tool parameters: a compiler error code.
+#[derive(Deserialize, JsonSchema)]
+pub struct ExplainParam {
+    /// The error code, e.g. `E0308` (`e0308` and `308` work too).
+    pub code: String,
+}
+
+/// Tool parameters: where to look for unused code, and whether to remove it.
+#[derive(Deserialize, JsonSchema)]
+pub struct UnusedReportParam {
+    /// Absolute directory (searched recursively, skipping `target/` and
+    /// hidden directories) or glob; defaults to the workspace root.
+    pub path: Option<String>,
+    /// Maximum number of files to check (default 500).
+    pub max_files: Option<usize>,
+    /// Apply rust-analyzer's preferred quick fix for each finding that has
+    /// one.
+    #[serde(default)]
+    pub fix: bool,
+    /// With `fix`: return the diff without writing any file.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Tool parameters: the symbol to rename and its new name.
+#[derive(Deserialize, JsonSchema)]
+pub struct RenameParam {
+    #[serde(flatten)]
+    pub position: PositionParam,
+    /// The symbol's new name.
+    pub new_name: String,
+    /// Write the rename to disk. By default the edits are only listed, as a diff.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Tool parameters: a file or directory to move, and where to.
+#[derive(Deserialize, JsonSchema)]
+pub struct MoveFileParam {
+    /// Absolute path of the Rust source file or directory to move.
+    pub file_path: String,
+    /// Its absolute new path, which must not exist yet.
+    pub new_path: String,
+    /// Only return the diff; write nothing.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Tool parameters: a file path and a range within it.
+#[derive(Deserialize, JsonSchema)]
+pub struct RangeParam {
+    /// Absolute path to the Rust source file.
+    pub file_path: String,
+    /// Zero-based line where the range starts.
+    pub start_line: u32,
+    /// Zero-based character offset where the range starts.
+    pub start_character: u32,
+    /// Zero-based line where the range ends.
+    pub end_line: u32,
+    /// Zero-based character offset where the range ends (exclusive).
+    pub end_character: u32,
+    /// Whether the lines and characters count from 0 (the default) or from
+    /// 1, as output locations do.
+    pub index_base: Option<u8>,
+}
+
+impl RangeParam {
+    fn lsp_range(&self) -> Result<lsp_types::Range, McpError> {
+        let base = self.index_base;
+        Ok(lsp_types::Range {
+            start: lsp_types::Position {
+                line: zero_based(self.start_line, base, "start_line")?,
+                character: zero_based(self.start_character, base, "start_character")?,
+            },
+            end: lsp_types::Position {
+                line: zero_based(self.end_line, base, "end_line")?,
+                character: zero_based(self.end_character, base, "end_character")?,
+            },
+        })
+    }
+}
+
+/// `value`, counted from `index_base` (0 or 1), counted from 0.
+fn zero_based(value: u32, index_base: Option<u8>, what: &str) -> Result<u32, McpError> {
+    match index_base.unwrap_or(0) {
+        0 => Ok(value),
+        1 => value.checked_sub(1).ok_or_else(|| {
+            McpError::invalid_params(
+                format!("{what} counts from 1 with index_base 1, but is 0"),
+                None,
+            )
+        }),
+        other => Err(McpError::invalid_params(
+            format!("index_base must be 0 or 1, got {other}"),
+            None,
+        )),
+    }
+}
+
+/// Tool parameters: the code to extract and the new function's name.
+#[derive(Deserialize, JsonSchema)]
+pub struct ExtractFunctionParam {
+    #[serde(flatten)]
+    pub range: RangeParam,
+    /// Name of the new function.
+    pub name: String,
+    /// Return the diff without writing the file.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Tool parameters: what to inline.
+#[derive(Deserialize, JsonSchema)]
+pub struct InlineParam {
+    #[serde(flatten)]
+    pub position: PositionParam,
+    /// The title of the inlining to apply, when several are offered at the
+    /// position (e.g. `Inline call` or ``Inline `f` into all callers``).
+    pub title: Option<String>,
+    /// Return the diff without writing any file.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Tool parameters: where to generate code, and which generator to run.
+#[derive(Deserialize, JsonSchema)]
+pub struct GenerateParam {
+    #[serde(flatten)]
+    pub position: PositionParam,
+    /// The generator to apply, as listed by a call without it. Omit to only
+    /// list the generators available at the position.
+    pub title: Option<String>,
+    /// Return the diff without writing any file.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Tool parameters: where to complete, and which completion to look at.
+#[derive(Deserialize, JsonSchema)]
+pub struct CompletionParam {
+    #[serde(flatten)]
+    pub position: PositionParam,
+    /// Only list completions whose label starts with this, ignoring case.
+    pub prefix: Option<String>,
+    /// Maximum number of completions to list (default 50).
+    pub max_items: Option<usize>,
+    /// The label of the completion to resolve, to see its documentation and
+    /// every edit choosing it makes.
+    pub label: Option<String>,
+    /// Instead of `label`: the completion's `index`, as listed, to pick one
+    /// of several with the same label.
+    pub index: Option<usize>,
+    /// With `label` or `index`: write the completion's edits to the file.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Tool parameters: the unresolved name to import, and which path to import
+/// it from.
+#[derive(Deserialize, JsonSchema)]
+pub struct AutoImportParam {
+    /// Absolute path to the Rust source file.
+    pub file_path: String,
+    /// The unresolved name, e.g. `HashMap`. Its first "cannot find" or
+    /// "failed to resolve" diagnostic locates it.
+    pub name: Option<String>,
+    /// Instead of `name`: zero-based line of the unresolved name.
+    pub line: Option<u32>,
+    /// With `line`: zero-based character offset (UTF-16 code units).
+    pub character: Option<u32>,
+    /// The path to import, e.g. `std::collections::HashMap`, when several
+    /// candidates match.
+    pub path: Option<String>,
+    /// Return the diff without writing any file.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Tool parameters: what `cargo check` builds.
+#[derive(Deserialize, JsonSchema)]
+pub struct CargoCheckParam {
+    /// Check only this package (`cargo check -p`); the whole workspace by
+    /// default.
+    pub package: Option<String>,
+    /// Also check tests, examples, and benches (default true).
+    pub all_targets: Option<bool>,
+    /// Leave out warnings.
+    #[serde(default)]
+    pub errors_only: bool,
+    /// Maximum number of diagnostics to return, errors first (default 200).
+    pub max_diagnostics: Option<usize>,
+    /// Seconds to wait for cargo before stopping it (default 600).
+    pub timeout_secs: Option<u64>,
+}
+
+/// A cargo runnable, as rust-analyzer's `experimental/runnables` returns it.
+#[derive(Deserialize, JsonSchema)]
+pub struct RunnableParam {
+    /// Arguments after `cargo`, starting with `test`.
+    #[serde(alias = "cargoArgs")]
+    pub cargo_args: Vec<String>,
+    /// Arguments after `--`, passed to the test binary.
+    #[serde(default, alias = "executableArgs")]
+    pub executable_args: Vec<String>,
+    /// The directory to run cargo in; the workspace root by default.
+    pub cwd: Option<String>,
+}
+
+/// Tool parameters: which tests `cargo test` runs.
+#[derive(Deserialize, JsonSchema)]
+pub struct CargoTestParam {
+    /// A runnable to run as given.
+    pub runnable: Option<RunnableParam>,
+    /// Instead of `runnable`: absolute path to a Rust source file; with
+    /// `line`, runs the test or test module around that line.
+    pub file_path: Option<String>,
+    /// With `file_path`: zero-based line inside a test or test module.
+    pub line: Option<u32>,
+    /// Instead of a runnable: run the tests whose path contains this, e.g.
+    /// `parser::tests`. All tests by default.
+    pub filter: Option<String>,
+    /// With `filter`: test only this package (`cargo test -p`).
+    pub package: Option<String>,
+    /// Maximum number of tests to return, failures first (default 200).
+    pub max_tests: Option<usize>,
+    /// Seconds to wait for cargo before stopping it (default 600).
+    pub timeout_secs: Option<u64>,
+}
+
+/// Tool parameters: what to report from `cargo metadata`.
+#[derive(Deserialize, JsonSchema)]
+pub struct CargoMetadataParam {
+    /// Report only this package, which may be a dependency; every workspace
+    /// member by default.
+    pub package: Option<String>,
+    /// Instead: absolute path of a file, to report the package and targets
+    /// that own it.
+    pub file_path: Option<String>,
+    /// Instead: a crate name, e.g. `serde`, to report which workspace
+    /// packages depend on it and the versions in the lockfile.
+    pub dependency: Option<String>,
+}
+
+/// Tool parameters: what `cargo expand` expands.
+#[derive(Deserialize, JsonSchema)]
+pub struct CargoExpandParam {
+    /// Absolute path of a Rust file; expands the module it defines, in the
+    /// package and target that own it.
+    pub file_path: Option<String>,
+    /// Instead of `file_path`: the package to expand (`cargo expand -p`).
+    pub package: Option<String>,
+    /// With `package`: the target, such as `bin:server`, `test:it`, or
+    /// `example:demo`; the library by default.
+    pub target: Option<String>,
+    /// With `package`: the module or item to expand, e.g. `parser::ast`;
+    /// the whole crate by default.
+    pub item: Option<String>,
+    /// Maximum characters of expanded code to return (default 50000).
+    pub max_chars: Option<usize>,
+    /// Seconds to wait for cargo before stopping it (default 600).
+    pub timeout_secs: Option<u64>,
+}
+
+/// Tool parameters: which part of the dependency graph to show.
+#[derive(Deserialize, JsonSchema)]
+pub struct CargoTreeParam {
+    /// The package to start from; every workspace member by default.
+    pub package: Option<String>,
+    /// Instead: show what depends on this crate (every version of it), up
+    /// to the workspace members, like `cargo tree -i`.
+    pub invert: Option<String>,
+    /// Instead: list the crates present at more than one version, with what
+    /// pulls each version in.
+    #[serde(default)]
+    pub duplicates: bool,
+    /// Levels of dependencies to show below the start; all by default.
+    pub depth: Option<usize>,
+    /// Maximum number of tree lines to return (default 500).
+    pub max_entries: Option<usize>,
+}
+
+/// Empty parameter struct for tools that take no arguments.
+#[derive(Deserialize, JsonSchema)]
+pub struct NoParams {}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LocationRecord {
+    pub file_path: String,
+    pub uri: String,
+    pub line: u32,
+    pub column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+    pub display: String,
+    /// Source around the location, when `context_lines` was requested.
+    pub snippet: Option<SourceSnippet>,
+}
+
+/// Lines of source surrounding a location.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct SourceSnippet {
+    /// One-based line number of `lines[0]`.
+    pub start_line: u32,
+    pub lines: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct PositionRecord {
+    pub line: u32,
+    pub character: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct RangeRecord {
+    pub start: PositionRecord,
+    pub end: PositionRecord,
+}
+
+/// A secondary span attached to a diagnostic, such as rustc's
+/// "expected due to this" note.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct RelatedRecord {
+    pub message: String,
+    pub location: LocationRecord,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DiagnosticRecord {
+    /// Position among all of the file's diagnostics, before filtering; pass
+    /// it to `rust_apply_quickfix`.
+    pub index: usize,
+    pub severity: String,
+    pub message: String,
+    /// Compiler or lint code, e.g. `E0308` or `clippy::needless_return`.
+    pub code: Option<String>,
+    /// Who reported it: `rustc`, `clippy`, or `rust-analyzer`.
+    pub source: Option<String>,
+    pub location: LocationRecord,
+    pub related: Vec<RelatedRecord>,
+    /// `unnecessary` (unused code) and/or `deprecated`.
+    pub tags: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DiagnosticsResponse {
+    pub file_path: String,
+    pub diagnostic_count: usize,
+    pub total_count: usize,
+    pub diagnostics: Vec<DiagnosticRecord>,
+    pub next_cursor: Option<String>,
+    /// With `explain`: what each error code among `diagnostics` means.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub explanations: Vec<ErrorExplanation>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DiagnosticsSnapshotResponse {
+    pub name: String,
+    pub path: String,
+    pub files_checked: usize,
+    /// Whether more files matched than `max_files` allowed checking.
+    pub truncated: bool,
+    pub error_count: usize,
+    pub warning_count: usize,
+    pub diagnostic_count: usize,
+    pub failures: Vec<FileFailure>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DiagnosticsDiffResponse {
+    pub name: String,
+    pub path: String,
+    /// Seconds since the snapshot was taken.
+    pub snapshot_age_secs: u64,
+    pub files_checked: usize,
+    pub errors_before: usize,
+    pub errors_after: usize,
+    pub warnings_before: usize,
+    pub warnings_after: usize,
+    /// Diagnostics gone since the snapshot, at their old locations.
+    pub fixed: Vec<DiagnosticRecord>,
+    /// Diagnostics that appeared since the snapshot.
+    pub introduced: Vec<DiagnosticRecord>,
+    pub unchanged_count: usize,
+    pub failures: Vec<FileFailure>,
+    /// Whether the snapshot now holds the current diagnostics.
+    pub updated: bool,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct WaitForDiagnosticsResponse {
+    pub file_path: String,
+    /// `checked` (a check ran to completion), `published` (the server pushed
+    /// new diagnostics without a check), `no_check` (nothing happened, so
+    /// check-on-save is likely off), or `timed_out`.
+    pub outcome: String,
+    pub waited_ms: u64,
+    pub diagnostic_count: usize,
+    pub diagnostics: Vec<DiagnosticRecord>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct FlycheckResponse {
+    /// `run`, `cancel`, or `clear`.
+    pub action: String,
+    /// The file whose workspace was checked; `None` for every workspace.
+    pub file_path: Option<String>,
+    /// For a run that was waited for: `checked`, `published`, `no_check`,
+    /// or `timed_out`, as for `rust_wait_for_diagnostics`.
+    pub outcome: Option<String>,
+    pub waited_ms: u64,
+    pub summary: String,
+}
+
+/// rustc's explanation of an error code.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct ErrorExplanation {
+    pub code: String,
+    /// The text of `rustc --explain`, in markdown.
+    pub explanation: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct ExplainResponse {
+    pub code: String,
+    /// Whether rustc has an explanation for the code.
+    pub found: bool,
+    pub explanation: Option<String>,
+    /// Whether the explanation was fetched by an earlier call.
+    pub cached: bool,
+    pub summary: String,
+}
+
+/// Diagnostics for one file of a `rust_diagnostics_dir` report.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct FileDiagnostics {
+    pub file_path: String,
+    pub error_count: usize,
+    pub warning_count: usize,
+    pub diagnostics: Vec<DiagnosticRecord>,
+}
+
+/// A file whose diagnostics could not be collected.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct FileFailure {
+    pub file_path: String,
+    pub error: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DirDiagnosticsResponse {
+    pub path: String,
+    pub files_checked: usize,
+    /// Whether more files matched than `max_files` allowed checking.
+    pub truncated: bool,
+    pub error_count: usize,
+    pub warning_count: usize,
+    pub diagnostic_count: usize,
+    /// Files with at least one matching diagnostic, sorted by path.
+    pub files: Vec<FileDiagnostics>,
+    pub failures: Vec<FileFailure>,
+    pub summary: String,
+}
+
+/// Unused code found in one file by `rust_unused_report`.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct UnusedFileRecord {
+    pub file_path: String,
+    pub count: usize,
+    pub diagnostics: Vec<DiagnosticRecord>,
+}
+
+/// How often one lint reported unused code.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct UnusedCodeCount {
+    /// The lint, e.g. `dead_code` or `unused_imports`; `unnecessary` for
+    /// findings without a code.
+    pub code: String,
+    pub count: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct UnusedReportResponse {
+    pub path: String,
+    pub files_checked: usize,
+    /// Whether more files matched than `max_files` allowed checking.
+    pub truncated: bool,
+    pub unused_count: usize,
+    /// Findings per lint, most frequent first.
+    pub by_code: Vec<UnusedCodeCount>,
+    /// Files with unused code, sorted by path. With `fix`, as found before
+    /// fixing.
+    pub files: Vec<UnusedFileRecord>,
+    pub failures: Vec<FileFailure>,
+    /// With `fix`: how many findings were fixed.
+    pub fixed_count: usize,
+    /// With `fix`: the combined edit of every fix taken.
+    pub fix: Option<EditResponse>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct HoverResponse {
+    pub file_path: String,
+    pub requested_position: PositionRecord,
+    pub found: bool,
+    pub contents: String,
+    /// Whether `contents` was cut to fit `max_chars`.
+    pub truncated: bool,
+    pub range: Option<RangeRecord>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct ImplRecord {
+    /// The impl header, e.g. `impl<T: Debug> Display for Wrapper<T>`, or the
+    /// derive attribute for derived impls.
+    pub header: String,
+    pub derived: bool,
+    pub location: LocationRecord,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct ImplGroupRecord {
+    /// The implementing type (for a trait) or implemented trait (for a
+    /// type), without generic arguments.
+    pub name: String,
+    pub impls: Vec<ImplRecord>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TraitImplsResponse {
+    pub subject: String,
+    /// `trait` or `type`.
+    pub subject_kind: String,
+    pub impl_count: usize,
+    /// For a trait, its implementors; for a type, the traits it implements.
+    pub groups: Vec<ImplGroupRecord>,
+    /// For a type, its inherent impl blocks.
+    pub inherent: Vec<ImplRecord>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct IndexedSymbolRecord {
+    pub name: String,
+    pub kind: String,
+    pub container_name: Option<String>,
+    pub location: LocationRecord,
+}
+
+/// A symbol found by `rust_locate`.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LocatedSymbolRecord {
+    pub name: String,
+    pub kind: String,
+    pub container_name: Option<String>,
+    /// Zero-based line of the symbol's name, ready to pass as `line`.
+    pub line: u32,
+    /// Zero-based offset of the name's first character, ready to pass as
+    /// `character`.
+    pub character: u32,
+    /// The name's range, one-based like every output location.
+    pub location: LocationRecord,
+}
+
+/// A link rust-analyzer found in a file.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DocumentLinkRecord {
+    /// The linked text, with its line as the snippet.
+    pub location: LocationRecord,
+    /// Where the link leads: a URL, or a `file://` URI.
+    pub target: Option<String>,
+    /// The local path `target` names, for passing to other tools.
+    pub target_path: Option<String>,
+    pub tooltip: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DocumentLinksResponse {
+    pub file_path: String,
+    pub link_count: usize,
+    /// In file order.
+    pub links: Vec<DocumentLinkRecord>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LocateResponse {
+    pub file_path: String,
+    pub name: String,
+    /// Symbols matching `name` and `kind`, before `nth` picks one.
+    pub match_count: usize,
+    pub symbols: Vec<LocatedSymbolRecord>,
+    /// With no match: names in the file that come closest.
+    pub suggestions: Vec<String>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct SymbolSearchResponse {
+    pub query: String,
+    pub symbol_count: usize,
+    pub total_count: usize,
+    pub symbols: Vec<IndexedSymbolRecord>,
+    pub next_cursor: Option<String>,
+    /// Files in the index.
+    pub indexed_files: usize,
+    /// Files (re)indexed for this call because they were new or changed.
+    pub reindexed_files: usize,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DocResponse {
+    pub path: String,
+    pub found: bool,
+    /// The symbol kind, e.g. `struct` or `method`.
+    pub kind: Option<String>,
+    /// Where the item is defined.
+    pub location: Option<LocationRecord>,
+    /// The signature and rustdoc text, as `rust_hover` shows them.
+    pub contents: String,
+    /// Whether `contents` was cut to fit `max_chars`.
+    pub truncated: bool,
+    /// The item's page on docs.rs (or doc.rust-lang.org for the standard
+    /// library); `None` for items of the workspace itself.
+    pub docs_url: Option<String>,
+    /// Other items with the same name that the path did not select.
+    pub other_matches: Vec<WorkspaceSymbolRecord>,
+    pub summary: String,
+}
+
+/// The locations of a response that fall in one file.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct FileLocations {
+    pub file_path: String,
+    pub count: usize,
+    pub locations: Vec<LocationRecord>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LocationsResponse {
+    pub file_path: String,
+    pub requested_position: PositionRecord,
+    pub found: bool,
+    pub location_count: usize,
+    pub total_count: usize,
+    /// Ungrouped locations; empty when `files` is used instead.
+    pub locations: Vec<LocationRecord>,
+    /// Locations grouped per file, when `group_by_file` was requested.
+    pub files: Vec<FileLocations>,
+    pub next_cursor: Option<String>,
+    pub summary: String,
+}
+
+/// A reference that keeps a symbol from being deleted.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct SafeDeleteBlockerRecord {
+    /// The name of the referenced symbol.
+    pub symbol: String,
+    pub location: LocationRecord,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct SafeDeleteResponse {
+    pub file_path: String,
+    /// The symbol checked; `None` when the whole file was.
+    pub symbol: Option<String>,
+    pub safe: bool,
+    pub symbols_checked: usize,
+    pub blocker_count: usize,
+    /// References from outside what would be deleted, by file and position.
+    pub blockers: Vec<SafeDeleteBlockerRecord>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct WorkspaceSymbolRecord {
+    pub name: String,
+    pub kind: String,
+    pub container_name: Option<String>,
+    pub location: LocationRecord,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct WorkspaceSymbolsResponse {
+    pub query: String,
+    pub symbol_count: usize,
+    pub total_count: usize,
+    pub symbols: Vec<WorkspaceSymbolRecord>,
+    pub next_cursor: Option<String>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct WarmupResponse {
+    pub server: String,
+    /// Whether rust-analyzer was already running before this call.
+    pub already_running: bool,
+    /// Time spent starting, priming, and (if requested) waiting for rust-analyzer.
+    pub elapsed_ms: u64,
+    /// Crate root files opened to make rust-analyzer load every package.
+    pub crate_roots_opened: usize,
+    /// Whether the symbol index was built with an empty `workspace/symbol` query.
+    pub symbols_indexed: bool,
+    /// Whether rust-analyzer reports that indexing has finished.
+    pub ready: bool,
+    pub readiness: ReadinessState,
+    pub workspace_root: Option<String>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct RestartResponse {
+    pub server: String,
+    /// Whether a connection was running before this call.
+    pub was_running: bool,
+    /// Whether the watchdog had found the old connection unresponsive.
+    pub was_degraded: bool,
+    /// Time spent shutting down, reconnecting, and reopening documents.
+    pub elapsed_ms: u64,
+    /// Documents of the old connection opened again in the new one.
+    pub documents_reopened: usize,
+    pub server_version: Option<String>,
+    pub workspace_root: Option<String>,
+    pub readiness: ReadinessState,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DocumentResponse {
+    pub file_path: String,
+    /// What happened: `opened`, `already_open`, `refreshed`, `unchanged`,
+    /// `closed`, or `not_open`.
+    pub action: String,
+    /// The version of the document rust-analyzer now has, if it is open.
+    pub version: Option<i32>,
+    /// Documents open in rust-analyzer after the call.
+    pub open_documents: usize,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LinkProjectResponse {
+    pub project_file: String,
+    /// The generator command run first, if `regenerate` was set.
+    pub regenerated_with: Option<String>,
+    /// Whether a running rust-analyzer was told to reload; otherwise the
+    /// project is loaded when it starts.
+    pub reloaded: bool,
+    pub summary: String,
+}
+
+/// The change an editing tool made to one file.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct FileDiff {
+    pub file_path: String,
+    /// Unified diff of the file's content before and after the edit, against
+    /// `/dev/null` for a created or deleted file and headed by `rename from`
+    /// and `rename to` lines for a moved one.
+    pub diff: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct EditResponse {
+    /// What was applied, e.g. the title of a code action.
+    pub title: String,
+    pub dry_run: bool,
+    /// Whether files on disk were changed.
+    pub applied: bool,
+    pub files: Vec<FileDiff>,
+    /// Titles of the other fixes that were offered.
+    pub alternatives: Vec<String>,
+    /// Where the original content of replaced or removed files was saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup_dir: Option<String>,
+    /// The placeholders of snippet edits, such as the name of an extracted
+    /// function, and where the cursor would end up (index 0), as written.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tab_stops: Vec<TabStopRecord>,
+    pub summary: String,
+}
+
+/// A tab stop of a snippet edit.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TabStopRecord {
+    /// The order an editor visits it in; `0` is the final cursor position.
+    pub index: u32,
+    /// The text to type over; empty for a bare tab stop.
+    pub placeholder: String,
+    pub location: LocationRecord,
+}
+
+/// What running a command did.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct ExecuteCommandResponse {
+    pub command: String,
+    /// Whether rust-analyzer ran the command. Its editor commands, such as
+    /// `rust-analyzer.runSingle`, are carried out here instead.
+    pub ran_on_server: bool,
+    /// What the server returned.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<serde_json::Value>,
+    /// Where a `gotoLocation` or `showReferences` command points.
+    pub locations: Vec<LocationRecord>,
+    /// The cargo command a `runSingle` command ran.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub run: Option<CommandRunRecord>,
+    /// The edits the server asked for while the command ran.
+    pub edits: Vec<EditResponse>,
+    pub summary: String,
+}
+
+/// A cargo command run for a runnable.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CommandRunRecord {
+    /// The directory cargo ran in.
+    pub cwd: String,
+    pub command: String,
+    pub success: bool,
+    /// The end of what cargo and the program printed, stderr first.
+    pub output: String,
+}
+
+/// A diagnostic from a cargo build.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CargoDiagnosticRecord {
+    pub severity: String,
+    pub message: String,
+    pub code: Option<String>,
+    /// The package being compiled; absent for errors cargo reports itself,
+    /// such as a failed build script.
+    pub package: Option<String>,
+    pub location: Option<LocationRecord>,
+    /// The diagnostic as cargo prints it, with source excerpts and notes.
+    pub rendered: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CargoCheckResponse {
+    pub workspace_root: String,
+    /// The cargo command that was run.
+    pub command: String,
+    /// Whether cargo finished without errors.
+    pub success: bool,
+    pub error_count: usize,
+    pub warning_count: usize,
+    pub diagnostics: Vec<CargoDiagnosticRecord>,
+    /// Whether diagnostics beyond `max_diagnostics` were left out.
+    pub truncated: bool,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TestRecord {
+    /// The test's path, e.g. `parser::tests::parses_empty_input`.
+    pub name: String,
+    /// `passed`, `failed`, or `ignored`.
+    pub outcome: String,
+    /// What a failed test printed, including its panic message.
+    pub output: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CargoTestResponse {
+    /// The directory cargo ran in.
+    pub cwd: String,
+    /// The cargo command that was run.
+    pub command: String,
+    /// Whether the tests built and none failed.
+    pub success: bool,
+    pub passed: usize,
+    pub failed: usize,
+    pub ignored: usize,
+    pub tests: Vec<TestRecord>,
+    /// Whether tests beyond `max_tests` were left out.
+    pub truncated: bool,
+    /// Compiler errors that kept the tests from building.
+    pub build_errors: Vec<CargoDiagnosticRecord>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TargetRecord {
+    pub name: String,
+    /// `lib`, `bin`, `test`, `example`, `bench`, `custom-build`, ...
+    pub kinds: Vec<String>,
+    pub src_path: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DependencyRecord {
+    pub name: String,
+    /// The name the package uses for it, if renamed.
+    pub rename: Option<String>,
+    /// The version requirement in the manifest, e.g. `^1.0`.
+    pub req: String,
+    /// The version the lockfile resolved it to; `None` for optional
+    /// dependencies that are not enabled.
+    pub resolved: Option<String>,
+    /// `normal`, `dev`, or `build`.
+    pub kind: String,
+    pub optional: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct PackageRecord {
+    pub name: String,
+    pub version: String,
+    pub manifest_path: String,
+    pub edition: String,
+    /// Each feature and the features or dependencies it enables.
+    pub features: BTreeMap<String, Vec<String>>,
+    pub targets: Vec<TargetRecord>,
+    pub dependencies: Vec<DependencyRecord>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct OwnerRecord {
+    pub package: String,
+    pub manifest_path: String,
+    /// The targets the file belongs to, e.g. `lib app`.
+    pub targets: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DependentRecord {
+    /// The workspace package that depends on the crate.
+    pub package: String,
+    pub dependency: DependencyRecord,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CargoMetadataResponse {
+    pub workspace_root: String,
+    pub target_directory: String,
+    /// The workspace members, or the requested `package`.
+    pub packages: Vec<PackageRecord>,
+    /// The package owning `file_path`.
+    pub owner: Option<OwnerRecord>,
+    /// The workspace packages declaring `dependency`.
+    pub dependents: Vec<DependentRecord>,
+    /// Every version of `dependency` in the lockfile, direct or not.
+    pub dependency_versions: Vec<String>,
+    /// Whether the metadata was reused from an earlier call; it is refreshed
+    /// when a manifest or the lockfile changes.
+    pub cached: bool,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CargoExpandResponse {
+    /// The cargo command that was run.
+    pub command: String,
+    /// The expanded code.
+    pub expansion: String,
+    /// Whether the expansion was cut to fit `max_chars`.
+    pub truncated: bool,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TreeEntryRecord {
+    /// 0 for the starting packages.
+    pub depth: usize,
+    pub name: String,
+    pub version: String,
+    /// How the parent depends on it (inverted: it on the parent): `normal`,
+    /// `build`, or `dev`.
+    pub kind: String,
+    /// Whether its dependencies were shown earlier in the tree and are left
+    /// out here.
+    pub repeated: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DuplicateVersionRecord {
+    pub version: String,
+    /// The packages depending on this version, as `name version`.
+    pub dependents: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct DuplicateRecord {
+    pub name: String,
+    pub versions: Vec<DuplicateVersionRecord>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CargoTreeResponse {
+    /// The tree in depth-first order.
+    pub entries: Vec<TreeEntryRecord>,
+    /// With `duplicates`: crates present at more than one version.
+    pub duplicates: Vec<DuplicateRecord>,
+    /// Whether tree lines beyond `max_entries` were left out.
+    pub truncated: bool,
+    /// Whether the metadata was reused from an earlier call.
+    pub cached: bool,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct GenerateResponse {
+    /// Titles of the generators available at the position.
+    pub available: Vec<String>,
+    /// What the chosen generator changed (or would change, in a dry run).
+    pub edit: Option<EditResponse>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CompletionRecord {
+    /// The item's place in the server's list, to pass as `index`.
+    pub index: usize,
+    pub label: String,
+    /// `function`, `method`, `struct`, `keyword`, ...
+    pub kind: Option<String>,
+    /// Usually the item's type or signature.
+    pub detail: Option<String>,
+    /// Where the item comes from, such as the path an auto-import
+    /// completion imports.
+    pub source: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TextEditRecord {
+    pub range: RangeRecord,
+    pub new_text: String,
+}
+
+/// A completion resolved for its documentation and edits.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CompletionDetailRecord {
+    pub label: String,
+    pub detail: Option<String>,
+    pub documentation: Option<String>,
+    /// The edit at the completed position.
+    pub text_edit: TextEditRecord,
+    /// Edits elsewhere in the file, such as the `use` declaration an
+    /// auto-import completion adds.
+    pub additional_edits: Vec<TextEditRecord>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct CompletionsResponse {
+    pub file_path: String,
+    pub requested_position: PositionRecord,
+    /// The completions, in the server's preferred order.
+    pub items: Vec<CompletionRecord>,
+    /// Whether typing more may change which items the server offers.
+    pub is_incomplete: bool,
+    /// Whether items beyond `max_items` were left out.
+    pub truncated: bool,
+    /// The completion picked with `label` or `index`.
+    pub selected: Option<CompletionDetailRecord>,
+    /// The diff of the picked completion's edits; written only with `apply`.
+    pub edit: Option<EditResponse>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct ServerStatusResponse {
+    pub server: String,
+    pub server_status: String,
+    pub workspace_root: Option<String>,
+    pub server_version: Option<String>,
+    /// Shared-service bootstrap outcome; `None` until rust-analyzer is started.
+    pub runtime: Option<RuntimeStatus>,
+    pub client: ClientIdentity,
+    /// The MCP session that issued this status request.
+    pub mcp_session_id: Option<String>,
+    pub readiness: ReadinessState,
+    pub telemetry: TelemetrySnapshot,
+    pub compiler_accounting: CompilerAccountingSnapshot,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LspmuxInstanceRecord {
+    pub pid: u32,
+    pub server: String,
+    pub workspace_root: String,
+    /// Ports of the editors and agents attached to this instance.
+    pub client_ports: Vec<u16>,
+    /// Whether more than one client shares this instance.
+    pub shared: bool,
+    /// Whether this is the workspace this MCP server queries.
+    pub this_workspace: bool,
+    /// How long the language server has been running; `None` if `ps`
+    /// cannot tell.
+    pub uptime_secs: Option<u64>,
+    /// Seconds since a client last sent it a message.
+    pub idle_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LspmuxStatusResponse {
+    /// The lspmux server asked; `None` while none is in use.
+    pub endpoint: Option<String>,
+    pub instance_count: usize,
+    pub client_count: usize,
+    pub instances: Vec<LspmuxInstanceRecord>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct RestartInstanceResponse {
+    /// The instance as it was before it was stopped.
+    pub instance: LspmuxInstanceRecord,
+    /// Whether this server's own connection was to it and was replaced.
+    pub reconnected: bool,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct ReapIdleResponse {
+    pub reaped: Vec<LspmuxInstanceRecord>,
+    /// Instances left running.
+    pub kept: usize,
+    /// Instances that could not be stopped, with the reason.
+    pub failures: Vec<String>,
+    pub summary: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct ToolStatsRecord {
+    pub tool: String,
+    pub call_count: u64,
+    pub failure_count: u64,
+    pub timeout_count: u64,
+    /// Percentiles over the tool's most recent calls.
+    pub latency: Option<LatencySummary>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct CacheStatsRecord {
+    pub hits: u64,
+    pub misses: u64,
+    /// Hits over lookups; `None` before the first lookup.
+    pub hit_rate: Option<f64>,
+    pub entries: usize,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct LspTrafficRecord {
+    pub pending_requests: usize,
+    pub open_documents: usize,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+pub struct ServerStatsResponse {
+    pub uptime_secs: u64,
+    /// Every tool called so far, busiest first.
+    pub tools: Vec<ToolStatsRecord>,
+    /// The cache of hover, definition, and reference answers.
+    pub response_cache: CacheStatsRecord,
+    /// `None` until rust-analyzer is started.
+    pub lsp: Option<LspTrafficRecord>,
+    pub summary: String,
+}
+
+/// A document rust-analyzer has been sent.
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TrackedDocumentRecord {
+    pub file_path: String,
+    /// The LSP version of the content rust-analyzer holds.
+    pub version: i32,
+    /// Hash of that content, in hex; it changes with every edit sent.
+    pub content_hash: String,
+    /// Bytes of content rust-analyzer holds.
+    pub bytes: u64,
+    /// Seconds since the content was last compared against the file.
+    pub synced_secs_ago: u64,
+    /// Seconds since a tool call last touched the document.
+    pub used_secs_ago: u64,
+    /// How the file on disk compares with what was read: `unchanged`,
+    /// `modified` (the next tool call on it resends it), `missing`, or
+    /// `unknown`.
+    pub on_disk: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct TrackedDocumentsResponse {
+    pub document_count: usize,
+    /// Bytes of content held across all documents.
+    pub total_bytes: u64,
+    /// Sorted by path.
+    pub documents: Vec<TrackedDocumentRecord>,
+    pub summary: String,
+}
+
+/// Human-readable rendering of a structured tool response.
+///
+/// Agents read `structuredContent`; the text rendering is what MCP hosts show
+/// to people and what older clients without structured output fall back to.
+trait RenderText {
+    fn render_text(&self) -> String;
+}
+
+/// A tool result carrying both `structuredContent` (the serialized value) and
+/// a plain-text `content` block produced by [`RenderText`].
+pub struct Structured<T>(pub T);
+
+impl<T: Serialize + RenderText> IntoCallToolResult for Structured<T> {
+    fn into_call_tool_result(self) -> Result<CallToolResult, McpError> {
+        let value = serde_json::to_value(&self.0)
+            .map_err(|e| internal_error(format!("failed to serialize structured content: {e}")))?;
+        Ok(CallToolResult {
+            content: vec![Content::text(self.0.render_text())],
+            structured_content: Some(value),
+            is_error: Some(false),
+            meta: None,
+        })
+    }
+}
+
+/// What a tool's text `content` block holds, chosen by the `output` argument
+/// that every tool accepts. `structuredContent` is the same either way.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum OutputFormat {
+    /// A human-readable summary followed by the results.
+    #[default]
+    Text,
+    /// The structured result itself, as pretty-printed JSON.
+    Json,
+}
+
+impl OutputFormat {
+    /// Remove the `output` argument from `arguments`, so tool parameter types
+    /// never see it, and parse it.
+    fn take(arguments: Option<&mut JsonObject>) -> Result<Self, McpError> {
+        match arguments.and_then(|arguments| arguments.remove("output")) {
+            None | Some(serde_json::Value::Null) => Ok(Self::Text),
+            Some(value) => serde_json::from_value(value).map_err(|_| {
+                McpError::invalid_params("`output` must be \"text\" or \"json\"", None)
+            }),
+        }
+    }
+
+    /// Rewrite `result` for this format.
+    fn apply(self, mut result: CallToolResult) -> Result<CallToolResult, McpError> {
+        if self == Self::Json {
+            if let Some(structured) = &result.structured_content {
+                let json = serde_json::to_string_pretty(structured).map_err(|e| {
+                    internal_error(format!("failed to serialize structured content: {e}"))
+                })?;
+                result.content = vec![Content::text(json)];
+            }
+        }
+        Ok(result)
+    }
+}
+
+/// `tool` with the `output` and `workspace_root` arguments every tool
+/// accepts added to its input schema.
+fn with_shared_params(mut tool: Tool) -> Tool {
+    let mut schema = (*tool.input_schema).clone();
+    let properties = schema
+        .entry("properties")
+        .or_insert_with(|| serde_json::Value::Object(JsonObject::new()));
+    if let Some(properties) = properties.as_object_mut() {
+        properties.insert(
+            "output".to_string(),
+            serde_json::json!({
+                "type": "string",
+                "enum": ["text", "json"],
+                "default": "text",
+                "description": "`text` (default) for a readable summary; `json` to get the structured result as the text content, with no prose."
+            }),
+        );
+        properties.insert(
+            "workspace_root".to_string(),
+            serde_json::json!({
+                "type": "string",
+                "description": "Absolute path of another Cargo workspace to query instead of the server's own, e.g. a sibling checkout of another branch. Its rust-analyzer starts on first use."
+            }),
+        );
+    }
+    tool.input_schema = Arc::new(schema);
+    tool
+}
+
+/// The declared `outputSchema` for a structured tool response type.
+fn output_schema<T: JsonSchema + 'static>() -> Arc<JsonObject> {
+    schema_for_output::<T>().unwrap_or_else(|e| {
+        panic!(
+            "invalid output schema for {}: {e}",
+            std::any::type_name::<T>()
+        )
+    })
+}
+
+/// Append one diagnostic, and its related spans, as rustc-style lines.
+fn render_diagnostic(text: &mut String, diagnostic: &DiagnosticRecord) {
+    let code = diagnostic
+        .code
+        .as_deref()
+        .map(|code| format!("[{code}]"))
+        .unwrap_or_default();
+    let _ = write!(
+        text,
+        "\n#{} {}: {}{code}: {}",
+        diagnostic.index, diagnostic.location.display, diagnostic.severity, diagnostic.message
+    );
+    if let Some(source) = &diagnostic.source {
+        let _ = write!(text, " ({source})");
+    }
+    if !diagnostic.tags.is_empty() {
+        let _ = write!(text, " [{}]", diagnostic.tags.join(", "));
+    }
+    for related in &diagnostic.related {
+        let _ = write!(
+            text,
+            "\n    --> {}: {}",
+            related.location.display, related.message
+        );
+    }
+}
+
+impl PageItem for DiagnosticRecord {}
+
+impl PageItem for LocationRecord {
+    fn file_path(&self) -> Option<&str> {
+        Some(&self.file_path)
+    }
+}
+
+impl PageItem for WorkspaceSymbolRecord {
+    fn file_path(&self) -> Option<&str> {
+        Some(&self.location.file_path)
+    }
+}
+
+impl PageItem for IndexedSymbolRecord {
+    fn file_path(&self) -> Option<&str> {
+        Some(&self.location.file_path)
+    }
+}
+
+impl RenderText for DiagnosticsResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for diagnostic in &self.diagnostics {
+            render_diagnostic(&mut text, diagnostic);
+        }
+        for explanation in &self.explanations {
+            let _ = write!(
+                text,
+                "\n\n# {}\n\n{}",
+                explanation.code, explanation.explanation
+            );
+        }
+        text
+    }
+}
+
+impl RenderText for DiagnosticsSnapshotResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for failure in &self.failures {
+            let _ = write!(text, "\n\nfailed: {}: {}", failure.file_path, failure.error);
+        }
+        text
+    }
+}
+
+impl RenderText for DiagnosticsDiffResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for (heading, diagnostics) in [("Fixed", &self.fixed), ("Introduced", &self.introduced)] {
+            if !diagnostics.is_empty() {
+                let _ = write!(text, "\n\n{heading}:");
+                for diagnostic in diagnostics {
+                    render_diagnostic(&mut text, diagnostic);
+                }
+            }
+        }
+        for failure in &self.failures {
+            let _ = write!(text, "\n\nfailed: {}: {}", failure.file_path, failure.error);
+        }
+        text
+    }
+}
+
+impl RenderText for WaitForDiagnosticsResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for diagnostic in &self.diagnostics {
+            render_diagnostic(&mut text, diagnostic);
+        }
+        text
+    }
+}
+
+impl RenderText for FlycheckResponse {
+    fn render_text(&self) -> String {
+        self.summary.clone()
+    }
+}
+
+impl RenderText for ExplainResponse {
+    fn render_text(&self) -> String {
+        self.explanation.as_ref().map_or_else(
+            || self.summary.clone(),
+            |explanation| format!("{}\n\n{explanation}", self.summary),
+        )
+    }
+}
+
+impl RenderText for DirDiagnosticsResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for file in &self.files {
+            let _ = write!(
+                text,
+                "\n\n{} ({} error(s), {} warning(s))",
+                file.file_path, file.error_count, file.warning_count
+            );
+            for diagnostic in &file.diagnostics {
+                render_diagnostic(&mut text, diagnostic);
+            }
+        }
+        for failure in &self.failures {
+            let _ = write!(text, "\n\nfailed: {}: {}", failure.file_path, failure.error);
+        }
+        text
+    }
+}
+
+impl RenderText for UnusedReportResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for file in &self.files {
+            let _ = write!(text, "\n\n{} ({} unused)", file.file_path, file.count);
+            for diagnostic in &file.diagnostics {
+                render_diagnostic(&mut text, diagnostic);
+            }
+        }
+        for failure in &self.failures {
+            let _ = write!(text, "\n\nfailed: {}: {}", failure.file_path, failure.error);
+        }
+        if let Some(fix) = &self.fix {
+            let _ = write!(text, "\n\n{}", fix.render_text());
+        }
+        text
+    }
+}
+
+impl RenderText for HoverResponse {
+    fn render_text(&self) -> String {
+        if self.found {
+            format!("{}\n\n{}", self.summary, self.contents)
+        } else {
+            self.summary.clone()
+        }
+    }
+}
+
+impl RenderText for TraitImplsResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for group in &self.groups {
+            let _ = write!(text, "\n{}", group.name);
+            for record in &group.impls {
+                let _ = write!(text, "\n  {}: {}", record.location.display, record.header);
+            }
+        }
+        if !self.inherent.is_empty() {
+            text.push_str("\ninherent");
+            for record in &self.inherent {
+                let _ = write!(text, "\n  {}: {}", record.location.display, record.header);
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for SymbolSearchResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for symbol in &self.symbols {
+            let _ = write!(
+                text,
+                "\n{} {} {}",
+                symbol.location.display, symbol.kind, symbol.name
+            );
+            if let Some(container) = &symbol.container_name {
+                let _ = write!(text, " (in {container})");
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for DocumentLinksResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for link in &self.links {
+            let target = link
+                .target_path
+                .as_deref()
+                .or(link.target.as_deref())
+                .unwrap_or("(unresolved)");
+            let _ = write!(text, "\n- {} -> {target}", link.location.display);
+        }
+        text
+    }
+}
+
+impl RenderText for LocateResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for symbol in &self.symbols {
+            let _ = write!(
+                text,
+                "\nline {} character {}: {} {}",
+                symbol.line, symbol.character, symbol.kind, symbol.name
+            );
+            if let Some(container) = &symbol.container_name {
+                let _ = write!(text, " (in {container})");
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for DocResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        if let Some(url) = &self.docs_url {
+            let _ = write!(text, "\n{url}");
+        }
+        if self.found {
+            let _ = write!(text, "\n\n{}", self.contents);
+        }
+        text
+    }
+}
+
+/// Append a location's snippet, if any, marking the target line.
+fn render_snippet(text: &mut String, location: &LocationRecord) {
+    if let Some(snippet) = &location.snippet {
+        for (line, source) in (snippet.start_line..).zip(&snippet.lines) {
+            let marker = if line == location.line { ">" } else { " " };
+            let _ = write!(text, "\n  {marker} {line:>5} | {source}");
+        }
+    }
+}
+
+/// Group `locations` by file, keeping the order in which files first appear.
+fn group_by_file(locations: Vec<LocationRecord>) -> Vec<FileLocations> {
+    let mut files: Vec<FileLocations> = Vec::new();
+    for location in locations {
+        match files
+            .iter_mut()
+            .find(|file| file.file_path == location.file_path)
+        {
+            Some(file) => {
+                file.count += 1;
+                file.locations.push(location);
+            }
+            None => files.push(FileLocations {
+                file_path: location.file_path.clone(),
+                count: 1,
+                locations: vec![location],
+            }),
+        }
+    }
+    files
+}
+
+impl RenderText for SafeDeleteResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for blocker in &self.blockers {
+            let _ = write!(text, "\n{} ({})", blocker.location.display, blocker.symbol);
+            render_snippet(&mut text, &blocker.location);
+        }
+        text
+    }
+}
+
+impl RenderText for LocationsResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for location in &self.locations {
+            let _ = write!(text, "\n{}", location.display);
+            render_snippet(&mut text, location);
+        }
+        for file in &self.files {
+            let _ = write!(text, "\n\n{} ({})", file.file_path, file.count);
+            for location in &file.locations {
+                let _ = write!(text, "\n  {}:{}", location.line, location.column);
+                render_snippet(&mut text, location);
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for WorkspaceSymbolsResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for symbol in &self.symbols {
+            let container = symbol
+                .container_name
+                .as_deref()
+                .map(|container| format!(" in {container}"))
+                .unwrap_or_default();
+            let _ = write!(
+                text,
+                "\n{} {}{container} at {}",
+                symbol.kind, symbol.name, symbol.location.display
+            );
+        }
+        text
+    }
+}
+
+impl RenderText for HealthReport {
+    fn render_text(&self) -> String {
+        self.render()
+    }
+}
+
+impl RenderText for WarmupResponse {
+    fn render_text(&self) -> String {
+        self.summary.clone()
+    }
+}
+
+impl RenderText for RestartResponse {
+    fn render_text(&self) -> String {
+        self.summary.clone()
+    }
+}
+
+impl RenderText for DocumentResponse {
+    fn render_text(&self) -> String {
+        self.summary.clone()
+    }
+}
+
+impl RenderText for LinkProjectResponse {
+    fn render_text(&self) -> String {
+        self.summary.clone()
+    }
+}
+
+impl EditResponse {
+    /// The diff of each file, then the tab stops, each block led by a
+    /// blank line.
+    fn details(&self) -> String {
+        let mut text = String::new();
+        for file in &self.files {
+            let _ = write!(text, "\n\n{}", file.diff.trim_end());
+        }
+        if !self.tab_stops.is_empty() {
+            text.push_str("\n\nTab stops:");
+            for stop in &self.tab_stops {
+                let _ = match stop.index {
+                    0 => write!(text, "\n- cursor at {}", stop.location.display),
+                    index => write!(
+                        text,
+                        "\n- ${index} `{}` at {}",
+                        stop.placeholder, stop.location.display
+                    ),
+                };
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for EditResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        text.push_str(&self.details());
+        if !self.alternatives.is_empty() {
+            let _ = write!(
+                text,
+                "\n\nOther fixes offered: {}",
+                self.alternatives.join("; ")
+            );
+        }
+        text
+    }
+}
+
+impl RenderText for ExecuteCommandResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for location in &self.locations {
+            let _ = write!(text, "\n{}", location.display);
+        }
+        if let Some(run) = &self.run {
+            let _ = write!(text, "\n\n{}", run.output.trim_end());
+        }
+        for edit in &self.edits {
+            let _ = write!(text, "\n\n{}", edit.render_text());
+        }
+        if let Some(result) = &self.result {
+            let _ = write!(text, "\n\nResult: {result}");
+        }
+        text
+    }
+}
+
+impl RenderText for CargoCheckResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for diagnostic in &self.diagnostics {
+            let code = diagnostic
+                .code
+                .as_deref()
+                .map(|code| format!("[{code}]"))
+                .unwrap_or_default();
+            text.push('\n');
+            if let Some(location) = &diagnostic.location {
+                let _ = write!(text, "{}: ", location.display);
+            }
+            let _ = write!(
+                text,
+                "{}{code}: {}",
+                diagnostic.severity, diagnostic.message
+            );
+            if let Some(package) = &diagnostic.package {
+                let _ = write!(text, " ({package})");
+            }
+            // Without a location, the rendering carries the detail, such as a
+            // build script's output.
+            if diagnostic.location.is_none() {
+                let rendered = diagnostic.rendered.as_deref().unwrap_or_default();
+                for line in rendered.lines().skip(1) {
+                    let _ = write!(text, "\n    {line}");
+                }
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for CargoTestResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for diagnostic in &self.build_errors {
+            text.push('\n');
+            if let Some(location) = &diagnostic.location {
+                let _ = write!(text, "{}: ", location.display);
+            }
+            let _ = write!(text, "{}: {}", diagnostic.severity, diagnostic.message);
+        }
+        for test in &self.tests {
+            let _ = write!(text, "\n{} {}", test.outcome, test.name);
+            for line in test.output.iter().flat_map(|output| output.lines()) {
+                let _ = write!(text, "\n    {line}");
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for CargoMetadataResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for package in &self.packages {
+            let _ = write!(
+                text,
+                "\n\n{} {} ({}, edition {})",
+                package.name, package.version, package.manifest_path, package.edition
+            );
+            for target in &package.targets {
+                let _ = write!(
+                    text,
+                    "\n  {} {}: {}",
+                    target.kinds.join(","),
+                    target.name,
+                    target.src_path
+                );
+            }
+            for (feature, enables) in &package.features {
+                let _ = write!(text, "\n  feature {feature} = [{}]", enables.join(", "));
+            }
+            for dependency in &package.dependencies {
+                let _ = write!(text, "\n  {}", dependency_line(dependency));
+            }
+        }
+        for dependent in &self.dependents {
+            let _ = write!(
+                text,
+                "\n{}: {}",
+                dependent.package,
+                dependency_line(&dependent.dependency)
+            );
+        }
+        text
+    }
+}
+
+/// A dependency as `kind name req -> resolved`, e.g. `dev tempfile ^3 -> 3.10.1`.
+fn dependency_line(dependency: &DependencyRecord) -> String {
+    let mut line = String::new();
+    if dependency.kind != "normal" {
+        let _ = write!(line, "{} ", dependency.kind);
+    }
+    line.push_str(&dependency.name);
+    if let Some(rename) = &dependency.rename {
+        let _ = write!(line, " (as {rename})");
+    }
+    let _ = write!(line, " {}", dependency.req);
+    if let Some(resolved) = &dependency.resolved {
+        let _ = write!(line, " -> {resolved}");
+    }
+    if dependency.optional {
+        line.push_str(" (optional)");
+    }
+    line
+}
+
+impl RenderText for CargoExpandResponse {
+    fn render_text(&self) -> String {
+        format!("{}\n\n{}", self.summary, self.expansion)
+    }
+}
+
+impl RenderText for CargoTreeResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for entry in &self.entries {
+            let _ = write!(
+                text,
+                "\n{}{} v{}",
+                "  ".repeat(entry.depth),
+                entry.name,
+                entry.version
+            );
+            if entry.kind != "normal" {
+                let _ = write!(text, " ({})", entry.kind);
+            }
+            if entry.repeated {
+                text.push_str(" (*)");
+            }
+        }
+        for duplicate in &self.duplicates {
+            let _ = write!(text, "\n{}", duplicate.name);
+            for version in &duplicate.versions {
+                let _ = write!(
+                    text,
+                    "\n  v{} <- {}",
+                    version.version,
+                    version.dependents.join(", ")
+                );
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for GenerateResponse {
+    fn render_text(&self) -> String {
+        if let Some(edit) = &self.edit {
+            return edit.render_text();
+        }
+        let mut text = self.summary.clone();
+        for title in &self.available {
+            let _ = write!(text, "\n- {title}");
+        }
+        text
+    }
+}
+
+impl RenderText for CompletionsResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        if let Some(selected) = &self.selected {
+            if let Some(detail) = &selected.detail {
+                let _ = write!(text, "\n\n{detail}");
+            }
+            if let Some(documentation) = &selected.documentation {
+                let _ = write!(text, "\n\n{}", documentation.trim_end());
+            }
+            if let Some(edit) = &self.edit {
+                text.push_str(&edit.details());
+            }
+            return text;
+        }
+        for item in &self.items {
+            let _ = write!(text, "\n- [{}] {}", item.index, item.label);
+            if let Some(kind) = &item.kind {
+                let _ = write!(text, " ({kind})");
+            }
+            if let Some(detail) = &item.detail {
+                let _ = write!(text, ": {detail}");
+            }
+            if let Some(source) = &item.source {
+                let _ = write!(text, " [{source}]");
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for ServerStatsResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for tool in &self.tools {
+            let _ = write!(
+                text,
+                "\n- {}: {} call(s), {} failed",
+                tool.tool, tool.call_count, tool.failure_count
+            );
+            if let Some(latency) = tool.latency {
+                let _ = write!(text, ", p50 {}ms, p95 {}ms", latency.p50_ms, latency.p95_ms);
+            }
+        }
+        if let Some(lsp) = &self.lsp {
+            let _ = write!(
+                text,
+                "\nLSP: {} pending request(s), {} open document(s), {} bytes sent, {} received",
+                lsp.pending_requests, lsp.open_documents, lsp.bytes_sent, lsp.bytes_received
+            );
+        }
+        text
+    }
+}
+
+impl RenderText for LspmuxStatusResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for instance in &self.instances {
+            let _ = write!(
+                text,
+                "\n- pid {} {} in {}: {} client(s)",
+                instance.pid,
+                instance.server,
+                instance.workspace_root,
+                instance.client_ports.len()
+            );
+            if let Some(uptime) = instance.uptime_secs {
+                let _ = write!(text, ", up {uptime}s");
+            }
+            let _ = write!(text, ", idle {}s", instance.idle_secs);
+            if instance.this_workspace {
+                text.push_str(" (this workspace)");
+            }
+        }
+        text
+    }
+}
+
+impl RenderText for RestartInstanceResponse {
+    fn render_text(&self) -> String {
+        self.summary.clone()
+    }
+}
+
+impl RenderText for ReapIdleResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for instance in &self.reaped {
+            let _ = write!(
+                text,
+                "\n- pid {} in {}, idle {}s",
+                instance.pid, instance.workspace_root, instance.idle_secs
+            );
+        }
+        for failure in &self.failures {
+            let _ = write!(text, "\n- {failure}");
+        }
+        text
+    }
+}
+
+impl RenderText for TrackedDocumentsResponse {
+    fn render_text(&self) -> String {
+        let mut text = self.summary.clone();
+        for document in &self.documents {
+            let _ = write!(
+                text,
+                "\n- {} v{} ({} bytes, hash {}): synced {}s ago, used {}s ago, {} on disk",
+                document.file_path,
+                document.version,
+                document.bytes,
+                document.content_hash,
+                document.synced_secs_ago,
+                document.used_secs_ago,
+                document.on_disk
+            );
+        }
+        text
+    }
+}
+
+impl RenderText for ServerStatusResponse {
+    fn render_text(&self) -> String {
+        let bootstrap = self.runtime.as_ref().map_or_else(
+            || "not started".to_string(),
+            |runtime| format!("{:?} ({:?})", runtime.service_mode, runtime.bootstrap_mode),
+        );
+        format!(
+            "{}\nserver version: {}\nbootstrap: {bootstrap}\nactive MCP sessions: {}",
+            self.summary,
+            self.server_version.as_deref().unwrap_or("<unknown>"),
+            self.telemetry.sessions.len()
+        )
+    }
+}
+
+fn location_record(uri: &lsp_types::Uri, range: &lsp_types::Range) -> LocationRecord {
+    let file_path = uri_to_path(uri);
+    LocationRecord {
+        display: format!(
+            "{}:{}:{}",
+            file_path,
+            range.start.line + 1,
+            range.start.character + 1,
+        ),
+        file_path,
+        uri: uri.to_string(),
+        line: range.start.line + 1,
+        column: range.start.character + 1,
+        end_line: range.end.line + 1,
+        end_column: range.end.character + 1,
+        snippet: None,
+    }
+}
+
+/// A location's identity for sorting and deduplication.
+fn location_key(location: &LocationRecord) -> (&str, u32, u32, u32, u32) {
+    (
+        &location.file_path,
+        location.line,
+        location.column,
+        location.end_line,
+        location.end_column,
+    )
+}
+
+/// The innermost document symbol whose name covers `position`: its name
+/// and the range of its whole declaration.
+fn declaring_symbol(
+    response: lsp_types::DocumentSymbolResponse,
+    position: lsp_types::Position,
+) -> Option<(String, lsp_types::Range)> {
+    let contains = |range: &lsp_types::Range| range.start <= position && position <= range.end;
+    match response {
+        lsp_types::DocumentSymbolResponse::Nested(mut level) => loop {
+            let symbol = level.into_iter().find(|symbol| contains(&symbol.range))?;
+            if contains(&symbol.selection_range) {
+                return Some((symbol.name, symbol.range));
+            }
+            level = symbol.children?;
+        },
+        // Flat symbols carry only the whole range; take the tightest.
+        #[allow(deprecated)]
+        lsp_types::DocumentSymbolResponse::Flat(symbols) => symbols
+            .into_iter()
+            .filter(|symbol| contains(&symbol.location.range))
+            .min_by_key(|symbol| {
+                let range = symbol.location.range;
+                (
+                    range.end.line - range.start.line,
+                    std::cmp::Reverse(range.start),
+                )
+            })
+            .map(|symbol| (symbol.name, symbol.location.range)),
+    }
+}
+
+/// Whether `location` starts inside `range` of `file_path`.
+fn location_within(location: &LocationRecord, file_path: &str, range: &lsp_types::Range) -> bool {
+    let start = lsp_types::Position::new(location.line - 1, location.column - 1);
+    location.file_path == file_path && range.start <= start && start <= range.end
+}
+
+/// Rewrite `location` to use the canonical form of its path (symlinks
+/// resolved), caching lookups in `canonical`. Paths that cannot be resolved
+/// are left alone.
+fn canonicalize_location(location: &mut LocationRecord, canonical: &mut HashMap<String, String>) {
+    let path = canonical
+        .entry(location.file_path.clone())
+        .or_insert_with(|| {
+            std::fs::canonicalize(&location.file_path).map_or_else(
+                |_| location.file_path.clone(),
+                |path| path.display().to_string(),
+            )
+        })
+        .clone();
+    if path == location.file_path {
+        return;
+    }
+    if let Ok(uri) = file_uri(&path) {
+        location.uri = uri.to_string();
+    }
+    location.display = format!("{path}:{}:{}", location.line, location.column);
+    location.file_path = path;
+}
+
+/// Canonicalize paths, sort by file and position, and drop duplicates.
+///
+/// rust-analyzer returns locations in no stable order and repeats sites
+/// reached through macro expansion; normalizing keeps repeated queries
+/// byte-for-byte identical.
+fn normalize_locations(mut locations: Vec<LocationRecord>) -> Vec<LocationRecord> {
+    let mut canonical = HashMap::new();
+    for location in &mut locations {
+        canonicalize_location(location, &mut canonical);
+    }
+    locations.sort_by(|a, b| location_key(a).cmp(&location_key(b)));
+    locations.dedup_by(|a, b| location_key(a) == location_key(b));
+    locations
+}
+
+/// Canonicalize symbol paths and drop exact duplicates, keeping
+/// rust-analyzer's relevance order.
+fn normalize_symbols(mut symbols: Vec<WorkspaceSymbolRecord>) -> Vec<WorkspaceSymbolRecord> {
+    let mut canonical = HashMap::new();
+    for symbol in &mut symbols {
+        canonicalize_location(&mut symbol.location, &mut canonical);
+    }
+    let mut seen = HashSet::new();
+    symbols.retain(|symbol| {
+        seen.insert((
+            symbol.name.clone(),
+            symbol.kind.clone(),
+            symbol.location.display.clone(),
+            symbol.location.end_line,
+            symbol.location.end_column,
+        ))
+    });
+    symbols
+}
+
+/// Fill in `snippet` for each location with its line plus `context_lines`
+/// lines on either side, reading each file once. Locations in unreadable
+/// files are left without one.
+fn attach_snippets(locations: &mut [LocationRecord], context_lines: Option<u32>) {
+    let Some(context_lines) = context_lines else {
+        return;
+    };
+    let context_lines = context_lines.min(MAX_CONTEXT_LINES);
+    let mut files: HashMap<String, Option<Vec<String>>> = HashMap::new();
+    for location in locations {
+        let Some(lines) = files.entry(location.file_path.clone()).or_insert_with(|| {
+            std::fs::read_to_string(&location.file_path)
+                .ok()
+                .map(|source| source.lines().map(String::from).collect())
+        }) else {
+            continue;
+        };
+        location.snippet = source_snippet(lines, location.line, context_lines);
+    }
+}
+
+/// The lines around one-based `line`, clamped to the file.
+fn source_snippet(lines: &[String], line: u32, context_lines: u32) -> Option<SourceSnippet> {
+    let target = usize::try_from(line).ok()?.checked_sub(1)?;
+    if target >= lines.len() {
+        return None;
+    }
+    let context = usize::try_from(context_lines).ok()?;
+    let first = target.saturating_sub(context);
+    let last = (target + context).min(lines.len() - 1);
+    Some(SourceSnippet {
+        start_line: u32::try_from(first + 1).ok()?,
+        lines: lines[first..=last].to_vec(),
+    })
+}
+
+fn diagnostic_code(code: &lsp_types::NumberOrString) -> String {
+    match code {
+        lsp_types::NumberOrString::String(value) => value.clone(),
+        lsp_types::NumberOrString::Number(value) => value.to_string(),
+    }
+}
+
+fn diagnostic_record(
+    uri: &lsp_types::Uri,
+    index: usize,
+    diagnostic: lsp_types::Diagnostic,
+) -> DiagnosticRecord {
+    DiagnosticRecord {
+        index,
+        severity: diagnostic_severity_name(diagnostic.severity).to_string(),
+        message: diagnostic.message,
+        code: diagnostic.code.as_ref().map(diagnostic_code),
+        source: diagnostic.source,
+        location: location_record(uri, &diagnostic.range),
+        related: diagnostic
+            .related_information
+            .unwrap_or_default()
+            .into_iter()
+            .map(|info| RelatedRecord {
+                message: info.message,
+                location: location_record(&info.location.uri, &info.location.range),
+            })
+            .collect(),
+        tags: diagnostic
+            .tags
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tag| match tag {
+                lsp_types::DiagnosticTag::UNNECESSARY => "unnecessary".to_string(),
+                lsp_types::DiagnosticTag::DEPRECATED => "deprecated".to_string(),
+                other => format!("{other:?}"),
+            })
+            .collect(),
+    }
+}
+
+const fn range_record(range: &lsp_types::Range) -> RangeRecord {
+    RangeRecord {
+        start: PositionRecord {
+            line: range.start.line + 1,
+            character: range.start.character + 1,
+        },
+        end: PositionRecord {
+            line: range.end.line + 1,
+            character: range.end.character + 1,
+        },
+    }
+}
+
+/// A connected MCP session. Dropping the last handle (when the session's
+/// service shuts down) removes it from the telemetry session table.
+struct SessionHandle {
+    id: String,
+    telemetry: TelemetryState,
+}
+
+impl Drop for SessionHandle {
+    fn drop(&mut self) {
+        self.telemetry.close_session(&self.id);
+    }
+}
+
+/// MCP server providing rust-analyzer tools via lspmux.
+///
+/// One instance is created per process and shared by every MCP session;
+/// [`Self::for_session`] derives the per-session copy that transports serve.
+#[derive(Clone)]
+pub struct RustAnalyzerTools {
+    /// The launcher of the workspace this call queries.
+    lsp: Arc<LspLauncher>,
+    /// Launchers for every workspace a call may name.
+    pool: Arc<LspPool>,
+    telemetry: TelemetryState,
+    session: Option<Arc<SessionHandle>>,
+    /// Tools turned off in the config file.
+    disabled: Arc<HashSet<String>>,
+    /// How many calls may run at once, shared by every session.
+    limits: CallLimits,
+    pages: ResultPages,
+    /// Hover, definition, and reference responses for unchanged files.
+    cache: ResponseCache,
+    /// `cargo metadata` until a manifest or the lockfile changes.
+    metadata: MetadataCache,
+    /// Every symbol in the workspace, for `rust_symbol_search`.
+    symbols: SymbolIndex,
+    /// `rustc --explain` texts fetched so far.
+    explanations: ExplainCache,
+    /// Named diagnostics snapshots, for `rust_diagnostics_diff`.
+    snapshots: DiagnosticSnapshots,
+    /// Restarts hung clients; its warnings are forwarded to every session.
+    watchdog: Option<Watchdog>,
+    /// How long queries are held while rust-analyzer loads the workspace.
+    index_wait: IndexWait,
+    tool_router: ToolRouter<Self>,
+}
+
+#[tool_router]
+impl RustAnalyzerTools {
+    /// Create a new tools instance; the launchers in `pool` start
+    /// rust-analyzer on first use.
+    pub fn new(pool: Arc<LspPool>, telemetry: TelemetryState) -> Self {
+        Self {
+            lsp: Arc::clone(pool.default_launcher()),
+            pool,
+            telemetry,
+            session: None,
+            disabled: Arc::default(),
+            limits: CallLimits::default(),
+            pages: ResultPages::default(),
+            cache: ResponseCache::default(),
+            metadata: MetadataCache::default(),
+            symbols: SymbolIndex::default(),
+            explanations: ExplainCache::default(),
+            snapshots: DiagnosticSnapshots::default(),
+            watchdog: None,
+            index_wait: IndexWait::default(),
+            tool_router: Self::tool_router(),
+        }
+    }
+
+    /// Hide `names` from `tools/list` and reject calls to them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the valid tools if any name is unknown.
+    pub fn with_disabled_tools(mut self, names: &[String]) -> anyhow::Result<Self> {
+        self.check_tool_names(names, "disabled")
+            .map_err(anyhow::Error::msg)?;
+        self.disabled = Arc::new(names.iter().cloned().collect());
+        Ok(self)
+    }
+
+    /// Queue calls beyond `limits` instead of running them at once.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error naming the valid tools if a limited tool is unknown.
+    pub fn with_call_limits(mut self, limits: CallLimits) -> anyhow::Result<Self> {
+        let names = limits
+            .limited_tools()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        self.check_tool_names(&names, "limits")
+            .map_err(anyhow::Error::msg)?;
+        self.limits = limits;
+        Ok(self)
+    }
+
+    /// Cut paginated results to `budget` bytes of items per page.
+    #[must_use]
+    pub fn with_result_budget(mut self, budget: usize) -> Self {
+        self.pages = ResultPages::with_budget(budget);
+        self
+    }
+
+    /// Hold queries for at most `limit` while rust-analyzer loads the
+    /// workspace.
+    #[must_use]
+    pub fn with_index_wait(mut self, limit: Duration) -> Self {
+        self.index_wait = IndexWait::new(limit);
+        self
+    }
+
+    /// Forward the warnings of `watchdog` to every session.
+    #[must_use]
+    pub fn with_watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    fn check_tool_names(&self, names: &[String], key: &str) -> Result<(), String> {
+        let known = self
+            .tool_router
+            .list_all()
+            .into_iter()
+            .map(|tool| tool.name.into_owned())
+            .collect::<Vec<_>>();
+        if let Some(unknown) = names.iter().find(|name| !known.contains(name)) {
+            return Err(format!(
+                "unknown tool {unknown:?} in `{key}`; known tools: {}",
+                known.join(", ")
+            ));
+        }
+        Ok(())
+    }
+
+    /// Apply the `.lspmux-cc.toml` governing this call: the nearest one above
+    /// the queried `file_path`, or above the workspace root for tools without one.
+    ///
+    /// Its rust-analyzer settings replace those of any previously applied
+    /// project, and its `[tools]` section may reject the call.
+    fn apply_project_config(
+        &self,
+        tool_name: &str,
+        arguments: Option<&JsonObject>,
+    ) -> Result<(), McpError> {
+        let start = match arguments
+            .and_then(|arguments| arguments.get("file_path"))
+            .and_then(serde_json::Value::as_str)
+        {
+            Some(file_path) => PathBuf::from(file_path),
+            None => match self.lsp.workspace_root() {
+                Some(root) => PathBuf::from(root),
+                None => return Ok(()),
+            },
+        };
+        let project = ProjectConfig::discover(&start)
+            .map_err(|e| McpError::invalid_params(format!("{e:#}"), None))?;
+        self.push_project_settings(project.as_ref())?;
+
+        let Some((path, config)) = project else {
+            return Ok(());
+        };
+        self.check_tool_names(&config.tools.disabled, "disabled")
+            .map_err(|e| McpError::invalid_params(format!("{}: {e}", path.display()), None))?;
+        if config.tools.disabled.iter().any(|name| name == tool_name) {
+            return Err(McpError::invalid_params(
+                format!("{tool_name} is disabled by {}", path.display()),
+                None,
+            ));
+        }
+        Ok(())
+    }
+
+    /// Send rust-analyzer the settings of `project`, and of a project file
+    /// linked with `rust_link_project`, if they differ from what it has.
+    fn push_project_settings(
+        &self,
+        project: Option<&(PathBuf, ProjectConfig)>,
+    ) -> Result<(), McpError> {
+        // Before startup there is nothing to update; the first call that
+        // starts rust-analyzer applies the settings.
+        let Some(client) = self.lsp.get() else {
+            return Ok(());
+        };
+        let mut overlay = project.and_then(|(_, config)| config.rust_analyzer_settings());
+        if let Some(linked) = self.lsp.linked_project_settings() {
+            merge_settings(
+                overlay.get_or_insert_with(|| serde_json::json!({})),
+                &linked,
+            );
+        }
+        let changed = client
+            .apply_project_settings(overlay.as_ref())
+            .map_err(|e| lsp_error(&e, format!("failed to update {SERVER_NAME} settings: {e}")))?;
+        if changed {
+            tracing::info!(
+                event = "project_settings_applied",
+                config = ?project.map(|(path, _)| path)
+            );
+        }
+        Ok(())
+    }
+
+    /// The lspmux server's endpoint, or an error while none is in use.
+    async fn lspmux_endpoint(&self) -> Result<ConnectAddr, McpError> {
+        self.lsp.lspmux_endpoint().await.ok_or_else(|| {
+            McpError::invalid_params(
+                "no lspmux server is in use: the managed one is not started yet, or \
+                 rust-analyzer runs unshared",
+                None,
+            )
+        })
+    }
+
+    /// `instance` as of `now` (seconds since the epoch), as tools report it.
+    fn instance_record(
+        &self,
+        instance: &LspmuxInstance,
+        now: u64,
+        uptime: Option<Duration>,
+    ) -> LspmuxInstanceRecord {
+        LspmuxInstanceRecord {
+            pid: instance.pid,
+            server: instance.server.clone(),
+            workspace_root: instance.path.clone(),
+            client_ports: instance.clients.iter().map(|client| client.port).collect(),
+            shared: instance.clients.len() > 1,
+            this_workspace: self
+                .lsp
+                .workspace_root()
+                .is_some_and(|root| Path::new(root) == Path::new(&instance.path)),
+            uptime_secs: uptime.map(|uptime| uptime.as_secs()),
+            idle_secs: instance.idle_secs(now),
+        }
+    }
+
+    /// Start rust-analyzer unless `tool_name` reports on it or starts it
+    /// itself, then apply the project config for the call.
+    async fn prepare_call(
+        &self,
+        tool_name: &str,
+        arguments: Option<&JsonObject>,
+    ) -> Result<(), McpError> {
+        if !NO_AUTOSTART_TOOLS.contains(&tool_name) {
+            self.client().await?;
+        }
+        self.apply_project_config(tool_name, arguments)
+    }
+
+    /// A copy of these tools bound to a newly opened MCP session over `transport`.
+    ///
+    /// The LSP client, its document cache, and telemetry are shared; only the
+    /// session identity (and its request accounting) is new.
+    #[must_use]
+    pub fn for_session(&self, transport: &str) -> Self {
+        let id = self.telemetry.open_session(transport);
+        Self {
+            session: Some(Arc::new(SessionHandle {
+                id,
+                telemetry: self.telemetry.clone(),
+            })),
+            ..self.clone()
+        }
+    }
+
+    fn session_id(&self) -> Option<&str> {
+        self.session.as_ref().map(|session| session.id.as_str())
+    }
+
+    /// Run the cargo command of the runnable a `rust-analyzer.runSingle`
+    /// command carries.
+    async fn run_runnable(
+        &self,
+        argument: Option<&serde_json::Value>,
+        timeout_secs: Option<u64>,
+    ) -> Result<CommandRunRecord, McpError> {
+        let runnable: Runnable = argument
+            .cloned()
+            .ok_or_else(|| McpError::invalid_params("the command has no runnable argument", None))
+            .and_then(|argument| {
+                serde_json::from_value(argument).map_err(|e| {
+                    McpError::invalid_params(format!("the argument is not a runnable: {e}"), None)
+                })
+            })?;
+        let runnable_args = runnable.cargo_args().ok_or_else(|| {
+            McpError::invalid_params(
+                format!("`{}` is not a cargo runnable", runnable.label),
+                None,
+            )
+        })?;
+        let cwd = match runnable_args.cwd.or(runnable_args.workspace_root) {
+            Some(cwd) => PathBuf::from(cwd),
+            None => self.cargo_root()?,
+        };
+        let mut args = runnable_args.cargo_args;
+        if !runnable_args.executable_args.is_empty() {
+            args.push("--".to_string());
+            args.extend(runnable_args.executable_args);
+        }
+        let timeout = Duration::from_secs(timeout_secs.unwrap_or(DEFAULT_CARGO_TIMEOUT_SECS));
+        let output = cargo::run(&cwd, &args, timeout).await?;
+        Ok(CommandRunRecord {
+            cwd: cwd.display().to_string(),
+            command: format!("cargo {}", args.join(" ")),
+            success: output.success,
+            output: output_tail(format!("{}{}", output.stderr, output.stdout)),
+        })
+    }
+
+    /// The workspace root cargo runs in.
+    fn cargo_root(&self) -> Result<PathBuf, McpError> {
+        self.lsp.workspace_root().map(PathBuf::from).ok_or_else(|| {
+            McpError::invalid_params(
+                "no workspace root is set; pass --workspace-root or set WORKSPACE_ROOT",
+                None,
+            )
+        })
+    }
+
+    /// The cargo command of the innermost test runnable around `line`.
+    async fn test_runnable_at(
+        &self,
+        file_path: &str,
+        line: u32,
+    ) -> Result<CargoRunnableArgs, McpError> {
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let runnables = lsp
+            .runnables(file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("runnables request failed: {e}")))?;
+        let tests: Vec<(Runnable, CargoRunnableArgs)> = runnables
+            .into_iter()
+            .filter_map(|runnable| {
+                let args = runnable.cargo_args()?;
+                (args.cargo_args.first().map(String::as_str) == Some("test"))
+                    .then_some((runnable, args))
+            })
+            .collect();
+        tests
+            .iter()
+            .filter_map(|(runnable, args)| {
+                let range = runnable.location.as_ref()?.target_range;
+                (range.start.line <= line && line <= range.end.line)
+                    .then_some((range.end.line - range.start.line, args))
+            })
+            .min_by_key(|(lines, _)| *lines)
+            .map(|(_, args)| args.clone())
+            .ok_or_else(|| {
+                let labels: Vec<&str> = tests
+                    .iter()
+                    .map(|(runnable, _)| runnable.label.as_str())
+                    .collect();
+                McpError::invalid_params(
+                    if labels.is_empty() {
+                        format!("{file_path} has no tests")
+                    } else {
+                        format!(
+                            "no test around line {line} of {file_path}; its tests: {}",
+                            labels.join(", ")
+                        )
+                    },
+                    None,
+                )
+            })
+    }
+
+    /// Send a flycheck `action` with `send`, if rust-analyzer is running.
+    fn flycheck_control(
+        &self,
+        action: &str,
+        send: fn(&LspClient) -> anyhow::Result<()>,
+        done: &str,
+    ) -> Result<Structured<FlycheckResponse>, McpError> {
+        let summary = match self.lsp.get() {
+            None => format!("Nothing to {action}: {SERVER_NAME} is not running."),
+            Some(lsp) => {
+                send(&lsp)
+                    .map_err(|e| lsp_error(&e, format!("failed to {action} checks: {e:#}")))?;
+                done.to_string()
+            }
+        };
+        Ok(Structured(FlycheckResponse {
+            action: action.to_string(),
+            file_path: None,
+            outcome: None,
+            waited_ms: 0,
+            summary,
+        }))
+    }
+
+    /// The LSP client, starting lspmux and rust-analyzer on first use.
+    async fn client(&self) -> Result<Arc<LspClient>, McpError> {
+        self.lsp
+            .client()
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to start {SERVER_NAME}: {e:#}")))
+    }
+
+    /// Get diagnostics (errors and warnings) for a Rust file.
+    #[tool(
+        name = "rust_diagnostics",
+        output_schema = output_schema::<DiagnosticsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Get Rust compiler errors and warnings for a file. Narrow the result with `min_severity` (e.g. `error` for errors only) and `codes` (e.g. [\"E0308\"]). Returns structured diagnostics with one-based locations, error code, source (rustc, clippy, or rust-analyzer), tags, and related spans such as `expected due to this`. Large results are paginated: pass `limit`, then `cursor` from `next_cursor`."
+    )]
+    async fn diagnostics(
+        &self,
+        params: Parameters<DiagnosticsParam>,
+    ) -> Result<Structured<DiagnosticsResponse>, McpError> {
+        let file = &params.0.file.file_path;
+        let filter = &params.0.filter;
+        let page = self
+            .pages
+            .page(
+                PageQuery::new("rust_diagnostics", &params.0),
+                &params.0.page,
+                async {
+                    let mut diagnostics = self.collect_diagnostics(file).await?;
+                    diagnostics.retain(|diagnostic| filter.matches(diagnostic));
+                    Ok(diagnostics)
+                },
+            )
+            .await?;
+
+        let diagnostic_count = page.items.len();
+        let summary = if page.total == 0 {
+            format!("No diagnostics found for {file}{}.", filter.note())
+        } else {
+            format!(
+                "Found {} diagnostic(s) for {file}{}.{}",
+                page.total,
+                filter.note(),
+                page.window_note()
+            )
+        };
+        let explanations = if params.0.explain {
+            self.explain_codes(&page.items).await
+        } else {
+            Vec::new()
+        };
+
+        Ok(Structured(DiagnosticsResponse {
+            file_path: file.clone(),
+            diagnostic_count,
+            total_count: page.total,
+            diagnostics: page.items,
+            next_cursor: page.next_cursor,
+            explanations,
+            summary,
+        }))
+    }
+
+    /// Get diagnostics for every Rust file under a directory or glob.
+    #[tool(
+        name = "rust_diagnostics_dir",
+        output_schema = output_schema::<DirDiagnosticsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Get Rust compiler errors and warnings for every .rs file under a directory (recursive, skipping target/) or matching a glob like `/repo/crates/*/src/**/*.rs`. Returns per-file groups with error/warning totals. Accepts the same `min_severity` and `codes` filters as rust_diagnostics; `max_files` caps the number of files checked (default 500)."
+    )]
+    async fn diagnostics_dir(
+        &self,
+        params: Parameters<DiagnosticsDirParam>,
+    ) -> Result<Structured<DirDiagnosticsResponse>, McpError> {
+        let DiagnosticsDirParam {
+            path,
+            filter,
+            max_files,
+        } = params.0;
+        let (files, truncated) = rust_files(&path, max_files.unwrap_or(DEFAULT_DIR_MAX_FILES))?;
+        let report = self
+            .diagnostics_report(path, "", files, truncated, &filter, None)
+            .await;
+        Ok(Structured(report))
+    }
+
+    /// Get diagnostics for every Rust file git reports as changed.
+    #[tool(
+        name = "rust_diagnostics_changed",
+        output_schema = output_schema::<DirDiagnosticsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Check work in progress: get Rust compiler errors and warnings for every .rs file that `git status` reports as modified, added, renamed, or untracked under `path` (default: the workspace root). Returns per-file groups with error/warning totals, like rust_diagnostics_dir. Accepts the same `min_severity` and `codes` filters; `max_files` caps the number of files checked (default 500). With `new_only: true`, reports only the diagnostics the changes introduced: those on lines that differ from `baseline` (a git revision, default HEAD; files changed since it are checked too), or, with `baseline: \"snapshot:<name>\"`, those missing from that rust_diagnostics_snapshot. Pre-existing warnings in touched files are then left out."
+    )]
+    async fn diagnostics_changed(
+        &self,
+        params: Parameters<DiagnosticsChangedParam>,
+    ) -> Result<Structured<DirDiagnosticsResponse>, McpError> {
+        let DiagnosticsChangedParam {
+            path,
+            filter,
+            max_files,
+            new_only,
+            baseline,
+        } = params.0;
+        let dir = match path {
+            Some(path) if Path::new(&path).is_absolute() => PathBuf::from(path),
+            Some(path) => {
+                return Err(McpError::invalid_params(
+                    format!("path must be absolute, got: {path}"),
+                    None,
+                ))
+            }
+            None => self.cargo_root()?,
+        };
+        let tree = git::WorkingTree::open(&dir).await?;
+        let baseline = if new_only || baseline.is_some() {
+            Some(self.diff_baseline(&tree, baseline).await?)
+        } else {
+            None
+        };
+        let since = match &baseline {
+            Some(Baseline::Revision(revision, _)) => Some(revision.as_str()),
+            _ => None,
+        };
+        let mut files = tree.changed_rust_files(since).await?;
+        let max_files = max_files.unwrap_or(DEFAULT_DIR_MAX_FILES);
+        let truncated = files.len() > max_files;
+        files.truncate(max_files);
+        let path = dir.display().to_string();
+        if files.is_empty() {
+            return Ok(Structured(DirDiagnosticsResponse {
+                summary: format!("No modified or untracked .rs files under {path}."),
+                path,
+                files_checked: 0,
+                truncated: false,
+                error_count: 0,
+                warning_count: 0,
+                diagnostic_count: 0,
+                files: Vec::new(),
+                failures: Vec::new(),
+            }));
+        }
+        let report = self
+            .diagnostics_report(
+                path,
+                "changed ",
+                files,
+                truncated,
+                &filter,
+                baseline.as_ref(),
+            )
+            .await;
+        Ok(Structured(report))
+    }
+
+    /// The baseline named by `rust_diagnostics_changed`'s `baseline`: a
+    /// `snapshot:<name>`, or a git revision of `tree` (default `HEAD`).
+    async fn diff_baseline(
+        &self,
+        tree: &git::WorkingTree,
+        baseline: Option<String>,
+    ) -> Result<Baseline, McpError> {
+        let baseline = baseline.unwrap_or_else(|| "HEAD".to_string());
+        if let Some(name) = baseline.strip_prefix("snapshot:") {
+            return Ok(Baseline::Snapshot(name.to_string(), self.snapshot(name)?));
+        }
+        let touched = tree.touched_lines(&baseline).await?;
+        Ok(Baseline::Revision(baseline, touched))
+    }
+
+    /// The diagnostics of `files`, found under `path`, matching `filter`
+    /// and new since `baseline` if given, grouped per file. `truncated`
+    /// tells that more files matched; `kind` (such as `"changed "`)
+    /// qualifies the files in the summary.
+    async fn diagnostics_report(
+        &self,
+        path: String,
+        kind: &str,
+        files: Vec<PathBuf>,
+        truncated: bool,
+        filter: &DiagnosticFilter,
+        baseline: Option<&Baseline>,
+    ) -> DirDiagnosticsResponse {
+        let files_checked = files.len();
+        let results = self.sweep_diagnostics(files).await;
+
+        let mut groups = Vec::new();
+        let mut failures = Vec::new();
+        for (file_path, diagnostics) in results {
+            match diagnostics {
+                Ok(mut diagnostics) => {
+                    diagnostics.retain(|diagnostic| filter.matches(diagnostic));
+                    if let Some(baseline) = baseline {
+                        diagnostics = baseline.introduced(&file_path, diagnostics);
+                    }
+                    if diagnostics.is_empty() {
+                        continue;
+                    }
+                    groups.push(FileDiagnostics {
+                        error_count: severity_count(&diagnostics, "error"),
+                        warning_count: severity_count(&diagnostics, "warning"),
+                        file_path,
+                        diagnostics,
+                    });
+                }
+                Err(e) => failures.push(FileFailure {
+                    file_path,
+                    error: e.message.to_string(),
+                }),
+            }
+        }
+        groups.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+        failures.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+        let error_count = groups.iter().map(|file| file.error_count).sum();
+        let warning_count = groups.iter().map(|file| file.warning_count).sum();
+        let diagnostic_count = groups.iter().map(|file| file.diagnostics.len()).sum();
+        let mut summary = format!(
+            "Checked {files_checked} {kind}file(s) under {path}: {diagnostic_count} diagnostic(s){} \
+             ({error_count} error(s), {warning_count} warning(s)) in {} file(s).",
+            filter.note(),
+            groups.len()
+        );
+        if let Some(baseline) = baseline {
+            summary.push_str(&baseline.note());
+        }
+        if truncated {
+            summary
+                .push_str(" More files matched; raise max_files or narrow the path to check them.");
+        }
+        if !failures.is_empty() {
+            let _ = write!(summary, " {} file(s) could not be checked.", failures.len());
+        }
+
+        DirDiagnosticsResponse {
+            path,
+            files_checked,
+            truncated,
+            error_count,
+            warning_count,
+            diagnostic_count,
+            files: groups,
+            failures,
+            summary,
+        }
+    }
+
+    /// Record the workspace's diagnostics under a name, to diff against later.
+    #[tool(
+        name = "rust_diagnostics_snapshot",
+        output_schema = output_schema::<DiagnosticsSnapshotResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Record the current diagnostics of every .rs file under a directory or glob (default: the workspace root) as a named snapshot kept in the server (`name`, default `default`). Before a multi-step fix session, take a snapshot; afterwards, `rust_diagnostics_diff` reports what the edits fixed and what they broke. Accepts the `min_severity`, `codes`, and `max_files` options of rust_diagnostics_dir."
+    )]
+    async fn diagnostics_snapshot(
+        &self,
+        params: Parameters<DiagnosticsSnapshotParam>,
+    ) -> Result<Structured<DiagnosticsSnapshotResponse>, McpError> {
+        let DiagnosticsSnapshotParam {
+            name,
+            path,
+            filter,
+            max_files,
+        } = params.0;
+        let name = name.unwrap_or_else(|| "default".to_string());
+        let path = match path {
+            Some(path) => path,
+            None => self.cargo_root()?.display().to_string(),
+        };
+        let max_files = max_files.unwrap_or(DEFAULT_DIR_MAX_FILES);
+        let (diagnostics, files_checked, truncated, failures) =
+            self.snapshot_sweep(&path, max_files, &filter).await?;
+
+        let error_count = severity_count(&diagnostics, "error");
+        let warning_count = severity_count(&diagnostics, "warning");
+        let diagnostic_count = diagnostics.len();
+        let mut summary = format!(
+            "Snapshot `{name}` of {path}: {diagnostic_count} diagnostic(s){} ({error_count} \
+             error(s), {warning_count} warning(s)) in {files_checked} file(s). Compare with \
+             rust_diagnostics_diff.",
+            filter.note()
+        );
+        if truncated {
+            summary.push_str(
+                " More files matched than max_files; files beyond it are not in the snapshot.",
+            );
+        }
+        if !failures.is_empty() {
+            let _ = write!(summary, " {} file(s) could not be checked.", failures.len());
+        }
+        self.snapshots.insert(
+            &name,
+            Snapshot {
+                path: path.clone(),
+                max_files,
+                filter,
+                taken_at: Instant::now(),
+                diagnostics,
+            },
+        );
+
+        Ok(Structured(DiagnosticsSnapshotResponse {
+            name,
+            path,
+            files_checked,
+            truncated,
+            error_count,
+            warning_count,
+            diagnostic_count,
+            failures,
+            summary,
+        }))
+    }
+
+    /// Compare the workspace's diagnostics with a snapshot.
+    #[tool(
+        name = "rust_diagnostics_diff",
+        output_schema = output_schema::<DiagnosticsDiffResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Compare the current diagnostics with a snapshot taken by rust_diagnostics_snapshot (`name`, default `default`), over the same files and filters: lists the diagnostics fixed since then and those introduced, with error and warning totals before and after. Diagnostics are matched by file, severity, code, and message, so code that merely moved is not reported. Pass `update: true` to make the current state the new snapshot."
+    )]
+    async fn diagnostics_diff(
+        &self,
+        params: Parameters<DiagnosticsDiffParam>,
+    ) -> Result<Structured<DiagnosticsDiffResponse>, McpError> {
+        let name = params.0.name.unwrap_or_else(|| "default".to_string());
+        let snapshot = self.snapshot(&name)?;
+        let (diagnostics, files_checked, _, failures) = self
+            .snapshot_sweep(&snapshot.path, snapshot.max_files, &snapshot.filter)
+            .await?;
+        let diff = snapshots::diff(&snapshot.diagnostics, &diagnostics);
+
+        let errors_before = severity_count(&snapshot.diagnostics, "error");
+        let errors_after = severity_count(&diagnostics, "error");
+        let warnings_before = severity_count(&snapshot.diagnostics, "warning");
+        let warnings_after = severity_count(&diagnostics, "warning");
+        let snapshot_age_secs = snapshot.taken_at.elapsed().as_secs();
+        let mut summary = format!(
+            "Since snapshot `{name}` of {} ({snapshot_age_secs}s ago): {} fixed, {} introduced, \
+             {} unchanged. Errors {errors_before} -> {errors_after}, warnings \
+             {warnings_before} -> {warnings_after}.",
+            snapshot.path,
+            diff.fixed.len(),
+            diff.introduced.len(),
+            diff.unchanged
+        );
+        if !failures.is_empty() {
+            let _ = write!(
+                summary,
+                " {} file(s) could not be checked; their diagnostics count as fixed.",
+                failures.len()
+            );
+        }
+        let updated = params.0.update;
+        if updated {
+            summary.push_str(" The snapshot now holds the current diagnostics.");
+            self.snapshots.insert(
+                &name,
+                Snapshot {
+                    taken_at: Instant::now(),
+                    diagnostics,
+                    ..snapshot.clone()
+                },
+            );
+        }
+
+        Ok(Structured(DiagnosticsDiffResponse {
+            name,
+            path: snapshot.path,
+            snapshot_age_secs,
+            files_checked,
+            errors_before,
+            errors_after,
+            warnings_before,
+            warnings_after,
+            fixed: diff.fixed,
+            introduced: diff.introduced,
+            unchanged_count: diff.unchanged,
+            failures,
+            updated,
+            summary,
+        }))
+    }
+
+    /// Save a file and return its diagnostics once the check that follows
+    /// has finished.
+    #[tool(
+        name = "rust_wait_for_diagnostics",
+        output_schema = output_schema::<WaitForDiagnosticsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Call after editing a file to get its post-edit diagnostics without sleeping and retrying. Tells rust-analyzer the file was saved, waits for the `cargo check` that follows to finish (at most `timeout_secs`, default 60), then returns the file's diagnostics as rust_diagnostics does, with the same `min_severity` and `codes` filters. `outcome` is `checked`, `published`, `no_check` (no check started, so check-on-save is likely off and the diagnostics are rust-analyzer's own), or `timed_out` (the diagnostics may be stale)."
+    )]
+    async fn wait_for_diagnostics(
+        &self,
+        params: Parameters<WaitForDiagnosticsParam>,
+    ) -> Result<Structured<WaitForDiagnosticsResponse>, McpError> {
+        let WaitForDiagnosticsParam {
+            file: FileParam { file_path },
+            filter,
+            timeout_secs,
+        } = params.0;
+        validate_file_path(&file_path)?;
+        let timeout = Duration::from_secs(
+            timeout_secs
+                .unwrap_or(DEFAULT_CHECK_WAIT_SECS)
+                .min(MAX_CHECK_WAIT_SECS),
+        );
+
+        let lsp = self.client().await?;
+        let started = Instant::now();
+        let outcome = flycheck::save_and_wait(&lsp, &file_path, timeout)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to save {file_path}: {e:#}")))?;
+        let mut diagnostics = self.collect_diagnostics(&file_path).await?;
+        diagnostics.retain(|diagnostic| filter.matches(diagnostic));
+        let waited_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+
+        let found = if diagnostics.is_empty() {
+            format!("No diagnostics for {file_path}{}.", filter.note())
+        } else {
+            format!(
+                "Found {} diagnostic(s) for {file_path}{}.",
+                diagnostics.len(),
+                filter.note()
+            )
+        };
+        let cycle = match outcome {
+            CycleOutcome::Checked => "after the check finished".to_string(),
+            CycleOutcome::Published => "after new diagnostics were published".to_string(),
+            CycleOutcome::NoCheck => "no check started, so check-on-save may be off; these \
+                                      are rust-analyzer's own diagnostics"
+                .to_string(),
+            CycleOutcome::TimedOut => format!(
+                "the check had not finished after {}s, so these may be stale",
+                timeout.as_secs()
+            ),
+        };
+        let summary = format!("{found} Waited {waited_ms}ms: {cycle}.");
+
+        Ok(Structured(WaitForDiagnosticsResponse {
+            file_path,
+            outcome: outcome.as_str().to_string(),
+            waited_ms,
+            diagnostic_count: diagnostics.len(),
+            diagnostics,
+            summary,
+        }))
+    }
+
+    /// Start rust-analyzer's `cargo check`.
+    #[tool(
+        name = "rust_run_flycheck",
+        output_schema = output_schema::<FlycheckResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Force a fresh `cargo check` (rust-analyzer's flycheck) of the workspace containing `file_path`, or of every workspace, and wait for it to finish (at most `timeout_secs`, default 60; 0 to return at once). Use when check-on-save is off or the cargo diagnostics look stale; read the results with rust_diagnostics. `outcome` is as for rust_wait_for_diagnostics."
+    )]
+    async fn run_flycheck(
+        &self,
+        params: Parameters<RunFlycheckParam>,
+    ) -> Result<Structured<FlycheckResponse>, McpError> {
+        let RunFlycheckParam {
+            file_path,
+            timeout_secs,
+        } = params.0;
+        let lsp = self.client().await?;
+        if let Some(file_path) = &file_path {
+            validate_file_path(file_path)?;
+            lsp.ensure_file_open(file_path).await.map_err(|e| {
+                lsp_error(&e, format!("failed to synchronize file with lspmux: {e}"))
+            })?;
+        }
+        let scope = file_path.as_ref().map_or_else(
+            || "every workspace".to_string(),
+            |file_path| format!("the workspace of {file_path}"),
+        );
+        let timeout = Duration::from_secs(
+            timeout_secs
+                .unwrap_or(DEFAULT_CHECK_WAIT_SECS)
+                .min(MAX_CHECK_WAIT_SECS),
+        );
+        let (outcome, waited_ms, summary) = if timeout.is_zero() {
+            lsp.run_flycheck(file_path.as_deref())
+                .map_err(|e| lsp_error(&e, format!("failed to start the check: {e:#}")))?;
+            (None, 0, format!("Started a check of {scope}."))
+        } else {
+            let started = Instant::now();
+            let outcome = flycheck::run_and_wait(&lsp, file_path.as_deref(), timeout)
+                .await
+                .map_err(|e| lsp_error(&e, format!("failed to start the check: {e:#}")))?;
+            let waited_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+            let result = match outcome {
+                CycleOutcome::Checked => "it finished".to_string(),
+                CycleOutcome::Published => {
+                    "no check was reported, but new diagnostics were published".to_string()
+                }
+                CycleOutcome::NoCheck => {
+                    "no check started; is `check.command` disabled?".to_string()
+                }
+                CycleOutcome::TimedOut => format!(
+                    "it had not finished after {}s; rust_cancel_flycheck stops it",
+                    timeout.as_secs()
+                ),
+            };
+            (
+                Some(outcome.as_str().to_string()),
+                waited_ms,
+                format!("Ran a check of {scope}: {result} ({waited_ms}ms)."),
+            )
+        };
+
+        Ok(Structured(FlycheckResponse {
+            action: "run".to_string(),
+            file_path,
+            outcome,
+            waited_ms,
+            summary,
+        }))
+    }
+
+    /// Stop rust-analyzer's running `cargo check`.
+    #[tool(
+        name = "rust_cancel_flycheck",
+        output_schema = output_schema::<FlycheckResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Stop every `cargo check` rust-analyzer is running, e.g. a slow check eating CPU during an interactive session. Its diagnostics so far are kept. Does not start rust-analyzer."
+    )]
+    async fn cancel_flycheck(
+        &self,
+        _params: Parameters<NoParams>,
+    ) -> Result<Structured<FlycheckResponse>, McpError> {
+        self.flycheck_control(
+            "cancel",
+            LspClient::cancel_flycheck,
+            "Cancelled every running check.",
+        )
+    }
+
+    /// Drop the diagnostics of rust-analyzer's `cargo check`.
+    #[tool(
+        name = "rust_clear_flycheck",
+        output_schema = output_schema::<FlycheckResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Clear the diagnostics rust-analyzer's `cargo check` published, leaving only rust-analyzer's own until the next check; useful when they are stale after large edits. Does not start rust-analyzer."
+    )]
+    async fn clear_flycheck(
+        &self,
+        _params: Parameters<NoParams>,
+    ) -> Result<Structured<FlycheckResponse>, McpError> {
+        self.flycheck_control(
+            "clear",
+            LspClient::clear_flycheck,
+            "Cleared the diagnostics of every check.",
+        )
+    }
+
+    /// Get type information and documentation at a position.
+    #[tool(
+        name = "rust_hover",
+        output_schema = output_schema::<HoverResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Get type signature and documentation for a symbol at a specific position in a Rust file, given as zero-based `line` and `character` or by `symbol` name (e.g. `LspClient::request`, `fn ensure_file_open`; `file_path` is then optional). Use `sections: \"signature\"` to skip the docs, `format: \"plaintext\"` to strip markdown, and `max_chars` to cap the length of large generic hovers."
+    )]
+    async fn hover(
+        &self,
+        params: Parameters<HoverParam>,
+    ) -> Result<Structured<HoverResponse>, McpError> {
+        let p = &self.resolve_position(&params.0.position).await?;
+
+        let lsp = self.client().await?;
+        let content_hash = lsp
+            .ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+
+        let requested_position = PositionRecord {
+            line: p.line,
+            character: p.character,
+        };
+        let key = CacheKey::new(
+            "textDocument/hover",
+            &p.file_path,
+            content_hash,
+            format!("{}:{}", p.line, p.character),
+        );
+        let hover = self
+            .cache
+            .get_or_fetch(lsp.generation(), key, async {
+                lsp.hover(&p.file_path, p.line, p.character)
+                    .await
+                    .map_err(|e| lsp_error(&e, format!("hover request failed: {e}")))
+            })
+            .await?;
+
+        match hover {
+            Some(hover) => {
+                let (contents, truncated) =
+                    shape_hover(&markup_to_text(hover.contents), &params.0.options);
+                Ok(Structured(HoverResponse {
+                    file_path: p.file_path.clone(),
+                    requested_position,
+                    found: true,
+                    truncated,
+                    range: hover.range.as_ref().map(range_record),
+                    summary: format!("Hover information found for {}.", p.file_path),
+                    contents,
+                }))
+            }
+            None => Ok(Structured(HoverResponse {
+                file_path: p.file_path.clone(),
+                requested_position,
+                found: false,
+                contents: String::new(),
+                truncated: false,
+                range: None,
+                summary: "No hover information available at this position.".to_string(),
+            })),
+        }
+    }
+
+    /// Find the definition of a symbol.
+    #[tool(
+        name = "rust_goto_definition",
+        output_schema = output_schema::<LocationsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Find where a symbol is defined, given a position (`line` and `character`) or a `symbol` name. Returns one-based file locations for the definition. Pass `context_lines` to inline the surrounding source."
+    )]
+    async fn goto_definition(
+        &self,
+        params: Parameters<DefinitionParam>,
+    ) -> Result<Structured<LocationsResponse>, McpError> {
+        let p = &self.resolve_position(&params.0.position).await?;
+
+        let lsp = self.client().await?;
+        let content_hash = lsp
+            .ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+
+        let key = CacheKey::new(
+            "textDocument/definition",
+            &p.file_path,
+            content_hash,
+            format!("{}:{}", p.line, p.character),
+        );
+        let response = self
+            .cache
+            .get_or_fetch(lsp.generation(), key, async {
+                lsp.goto_definition(&p.file_path, p.line, p.character)
+                    .await
+                    .map_err(|e| lsp_error(&e, format!("go to definition failed: {e}")))
+            })
+            .await?;
+
+        let mut locations = match response {
+            Some(lsp_types::GotoDefinitionResponse::Scalar(location)) => {
+                vec![location_record(&location.uri, &location.range)]
+            }
+            Some(lsp_types::GotoDefinitionResponse::Array(locations)) => locations
+                .into_iter()
+                .map(|location| location_record(&location.uri, &location.range))
+                .collect(),
+            Some(lsp_types::GotoDefinitionResponse::Link(links)) => links
+                .into_iter()
+                .map(|link| location_record(&link.target_uri, &link.target_selection_range))
+                .collect(),
+            None => vec![],
+        };
+        locations = normalize_locations(locations);
+        attach_snippets(&mut locations, params.0.snippet.context_lines);
+
+        let found = !locations.is_empty();
+        let location_count = locations.len();
+        let summary = if found {
+            format!("Found {location_count} definition location(s).")
+        } else {
+            "No definition found at this position.".to_string()
+        };
+
+        Ok(Structured(LocationsResponse {
+            file_path: p.file_path.clone(),
+            requested_position: PositionRecord {
+                line: p.line,
+                character: p.character,
+            },
+            found,
+            location_count,
+            total_count: location_count,
+            locations,
+            files: Vec::new(),
+            next_cursor: None,
+            summary,
+        }))
+    }
+
+    /// Find all references to a symbol.
+    #[tool(
+        name = "rust_find_references",
+        output_schema = output_schema::<LocationsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Find all references to a symbol at a specific position, or named by `symbol` (e.g. `Config::load`). Returns one-based file locations; pass `context_lines` to inline the surrounding source. Use `group_by_file` for per-file groups with counts, `include_declaration: false` to omit the definition itself, and `max_results` to cap the total. Large results are paginated: pass `limit`, then `cursor` from `next_cursor`."
+    )]
+    async fn find_references(
+        &self,
+        params: Parameters<ReferencesParam>,
+    ) -> Result<Structured<LocationsResponse>, McpError> {
+        let params = params.0;
+        let p = &self.resolve_position(&params.position).await?;
+        let include_declaration = params.include_declaration.unwrap_or(true);
+        // Set only when this call computed the result set (not for a cursor).
+        let mut dropped = 0;
+        let mut page = self
+            .pages
+            .page(
+                PageQuery::new("rust_find_references", &params),
+                &params.page,
+                async {
+                    let mut references = self.collect_references(p, include_declaration).await?;
+                    if let Some(max_results) = params.max_results {
+                        dropped = references.len().saturating_sub(max_results);
+                        references.truncate(max_results);
+                    }
+                    Ok(references)
+                },
+            )
+            .await?;
+        attach_snippets(&mut page.items, params.snippet.context_lines);
+
+        let found = page.total > 0;
+        let location_count = page.items.len();
+        let summary = if found {
+            let mut summary = format!("Found {} reference(s)", page.total + dropped);
+            if location_count == page.total {
+                let file_count = page
+                    .items
+                    .iter()
+                    .map(|location| &location.file_path)
+                    .collect::<HashSet<_>>()
+                    .len();
+                let _ = write!(summary, " in {file_count} file(s)");
+            }
+            let _ = write!(summary, ".{}", page.window_note());
+            if dropped > 0 {
+                let _ = write!(
+                    summary,
+                    " Kept the first {} (max_results); {dropped} dropped.",
+                    page.total
+                );
+            }
+            summary
+        } else {
+            "No references found at this position.".to_string()
+        };
+        let (locations, files) = if params.group_by_file {
+            (Vec::new(), group_by_file(page.items))
+        } else {
+            (page.items, Vec::new())
+        };
+
+        Ok(Structured(LocationsResponse {
+            file_path: p.file_path.clone(),
+            requested_position: PositionRecord {
+                line: p.line,
+                character: p.character,
+            },
+            found,
+            location_count,
+            total_count: page.total,
+            locations,
+            files,
+            next_cursor: page.next_cursor,
+            summary,
+        }))
+    }
+
+    /// Check whether a symbol, or every symbol of a file, is referenced
+    /// from outside what deleting it would remove.
+    #[tool(
+        name = "rust_safe_delete_check",
+        output_schema = output_schema::<SafeDeleteResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Check whether deleting a symbol, or a whole file, would break other code. Point at the symbol by position or `symbol` (e.g. `Config::load`) to check it alone: references inside its own body, such as recursion, do not count. Pass only `file_path` to check every symbol the file declares against references from other files. Returns `safe` and the blocking references with one-based locations; pass `context_lines` to inline the surrounding source."
+    )]
+    async fn safe_delete_check(
+        &self,
+        params: Parameters<SafeDeleteParam>,
+    ) -> Result<Structured<SafeDeleteResponse>, McpError> {
+        let params = params.0;
+        let position = &params.position;
+        let whole_file = position.symbol.is_none()
+            && position.line.is_none()
+            && position.character.is_none()
+            && position.offset.is_none();
+        let (file_path, symbol, symbols_checked, mut blockers) = match &position.file_path {
+            Some(file_path) if whole_file => {
+                validate_file_path(file_path)?;
+                let (checked, blockers) = self.file_delete_blockers(file_path).await?;
+                (file_path.clone(), None, checked, blockers)
+            }
+            _ => {
+                let p = self.resolve_position(position).await?;
+                let (name, blockers) = self.symbol_delete_blockers(&p).await?;
+                (p.file_path, Some(name), 1, blockers)
+            }
+        };
+        blockers.sort_by(|a, b| {
+            location_key(&a.location)
+                .cmp(&location_key(&b.location))
+                .then_with(|| a.symbol.cmp(&b.symbol))
+        });
+        let mut locations = blockers
+            .iter()
+            .map(|blocker| blocker.location.clone())
+            .collect::<Vec<_>>();
+        attach_snippets(&mut locations, params.snippet.context_lines);
+        for (blocker, location) in blockers.iter_mut().zip(locations) {
+            blocker.location = location;
+        }
+
+        let file_count = blockers
+            .iter()
+            .map(|blocker| &blocker.location.file_path)
+            .collect::<HashSet<_>>()
+            .len();
+        let summary = match (&symbol, blockers.len()) {
+            (Some(name), 0) => {
+                format!("Safe to delete `{name}`: nothing outside it refers to it.")
+            }
+            (Some(name), count) => format!(
+                "Not safe to delete `{name}`: {count} reference(s) in {file_count} file(s) use it."
+            ),
+            (None, 0) => format!(
+                "Safe to delete {file_path}: no other file refers to its {symbols_checked} \
+                 symbol(s). Remove the `mod` declaration that includes it too."
+            ),
+            (None, count) => format!(
+                "Not safe to delete {file_path}: {count} reference(s) in {file_count} other \
+                 file(s) use its symbols."
+            ),
+        };
+        Ok(Structured(SafeDeleteResponse {
+            file_path,
+            symbol,
+            safe: blockers.is_empty(),
+            symbols_checked,
+            blocker_count: blockers.len(),
+            blockers,
+            summary,
+        }))
+    }
+
+    /// Search for symbols by name across the workspace.
+    #[tool(
+        name = "rust_workspace_symbol",
+        output_schema = output_schema::<WorkspaceSymbolsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Search for symbols by name across the entire workspace. Returns one-based locations and normalized symbol kinds. Large results are paginated: pass `limit`, then `cursor` from `next_cursor`."
+    )]
+    async fn workspace_symbol(
+        &self,
+        params: Parameters<WorkspaceSymbolParam>,
+    ) -> Result<Structured<WorkspaceSymbolsResponse>, McpError> {
+        let query = &params.0.query;
+        let page = self
+            .pages
+            .page(
+                PageQuery::new("rust_workspace_symbol", &params.0),
+                &params.0.page,
+                self.collect_workspace_symbols(query),
+            )
+            .await?;
+
+        let symbol_count = page.items.len();
+        let summary = if page.total == 0 {
+            format!("No symbols found matching {query:?}.")
+        } else {
+            format!(
+                "Found {} symbol(s) matching {query:?}.{}",
+                page.total,
+                page.window_note()
+            )
+        };
+
+        Ok(Structured(WorkspaceSymbolsResponse {
+            query: query.clone(),
+            symbol_count,
+            total_count: page.total,
+            symbols: page.items,
+            next_cursor: page.next_cursor,
+            summary,
+        }))
+    }
+
+    /// List a trait's implementors, or the traits a type implements.
+    #[tool(
+        name = "rust_trait_impls",
+        output_schema = output_schema::<TraitImplsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "For a trait, list every type implementing it; for a type, list every trait it implements (derived ones included) and its inherent impl blocks. Results are grouped by implementing type or trait name, each impl with its header (e.g. `impl<T> From<T> for Wrapper<T>`) and one-based location. Name the trait or type with `name` (e.g. `Display`), or point at it with `file_path`, `line`, and `character`."
+    )]
+    async fn trait_impls(
+        &self,
+        params: Parameters<TraitImplsParam>,
+    ) -> Result<Structured<TraitImplsResponse>, McpError> {
+        let p = &params.0;
+        let lsp = self.client().await?;
+        let (subject, file_path, line, character, is_trait) =
+            match (&p.name, &p.file_path, p.line, p.character) {
+                (_, Some(file_path), Some(line), Some(character)) => {
+                    validate_file_path(file_path)?;
+                    lsp.ensure_file_open(file_path).await.map_err(|e| {
+                        lsp_error(&e, format!("failed to synchronize file with lspmux: {e}"))
+                    })?;
+                    let hover = lsp
+                        .hover(file_path, line, character)
+                        .await
+                        .map_err(|e| lsp_error(&e, format!("hover request failed: {e}")))?
+                        .map(|hover| markup_to_text(hover.contents))
+                        .unwrap_or_default();
+                    let source = std::fs::read_to_string(file_path).map_err(|e| {
+                        McpError::invalid_params(format!("cannot read {file_path}: {e}"), None)
+                    })?;
+                    let subject = word_at(&source, line, character)
+                        .unwrap_or_default()
+                        .to_string();
+                    (
+                        subject,
+                        file_path.clone(),
+                        line,
+                        character,
+                        is_trait_hover(&hover),
+                    )
+                }
+                (Some(name), None, None, None) => {
+                    let mut candidates: Vec<WorkspaceSymbolRecord> = self
+                        .collect_workspace_symbols(name)
+                        .await?
+                        .into_iter()
+                        .filter(|symbol| {
+                            &symbol.name == name
+                                && matches!(symbol.kind.as_str(), "interface" | "struct" | "enum")
+                        })
+                        .collect();
+                    if candidates.len() > 1 {
+                        let listed: Vec<String> = candidates
+                            .iter()
+                            .map(|symbol| format!("{} {}", symbol.kind, symbol.location.display))
+                            .collect();
+                        return Err(McpError::invalid_params(
+                            format!(
+                                "several traits and types are named `{name}`: {}; pass the \
+                                 `file_path`, `line`, and `character` of the one you mean",
+                                listed.join(", ")
+                            ),
+                            None,
+                        ));
+                    }
+                    let symbol = candidates.pop().ok_or_else(|| {
+                        McpError::invalid_params(
+                            format!("no trait or type named `{name}` in the workspace"),
+                            None,
+                        )
+                    })?;
+                    lsp.ensure_file_open(&symbol.location.file_path)
+                        .await
+                        .map_err(|e| {
+                            lsp_error(&e, format!("failed to synchronize file with lspmux: {e}"))
+                        })?;
+                    (
+                        name.clone(),
+                        symbol.location.file_path,
+                        symbol.location.line.saturating_sub(1),
+                        symbol.location.column.saturating_sub(1),
+                        symbol.kind == "interface",
+                    )
+                }
+                _ => {
+                    return Err(McpError::invalid_params(
+                        "pass either `name`, or `file_path` with `line` and `character`",
+                        None,
+                    ))
+                }
+            };
+
+        let response = lsp
+            .implementations(&file_path, line, character)
+            .await
+            .map_err(|e| lsp_error(&e, format!("implementation request failed: {e}")))?;
+        let mut locations = match response {
+            Some(lsp_types::GotoDefinitionResponse::Scalar(location)) => {
+                vec![location_record(&location.uri, &location.range)]
+            }
+            Some(lsp_types::GotoDefinitionResponse::Array(locations)) => locations
+                .into_iter()
+                .map(|location| location_record(&location.uri, &location.range))
+                .collect(),
+            Some(lsp_types::GotoDefinitionResponse::Link(links)) => links
+                .into_iter()
+                .map(|link| location_record(&link.target_uri, &link.target_selection_range))
+                .collect(),
+            None => vec![],
+        };
+        locations = normalize_locations(locations);
+
+        let mut sources: HashMap<String, Option<String>> = HashMap::new();
+        let mut groups: BTreeMap<String, Vec<ImplRecord>> = BTreeMap::new();
+        let mut inherent = Vec::new();
+        for location in locations {
+            let source = sources
+                .entry(location.file_path.clone())
+                .or_insert_with(|| std::fs::read_to_string(&location.file_path).ok());
+            let header = source.as_deref().and_then(|source| {
+                impl_header(
+                    source,
+                    location.line.saturating_sub(1),
+                    location.column.saturating_sub(1),
+                )
+            });
+            let Some(header) = header else {
+                groups
+                    .entry("(unrecognized)".to_string())
+                    .or_default()
+                    .push(ImplRecord {
+                        header: String::new(),
+                        derived: false,
+                        location,
+                    });
+                continue;
+            };
+            let name = if is_trait {
+                Some(header.self_type)
+            } else {
+                header.trait_name
+            };
+            let record = ImplRecord {
+                header: header.text,
+                derived: header.derived,
+                location,
+            };
+            match name {
+                Some(name) => groups.entry(base_name(&name)).or_default().push(record),
+                None => inherent.push(record),
+            }
+        }
+
+        let impl_count = groups.values().map(Vec::len).sum::<usize>() + inherent.len();
+        let summary = if is_trait {
+            format!(
+                "`{subject}` is implemented by {} type(s) in {impl_count} impl(s).",
+                groups.len()
+            )
+        } else {
+            format!(
+                "`{subject}` implements {} trait(s); it has {} inherent impl block(s).",
+                groups.len(),
+                inherent.len()
+            )
+        };
+        Ok(Structured(TraitImplsResponse {
+            subject,
+            subject_kind: if is_trait { "trait" } else { "type" }.to_string(),
+            impl_count,
+            groups: groups
+                .into_iter()
+                .map(|(name, impls)| ImplGroupRecord { name, impls })
+                .collect(),
+            inherent,
+            summary,
+        }))
+    }
+
+    /// List the links rust-analyzer finds in a file.
+    #[tool(
+        name = "rust_document_links",
+        output_schema = output_schema::<DocumentLinksResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "List the links rust-analyzer computes in a Rust file, such as doc links in comments and files named in strings like `include!(\"...\")`, with the one-based location of each and its target URL or local file path. Follow a local target with the other tools to reach code the source only refers to by name."
+    )]
+    async fn document_links(
+        &self,
+        params: Parameters<FileParam>,
+    ) -> Result<Structured<DocumentLinksResponse>, McpError> {
+        let file_path = params.0.file_path;
+        validate_file_path(&file_path)?;
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(&file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let links = lsp
+            .document_links(&file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("document link request failed: {e}")))?
+            .unwrap_or_default();
+        let resolvable = lsp
+            .capabilities()
+            .document_link_provider
+            .and_then(|provider| provider.resolve_provider)
+            .unwrap_or(false);
+
+        let uri = file_uri(&file_path)
+            .map_err(|e| McpError::invalid_params(format!("invalid file path: {e}"), None))?;
+        let mut records = Vec::with_capacity(links.len());
+        for mut link in links {
+            if link.target.is_none() && resolvable {
+                match lsp.resolve_document_link(link.clone()).await {
+                    Ok(resolved) => link = resolved,
+                    Err(e) => tracing::debug!("failed to resolve a document link: {e:#}"),
+                }
+            }
+            let target_path = link
+                .target
+                .as_ref()
+                .filter(|target| {
+                    target
+                        .scheme()
+                        .is_some_and(|scheme| scheme.as_str() == "file")
+                })
+                .map(uri_to_path);
+            records.push(DocumentLinkRecord {
+                location: location_record(&uri, &link.range),
+                target: link.target.map(|target| target.to_string()),
+                target_path,
+                tooltip: link.tooltip,
+            });
+        }
+        records.sort_by(|a, b| location_key(&a.location).cmp(&location_key(&b.location)));
+        let mut locations = records
+            .iter()
+            .map(|record| record.location.clone())
+            .collect::<Vec<_>>();
+        attach_snippets(&mut locations, Some(0));
+        for (record, location) in records.iter_mut().zip(locations) {
+            record.location = location;
+        }
+
+        let summary = if records.is_empty() {
+            format!("No links in {file_path}.")
+        } else {
+            format!("Found {} link(s) in {file_path}.", records.len())
+        };
+        Ok(Structured(DocumentLinksResponse {
+            file_path,
+            link_count: records.len(),
+            links: records,
+            summary,
+        }))
+    }
+
+    /// Find the exact position of a symbol's name in a file.
+    #[tool(
+        name = "rust_locate",
+        output_schema = output_schema::<LocateResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Find where a symbol is named in a Rust file, from rust-analyzer's document symbols, without counting characters. `name` may be qualified (`Config::load`) or start with a keyword (`fn load`, `struct Config`); filter further by `kind` (e.g. `method`) and pick one of several with one-based `nth`. Each match carries the ZERO-BASED `line` and `character` of the name's start, ready to pass to rust_hover, rust_find_references, rust_rename, and the other position-based tools."
+    )]
+    async fn locate(
+        &self,
+        params: Parameters<LocateParam>,
+    ) -> Result<Structured<LocateResponse>, McpError> {
+        let p = &params.0;
+        validate_file_path(&p.file_path)?;
+        let query = SymbolQuery::parse(&p.name)?;
+        let kind = p.kind.as_deref().map(symbol_kind_from_name).transpose()?;
+
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let symbols = lsp
+            .document_symbols(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("document symbol request failed: {e}")))?
+            .map(|symbols| symbols::flatten(symbols, &p.file_path))
+            .unwrap_or_default();
+
+        let (mut matches, others): (Vec<IndexedSymbol>, Vec<IndexedSymbol>) =
+            symbols.into_iter().partition(|symbol| {
+                query.matches(symbol) && kind.is_none_or(|kind| symbol.kind == kind)
+            });
+        matches.sort_by_key(|symbol| symbol.range.start);
+        let match_count = matches.len();
+        if let Some(nth) = p.nth {
+            if nth == 0 || nth > match_count {
+                return Err(McpError::invalid_params(
+                    format!(
+                        "nth is one-based and {} has {match_count} match(es) for `{}`",
+                        p.file_path, p.name
+                    ),
+                    None,
+                ));
+            }
+            matches = vec![matches.swap_remove(nth - 1)];
+        }
+
+        let uri = file_uri(&p.file_path)
+            .map_err(|e| McpError::invalid_params(format!("invalid file path: {e}"), None))?;
+        let located: Vec<LocatedSymbolRecord> = matches
+            .into_iter()
+            .map(|symbol| LocatedSymbolRecord {
+                kind: symbol_kind_name(symbol.kind).to_string(),
+                line: symbol.range.start.line,
+                character: symbol.range.start.character,
+                location: location_record(&uri, &symbol.range),
+                name: symbol.name,
+                container_name: symbol.container,
+            })
+            .collect();
+        let suggestions = if match_count == 0 {
+            closest_names(query.name, &others)
+        } else {
+            Vec::new()
+        };
+
+        let summary = match (located.as_slice(), match_count) {
+            ([], _) if suggestions.is_empty() => {
+                format!("No symbol matching `{}` in {}.", p.name, p.file_path)
+            }
+            ([], _) => format!(
+                "No symbol matching `{}` in {}. Closest names: {}.",
+                p.name,
+                p.file_path,
+                suggestions.join(", ")
+            ),
+            ([symbol], 1) => format!(
+                "`{}` is at line {} character {} (zero-based; {}).",
+                p.name, symbol.line, symbol.character, symbol.location.display
+            ),
+            ([symbol], _) => format!(
+                "Match {} of {match_count} for `{}` is at line {} character {} (zero-based; {}).",
+                p.nth.unwrap_or(1),
+                p.name,
+                symbol.line,
+                symbol.character,
+                symbol.location.display
+            ),
+            _ => format!(
+                "Found {match_count} symbols matching `{}` in {}; positions are zero-based. \
+                 Pass `nth` or a qualified name to pick one.",
+                p.name, p.file_path
+            ),
+        };
+
+        Ok(Structured(LocateResponse {
+            file_path: p.file_path.clone(),
+            name: p.name.clone(),
+            match_count,
+            symbols: located,
+            suggestions,
+            summary,
+        }))
+    }
+
+    /// Search the workspace's symbols from the in-memory index.
+    #[tool(
+        name = "rust_symbol_search",
+        output_schema = output_schema::<SymbolSearchResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Fuzzy-search every symbol defined in the workspace, including methods, fields, and variants, from an index kept in memory. The first call reads every Rust file's symbols; later calls re-read only files that changed, so repeated searches are near-instant. `query` matches exactly, by prefix, by substring, or by characters in order (`cfgld` finds `ConfigLoader`). Filter with `kinds` (e.g. [\"struct\", \"function\"]) and `crate`; omit `query` to list, for example, every trait in a crate. Returns one-based locations; large results are paginated: pass `limit`, then `cursor` from `next_cursor`."
+    )]
+    async fn symbol_search(
+        &self,
+        params: Parameters<SymbolSearchParam>,
+    ) -> Result<Structured<SymbolSearchResponse>, McpError> {
+        let p = &params.0;
+        let query = p.query.as_deref().unwrap_or_default().trim();
+        let kinds = p
+            .kinds
+            .iter()
+            .flatten()
+            .map(|name| symbol_kind_from_name(name))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut reindexed_files = 0;
+        let mut indexed_files = None;
+        let page = self
+            .pages
+            .page(PageQuery::new("rust_symbol_search", p), &p.page, async {
+                let root = self.cargo_root()?;
+                let lsp = self.client().await?;
+                // Crates come from cargo; without it, `crate` matches nothing.
+                let metadata = self
+                    .metadata
+                    .get(&root, Duration::from_secs(DEFAULT_CARGO_TIMEOUT_SECS))
+                    .await
+                    .ok()
+                    .map(|(metadata, _)| metadata);
+                let crate_of = |file: &Path| {
+                    let (package, _) = metadata.as_ref()?.owner(file)?;
+                    Some(package.name.clone())
+                };
+                let filter = SymbolFilter {
+                    query,
+                    kinds: &kinds,
+                    crate_name: p.crate_name.as_deref(),
+                };
+                let (symbols, refresh) =
+                    self.symbols.search(&lsp, &root, crate_of, &filter).await?;
+                reindexed_files = refresh.swept_files;
+                indexed_files = Some((
+                    refresh.indexed_files,
+                    refresh.failed_files,
+                    refresh.truncated,
+                ));
+                let mut canonical = HashMap::new();
+                Ok(symbols
+                    .into_iter()
+                    .filter_map(|symbol| {
+                        let uri = file_uri(&symbol.file_path).ok()?;
+                        let mut location = location_record(&uri, &symbol.range);
+                        canonicalize_location(&mut location, &mut canonical);
+                        Some(IndexedSymbolRecord {
+                            name: symbol.name,
+                            kind: symbol_kind_name(symbol.kind).to_string(),
+                            container_name: symbol.container,
+                            location,
+                        })
+                    })
+                    .collect())
+            })
+            .await?;
+
+        let mut summary = if page.total == 0 {
+            format!("No indexed symbols match {query:?}.")
+        } else {
+            format!(
+                "Found {} symbol(s) matching {query:?}.{}",
+                page.total,
+                page.window_note()
+            )
+        };
+        let (indexed, failed, truncated) = indexed_files.unwrap_or_default();
+        if reindexed_files > 0 {
+            let _ = write!(
+                summary,
+                " Indexed {reindexed_files} new or changed file(s)."
+            );
+        }
+        if failed > 0 {
+            let _ = write!(
+                summary,
+                " {failed} file(s) could not be indexed and will be retried."
+            );
+        }
+        if truncated {
+            let _ = write!(summary, " Only the first {indexed} Rust files are indexed.");
+        }
+        Ok(Structured(SymbolSearchResponse {
+            query: query.to_string(),
+            symbol_count: page.items.len(),
+            total_count: page.total,
+            symbols: page.items,
+            next_cursor: page.next_cursor,
+            indexed_files: indexed,
+            reindexed_files,
+            summary,
+        }))
+    }
+
+    /// Get the documentation of an item by its path.
+    #[tool(
+        name = "rust_doc",
+        output_schema = output_schema::<DocResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Get the signature and rustdoc of an item by its path, such as `tokio::sync::Mutex::lock` or `std::collections::HashMap`, without knowing where it is used. Finds the item among the workspace's and its dependencies' symbols, returns its docs as rust_hover would (`format`, `sections`, and `max_chars` apply) and its definition location, plus a docs.rs (or doc.rust-lang.org) URL for dependencies. Items with the same name that the path did not select are listed in `other_matches`."
+    )]
+    async fn doc(&self, params: Parameters<DocParam>) -> Result<Structured<DocResponse>, McpError> {
+        let path = params.0.path.trim().trim_start_matches("::");
+        let segments: Vec<&str> = path.split("::").map(str::trim).collect();
+        let Some((&name, qualifiers)) = segments.split_last().filter(|(name, _)| !name.is_empty())
+        else {
+            return Err(McpError::invalid_params("`path` is empty", None));
+        };
+        let krate = qualifiers.first().copied();
+        let parent = qualifiers.last().copied();
+
+        // A `#` in the query makes rust-analyzer search dependencies too.
+        let mut candidates: Vec<WorkspaceSymbolRecord> = self
+            .collect_workspace_symbols(&format!("{name}#"))
+            .await?
+            .into_iter()
+            .filter(|symbol| symbol.name == name)
+            .collect();
+        candidates.sort_by_key(|symbol| {
+            !parent
+                .is_some_and(|parent| container_matches(symbol.container_name.as_deref(), parent))
+        });
+
+        let lsp = self.client().await?;
+        let mut found = None;
+        for (index, symbol) in candidates.iter().enumerate().take(DOC_MAX_HOVERS) {
+            let file_path = &symbol.location.file_path;
+            lsp.ensure_file_open(file_path).await.map_err(|e| {
+                lsp_error(&e, format!("failed to synchronize file with lspmux: {e}"))
+            })?;
+            let hover = lsp
+                .hover(
+                    file_path,
+                    symbol.location.line.saturating_sub(1),
+                    symbol.location.column.saturating_sub(1),
+                )
+                .await
+                .map_err(|e| lsp_error(&e, format!("hover request failed: {e}")))?;
+            let Some(hover) = hover else {
+                continue;
+            };
+            let markdown = markup_to_text(hover.contents);
+            let defined_in = hover_module_path(&markdown)
+                .and_then(|module| module.split("::").next())
+                .unwrap_or_default();
+            if krate.is_none_or(|krate| same_crate(krate, defined_in)) {
+                found = Some((index, markdown));
+                break;
+            }
+        }
+
+        let Some((index, markdown)) = found else {
+            return Ok(Structured(DocResponse {
+                path: path.to_string(),
+                found: false,
+                kind: None,
+                location: None,
+                contents: String::new(),
+                truncated: false,
+                docs_url: None,
+                summary: format!(
+                    "No item `{path}` found{}; try rust_workspace_symbol with `{name}`.",
+                    if candidates.is_empty() {
+                        String::new()
+                    } else {
+                        format!(" among the {} item(s) named `{name}`", candidates.len())
+                    }
+                ),
+                other_matches: candidates,
+            }));
+        };
+        let symbol = candidates.remove(index);
+
+        // Members of a type are documented on the type's page.
+        let member_of = match parent {
+            Some(parent) if parent.starts_with(char::is_uppercase) => self
+                .collect_workspace_symbols(&format!("{parent}#"))
+                .await?
+                .into_iter()
+                .filter(|candidate| {
+                    candidate.name == parent
+                        && matches!(candidate.kind.as_str(), "struct" | "enum" | "interface")
+                })
+                .min_by_key(|candidate| candidate.location.file_path != symbol.location.file_path)
+                .map(|candidate| candidate.kind),
+            _ => None,
+        };
+        let docs_url = docs_url(
+            &segments,
+            &symbol.kind,
+            member_of.as_deref(),
+            &symbol.location.file_path,
+        );
+        let (contents, truncated) = shape_hover(&markdown, &params.0.options);
+        Ok(Structured(DocResponse {
+            path: path.to_string(),
+            found: true,
+            kind: Some(symbol.kind),
+            summary: format!("Documentation for `{path}` ({}).", symbol.location.display),
+            location: Some(symbol.location),
+            contents,
+            truncated,
+            docs_url,
+            other_matches: candidates,
+        }))
+    }
+
+    /// Return server health and configuration status.
+    #[tool(
+        name = "rust_server_status",
+        output_schema = output_schema::<ServerStatusResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Check rust-analyzer liveness, readiness, active workspace root, and shared lspmux bootstrap metadata. Does not start rust-analyzer; it reports `not_started` until the first query or rust_warmup, and `degraded` after a hung rust-analyzer was restarted until the new one answers."
+    )]
+    async fn server_status(
+        &self,
+        _params: Parameters<NoParams>,
+    ) -> Result<Structured<ServerStatusResponse>, McpError> {
+        let lsp = self.lsp.get();
+        let server_status = match &lsp {
+            None => "not_started",
+            Some(client) if !client.is_alive() => "stopped",
+            Some(_) if self.lsp.is_degraded() => "degraded",
+            Some(_) => "running",
+        };
+        let (workspace_root, server_version, readiness) = match &lsp {
+            Some(client) => (
+                client.workspace_root().await,
+                client.server_version().await,
+                client.readiness().await,
+            ),
+            None => (
+                self.lsp.workspace_root().map(String::from),
+                None,
+                ReadinessState::default(),
+            ),
+        };
+        self.telemetry
+            .refresh_compiler_accounting(workspace_root.as_deref());
+        let telemetry = self.telemetry.snapshot();
+        let client = self.telemetry.client_identity();
+        let compiler_accounting = self.telemetry.compiler_accounting_snapshot();
+        let summary = format!(
+            "{SERVER_NAME} liveness: {server_status}; readiness: {}; workspace root: {}",
+            readiness.health,
+            workspace_root
+                .clone()
+                .unwrap_or_else(|| "<unknown>".to_string())
+        );
+
+        Ok(Structured(ServerStatusResponse {
+            server: SERVER_NAME.to_string(),
+            server_status: server_status.to_string(),
+            workspace_root,
+            server_version,
+            runtime: self.lsp.runtime_status(),
+            client,
+            mcp_session_id: self.session_id().map(String::from),
+            readiness,
+            telemetry,
+            compiler_accounting,
+            summary,
+        }))
+    }
+
+    /// Report this server's own usage statistics.
+    #[tool(
+        name = "mcp_server_stats",
+        output_schema = output_schema::<ServerStatsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Statistics about this MCP server: uptime, calls per tool with p50/p95 latencies, response cache hit rate, pending LSP requests, and bytes exchanged with rust-analyzer. Use it to spot a slow or failing tool and adapt, e.g. stop retrying it. Does not start rust-analyzer."
+    )]
+    async fn server_stats(
+        &self,
+        _params: Parameters<NoParams>,
+    ) -> Result<Structured<ServerStatsResponse>, McpError> {
+        let snapshot = self.telemetry.snapshot();
+        let mut latencies = self.telemetry.tool_latencies();
+        let mut tools: Vec<ToolStatsRecord> = snapshot
+            .tools
+            .into_iter()
+            .map(|(tool, stats)| ToolStatsRecord {
+                latency: latencies.remove(&tool),
+                tool,
+                call_count: stats.call_count,
+                failure_count: stats.failure_count,
+                timeout_count: stats.timeout_count,
+            })
+            .collect();
+        tools.sort_by(|a, b| b.call_count.cmp(&a.call_count).then(a.tool.cmp(&b.tool)));
+
+        let cache = self.cache.stats();
+        let lookups = cache.hits + cache.misses;
+        #[allow(clippy::cast_precision_loss)] // counts stay far below 2^52
+        let hit_rate = (lookups > 0).then(|| cache.hits as f64 / lookups as f64);
+        let lsp = self.lsp.get().map(|client| LspTrafficRecord {
+            pending_requests: client.pending_request_count(),
+            open_documents: client.open_document_count(),
+            bytes_sent: client.bytes_sent(),
+            bytes_received: client.bytes_received(),
+        });
+
+        let uptime_secs = self.telemetry.uptime().as_secs();
+        let calls: u64 = tools.iter().map(|tool| tool.call_count).sum();
+        let hit_rate_text =
+            hit_rate.map_or_else(|| "n/a".to_string(), |rate| format!("{:.0}%", rate * 100.0));
+        let summary = format!(
+            "up {uptime_secs}s; {calls} tool call(s) across {} tool(s); response cache hit rate {hit_rate_text}",
+            tools.len()
+        );
+        Ok(Structured(ServerStatsResponse {
+            uptime_secs,
+            tools,
+            response_cache: CacheStatsRecord {
+                hits: cache.hits,
+                misses: cache.misses,
+                hit_rate,
+                entries: cache.entries,
+            },
+            lsp,
+            summary,
+        }))
+    }
+
+    /// Report the lspmux server's language-server instances and clients.
+    #[tool(
+        name = "lspmux_status",
+        output_schema = output_schema::<LspmuxStatusResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Ask the lspmux server which language-server instances it runs: each one's workspace root, uptime, idle time, and attached clients. An instance with several clients is shared, e.g. by this server and an editor. Does not start rust-analyzer."
+    )]
+    async fn lspmux_status(
+        &self,
+        _params: Parameters<NoParams>,
+    ) -> Result<Structured<LspmuxStatusResponse>, McpError> {
+        let Some(endpoint) = self.lsp.lspmux_endpoint().await else {
+            return Ok(Structured(LspmuxStatusResponse {
+                endpoint: None,
+                instance_count: 0,
+                client_count: 0,
+                instances: Vec::new(),
+                summary: "no lspmux server is in use: the managed one is not started yet, or \
+                          rust-analyzer runs unshared"
+                    .to_string(),
+            }));
+        };
+        let status = lspmux_status::lspmux_status(&endpoint)
+            .await
+            .map_err(|e| lsp_error(&e, format!("{e:#}")))?;
+        let now = unix_now_secs();
+        let mut instances = Vec::with_capacity(status.instances.len());
+        for instance in &status.instances {
+            let uptime = lspmux_status::process_uptime(instance.pid).await;
+            instances.push(self.instance_record(instance, now, uptime));
+        }
+        let client_count = instances.iter().map(|i| i.client_ports.len()).sum();
+        let shared = instances.iter().filter(|i| i.shared).count();
+        let summary = format!(
+            "lspmux at {endpoint}: {} instance(s), {client_count} client(s); {shared} shared by \
+             more than one client",
+            instances.len()
+        );
+        Ok(Structured(LspmuxStatusResponse {
+            endpoint: Some(endpoint.to_string()),
+            instance_count: instances.len(),
+            client_count,
+            instances,
+            summary,
+        }))
+    }
+
+    /// Restart one of the lspmux server's language-server instances.
+    #[tool(
+        name = "lspmux_restart_instance",
+        output_schema = output_schema::<RestartInstanceResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = true,
+            open_world_hint = false
+        ),
+        description = "Stop a language-server instance the lspmux server runs (default: the one serving this workspace), disconnecting every client attached to it, e.g. an editor; this server reconnects to a fresh instance, and so do editors that reconnect on their own. Use it to recover a wedged shared rust-analyzer for all clients at once; rust_restart_analyzer only replaces this server's connection. Get process ids from lspmux_status."
+    )]
+    async fn restart_instance(
+        &self,
+        Parameters(params): Parameters<RestartInstanceParam>,
+    ) -> Result<Structured<RestartInstanceResponse>, McpError> {
+        let endpoint = self.lspmux_endpoint().await?;
+        let now = unix_now_secs();
+        let pid = match params.pid {
+            Some(pid) => pid,
+            None => lspmux_status::lspmux_status(&endpoint)
+                .await
+                .map_err(|e| lsp_error(&e, format!("{e:#}")))?
+                .instances
+                .iter()
+                .find(|instance| self.instance_record(instance, now, None).this_workspace)
+                .map(|instance| instance.pid)
+                .ok_or_else(|| {
+                    McpError::invalid_params(
+                        format!("lspmux at {endpoint} runs no instance for this workspace"),
+                        None,
+                    )
+                })?,
+        };
+        let stopped = lspmux_status::stop_instance(&endpoint, pid)
+            .await
+            .map_err(|e| lsp_error(&e, format!("{e:#}")))?;
+        let instance = self.instance_record(&stopped, now, None);
+        let reconnected = instance.this_workspace && self.lsp.get().is_some();
+        if reconnected {
+            self.lsp
+                .restart()
+                .await
+                .map_err(|e| lsp_error(&e, format!("failed to reconnect: {e:#}")))?;
+        }
+        let summary = format!(
+            "stopped pid {} serving {}, disconnecting {} client(s){}",
+            instance.pid,
+            instance.workspace_root,
+            instance.client_ports.len(),
+            if reconnected {
+                "; this server reconnected to a fresh instance"
+            } else {
+                ""
+            }
+        );
+        Ok(Structured(RestartInstanceResponse {
+            instance,
+            reconnected,
+            summary,
+        }))
+    }
+
+    /// Stop the lspmux server's instances that no client uses.
+    #[tool(
+        name = "lspmux_reap_idle",
+        output_schema = output_schema::<ReapIdleResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = true,
+            open_world_hint = false
+        ),
+        description = "Stop the language-server instances the lspmux server keeps running with no client attached, once idle for idle_secs (default 300), to free their memory. Instances with a client, such as this server's or an editor's, are kept. Does not start rust-analyzer."
+    )]
+    async fn reap_idle(
+        &self,
+        Parameters(params): Parameters<ReapIdleParam>,
+    ) -> Result<Structured<ReapIdleResponse>, McpError> {
+        let endpoint = self.lspmux_endpoint().await?;
+        let status = lspmux_status::lspmux_status(&endpoint)
+            .await
+            .map_err(|e| lsp_error(&e, format!("{e:#}")))?;
+        let now = unix_now_secs();
+        let min_idle = Duration::from_secs(params.idle_secs.unwrap_or(DEFAULT_REAP_IDLE_SECS));
+        let mut reaped = Vec::new();
+        let mut failures = Vec::new();
+        for instance in status.idle_instances(min_idle, now) {
+            match lspmux_status::stop_instance(&endpoint, instance.pid).await {
+                Ok(stopped) => reaped.push(self.instance_record(&stopped, now, None)),
+                Err(e) => failures.push(format!("pid {}: {e:#}", instance.pid)),
+            }
+        }
+        let kept = status.instances.len() - reaped.len();
+        let summary = format!(
+            "stopped {} idle instance(s) of lspmux at {endpoint}; {kept} kept{}",
+            reaped.len(),
+            if failures.is_empty() {
+                String::new()
+            } else {
+                format!("; {} could not be stopped", failures.len())
+            }
+        );
+        Ok(Structured(ReapIdleResponse {
+            reaped,
+            kept,
+            failures,
+            summary,
+        }))
+    }
+
+    /// List the documents rust-analyzer has been sent.
+    #[tool(
+        name = "rust_tracked_documents",
+        output_schema = output_schema::<TrackedDocumentsResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Debugging aid: list every document this server has sent rust-analyzer, with its version, content hash, size, when it was last synchronized and used, and whether the file changed on disk since. Use it when answers look stale or to see what is held in memory; rust_refresh_file resends a document. Does not start rust-analyzer."
+    )]
+    async fn tracked_documents(
+        &self,
+        _params: Parameters<NoParams>,
+    ) -> Result<Structured<TrackedDocumentsResponse>, McpError> {
+        let Some(lsp) = self.lsp.get() else {
+            return Ok(Structured(TrackedDocumentsResponse {
+                document_count: 0,
+                total_bytes: 0,
+                documents: Vec::new(),
+                summary: format!("No documents are tracked: {SERVER_NAME} is not running."),
+            }));
+        };
+        let mut documents = Vec::new();
+        for document in lsp.tracked_documents() {
+            let on_disk =
+                tokio::fs::metadata(&document.file_path)
+                    .await
+                    .map_or("missing", |metadata| {
+                        match document.matches_disk(&metadata) {
+                            Some(true) => "unchanged",
+                            Some(false) => "modified",
+                            None => "unknown",
+                        }
+                    });
+            documents.push(TrackedDocumentRecord {
+                version: document.version,
+                content_hash: format!("{:016x}", document.content_hash),
+                bytes: document.len,
+                synced_secs_ago: document.since_sync.as_secs(),
+                used_secs_ago: document.since_use.as_secs(),
+                on_disk: on_disk.to_string(),
+                file_path: document.file_path,
+            });
+        }
+        let total_bytes = documents.iter().map(|document| document.bytes).sum();
+        let stale = documents
+            .iter()
+            .filter(|document| document.on_disk != "unchanged")
+            .count();
+        let mut summary = format!(
+            "{} document(s) tracked by {SERVER_NAME}, {total_bytes} bytes in all.",
+            documents.len()
+        );
+        if stale > 0 {
+            let _ = write!(
+                summary,
+                " {stale} may be stale; the next tool call on each resends it if it changed."
+            );
+        }
+        Ok(Structured(TrackedDocumentsResponse {
+            document_count: documents.len(),
+            total_bytes,
+            documents,
+            summary,
+        }))
+    }
+
+    /// Run health checks without starting rust-analyzer.
+    #[tool(
+        name = "rust_analyzer_health",
+        output_schema = output_schema::<HealthReport>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Run health checks: lspmux connectivity, rust-analyzer liveness and version, workspace load status, indexing progress, and open document / pending request counts. Every failing check includes a suggested fix. Does not start rust-analyzer."
+    )]
+    async fn analyzer_health(
+        &self,
+        _params: Parameters<NoParams>,
+    ) -> Result<Structured<HealthReport>, McpError> {
+        Ok(Structured(health::check(&self.lsp, false).await))
+    }
+
+    /// Start rust-analyzer ahead of the first query.
+    #[tool(
+        name = "rust_warmup",
+        output_schema = output_schema::<WarmupResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Start rust-analyzer now instead of on the first query, and prime it: open every crate root and build the workspace symbol index. Set `wait_until_ready` to also wait (up to 5 minutes) until indexing finishes. Other rust_* tools start it on demand, so this is optional."
+    )]
+    async fn warmup(
+        &self,
+        params: Parameters<WarmupParam>,
+    ) -> Result<Structured<WarmupResponse>, McpError> {
+        let started = Instant::now();
+        let already_running = self.lsp.get().is_some();
+        let lsp = self.client().await?;
+        let wait = if params.0.wait_until_ready {
+            warmup::READY_LIMIT
+        } else {
+            Duration::ZERO
+        };
+        let primed = warmup::prime(&lsp, wait).await;
+
+        let ready = primed.ready();
+        let readiness = primed.readiness;
+        let elapsed_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+        let state = if already_running {
+            "was already running"
+        } else {
+            "started"
+        };
+        let roots = primed.crate_roots.len();
+        let summary = if ready {
+            format!(
+                "{SERVER_NAME} {state}, primed {roots} crate root(s), and has finished indexing \
+                 ({elapsed_ms} ms)."
+            )
+        } else {
+            format!(
+                "{SERVER_NAME} {state} and primed {roots} crate root(s) ({elapsed_ms} ms); \
+                 readiness: {}. Queries may be slow or incomplete until indexing finishes.",
+                readiness.health
+            )
+        };
+
+        Ok(Structured(WarmupResponse {
+            server: SERVER_NAME.to_string(),
+            already_running,
+            elapsed_ms,
+            crate_roots_opened: roots,
+            symbols_indexed: primed.symbols_indexed,
+            ready,
+            readiness,
+            workspace_root: lsp.workspace_root().await,
+            summary,
+        }))
+    }
+
+    /// Replace the connection to rust-analyzer with a fresh one.
+    #[tool(
+        name = "rust_restart_analyzer",
+        output_schema = output_schema::<RestartResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            open_world_hint = false
+        ),
+        description = "Recover from a degraded or confused rust-analyzer session without restarting the MCP server: shuts the current connection down, starts a new lspmux client, initializes it, and reopens the documents the old connection had open. Returns the new session's version, workspace root, and readiness. Use when queries keep timing out, rust_server_status reports `degraded` or `stopped`, or answers stay wrong after rust_refresh_file."
+    )]
+    async fn restart_analyzer(
+        &self,
+        _params: Parameters<NoParams>,
+    ) -> Result<Structured<RestartResponse>, McpError> {
+        let started = Instant::now();
+        let old = self.lsp.get();
+        let was_running = old.as_ref().is_some_and(|client| client.is_alive());
+        let was_degraded = self.lsp.is_degraded();
+        let to_reopen = old.map_or(0, |client| client.open_document_count());
+        let lsp = self
+            .lsp
+            .restart()
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to restart {SERVER_NAME}: {e:#}")))?;
+        let elapsed_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX);
+        let documents_reopened = lsp.open_document_count();
+        let readiness = lsp.readiness().await;
+        let previous = match (was_running, was_degraded) {
+            (false, _) => "was not running",
+            (true, true) => "was unresponsive",
+            (true, false) => "was running",
+        };
+        let summary = format!(
+            "{SERVER_NAME} {previous}; started a new connection and reopened \
+             {documents_reopened} of {to_reopen} document(s) ({elapsed_ms} ms). Readiness: {}.",
+            readiness.health
+        );
+
+        Ok(Structured(RestartResponse {
+            server: SERVER_NAME.to_string(),
+            was_running,
+            was_degraded,
+            elapsed_ms,
+            documents_reopened,
+            server_version: lsp.server_version().await,
+            workspace_root: lsp.workspace_root().await,
+            readiness,
+            summary,
+        }))
+    }
+
+    /// Open a file in rust-analyzer.
+    #[tool(
+        name = "rust_open_file",
+        output_schema = output_schema::<DocumentResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Open a file in rust-analyzer so it tracks the file's content and analyses it ahead of queries. Other rust_* tools open the files they touch on demand, so this is only needed to prepare files in advance or to control exactly which documents are tracked. Reports the document version and how many documents are open."
+    )]
+    async fn open_file(
+        &self,
+        params: Parameters<FileParam>,
+    ) -> Result<Structured<DocumentResponse>, McpError> {
+        let file_path = params.0.file_path;
+        validate_file_path(&file_path)?;
+        let lsp = self.client().await?;
+        let was_open = lsp.document_version(&file_path).is_some();
+        lsp.ensure_file_open(&file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to open {file_path}: {e:#}")))?;
+        let (action, summary) = if was_open {
+            ("already_open", format!("{file_path} was already open."))
+        } else {
+            ("opened", format!("Opened {file_path}."))
+        };
+        Ok(Structured(document_response(
+            &lsp, file_path, action, summary,
+        )))
+    }
+
+    /// Close a file in rust-analyzer.
+    #[tool(
+        name = "rust_close_file",
+        output_schema = output_schema::<DocumentResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Close a file in rust-analyzer, freeing the memory its document takes, once you are done with it. The file itself is untouched and may already be deleted; a later query on it opens it again. Does not start rust-analyzer."
+    )]
+    async fn close_file(
+        &self,
+        params: Parameters<FileParam>,
+    ) -> Result<Structured<DocumentResponse>, McpError> {
+        let file_path = params.0.file_path;
+        if !Path::new(&file_path).is_absolute() {
+            return Err(McpError::invalid_params(
+                format!("file_path must be absolute, got: {file_path}"),
+                None,
+            ));
+        }
+        let Some(lsp) = self.lsp.get() else {
+            return Ok(Structured(DocumentResponse {
+                summary: format!("{file_path} was not open: {SERVER_NAME} is not running."),
+                file_path,
+                action: "not_open".to_string(),
+                version: None,
+                open_documents: 0,
+            }));
+        };
+        let was_open = lsp.document_version(&file_path).is_some();
+        lsp.close_document(&file_path)
+            .map_err(|e| lsp_error(&e, format!("failed to close {file_path}: {e:#}")))?;
+        let (action, summary) = if was_open {
+            ("closed", format!("Closed {file_path}."))
+        } else {
+            ("not_open", format!("{file_path} was not open."))
+        };
+        Ok(Structured(document_response(
+            &lsp, file_path, action, summary,
+        )))
+    }
+
+    /// Resend a file's content to rust-analyzer.
+    #[tool(
+        name = "rust_refresh_file",
+        output_schema = output_schema::<DocumentResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Re-read a file from disk and send rust-analyzer its content if it differs from what rust-analyzer has, ignoring the usual modification-time check. Use after a tool outside this server edited the file, when answers look stale. Opens the file if it is not open yet."
+    )]
+    async fn refresh_file(
+        &self,
+        params: Parameters<FileParam>,
+    ) -> Result<Structured<DocumentResponse>, McpError> {
+        let file_path = params.0.file_path;
+        validate_file_path(&file_path)?;
+        let lsp = self.client().await?;
+        let before = lsp.synced_content_hash(&file_path);
+        lsp.invalidate_document(&file_path);
+        let after = lsp
+            .ensure_file_open(&file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to refresh {file_path}: {e:#}")))?;
+        let (action, summary) = match before {
+            None => ("opened", format!("{file_path} was not open; opened it.")),
+            Some(before) if before == after => (
+                "unchanged",
+                format!("{SERVER_NAME} already had the current content of {file_path}."),
+            ),
+            Some(_) => (
+                "refreshed",
+                format!("Sent the new content of {file_path} to {SERVER_NAME}."),
+            ),
+        };
+        Ok(Structured(document_response(
+            &lsp, file_path, action, summary,
+        )))
+    }
+
+    /// Point rust-analyzer at a `rust-project.json`, optionally regenerating it.
+    #[tool(
+        name = "rust_link_project",
+        output_schema = output_schema::<LinkProjectResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            open_world_hint = false
+        ),
+        description = "Load a non-Cargo project (Buck, Bazel, ...) from a `rust-project.json`: `project_file` names it, defaulting to the project config's `project_file`, else `rust-project.json` in the workspace root. Set `regenerate` to first run the project config's `project_generator` in the workspace root. A running rust-analyzer reloads the workspace; otherwise the file is loaded when it starts. Does not start rust-analyzer."
+    )]
+    async fn link_project(
+        &self,
+        params: Parameters<LinkProjectParam>,
+    ) -> Result<Structured<LinkProjectResponse>, McpError> {
+        let p = params.0;
+        let root = self.cargo_root()?;
+        let project = ProjectConfig::discover(&root)
+            .map_err(|e| McpError::invalid_params(format!("{e:#}"), None))?;
+        let ra = project.as_ref().map(|(_, config)| &config.rust_analyzer);
+
+        let regenerated_with = if p.regenerate {
+            let Some((program, args)) = ra
+                .and_then(|ra| ra.project_generator.as_deref())
+                .and_then(<[String]>::split_first)
+            else {
+                return Err(McpError::invalid_params(
+                    "`regenerate` needs `rust_analyzer.project_generator` in the project config",
+                    None,
+                ));
+            };
+            let command = std::iter::once(program)
+                .chain(args)
+                .map(String::as_str)
+                .collect::<Vec<_>>()
+                .join(" ");
+            let timeout = Duration::from_secs(p.timeout_secs.unwrap_or(DEFAULT_CARGO_TIMEOUT_SECS));
+            let output = cargo::run_program(program, &root, args, timeout).await?;
+            if !output.success {
+                return Err(internal_error(format!(
+                    "`{command}` failed:\n{}",
+                    output.stderr.trim_end()
+                )));
+            }
+            Some(command)
+        } else {
+            None
+        };
+
+        let project_file = match p.project_file {
+            Some(file) => {
+                if !Path::new(&file).is_absolute() {
+                    return Err(McpError::invalid_params(
+                        format!("project_file must be absolute, got: {file}"),
+                        None,
+                    ));
+                }
+                PathBuf::from(file)
+            }
+            None => root.join(
+                ra.and_then(|ra| ra.project_file.as_deref())
+                    .unwrap_or(RUST_PROJECT_FILE),
+            ),
+        };
+        if !project_file.is_file() {
+            let hint = if regenerated_with.is_none()
+                && ra.is_some_and(|ra| ra.project_generator.is_some())
+            {
+                "; pass `regenerate` to generate it"
+            } else {
+                ""
+            };
+            return Err(McpError::invalid_params(
+                format!("project file not found: {}{hint}", project_file.display()),
+                None,
+            ));
+        }
+
+        self.lsp.link_project(project_file.clone());
+        let reloaded = self.lsp.get().is_some();
+        self.push_project_settings(project.as_ref())?;
+
+        let shown = project_file.display().to_string();
+        let summary = match (&regenerated_with, reloaded) {
+            (Some(command), true) => {
+                format!("Regenerated {shown} with `{command}`; {SERVER_NAME} is reloading it.")
+            }
+            (Some(command), false) => format!(
+                "Regenerated {shown} with `{command}`; {SERVER_NAME} loads it when it starts."
+            ),
+            (None, true) => format!("Linked {shown}; {SERVER_NAME} is reloading it."),
+            (None, false) => format!("Linked {shown}; {SERVER_NAME} loads it when it starts."),
+        };
+        Ok(Structured(LinkProjectResponse {
+            project_file: shown,
+            regenerated_with,
+            reloaded,
+            summary,
+        }))
+    }
+    /// Apply rust-analyzer's fix for one diagnostic.
+    #[tool(
+        name = "rust_apply_quickfix",
+        output_schema = output_schema::<EditResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "Fix a diagnostic from `rust_diagnostics`: name it by `index`, or by `code` (plus one-based `line` when several share a code). The quick fix rust-analyzer prefers for it (for compiler suggestions, the machine-applicable one) is written to disk, and a unified diff of every changed file is returned. Pass `dry_run` to preview the diff without writing, and `title` to choose among several fixes when none is preferred."
+    )]
+    async fn apply_quickfix(
+        &self,
+        params: Parameters<QuickfixParam>,
+    ) -> Result<Structured<EditResponse>, McpError> {
+        let p = &params.0;
+        validate_file_path(&p.file_path)?;
+
+        let lsp = self.client().await?;
+        let diagnostics = document_diagnostics(&lsp, &p.file_path).await?;
+        let (index, diagnostic) = select_diagnostic(diagnostics, p)?;
+        let uri = file_uri(&p.file_path)
+            .map_err(|e| McpError::invalid_params(format!("invalid file path: {e}"), None))?;
+        let target = diagnostic_record(&uri, index, diagnostic.clone());
+
+        let actions = lsp
+            .code_actions(
+                &p.file_path,
+                diagnostic.range,
+                vec![diagnostic.clone()],
+                Some(vec![lsp_types::CodeActionKind::QUICKFIX]),
+            )
+            .await
+            .map_err(|e| lsp_error(&e, format!("code action request failed: {e}")))?;
+        let fixes = code_action_literals(actions)
+            .filter(|action| fixes_diagnostic(action, &diagnostic))
+            .collect();
+        let (fix, alternatives) = choose_action(fixes, p.title.as_deref(), "quick fix")?;
+        let (title, edit) = resolved_edit(&lsp, fix).await?;
+
+        let mut response = apply_workspace_edit(&lsp, title, edit, p.dry_run, alternatives).await?;
+        let code = target
+            .code
+            .as_deref()
+            .map(|code| format!("[{code}]"))
+            .unwrap_or_default();
+        response.summary = format!(
+            "{} Fixes {}{code} at {}: {}",
+            response.summary, target.severity, target.location.display, target.message
+        );
+        Ok(Structured(response))
+    }
+
+    /// Remove unused imports, then merge and sort the rest.
+    #[tool(
+        name = "rust_organize_imports",
+        output_schema = output_schema::<EditResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = true,
+            open_world_hint = false
+        ),
+        description = "Organize the `use` declarations of a Rust file: remove unused imports (using rust-analyzer's fixes), optionally `merge` imports that share a prefix, and sort each block of consecutive single-line imports the way rustfmt does (blank lines, comments, and attributes keep their groups). Writes the file and returns a unified diff; pass `dry_run` to only preview it."
+    )]
+    async fn organize_imports(
+        &self,
+        params: Parameters<OrganizeImportsParam>,
+    ) -> Result<Structured<EditResponse>, McpError> {
+        let p = &params.0;
+        validate_file_path(&p.file_path)?;
+
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let original = std::fs::read_to_string(&p.file_path).map_err(|e| {
+            McpError::invalid_params(format!("failed to read {}: {e}", p.file_path), None)
+        })?;
+
+        let organized = organize(&lsp, &p.file_path, &original, p.merge).await;
+        // Drop any preview `organize` left in the server before answering.
+        lsp.invalidate_document(&p.file_path);
+        lsp.ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let (edited, steps) = organized?;
+
+        let plan = edits::Plan::modify(p.file_path.clone(), original, edited);
+        let mut response = finish_edit(
+            &lsp,
+            "Organize imports".to_string(),
+            plan,
+            p.dry_run,
+            Vec::new(),
+        )
+        .await?;
+        response.summary = if steps.is_empty() {
+            format!("Imports in {} are already organized.", p.file_path)
+        } else {
+            format!("{} Steps: {}.", response.summary, steps.join(", "))
+        };
+        Ok(Structured(response))
+    }
+
+    /// Explain a compiler error code with `rustc --explain`.
+    #[tool(
+        name = "rust_explain_error",
+        output_schema = output_schema::<ExplainResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Explain a Rust compiler error code such as `E0308`, with the text of `rustc --explain` from the workspace's toolchain: what the error means, an example that triggers it, and how to fix it. Read this instead of guessing what a code means. Explanations are cached for the life of the server. `rust_diagnostics` attaches them for every code it reports when passed `explain: true`."
+    )]
+    async fn explain_error(
+        &self,
+        params: Parameters<ExplainParam>,
+    ) -> Result<Structured<ExplainResponse>, McpError> {
+        let requested = &params.0.code;
+        let code = explain::error_code(requested).ok_or_else(|| {
+            McpError::invalid_params(
+                format!(
+                    "{requested:?} is not a compiler error code like E0308; lints such as \
+                     `dead_code` have no `rustc --explain` entry"
+                ),
+                None,
+            )
+        })?;
+        let root = self.cargo_root().unwrap_or_else(|_| PathBuf::from("."));
+        let (explanation, cached) = self.explanations.get(&root, &code).await?;
+        let summary = if explanation.is_some() {
+            format!("rustc --explain {code}:")
+        } else {
+            format!(
+                "rustc has no explanation for {code}; it may be unused, retired, or newer than \
+                 the toolchain."
+            )
+        };
+        Ok(Structured(ExplainResponse {
+            found: explanation.is_some(),
+            explanation: explanation.map(|explanation| explanation.to_string()),
+            code,
+            cached,
+            summary,
+        }))
+    }
+
+    /// Report unused and dead code across the workspace, optionally fixing it.
+    #[tool(
+        name = "rust_unused_report",
+        output_schema = output_schema::<UnusedReportResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "Find unused and dead code in every .rs file under a directory or glob (default: the workspace root): unused imports, variables, `mut`s, and items (`dead_code`), plus anything else rust-analyzer marks unnecessary. Returns findings grouped per file with counts, and totals per lint. With `fix: true`, rust-analyzer's preferred quick fix for each finding that has one (e.g. removing an import or prefixing a variable with `_`) is written and a unified diff returned; `dry_run` previews it. Dead items have no automatic fix and are only reported."
+    )]
+    async fn unused_report(
+        &self,
+        params: Parameters<UnusedReportParam>,
+    ) -> Result<Structured<UnusedReportResponse>, McpError> {
+        let UnusedReportParam {
+            path,
+            max_files,
+            fix,
+            dry_run,
+        } = params.0;
+        let path = match path {
+            Some(path) => path,
+            None => self.cargo_root()?.display().to_string(),
+        };
+        let (files, truncated) = rust_files(&path, max_files.unwrap_or(DEFAULT_DIR_MAX_FILES))?;
+        let files_checked = files.len();
+
+        let results = self.sweep_diagnostics(files).await;
+
+        let mut groups = Vec::new();
+        let mut failures = Vec::new();
+        for (file_path, diagnostics) in results {
+            match diagnostics {
+                Ok(mut diagnostics) => {
+                    diagnostics.retain(is_unused);
+                    if !diagnostics.is_empty() {
+                        groups.push(UnusedFileRecord {
+                            file_path,
+                            count: diagnostics.len(),
+                            diagnostics,
+                        });
+                    }
+                }
+                Err(e) => failures.push(FileFailure {
+                    file_path,
+                    error: e.message.to_string(),
+                }),
+            }
+        }
+        groups.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+        let (fixed_count, fix) = if fix && !groups.is_empty() {
+            let lsp = self.client().await?;
+            let (fixed_count, response) = fix_unused(&lsp, &groups, dry_run, &mut failures).await?;
+            (fixed_count, Some(response))
+        } else {
+            (0, None)
+        };
+        failures.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+
+        let unused_count = groups.iter().map(|file| file.count).sum();
+        let by_code = unused_counts(&groups);
+        let mut summary = format!(
+            "Checked {files_checked} file(s) under {path}: {unused_count} unused item(s) in {} \
+             file(s).",
+            groups.len()
+        );
+        if !by_code.is_empty() {
+            let counts: Vec<String> = by_code
+                .iter()
+                .map(|count| format!("{} {}", count.count, count.code))
+                .collect();
+            let _ = write!(summary, " By lint: {}.", counts.join(", "));
+        }
+        if truncated {
+            summary
+                .push_str(" More files matched; raise max_files or narrow the path to check them.");
+        }
+        if !failures.is_empty() {
+            let _ = write!(
+                summary,
+                " {} file(s) could not be checked or fixed.",
+                failures.len()
+            );
+        }
+        if let Some(fix) = &fix {
+            let _ = write!(
+                summary,
+                " {fixed_count} of {unused_count} had a fix that was taken. {}",
+                fix.summary
+            );
+        }
+
+        Ok(Structured(UnusedReportResponse {
+            path,
+            files_checked,
+            truncated,
+            unused_count,
+            by_code,
+            files: groups,
+            failures,
+            fixed_count,
+            fix,
+            summary,
+        }))
+    }
+
+    /// Rename a symbol everywhere it is used.
+    #[tool(
+        name = "rust_rename",
+        output_schema = output_schema::<EditResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "Rename the symbol at a position (zero-based `line` and `character`, or a `symbol` such as `Config::load`) to `new_name` across the workspace. By default nothing is written: the result is a unified diff of every edit. With `apply: true` all files are written together or not at all, and the rename is refused if any of them changed since rust-analyzer computed it."
+    )]
+    async fn rename(
+        &self,
+        params: Parameters<RenameParam>,
+    ) -> Result<Structured<EditResponse>, McpError> {
+        let new_name = params.0.new_name.trim();
+        if new_name.is_empty() {
+            return Err(McpError::invalid_params("new_name must not be empty", None));
+        }
+        let p = &self.resolve_position(&params.0.position).await?;
+
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let edit = lsp
+            .rename(&p.file_path, p.line, p.character, new_name)
+            .await
+            .map_err(|e| McpError::invalid_params(format!("rename failed: {e}"), None))?
+            .ok_or_else(|| {
+                McpError::invalid_params("there is no renameable symbol at this position", None)
+            })?;
+
+        let title = format!("Rename to {new_name}");
+        let mut response =
+            apply_workspace_edit(&lsp, title, edit, !params.0.apply, Vec::new()).await?;
+        if !params.0.apply && !response.files.is_empty() {
+            response.summary = format!(
+                "Renaming to {new_name} would change {} file(s); pass `apply: true` to write them.",
+                response.files.len()
+            );
+        }
+        Ok(Structured(response))
+    }
+
+    /// Move a source file, fixing the module declarations and paths that
+    /// refer to it.
+    #[tool(
+        name = "rust_move_file",
+        output_schema = output_schema::<EditResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "Move or rename a Rust source file or directory to `new_path` without breaking the build: rust-analyzer first works out the edits the move needs, such as renaming `mod` declarations and fixing `#[path]` attributes, then the edits and the move are written together or not at all. Returns a unified diff with `rename from`/`rename to` headers; pass `dry_run` to only preview it. Moving with a shell command instead leaves the module tree broken."
+    )]
+    async fn move_file(
+        &self,
+        params: Parameters<MoveFileParam>,
+    ) -> Result<Structured<EditResponse>, McpError> {
+        let MoveFileParam {
+            file_path,
+            new_path,
+            dry_run,
+        } = params.0;
+        validate_file_path(&file_path)?;
+        if !Path::new(&new_path).is_absolute() {
+            return Err(McpError::invalid_params(
+                format!("new_path must be absolute, got: {new_path}"),
+                None,
+            ));
+        }
+        if Path::new(&new_path).exists() {
+            return Err(McpError::invalid_params(
+                format!("{new_path} already exists"),
+                None,
+            ));
+        }
+
+        let lsp = self.client().await?;
+        let moving_dir = Path::new(&file_path).is_dir();
+        if !moving_dir {
+            lsp.ensure_file_open(&file_path).await.map_err(|e| {
+                lsp_error(&e, format!("failed to synchronize file with lspmux: {e}"))
+            })?;
+        }
+        let edit = lsp
+            .will_rename_file(&file_path, &new_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("workspace/willRenameFiles request failed: {e}")))?;
+        let mut operations = edit.map(edits::operations).unwrap_or_default();
+        operations.push(edits::Operation::Rename {
+            from: file_path.clone(),
+            to: new_path.clone(),
+            overwrite: false,
+            ignore_if_exists: false,
+        });
+        let plan = edits::plan(operations, lsp.as_ref())?;
+
+        let title = format!("Move {file_path} to {new_path}");
+        let response = finish_edit(&lsp, title, plan, dry_run, Vec::new()).await?;
+        if response.applied {
+            if moving_dir {
+                // The plan closed the files it moved; documents open under
+                // the old directory but gone from disk are stale too.
+                let old_dir = format!("{}/", file_path.trim_end_matches('/'));
+                for document in lsp.tracked_documents() {
+                    if document.file_path.starts_with(&old_dir) {
+                        if let Err(e) = lsp.close_document(&document.file_path) {
+                            tracing::warn!("failed to close {}: {e:#}", document.file_path);
+                        }
+                    }
+                }
+            }
+            if let Err(e) = lsp.did_rename_file(&file_path, &new_path) {
+                tracing::warn!("failed to report the move of {file_path}: {e:#}");
+            }
+        }
+        Ok(Structured(response))
+    }
+
+    /// Move a range of statements or an expression into a new function.
+    #[tool(
+        name = "rust_extract_function",
+        output_schema = output_schema::<EditResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "Extract the code in a range (zero-based `start_line`/`start_character` to `end_line`/`end_character`) into a new function called `name`, replacing it with a call. rust-analyzer works out the parameters, borrows, generics, and return value. Select whole statements or a single expression. Writes the file and returns a unified diff; pass `dry_run` to only preview it."
+    )]
+    async fn extract_function(
+        &self,
+        params: Parameters<ExtractFunctionParam>,
+    ) -> Result<Structured<EditResponse>, McpError> {
+        /// The name rust-analyzer gives every extracted function.
+        const PLACEHOLDER_NAME: &str = "fun_name";
+
+        let p = &params.0;
+        let file = &p.range.file_path;
+        validate_file_path(file)?;
+        if !edits::is_identifier(&p.name) {
+            return Err(McpError::invalid_params(
+                format!("{:?} is not a valid function name", p.name),
+                None,
+            ));
+        }
+
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(file)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let is_extract = |title: &str| title == "Extract into function";
+        let kind = lsp_types::CodeActionKind::REFACTOR_EXTRACT;
+        let Some(action) =
+            find_code_action(&lsp, file, p.range.lsp_range()?, kind, is_extract).await?
+        else {
+            return Err(McpError::invalid_params(
+                format!(
+                    "{SERVER_NAME} cannot extract this range into a function; select whole \
+                     statements or a single expression"
+                ),
+                None,
+            ));
+        };
+        let (_, edit) = action_edit(action)?;
+
+        // The placeholder name appears only in the text the extraction inserts.
+        let mut file_edits = edits::text_edits_by_file(edit)?;
+        for text_edit in file_edits.iter_mut().flat_map(|file| file.edits.iter_mut()) {
+            text_edit.new_text =
+                edits::replace_identifier(&text_edit.new_text, PLACEHOLDER_NAME, &p.name);
+        }
+        let title = format!("Extract into function `{}`", p.name);
+        let operations = file_edits.into_iter().map(edits::Operation::Edit).collect();
+        let plan = edits::plan(operations, &*lsp)?;
+        let response = finish_edit(&lsp, title, plan, p.dry_run, Vec::new()).await?;
+        Ok(Structured(response))
+    }
+
+    /// Inline the variable, call, constant, or type alias at a position.
+    #[tool(
+        name = "rust_inline",
+        output_schema = output_schema::<EditResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "Inline the symbol at a position (zero-based `line` and `character`, or a `symbol` such as `fn helper`): a local variable into its uses, a function call into its body, a constant as its value, or a type alias as its type. On a function's definition rust-analyzer can inline it into every caller. When several inlinings apply, the error lists them; pass one as `title`. Writes the files and returns a unified diff; pass `dry_run` to only preview it."
+    )]
+    async fn inline(
+        &self,
+        params: Parameters<InlineParam>,
+    ) -> Result<Structured<EditResponse>, McpError> {
+        let p = &self.resolve_position(&params.0.position).await?;
+
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let position = lsp_types::Position::new(p.line, p.character);
+        let kind = lsp_types::CodeActionKind::REFACTOR_INLINE;
+        let actions = lsp
+            .code_actions(
+                &p.file_path,
+                lsp_types::Range::new(position, position),
+                Vec::new(),
+                Some(vec![kind.clone()]),
+            )
+            .await
+            .map_err(|e| lsp_error(&e, format!("code action request failed: {e}")))?;
+        let inlinings = code_action_literals(actions)
+            .filter(|action| {
+                action
+                    .kind
+                    .as_ref()
+                    .is_some_and(|found| found.as_str().starts_with(kind.as_str()))
+            })
+            .collect();
+        let (action, alternatives) =
+            choose_action(inlinings, params.0.title.as_deref(), "inlining")?;
+        let (title, edit) = resolved_edit(&lsp, action).await?;
+
+        let response =
+            apply_workspace_edit(&lsp, title, edit, params.0.dry_run, alternatives).await?;
+        Ok(Structured(response))
+    }
+
+    /// Run a command from a code action or code lens.
+    #[tool(
+        name = "rust_execute_command",
+        output_schema = output_schema::<ExecuteCommandResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "Run the command a code action or code lens carries, given by its `command` name and `arguments`. A command rust-analyzer advertises runs on the server, and the edits it asks for meanwhile are written to disk and returned as unified diffs; pass `dry_run` to only preview them. rust-analyzer's editor commands are carried out here: `rust-analyzer.runSingle` runs the runnable's cargo command and returns its output, and `rust-analyzer.gotoLocation` and `rust-analyzer.showReferences` return the locations they point to."
+    )]
+    async fn execute_command(
+        &self,
+        params: Parameters<ExecuteCommandParam>,
+    ) -> Result<Structured<ExecuteCommandResponse>, McpError> {
+        let p = params.0;
+        let lsp = self.client().await?;
+        let server_commands = lsp.server_commands();
+        let mut response = ExecuteCommandResponse {
+            command: p.command.clone(),
+            ran_on_server: false,
+            result: None,
+            locations: Vec::new(),
+            run: None,
+            edits: Vec::new(),
+            summary: String::new(),
+        };
+        if server_commands.contains(&p.command) {
+            let (result, requested) = lsp
+                .execute_command(&p.command, p.arguments)
+                .await
+                .map_err(|e| internal_error(format!("`{}` failed: {e}", p.command)))?;
+            for edit in requested {
+                let applied =
+                    apply_workspace_edit(&lsp, p.command.clone(), edit, p.dry_run, Vec::new())
+                        .await?;
+                response.edits.push(applied);
+            }
+            response.ran_on_server = true;
+            response.result = result.filter(|result| !result.is_null());
+            response.summary = std::iter::once(format!("Ran `{}` on {SERVER_NAME}.", p.command))
+                .chain(response.edits.iter().map(|edit| edit.summary.clone()))
+                .collect::<Vec<_>>()
+                .join(" ");
+            return Ok(Structured(response));
+        }
+        match p.command.as_str() {
+            "rust-analyzer.runSingle" => {
+                let run = self
+                    .run_runnable(p.arguments.first(), p.timeout_secs)
+                    .await?;
+                response.summary = if run.success {
+                    format!("`{}` succeeded.", run.command)
+                } else {
+                    format!("`{}` failed; see its output.", run.command)
+                };
+                response.run = Some(run);
+            }
+            "rust-analyzer.gotoLocation" | "rust-analyzer.showReferences" => {
+                response.locations = command_locations(&p.command, &p.arguments)?
+                    .iter()
+                    .map(|location| location_record(&location.uri, &location.range))
+                    .collect();
+                response.summary = format!(
+                    "`{}` points to {} location(s).",
+                    p.command,
+                    response.locations.len()
+                );
+            }
+            command => {
+                let runs = if server_commands.is_empty() {
+                    "it runs none".to_string()
+                } else {
+                    format!("it runs {}", server_commands.join(", "))
+                };
+                return Err(McpError::invalid_params(
+                    format!(
+                        "`{command}` is not a command {SERVER_NAME} runs ({runs}) nor one \
+                         carried out here ({})",
+                        CLIENT_COMMANDS.join(", ")
+                    ),
+                    None,
+                ));
+            }
+        }
+        Ok(Structured(response))
+    }
+
+    /// Run `cargo check` on the workspace and return its diagnostics.
+    #[tool(
+        name = "cargo_check",
+        output_schema = output_schema::<CargoCheckResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Run `cargo check --message-format=json` in the workspace root, with the features and target directory from the project's `.lspmux-cc.toml`, and return the compiler's errors and warnings with one-based locations. Unlike rust_diagnostics, this builds the whole workspace, so it also reports what only a build shows: failing build scripts, errors in dependencies, and errors in files rust-analyzer has not opened. Narrow it with `package`; `all_targets` (default true) includes tests, examples, and benches. Slower than rust_diagnostics; use it to confirm a change compiles."
+    )]
+    async fn cargo_check(
+        &self,
+        params: Parameters<CargoCheckParam>,
+    ) -> Result<Structured<CargoCheckResponse>, McpError> {
+        let p = &params.0;
+        let root = self.cargo_root()?;
+        let project = ProjectConfig::discover(&root)
+            .map_err(|e| McpError::invalid_params(format!("{e:#}"), None))?;
+
+        let mut args = vec!["check".to_string(), "--message-format=json".to_string()];
+        if p.all_targets.unwrap_or(true) {
+            args.push("--all-targets".to_string());
+        }
+        if let Some(package) = &p.package {
+            args.extend(["-p".to_string(), package.clone()]);
+        }
+        if let Some((_, config)) = &project {
+            args.extend(config.cargo_args());
+        }
+        let timeout = Duration::from_secs(p.timeout_secs.unwrap_or(DEFAULT_CARGO_TIMEOUT_SECS));
+        let output = cargo::run(&root, &args, timeout).await?;
+
+        let mut diagnostics: Vec<CargoDiagnosticRecord> =
+            cargo::compiler_messages(&root, &output.stdout, &output.stderr)
+                .into_iter()
+                .filter(|message| {
+                    message.level.starts_with("error")
+                        || (message.level == "warning" && !p.errors_only)
+                })
+                .map(cargo_diagnostic_record)
+                .collect();
+        // Stable, so each severity keeps cargo's order.
+        diagnostics.sort_by_key(|diagnostic| !diagnostic.severity.starts_with("error"));
+        let error_count = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity.starts_with("error"))
+            .count();
+        let warning_count = diagnostics.len() - error_count;
+        let max = p
+            .max_diagnostics
+            .unwrap_or(DEFAULT_CARGO_MAX_DIAGNOSTICS)
+            .max(1);
+        let truncated = diagnostics.len() > max;
+        diagnostics.truncate(max);
+
+        let command = format!("cargo {}", args.join(" "));
+        let mut summary = match (output.success, error_count) {
+            (true, _) => format!("`{command}` succeeded with {warning_count} warning(s)."),
+            (false, 0) => format!(
+                "`{command}` failed without compiler errors; see its output:\n{}",
+                output.stderr.trim_end()
+            ),
+            (false, _) => {
+                format!("`{command}` failed: {error_count} error(s), {warning_count} warning(s).")
+            }
+        };
+        if truncated {
+            let _ = write!(
+                summary,
+                " Showing the first {max}; raise `max_diagnostics` to see more."
+            );
+        }
+        Ok(Structured(CargoCheckResponse {
+            workspace_root: root.display().to_string(),
+            command,
+            success: output.success,
+            error_count,
+            warning_count,
+            diagnostics,
+            truncated,
+            summary,
+        }))
+    }
+
+    /// Run tests with `cargo test` and report each one's outcome.
+    #[tool(
+        name = "cargo_test",
+        output_schema = output_schema::<CargoTestResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = true,
+            idempotent_hint = false,
+            open_world_hint = true
+        ),
+        description = "Run tests with `cargo test` and return each test's outcome (passed, failed, or ignored), with the captured output and panic message of every failure. Choose the tests with a `runnable` as rust-analyzer's runnables describe it ({cargo_args, executable_args, cwd}), with `file_path` and a zero-based `line` inside a test or test module, or with a test-path `filter` and optional `package`; with none of these, runs every test in the workspace. Compiler errors that keep the tests from building are returned as `build_errors`. Use it to verify a fix."
+    )]
+    async fn cargo_test(
+        &self,
+        params: Parameters<CargoTestParam>,
+    ) -> Result<Structured<CargoTestResponse>, McpError> {
+        let p = &params.0;
+        if (p.runnable.is_some() || p.file_path.is_some())
+            && (p.filter.is_some() || p.package.is_some())
+        {
+            return Err(McpError::invalid_params(
+                "`filter` and `package` choose tests instead of `runnable` or `file_path`; \
+                 pass one or the other",
+                None,
+            ));
+        }
+        let (cwd, mut args, mut executable_args) = match (&p.runnable, &p.file_path) {
+            (Some(runnable), None) => (
+                runnable
+                    .cwd
+                    .as_ref()
+                    .map_or_else(|| self.cargo_root(), |cwd| Ok(PathBuf::from(cwd)))?,
+                runnable.cargo_args.clone(),
+                runnable.executable_args.clone(),
+            ),
+            (None, Some(file_path)) => {
+                validate_file_path(file_path)?;
+                let line = p.line.ok_or_else(|| {
+                    McpError::invalid_params("`file_path` needs the `line` of a test", None)
+                })?;
+                let runnable = self.test_runnable_at(file_path, line).await?;
+                let cwd = match runnable.cwd.or(runnable.workspace_root) {
+                    Some(cwd) => PathBuf::from(cwd),
+                    None => self.cargo_root()?,
+                };
+                (cwd, runnable.cargo_args, runnable.executable_args)
+            }
+            (None, None) => {
+                let root = self.cargo_root()?;
+                let project = ProjectConfig::discover(&root)
+                    .map_err(|e| McpError::invalid_params(format!("{e:#}"), None))?;
+                let mut args = vec!["test".to_string()];
+                if let Some(package) = &p.package {
+                    args.extend(["-p".to_string(), package.clone()]);
+                }
+                if let Some((_, config)) = &project {
+                    args.extend(config.cargo_args());
+                }
+                (root, args, p.filter.iter().cloned().collect())
+            }
+            (Some(_), Some(_)) => {
+                return Err(McpError::invalid_params(
+                    "pass either `runnable` or `file_path`, not both",
+                    None,
+                ))
+            }
+        };
+        if args.first().map(String::as_str) != Some("test") {
+            return Err(McpError::invalid_params(
+                format!("`cargo {}` does not run tests", args.join(" ")),
+                None,
+            ));
+        }
+        args.push("--message-format=json".to_string());
+        // Uncaptured output would interleave with the results.
+        executable_args.retain(|arg| arg != "--nocapture");
+        if !executable_args.is_empty() {
+            args.push("--".to_string());
+            args.extend(executable_args);
+        }
+
+        let timeout = Duration::from_secs(p.timeout_secs.unwrap_or(DEFAULT_CARGO_TIMEOUT_SECS));
+        let output = cargo::run(&cwd, &args, timeout).await?;
+
+        let build_errors: Vec<CargoDiagnosticRecord> =
+            cargo::compiler_messages(&cwd, &output.stdout, &output.stderr)
+                .into_iter()
+                .filter(|message| message.level.starts_with("error"))
+                .map(cargo_diagnostic_record)
+                .collect();
+        let mut results = cargo::test_results(&output.stdout);
+        let count = |outcome| {
+            results
+                .iter()
+                .filter(|result| result.outcome == outcome)
+                .count()
+        };
+        let passed = count(cargo::TestOutcome::Passed);
+        let failed = count(cargo::TestOutcome::Failed);
+        let ignored = count(cargo::TestOutcome::Ignored);
+        // Stable, so each outcome keeps the order the tests finished in.
+        results.sort_by_key(|result| result.outcome != cargo::TestOutcome::Failed);
+        let max = p.max_tests.unwrap_or(DEFAULT_CARGO_MAX_TESTS).max(1);
+        let truncated = results.len() > max;
+        results.truncate(max);
+
+        let command = format!("cargo {}", args.join(" "));
+        let counts = format!("{passed} passed, {failed} failed, {ignored} ignored");
+        let mut summary = if !build_errors.is_empty() {
+            format!(
+                "`{command}` failed to build: {} error(s).",
+                build_errors.len()
+            )
+        } else if output.success && results.is_empty() {
+            format!("`{command}` ran no tests; check the filter.")
+        } else if output.success {
+            format!("`{command}` passed: {counts}.")
+        } else if failed > 0 {
+            format!("`{command}` failed: {counts}.")
+        } else {
+            format!(
+                "`{command}` failed without a failing test; see its output:\n{}",
+                output.stderr.trim_end()
+            )
+        };
+        if truncated {
+            let _ = write!(
+                summary,
+                " Showing the first {max} tests; raise `max_tests` to see more."
+            );
+        }
+        Ok(Structured(CargoTestResponse {
+            cwd: cwd.display().to_string(),
+            command,
+            success: output.success,
+            passed,
+            failed,
+            ignored,
+            tests: results
+                .into_iter()
+                .map(|result| TestRecord {
+                    name: result.name,
+                    outcome: result.outcome.as_str().to_string(),
+                    output: result.output,
+                })
+                .collect(),
+            truncated,
+            build_errors,
+            summary,
+        }))
+    }
+
+    /// Report packages, targets, features, and dependencies from `cargo metadata`.
+    #[tool(
+        name = "cargo_metadata",
+        output_schema = output_schema::<CargoMetadataResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Report the workspace's packages from `cargo metadata`: each member's version, targets (lib, bins, tests, examples, benches) with their source files, features, and dependencies with the version the lockfile resolved them to. Pass `package` for one package (a dependency works too, e.g. to see its features); `file_path` to find the package and targets that own a file; or `dependency` (e.g. `serde`) to find which workspace packages depend on a crate and every version of it in the lockfile. The result is cached until a Cargo.toml or Cargo.lock changes."
+    )]
+    async fn cargo_metadata(
+        &self,
+        params: Parameters<CargoMetadataParam>,
+    ) -> Result<Structured<CargoMetadataResponse>, McpError> {
+        let p = &params.0;
+        let root = self.cargo_root()?;
+        let (metadata, cached) = self
+            .metadata
+            .get(&root, Duration::from_secs(DEFAULT_CARGO_TIMEOUT_SECS))
+            .await?;
+
+        let mut packages = Vec::new();
+        let mut owner = None;
+        let mut dependents = Vec::new();
+        let mut dependency_versions = Vec::new();
+        let summary = if let Some(file_path) = &p.file_path {
+            validate_file_path(file_path)?;
+            let (package, targets) = metadata.owner(Path::new(file_path)).ok_or_else(|| {
+                McpError::invalid_params(
+                    format!("{file_path} is not in any package of the workspace"),
+                    None,
+                )
+            })?;
+            let targets: Vec<String> = targets
+                .iter()
+                .map(|target| format!("{} {}", target.kind.join(","), target.name))
+                .collect();
+            let summary = if targets.is_empty() {
+                format!("{file_path} is in package {}.", package.name)
+            } else {
+                format!(
+                    "{file_path} is in package {} ({}).",
+                    package.name,
+                    targets.join("; ")
+                )
+            };
+            owner = Some(OwnerRecord {
+                package: package.name.clone(),
+                manifest_path: package.manifest_path.clone(),
+                targets,
+            });
+            summary
+        } else if let Some(name) = &p.dependency {
+            for package in metadata.members() {
+                for dependency in &package.dependencies {
+                    if dependency.is_named(name) {
+                        dependents.push(DependentRecord {
+                            package: package.name.clone(),
+                            dependency: dependency_record(&metadata, package, dependency),
+                        });
+                    }
+                }
+            }
+            dependency_versions = metadata
+                .versions_of(name)
+                .into_iter()
+                .map(str::to_string)
+                .collect();
+            let versions = if dependency_versions.is_empty() {
+                "is not in the lockfile".to_string()
+            } else {
+                format!("is locked at {}", dependency_versions.join(", "))
+            };
+            let mut names: Vec<&str> = dependents
+                .iter()
+                .map(|dependent| dependent.package.as_str())
+                .collect();
+            names.dedup();
+            if names.is_empty() {
+                format!("No workspace package depends on `{name}` directly; it {versions}.")
+            } else {
+                format!(
+                    "`{name}` is a dependency of {}; it {versions}.",
+                    names.join(", ")
+                )
+            }
+        } else if let Some(name) = &p.package {
+            let package = metadata
+                .members()
+                .find(|package| &package.name == name)
+                .or_else(|| {
+                    metadata
+                        .packages
+                        .iter()
+                        .find(|package| &package.name == name)
+                })
+                .ok_or_else(|| {
+                    McpError::invalid_params(
+                        format!("no package named `{name}` in the workspace or its dependencies"),
+                        None,
+                    )
+                })?;
+            packages.push(package_record(&metadata, package));
+            format!("Package {name} {}.", package.version)
+        } else {
+            packages = metadata
+                .members()
+                .map(|package| package_record(&metadata, package))
+                .collect();
+            format!(
+                "{} workspace package(s) in {}.",
+                packages.len(),
+                metadata.workspace_root
+            )
+        };
+
+        Ok(Structured(CargoMetadataResponse {
+            workspace_root: metadata.workspace_root.clone(),
+            target_directory: metadata.target_directory.clone(),
+            packages,
+            owner,
+            dependents,
+            dependency_versions,
+            cached,
+            summary,
+        }))
+    }
+
+    /// Expand every macro in a crate or module with `cargo expand`.
+    #[tool(
+        name = "cargo_expand",
+        output_schema = output_schema::<CargoExpandResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Show a whole module or crate with every macro expanded, by running `cargo expand` (the cargo-expand subcommand, installed separately). Pass `file_path` to expand the module a file defines, or `package` with an optional `target` (`lib`, `bin:NAME`, `test:NAME`, `example:NAME`, `bench:NAME`) and `item` (a module path such as `parser::ast`). Useful to see what derives and attribute macros generate. Output is capped by `max_chars` (default 50000)."
+    )]
+    async fn cargo_expand(
+        &self,
+        params: Parameters<CargoExpandParam>,
+    ) -> Result<Structured<CargoExpandResponse>, McpError> {
+        let p = &params.0;
+        let root = self.cargo_root()?;
+        let (package, target, item) = if let Some(file_path) = &p.file_path {
+            validate_file_path(file_path)?;
+            let (metadata, _) = self
+                .metadata
+                .get(&root, Duration::from_secs(DEFAULT_CARGO_TIMEOUT_SECS))
+                .await?;
+            let not_in_target = || {
+                McpError::invalid_params(
+                    format!("{file_path} is not in any target of the workspace's packages"),
+                    None,
+                )
+            };
+            let (package, targets) = metadata
+                .owner(Path::new(file_path))
+                .ok_or_else(not_in_target)?;
+            // A file under `src/` belongs to the library and any binaries
+            // alike; unless it is a binary's root, the library is what other
+            // code sees.
+            let target = targets
+                .iter()
+                .find(|target| Path::new(&target.src_path) == Path::new(file_path))
+                .or_else(|| {
+                    targets.iter().find(|target| {
+                        target
+                            .kind
+                            .iter()
+                            .any(|kind| LIBRARY_KINDS.contains(&kind.as_str()))
+                    })
+                })
+                .or_else(|| targets.first())
+                .ok_or_else(not_in_target)?;
+            let item = metadata::module_path(Path::new(&target.src_path), Path::new(file_path))
+                .ok_or_else(not_in_target)?;
+            let kind = target.kind.first().map_or("lib", String::as_str);
+            let target = if LIBRARY_KINDS.contains(&kind) {
+                "lib".to_string()
+            } else {
+                format!("{kind}:{}", target.name)
+            };
+            (
+                Some(package.name.clone()),
+                Some(target),
+                Some(item).filter(|item| !item.is_empty()),
+            )
+        } else {
+            (p.package.clone(), p.target.clone(), p.item.clone())
+        };
+
+        let mut args = vec!["expand".to_string()];
+        if let Some(package) = package {
+            args.extend(["-p".to_string(), package]);
+        }
+        if let Some(target) = &target {
+            args.extend(target_args(target)?);
+        }
+        if let Some((_, config)) = ProjectConfig::discover(&root)
+            .map_err(|e| McpError::invalid_params(format!("{e:#}"), None))?
+        {
+            args.extend(config.cargo_args());
+        }
+        if let Some(item) = &item {
+            args.push(item.clone());
+        }
+
+        let timeout = Duration::from_secs(p.timeout_secs.unwrap_or(DEFAULT_CARGO_TIMEOUT_SECS));
+        let output = cargo::run(&root, &args, timeout).await?;
+        let command = format!("cargo {}", args.join(" "));
+        if !output.success {
+            if output.stderr.contains("no such command: `expand`") {
+                return Err(McpError::invalid_params(
+                    "`cargo expand` is not available; install it with \
+                     `cargo install cargo-expand` and try again",
+                    None,
+                ));
+            }
+            return Err(McpError::internal_error(
+                format!("`{command}` failed:\n{}", output.stderr.trim_end()),
+                None,
+            ));
+        }
+
+        let max_chars = p.max_chars.unwrap_or(DEFAULT_EXPAND_MAX_CHARS).max(1);
+        let mut expansion = output.stdout;
+        let truncated = expansion.chars().count() > max_chars;
+        if truncated {
+            let end = expansion
+                .char_indices()
+                .nth(max_chars)
+                .map_or(expansion.len(), |(index, _)| index);
+            let end = expansion[..end]
+                .rfind('\n')
+                .map_or(end, |newline| newline + 1);
+            expansion.truncate(end);
+        }
+        let lines = expansion.lines().count();
+        let mut summary = format!(
+            "`{command}`: {lines} line(s) of expanded {}.",
+            item.as_deref()
+                .map_or_else(|| "crate".to_string(), |item| format!("`{item}`"))
+        );
+        if truncated {
+            let _ = write!(
+                summary,
+                " Cut at {max_chars} characters; raise `max_chars` or expand a smaller `item`."
+            );
+        }
+        Ok(Structured(CargoExpandResponse {
+            command,
+            expansion,
+            truncated,
+            summary,
+        }))
+    }
+
+    /// Show the resolved dependency tree, inverted trees, or duplicate versions.
+    #[tool(
+        name = "cargo_tree",
+        output_schema = output_schema::<CargoTreeResponse>(),
+        annotations(read_only_hint = true, open_world_hint = false),
+        description = "Show the workspace's resolved dependency tree, computed from `cargo metadata` like `cargo tree`: each line gives a crate, its locked version, and whether it is a build or dev dependency; a subtree already shown is marked `repeated`. Start from one `package`, limit `depth`, or pass `invert` (a crate name) to see what pulls that crate in, up to the workspace members. `duplicates` lists every crate locked at more than one version, with the packages depending on each version. Use it for dependency audits and upgrades."
+    )]
+    async fn cargo_tree(
+        &self,
+        params: Parameters<CargoTreeParam>,
+    ) -> Result<Structured<CargoTreeResponse>, McpError> {
+        let p = &params.0;
+        let root = self.cargo_root()?;
+        let (metadata, cached) = self
+            .metadata
+            .get(&root, Duration::from_secs(DEFAULT_CARGO_TIMEOUT_SECS))
+            .await?;
+        let label = |package: &Package| format!("{} v{}", package.name, package.version);
+
+        if p.duplicates {
+            let duplicates: Vec<DuplicateRecord> = metadata
+                .duplicates()
+                .into_iter()
+                .map(|duplicate| DuplicateRecord {
+                    name: duplicate.name.to_string(),
+                    versions: duplicate
+                        .versions
+                        .into_iter()
+                        .map(|(package, dependents)| DuplicateVersionRecord {
+                            version: package.version.clone(),
+                            dependents: dependents.into_iter().map(label).collect(),
+                        })
+                        .collect(),
+                })
+                .collect();
+            let summary = if duplicates.is_empty() {
+                "Every crate is locked at a single version.".to_string()
+            } else {
+                format!(
+                    "{} crate(s) are locked at more than one version.",
+                    duplicates.len()
+                )
+            };
+            return Ok(Structured(CargoTreeResponse {
+                entries: Vec::new(),
+                duplicates,
+                truncated: false,
+                cached,
+                summary,
+            }));
+        }
+
+        let inverted = p.invert.is_some();
+        let roots: Vec<&Package> = match (&p.invert, &p.package) {
+            (Some(name), _) => metadata
+                .packages
+                .iter()
+                .filter(|package| {
+                    Dependency::normalize(&package.name) == Dependency::normalize(name)
+                })
+                .collect(),
+            (None, Some(name)) => metadata
+                .members()
+                .find(|package| &package.name == name)
+                .or_else(|| {
+                    metadata
+                        .packages
+                        .iter()
+                        .find(|package| &package.name == name)
+                })
+                .into_iter()
+                .collect(),
+            (None, None) => metadata.members().collect(),
+        };
+        if roots.is_empty() {
+            let name = p.invert.as_ref().or(p.package.as_ref());
+            return Err(McpError::invalid_params(
+                format!(
+                    "no package named `{}` in the workspace or its dependencies",
+                    name.map_or("", String::as_str)
+                ),
+                None,
+            ));
+        }
+        let ids: Vec<&str> = roots.iter().map(|package| package.id.as_str()).collect();
+        let mut entries: Vec<TreeEntryRecord> = metadata
+            .tree(&ids, p.depth, inverted)
+            .into_iter()
+            .map(|entry| TreeEntryRecord {
+                depth: entry.depth,
+                name: entry.package.name.clone(),
+                version: entry.package.version.clone(),
+                kind: entry.kind.to_string(),
+                repeated: entry.repeated,
+            })
+            .collect();
+        let max = p.max_entries.unwrap_or(DEFAULT_TREE_MAX_ENTRIES).max(1);
+        let truncated = entries.len() > max;
+        entries.truncate(max);
+
+        let roots: Vec<String> = roots.into_iter().map(label).collect();
+        let mut summary = if inverted {
+            format!("What depends on {}:", roots.join(", "))
+        } else {
+            format!("Dependencies of {}:", roots.join(", "))
+        };
+        if truncated {
+            let _ = write!(
+                summary,
+                " (first {max} lines; raise `max_entries`, lower `depth`, or pick a `package` to see more)"
+            );
+        }
+        Ok(Structured(CargoTreeResponse {
+            entries,
+            duplicates: Vec::new(),
+            truncated,
+            cached,
+            summary,
+        }))
+    }
+
+    /// Import an unresolved name.
+    #[tool(
+        name = "rust_auto_import",
+        output_schema = output_schema::<EditResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "Add the `use` declaration for a name that is not in scope. Give the unresolved `name` (located by its E0433/E0412/E0425-style diagnostic) or the zero-based `line` and `character` of a use of it. When several paths provide the name, the error lists them; pass one as `path`. Writes the file and returns a unified diff; pass `dry_run` to only preview it."
+    )]
+    async fn auto_import(
+        &self,
+        params: Parameters<AutoImportParam>,
+    ) -> Result<Structured<EditResponse>, McpError> {
+        let p = &params.0;
+        validate_file_path(&p.file_path)?;
+
+        let lsp = self.client().await?;
+        let (range, diagnostics) = match (p.line, p.character, &p.name) {
+            (Some(line), Some(character), _) => {
+                lsp.ensure_file_open(&p.file_path).await.map_err(|e| {
+                    lsp_error(&e, format!("failed to synchronize file with lspmux: {e}"))
+                })?;
+                let position = lsp_types::Position::new(line, character);
+                (lsp_types::Range::new(position, position), Vec::new())
+            }
+            (None, None, Some(name)) => {
+                let diagnostics = document_diagnostics(&lsp, &p.file_path).await?;
+                let diagnostic =
+                    unresolved_name_diagnostic(diagnostics, name).ok_or_else(|| {
+                        McpError::invalid_params(
+                            format!(
+                                "no unresolved `{name}` in {}; pass the `line` and `character` \
+                             of a use of it instead",
+                                p.file_path
+                            ),
+                            None,
+                        )
+                    })?;
+                (diagnostic.range, vec![diagnostic])
+            }
+            _ => {
+                return Err(McpError::invalid_params(
+                    "pass the unresolved `name`, or both `line` and `character`",
+                    None,
+                ))
+            }
+        };
+
+        let actions = lsp
+            .code_actions(&p.file_path, range, diagnostics, None)
+            .await
+            .map_err(|e| lsp_error(&e, format!("code action request failed: {e}")))?;
+        let mut imports: Vec<_> = code_action_literals(actions)
+            .filter(|action| {
+                import_path(&action.title).is_some_and(|path| {
+                    p.name
+                        .as_deref()
+                        .is_none_or(|name| path == name || path.ends_with(&format!("::{name}")))
+                })
+            })
+            .collect();
+        let paths: Vec<String> = imports
+            .iter()
+            .filter_map(|action| import_path(&action.title).map(str::to_string))
+            .collect();
+        let chosen = match (&p.path, paths.len()) {
+            (_, 0) => {
+                return Err(McpError::invalid_params(
+                    format!(
+                        "{SERVER_NAME} offers no import here; is the name defined in the \
+                         workspace or its dependencies?"
+                    ),
+                    None,
+                ))
+            }
+            (Some(path), _) => paths
+                .iter()
+                .position(|found| found == path)
+                .ok_or_else(|| {
+                    McpError::invalid_params(
+                        format!("`{path}` is not a candidate; offered: {}", paths.join(", ")),
+                        None,
+                    )
+                })?,
+            (None, 1) => 0,
+            (None, count) => {
+                return Err(McpError::invalid_params(
+                    format!(
+                        "{count} paths provide this name; pass one as `path`: {}",
+                        paths.join(", ")
+                    ),
+                    None,
+                ))
+            }
+        };
+        let action = imports.swap_remove(chosen);
+        let alternatives = paths
+            .into_iter()
+            .enumerate()
+            .filter_map(|(i, path)| (i != chosen).then_some(path))
+            .collect();
+
+        let (title, edit) = resolved_edit(&lsp, action).await?;
+        let response = apply_workspace_edit(&lsp, title, edit, p.dry_run, alternatives).await?;
+        Ok(Structured(response))
+    }
+
+    /// List the code generators available at a position, or apply one.
+    #[tool(
+        name = "rust_generate",
+        output_schema = output_schema::<GenerateResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "List the code generators rust-analyzer offers at a position (zero-based `line` and `character`, or a `symbol` such as `enum Mode`), such as `Fill match arms`, `Implement missing members`, `Generate impl`, `Add #[derive]`, `Generate getter`, or `Generate new`. Call without `title` to list them (nothing is written); call again with one of the listed titles to apply it. Applying writes the files and returns a unified diff; pass `dry_run` to only preview it."
+    )]
+    async fn generate(
+        &self,
+        params: Parameters<GenerateParam>,
+    ) -> Result<Structured<GenerateResponse>, McpError> {
+        let p = &self.resolve_position(&params.0.position).await?;
+
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let position = lsp_types::Position::new(p.line, p.character);
+        let actions = lsp
+            .code_actions(
+                &p.file_path,
+                lsp_types::Range::new(position, position),
+                Vec::new(),
+                None,
+            )
+            .await
+            .map_err(|e| lsp_error(&e, format!("code action request failed: {e}")))?;
+        let generators: Vec<_> = code_action_literals(actions).filter(is_generator).collect();
+        let available: Vec<String> = generators
+            .iter()
+            .map(|action| action.title.clone())
+            .collect();
+
+        let Some(title) = params.0.title.as_deref() else {
+            let summary = if available.is_empty() {
+                "No generators are available at this position.".to_string()
+            } else {
+                format!(
+                    "{} generator(s) available; pass one as `title` to apply it:",
+                    available.len()
+                )
+            };
+            return Ok(Structured(GenerateResponse {
+                available,
+                edit: None,
+                summary,
+            }));
+        };
+
+        let (action, _) = choose_action(generators, Some(title), "generator")?;
+        let (title, edit) = resolved_edit(&lsp, action).await?;
+        let edit = apply_workspace_edit(&lsp, title, edit, params.0.dry_run, Vec::new()).await?;
+        Ok(Structured(GenerateResponse {
+            available,
+            summary: edit.summary.clone(),
+            edit: Some(edit),
+        }))
+    }
+
+    /// List the completions at a position, or resolve and apply one.
+    #[tool(
+        name = "rust_completions",
+        output_schema = output_schema::<CompletionsResponse>(),
+        annotations(
+            read_only_hint = false,
+            destructive_hint = false,
+            idempotent_hint = false,
+            open_world_hint = false
+        ),
+        description = "List what rust-analyzer would complete at a position (zero-based `line` and `character`, usually just after a partial name or a `.`), with each item's kind and signature; narrow the list with `prefix`. Pick one by `label` (or `index`, among items sharing a label) to resolve its documentation and every edit choosing it makes, including the `use` declaration an auto-import completion adds elsewhere in the file; the edits are returned as a unified diff and written only with `apply: true`."
+    )]
+    async fn completions(
+        &self,
+        params: Parameters<CompletionParam>,
+    ) -> Result<Structured<CompletionsResponse>, McpError> {
+        let p = &self.resolve_position(&params.0.position).await?;
+
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let (is_incomplete, all) = match lsp
+            .completion(&p.file_path, p.line, p.character)
+            .await
+            .map_err(|e| lsp_error(&e, format!("completion request failed: {e}")))?
+        {
+            None => (false, Vec::new()),
+            Some(lsp_types::CompletionResponse::Array(items)) => (false, items),
+            Some(lsp_types::CompletionResponse::List(list)) => (list.is_incomplete, list.items),
+        };
+        let prefix = params.0.prefix.as_deref().map(str::to_lowercase);
+        let mut items: Vec<CompletionRecord> = all
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| {
+                prefix
+                    .as_deref()
+                    .is_none_or(|prefix| item.label.to_lowercase().starts_with(prefix))
+            })
+            .map(|(index, item)| completion_record(index, item))
+            .collect();
+        let sort_text = |record: &CompletionRecord| {
+            let item = &all[record.index];
+            item.sort_text.clone().unwrap_or_else(|| item.label.clone())
+        };
+        items.sort_by_cached_key(sort_text);
+        let max = params.0.max_items.unwrap_or(DEFAULT_MAX_COMPLETIONS).max(1);
+        let truncated = items.len() > max;
+        items.truncate(max);
+        let requested_position = PositionRecord {
+            line: p.line,
+            character: p.character,
+        };
+
+        let Some(index) = pick_completion(&all, params.0.label.as_deref(), params.0.index)? else {
+            let summary = match items.len() {
+                0 => "No completions at this position.".to_string(),
+                count => format!(
+                    "{count} completion(s){}; pass one's `label` or `index` to see its \
+                     documentation and edits.",
+                    if truncated { ", more left out" } else { "" }
+                ),
+            };
+            return Ok(Structured(CompletionsResponse {
+                file_path: p.file_path.clone(),
+                requested_position,
+                items,
+                is_incomplete,
+                truncated,
+                selected: None,
+                edit: None,
+                summary,
+            }));
+        };
+
+        let label = all[index].label.clone();
+        let item = lsp
+            .resolve_completion(all[index].clone())
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to resolve `{label}`: {e}")))?;
+        let position = lsp_types::Position::new(p.line, p.character);
+        let text_edit = completion_text_edit(&item, position);
+        let additional = item.additional_text_edits.clone().unwrap_or_default();
+        let uri = file_uri(&p.file_path)
+            .map_err(|e| McpError::invalid_params(format!("invalid file path: {e}"), None))?;
+        let workspace_edit = completion_workspace_edit(uri, &item, &text_edit, &additional);
+        let edit = apply_workspace_edit(
+            &lsp,
+            format!("Complete `{label}`"),
+            workspace_edit,
+            !params.0.apply,
+            Vec::new(),
+        )
+        .await?;
+        let mut summary = edit.summary.clone();
+        if !additional.is_empty() {
+            let _ = write!(
+                summary,
+                " It also makes {} edit(s) elsewhere in the file, such as adding a `use` \
+                 declaration.",
+                additional.len()
+            );
+        }
+        Ok(Structured(CompletionsResponse {
+            file_path: p.file_path.clone(),
+            requested_position,
+            items,
+            is_incomplete,
+            truncated,
+            selected: Some(CompletionDetailRecord {
+                label,
+                detail: item.detail.clone(),
+                documentation: item.documentation.map(|documentation| match documentation {
+                    lsp_types::Documentation::String(text) => text,
+                    lsp_types::Documentation::MarkupContent(markup) => markup.value,
+                }),
+                text_edit: TextEditRecord {
+                    range: range_record(&text_edit.range),
+                    new_text: if item.insert_text_format
+                        == Some(lsp_types::InsertTextFormat::SNIPPET)
+                    {
+                        snippet::parse(&text_edit.new_text).text
+                    } else {
+                        text_edit.new_text.clone()
+                    },
+                },
+                additional_edits: additional.iter().map(text_edit_record).collect(),
+            }),
+            edit: Some(edit),
+            summary,
+        }))
+    }
+}
+
+/// Delegation methods for `ServerHandler` integration.
+impl RustAnalyzerTools {
+    /// List the tools supported by the connected language server, or every
+    /// enabled tool while it has not been started yet.
+    pub fn list_tools(&self) -> ListToolsResult {
+        let capabilities = self.lsp.get().map(|client| client.capabilities());
+        ListToolsResult {
+            tools: self
+                .tool_router
+                .list_all()
+                .into_iter()
+                .filter(|tool| {
+                    !self.disabled.contains(tool.name.as_ref())
+                        && capabilities
+                            .as_ref()
+                            .is_none_or(|capabilities| tool_supported(&tool.name, capabilities))
+                })
+                .map(with_shared_params)
+                .collect(),
+            ..ListToolsResult::default()
+        }
+    }
+
+    /// Notify `peer` with `notifications/tools/list_changed` once the language
+    /// server starts and whenever its capabilities change (e.g. after it reloads).
+    pub fn spawn_tool_list_watcher(&self, peer: Peer<RoleServer>) {
+        let lsp = Arc::clone(&self.lsp);
+        tokio::spawn(async move {
+            // Each pass follows one client, until it is restarted.
+            loop {
+                let mut capabilities = lsp.wait_started().await.watch_capabilities();
+                // The list served before startup assumed every tool was
+                // supported, and a restarted client may support others.
+                capabilities.mark_changed();
+                while capabilities.changed().await.is_ok() {
+                    if let Err(e) = peer.notify_tool_list_changed().await {
+                        tracing::debug!("failed to send tools/list_changed notification: {e}");
+                        return;
+                    }
+                }
+            }
+        });
+    }
+
+    /// Send `peer` a warning log message whenever the watchdog restarts a
+    /// hung rust-analyzer connection.
+    pub fn spawn_warning_forwarder(&self, peer: Peer<RoleServer>) {
+        let Some(watchdog) = &self.watchdog else {
+            return;
+        };
+        let mut warnings = watchdog.subscribe();
+        tokio::spawn(async move {
+            loop {
+                let warning = match warnings.recv().await {
+                    Ok(warning) => warning,
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return,
+                };
+                let param = LoggingMessageNotificationParam {
+                    level: LoggingLevel::Warning,
+                    logger: Some(env!("CARGO_PKG_NAME").to_string()),
+                    data: serde_json::Value::from(warning),
+                };
+                if let Err(e) = peer.notify_logging_message(param).await {
+                    tracing::debug!("failed to send a log message notification: {e}");
+                    return;
+                }
+            }
+        });
+    }
+
+    /// Call a tool by name.
+    pub async fn call_tool(
+        &self,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let tool_name = request.name.clone();
+        let correlation_id = next_correlation_id();
+        // Every log line of the call, including those of the LSP requests it
+        // makes, carries the correlation id.
+        let span = tracing::info_span!(
+            "tool_call",
+            tool = %tool_name,
+            correlation_id = %correlation_id
+        );
+        let mut result = self
+            .call_tool_traced(&tool_name, request, context)
+            .instrument(span)
+            .await;
+        // Returned so a report from the client can be matched to the logs.
+        match &mut result {
+            Ok(result) => {
+                result
+                    .meta
+                    .get_or_insert_with(Meta::new)
+                    .insert(CORRELATION_ID_FIELD.to_string(), correlation_id.into());
+            }
+            Err(error) => {
+                if let serde_json::Value::Object(data) = error
+                    .data
+                    .get_or_insert_with(|| serde_json::Value::Object(JsonObject::new()))
+                {
+                    data.insert(CORRELATION_ID_FIELD.to_string(), correlation_id.into());
+                }
+            }
+        }
+        result
+    }
+
+    /// [`Self::call_tool`] inside its tracing span.
+    async fn call_tool_traced(
+        &self,
+        tool_name: &str,
+        request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        let client = self.telemetry.client_identity();
+        let started = Instant::now();
+        tracing::info!(
+            event = "tool_start",
+            client_kind = %client.kind,
+            client_host = %client.host,
+            session_id = %client.session_id,
+            mcp_session_id = self.session_id().unwrap_or("-")
+        );
+        let result = self.dispatch(tool_name, request, context).await;
+        let latency_ms = started.elapsed().as_millis();
+        let latency_ms_u64 = u64::try_from(latency_ms).unwrap_or(u64::MAX);
+
+        if let Some(session_id) = self.session_id() {
+            let outcome = result
+                .as_ref()
+                .map_or_else(classify_tool_error, |_| ToolOutcome::Success);
+            self.telemetry
+                .record_session_call(session_id, tool_name, outcome);
+        }
+
+        match &result {
+            Ok(_) => {
+                self.telemetry.record_tool_result(
+                    tool_name,
+                    ToolOutcome::Success,
+                    latency_ms_u64,
+                    None,
+                    None,
+                );
+                tracing::info!(
+                    event = "tool_result",
+                    outcome = "success",
+                    latency_ms = latency_ms
+                );
+                if tool_name != "rust_server_status" {
+                    self.telemetry
+                        .refresh_compiler_accounting(self.lsp.workspace_root());
+                }
+            }
+            Err(error) => {
+                let outcome = classify_tool_error(error);
+                self.telemetry.record_tool_result(
+                    tool_name,
+                    outcome,
+                    latency_ms_u64,
+                    Some(error_code_name(error.code)),
+                    Some(&error.message),
+                );
+                tracing::warn!(
+                    event = "tool_result",
+                    outcome = %outcome.as_str(),
+                    error_code = ?error.code,
+                    error = %error.message,
+                    latency_ms = latency_ms
+                );
+            }
+        }
+
+        result
+    }
+
+    /// Run one tool call after the disabled, startup, project, and capability
+    /// checks, once the call limits admit it, racing it against client
+    /// cancellation.
+    async fn dispatch(
+        &self,
+        tool_name: &str,
+        mut request: CallToolRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CallToolResult, McpError> {
+        if self.disabled.contains(tool_name) {
+            return Err(McpError::invalid_params(
+                format!("{tool_name} is disabled in the lspmux-cc configuration"),
+                None,
+            ));
+        }
+        let output = OutputFormat::take(request.arguments.as_mut())?;
+        let tools = self
+            .take_workspace(request.arguments.as_mut())?
+            .map_or(Cow::Borrowed(self), Cow::Owned);
+        tools
+            .prepare_call(tool_name, request.arguments.as_ref())
+            .await?;
+        if let Some(client) = tools.lsp.get() {
+            if let Some(feature) = missing_capability(tool_name, &client.capabilities()) {
+                return Err(McpError::invalid_params(
+                    format!(
+                        "the connected {SERVER_NAME} does not support {feature}, which \
+                         {tool_name} needs"
+                    ),
+                    None,
+                ));
+            }
+        }
+
+        // One forwarder reports every phase, so progress never goes back.
+        let mut progress = context
+            .meta
+            .get_progress_token()
+            .map(ProgressForwarder::new);
+        let peer = context.peer.clone();
+        let cancelled = context.ct.clone();
+        tokio::select! {
+            biased;
+            () = cancelled.cancelled() => {
+                tracing::info!(event = "tool_cancelled", tool = %tool_name);
+                return Err(McpError::internal_error("tool call cancelled by the client", None));
+            }
+            loaded = tools.await_workspace(tool_name, progress.as_mut(), &peer) => loaded?,
+        }
+        // Held until the call finishes.
+        let _permit = tokio::select! {
+            biased;
+            () = cancelled.cancelled() => {
+                tracing::info!(event = "tool_cancelled", tool = %tool_name);
+                return Err(McpError::internal_error("tool call cancelled by the client", None));
+            }
+            permit = self.admit(tool_name, progress.as_mut(), &peer) => permit?,
+        };
+        let ctx = ToolCallContext::new(&*tools, request, context);
+        let call = tools.tool_router.call(ctx);
+        let call = async {
+            match progress {
+                Some(forwarder) => tools.forward_progress(forwarder, peer, call).await,
+                None => call.await,
+            }
+        };
+        // Dropping `call` on cancellation drops any in-flight LSP request,
+        // which sends `$/cancelRequest` to rust-analyzer.
+        tokio::select! {
+            biased;
+            () = cancelled.cancelled() => {
+                tracing::info!(event = "tool_cancelled", tool = %tool_name);
+                Err(McpError::internal_error("tool call cancelled by the client", None))
+            }
+            result = call => output.apply(result.map_err(|error| tools.with_stderr_tail(error))?),
+        }
+    }
+
+    /// Append the last lines the language server wrote to stderr to an
+    /// internal `error`, where a panic or out-of-memory abort shows up.
+    fn with_stderr_tail(&self, mut error: McpError) -> McpError {
+        if error.code == ErrorCode::INTERNAL_ERROR {
+            error.message = self.lsp.stderr_tail().annotate(&error.message).into();
+        }
+        error
+    }
+
+    /// Remove the `workspace_root` argument from `arguments` and, if the call
+    /// belongs to a workspace other than the default one, return these tools
+    /// bound to that workspace's launcher: the one named, or else the one
+    /// detected around `file_path` (see [`LspPool::launcher_for_file`]).
+    fn take_workspace(&self, arguments: Option<&mut JsonObject>) -> Result<Option<Self>, McpError> {
+        let (named, file_path) = arguments.map_or((None, None), |arguments| {
+            let file_path = arguments
+                .get("file_path")
+                .and_then(serde_json::Value::as_str)
+                .map(PathBuf::from);
+            (arguments.remove("workspace_root"), file_path)
+        });
+        let lsp = match named {
+            None | Some(serde_json::Value::Null) => file_path.map_or_else(
+                || self.pool.detected_launcher(),
+                |file_path| self.pool.launcher_for_file(&file_path),
+            ),
+            Some(serde_json::Value::String(root)) => self.pool.launcher(&root).map(Some),
+            Some(_) => {
+                return Err(McpError::invalid_params(
+                    "`workspace_root` must be a string",
+                    None,
+                ))
+            }
+        }
+        .map_err(|e| McpError::invalid_params(format!("{e:#}"), None))?;
+        let Some(lsp) = lsp.filter(|lsp| !Arc::ptr_eq(lsp, &self.lsp)) else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            lsp,
+            ..self.clone()
+        }))
+    }
+
+    /// Take a slot for a `tool_name` call under the call limits, waiting in
+    /// the queue if they are reached. A queued caller is told so through a
+    /// progress notification.
+    async fn admit(
+        &self,
+        tool_name: &str,
+        progress: Option<&mut ProgressForwarder>,
+        peer: &Peer<RoleServer>,
+    ) -> Result<CallPermit, McpError> {
+        if let Some(permit) = self.limits.try_acquire(tool_name) {
+            return Ok(permit);
+        }
+        let reason = self.limits.busy_reason(tool_name);
+        tracing::info!(event = "tool_queued", reason = %reason);
+        counter!("lspmux_cc_tool_queued_total", "tool" => tool_name.to_string()).increment(1);
+        if let Some(forwarder) = progress {
+            let param = forwarder.message(format!("server busy, queued: {reason}"));
+            if let Err(e) = peer.notify_progress(param).await {
+                tracing::debug!("failed to send MCP progress notification: {e}");
+            }
+        }
+        let queued = Instant::now();
+        let permit = tokio::time::timeout(MAX_QUEUE_WAIT, self.limits.acquire(tool_name))
+            .await
+            .map_err(|_| {
+                timeout_error(format!(
+                    "server busy: timed out after {}s in the queue ({reason}); retry later",
+                    MAX_QUEUE_WAIT.as_secs()
+                ))
+            })?;
+        histogram!("lspmux_cc_tool_queue_wait_seconds", "tool" => tool_name.to_string())
+            .record(queued.elapsed().as_secs_f64());
+        tracing::info!(
+            event = "tool_dequeued",
+            queued_ms = queued.elapsed().as_millis()
+        );
+        Ok(permit)
+    }
+
+    /// Hold a query while rust-analyzer loads the workspace, until it is
+    /// quiescent or the index wait runs out, telling the client what it
+    /// waits for; then refuse it if the load failed.
+    async fn await_workspace(
+        &self,
+        tool_name: &str,
+        mut progress: Option<&mut ProgressForwarder>,
+        peer: &Peer<RoleServer>,
+    ) -> Result<(), McpError> {
+        if NO_AUTOSTART_TOOLS.contains(&tool_name) || ANALYSIS_FREE_TOOLS.contains(&tool_name) {
+            return Ok(());
+        }
+        let Some(lsp) = self.lsp.get() else {
+            return Ok(());
+        };
+        let mut readiness = lsp.readiness().await;
+        let limit = self.index_wait.limit();
+        if readiness.quiescent == Some(false) && !limit.is_zero() {
+            let _waiter = self.index_wait.try_enter().ok_or_else(|| {
+                internal_error(format!(
+                    "server busy: {MAX_INDEX_WAITERS} calls already wait for {SERVER_NAME} to \
+                     finish indexing; retry later"
+                ))
+            })?;
+            tracing::info!(event = "tool_awaiting_index", tool = %tool_name);
+            let started = Instant::now();
+            let deadline = started + limit;
+            while readiness.quiescent == Some(false) && lsp.is_alive() && Instant::now() < deadline
+            {
+                if let Some(forwarder) = progress.as_deref_mut() {
+                    let param = forwarder.waiting(
+                        started.elapsed().as_secs_f64() / limit.as_secs_f64(),
+                        format!(
+                            "waiting for {SERVER_NAME} to finish indexing: {}",
+                            indexing_activity(&lsp.active_progress().await)
+                        ),
+                    );
+                    if let Err(e) = peer.notify_progress(param).await {
+                        tracing::debug!("failed to send MCP progress notification: {e}");
+                    }
+                }
+                let next_update = (Instant::now() + INDEX_WAIT_UPDATE).min(deadline);
+                readiness = warmup::wait_until_quiescent(&lsp, next_update).await;
+            }
+            histogram!("lspmux_cc_tool_index_wait_seconds", "tool" => tool_name.to_string())
+                .record(started.elapsed().as_secs_f64());
+            if readiness.quiescent == Some(false) {
+                tracing::info!(
+                    event = "tool_index_wait_expired",
+                    tool = %tool_name,
+                    "still indexing after {}s; running the call anyway",
+                    limit.as_secs()
+                );
+            }
+        }
+        load_failure(&readiness).map_or(Ok(()), Err)
+    }
+
+    /// Drive `work` to completion while relaying rust-analyzer `$/progress`
+    /// events to the MCP client through `forwarder`.
+    async fn forward_progress<F: Future>(
+        &self,
+        mut forwarder: ProgressForwarder,
+        peer: Peer<RoleServer>,
+        work: F,
+    ) -> F::Output {
+        tokio::pin!(work);
+        // `work` may be what starts the client, so wait for it alongside.
+        let client = tokio::select! {
+            biased;
+            output = &mut work => return output,
+            client = self.lsp.wait_started() => client,
+        };
+        let mut events = client.subscribe_progress();
+        loop {
+            tokio::select! {
+                biased;
+                output = &mut work => return output,
+                event = events.recv() => match event {
+                    Ok(event) => {
+                        let param = forwarder.next(&event);
+                        if let Err(e) = peer.notify_progress(param).await {
+                            tracing::debug!("failed to send MCP progress notification: {e}");
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::debug!("dropped {skipped} stale progress event(s)");
+                    }
+                    Err(RecvError::Closed) => return work.await,
+                },
+            }
+        }
+    }
+
+    /// Query rust-analyzer for the diagnostics of each of `files`, several at
+    /// once, in no particular order.
+    async fn sweep_diagnostics(
+        &self,
+        files: Vec<PathBuf>,
+    ) -> Vec<(String, Result<Vec<DiagnosticRecord>, McpError>)> {
+        stream::iter(files)
+            .map(|file| {
+                let file_path = file.display().to_string();
+                async move {
+                    let diagnostics = self.collect_diagnostics(&file_path).await;
+                    (file_path, diagnostics)
+                }
+            })
+            .buffer_unordered(DIR_DIAGNOSTICS_CONCURRENCY)
+            .collect()
+            .await
+    }
+
+    /// The diagnostics snapshot called `name`.
+    fn snapshot(&self, name: &str) -> Result<Snapshot, McpError> {
+        self.snapshots.get(name).ok_or_else(|| {
+            let names = self.snapshots.names();
+            let known = if names.is_empty() {
+                "none have been taken".to_string()
+            } else {
+                format!("known snapshots: {}", names.join(", "))
+            };
+            McpError::invalid_params(
+                format!(
+                    "no diagnostics snapshot named `{name}` ({known}); take one with \
+                     rust_diagnostics_snapshot"
+                ),
+                None,
+            )
+        })
+    }
+
+    /// The diagnostics under `path` matching `filter`, sorted by file, for a
+    /// snapshot; and how many files were checked, whether more matched, and
+    /// the files that could not be checked.
+    async fn snapshot_sweep(
+        &self,
+        path: &str,
+        max_files: usize,
+        filter: &DiagnosticFilter,
+    ) -> Result<(Vec<DiagnosticRecord>, usize, bool, Vec<FileFailure>), McpError> {
+        let (files, truncated) = rust_files(path, max_files)?;
+        let files_checked = files.len();
+        let mut diagnostics = Vec::new();
+        let mut failures = Vec::new();
+        for (file_path, result) in self.sweep_diagnostics(files).await {
+            match result {
+                Ok(found) => diagnostics.extend(found.into_iter().filter(|d| filter.matches(d))),
+                Err(e) => failures.push(FileFailure {
+                    file_path,
+                    error: e.message.to_string(),
+                }),
+            }
+        }
+        diagnostics.sort_by(|a, b| {
+            a.location
+                .file_path
+                .cmp(&b.location.file_path)
+                .then_with(|| a.index.cmp(&b.index))
+        });
+        failures.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+        Ok((diagnostics, files_checked, truncated, failures))
+    }
+
+    /// Query rust-analyzer for every diagnostic in `file`.
+    async fn collect_diagnostics(&self, file: &str) -> Result<Vec<DiagnosticRecord>, McpError> {
+        validate_file_path(file)?;
+
+        let lsp = self.client().await?;
+        let uri = file_uri(file)
+            .map_err(|e| McpError::invalid_params(format!("invalid file path: {e}"), None))?;
+        Ok(document_diagnostics(&lsp, file)
+            .await?
+            .into_iter()
+            .enumerate()
+            .map(|(index, diagnostic)| diagnostic_record(&uri, index, diagnostic))
+            .collect())
+    }
+
+    /// The `rustc --explain` text of each distinct error code among
+    /// `diagnostics`. Codes rustc cannot explain are left out.
+    async fn explain_codes(&self, diagnostics: &[DiagnosticRecord]) -> Vec<ErrorExplanation> {
+        let root = self.cargo_root().unwrap_or_else(|_| PathBuf::from("."));
+        let codes: std::collections::BTreeSet<String> = diagnostics
+            .iter()
+            .filter_map(|diagnostic| explain::error_code(diagnostic.code.as_deref()?))
+            .collect();
+        let mut explanations = Vec::new();
+        for code in codes {
+            match self.explanations.get(&root, &code).await {
+                Ok((Some(explanation), _)) => explanations.push(ErrorExplanation {
+                    code,
+                    explanation: explanation.to_string(),
+                }),
+                Ok((None, _)) => {}
+                Err(e) => tracing::warn!("failed to explain {code}: {}", e.message),
+            }
+        }
+        explanations
+    }
+
+    /// The position `p` names: its `line` and `character`, its byte
+    /// `offset`, or the start of the name of its `symbol`.
+    async fn resolve_position(&self, p: &PositionParam) -> Result<FilePosition, McpError> {
+        match (&p.symbol, &p.file_path, p.line, p.character, p.offset) {
+            (None, Some(file_path), Some(line), Some(character), None) => {
+                validate_file_path(file_path)?;
+                Ok(FilePosition {
+                    file_path: file_path.clone(),
+                    line: zero_based(line, p.index_base, "line")?,
+                    character: zero_based(character, p.index_base, "character")?,
+                })
+            }
+            (None, Some(file_path), None, None, Some(offset)) => {
+                validate_file_path(file_path)?;
+                let text = std::fs::read_to_string(file_path).map_err(|e| {
+                    McpError::invalid_params(format!("failed to read {file_path}: {e}"), None)
+                })?;
+                let position = edits::position_at(&text, offset).ok_or_else(|| {
+                    McpError::invalid_params(
+                        format!(
+                            "offset {offset} is past the end of {file_path} ({} bytes) or \
+                             inside a character",
+                            text.len()
+                        ),
+                        None,
+                    )
+                })?;
+                Ok(FilePosition {
+                    file_path: file_path.clone(),
+                    line: position.line,
+                    character: position.character,
+                })
+            }
+            (Some(symbol), _, None, None, None) => {
+                self.resolve_symbol(symbol, p.file_path.as_deref()).await
+            }
+            (None, _, None, None, None) => Err(McpError::invalid_params(
+                "pass `file_path` with `line` and `character` or `offset`, or `symbol`",
+                None,
+            )),
+            _ => Err(McpError::invalid_params(
+                "pass one of `line` and `character` (both, with `file_path`), `offset` \
+                 (with `file_path`), or `symbol`",
+                None,
+            )),
+        }
+    }
+
+    /// The start of the name of `symbol`, looked up among the symbols of
+    /// `file_path` or, without it, across the workspace.
+    async fn resolve_symbol(
+        &self,
+        symbol: &str,
+        file_path: Option<&str>,
+    ) -> Result<FilePosition, McpError> {
+        let query = SymbolQuery::parse(symbol)?;
+
+        let lsp = self.client().await?;
+        let mut candidates: Vec<IndexedSymbol> = if let Some(file_path) = file_path {
+            validate_file_path(file_path)?;
+            lsp.ensure_file_open(file_path).await.map_err(|e| {
+                lsp_error(&e, format!("failed to synchronize file with lspmux: {e}"))
+            })?;
+            let symbols = lsp
+                .document_symbols(file_path)
+                .await
+                .map_err(|e| lsp_error(&e, format!("document symbol request failed: {e}")))?;
+            symbols
+                .map(|symbols| symbols::flatten(symbols, file_path))
+                .unwrap_or_default()
+        } else {
+            let symbols = lsp
+                .workspace_symbols(query.name)
+                .await
+                .map_err(|e| lsp_error(&e, format!("workspace symbol search failed: {e}")))?;
+            workspace_symbol_candidates(symbols)
+        };
+        candidates.retain(|candidate| query.matches(candidate));
+        candidates.sort_by(|a, b| {
+            a.file_path
+                .cmp(&b.file_path)
+                .then_with(|| a.range.start.cmp(&b.range.start))
+        });
+        candidates.dedup_by(|a, b| a.file_path == b.file_path && a.range.start == b.range.start);
+
+        let scope = file_path.unwrap_or("the workspace");
+        match candidates.as_slice() {
+            [found] => Ok(FilePosition {
+                file_path: found.file_path.clone(),
+                line: found.range.start.line,
+                character: found.range.start.character,
+            }),
+            [] => Err(McpError::invalid_params(
+                format!("no symbol matching `{symbol}` in {scope}"),
+                None,
+            )),
+            several => {
+                let listed: Vec<String> = several
+                    .iter()
+                    .take(10)
+                    .map(|candidate| {
+                        let container = candidate
+                            .container
+                            .as_deref()
+                            .map(|container| format!(" in `{container}`"))
+                            .unwrap_or_default();
+                        format!(
+                            "{} `{}`{container} at {}:{}",
+                            symbol_kind_name(candidate.kind),
+                            candidate.name,
+                            candidate.file_path,
+                            candidate.range.start.line + 1
+                        )
+                    })
+                    .collect();
+                Err(McpError::invalid_params(
+                    format!(
+                        "`{symbol}` matches {} symbols in {scope}: {}; qualify it (`Type::name`, \
+                         `fn name`) or pass `file_path`",
+                        several.len(),
+                        listed.join("; ")
+                    ),
+                    None,
+                ))
+            }
+        }
+    }
+
+    /// Query rust-analyzer for every reference to the symbol at `p`.
+    async fn collect_references(
+        &self,
+        p: &FilePosition,
+        include_declaration: bool,
+    ) -> Result<Vec<LocationRecord>, McpError> {
+        let lsp = self.client().await?;
+        let content_hash = lsp
+            .ensure_file_open(&p.file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+
+        let key = CacheKey::new(
+            "textDocument/references",
+            &p.file_path,
+            content_hash,
+            format!("{}:{}:{include_declaration}", p.line, p.character),
+        );
+        let references = self
+            .cache
+            .get_or_fetch(lsp.generation(), key, async {
+                lsp.find_references(&p.file_path, p.line, p.character, include_declaration)
+                    .await
+                    .map_err(|e| lsp_error(&e, format!("find references failed: {e}")))
+            })
+            .await?;
+        Ok(normalize_locations(
+            references
+                .unwrap_or_default()
+                .into_iter()
+                .map(|location| location_record(&location.uri, &location.range))
+                .collect(),
+        ))
+    }
+
+    /// The name of the symbol at `p` and the references to it from outside
+    /// its own declaration.
+    async fn symbol_delete_blockers(
+        &self,
+        p: &FilePosition,
+    ) -> Result<(String, Vec<SafeDeleteBlockerRecord>), McpError> {
+        let with_declaration = self.collect_references(p, true).await?;
+        let references = self.collect_references(p, false).await?;
+        let declaration = with_declaration.into_iter().find(|location| {
+            !references
+                .iter()
+                .any(|reference| location_key(reference) == location_key(location))
+        });
+
+        // The declaration's full range, so uses inside the body do not count.
+        let mut name = None;
+        let mut extent = None;
+        if let Some(declaration) = &declaration {
+            let lsp = self.client().await?;
+            lsp.ensure_file_open(&declaration.file_path)
+                .await
+                .map_err(|e| {
+                    lsp_error(&e, format!("failed to synchronize file with lspmux: {e}"))
+                })?;
+            let symbols = lsp
+                .document_symbols(&declaration.file_path)
+                .await
+                .map_err(|e| lsp_error(&e, format!("document symbol request failed: {e}")))?;
+            let start = lsp_types::Position::new(declaration.line - 1, declaration.column - 1);
+            if let Some((symbol, range)) =
+                symbols.and_then(|symbols| declaring_symbol(symbols, start))
+            {
+                name = Some(symbol);
+                extent = Some((declaration.file_path.clone(), range));
+            }
+        }
+        let name = name.unwrap_or_else(|| {
+            format!(
+                "symbol at {}:{}:{}",
+                p.file_path,
+                p.line + 1,
+                p.character + 1
+            )
+        });
+        let blockers = references
+            .into_iter()
+            .filter(|location| {
+                extent
+                    .as_ref()
+                    .is_none_or(|(file_path, range)| !location_within(location, file_path, range))
+            })
+            .map(|location| SafeDeleteBlockerRecord {
+                symbol: name.clone(),
+                location,
+            })
+            .collect();
+        Ok((name, blockers))
+    }
+
+    /// How many symbols `file_path` declares, and the references to them
+    /// from other files. Impl blocks are skipped; their items are checked.
+    async fn file_delete_blockers(
+        &self,
+        file_path: &str,
+    ) -> Result<(usize, Vec<SafeDeleteBlockerRecord>), McpError> {
+        let lsp = self.client().await?;
+        lsp.ensure_file_open(file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+        let symbols = lsp
+            .document_symbols(file_path)
+            .await
+            .map_err(|e| lsp_error(&e, format!("document symbol request failed: {e}")))?
+            .map(|symbols| symbols::flatten(symbols, file_path))
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|symbol| symbol.kind != lsp_types::SymbolKind::OBJECT)
+            .collect::<Vec<_>>();
+        // References come back with canonical paths.
+        let canonical = std::fs::canonicalize(file_path)
+            .map_or_else(|_| file_path.to_string(), |path| path.display().to_string());
+
+        let mut blockers = Vec::new();
+        for symbol in &symbols {
+            let p = FilePosition {
+                file_path: file_path.to_string(),
+                line: symbol.range.start.line,
+                character: symbol.range.start.character,
+            };
+            blockers.extend(
+                self.collect_references(&p, false)
+                    .await?
+                    .into_iter()
+                    .filter(|location| location.file_path != canonical)
+                    .map(|location| SafeDeleteBlockerRecord {
+                        symbol: symbol.name.clone(),
+                        location,
+                    }),
+            );
+        }
+        Ok((symbols.len(), blockers))
+    }
+
+    /// Query rust-analyzer for every workspace symbol matching `query`.
+    async fn collect_workspace_symbols(
+        &self,
+        query: &str,
+    ) -> Result<Vec<WorkspaceSymbolRecord>, McpError> {
+        let symbols = self
+            .client()
+            .await?
+            .workspace_symbols(query.to_string())
+            .await
+            .map_err(|e| lsp_error(&e, format!("workspace symbol search failed: {e}")))?;
+
+        Ok(normalize_symbols(match symbols {
+            Some(lsp_types::WorkspaceSymbolResponse::Flat(symbols)) => symbols
+                .into_iter()
+                .map(|symbol| WorkspaceSymbolRecord {
+                    name: symbol.name,
+                    kind: symbol_kind_name(symbol.kind).to_string(),
+                    container_name: symbol.container_name,
+                    location: location_record(&symbol.location.uri, &symbol.location.range),
+                })
+                .collect(),
+            Some(lsp_types::WorkspaceSymbolResponse::Nested(symbols)) => symbols
+                .into_iter()
+                .filter_map(|symbol| {
+                    if let lsp_types::OneOf::Left(location) = symbol.location {
+                        Some(WorkspaceSymbolRecord {
+                            name: symbol.name,
+                            kind: symbol_kind_name(symbol.kind).to_string(),
+                            container_name: symbol.container_name,
+                            location: location_record(&location.uri, &location.range),
+                        })
+                    } else {
+                        None
+                    }
+                })
+                .collect(),
+            None => vec![],
+        }))
+    }
+}
+
+/// Up to five distinct names of `symbols` that come closest to `name`.
+fn closest_names(name: &str, symbols: &[IndexedSymbol]) -> Vec<String> {
+    let mut scored: Vec<(u32, &str)> = symbols
+        .iter()
+        .filter_map(|symbol| Some((symbols::fuzzy_score(name, &symbol.name)?, &*symbol.name)))
+        .collect();
+    scored.sort_by(|(a_score, a), (b_score, b)| b_score.cmp(a_score).then_with(|| a.cmp(b)));
+    let mut names: Vec<String> = Vec::new();
+    for (_, candidate) in scored {
+        if names.len() == 5 {
+            break;
+        }
+        if !names.iter().any(|known| known == candidate) {
+            names.push(candidate.to_string());
+        }
+    }
+    names
+}
+
+/// The symbols of a `workspace/symbol` response, located at their names.
+fn workspace_symbol_candidates(
+    symbols: Option<lsp_types::WorkspaceSymbolResponse>,
+) -> Vec<IndexedSymbol> {
+    match symbols {
+        Some(lsp_types::WorkspaceSymbolResponse::Flat(symbols)) => symbols
+            .into_iter()
+            .map(|symbol| IndexedSymbol {
+                file_path: uri_to_path(&symbol.location.uri),
+                name: symbol.name,
+                kind: symbol.kind,
+                container: symbol.container_name,
+                range: symbol.location.range,
+            })
+            .collect(),
+        Some(lsp_types::WorkspaceSymbolResponse::Nested(symbols)) => symbols
+            .into_iter()
+            .filter_map(|symbol| {
+                let lsp_types::OneOf::Left(location) = symbol.location else {
+                    return None;
+                };
+                Some(IndexedSymbol {
+                    file_path: uri_to_path(&location.uri),
+                    name: symbol.name,
+                    kind: symbol.kind,
+                    container: symbol.container_name,
+                    range: location.range,
+                })
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Synchronize `file` and pull its diagnostics, in the server's order.
+async fn document_diagnostics(
+    lsp: &LspClient,
+    file: &str,
+) -> Result<Vec<lsp_types::Diagnostic>, McpError> {
+    lsp.ensure_file_open(file)
+        .await
+        .map_err(|e| lsp_error(&e, format!("failed to synchronize file with lspmux: {e}")))?;
+
+    let uri = file_uri(file)
+        .map_err(|e| McpError::invalid_params(format!("invalid file path: {e}"), None))?;
+    let diag_params = lsp_types::DocumentDiagnosticParams {
+        text_document: lsp_types::TextDocumentIdentifier { uri },
+        identifier: None,
+        previous_result_id: None,
+        work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
+        partial_result_params: lsp_types::PartialResultParams::default(),
+    };
+
+    let report = lsp
+        .request::<lsp_types::request::DocumentDiagnosticRequest>(diag_params)
+        .await
+        .map_err(|e| {
+            internal_error(format!(
+                "diagnostics request failed: {e}. rust-analyzer may still be indexing"
+            ))
+        })?;
+
+    Ok(match report {
+        lsp_types::DocumentDiagnosticReportResult::Report(
+            lsp_types::DocumentDiagnosticReport::Full(full),
+        ) => full.full_document_diagnostic_report.items,
+        lsp_types::DocumentDiagnosticReportResult::Report(
+            lsp_types::DocumentDiagnosticReport::Unchanged(_),
+        )
+        | lsp_types::DocumentDiagnosticReportResult::Partial(_) => vec![],
+    })
+}
+
+/// Target kinds cargo builds with `--lib`.
+const LIBRARY_KINDS: &[&str] = &["lib", "rlib", "dylib", "cdylib", "staticlib", "proc-macro"];
+
+/// The cargo arguments selecting `target`, given as `lib` or `KIND:NAME`.
+fn target_args(target: &str) -> Result<Vec<String>, McpError> {
+    match target.split_once(':') {
+        None if target == "lib" => Ok(vec!["--lib".to_string()]),
+        Some((kind @ ("bin" | "test" | "example" | "bench"), name)) if !name.is_empty() => {
+            Ok(vec![format!("--{kind}"), name.to_string()])
+        }
+        _ => Err(McpError::invalid_params(
+            format!(
+                "cannot expand target {target:?}; use `lib`, `bin:NAME`, `test:NAME`, \
+                 `example:NAME`, or `bench:NAME`"
+            ),
+            None,
+        )),
+    }
+}
+
+fn package_record(metadata: &Metadata, package: &Package) -> PackageRecord {
+    PackageRecord {
+        name: package.name.clone(),
+        version: package.version.clone(),
+        manifest_path: package.manifest_path.clone(),
+        edition: package.edition.clone(),
+        features: package.features.clone(),
+        targets: package
+            .targets
+            .iter()
+            .map(|target| TargetRecord {
+                name: target.name.clone(),
+                kinds: target.kind.clone(),
+                src_path: target.src_path.clone(),
+            })
+            .collect(),
+        dependencies: package
+            .dependencies
+            .iter()
+            .map(|dependency| dependency_record(metadata, package, dependency))
+            .collect(),
+    }
+}
+
+fn dependency_record(
+    metadata: &Metadata,
+    package: &Package,
+    dependency: &Dependency,
+) -> DependencyRecord {
+    DependencyRecord {
+        name: dependency.name.clone(),
+        rename: dependency.rename.clone(),
+        req: dependency.req.clone(),
+        resolved: metadata
+            .resolved_version(package, dependency)
+            .map(str::to_string),
+        kind: dependency
+            .kind
+            .clone()
+            .unwrap_or_else(|| "normal".to_string()),
+        optional: dependency.optional,
+    }
+}
+
+fn cargo_diagnostic_record(message: cargo::CompilerMessage) -> CargoDiagnosticRecord {
+    let location = message.span.as_ref().and_then(|span| {
+        let uri = file_uri(&span.file_path).ok()?;
+        let range = lsp_types::Range::new(
+            lsp_types::Position::new(
+                span.line_start.saturating_sub(1),
+                span.column_start.saturating_sub(1),
+            ),
+            lsp_types::Position::new(
+                span.line_end.saturating_sub(1),
+                span.column_end.saturating_sub(1),
+            ),
+        );
+        Some(location_record(&uri, &range))
+    });
+    CargoDiagnosticRecord {
+        severity: message.level,
+        message: message.message,
+        code: message.code,
+        package: message.package,
+        location,
+        rendered: message.rendered,
+    }
+}
+
+/// The first diagnostic saying `name` is not in scope.
+fn unresolved_name_diagnostic(
+    diagnostics: Vec<lsp_types::Diagnostic>,
+    name: &str,
+) -> Option<lsp_types::Diagnostic> {
+    let quoted = format!("`{name}`");
+    diagnostics.into_iter().find(|diagnostic| {
+        diagnostic
+            .code
+            .as_ref()
+            .is_some_and(|code| UNRESOLVED_NAME_CODES.contains(&diagnostic_code(code).as_str()))
+            && diagnostic.message.contains(&quoted)
+    })
+}
+
+/// The path an auto-import action titled e.g. "Import `std::fmt::Write`"
+/// imports.
+fn import_path(title: &str) -> Option<&str> {
+    title.strip_prefix("Import `")?.strip_suffix('`')
+}
+
+/// The diagnostic `params` names, by index or by code (and line), with its index.
+fn select_diagnostic(
+    mut diagnostics: Vec<lsp_types::Diagnostic>,
+    params: &QuickfixParam,
+) -> Result<(usize, lsp_types::Diagnostic), McpError> {
+    let count = diagnostics.len();
+    let index = match (params.index, &params.code) {
+        (Some(index), _) => Some(index).filter(|&index| index < count).ok_or_else(|| {
+            McpError::invalid_params(
+                format!(
+                    "no diagnostic #{index}: {} has {count} diagnostic(s); rerun rust_diagnostics",
+                    params.file_path
+                ),
+                None,
+            )
+        })?,
+        (None, Some(code)) => diagnostics
+            .iter()
+            .position(|diagnostic| {
+                diagnostic
+                    .code
+                    .as_ref()
+                    .is_some_and(|found| diagnostic_code(found) == *code)
+                    && params
+                        .line
+                        .is_none_or(|line| diagnostic.range.start.line + 1 == line)
+            })
+            .ok_or_else(|| {
+                let line = params
+                    .line
+                    .map(|line| format!(" on line {line}"))
+                    .unwrap_or_default();
+                McpError::invalid_params(
+                    format!("no {code} diagnostic{line} in {}", params.file_path),
+                    None,
+                )
+            })?,
+        (None, None) => {
+            return Err(McpError::invalid_params(
+                "pass the diagnostic's `index` or `code` from rust_diagnostics",
+                None,
+            ))
+        }
+    };
+    Ok((index, diagnostics.swap_remove(index)))
+}
+
+/// Whether `action` fixes `diagnostic`. Actions that do not say which
+/// diagnostics they fix are assumed to fix the one they were requested for.
+fn fixes_diagnostic(action: &lsp_types::CodeAction, diagnostic: &lsp_types::Diagnostic) -> bool {
+    action.diagnostics.as_ref().is_none_or(|fixed| {
+        fixed.is_empty()
+            || fixed
+                .iter()
+                .any(|d| d.range == diagnostic.range && d.message == diagnostic.message)
+    })
+}
+
+/// The action to apply and the titles of the others: the one titled
+/// `title` if given, else the one the server prefers (for compiler
+/// suggestions, the machine-applicable fix), else the only one offered.
+/// `what` names the kind of action in errors, e.g. "quick fix".
+fn choose_action(
+    mut actions: Vec<lsp_types::CodeAction>,
+    title: Option<&str>,
+    what: &str,
+) -> Result<(lsp_types::CodeAction, Vec<String>), McpError> {
+    let titles = actions
+        .iter()
+        .map(|action| action.title.clone())
+        .collect::<Vec<_>>();
+    let chosen = match title {
+        Some(title) => actions
+            .iter()
+            .position(|action| action.title == title)
+            .ok_or_else(|| {
+                McpError::invalid_params(
+                    format!(
+                        "no {what} titled {title:?}; offered: {}",
+                        if titles.is_empty() {
+                            "none".to_string()
+                        } else {
+                            titles.join("; ")
+                        }
+                    ),
+                    None,
+                )
+            })?,
+        None => match actions
+            .iter()
+            .position(|action| action.is_preferred == Some(true))
+        {
+            Some(preferred) => preferred,
+            None if actions.len() == 1 => 0,
+            None if actions.is_empty() => {
+                return Err(McpError::invalid_params(
+                    format!("{SERVER_NAME} offers no {what} here"),
+                    None,
+                ))
+            }
+            None => {
+                return Err(McpError::invalid_params(
+                    format!(
+                        "{} options are offered and none is preferred; pass `title` to choose one of: {}",
+                        actions.len(),
+                        titles.join("; ")
+                    ),
+                    None,
+                ))
+            }
+        },
+    };
+    let action = actions.remove(chosen);
+    let alternatives = titles
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, title)| (i != chosen).then_some(title))
+        .collect();
+    Ok((action, alternatives))
+}
+
+/// Whether `action` generates code: rust-analyzer files its generate assists
+/// under the empty kind, and some (like "Fill match arms") as quick fixes.
+fn is_generator(action: &lsp_types::CodeAction) -> bool {
+    action
+        .kind
+        .as_ref()
+        .is_none_or(|kind| kind.as_str().is_empty() || kind.as_str().starts_with("generate"))
+        || GENERATOR_PREFIXES
+            .iter()
+            .any(|prefix| action.title.starts_with(prefix))
+}
+
+/// The locations a `rust-analyzer.gotoLocation` or
+/// `rust-analyzer.showReferences` command points to.
+fn command_locations(
+    command: &str,
+    arguments: &[serde_json::Value],
+) -> Result<Vec<lsp_types::Location>, McpError> {
+    let invalid = |e: String| {
+        McpError::invalid_params(format!("invalid arguments for `{command}`: {e}"), None)
+    };
+    if command == "rust-analyzer.gotoLocation" {
+        let location = arguments
+            .first()
+            .ok_or_else(|| invalid("expected a location".to_string()))?;
+        serde_json::from_value(location.clone())
+            .map(|location| vec![location])
+            .map_err(|e| invalid(e.to_string()))
+    } else {
+        // `[uri, position, locations]`, as for VS Code's `editor.action.showReferences`.
+        let locations = arguments
+            .get(2)
+            .ok_or_else(|| invalid("expected a uri, a position, and locations".to_string()))?;
+        serde_json::from_value(locations.clone()).map_err(|e| invalid(e.to_string()))
+    }
+}
+
+/// The last [`MAX_RUN_OUTPUT_BYTES`] of `output`.
+fn output_tail(mut output: String) -> String {
+    if output.len() <= MAX_RUN_OUTPUT_BYTES {
+        return output;
+    }
+    let mut start = output.len() - MAX_RUN_OUTPUT_BYTES;
+    while !output.is_char_boundary(start) {
+        start += 1;
+    }
+    output.replace_range(..start, "[output truncated]\n");
+    output
+}
+
+/// The completion `label` or `index` picks among `items`, if either is
+/// given.
+fn pick_completion(
+    items: &[lsp_types::CompletionItem],
+    label: Option<&str>,
+    index: Option<usize>,
+) -> Result<Option<usize>, McpError> {
+    match (label, index) {
+        (None, None) => Ok(None),
+        (Some(_), Some(_)) => Err(McpError::invalid_params(
+            "pass either `label` or `index`, not both",
+            None,
+        )),
+        (None, Some(index)) if index < items.len() => Ok(Some(index)),
+        (None, Some(index)) => Err(McpError::invalid_params(
+            format!("no completion has index {index}; there are {}", items.len()),
+            None,
+        )),
+        (Some(label), None) => {
+            let found: Vec<usize> = (0..items.len())
+                .filter(|&i| items[i].label == label)
+                .collect();
+            match found.as_slice() {
+                [] => Err(McpError::invalid_params(
+                    format!("no completion is labelled `{label}`"),
+                    None,
+                )),
+                [index] => Ok(Some(*index)),
+                several => Err(McpError::invalid_params(
+                    format!(
+                        "{} completions are labelled `{label}`; pass one's `index`: {}",
+                        several.len(),
+                        several
+                            .iter()
+                            .map(|&i| completion_record(i, &items[i]))
+                            .map(|item| format!(
+                                "{} ({})",
+                                item.index,
+                                item.source.or(item.detail).unwrap_or_default()
+                            ))
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    ),
+                    None,
+                )),
+            }
+        }
+    }
+}
+
+fn completion_record(index: usize, item: &lsp_types::CompletionItem) -> CompletionRecord {
+    CompletionRecord {
+        index,
+        label: item.label.clone(),
+        kind: item.kind.map(|kind| completion_kind_name(kind).to_string()),
+        detail: item.detail.clone(),
+        source: item
+            .label_details
+            .as_ref()
+            .and_then(|details| details.description.clone()),
+    }
+}
+
+/// The edit a completion makes at `position`, as a server without text
+/// edits leaves it to the client: inserting its text there.
+fn completion_text_edit(
+    item: &lsp_types::CompletionItem,
+    position: lsp_types::Position,
+) -> lsp_types::TextEdit {
+    match &item.text_edit {
+        Some(lsp_types::CompletionTextEdit::Edit(edit)) => edit.clone(),
+        Some(lsp_types::CompletionTextEdit::InsertAndReplace(edit)) => {
+            lsp_types::TextEdit::new(edit.insert, edit.new_text.clone())
+        }
+        None => lsp_types::TextEdit::new(
+            lsp_types::Range::new(position, position),
+            item.insert_text
+                .clone()
+                .unwrap_or_else(|| item.label.clone()),
+        ),
+    }
+}
+
+/// The edit choosing `item` makes: `text_edit` at the completed position,
+/// marked as a snippet if it is one, and the `additional` edits elsewhere.
+fn completion_workspace_edit(
+    uri: lsp_types::Uri,
+    item: &lsp_types::CompletionItem,
+    text_edit: &lsp_types::TextEdit,
+    additional: &[lsp_types::TextEdit],
+) -> lsp_types::WorkspaceEdit {
+    let main = if item.insert_text_format == Some(lsp_types::InsertTextFormat::SNIPPET) {
+        lsp_types::OneOf::Right(lsp_types::AnnotatedTextEdit {
+            text_edit: text_edit.clone(),
+            annotation_id: SNIPPET_ANNOTATION.to_string(),
+        })
+    } else {
+        lsp_types::OneOf::Left(text_edit.clone())
+    };
+    lsp_types::WorkspaceEdit {
+        document_changes: Some(lsp_types::DocumentChanges::Edits(vec![
+            lsp_types::TextDocumentEdit {
+                text_document: lsp_types::OptionalVersionedTextDocumentIdentifier {
+                    uri,
+                    version: None,
+                },
+                edits: std::iter::once(main)
+                    .chain(additional.iter().cloned().map(lsp_types::OneOf::Left))
+                    .collect(),
+            },
+        ])),
+        ..lsp_types::WorkspaceEdit::default()
+    }
+}
+
+fn text_edit_record(edit: &lsp_types::TextEdit) -> TextEditRecord {
+    TextEditRecord {
+        range: range_record(&edit.range),
+        new_text: edit.new_text.clone(),
+    }
+}
+
+/// Resolve the code action a tool picked, so its edit is computed only
+/// now, and return its title and edit.
+async fn resolved_edit(
+    lsp: &LspClient,
+    action: lsp_types::CodeAction,
+) -> Result<(String, lsp_types::WorkspaceEdit), McpError> {
+    let title = action.title.clone();
+    let action = lsp
+        .resolve_code_action(action)
+        .await
+        .map_err(|e| lsp_error(&e, format!("failed to resolve `{title}`: {e}")))?;
+    action_edit(action)
+}
+
+/// The title and edit of a resolved code action. One that runs an editor
+/// command instead is refused with the command to run.
+fn action_edit(
+    action: lsp_types::CodeAction,
+) -> Result<(String, lsp_types::WorkspaceEdit), McpError> {
+    match (action.edit, action.command) {
+        (Some(edit), _) => Ok((action.title, edit)),
+        (None, Some(command)) => Err(McpError::invalid_params(
+            format!(
+                "`{}` runs an editor command instead of editing files; pass its command \
+                 to `rust_execute_command`: {}",
+                action.title,
+                serde_json::to_string(&command).unwrap_or_default()
+            ),
+            None,
+        )),
+        (None, None) => Err(internal_error(format!(
+            "{SERVER_NAME} returned `{}` without an edit",
+            action.title
+        ))),
+    }
+}
+
+/// The code actions among `actions`, leaving out bare commands.
+fn code_action_literals(
+    actions: Option<lsp_types::CodeActionResponse>,
+) -> impl Iterator<Item = lsp_types::CodeAction> {
+    actions
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|action| match action {
+            lsp_types::CodeActionOrCommand::CodeAction(action) => Some(action),
+            lsp_types::CodeActionOrCommand::Command(_) => None,
+        })
+}
+
+/// A range covering all of `text`.
+fn whole_file_range(text: &str) -> lsp_types::Range {
+    let lines = u32::try_from(text.lines().count()).unwrap_or(u32::MAX);
+    lsp_types::Range::new(
+        lsp_types::Position::new(0, 0),
+        lsp_types::Position::new(lines, 0),
+    )
+}
+
+/// The first action of `kind` over `range` whose title `accept`s, resolved.
+async fn find_code_action(
+    lsp: &LspClient,
+    file: &str,
+    range: lsp_types::Range,
+    kind: lsp_types::CodeActionKind,
+    accept: impl Fn(&str) -> bool,
+) -> Result<Option<lsp_types::CodeAction>, McpError> {
+    let actions = lsp
+        .code_actions(file, range, Vec::new(), Some(vec![kind.clone()]))
+        .await
+        .map_err(|e| lsp_error(&e, format!("code action request failed: {e}")))?;
+    let Some(action) = code_action_literals(actions).find(|action| {
+        action
+            .kind
+            .as_ref()
+            .is_none_or(|found| found.as_str().starts_with(kind.as_str()))
+            && accept(&action.title)
+    }) else {
+        return Ok(None);
+    };
+    lsp.resolve_code_action(action)
+        .await
+        .map(Some)
+        .map_err(|e| internal_error(format!("failed to resolve `{}`: {e}", kind.as_str())))
+}
+
+/// The text edits `action` makes to `file`, refusing actions that edit other
+/// files too.
+fn single_file_edits(
+    file: &str,
+    action: lsp_types::CodeAction,
+) -> Result<Vec<lsp_types::TextEdit>, McpError> {
+    let (title, edit) = action_edit(action)?;
+    let mut edits = Vec::new();
+    for file_edits in edits::text_edits_by_file(edit)? {
+        if file_edits.file_path != file {
+            return Err(McpError::invalid_params(
+                format!("`{title}` also edits {}", file_edits.file_path),
+                None,
+            ));
+        }
+        edits.extend(file_edits.edits);
+    }
+    Ok(edits)
+}
+
+/// Organize the imports of `file`, whose content is `original`: with the
+/// server's own "organize imports" action if it has one, otherwise by
+/// removing unused imports, merging (with `merge`), and sorting. Returns the
+/// new content and a description of each step taken.
+///
+/// Merging is computed against a preview of the previous steps, which the
+/// caller must drop with [`LspClient::invalidate_document`].
+async fn organize(
+    lsp: &LspClient,
+    file: &str,
+    original: &str,
+    merge: bool,
+) -> Result<(String, Vec<String>), McpError> {
+    let organize_kind = lsp_types::CodeActionKind::SOURCE_ORGANIZE_IMPORTS;
+    let range = whole_file_range(original);
+    if let Some(action) = find_code_action(lsp, file, range, organize_kind, |_| true).await? {
+        let title = action.title.clone();
+        let text = edits::apply_text_edits(original, &single_file_edits(file, action)?)?;
+        return Ok((text, vec![title]));
+    }
+
+    let mut steps = Vec::new();
+    let mut text = remove_unused_imports(lsp, file, original, &mut steps).await?;
+    if merge {
+        if text != original {
+            lsp.preview_document(file, text.clone())
+                .await
+                .map_err(|e| lsp_error(&e, format!("failed to preview the edit: {e}")))?;
+        }
+        let is_merge = |title: &str| title.eq_ignore_ascii_case("merge imports");
+        let rewrite = lsp_types::CodeActionKind::REFACTOR_REWRITE;
+        let range = whole_file_range(&text);
+        if let Some(action) = find_code_action(lsp, file, range, rewrite, is_merge).await? {
+            steps.push(action.title.clone());
+            text = edits::apply_text_edits(&text, &single_file_edits(file, action)?)?;
+        } else {
+            tracing::debug!("{SERVER_NAME} offered no import merge for {file}");
+        }
+    }
+
+    let sorted = edits::sort_use_runs(&text);
+    if sorted != text {
+        steps.push("Sort imports".to_string());
+        text = sorted;
+    }
+    Ok((text, steps))
+}
+
+/// `original` with its unused imports removed, by the server's action for
+/// the whole file if it has one, else by the fix for each unused-import
+/// diagnostic (skipping fixes that overlap ones already taken).
+async fn remove_unused_imports(
+    lsp: &LspClient,
+    file: &str,
+    original: &str,
+    steps: &mut Vec<String>,
+) -> Result<String, McpError> {
+    let quickfix = lsp_types::CodeActionKind::QUICKFIX;
+    let is_removal = |title: &str| title.to_lowercase().contains("unused import");
+    let range = whole_file_range(original);
+    if let Some(action) = find_code_action(lsp, file, range, quickfix, is_removal).await? {
+        steps.push(action.title.clone());
+        return edits::apply_text_edits(original, &single_file_edits(file, action)?);
+    }
+
+    let unused: Vec<_> = document_diagnostics(lsp, file)
+        .await?
+        .into_iter()
+        .filter(|diagnostic| {
+            diagnostic
+                .code
+                .as_ref()
+                .is_some_and(|code| diagnostic_code(code) == "unused_imports")
+        })
+        .collect();
+    let (text, removed) = apply_preferred_fixes(lsp, file, original, unused).await?;
+    if removed > 0 {
+        steps.push(format!("Remove {removed} unused import(s)"));
+    }
+    Ok(text)
+}
+
+/// `original` with the preferred quick fix of each of `diagnostics` applied,
+/// skipping diagnostics without one and fixes that overlap ones already
+/// taken, and how many fixes were taken.
+async fn apply_preferred_fixes(
+    lsp: &LspClient,
+    file: &str,
+    original: &str,
+    diagnostics: Vec<lsp_types::Diagnostic>,
+) -> Result<(String, usize), McpError> {
+    let quickfix = lsp_types::CodeActionKind::QUICKFIX;
+    let mut taken = Vec::new();
+    let mut fixed = 0;
+    for diagnostic in diagnostics {
+        let actions = lsp
+            .code_actions(
+                file,
+                diagnostic.range,
+                vec![diagnostic.clone()],
+                Some(vec![quickfix.clone()]),
+            )
+            .await
+            .map_err(|e| lsp_error(&e, format!("code action request failed: {e}")))?;
+        let Some(fix) = code_action_literals(actions).find(|action| {
+            action.is_preferred == Some(true) && fixes_diagnostic(action, &diagnostic)
+        }) else {
+            continue;
+        };
+        let fix = lsp
+            .resolve_code_action(fix)
+            .await
+            .map_err(|e| lsp_error(&e, format!("failed to resolve the fix: {e}")))?;
+        let mut candidate = taken.clone();
+        candidate.extend(single_file_edits(file, fix)?);
+        if edits::apply_text_edits(original, &candidate).is_ok() {
+            taken = candidate;
+            fixed += 1;
+        }
+    }
+    Ok((edits::apply_text_edits(original, &taken)?, fixed))
+}
+
+/// How many of `diagnostics` have `severity`.
+fn severity_count(diagnostics: &[DiagnosticRecord], severity: &str) -> usize {
+    diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity == severity)
+        .count()
+}
+
+/// Whether `diagnostic` reports unused or dead code: the `dead_code` and
+/// `unused_*` lints (rust-analyzer's own checks included), and anything else
+/// tagged unnecessary except code disabled by `#[cfg]`. Unused results are
+/// left out; they are not code to remove.
+fn is_unused(diagnostic: &DiagnosticRecord) -> bool {
+    let code = diagnostic
+        .code
+        .as_deref()
+        .map(|code| code.replace('-', "_"));
+    match code.as_deref() {
+        Some("inactive_code" | "unused_must_use" | "unused_results") => false,
+        Some(code) if code == "dead_code" || code.starts_with("unused_") => true,
+        _ => diagnostic.tags.iter().any(|tag| tag == "unnecessary"),
+    }
+}
+
+/// How many findings each lint reported across `files`, most first.
+fn unused_counts(files: &[UnusedFileRecord]) -> Vec<UnusedCodeCount> {
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    for diagnostic in files.iter().flat_map(|file| &file.diagnostics) {
+        *counts
+            .entry(diagnostic.code.as_deref().unwrap_or("unnecessary"))
+            .or_default() += 1;
+    }
+    let mut counts: Vec<UnusedCodeCount> = counts
+        .into_iter()
+        .map(|(code, count)| UnusedCodeCount {
+            code: code.to_string(),
+            count,
+        })
+        .collect();
+    counts.sort_by_key(|count| std::cmp::Reverse(count.count));
+    counts
+}
+
+/// The content of `file` before and after fixing its unused code, and how
+/// many fixes were taken.
+async fn fix_unused_in(lsp: &LspClient, file: &str) -> Result<(String, String, usize), McpError> {
+    let original = std::fs::read_to_string(file)
+        .map_err(|e| McpError::invalid_params(format!("failed to read {file}: {e}"), None))?;
+    let uri = file_uri(file)
+        .map_err(|e| McpError::invalid_params(format!("invalid file path: {e}"), None))?;
+    let mut unused = Vec::new();
+    for (index, diagnostic) in document_diagnostics(lsp, file)
+        .await?
+        .into_iter()
+        .enumerate()
+    {
+        if is_unused(&diagnostic_record(&uri, index, diagnostic.clone())) {
+            unused.push(diagnostic);
+        }
+    }
+    let (edited, count) = apply_preferred_fixes(lsp, file, &original, unused).await?;
+    Ok((original, edited, count))
+}
+
+/// Apply the preferred quick fix of the unused code in each of `files`, as
+/// one edit, and count the fixes taken. Files that cannot be fixed are added
+/// to `failures` and left out of the edit.
+async fn fix_unused(
+    lsp: &LspClient,
+    files: &[UnusedFileRecord],
+    dry_run: bool,
+    failures: &mut Vec<FileFailure>,
+) -> Result<(usize, EditResponse), McpError> {
+    let paths: Vec<String> = files.iter().map(|file| file.file_path.clone()).collect();
+    let results: Vec<_> = stream::iter(paths)
+        .map(|file_path| async move {
+            let fixed = fix_unused_in(lsp, &file_path).await;
+            (file_path, fixed)
+        })
+        .buffer_unordered(DIR_DIAGNOSTICS_CONCURRENCY)
+        .collect()
+        .await;
+
+    let mut fixed_count = 0;
+    let mut changes = Vec::new();
+    for (file_path, fixed) in results {
+        match fixed {
+            Ok((original, edited, count)) => {
+                fixed_count += count;
+                changes.push((file_path, original, edited));
+            }
+            Err(e) => failures.push(FileFailure {
+                file_path,
+                error: format!("fix failed: {}", e.message),
+            }),
+        }
+    }
+    let plan = edits::Plan::modify_all(changes);
+    let response = finish_edit(
+        lsp,
+        "Remove unused code".to_string(),
+        plan,
+        dry_run,
+        Vec::new(),
+    )
+    .await?;
+    Ok((fixed_count, response))
+}
+
+/// Apply `edit` with [`finish_edit`], after replaying it in memory.
+async fn apply_workspace_edit(
+    lsp: &LspClient,
+    title: String,
+    edit: lsp_types::WorkspaceEdit,
+    dry_run: bool,
+    alternatives: Vec<String>,
+) -> Result<EditResponse, McpError> {
+    let plan = edits::plan(edits::operations(edit), lsp)?;
+    finish_edit(lsp, title, plan, dry_run, alternatives).await
+}
+
+/// Write `plan` unless `dry_run`, resynchronize the files it touches with
+/// the server, and describe the result.
+///
+/// Refuses if a file no longer has the content the server computed the edit
+/// against.
+async fn finish_edit(
+    lsp: &LspClient,
+    title: String,
+    plan: edits::Plan,
+    dry_run: bool,
+    alternatives: Vec<String>,
+) -> Result<EditResponse, McpError> {
+    plan.check_synced(lsp)?;
+
+    let applied = !dry_run && !plan.changes.is_empty();
+    let backup = if applied {
+        let backup = edits::commit(&plan, &edits::backup_root())?;
+        for path in plan.removed_paths() {
+            if let Err(e) = lsp.close_document(path) {
+                tracing::warn!("failed to close {path}: {e:#}");
+            }
+        }
+        for change in plan.changes.iter().filter(|change| change.edited.is_some()) {
+            lsp.invalidate_document(&change.file_path);
+            if let Err(e) = lsp.ensure_file_open(&change.file_path).await {
+                tracing::warn!("failed to resynchronize {}: {e:#}", change.file_path);
+            }
+        }
+        backup
+    } else {
+        None
+    };
+
+    let count = plan.changes.len();
+    let mut summary = if count == 0 {
+        format!("`{title}` makes no changes.")
+    } else if dry_run {
+        format!("Dry run: `{title}` would change {count} file(s); nothing was written.")
+    } else {
+        format!("Applied `{title}`, changing {count} file(s).")
+    };
+    if let Some(backup) = &backup {
+        let _ = write!(summary, " Originals are backed up in {}.", backup.display());
+    }
+    Ok(EditResponse {
+        title,
+        dry_run,
+        applied,
+        files: plan
+            .changes
+            .iter()
+            .map(|change| FileDiff {
+                file_path: change.file_path.clone(),
+                diff: change.diff(),
+            })
+            .collect(),
+        alternatives,
+        backup_dir: backup.map(|dir| dir.display().to_string()),
+        tab_stops: plan
+            .tab_stops
+            .iter()
+            .filter_map(|stop| {
+                Some(TabStopRecord {
+                    index: stop.index,
+                    placeholder: stop.placeholder.clone(),
+                    location: location_record(&file_uri(&stop.file_path).ok()?, &stop.range),
+                })
+            })
+            .collect(),
+        summary,
+    })
+}
+
+/// Maps LSP work-done progress onto a single monotonic MCP progress stream.
+///
+/// rust-analyzer may report several concurrent progress tokens (indexing,
+/// cargo check, ...); MCP requires `progress` to never decrease, so the
+/// highest percentage seen so far is reported. The waits before a call runs
+/// report through the same forwarder, so they cannot set it back either.
+struct ProgressForwarder {
+    token: ProgressToken,
+    progress: f64,
+    titles: HashMap<String, String>,
+}
+
+impl ProgressForwarder {
+    fn new(token: ProgressToken) -> Self {
+        Self {
+            token,
+            progress: 0.0,
+            titles: HashMap::new(),
+        }
+    }
+
+    /// An update while a call waits for indexing, `fraction` of the way
+    /// through the wait.
+    fn waiting(&mut self, fraction: f64, message: String) -> ProgressNotificationParam {
+        self.progress = self
+            .progress
+            .max((fraction.clamp(0.0, 1.0) * INDEX_WAIT_PROGRESS).floor());
+        self.message(message)
+    }
+
+    /// An update with `message` that leaves the progress where it is.
+    fn message(&self, message: String) -> ProgressNotificationParam {
+        ProgressNotificationParam {
+            progress_token: self.token.clone(),
+            progress: self.progress,
+            total: Some(100.0),
+            message: Some(message),
+        }
+    }
+
+    fn next(&mut self, event: &ProgressEvent) -> ProgressNotificationParam {
+        if let Some(title) = &event.title {
+            self.titles.insert(event.token.clone(), title.clone());
+        }
+        let title = if event.kind == "end" {
+            self.titles.remove(&event.token)
+        } else {
+            self.titles.get(&event.token).cloned()
+        };
+        if let Some(percentage) = event.percentage {
+            self.progress = self.progress.max(f64::from(percentage.min(100)));
+        }
+        let message = match (title, &event.message) {
+            (Some(title), Some(message)) => Some(format!("{title}: {message}")),
+            (Some(title), None) if event.kind == "end" => Some(format!("{title}: done")),
+            (Some(title), None) => Some(title),
+            (None, message) => message.clone(),
+        };
+        ProgressNotificationParam {
+            progress_token: self.token.clone(),
+            progress: self.progress,
+            total: Some(100.0),
+            message,
+        }
+    }
+}
+
+fn classify_tool_error(error: &McpError) -> ToolOutcome {
+    if error.code == ErrorCode::INVALID_PARAMS {
+        ToolOutcome::InvalidParams
+    } else if is_timeout_error(error) {
+        ToolOutcome::Timeout
+    } else {
+        ToolOutcome::Failure
+    }
+}
+
+const fn error_code_name(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::PARSE_ERROR => "parse_error",
+        ErrorCode::INVALID_REQUEST => "invalid_request",
+        ErrorCode::METHOD_NOT_FOUND => "method_not_found",
+        ErrorCode::INVALID_PARAMS => "invalid_params",
+        ErrorCode::INTERNAL_ERROR => "internal_error",
+        ErrorCode::RESOURCE_NOT_FOUND => "resource_not_found",
+        ErrorCode::URL_ELICITATION_REQUIRED => "url_elicitation_required",
+        _ => "other",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use lspmux_cc_mcp::bootstrap::{BootstrapMode, LspTransport, RuntimeConfig};
+    use lspmux_cc_mcp::mock_lsp;
+
+    use super::*;
+
+    #[test]
+    fn validate_file_path_rejects_relative() {
+        let err = validate_file_path("relative/path.rs").unwrap_err();
+        assert!(err.message.contains("must be absolute"));
+    }
+
+    #[test]
+    fn validate_file_path_rejects_nonexistent() {
+        let err = validate_file_path("/nonexistent/path/to/file.rs").unwrap_err();
+        assert!(err.message.contains("file not found"));
+    }
+
+    #[test]
+    fn validate_file_path_accepts_existing_absolute() {
+        let manifest = env!("CARGO_MANIFEST_DIR");
+        let path = format!("{manifest}/Cargo.toml");
+        assert!(validate_file_path(&path).is_ok());
+    }
+
+    #[test]
+    fn workspace_symbol_param_deserializes() {
+        let json = serde_json::json!({ "query": "MyStruct" });
+        let param: WorkspaceSymbolParam = serde_json::from_value(json).unwrap();
+        assert_eq!(param.query, "MyStruct");
+        assert!(param.page.cursor.is_none());
+    }
+
+    #[test]
+    fn references_param_flattens_position_and_page() {
+        let json = serde_json::json!({
+            "file_path": "/tmp/test.rs",
+            "line": 3,
+            "character": 7,
+            "limit": 50,
+            "cursor": "0:50",
+        });
+        let param: ReferencesParam = serde_json::from_value(json).unwrap();
+        assert_eq!(param.position.line, Some(3));
+        assert_eq!(param.page.limit, Some(50));
+        assert_eq!(param.page.cursor.as_deref(), Some("0:50"));
+    }
+
+    #[test]
+    fn one_based_coordinates_count_from_zero_internally() {
+        assert_eq!(zero_based(7, None, "line").unwrap(), 7);
+        assert_eq!(zero_based(7, Some(1), "line").unwrap(), 6);
+        assert!(zero_based(0, Some(1), "line").is_err());
+        assert!(zero_based(7, Some(2), "line").is_err());
+
+        let json = serde_json::json!({
+            "file_path": "/tmp/test.rs",
+            "start_line": 3,
+            "start_character": 1,
+            "end_line": 4,
+            "end_character": 9,
+            "index_base": 1,
+        });
+        let range: RangeParam = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            range.lsp_range().unwrap(),
+            lsp_types::Range::new(
+                lsp_types::Position::new(2, 0),
+                lsp_types::Position::new(3, 8)
+            )
+        );
+    }
+
+    #[test]
+    fn no_params_deserializes_from_empty_object() {
+        let json = serde_json::json!({});
+        let _param: NoParams = serde_json::from_value(json).unwrap();
+    }
+
+    #[test]
+    fn warmup_does_not_wait_by_default() {
+        let param: WarmupParam = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert!(!param.wait_until_ready);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn the_declaring_symbol_is_the_innermost_one_named_at_the_position() {
+        let range = |start: u32, end: u32| {
+            lsp_types::Range::new(
+                lsp_types::Position::new(start, 0),
+                lsp_types::Position::new(end, 1),
+            )
+        };
+        let symbol = |name: &str, whole, selection, children| lsp_types::DocumentSymbol {
+            name: name.to_string(),
+            detail: None,
+            kind: lsp_types::SymbolKind::FUNCTION,
+            tags: None,
+            deprecated: None,
+            range: whole,
+            selection_range: selection,
+            children,
+        };
+        let symbols = || {
+            lsp_types::DocumentSymbolResponse::Nested(vec![
+                symbol("helper", range(0, 2), range(0, 0), None),
+                symbol(
+                    "impl Config",
+                    range(4, 20),
+                    range(4, 4),
+                    Some(vec![symbol("load", range(6, 12), range(6, 6), None)]),
+                ),
+            ])
+        };
+
+        let at = |line| lsp_types::Position::new(line, 0);
+        assert_eq!(
+            declaring_symbol(symbols(), at(6)),
+            Some(("load".to_string(), range(6, 12)))
+        );
+        assert_eq!(
+            declaring_symbol(symbols(), at(0)),
+            Some(("helper".to_string(), range(0, 2)))
+        );
+        // Inside a body, but not on any symbol's name.
+        assert_eq!(declaring_symbol(symbols(), at(8)), None);
+        assert_eq!(declaring_symbol(symbols(), at(3)), None);
+    }
+
+    #[test]
+    fn completions_are_picked_by_label_or_index() {
+        let item = |label: &str, source: &str| lsp_types::CompletionItem {
+            label: label.to_string(),
+            label_details: Some(lsp_types::CompletionItemLabelDetails {
+                detail: None,
+                description: Some(source.to_string()),
+            }),
+            ..lsp_types::CompletionItem::default()
+        };
+        let items = [
+            item("HashMap", "std::collections::HashMap"),
+            item("HashMap", "hashbrown::HashMap"),
+            item("HashSet", "std::collections::HashSet"),
+        ];
+        assert_eq!(pick_completion(&items, None, None).unwrap(), None);
+        assert_eq!(
+            pick_completion(&items, Some("HashSet"), None).unwrap(),
+            Some(2)
+        );
+        assert_eq!(pick_completion(&items, None, Some(1)).unwrap(), Some(1));
+        let ambiguous = pick_completion(&items, Some("HashMap"), None).unwrap_err();
+        assert!(
+            ambiguous.message.contains("1 (hashbrown::HashMap)"),
+            "{}",
+            ambiguous.message
+        );
+        assert!(pick_completion(&items, Some("Vec"), None).is_err());
+        assert!(pick_completion(&items, None, Some(3)).is_err());
+
+        // Without a text edit, the item's text is inserted at the position.
+        let position = lsp_types::Position::new(4, 8);
+        let edit = completion_text_edit(&items[2], position);
+        assert_eq!(edit.range, lsp_types::Range::new(position, position));
+        assert_eq!(edit.new_text, "HashSet");
+    }
+
+    #[test]
+    fn editor_commands_point_to_their_locations() {
+        let location = serde_json::json!({
+            "uri": "file:///tmp/lib.rs",
+            "range": {
+                "start": { "line": 3, "character": 4 },
+                "end": { "line": 3, "character": 9 },
+            },
+        });
+        let goto = command_locations(
+            "rust-analyzer.gotoLocation",
+            std::slice::from_ref(&location),
+        )
+        .unwrap();
+        assert_eq!(goto.len(), 1);
+        assert_eq!(goto[0].range.start.line, 3);
+
+        let references = command_locations(
+            "rust-analyzer.showReferences",
+            &[
+                serde_json::json!("file:///tmp/lib.rs"),
+                serde_json::json!({ "line": 0, "character": 0 }),
+                serde_json::json!([location.clone(), location]),
+            ],
+        )
+        .unwrap();
+        assert_eq!(references.len(), 2);
+        assert!(command_locations("rust-analyzer.showReferences", &[]).is_err());
+
+        let output = output_tail("é".repeat(MAX_RUN_OUTPUT_BYTES));
+        assert!(output.starts_with("[output truncated]\n"));
+        assert!(output.len() <= MAX_RUN_OUTPUT_BYTES + "[output truncated]\n".len());
+    }
+
+    #[test]
+    fn location_record_is_one_based() {
+        let loc = lsp_types::Location {
+            uri: lspmux_cc_mcp::lsp_client::file_uri("/tmp/test.rs").unwrap(),
+            range: lsp_types::Range {
+                start: lsp_types::Position::new(0, 0),
+                end: lsp_types::Position::new(0, 5),
+            },
+        };
+        let formatted = location_record(&loc.uri, &loc.range);
+        assert_eq!(formatted.display, "/tmp/test.rs:1:1");
+        assert_eq!(formatted.line, 1);
+        assert_eq!(formatted.column, 1);
+    }
+
+    #[test]
+    fn range_record_is_one_based() {
+        let range = lsp_types::Range {
+            start: lsp_types::Position::new(0, 1),
+            end: lsp_types::Position::new(2, 3),
+        };
+        let formatted = range_record(&range);
+        assert_eq!(formatted.start.line, 1);
+        assert_eq!(formatted.start.character, 2);
+        assert_eq!(formatted.end.line, 3);
+        assert_eq!(formatted.end.character, 4);
+    }
+
+    #[test]
+    fn impl_headers_are_read_from_source() {
+        let source = "#[derive(Debug, Clone)]\n\
+            pub struct Wrapper<T>(T);\n\
+            \n\
+            impl<T: Clone> From<Vec<T>>\n    for Wrapper<T>\nwhere\n    T: Debug,\n{\n}\n\
+            impl Wrapper<u8> {}\n";
+        let header = impl_header(source, 4, 8).unwrap();
+        assert_eq!(
+            header.text,
+            "impl<T: Clone> From<Vec<T>> for Wrapper<T> where T: Debug,"
+        );
+        assert_eq!(header.trait_name.as_deref(), Some("From<Vec<T>>"));
+        assert_eq!(base_name(&header.self_type), "Wrapper");
+
+        let inherent = impl_header(source, 10, 6).unwrap();
+        assert_eq!(
+            (inherent.trait_name, inherent.self_type.as_str()),
+            (None, "Wrapper<u8>")
+        );
+
+        let derived = impl_header(source, 0, 17).unwrap();
+        assert!(derived.derived);
+        assert_eq!(derived.trait_name.as_deref(), Some("Clone"));
+        assert_eq!(derived.self_type, "Wrapper");
+
+        assert!(is_trait_hover(
+            "```rust\npub(crate) unsafe trait Backend\n```"
+        ));
+        assert!(!is_trait_hover("```rust\npub struct Wrapper<T>\n```"));
+    }
+
+    #[test]
+    fn words_are_found_by_utf16_position_within_their_line() {
+        // Each crab is two UTF-16 units, so `foo` starts at character 9.
+        let source = "\"🦀🦀🦀\" foo bar\nbaz\n";
+        assert_eq!(word_at(source, 0, 10), Some("foo"));
+        assert_eq!(word_at(source, 0, 14), Some("bar"));
+        // A column past the end of a line stays on that line.
+        assert_eq!(word_at(source, 0, 40), Some("bar"));
+        assert_eq!(word_at(source, 1, 1), Some("baz"));
+    }
+
+    #[test]
+    fn symbol_kinds_are_named_as_reported() {
+        assert_eq!(
+            symbol_kind_from_name("struct").unwrap(),
+            lsp_types::SymbolKind::STRUCT
+        );
+        assert_eq!(
+            symbol_kind_from_name("type_parameter").unwrap(),
+            lsp_types::SymbolKind::TYPE_PARAMETER
+        );
+        let error = symbol_kind_from_name("class-ish").unwrap_err();
+        assert!(error.message.contains("known kinds: file, module"));
+    }
+
+    #[test]
+    fn item_paths_map_to_rustdoc_pages() {
+        let tokio = "/home/u/.cargo/registry/src/index.crates.io-6f17d22bba15001f/tokio-1.40.0/src/sync/mutex.rs";
+        assert_eq!(
+            docs_url(&["tokio", "sync", "Mutex"], "struct", None, tokio).as_deref(),
+            Some("https://docs.rs/tokio/1.40.0/tokio/sync/struct.Mutex.html")
+        );
+        assert_eq!(
+            docs_url(
+                &["tokio", "sync", "Mutex", "lock"],
+                "method",
+                Some("struct"),
+                tokio
+            )
+            .as_deref(),
+            Some("https://docs.rs/tokio/1.40.0/tokio/sync/struct.Mutex.html#method.lock")
+        );
+        assert_eq!(
+            docs_url(
+                &["std", "collections", "HashMap"],
+                "struct",
+                None,
+                "/rustlib/x.rs"
+            )
+            .as_deref(),
+            Some("https://doc.rust-lang.org/std/collections/struct.HashMap.html")
+        );
+        // Workspace items have no published docs.
+        assert_eq!(
+            docs_url(&["app", "run"], "function", None, "/ws/src/lib.rs"),
+            None
+        );
+        assert_eq!(
+            registry_package("/r/sha-1-0.10.0/src/lib.rs", "sha_1"),
+            Some(("sha-1", "0.10.0"))
+        );
+
+        assert!(container_matches(Some("impl<T> Mutex<T>"), "Mutex"));
+        assert!(container_matches(
+            Some("impl Display for Mutex<T>"),
+            "Mutex"
+        ));
+        assert!(!container_matches(Some("RwLock<T>"), "Mutex"));
+        assert_eq!(
+            hover_module_path(
+                "\n```rust\ntokio::sync::mutex\n```\n\n```rust\npub struct Mutex<T>\n```"
+            ),
+            Some("tokio::sync::mutex")
+        );
+        assert!(same_crate("std", "core"));
+        assert!(!same_crate("tokio", "std"));
+    }
+
+    #[test]
+    fn markup_to_text_preserves_language_blocks() {
+        let text = markup_to_text(lsp_types::HoverContents::Scalar(
+            lsp_types::MarkedString::LanguageString(lsp_types::LanguageString {
+                language: "rust".to_string(),
+                value: "fn demo()".to_string(),
+            }),
+        ));
+        assert!(text.contains("```rust"));
+        assert!(text.contains("fn demo()"));
+    }
+
+    const HOVER: &str = "```rust\ncrate::lsp\n```\n\n```rust\npub fn request<R>(&self) -> Result<R>\n```\n\n---\n\nSend a request. See [`LspClient`](https://docs.rs/x) for details.";
 
-        let diagnostic_uri = uri.clone();
-        let diag_params = lsp_types::DocumentDiagnosticParams {
-            text_document: lsp_types::TextDocumentIdentifier { uri },
-            identifier: None,
-            previous_result_id: None,
-            work_done_progress_params: lsp_types::WorkDoneProgressParams::default(),
-            partial_result_params: lsp_types::PartialResultParams::default(),
+    #[test]
+    fn hover_sections_and_plaintext() {
+        let signature = HoverOptions {
+            sections: HoverSections::Signature,
+            ..HoverOptions::default()
         };
+        let (text, truncated) = shape_hover(HOVER, &signature);
+        assert!(text.ends_with("Result<R>\n```"), "{text}");
+        assert!(!text.contains("Send a request"));
+        assert!(!truncated);
 
-        let report = self
-            .lsp
-            .request::<lsp_types::request::DocumentDiagnosticRequest>(diag_params)
-            .await
-            .map_err(|e| {
-                internal_error(format!(
-                    "diagnostics request failed: {e}. rust-analyzer may still be indexing"
-                ))
-            })?;
+        let plain = HoverOptions {
+            format: HoverFormat::Plaintext,
+            ..HoverOptions::default()
+        };
+        let (text, _) = shape_hover(HOVER, &plain);
+        assert_eq!(
+            text,
+            "crate::lsp\n\npub fn request<R>(&self) -> Result<R>\n\nSend a request. See LspClient for details."
+        );
+    }
 
-        let items = match report {
-            lsp_types::DocumentDiagnosticReportResult::Report(
-                lsp_types::DocumentDiagnosticReport::Full(full),
-            ) => full.full_document_diagnostic_report.items,
-            lsp_types::DocumentDiagnosticReportResult::Report(
-                lsp_types::DocumentDiagnosticReport::Unchanged(_),
-            )
-            | lsp_types::DocumentDiagnosticReportResult::Partial(_) => vec![],
+    #[test]
+    fn hover_truncation_keeps_fences_balanced() {
+        let options = HoverOptions {
+            max_chars: Some(60),
+            ..HoverOptions::default()
         };
+        let (text, truncated) = shape_hover(HOVER, &options);
+        assert!(truncated);
+        assert_eq!(text.matches("```").count() % 2, 0, "{text}");
+        assert!(text.contains("… truncated ("), "{text}");
+        assert!(!text.contains("Send a request"));
+    }
 
-        let diagnostics = items
-            .into_iter()
-            .map(|diagnostic| DiagnosticRecord {
-                severity: diagnostic_severity_name(diagnostic.severity).to_string(),
-                message: diagnostic.message,
-                code: diagnostic.code.map(|code| match code {
-                    lsp_types::NumberOrString::String(value) => value,
-                    lsp_types::NumberOrString::Number(value) => value.to_string(),
-                }),
-                source: diagnostic.source,
-                location: location_record(&diagnostic_uri, &diagnostic.range),
-            })
-            .collect::<Vec<_>>();
+    fn progress_event(kind: &str, title: Option<&str>, percentage: Option<u32>) -> ProgressEvent {
+        ProgressEvent {
+            token: "rustAnalyzer/Indexing".to_string(),
+            kind: kind.to_string(),
+            title: title.map(String::from),
+            message: None,
+            percentage,
+        }
+    }
 
-        let diagnostic_count = diagnostics.len();
-        let summary = if diagnostic_count == 0 {
-            format!("No diagnostics found for {file}.")
-        } else {
-            format!("Found {diagnostic_count} diagnostic(s) for {file}.")
-        };
+    #[test]
+    fn progress_forwarder_is_monotonic_and_keeps_titles() {
+        let token = ProgressToken(rmcp::model::NumberOrString::Number(1));
+        let mut forwarder = ProgressForwarder::new(token);
 
-        Ok(Json(DiagnosticsResponse {
-            file_path: file.clone(),
-            diagnostic_count,
-            diagnostics,
-            summary,
-        }))
+        let begin = forwarder.next(&progress_event("begin", Some("Indexing"), Some(0)));
+        assert!(begin.progress.abs() < f64::EPSILON);
+        assert_eq!(begin.message.as_deref(), Some("Indexing"));
+
+        let report = forwarder.next(&progress_event("report", None, Some(40)));
+        assert!((report.progress - 40.0).abs() < f64::EPSILON);
+        assert_eq!(report.message.as_deref(), Some("Indexing"));
+
+        let regress = forwarder.next(&progress_event("report", None, Some(10)));
+        assert!((regress.progress - 40.0).abs() < f64::EPSILON);
+
+        let end = forwarder.next(&progress_event("end", None, None));
+        assert_eq!(end.message.as_deref(), Some("Indexing: done"));
+        assert_eq!(end.total, Some(100.0));
     }
 
-    /// Get type information and documentation at a position.
-    #[tool(
-        name = "rust_hover",
-        description = "Get type signature and documentation for a symbol at a specific position in a Rust file."
-    )]
-    async fn hover(
-        &self,
-        params: Parameters<PositionParam>,
-    ) -> Result<Json<HoverResponse>, McpError> {
-        let p = &params.0;
-        validate_file_path(&p.file_path)?;
+    #[test]
+    fn waiting_and_queueing_never_set_progress_back() {
+        let token = ProgressToken(rmcp::model::NumberOrString::Number(1));
+        let mut forwarder = ProgressForwarder::new(token);
 
-        self.lsp
-            .ensure_file_open(&p.file_path)
-            .await
-            .map_err(|e| internal_error(format!("failed to synchronize file with lspmux: {e}")))?;
+        let mut last = 0.0;
+        for fraction in [0.0, 0.5, 0.9, 2.0] {
+            let update = forwarder.waiting(fraction, "waiting".to_string());
+            assert!(update.progress >= last && update.progress <= INDEX_WAIT_PROGRESS);
+            assert_eq!(update.total, Some(100.0));
+            last = update.progress;
+        }
+        let queued = forwarder.message("server busy, queued".to_string());
+        assert!((queued.progress - last).abs() < f64::EPSILON);
+        let report = forwarder.next(&progress_event("report", Some("Indexing"), Some(5)));
+        assert!((report.progress - last).abs() < f64::EPSILON);
+        let report = forwarder.next(&progress_event("report", None, Some(60)));
+        assert!((report.progress - 60.0).abs() < f64::EPSILON);
+    }
 
-        let requested_position = PositionRecord {
-            line: p.line,
-            character: p.character,
+    #[test]
+    fn indexing_activity_names_what_rust_analyzer_is_doing() {
+        assert_eq!(indexing_activity(&[]), "loading the workspace");
+        let active = [
+            ProgressEvent {
+                token: "rustAnalyzer/Fetching".to_string(),
+                kind: "begin".to_string(),
+                title: Some("Fetching".to_string()),
+                message: None,
+                percentage: None,
+            },
+            ProgressEvent {
+                token: "rustAnalyzer/Indexing".to_string(),
+                kind: "report".to_string(),
+                title: Some("Indexing".to_string()),
+                message: Some("12/40 (core)".to_string()),
+                percentage: Some(30),
+            },
+        ];
+        assert_eq!(
+            indexing_activity(&active),
+            "Fetching, Indexing: 12/40 (core) (30%)"
+        );
+    }
+
+    #[test]
+    fn only_an_unhealthy_workspace_refuses_queries() {
+        let mut readiness = ReadinessState::default();
+        assert!(load_failure(&readiness).is_none());
+        readiness.health = "warning".to_string();
+        assert!(load_failure(&readiness).is_none());
+
+        readiness.health = "error".to_string();
+        readiness.message = Some("Failed to load workspace: Cargo.toml is invalid\n".to_string());
+        let error = load_failure(&readiness).unwrap();
+        assert!(error.message.starts_with(
+            "workspace failed to load: Failed to load workspace: Cargo.toml is invalid."
+        ));
+    }
+
+    #[test]
+    fn tool_supported_follows_server_capabilities() {
+        let mut capabilities = lsp_types::ServerCapabilities::default();
+        assert!(!tool_supported("rust_hover", &capabilities));
+        assert!(!tool_supported("rust_find_references", &capabilities));
+        assert!(tool_supported("rust_server_status", &capabilities));
+
+        capabilities.hover_provider = Some(lsp_types::HoverProviderCapability::Simple(true));
+        capabilities.references_provider = Some(lsp_types::OneOf::Left(false));
+        assert!(tool_supported("rust_hover", &capabilities));
+        assert!(!tool_supported("rust_find_references", &capabilities));
+        assert_eq!(
+            missing_capability("rust_safe_delete_check", &capabilities),
+            Some("document symbols")
+        );
+        capabilities.document_symbol_provider = Some(lsp_types::OneOf::Left(true));
+        assert_eq!(
+            missing_capability("rust_safe_delete_check", &capabilities),
+            Some("find references")
+        );
+    }
+
+    #[test]
+    fn structured_result_pairs_text_with_json() {
+        let uri = lspmux_cc_mcp::lsp_client::file_uri("/tmp/test.rs").unwrap();
+        let range = lsp_types::Range {
+            start: lsp_types::Position::new(2, 4),
+            end: lsp_types::Position::new(2, 9),
+        };
+        let response = DiagnosticsResponse {
+            file_path: "/tmp/test.rs".to_string(),
+            diagnostic_count: 1,
+            total_count: 1,
+            diagnostics: vec![DiagnosticRecord {
+                index: 0,
+                severity: "error".to_string(),
+                message: "mismatched types".to_string(),
+                code: Some("E0308".to_string()),
+                source: Some("rustc".to_string()),
+                location: location_record(&uri, &range),
+                related: Vec::new(),
+                tags: Vec::new(),
+            }],
+            next_cursor: None,
+            explanations: Vec::new(),
+            summary: "Found 1 diagnostic(s) for /tmp/test.rs.".to_string(),
         };
-        let hover = self
-            .lsp
-            .hover(&p.file_path, p.line, p.character)
-            .await
-            .map_err(|e| internal_error(format!("hover request failed: {e}")))?;
 
-        match hover {
-            Some(hover) => {
-                let contents = markup_to_text(hover.contents);
-                Ok(Json(HoverResponse {
-                    file_path: p.file_path.clone(),
-                    requested_position,
-                    found: true,
-                    range: hover.range.as_ref().map(range_record),
-                    summary: format!("Hover information found for {}.", p.file_path),
-                    contents,
-                }))
-            }
-            None => Ok(Json(HoverResponse {
-                file_path: p.file_path.clone(),
-                requested_position,
-                found: false,
-                contents: String::new(),
-                range: None,
-                summary: "No hover information available at this position.".to_string(),
-            })),
-        }
+        let result = Structured(response.clone())
+            .into_call_tool_result()
+            .unwrap();
+        let structured = result.structured_content.unwrap();
+        assert_eq!(structured["diagnostics"][0]["code"], "E0308");
+        assert_eq!(structured["diagnostics"][0]["location"]["line"], 3);
+        assert!(structured.get("explanations").is_none());
+        let text = &result.content[0].as_text().unwrap().text;
+        assert!(text.contains("/tmp/test.rs:3:5: error[E0308]: mismatched types"));
+
+        let explained = DiagnosticsResponse {
+            explanations: vec![ErrorExplanation {
+                code: "E0308".to_string(),
+                explanation: "Expected type did not match the received type.".to_string(),
+            }],
+            ..response
+        };
+        assert!(explained
+            .render_text()
+            .ends_with("\n\n# E0308\n\nExpected type did not match the received type."));
     }
 
-    /// Find the definition of a symbol.
-    #[tool(
-        name = "rust_goto_definition",
-        description = "Find where a symbol is defined. Returns one-based file locations for the definition."
-    )]
-    async fn goto_definition(
-        &self,
-        params: Parameters<PositionParam>,
-    ) -> Result<Json<LocationsResponse>, McpError> {
-        let p = &params.0;
-        validate_file_path(&p.file_path)?;
+    #[test]
+    fn diagnostic_record_keeps_related_spans_and_tags() {
+        let uri = lspmux_cc_mcp::lsp_client::file_uri("/tmp/test.rs").unwrap();
+        let range = |line| lsp_types::Range {
+            start: lsp_types::Position::new(line, 0),
+            end: lsp_types::Position::new(line, 4),
+        };
+        let diagnostic = lsp_types::Diagnostic {
+            range: range(9),
+            severity: Some(lsp_types::DiagnosticSeverity::ERROR),
+            code: Some(lsp_types::NumberOrString::String("E0308".to_string())),
+            source: Some("rustc".to_string()),
+            message: "mismatched types".to_string(),
+            related_information: Some(vec![lsp_types::DiagnosticRelatedInformation {
+                location: lsp_types::Location::new(uri.clone(), range(4)),
+                message: "expected due to this".to_string(),
+            }]),
+            tags: Some(vec![lsp_types::DiagnosticTag::DEPRECATED]),
+            ..lsp_types::Diagnostic::default()
+        };
 
-        self.lsp
-            .ensure_file_open(&p.file_path)
-            .await
-            .map_err(|e| internal_error(format!("failed to synchronize file with lspmux: {e}")))?;
+        let record = diagnostic_record(&uri, 2, diagnostic);
+        assert_eq!(record.related[0].location.line, 5);
+        assert_eq!(record.tags, ["deprecated"]);
 
-        let response = self
-            .lsp
-            .goto_definition(&p.file_path, p.line, p.character)
-            .await
-            .map_err(|e| internal_error(format!("go to definition failed: {e}")))?;
+        let response = DiagnosticsResponse {
+            file_path: "/tmp/test.rs".to_string(),
+            diagnostic_count: 1,
+            total_count: 1,
+            diagnostics: vec![record],
+            next_cursor: None,
+            explanations: Vec::new(),
+            summary: String::new(),
+        };
+        let text = response.render_text();
+        assert!(
+            text.contains(
+                "#2 /tmp/test.rs:10:1: error[E0308]: mismatched types (rustc) [deprecated]\n    \
+                 --> /tmp/test.rs:5:1: expected due to this"
+            ),
+            "{text}"
+        );
+    }
 
-        let locations = match response {
-            Some(lsp_types::GotoDefinitionResponse::Scalar(location)) => {
-                vec![location_record(&location.uri, &location.range)]
-            }
-            Some(lsp_types::GotoDefinitionResponse::Array(locations)) => locations
-                .into_iter()
-                .map(|location| location_record(&location.uri, &location.range))
-                .collect(),
-            Some(lsp_types::GotoDefinitionResponse::Link(links)) => links
+    #[test]
+    fn diagnostic_filter_checks_severity_and_code() {
+        let uri = lspmux_cc_mcp::lsp_client::file_uri("/tmp/test.rs").unwrap();
+        let record = |severity: &str, code: Option<&str>| DiagnosticRecord {
+            index: 0,
+            severity: severity.to_string(),
+            message: String::new(),
+            code: code.map(String::from),
+            source: None,
+            location: location_record(&uri, &lsp_types::Range::default()),
+            related: Vec::new(),
+            tags: Vec::new(),
+        };
+        let error = record("error", Some("E0308"));
+        let warning = record("warning", Some("dead_code"));
+
+        let all = DiagnosticFilter::default();
+        assert!(all.matches(&error) && all.matches(&warning));
+        assert_eq!(all.note(), "");
+
+        let errors: DiagnosticFilter =
+            serde_json::from_value(serde_json::json!({ "min_severity": "error" })).unwrap();
+        assert!(errors.matches(&error));
+        assert!(!errors.matches(&warning));
+
+        let codes = DiagnosticFilter {
+            min_severity: Some(SeverityLevel::Warning),
+            codes: vec!["dead_code".to_string()],
+        };
+        assert!(!codes.matches(&error));
+        assert!(codes.matches(&warning));
+        assert!(!codes.matches(&record("warning", None)));
+        assert_eq!(
+            codes.note(),
+            " matching severity >= warning, code in dead_code"
+        );
+    }
+
+    #[test]
+    fn symbol_queries_name_a_symbol_by_kind_and_parent() {
+        let query = SymbolQuery::parse("fn ensure_file_open").unwrap();
+        assert_eq!(query.name, "ensure_file_open");
+        assert_eq!(query.parent, None);
+        assert!(query.kinds.contains(&lsp_types::SymbolKind::METHOD));
+        let query = SymbolQuery::parse("lsp_client::LspClient::request()").unwrap();
+        assert_eq!((query.parent, query.name), (Some("LspClient"), "request"));
+        assert!(query.kinds.is_empty());
+        assert!(SymbolQuery::parse("fn").is_err());
+        assert!(SymbolQuery::parse("::request").is_err());
+        assert!(SymbolQuery::parse("LspClient::").is_err());
+
+        let symbol = |name: &str, kind, container: Option<&str>, file_path: &str| IndexedSymbol {
+            name: name.to_string(),
+            kind,
+            container: container.map(str::to_string),
+            file_path: file_path.to_string(),
+            range: lsp_types::Range::default(),
+        };
+        let method = symbol(
+            "request",
+            lsp_types::SymbolKind::METHOD,
+            Some("impl LspClient"),
+            "/ws/src/lsp_client.rs",
+        );
+        let function = symbol(
+            "request",
+            lsp_types::SymbolKind::FUNCTION,
+            None,
+            "/ws/src/http/mod.rs",
+        );
+        let matching = |symbol: &str| {
+            let query = SymbolQuery::parse(symbol).unwrap();
+            [&method, &function]
                 .into_iter()
-                .map(|link| location_record(&link.target_uri, &link.target_selection_range))
-                .collect(),
-            None => vec![],
+                .filter(|candidate| query.matches(candidate))
+                .count()
         };
+        assert_eq!(matching("request"), 2);
+        assert_eq!(matching("fn request"), 2);
+        assert_eq!(matching("LspClient::request"), 1);
+        assert_eq!(matching("http::request"), 1);
+        assert_eq!(matching("struct request"), 0);
+        assert_eq!(matching("Other::request"), 0);
+    }
 
-        let found = !locations.is_empty();
-        let location_count = locations.len();
-        let summary = if found {
-            format!("Found {location_count} definition location(s).")
-        } else {
-            "No definition found at this position.".to_string()
+    #[test]
+    fn missing_symbols_suggest_the_closest_names() {
+        let symbols: Vec<IndexedSymbol> = ["load_config", "Config", "load", "save", "load"]
+            .into_iter()
+            .map(|name| IndexedSymbol {
+                name: name.to_string(),
+                kind: lsp_types::SymbolKind::FUNCTION,
+                container: None,
+                file_path: "/ws/src/lib.rs".to_string(),
+                range: lsp_types::Range::default(),
+            })
+            .collect();
+        assert_eq!(closest_names("lod", &symbols), ["load", "load_config"]);
+        assert!(closest_names("xyz", &symbols).is_empty());
+    }
+
+    #[test]
+    fn unused_reports_keep_unused_code_and_count_it_per_lint() {
+        let uri = lspmux_cc_mcp::lsp_client::file_uri("/tmp/test.rs").unwrap();
+        let record = |code: Option<&str>, tags: &[&str]| DiagnosticRecord {
+            index: 0,
+            severity: "warning".to_string(),
+            message: String::new(),
+            code: code.map(String::from),
+            source: None,
+            location: location_record(&uri, &lsp_types::Range::default()),
+            related: Vec::new(),
+            tags: tags.iter().map(|tag| (*tag).to_string()).collect(),
         };
+        assert!(is_unused(&record(Some("dead_code"), &[])));
+        assert!(is_unused(&record(Some("unused_imports"), &["unnecessary"])));
+        assert!(is_unused(&record(Some("unused-variables"), &[])));
+        assert!(is_unused(&record(None, &["unnecessary"])));
+        assert!(!is_unused(&record(Some("inactive-code"), &["unnecessary"])));
+        assert!(!is_unused(&record(Some("unused_must_use"), &[])));
+        assert!(!is_unused(&record(Some("E0308"), &[])));
 
-        Ok(Json(LocationsResponse {
-            file_path: p.file_path.clone(),
-            requested_position: PositionRecord {
-                line: p.line,
-                character: p.character,
-            },
-            found,
-            location_count,
-            locations,
-            summary,
-        }))
+        let file = |diagnostics: Vec<DiagnosticRecord>| UnusedFileRecord {
+            file_path: "/tmp/test.rs".to_string(),
+            count: diagnostics.len(),
+            diagnostics,
+        };
+        let counts = unused_counts(&[
+            file(vec![
+                record(Some("unused_imports"), &[]),
+                record(Some("dead_code"), &[]),
+            ]),
+            file(vec![
+                record(Some("dead_code"), &[]),
+                record(None, &["unnecessary"]),
+            ]),
+        ]);
+        let counts: Vec<(&str, usize)> = counts
+            .iter()
+            .map(|count| (count.code.as_str(), count.count))
+            .collect();
+        assert_eq!(
+            counts,
+            [("dead_code", 2), ("unnecessary", 1), ("unused_imports", 1)]
+        );
     }
 
-    /// Find all references to a symbol.
-    #[tool(
-        name = "rust_find_references",
-        description = "Find all references to a symbol at a specific position. Returns one-based file locations."
-    )]
-    async fn find_references(
-        &self,
-        params: Parameters<PositionParam>,
-    ) -> Result<Json<LocationsResponse>, McpError> {
-        let p = &params.0;
-        validate_file_path(&p.file_path)?;
+    #[test]
+    fn quickfix_targets_a_diagnostic_and_its_preferred_fix() {
+        let diagnostic = |line, code: &str| lsp_types::Diagnostic {
+            range: lsp_types::Range::new(
+                lsp_types::Position::new(line, 0),
+                lsp_types::Position::new(line, 3),
+            ),
+            code: Some(lsp_types::NumberOrString::String(code.to_string())),
+            message: format!("{code} here"),
+            ..lsp_types::Diagnostic::default()
+        };
+        let diagnostics = vec![
+            diagnostic(0, "unused_imports"),
+            diagnostic(4, "E0425"),
+            diagnostic(9, "E0425"),
+        ];
+        let params = |value: serde_json::Value| -> QuickfixParam {
+            let mut value = value;
+            value["file_path"] = "/src/lib.rs".into();
+            serde_json::from_value(value).unwrap()
+        };
 
-        self.lsp
-            .ensure_file_open(&p.file_path)
-            .await
-            .map_err(|e| internal_error(format!("failed to synchronize file with lspmux: {e}")))?;
+        let by_index = select_diagnostic(
+            diagnostics.clone(),
+            &params(serde_json::json!({ "index": 2 })),
+        );
+        assert_eq!(by_index.unwrap().1.range.start.line, 9);
+        let by_code = select_diagnostic(
+            diagnostics.clone(),
+            &params(serde_json::json!({ "code": "E0425", "line": 10 })),
+        );
+        assert_eq!(by_code.unwrap().0, 2);
+        assert!(select_diagnostic(
+            diagnostics.clone(),
+            &params(serde_json::json!({ "index": 3 }))
+        )
+        .is_err());
+        assert!(select_diagnostic(diagnostics, &params(serde_json::json!({}))).is_err());
 
-        let locations = self
-            .lsp
-            .find_references(&p.file_path, p.line, p.character)
-            .await
-            .map_err(|e| internal_error(format!("find references failed: {e}")))?
-            .unwrap_or_default()
-            .into_iter()
-            .map(|location| location_record(&location.uri, &location.range))
-            .collect::<Vec<_>>();
+        let fix = |title: &str, preferred| lsp_types::CodeAction {
+            title: title.to_string(),
+            is_preferred: preferred,
+            ..lsp_types::CodeAction::default()
+        };
+        let (chosen, alternatives) =
+            choose_action(vec![fix("a", None), fix("b", Some(true))], None, "fix").unwrap();
+        assert_eq!(
+            (chosen.title.as_str(), alternatives),
+            ("b", vec!["a".to_string()])
+        );
+        assert!(choose_action(vec![fix("a", None), fix("b", None)], None, "fix").is_err());
+        let (chosen, _) =
+            choose_action(vec![fix("a", None), fix("b", None)], Some("a"), "fix").unwrap();
+        assert_eq!(chosen.title, "a");
+        assert!(choose_action(Vec::new(), None, "fix").is_err());
+    }
 
-        let found = !locations.is_empty();
-        let location_count = locations.len();
-        let summary = if found {
-            format!("Found {location_count} reference(s).")
-        } else {
-            "No references found at this position.".to_string()
+    #[test]
+    fn imports_target_unresolved_names() {
+        let diagnostic = |code: &str, message: &str| lsp_types::Diagnostic {
+            code: Some(lsp_types::NumberOrString::String(code.to_string())),
+            message: message.to_string(),
+            ..lsp_types::Diagnostic::default()
         };
+        let diagnostics = vec![
+            diagnostic("unused_variables", "unused variable: `HashMap`"),
+            diagnostic(
+                "E0433",
+                "failed to resolve: use of undeclared type `HashMap`",
+            ),
+        ];
+        let found = unresolved_name_diagnostic(diagnostics.clone(), "HashMap").unwrap();
+        assert_eq!(found.code, diagnostics[1].code);
+        assert!(unresolved_name_diagnostic(diagnostics, "Map").is_none());
 
-        Ok(Json(LocationsResponse {
-            file_path: p.file_path.clone(),
-            requested_position: PositionRecord {
-                line: p.line,
-                character: p.character,
-            },
-            found,
-            location_count,
-            locations,
-            summary,
-        }))
+        assert_eq!(
+            import_path("Import `std::collections::HashMap`"),
+            Some("std::collections::HashMap")
+        );
+        assert_eq!(import_path("Qualify as `std::collections::HashMap`"), None);
     }
 
-    /// Search for symbols by name across the workspace.
-    #[tool(
-        name = "rust_workspace_symbol",
-        description = "Search for symbols by name across the entire workspace. Returns one-based locations and normalized symbol kinds."
-    )]
-    async fn workspace_symbol(
-        &self,
-        params: Parameters<WorkspaceSymbolParam>,
-    ) -> Result<Json<WorkspaceSymbolsResponse>, McpError> {
-        let query = &params.0.query;
-        let symbols = self
-            .lsp
-            .workspace_symbols(query.clone())
-            .await
-            .map_err(|e| internal_error(format!("workspace symbol search failed: {e}")))?;
+    #[test]
+    fn expand_targets_map_to_cargo_arguments() {
+        assert_eq!(target_args("lib").unwrap(), ["--lib"]);
+        assert_eq!(target_args("bin:server").unwrap(), ["--bin", "server"]);
+        assert_eq!(target_args("test:it").unwrap(), ["--test", "it"]);
+        assert!(target_args("custom-build:build-script-build").is_err());
+        assert!(target_args("bin:").is_err());
+    }
 
-        let records = match symbols {
-            Some(lsp_types::WorkspaceSymbolResponse::Flat(symbols)) => symbols
-                .into_iter()
-                .map(|symbol| WorkspaceSymbolRecord {
-                    name: symbol.name,
-                    kind: symbol_kind_name(symbol.kind).to_string(),
-                    container_name: symbol.container_name,
-                    location: location_record(&symbol.location.uri, &symbol.location.range),
-                })
-                .collect(),
-            Some(lsp_types::WorkspaceSymbolResponse::Nested(symbols)) => symbols
-                .into_iter()
-                .filter_map(|symbol| {
-                    if let lsp_types::OneOf::Left(location) = symbol.location {
-                        Some(WorkspaceSymbolRecord {
-                            name: symbol.name,
-                            kind: symbol_kind_name(symbol.kind).to_string(),
-                            container_name: symbol.container_name,
-                            location: location_record(&location.uri, &location.range),
-                        })
-                    } else {
-                        None
-                    }
-                })
-                .collect(),
-            None => vec![],
+    #[test]
+    fn generators_are_recognized_by_kind_or_title() {
+        let action = |title: &str, kind: Option<&str>| lsp_types::CodeAction {
+            title: title.to_string(),
+            kind: kind.map(|kind| lsp_types::CodeActionKind::from(kind.to_string())),
+            ..lsp_types::CodeAction::default()
+        };
+        assert!(is_generator(&action("Generate getter", Some(""))));
+        assert!(is_generator(&action("Fill match arms", Some("quickfix"))));
+        assert!(is_generator(&action("Add `#[derive]`", None)));
+        assert!(!is_generator(&action(
+            "Extract into variable",
+            Some("refactor.extract")
+        )));
+        assert!(!is_generator(&action(
+            "Remove unused import",
+            Some("quickfix")
+        )));
+    }
+
+    #[test]
+    fn snippets_are_clamped_to_the_file_and_rendered_with_a_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lib.rs");
+        std::fs::write(&path, "fn a() {}\nfn b() {}\nfn c() {}\n").unwrap();
+        let uri = lspmux_cc_mcp::lsp_client::file_uri(path.to_str().unwrap()).unwrap();
+        let at = |line| {
+            location_record(
+                &uri,
+                &lsp_types::Range {
+                    start: lsp_types::Position::new(line, 3),
+                    end: lsp_types::Position::new(line, 4),
+                },
+            )
         };
 
-        let symbol_count = records.len();
-        let summary = if symbol_count == 0 {
-            format!("No symbols found matching {query:?}.")
-        } else {
-            format!("Found {symbol_count} symbol(s) matching {query:?}.")
+        let mut locations = vec![at(0), at(2), at(7)];
+        attach_snippets(&mut locations, Some(1));
+        let first = locations[0].snippet.as_ref().unwrap();
+        assert_eq!(first.start_line, 1);
+        assert_eq!(first.lines, ["fn a() {}", "fn b() {}"]);
+        assert_eq!(locations[1].snippet.as_ref().unwrap().start_line, 2);
+        assert!(locations[2].snippet.is_none(), "past the end of the file");
+
+        let response = LocationsResponse {
+            file_path: path.display().to_string(),
+            requested_position: PositionRecord {
+                line: 0,
+                character: 3,
+            },
+            found: true,
+            location_count: 1,
+            total_count: 1,
+            locations: locations[..1].to_vec(),
+            files: Vec::new(),
+            next_cursor: None,
+            summary: String::new(),
         };
+        let text = response.render_text();
+        assert!(
+            text.contains("  >     1 | fn a() {}\n        2 | fn b() {}"),
+            "{text}"
+        );
 
-        Ok(Json(WorkspaceSymbolsResponse {
-            query: query.clone(),
-            symbol_count,
-            symbols: records,
-            summary,
-        }))
+        let mut untouched = vec![at(0)];
+        attach_snippets(&mut untouched, None);
+        assert!(untouched[0].snippet.is_none());
     }
 
-    /// Return server health and configuration status.
-    #[tool(
-        name = "rust_server_status",
-        description = "Check rust-analyzer liveness, readiness, active workspace root, and shared lspmux bootstrap metadata."
-    )]
-    async fn server_status(
-        &self,
-        _params: Parameters<NoParams>,
-    ) -> Result<Json<ServerStatusResponse>, McpError> {
-        let server_status = if self.lsp.is_alive() {
-            "running"
-        } else {
-            "stopped"
+    #[test]
+    fn grouped_references_render_a_header_per_file() {
+        let at = |file: &str, line| {
+            let uri = lspmux_cc_mcp::lsp_client::file_uri(file).unwrap();
+            location_record(
+                &uri,
+                &lsp_types::Range {
+                    start: lsp_types::Position::new(line, 0),
+                    end: lsp_types::Position::new(line, 1),
+                },
+            )
         };
-        let workspace_root = self.lsp.workspace_root().await;
-        let server_version = self.lsp.server_version().await;
-        self.telemetry
-            .refresh_compiler_accounting(workspace_root.as_deref());
-        let readiness = self.lsp.readiness().await;
-        let telemetry = self.telemetry.snapshot();
-        let client = self.telemetry.client_identity();
-        let compiler_accounting = self.telemetry.compiler_accounting_snapshot();
-        let summary = format!(
-            "{SERVER_NAME} liveness: {server_status}; readiness: {}; workspace root: {}",
-            readiness.health,
-            workspace_root
-                .clone()
-                .unwrap_or_else(|| "<unknown>".to_string())
+        let files = group_by_file(vec![at("/b.rs", 4), at("/a.rs", 0), at("/b.rs", 9)]);
+        assert_eq!(
+            files
+                .iter()
+                .map(|file| (file.file_path.as_str(), file.count))
+                .collect::<Vec<_>>(),
+            [("/b.rs", 2), ("/a.rs", 1)]
         );
 
-        Ok(Json(ServerStatusResponse {
-            server: SERVER_NAME.to_string(),
-            server_status: server_status.to_string(),
-            workspace_root,
-            server_version,
-            runtime: self.runtime_status.clone(),
-            client,
-            readiness,
-            telemetry,
-            compiler_accounting,
-            summary,
-        }))
+        let response = LocationsResponse {
+            file_path: "/a.rs".to_string(),
+            requested_position: PositionRecord {
+                line: 0,
+                character: 0,
+            },
+            found: true,
+            location_count: 3,
+            total_count: 3,
+            locations: Vec::new(),
+            files,
+            next_cursor: None,
+            summary: "Found 3 reference(s) in 2 file(s).".to_string(),
+        };
+        assert_eq!(
+            response.render_text(),
+            "Found 3 reference(s) in 2 file(s).\n\n/b.rs (2)\n  5:1\n  10:1\n\n/a.rs (1)\n  1:1"
+        );
     }
-}
 
-/// Delegation methods for `ServerHandler` integration.
-impl RustAnalyzerTools {
-    /// List all available tools.
-    pub fn list_tools(&self) -> ListToolsResult {
-        ListToolsResult {
-            tools: self.tool_router.list_all(),
-            ..ListToolsResult::default()
-        }
+    #[cfg(unix)]
+    #[test]
+    fn locations_are_canonical_sorted_and_unique() {
+        let dir = tempfile::tempdir().unwrap();
+        let real = dir.path().canonicalize().unwrap().join("lib.rs");
+        std::fs::write(&real, "").unwrap();
+        let link = dir.path().join("link.rs");
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let at = |path: &Path, line| {
+            let uri = lspmux_cc_mcp::lsp_client::file_uri(path.to_str().unwrap()).unwrap();
+            location_record(
+                &uri,
+                &lsp_types::Range {
+                    start: lsp_types::Position::new(line, 0),
+                    end: lsp_types::Position::new(line, 1),
+                },
+            )
+        };
+        let locations =
+            normalize_locations(vec![at(&real, 9), at(&link, 2), at(&real, 2), at(&real, 9)]);
+        let lines: Vec<_> = locations.iter().map(|location| location.line).collect();
+        assert_eq!(lines, [3, 10]);
+        assert!(locations
+            .iter()
+            .all(|location| location.file_path == real.display().to_string()));
     }
 
-    /// Call a tool by name.
-    pub async fn call_tool(
-        &self,
-        request: CallToolRequestParams,
-        context: RequestContext<RoleServer>,
-    ) -> Result<CallToolResult, McpError> {
-        let tool_name = request.name.clone();
-        let client = self.telemetry.client_identity();
-        let started = Instant::now();
-        tracing::info!(
-            event = "tool_start",
-            tool = %tool_name,
-            client_kind = %client.kind,
-            client_host = %client.host,
-            session_id = %client.session_id
+    #[test]
+    fn shared_arguments_are_advertised_and_stripped() {
+        for tool in RustAnalyzerTools::tool_router().list_all() {
+            let tool = with_shared_params(tool);
+            assert_eq!(
+                tool.input_schema["properties"]["output"]["enum"],
+                serde_json::json!(["text", "json"]),
+                "{}",
+                tool.name
+            );
+            assert_eq!(
+                tool.input_schema["properties"]["workspace_root"]["type"], "string",
+                "{}",
+                tool.name
+            );
+        }
+
+        let mut arguments = serde_json::json!({ "query": "Foo", "output": "json" });
+        let arguments = arguments.as_object_mut().unwrap();
+        assert_eq!(
+            OutputFormat::take(Some(arguments)).unwrap(),
+            OutputFormat::Json
+        );
+        assert!(!arguments.contains_key("output"));
+        assert_eq!(
+            OutputFormat::take(Some(arguments)).unwrap(),
+            OutputFormat::Text
         );
-        let ctx = ToolCallContext::new(self, request, context);
-        let result = self.tool_router.call(ctx).await;
-        let latency_ms = started.elapsed().as_millis();
-        let latency_ms_u64 = u64::try_from(latency_ms).unwrap_or(u64::MAX);
 
-        match &result {
-            Ok(_) => {
-                self.telemetry.record_tool_result(
-                    &tool_name,
-                    ToolOutcome::Success,
-                    latency_ms_u64,
-                    None,
-                    None,
-                );
-                tracing::info!(
-                    event = "tool_result",
-                    tool = %tool_name,
-                    outcome = "success",
-                    latency_ms = latency_ms
-                );
-                if tool_name != "rust_server_status" {
-                    let workspace_root = self.lsp.workspace_root().await;
-                    self.telemetry
-                        .refresh_compiler_accounting(workspace_root.as_deref());
-                }
-            }
-            Err(error) => {
-                let outcome = classify_tool_error(error);
-                self.telemetry.record_tool_result(
-                    &tool_name,
-                    outcome,
-                    latency_ms_u64,
-                    Some(error_code_name(error.code)),
-                    Some(&error.message),
-                );
-                tracing::warn!(
-                    event = "tool_result",
-                    tool = %tool_name,
-                    outcome = %outcome.as_str(),
-                    error_code = ?error.code,
-                    error = %error.message,
-                    latency_ms = latency_ms
+        let mut bad = serde_json::json!({ "output": "yaml" });
+        assert!(OutputFormat::take(bad.as_object_mut()).is_err());
+    }
+
+    #[test]
+    fn json_output_replaces_the_text_rendering() {
+        let response = WorkspaceSymbolsResponse {
+            query: "Foo".to_string(),
+            symbol_count: 0,
+            total_count: 0,
+            symbols: Vec::new(),
+            next_cursor: None,
+            summary: "No symbols found.".to_string(),
+        };
+        let result = Structured(response).into_call_tool_result().unwrap();
+        let text = OutputFormat::Text.apply(result.clone()).unwrap();
+        assert_eq!(text.content[0].as_text().unwrap().text, "No symbols found.");
+
+        let json = OutputFormat::Json.apply(result).unwrap();
+        let parsed: serde_json::Value =
+            serde_json::from_str(&json.content[0].as_text().unwrap().text).unwrap();
+        assert_eq!(Some(parsed), json.structured_content);
+    }
+
+    #[test]
+    fn every_tool_declares_whether_it_mutates() {
+        for tool in RustAnalyzerTools::tool_router().list_all() {
+            let annotations = tool
+                .annotations
+                .unwrap_or_else(|| panic!("{} lacks annotations", tool.name));
+            assert!(
+                annotations.read_only_hint.is_some(),
+                "{} must declare read_only_hint",
+                tool.name
+            );
+            if annotations.read_only_hint == Some(false) {
+                assert!(
+                    annotations.destructive_hint.is_some(),
+                    "mutating tool {} must declare destructive_hint",
+                    tool.name
                 );
             }
         }
+    }
 
-        result
+    #[test]
+    fn every_tool_declares_an_output_schema() {
+        for tool in RustAnalyzerTools::tool_router().list_all() {
+            assert!(
+                tool.output_schema.is_some(),
+                "{} lacks an output schema",
+                tool.name
+            );
+        }
     }
-}
 
-fn classify_tool_error(error: &McpError) -> ToolOutcome {
-    if error.code == ErrorCode::INVALID_PARAMS {
-        ToolOutcome::InvalidParams
-    } else if error.message.contains("timed out") {
-        ToolOutcome::Timeout
-    } else {
-        ToolOutcome::Failure
+    /// Tools whose default workspace is served by a mock LSP server
+    /// advertising `capabilities`.
+    async fn mock_tools(
+        capabilities: serde_json::Value,
+    ) -> (RustAnalyzerTools, Arc<LspClient>, mock_lsp::MockServer) {
+        let runtime = RuntimeConfig {
+            lspmux_path: "/nonexistent/lspmux".to_string(),
+            server_path: "/nonexistent/rust-analyzer".to_string(),
+            server_path_pinned: false,
+            workspace_root: Some(mock_lsp::MOCK_WORKSPACE_ROOT.to_string()),
+            config_path: "/nonexistent/lspmux.toml".to_string(),
+            socket_path: "/nonexistent/lspmux.sock".to_string(),
+            bootstrap_mode: BootstrapMode::Off,
+            connect_addr: None,
+            lsp_transport: LspTransport::Child,
+            no_mux_fallback: false,
+        };
+        let launcher = Arc::new(LspLauncher::new(runtime, TelemetryState::from_env()));
+        let (client, server) = mock_lsp::connect(capabilities).await.unwrap();
+        let client = launcher.adopt(client);
+        let tools =
+            RustAnalyzerTools::new(Arc::new(LspPool::new(launcher)), TelemetryState::from_env());
+        (tools, client, server)
     }
-}
 
-const fn error_code_name(code: ErrorCode) -> &'static str {
-    match code {
-        ErrorCode::PARSE_ERROR => "parse_error",
-        ErrorCode::INVALID_REQUEST => "invalid_request",
-        ErrorCode::METHOD_NOT_FOUND => "method_not_found",
-        ErrorCode::INVALID_PARAMS => "invalid_params",
-        ErrorCode::INTERNAL_ERROR => "internal_error",
-        ErrorCode::RESOURCE_NOT_FOUND => "resource_not_found",
-        ErrorCode::URL_ELICITATION_REQUIRED => "url_elicitation_required",
-        _ => "other",
+    /// A crate whose `src/lib.rs` declares `mod old;`, held in `src/old.rs`.
+    fn crate_with_module() -> (tempfile::TempDir, String, String) {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        let lib = dir.path().join("src/lib.rs").display().to_string();
+        let old = dir.path().join("src/old.rs").display().to_string();
+        std::fs::write(&lib, "mod old;\n").unwrap();
+        std::fs::write(&old, "pub fn f() {}\n").unwrap();
+        (dir, lib, old)
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// The edit rust-analyzer proposes for moving `old.rs` to `new.rs`:
+    /// `mod old;` in `lib` becomes `mod new;`.
+    fn rename_module_edit(lib: &str) -> serde_json::Value {
+        serde_json::json!({
+            "changes": {
+                file_uri(lib).unwrap().as_str(): [{
+                    "range": { "start": { "line": 0, "character": 4 }, "end": { "line": 0, "character": 7 } },
+                    "newText": "new",
+                }],
+            },
+        })
+    }
 
-    #[test]
-    fn validate_file_path_rejects_relative() {
-        let err = validate_file_path("relative/path.rs").unwrap_err();
-        assert!(err.message.contains("must be absolute"));
+    fn move_params(file_path: &str, new_path: &str, dry_run: bool) -> Parameters<MoveFileParam> {
+        Parameters(MoveFileParam {
+            file_path: file_path.to_string(),
+            new_path: new_path.to_string(),
+            dry_run,
+        })
     }
 
-    #[test]
-    fn validate_file_path_rejects_nonexistent() {
-        let err = validate_file_path("/nonexistent/path/to/file.rs").unwrap_err();
-        assert!(err.message.contains("file not found"));
+    #[tokio::test]
+    async fn moving_a_file_applies_the_servers_edit_and_renames_it() {
+        let (dir, lib, old) = crate_with_module();
+        let new = dir.path().join("src/new.rs").display().to_string();
+        let (tools, client, mut server) = mock_tools(serde_json::json!({})).await;
+
+        let answer = async {
+            let (id, params) = server
+                .expect_request("workspace/willRenameFiles")
+                .await
+                .unwrap();
+            assert_eq!(
+                params["files"][0]["oldUri"],
+                file_uri(&old).unwrap().as_str()
+            );
+            assert_eq!(
+                params["files"][0]["newUri"],
+                file_uri(&new).unwrap().as_str()
+            );
+            server.respond(id, rename_module_edit(&lib)).await.unwrap();
+            server
+                .expect_notification("workspace/didRenameFiles")
+                .await
+                .unwrap()
+        };
+        let (moved, renamed) =
+            tokio::join!(tools.move_file(move_params(&old, &new, false)), answer);
+        let Structured(moved) = moved.unwrap();
+
+        assert!(moved.applied);
+        assert_eq!(moved.files.len(), 2);
+        assert_eq!(
+            renamed["files"][0]["newUri"],
+            file_uri(&new).unwrap().as_str()
+        );
+        assert_eq!(std::fs::read_to_string(&lib).unwrap(), "mod new;\n");
+        assert_eq!(std::fs::read_to_string(&new).unwrap(), "pub fn f() {}\n");
+        assert!(!Path::new(&old).exists());
+        let open: Vec<String> = client
+            .tracked_documents()
+            .into_iter()
+            .map(|document| document.file_path)
+            .collect();
+        assert!(!open.contains(&old), "{open:?}");
+        assert!(open.contains(&new), "{open:?}");
     }
 
-    #[test]
-    fn validate_file_path_accepts_existing_absolute() {
-        let manifest = env!("CARGO_MANIFEST_DIR");
-        let path = format!("{manifest}/Cargo.toml");
-        assert!(validate_file_path(&path).is_ok());
+    #[tokio::test]
+    async fn a_dry_run_move_writes_nothing() {
+        let (dir, lib, old) = crate_with_module();
+        let new = dir.path().join("src/new.rs").display().to_string();
+        let (tools, _client, mut server) = mock_tools(serde_json::json!({})).await;
+
+        let answer = async {
+            let (id, _) = server
+                .expect_request("workspace/willRenameFiles")
+                .await
+                .unwrap();
+            server.respond(id, rename_module_edit(&lib)).await.unwrap();
+        };
+        let (moved, ()) = tokio::join!(tools.move_file(move_params(&old, &new, true)), answer);
+        let Structured(moved) = moved.unwrap();
+
+        assert!(!moved.applied);
+        assert!(moved.backup_dir.is_none());
+        assert_eq!(moved.files.len(), 2);
+        assert!(moved
+            .files
+            .iter()
+            .any(|file| file.diff.contains("+mod new;")));
+        assert_eq!(std::fs::read_to_string(&lib).unwrap(), "mod old;\n");
+        assert!(Path::new(&old).exists());
+        assert!(!Path::new(&new).exists());
     }
 
-    #[test]
-    fn workspace_symbol_param_deserializes() {
-        let json = serde_json::json!({ "query": "MyStruct" });
-        let param: WorkspaceSymbolParam = serde_json::from_value(json).unwrap();
-        assert_eq!(param.query, "MyStruct");
+    #[tokio::test]
+    async fn a_move_onto_an_existing_path_is_refused() {
+        let (_dir, lib, old) = crate_with_module();
+        let (tools, _client, _server) = mock_tools(serde_json::json!({})).await;
+
+        let Err(err) = tools.move_file(move_params(&old, &lib, false)).await else {
+            panic!("moved {old} onto {lib}");
+        };
+        assert!(err.message.contains("already exists"), "{}", err.message);
+        assert_eq!(std::fs::read_to_string(&lib).unwrap(), "mod old;\n");
+        assert_eq!(std::fs::read_to_string(&old).unwrap(), "pub fn f() {}\n");
     }
 
-    #[test]
-    fn no_params_deserializes_from_empty_object() {
-        let json = serde_json::json!({});
-        let _param: NoParams = serde_json::from_value(json).unwrap();
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn a_move_that_fails_partway_is_undone() {
+        let (dir, lib, old) = crate_with_module();
+        let new = dir.path().join("src/new.rs").display().to_string();
+        let (tools, _client, mut server) = mock_tools(serde_json::json!({})).await;
+
+        // Nobody, root included, may remove a file under /proc, so deleting
+        // one fails after the files in the crate were written.
+        let answer = async {
+            let (id, _) = server
+                .expect_request("workspace/willRenameFiles")
+                .await
+                .unwrap();
+            let mut edit = rename_module_edit(&lib);
+            edit["documentChanges"] = serde_json::json!([
+                {
+                    "textDocument": { "uri": file_uri(&lib).unwrap().as_str(), "version": null },
+                    "edits": edit["changes"][file_uri(&lib).unwrap().as_str()].clone(),
+                },
+                { "kind": "delete", "uri": "file:///proc/self/comm" },
+            ]);
+            server.respond(id, edit).await.unwrap();
+        };
+        let (moved, ()) = tokio::join!(tools.move_file(move_params(&old, &new, false)), answer);
+
+        let Err(err) = moved else {
+            panic!("the move succeeded");
+        };
+        assert!(
+            err.message.contains("failed to remove /proc/self/comm"),
+            "{}",
+            err.message
+        );
+        assert!(err.message.contains("undid 2 of 2"), "{}", err.message);
+        assert_eq!(std::fs::read_to_string(&lib).unwrap(), "mod old;\n");
+        assert_eq!(std::fs::read_to_string(&old).unwrap(), "pub fn f() {}\n");
+        assert!(!Path::new(&new).exists());
     }
 
-    #[test]
-    fn location_record_is_one_based() {
-        let loc = lsp_types::Location {
-            uri: lspmux_cc_mcp::lsp_client::file_uri("/tmp/test.rs").unwrap(),
-            range: lsp_types::Range {
-                start: lsp_types::Position::new(0, 0),
-                end: lsp_types::Position::new(0, 5),
+    #[tokio::test]
+    async fn moving_a_directory_closes_the_documents_under_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let module = dir.path().join("module");
+        std::fs::create_dir(&module).unwrap();
+        let module_rs = module.join("mod.rs").display().to_string();
+        let gone = module.join("gone.rs").display().to_string();
+        std::fs::write(&module_rs, "pub fn f() {}\n").unwrap();
+        std::fs::write(&gone, "pub fn g() {}\n").unwrap();
+        let (tools, client, mut server) = mock_tools(serde_json::json!({})).await;
+        client.ensure_file_open(&module_rs).await.unwrap();
+        client.ensure_file_open(&gone).await.unwrap();
+        // Deleted behind the server's back, so not part of the move.
+        std::fs::remove_file(&gone).unwrap();
+
+        let from = module.display().to_string();
+        let to = dir.path().join("renamed").display().to_string();
+        let answer = async {
+            let (id, _) = server
+                .expect_request("workspace/willRenameFiles")
+                .await
+                .unwrap();
+            server.respond(id, serde_json::Value::Null).await.unwrap();
+        };
+        let (moved, ()) = tokio::join!(tools.move_file(move_params(&from, &to, false)), answer);
+        assert!(moved.unwrap().0.applied);
+
+        assert_eq!(
+            std::fs::read_to_string(format!("{to}/mod.rs")).unwrap(),
+            "pub fn f() {}\n"
+        );
+        let open: Vec<String> = client
+            .tracked_documents()
+            .into_iter()
+            .map(|document| document.file_path)
+            .collect();
+        assert!(
+            open.iter()
+                .all(|path| !path.starts_with(&format!("{from}/"))),
+            "{open:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn inlining_resolves_the_offered_action_and_writes_its_edit() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("lib.rs").display().to_string();
+        std::fs::write(&lib, "fn f() -> i32 {\n    let x = 1;\n    x\n}\n").unwrap();
+        let (tools, _client, mut server) =
+            mock_tools(serde_json::json!({ "codeActionProvider": { "resolveProvider": true } }))
+                .await;
+
+        let answer = async {
+            let (id, params) = server
+                .expect_request("textDocument/codeAction")
+                .await
+                .unwrap();
+            assert_eq!(
+                params["range"]["start"],
+                serde_json::json!({ "line": 1, "character": 8 })
+            );
+            assert_eq!(
+                params["context"]["only"],
+                serde_json::json!(["refactor.inline"])
+            );
+            let offered = serde_json::json!([
+                { "title": "Inline variable", "kind": "refactor.inline", "data": { "id": 1 } },
+                { "title": "Add type annotation", "kind": "refactor.rewrite", "data": { "id": 2 } },
+            ]);
+            server.respond(id, offered).await.unwrap();
+
+            let (id, action) = server.expect_request("codeAction/resolve").await.unwrap();
+            assert_eq!(action["title"], "Inline variable");
+            let uri = file_uri(&lib).unwrap();
+            let mut resolved = action;
+            resolved["edit"] = serde_json::json!({
+                "changes": {
+                    uri.as_str(): [
+                        {
+                            "range": { "start": { "line": 1, "character": 0 }, "end": { "line": 2, "character": 0 } },
+                            "newText": "",
+                        },
+                        {
+                            "range": { "start": { "line": 2, "character": 4 }, "end": { "line": 2, "character": 5 } },
+                            "newText": "1",
+                        },
+                    ],
+                },
+            });
+            server.respond(id, resolved).await.unwrap();
+        };
+        let params = Parameters(InlineParam {
+            position: PositionParam {
+                file_path: Some(lib.clone()),
+                line: Some(1),
+                character: Some(8),
+                symbol: None,
+                offset: None,
+                index_base: None,
             },
+            title: None,
+            dry_run: false,
+        });
+        let (inlined, ()) = tokio::join!(tools.inline(params), answer);
+        let Structured(inlined) = inlined.unwrap();
+
+        assert_eq!(inlined.title, "Inline variable");
+        assert!(inlined.applied);
+        assert!(
+            inlined.alternatives.is_empty(),
+            "{:?}",
+            inlined.alternatives
+        );
+        assert_eq!(
+            std::fs::read_to_string(&lib).unwrap(),
+            "fn f() -> i32 {\n    1\n}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn documents_are_opened_refreshed_and_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("lib.rs").display().to_string();
+        std::fs::write(&lib, "pub fn f() {}\n").unwrap();
+        let (tools, client, mut server) = mock_tools(serde_json::json!({})).await;
+        let file = || {
+            Parameters(FileParam {
+                file_path: lib.clone(),
+            })
         };
-        let formatted = location_record(&loc.uri, &loc.range);
-        assert_eq!(formatted.display, "/tmp/test.rs:1:1");
-        assert_eq!(formatted.line, 1);
-        assert_eq!(formatted.column, 1);
+
+        let Structured(opened) = tools.open_file(file()).await.unwrap();
+        assert_eq!(opened.action, "opened");
+        assert_eq!(opened.open_documents, 1);
+        let opened_params = server
+            .expect_notification("textDocument/didOpen")
+            .await
+            .unwrap();
+        assert_eq!(opened_params["textDocument"]["text"], "pub fn f() {}\n");
+        let Structured(again) = tools.open_file(file()).await.unwrap();
+        assert_eq!(again.action, "already_open");
+        assert_eq!(again.version, opened.version);
+
+        let Structured(unchanged) = tools.refresh_file(file()).await.unwrap();
+        assert_eq!(unchanged.action, "unchanged");
+        assert_eq!(unchanged.version, opened.version);
+
+        std::fs::write(&lib, "pub fn g() {}\n").unwrap();
+        let Structured(refreshed) = tools.refresh_file(file()).await.unwrap();
+        assert_eq!(refreshed.action, "refreshed");
+        assert!(refreshed.version > opened.version);
+        let changed = server
+            .expect_notification("textDocument/didChange")
+            .await
+            .unwrap();
+        assert_eq!(changed["contentChanges"][0]["text"], "pub fn g() {}\n");
+        assert_eq!(
+            client.synced_content_hash(&lib),
+            Some(lsp_client::content_hash("pub fn g() {}\n"))
+        );
+
+        let Structured(closed) = tools.close_file(file()).await.unwrap();
+        assert_eq!(closed.action, "closed");
+        assert_eq!(closed.version, None);
+        assert_eq!(closed.open_documents, 0);
+        server
+            .expect_notification("textDocument/didClose")
+            .await
+            .unwrap();
+        assert!(client.tracked_documents().is_empty());
+        let Structured(closed) = tools.close_file(file()).await.unwrap();
+        assert_eq!(closed.action, "not_open");
     }
 
-    #[test]
-    fn range_record_is_one_based() {
-        let range = lsp_types::Range {
-            start: lsp_types::Position::new(0, 1),
-            end: lsp_types::Position::new(2, 3),
+    #[tokio::test]
+    async fn flychecks_are_run_cancelled_and_cleared() {
+        let dir = tempfile::tempdir().unwrap();
+        let lib = dir.path().join("lib.rs").display().to_string();
+        std::fs::write(&lib, "pub fn f() {}\n").unwrap();
+        let (tools, _client, mut server) = mock_tools(serde_json::json!({})).await;
+
+        // The server checks when asked, reporting the check as progress.
+        let check = async {
+            let params = server
+                .expect_notification("rust-analyzer/runFlycheck")
+                .await
+                .unwrap();
+            assert_eq!(
+                params["textDocument"]["uri"],
+                file_uri(&lib).unwrap().as_str()
+            );
+            for kind in ["begin", "end"] {
+                let value = serde_json::json!({ "kind": kind, "title": "cargo check" });
+                server
+                    .notify(
+                        "$/progress",
+                        serde_json::json!({ "token": "rust-analyzer/flycheck/0", "value": value }),
+                    )
+                    .await
+                    .unwrap();
+            }
         };
-        let formatted = range_record(&range);
-        assert_eq!(formatted.start.line, 1);
-        assert_eq!(formatted.start.character, 2);
-        assert_eq!(formatted.end.line, 3);
-        assert_eq!(formatted.end.character, 4);
+        let params = Parameters(RunFlycheckParam {
+            file_path: Some(lib.clone()),
+            timeout_secs: Some(5),
+        });
+        let (ran, ()) = tokio::join!(tools.run_flycheck(params), check);
+        let Structured(ran) = ran.unwrap();
+        assert_eq!(ran.action, "run");
+        assert_eq!(ran.outcome.as_deref(), Some("checked"));
+
+        // Without a wait, the check is only started, for every workspace.
+        let params = Parameters(RunFlycheckParam {
+            file_path: None,
+            timeout_secs: Some(0),
+        });
+        let Structured(started) = tools.run_flycheck(params).await.unwrap();
+        assert_eq!(started.outcome, None);
+        let params = server
+            .expect_notification("rust-analyzer/runFlycheck")
+            .await
+            .unwrap();
+        assert_eq!(params["textDocument"], serde_json::Value::Null);
+
+        let Structured(cancelled) = tools
+            .cancel_flycheck(Parameters(NoParams {}))
+            .await
+            .unwrap();
+        assert_eq!(cancelled.action, "cancel");
+        server
+            .expect_notification("rust-analyzer/cancelFlycheck")
+            .await
+            .unwrap();
+        let Structured(cleared) = tools.clear_flycheck(Parameters(NoParams {})).await.unwrap();
+        assert_eq!(cleared.action, "clear");
+        server
+            .expect_notification("rust-analyzer/clearFlycheck")
+            .await
+            .unwrap();
     }
 
-    #[test]
-    fn markup_to_text_preserves_language_blocks() {
-        let text = markup_to_text(lsp_types::HoverContents::Scalar(
-            lsp_types::MarkedString::LanguageString(lsp_types::LanguageString {
-                language: "rust".to_string(),
-                value: "fn demo()".to_string(),
-            }),
-        ));
-        assert!(text.contains("```rust"));
-        assert!(text.contains("fn demo()"));
+    #[tokio::test]
+    async fn document_links_are_resolved_and_sorted() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("src")).unwrap();
+        let lib = dir.path().join("src/lib.rs").display().to_string();
+        let readme = dir.path().join("README.md").display().to_string();
+        std::fs::write(
+            &lib,
+            "#![doc = include_str!(\"../README.md\")]\n/// See [`Vec`].\npub fn f() {}\n",
+        )
+        .unwrap();
+        let (tools, _client, mut server) =
+            mock_tools(serde_json::json!({ "documentLinkProvider": { "resolveProvider": true } }))
+                .await;
+
+        let answer = async {
+            let (id, params) = server
+                .expect_request("textDocument/documentLink")
+                .await
+                .unwrap();
+            assert_eq!(
+                params["textDocument"]["uri"],
+                file_uri(&lib).unwrap().as_str()
+            );
+            let links = serde_json::json!([
+                {
+                    "range": { "start": { "line": 1, "character": 10 }, "end": { "line": 1, "character": 13 } },
+                    "target": "https://doc.rust-lang.org/std/vec/struct.Vec.html",
+                },
+                {
+                    "range": { "start": { "line": 0, "character": 23 }, "end": { "line": 0, "character": 37 } },
+                    "data": { "id": 1 },
+                },
+            ]);
+            server.respond(id, links).await.unwrap();
+
+            // Only the link without a target is resolved.
+            let (id, mut link) = server.expect_request("documentLink/resolve").await.unwrap();
+            assert_eq!(link["data"]["id"], 1);
+            link["target"] = file_uri(&readme).unwrap().as_str().into();
+            server.respond(id, link).await.unwrap();
+        };
+        let params = Parameters(FileParam {
+            file_path: lib.clone(),
+        });
+        let (found, ()) = tokio::join!(tools.document_links(params), answer);
+        let Structured(found) = found.unwrap();
+
+        assert_eq!(found.link_count, 2);
+        let include = &found.links[0];
+        assert_eq!(include.location.line, 1);
+        assert_eq!(include.target_path.as_deref(), Some(readme.as_str()));
+        let doc_link = &found.links[1];
+        assert_eq!(doc_link.location.line, 2);
+        assert_eq!(
+            doc_link.target.as_deref(),
+            Some("https://doc.rust-lang.org/std/vec/struct.Vec.html")
+        );
+        assert_eq!(doc_link.target_path, None);
+    }
+
+    #[tokio::test]
+    async fn cargo_test_refuses_a_filter_it_would_ignore() {
+        let (tools, _client, _server) = mock_tools(serde_json::json!({})).await;
+        let params: CargoTestParam = serde_json::from_value(serde_json::json!({
+            "file_path": "/mock/workspace/src/lib.rs",
+            "line": 3,
+            "filter": "parser::tests",
+        }))
+        .unwrap();
+        let Err(err) = tools.cargo_test(Parameters(params)).await else {
+            panic!("ran tests with a filter it ignored");
+        };
+        assert_eq!(err.code, ErrorCode::INVALID_PARAMS);
+        assert!(err.message.contains("`filter`"), "{}", err.message);
     }
 }
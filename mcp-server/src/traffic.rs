@@ -0,0 +1,211 @@
+//! Observing the JSON-RPC messages exchanged with lspmux.
+//!
+//! [`Tap`] wraps either side of the client's transport and passes the bytes
+//! through unchanged, handing each complete message to every
+//! [`TrafficObserver`] (such as [`crate::record::Recorder`] or
+//! [`crate::wiretap::WireTap`]). Observing at the transport sees exactly what
+//! crossed the wire, including messages the client answers or sends on its
+//! own. Every [`Tap`] also counts the bytes it passes in [`TrafficTotals`].
+
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Which side sent a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Origin {
+    /// This process, i.e. the LSP client.
+    Client,
+    /// lspmux, speaking for rust-analyzer.
+    Server,
+}
+
+/// Something that wants to see every message on the wire.
+pub trait TrafficObserver: Send + Sync {
+    /// Called once per complete message, in the order each side sent them.
+    /// Runs inside transport I/O, so it must not block for long.
+    fn observe(&self, origin: Origin, message: &Value);
+}
+
+/// The observers of one client's traffic.
+pub type Observers = Arc<[Arc<dyn TrafficObserver>]>;
+
+/// Bytes that have crossed the wire in each direction.
+#[derive(Debug, Default)]
+pub struct TrafficTotals {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl TrafficTotals {
+    /// Bytes written by the client, headers included.
+    pub fn sent(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    /// Bytes read from the server, headers included.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, origin: Origin, bytes: usize) {
+        let total = match origin {
+            Origin::Client => &self.sent,
+            Origin::Server => &self.received,
+        };
+        total.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// Reassembles `Content-Length` framed messages from a byte stream.
+#[derive(Debug, Default)]
+struct FrameParser {
+    buffer: Vec<u8>,
+}
+
+impl FrameParser {
+    /// Append `bytes` and return the messages they complete. Frames whose
+    /// header or body cannot be parsed are skipped.
+    fn push(&mut self, bytes: &[u8]) -> Vec<Value> {
+        self.buffer.extend_from_slice(bytes);
+        let mut messages = Vec::new();
+        while let Some(header_end) = self
+            .buffer
+            .windows(4)
+            .position(|window| window == b"\r\n\r\n")
+        {
+            let body_start = header_end + 4;
+            let length = std::str::from_utf8(&self.buffer[..header_end])
+                .ok()
+                .and_then(|header| {
+                    header
+                        .lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                })
+                .and_then(|length| length.trim().parse::<usize>().ok());
+            let Some(length) = length else {
+                self.buffer.drain(..body_start);
+                continue;
+            };
+            if self.buffer.len() < body_start + length {
+                break;
+            }
+            if let Ok(message) =
+                serde_json::from_slice(&self.buffer[body_start..body_start + length])
+            {
+                messages.push(message);
+            }
+            self.buffer.drain(..body_start + length);
+        }
+        messages
+    }
+}
+
+/// A reader or writer that shows the messages passing through it to
+/// observers. Reads are the server's messages, writes the client's.
+pub struct Tap<T> {
+    inner: T,
+    origin: Origin,
+    parser: FrameParser,
+    observers: Observers,
+    totals: Arc<TrafficTotals>,
+}
+
+impl<T> Tap<T> {
+    /// Wrap `inner`, whose traffic was sent by `origin`, counting its bytes
+    /// in `totals`.
+    pub fn new(inner: T, origin: Origin, observers: Observers, totals: Arc<TrafficTotals>) -> Self {
+        Self {
+            inner,
+            origin,
+            parser: FrameParser::default(),
+            observers,
+            totals,
+        }
+    }
+
+    fn observe(&mut self, bytes: &[u8]) {
+        self.totals.add(self.origin, bytes.len());
+        if self.observers.is_empty() || bytes.is_empty() {
+            return;
+        }
+        for message in self.parser.push(bytes) {
+            for observer in self.observers.iter() {
+                observer.observe(self.origin, &message);
+            }
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Tap<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if matches!(result, Poll::Ready(Ok(()))) {
+            self.observe(&buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Tap<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = result {
+            self.observe(&buf[..written]);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn frame(message: &Value) -> Vec<u8> {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{body}", body.len()).into_bytes()
+    }
+
+    #[test]
+    fn frames_split_across_reads_are_reassembled() {
+        let first = json!({ "jsonrpc": "2.0", "id": 1, "result": null });
+        let second = json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} });
+        let mut bytes = frame(&first);
+        bytes.extend(b"Content-Type: x\r\n\r\n");
+        bytes.extend(frame(&second));
+
+        let mut parser = FrameParser::default();
+        let mut messages = Vec::new();
+        for chunk in bytes.chunks(7) {
+            messages.extend(parser.push(chunk));
+        }
+        // The header-only frame without a length is skipped.
+        assert_eq!(messages, [first, second]);
+        assert!(parser.buffer.is_empty());
+    }
+}
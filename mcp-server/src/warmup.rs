@@ -0,0 +1,164 @@
+//! Priming rust-analyzer so the first real query does not hit a cold index.
+//!
+//! Used by the `rust_warmup` tool and by `--warmup` at startup. Priming opens
+//! each crate root under the workspace (so rust-analyzer loads and analyzes
+//! them), sends an empty `workspace/symbol` query to build the symbol index,
+//! and optionally waits until rust-analyzer reports that it is quiescent.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::lsp_client::LspClient;
+use crate::telemetry::ReadinessState;
+
+/// Crate roots opened at most, so huge monorepos do not flood the server.
+const MAX_CRATE_ROOTS: usize = 64;
+
+/// How deep below the workspace root to look for `Cargo.toml` files.
+const MAX_MANIFEST_DEPTH: usize = 4;
+
+/// Longest a warm-up waits for indexing to finish.
+pub const READY_LIMIT: Duration = Duration::from_mins(5);
+
+/// How often readiness is re-checked while waiting.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What priming did.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Primed {
+    /// Crate root files now open in rust-analyzer.
+    pub crate_roots: Vec<String>,
+    /// Whether the empty `workspace/symbol` query succeeded.
+    pub symbols_indexed: bool,
+    /// Readiness when priming finished.
+    pub readiness: ReadinessState,
+}
+
+impl Primed {
+    /// Whether rust-analyzer reports that indexing has finished.
+    #[must_use]
+    pub fn ready(&self) -> bool {
+        self.readiness.quiescent == Some(true)
+    }
+}
+
+/// Prime `client`'s workspace, then wait up to `wait` for rust-analyzer to
+/// become quiescent. Failures along the way are logged, not returned: a
+/// partly primed server is still warmer than a cold one.
+pub async fn prime(client: &LspClient, wait: Duration) -> Primed {
+    let deadline = Instant::now() + wait;
+    let mut primed = Primed::default();
+
+    let roots = client
+        .workspace_root()
+        .await
+        .map(|root| crate_roots(Path::new(&root)))
+        .unwrap_or_default();
+    for root in roots {
+        let root = root.display().to_string();
+        match client.ensure_file_open(&root).await {
+            Ok(_) => primed.crate_roots.push(root),
+            Err(e) => tracing::warn!("warm-up could not open {root}: {e:#}"),
+        }
+    }
+
+    match client.workspace_symbols("").await {
+        Ok(_) => primed.symbols_indexed = true,
+        Err(e) => tracing::warn!("warm-up workspace/symbol query failed: {e:#}"),
+    }
+
+    primed.readiness = wait_until_quiescent(client, deadline).await;
+    tracing::info!(
+        event = "warmup_primed",
+        crate_roots = primed.crate_roots.len(),
+        symbols_indexed = primed.symbols_indexed,
+        ready = primed.ready()
+    );
+    primed
+}
+
+/// Poll readiness until rust-analyzer is quiescent, exits, or `deadline`
+/// passes, returning the last readiness seen.
+pub async fn wait_until_quiescent(client: &LspClient, deadline: Instant) -> ReadinessState {
+    let mut readiness = client.readiness().await;
+    while readiness.quiescent != Some(true) && client.is_alive() && Instant::now() < deadline {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        readiness = client.readiness().await;
+    }
+    readiness
+}
+
+/// The `src/lib.rs` and `src/main.rs` of every package under `workspace`,
+/// sorted, skipping build output and hidden directories.
+#[must_use]
+pub fn crate_roots(workspace: &Path) -> Vec<PathBuf> {
+    let mut roots = package_dirs(workspace)
+        .into_iter()
+        .flat_map(|dir| ["src/lib.rs", "src/main.rs"].map(|root| dir.join(root)))
+        .filter(|root| root.is_file())
+        .collect::<Vec<_>>();
+    roots.sort();
+    roots.truncate(MAX_CRATE_ROOTS);
+    roots
+}
+
+/// The directories under `workspace`, itself included, that hold a
+/// `Cargo.toml`, skipping build output and hidden directories.
+#[must_use]
+pub fn package_dirs(workspace: &Path) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut pending = vec![(workspace.to_path_buf(), 0)];
+    while let Some((dir, depth)) = pending.pop() {
+        if dir.join("Cargo.toml").is_file() {
+            dirs.push(dir.clone());
+        }
+        if depth == MAX_MANIFEST_DEPTH {
+            continue;
+        }
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if entry.file_type().is_ok_and(|kind| kind.is_dir())
+                && !name.starts_with('.')
+                && name != "target"
+            {
+                pending.push((entry.path(), depth + 1));
+            }
+        }
+    }
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crate_roots_finds_library_and_binary_roots_of_every_package() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in [
+            "Cargo.toml",
+            "src/main.rs",
+            "crates/core/Cargo.toml",
+            "crates/core/src/lib.rs",
+            "crates/core/src/util.rs",
+            "target/package/x/Cargo.toml",
+            "target/package/x/src/lib.rs",
+            "examples/no-manifest/src/lib.rs",
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+
+        let roots: Vec<_> = crate_roots(root)
+            .iter()
+            .map(|path| path.strip_prefix(root).unwrap().display().to_string())
+            .collect();
+        assert_eq!(roots, ["crates/core/src/lib.rs", "src/main.rs"]);
+    }
+}
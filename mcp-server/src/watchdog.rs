@@ -0,0 +1,209 @@
+//! Detecting a hung rust-analyzer and recovering from it.
+//!
+//! A wedged rust-analyzer does not exit; it stops answering, and every tool
+//! call waits out the request timeout. The [`Watchdog`] probes each started
+//! client in the pool with a cheap `rust-analyzer/analyzerStatus` request.
+//! When [`MAX_MISSED_PROBES`] probes in a row go unanswered while the
+//! connection is still up, the launcher is marked degraded, its client is
+//! restarted, and every MCP session is sent a warning. The next answered
+//! probe clears the mark.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use metrics::counter;
+use tokio::sync::broadcast;
+use tokio_util::sync::CancellationToken;
+
+use crate::bootstrap::SERVER_NAME;
+use crate::launcher::LspLauncher;
+use crate::lsp_client::LspClient;
+use crate::pool::LspPool;
+
+/// How often each client is probed, unless configured.
+pub const DEFAULT_PROBE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long a probe waits for an answer.
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Unanswered probes in a row after which the client is restarted.
+pub const MAX_MISSED_PROBES: u32 = 3;
+
+/// Warnings queued for sessions that are slow to forward them.
+const WARNING_CHANNEL_CAPACITY: usize = 16;
+
+/// What came of one probe.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Probe {
+    /// The server answered, even if with an error.
+    Answered,
+    /// No answer within the timeout.
+    TimedOut,
+    /// The connection is gone; there is nothing to probe.
+    Disconnected,
+}
+
+/// Ask `client` for its analyzer status, waiting at most `timeout`.
+pub async fn probe(client: &LspClient, timeout: Duration) -> Probe {
+    if !client.is_alive() {
+        return Probe::Disconnected;
+    }
+    match tokio::time::timeout(timeout, client.analyzer_status()).await {
+        Err(_) => Probe::TimedOut,
+        Ok(_) if !client.is_alive() => Probe::Disconnected,
+        // An error response still shows the server is answering.
+        Ok(_) => Probe::Answered,
+    }
+}
+
+/// Probes the clients of a pool and restarts those that hang.
+#[derive(Clone)]
+pub struct Watchdog {
+    pool: Arc<LspPool>,
+    interval: Duration,
+    warnings: broadcast::Sender<String>,
+}
+
+impl Watchdog {
+    /// A watchdog probing every client in `pool` each `interval`.
+    #[must_use]
+    pub fn new(pool: Arc<LspPool>, interval: Duration) -> Self {
+        Self {
+            pool,
+            interval,
+            warnings: broadcast::Sender::new(WARNING_CHANNEL_CAPACITY),
+        }
+    }
+
+    /// The warnings sent when a client is restarted, for forwarding to an
+    /// MCP session.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<String> {
+        self.warnings.subscribe()
+    }
+
+    /// Probe in the background until `shutdown` is cancelled.
+    pub fn spawn(&self, shutdown: CancellationToken) {
+        let watchdog = self.clone();
+        tokio::spawn(async move {
+            let mut missed = HashMap::new();
+            let mut interval = tokio::time::interval(watchdog.interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                tokio::select! {
+                    () = shutdown.cancelled() => return,
+                    _ = interval.tick() => {}
+                }
+                tokio::select! {
+                    () = shutdown.cancelled() => return,
+                    () = watchdog.probe_all(&mut missed) => {}
+                }
+            }
+        });
+    }
+
+    /// Probe every started client once, counting unanswered probes per
+    /// launcher in `missed`.
+    async fn probe_all(&self, missed: &mut HashMap<usize, u32>) {
+        let launchers = self.pool.launchers();
+        missed.retain(|key, _| {
+            launchers
+                .iter()
+                .any(|launcher| Arc::as_ptr(launcher) as usize == *key)
+        });
+        for launcher in launchers {
+            let Some(client) = launcher.get() else {
+                continue;
+            };
+            let key = Arc::as_ptr(&launcher) as usize;
+            match probe(&client, PROBE_TIMEOUT).await {
+                Probe::Answered => {
+                    missed.remove(&key);
+                    if launcher.is_degraded() {
+                        launcher.set_degraded(false);
+                        tracing::info!(event = "lsp_recovered", root = launcher.workspace_root());
+                    }
+                }
+                Probe::TimedOut => {
+                    let count = missed.entry(key).or_default();
+                    *count += 1;
+                    tracing::debug!(event = "lsp_probe_timeout", missed = *count);
+                    if *count >= MAX_MISSED_PROBES {
+                        missed.remove(&key);
+                        self.restart(&launcher).await;
+                    }
+                }
+                Probe::Disconnected => {
+                    missed.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Mark `launcher` degraded, tell every session, and restart its client.
+    async fn restart(&self, launcher: &LspLauncher) {
+        launcher.set_degraded(true);
+        counter!("lspmux_cc_watchdog_restarts_total").increment(1);
+        let root = launcher.workspace_root().unwrap_or("(no workspace root)");
+        let mut warning = format!(
+            "{SERVER_NAME} for {root} did not answer {MAX_MISSED_PROBES} health probes in a \
+             row ({}s each); restarting the LSP connection",
+            PROBE_TIMEOUT.as_secs()
+        );
+        if let Err(e) = launcher.restart().await {
+            warning = format!("{warning}, which failed: {e:#}");
+        }
+        tracing::warn!(event = "lsp_hung", "{warning}");
+        // Nobody listening is fine; the warning is in the log too.
+        let _ = self.warnings.send(warning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::mock_lsp;
+
+    #[tokio::test]
+    async fn probes_tell_a_hung_server_from_a_busy_one() {
+        let (client, mut server) = mock_lsp::connect(json!({})).await.unwrap();
+
+        let answer = async {
+            let (id, _) = server
+                .expect_request("rust-analyzer/analyzerStatus")
+                .await
+                .unwrap();
+            server.respond_error(id, -32801, "busy").await.unwrap();
+        };
+        let (probed, ()) = tokio::join!(probe(&client, Duration::from_secs(5)), answer);
+        assert_eq!(probed, Probe::Answered);
+
+        // The hung server reads the probe but never answers; the probe is
+        // cancelled when it gives up.
+        let ignore = async {
+            server
+                .expect_request("rust-analyzer/analyzerStatus")
+                .await
+                .unwrap();
+            server.expect_notification("$/cancelRequest").await.unwrap();
+        };
+        let (probed, ()) = tokio::join!(probe(&client, Duration::from_millis(50)), ignore);
+        assert_eq!(probed, Probe::TimedOut);
+
+        drop(server);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while client.is_alive() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            probe(&client, Duration::from_secs(5)).await,
+            Probe::Disconnected
+        );
+    }
+}
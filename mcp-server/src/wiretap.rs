@@ -0,0 +1,230 @@
+//! Debug logging of the LSP traffic with lspmux.
+//!
+//! With `--wiretap <FILE>`, every message exchanged with lspmux is appended to
+//! `FILE`, one line per message:
+//!
+//! ```text
+//! 1760000000123 --> {"jsonrpc":"2.0","id":4,"method":"textDocument/hover",...}
+//! 1760000000180 <-- {"jsonrpc":"2.0","id":4,"result":{...}}
+//! ```
+//!
+//! `-->` is this process speaking, `<--` lspmux. Unlike a recording (see
+//! [`crate::record`]), the log is meant for reading: the file contents sent
+//! by `didOpen` and `didChange` are cut to a short preview, and the file is
+//! rotated so it cannot grow without bound.
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+use crate::telemetry::now_unix_ms;
+use crate::traffic::{Origin, TrafficObserver};
+
+/// Size at which the log is rotated.
+pub const DEFAULT_MAX_LOG_BYTES: u64 = 10 * 1024 * 1024;
+
+/// How many rotated logs (`FILE.1` being the newest) are kept.
+const KEEP_ROTATED: usize = 3;
+
+/// Characters of a document's text kept in the log.
+const TEXT_PREVIEW_CHARS: usize = 80;
+
+/// An open log file and how much has been written to it.
+struct LogFile {
+    writer: BufWriter<File>,
+    written: u64,
+}
+
+/// Appends the traffic of a client to a size-rotated log, redacted.
+pub struct WireTap {
+    path: PathBuf,
+    max_bytes: u64,
+    file: Mutex<LogFile>,
+}
+
+impl WireTap {
+    /// Append to the log at `path`, rotating it whenever it reaches
+    /// `max_bytes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file or its directory cannot be created.
+    pub fn open(path: &Path, max_bytes: u64) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create log directory {}", dir.display()))?;
+        }
+        let file = open_log(path)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_bytes,
+            file: Mutex::new(file),
+        })
+    }
+
+    /// The file being logged to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn write_line(&self, line: &str) -> std::io::Result<()> {
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        if file.written > 0 && file.written + line.len() as u64 > self.max_bytes {
+            file.writer.flush()?;
+            rotate(&self.path)?;
+            *file = open_log(&self.path).map_err(std::io::Error::other)?;
+        }
+        file.writer.write_all(line.as_bytes())?;
+        // Flush per message so the log is current when the process dies.
+        file.writer.flush()?;
+        file.written += line.len() as u64;
+        drop(file);
+        Ok(())
+    }
+}
+
+impl TrafficObserver for WireTap {
+    fn observe(&self, origin: Origin, message: &Value) {
+        let arrow = match origin {
+            Origin::Client => "-->",
+            Origin::Server => "<--",
+        };
+        let line = format!(
+            "{} {arrow} {}\n",
+            now_unix_ms().unwrap_or_default(),
+            redact(message)
+        );
+        if let Err(e) = self.write_line(&line) {
+            tracing::warn!("failed to log LSP message to {}: {e}", self.path.display());
+        }
+    }
+}
+
+fn open_log(path: &Path) -> Result<LogFile> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("failed to open LSP log {}", path.display()))?;
+    let written = file.metadata().map_or(0, |metadata| metadata.len());
+    Ok(LogFile {
+        writer: BufWriter::new(file),
+        written,
+    })
+}
+
+/// `path` with `.n` appended.
+fn rotated(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(format!(".{n}"));
+    PathBuf::from(name)
+}
+
+/// Shift `path` to `path.1`, `path.1` to `path.2`, and so on, dropping the
+/// oldest.
+fn rotate(path: &Path) -> std::io::Result<()> {
+    for n in (1..KEEP_ROTATED).rev() {
+        let from = rotated(path, n);
+        if from.exists() {
+            std::fs::rename(&from, rotated(path, n + 1))?;
+        }
+    }
+    std::fs::rename(path, rotated(path, 1))
+}
+
+/// `message` with the document text of `didOpen` and `didChange` cut to a
+/// preview.
+fn redact(message: &Value) -> Value {
+    let mut message = message.clone();
+    match message["method"].as_str() {
+        Some("textDocument/didOpen") => {
+            if let Some(text) = message.pointer_mut("/params/textDocument/text") {
+                redact_text(text);
+            }
+        }
+        Some("textDocument/didChange") => {
+            if let Some(changes) = message
+                .pointer_mut("/params/contentChanges")
+                .and_then(Value::as_array_mut)
+            {
+                for change in changes {
+                    if let Some(text) = change.get_mut("text") {
+                        redact_text(text);
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    message
+}
+
+/// Replace the string `text` with its first few characters and its size.
+fn redact_text(text: &mut Value) {
+    let Some(full) = text.as_str() else {
+        return;
+    };
+    let Some((cut, _)) = full.char_indices().nth(TEXT_PREVIEW_CHARS) else {
+        return;
+    };
+    let elided = full.len() - cut;
+    *text = Value::String(format!("{}… [{elided} more bytes]", &full[..cut]));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn document_text_is_cut_to_a_preview() {
+        let source = "fn main() {}\n".repeat(100);
+        let open = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": "file:///ws/src/main.rs", "text": source } },
+        });
+        let text = redact(&open)["params"]["textDocument"]["text"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        assert!(text.starts_with("fn main() {}\nfn main"));
+        assert!(text.ends_with(&format!("… [{} more bytes]", source.len() - 80)));
+
+        let change = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": { "contentChanges": [{ "text": source }, { "text": "short" }] },
+        });
+        let changes = &redact(&change)["params"]["contentChanges"];
+        assert!(changes[0]["text"].as_str().unwrap().contains("more bytes"));
+        assert_eq!(changes[1]["text"], "short");
+
+        // Other messages, including ones that mention `text`, are untouched.
+        let hover = json!({ "jsonrpc": "2.0", "id": 1, "result": { "text": source } });
+        assert_eq!(redact(&hover), hover);
+    }
+
+    #[test]
+    fn the_log_rotates_at_its_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("lsp.log");
+        let tap = WireTap::open(&path, 200).unwrap();
+        let message = json!({ "jsonrpc": "2.0", "method": "$/ping", "params": "x".repeat(60) });
+        for _ in 0..12 {
+            tap.observe(Origin::Server, &message);
+        }
+
+        let current = std::fs::read_to_string(&path).unwrap();
+        assert!(current.lines().all(|line| line.contains(" <-- ")));
+        assert!(current.len() <= 200);
+        for n in 1..=KEEP_ROTATED {
+            assert!(rotated(&path, n).exists(), "missing {n}");
+        }
+        assert!(!rotated(&path, KEEP_ROTATED + 1).exists());
+    }
+}
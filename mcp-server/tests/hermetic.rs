@@ -0,0 +1,492 @@
+//! Hermetic integration tests: the LSP client and the MCP server against
+//! `fake_lspmux`, a stand-in for lspmux and rust-analyzer built from this
+//! package, so they run anywhere `cargo test` does.
+//!
+//! See `src/bin/fake_lspmux.rs` for what the fake answers.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+
+use lspmux_cc_mcp::lsp_client::LspClient;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::TcpStream;
+use tokio::process::{Child, ChildStdin, ChildStdout, Command};
+use tokio::time::timeout;
+
+const FAKE_LSPMUX: &str = env!("CARGO_BIN_EXE_fake_lspmux");
+const MCP_SERVER: &str = env!("CARGO_BIN_EXE_lspmux-cc-mcp");
+
+/// How long any one exchange may take before the test fails.
+const STEP_TIMEOUT: Duration = Duration::from_secs(20);
+
+const LIB_RS: &str = "\
+pub struct Config {
+    pub name: String,
+}
+
+pub fn load() -> Config {
+    Config { name: String::new() }
+}
+";
+
+/// A workspace with `src/lib.rs` holding [`LIB_RS`].
+fn workspace() -> (tempfile::TempDir, PathBuf) {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::create_dir(dir.path().join("src")).unwrap();
+    let lib = dir.path().join("src/lib.rs");
+    std::fs::write(&lib, LIB_RS).unwrap();
+    std::fs::write(
+        dir.path().join("Cargo.toml"),
+        "[package]\nname = \"fixture\"\nversion = \"0.1.0\"\nedition = \"2021\"\n",
+    )
+    .unwrap();
+    (dir, lib)
+}
+
+#[tokio::test]
+async fn two_clients_get_the_same_answers() {
+    let (dir, lib) = workspace();
+    let root = dir.path().to_str().unwrap();
+    let lib = lib.to_str().unwrap();
+
+    let client_a = LspClient::new(FAKE_LSPMUX, FAKE_LSPMUX, Some(root))
+        .await
+        .unwrap();
+    let client_b = LspClient::new(FAKE_LSPMUX, FAKE_LSPMUX, Some(root))
+        .await
+        .unwrap();
+
+    for client in [&client_a, &client_b] {
+        client.ensure_file_open(lib).await.unwrap();
+
+        // `Config` in `pub fn load() -> Config {`.
+        let hover = client.hover(lib, 4, 18).await.unwrap().unwrap();
+        assert!(format!("{:?}", hover.contents).contains("Config"));
+
+        let definition = client.goto_definition(lib, 5, 4).await.unwrap().unwrap();
+        let lsp_types::GotoDefinitionResponse::Scalar(location) = definition else {
+            panic!("expected a single location, got {definition:?}");
+        };
+        assert_eq!(location.range.start, lsp_types::Position::new(0, 11));
+
+        let references = client
+            .find_references(lib, 0, 11, true)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(references.len(), 3);
+    }
+
+    client_a.shutdown().await;
+    client_b.shutdown().await;
+}
+
+/// The server binary, set up to run against `fake_lspmux` in `root` with
+/// its own home directory.
+fn server_command(root: &Path) -> Command {
+    let home = root.join(".home");
+    std::fs::create_dir_all(&home).unwrap();
+    let lspmux_config = home.join("lspmux.toml");
+    std::fs::write(&lspmux_config, "").unwrap();
+
+    let mut command = Command::new(MCP_SERVER);
+    command
+        .env("LSPMUX_PATH", FAKE_LSPMUX)
+        .env("RUST_ANALYZER_PATH", FAKE_LSPMUX)
+        .env("LSPMUX_BOOTSTRAP", "off")
+        .env("LSPMUX_CONFIG_PATH", &lspmux_config)
+        .env("WORKSPACE_ROOT", root)
+        .env("HOME", &home)
+        .env("XDG_CONFIG_HOME", home.join("config"))
+        .env("XDG_RUNTIME_DIR", &home)
+        .env_remove("LSPMUX_CC_CONFIG")
+        .kill_on_drop(true);
+    command
+}
+
+/// An MCP client talking to the server binary over stdio.
+struct McpSession {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Lines<BufReader<ChildStdout>>,
+    next_id: u64,
+}
+
+impl McpSession {
+    async fn start(root: &Path) -> Self {
+        Self::start_with_env(root, &[]).await
+    }
+
+    /// Start the server with `env` set on top of the hermetic defaults.
+    async fn start_with_env(root: &Path, env: &[(&str, &str)]) -> Self {
+        let mut child = server_command(root)
+            .envs(env.iter().copied())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap()).lines();
+        let mut session = Self {
+            child,
+            stdin,
+            stdout,
+            next_id: 0,
+        };
+        session
+            .request(
+                "initialize",
+                json!({
+                    "protocolVersion": "2025-03-26",
+                    "capabilities": {},
+                    "clientInfo": { "name": "hermetic-test", "version": "0" },
+                }),
+            )
+            .await;
+        session
+            .send(&json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))
+            .await;
+        session
+    }
+
+    async fn send(&mut self, message: &Value) {
+        let mut line = serde_json::to_string(message).unwrap();
+        line.push('\n');
+        self.stdin.write_all(line.as_bytes()).await.unwrap();
+        self.stdin.flush().await.unwrap();
+    }
+
+    /// Send a request and return its result, skipping notifications.
+    async fn request(&mut self, method: &str, params: Value) -> Value {
+        self.next_id += 1;
+        let id = self.next_id;
+        self.send(&json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }))
+            .await;
+        loop {
+            let line = timeout(STEP_TIMEOUT, self.stdout.next_line())
+                .await
+                .unwrap_or_else(|_| panic!("no response to {method} in time"))
+                .unwrap()
+                .expect("the MCP server closed stdout");
+            let message: Value = serde_json::from_str(&line).unwrap();
+            if message["id"] == id {
+                assert!(message.get("error").is_none(), "{method} failed: {message}");
+                return message["result"].clone();
+            }
+        }
+    }
+
+    /// Call `tool` and return its structured content.
+    async fn call(&mut self, tool: &str, arguments: Value) -> Value {
+        let result = self
+            .request(
+                "tools/call",
+                json!({ "name": tool, "arguments": arguments }),
+            )
+            .await;
+        assert_ne!(result["isError"], true, "{tool} failed: {result}");
+        result["structuredContent"].clone()
+    }
+}
+
+#[tokio::test]
+async fn mcp_tools_round_trip_through_the_fake_server() {
+    let (dir, lib) = workspace();
+    let lib = lib.to_str().unwrap();
+    let mut session = McpSession::start(dir.path()).await;
+
+    let tools = session.request("tools/list", json!({})).await;
+    assert!(tools["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|tool| tool["name"] == "rust_hover"));
+
+    // Each call's correlation id, which tags its log lines, is returned.
+    let status_call = session
+        .request(
+            "tools/call",
+            json!({ "name": "rust_server_status", "arguments": {} }),
+        )
+        .await;
+    let correlation_id = status_call["_meta"]["correlation_id"].as_str().unwrap();
+    assert!(correlation_id.ends_with("-1"), "{correlation_id}");
+
+    let hover = session
+        .call("rust_hover", json!({ "file_path": lib, "symbol": "load" }))
+        .await;
+    assert_eq!(hover["found"], true);
+    assert!(hover["contents"].as_str().unwrap().contains("load"));
+
+    let diagnostics = session
+        .call("rust_diagnostics", json!({ "file_path": lib }))
+        .await;
+    assert_eq!(diagnostics["total_count"], 0);
+
+    std::fs::write(
+        dir.path().join("src/lib.rs"),
+        format!("{LIB_RS}compile_error!(\"broken on purpose\");\n"),
+    )
+    .unwrap();
+    let fresh = session
+        .call("rust_wait_for_diagnostics", json!({ "file_path": lib }))
+        .await;
+    assert_eq!(fresh["outcome"], "checked");
+    assert_eq!(fresh["diagnostics"][0]["message"], "broken on purpose");
+
+    let stats = session.call("mcp_server_stats", json!({})).await;
+    let hover_stats = stats["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|tool| tool["tool"] == "rust_hover")
+        .unwrap();
+    assert_eq!(hover_stats["call_count"], 1);
+    assert!(hover_stats["latency"]["p95_ms"].is_u64());
+    assert!(stats["lsp"]["bytes_received"].as_u64().unwrap() > 0);
+
+    // A call can name a sibling checkout, which gets a client of its own.
+    let (sibling, sibling_lib) = workspace();
+    let sibling_root = sibling.path().to_str().unwrap();
+    let hover = session
+        .call(
+            "rust_hover",
+            json!({
+                "file_path": sibling_lib.to_str().unwrap(),
+                "symbol": "load",
+                "workspace_root": sibling_root,
+            }),
+        )
+        .await;
+    assert_eq!(hover["found"], true);
+    let sibling_status = session
+        .call(
+            "rust_server_status",
+            json!({ "workspace_root": sibling_root }),
+        )
+        .await;
+    assert_eq!(sibling_status["workspace_root"], sibling_root);
+
+    drop(session.stdin);
+    let _ = timeout(STEP_TIMEOUT, session.child.wait()).await;
+}
+
+#[tokio::test]
+async fn a_missing_lspmux_falls_back_to_an_unshared_server() {
+    let (dir, lib) = workspace();
+    let lib = lib.to_str().unwrap();
+    let mut session = McpSession::start_with_env(
+        dir.path(),
+        &[
+            ("LSPMUX_PATH", "/nonexistent/lspmux"),
+            ("LSPMUX_BOOTSTRAP", "auto"),
+            ("LSPMUX_NO_MUX_FALLBACK", "true"),
+        ],
+    )
+    .await;
+
+    let hover = session
+        .call("rust_hover", json!({ "file_path": lib, "symbol": "load" }))
+        .await;
+    assert_eq!(hover["found"], true);
+    let status = session.call("rust_server_status", json!({})).await;
+    assert_eq!(status["runtime"]["service_mode"], "unshared");
+    let lspmux = session.call("lspmux_status", json!({})).await;
+    assert_eq!(lspmux["endpoint"], Value::Null);
+
+    drop(session.stdin);
+    let _ = timeout(STEP_TIMEOUT, session.child.wait()).await;
+}
+
+#[tokio::test]
+async fn a_restart_reopens_the_documents_of_the_old_connection() {
+    let (dir, lib) = workspace();
+    let lib = lib.to_str().unwrap();
+    let mut session = McpSession::start(dir.path()).await;
+
+    let opened = session
+        .call("rust_open_file", json!({ "file_path": lib }))
+        .await;
+    assert_eq!(opened["action"], "opened");
+
+    let restarted = session.call("rust_restart_analyzer", json!({})).await;
+    assert_eq!(restarted["was_running"], true);
+    assert_eq!(restarted["documents_reopened"], 1);
+    assert_eq!(restarted["server_version"], "0.0.0-fake");
+
+    let tracked = session.call("rust_tracked_documents", json!({})).await;
+    assert_eq!(tracked["document_count"], 1);
+    assert_eq!(tracked["documents"][0]["file_path"], lib);
+    assert_eq!(tracked["documents"][0]["version"], 0);
+
+    drop(session.stdin);
+    let _ = timeout(STEP_TIMEOUT, session.child.wait()).await;
+}
+
+/// Send `method path` with `body` to the HTTP API at `addr`, once it is up,
+/// and return the response status and JSON body.
+async fn api_request(
+    addr: SocketAddr,
+    token: &str,
+    method: &str,
+    path: &str,
+    body: &str,
+) -> (u16, Value) {
+    let mut stream = timeout(STEP_TIMEOUT, async {
+        loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+    })
+    .await
+    .expect("the HTTP API never came up");
+    let request = format!(
+        "{method} {path} HTTP/1.1\r\nHost: {addr}\r\nAuthorization: Bearer {token}\r\n\
+         Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    timeout(STEP_TIMEOUT, stream.read_to_string(&mut response))
+        .await
+        .unwrap_or_else(|_| panic!("no response to {method} {path} in time"))
+        .unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, serde_json::from_str(body).unwrap())
+}
+
+#[tokio::test]
+async fn the_http_api_serves_tools_to_token_holders() {
+    let (dir, lib) = workspace();
+    let lib = lib.to_str().unwrap();
+    let addr = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let session = McpSession::start_with_env(
+        dir.path(),
+        &[
+            ("LSPMUX_API_LISTEN", &addr.to_string()),
+            ("LSPMUX_API_TOKEN", "hermetic-token"),
+        ],
+    )
+    .await;
+
+    let (status, _) = api_request(addr, "wrong-token", "GET", "/tools", "").await;
+    assert_eq!(status, 401);
+    let (status, tools) = api_request(addr, "hermetic-token", "GET", "/tools", "").await;
+    assert_eq!(status, 200);
+    assert!(tools["tools"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .any(|tool| tool["name"] == "rust_hover"));
+
+    let arguments = json!({ "file_path": lib, "symbol": "load" }).to_string();
+    let (status, hover) = api_request(
+        addr,
+        "hermetic-token",
+        "POST",
+        "/tools/rust_hover",
+        &arguments,
+    )
+    .await;
+    assert_eq!(status, 200, "{hover}");
+    assert_eq!(hover["found"], true);
+
+    let (status, error) =
+        api_request(addr, "hermetic-token", "POST", "/tools/rust_nothing", "").await;
+    assert_eq!(status, 404, "{error}");
+    let (status, error) = api_request(
+        addr,
+        "hermetic-token",
+        "POST",
+        "/tools/rust_hover",
+        r#"{"file_path": "src/lib.rs", "line": 0, "character": 0}"#,
+    )
+    .await;
+    assert_eq!(status, 400, "{error}");
+    assert!(error["error"]["message"].is_string());
+
+    let mut session = session;
+    drop(session.stdin);
+    let _ = timeout(STEP_TIMEOUT, session.child.wait()).await;
+}
+
+#[tokio::test]
+async fn the_call_subcommand_runs_one_tool_and_exits() {
+    let (dir, lib) = workspace();
+    let arguments = json!({ "file_path": lib, "symbol": "load" }).to_string();
+    let call = |args: Vec<&str>| {
+        let mut command = server_command(dir.path());
+        command.arg("call").args(args).stdin(Stdio::null());
+        async move {
+            timeout(STEP_TIMEOUT, command.output())
+                .await
+                .unwrap()
+                .unwrap()
+        }
+    };
+
+    let hover = call(vec!["rust_hover", "--json", &arguments, "--output", "json"]).await;
+    assert_eq!(hover.status.code(), Some(0), "{hover:?}");
+    let hover: Value = serde_json::from_slice(&hover.stdout).unwrap();
+    assert_eq!(hover["found"], true);
+
+    let unknown = call(vec!["rust_nothing"]).await;
+    assert_eq!(unknown.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&unknown.stderr).contains("rust_hover"));
+    let invalid = call(vec![
+        "rust_hover",
+        "--json",
+        r#"{"file_path": "src/lib.rs"}"#,
+    ])
+    .await;
+    assert_eq!(invalid.status.code(), Some(2));
+}
+
+#[tokio::test]
+async fn the_check_subcommand_fails_on_errors() {
+    let (dir, lib) = workspace();
+    let check = |args: Vec<&str>| {
+        let mut command = server_command(dir.path());
+        command
+            .arg("check")
+            .args(args)
+            .current_dir(dir.path())
+            .stdin(Stdio::null());
+        async move {
+            timeout(STEP_TIMEOUT, command.output())
+                .await
+                .unwrap()
+                .unwrap()
+        }
+    };
+
+    let clean = check(vec![]).await;
+    assert_eq!(clean.status.code(), Some(0), "{clean:?}");
+    assert!(String::from_utf8_lossy(&clean.stdout).contains("0 error(s)"));
+
+    std::fs::write(
+        &lib,
+        format!("{LIB_RS}compile_error!(\"broken on purpose\");\n"),
+    )
+    .unwrap();
+    let broken = check(vec!["src", "--format", "sarif"]).await;
+    assert_eq!(broken.status.code(), Some(1), "{broken:?}");
+    let sarif: Value = serde_json::from_slice(&broken.stdout).unwrap();
+    let result = &sarif["runs"][0]["results"][0];
+    assert_eq!(result["level"], "error");
+    assert_eq!(result["message"]["text"], "broken on purpose");
+    assert_eq!(
+        result["locations"][0]["physicalLocation"]["artifactLocation"]["uri"],
+        "src/lib.rs"
+    );
+}
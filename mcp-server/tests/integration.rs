@@ -22,6 +22,7 @@ use std::process::Command as StdCommand;
 use std::time::Duration;
 
 use lspmux_cc_mcp::lsp_client::LspClient;
+use lspmux_cc_mcp::supervisor::loopback_config;
 use tokio::process::Command;
 use tokio::time::sleep;
 
@@ -50,8 +51,7 @@ fn binary_exists(name: &str) -> bool {
     StdCommand::new("which")
         .arg(name)
         .output()
-        .map(|o| o.status.success())
-        .unwrap_or(false)
+        .is_ok_and(|o| o.status.success())
 }
 
 /// Count direct child processes of `parent_pid` whose command contains `needle`.
@@ -111,12 +111,7 @@ fn write_lspmux_config(home_dir: &Path, port: u16) {
     };
     std::fs::create_dir_all(&config_dir).expect("failed to create config dir");
 
-    let config_content = format!(
-        r#"listen = "127.0.0.1:{port}"
-connect = "127.0.0.1:{port}"
-"#
-    );
-    std::fs::write(config_dir.join("config.toml"), config_content)
+    std::fs::write(config_dir.join("config.toml"), loopback_config(port))
         .expect("failed to write lspmux config");
 }
 
@@ -257,11 +252,11 @@ async fn two_clients_share_single_rust_analyzer() {
 
     // ── 8. Both find_references on LspClient ────────────────────────────
     let refs_a = client_a
-        .find_references(target_file_str, struct_line, struct_col)
+        .find_references(target_file_str, struct_line, struct_col, true)
         .await
         .expect("Client A: find_references failed");
     let refs_b = client_b
-        .find_references(target_file_str, struct_line, struct_col)
+        .find_references(target_file_str, struct_line, struct_col, true)
         .await
         .expect("Client B: find_references failed");
 